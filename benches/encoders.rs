@@ -296,6 +296,28 @@ fn timestamp_decode_sequential(c: &mut Criterion) {
     )
 }
 
+fn timestamp_decode_random(c: &mut Criterion) {
+    benchmark_decode(
+        c,
+        "timestamp_decode_random",
+        &LARGER_BATCH_SIZES,
+        |batch_size| {
+            // one timestamp per second or so, in nanoseconds
+            let mut ts = 1_600_000_000_000_000_000;
+            let decoded: Vec<i64> = (1..batch_size)
+                .map(|_| {
+                    ts += rand::thread_rng().gen_range(1, 3) * 1_000_000_000;
+                    ts
+                })
+                .collect();
+            let mut encoded = vec![];
+            influxdb_tsm::encoders::timestamp::encode(&decoded, &mut encoded).unwrap();
+            (decoded.len(), encoded)
+        },
+        influxdb_tsm::encoders::timestamp::decode,
+    )
+}
+
 fn float_decode_random(c: &mut Criterion) {
     benchmark_decode(
         c,
@@ -345,6 +367,7 @@ criterion_group!(
     float_decode_sequential,
     integer_decode_sequential,
     timestamp_decode_sequential,
+    timestamp_decode_random,
     float_decode_random,
     integer_decode_random,
 );
@@ -1,8 +1,12 @@
-use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
 use std::time::Duration;
+use test_helpers::datasets;
 
 static LINES: &str = include_str!("../tests/fixtures/lineproto/prometheus.lp");
 
+/// The number of lines of each generated dataset
+const GENERATED_LINES: usize = 10_000;
+
 fn line_parser(c: &mut Criterion) {
     let mut group = c.benchmark_group("line_parser");
 
@@ -23,6 +27,27 @@ fn line_parser(c: &mut Criterion) {
     group.finish();
 }
 
-criterion_group!(benches, line_parser);
+fn line_parser_generated(c: &mut Criterion) {
+    let mut group = c.benchmark_group("line_parser_generated");
+
+    for &cardinality in &[10, 1_000, 10_000] {
+        let lp = datasets::line_protocol(cardinality, GENERATED_LINES / cardinality);
+        group.throughput(Throughput::Bytes(lp.len() as u64));
+
+        group.bench_with_input(BenchmarkId::new("series", cardinality), &lp, |b, lp| {
+            b.iter(|| {
+                let lines = influxdb_line_protocol::parse_lines(lp)
+                    .collect::<Result<Vec<_>, _>>()
+                    .unwrap();
+
+                assert_eq!(GENERATED_LINES, lines.len());
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, line_parser, line_parser_generated);
 
 criterion_main!(benches);
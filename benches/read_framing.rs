@@ -0,0 +1,142 @@
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use std::sync::Arc;
+
+use arrow_deps::{
+    arrow::{
+        array::{ArrayRef, Float64Array, Int64Array, StringArray},
+        datatypes::{DataType, Field, Schema},
+        record_batch::RecordBatch,
+    },
+    datafusion::physical_plan::common::SizedRecordBatchStream,
+};
+use query::exec::{
+    field::FieldColumns,
+    seriesset::{SeriesSet, SeriesSetConverter, SeriesSetItem},
+};
+use test_helpers::{datasets, str_vec_to_arc_vec};
+use tokio::{runtime::Runtime, sync::mpsc};
+
+// The conversion of series sets into the frames of read responses lives in
+// the binary, so it is built into the benchmark from its source, along with
+// the tag keys of `rpc::storage` it uses
+#[allow(dead_code)]
+#[path = "../src/influxdb_ioxd/rpc/storage/data.rs"]
+mod data;
+
+const TAG_KEY_MEASUREMENT: &[u8] = &[0];
+const TAG_KEY_FIELD: &[u8] = &[255];
+
+/// The number of rows of each dataset
+const ROWS: usize = 10_000;
+
+/// Returns the rows of the dataset of `cardinality` series, sorted by series
+/// and time as the plans of read requests produce them
+fn generate_batch(cardinality: usize) -> RecordBatch {
+    let points_per_series = ROWS / cardinality;
+    let mut hosts = Vec::with_capacity(ROWS);
+    let mut regions = Vec::with_capacity(ROWS);
+    let mut usages = Vec::with_capacity(ROWS);
+    let mut times = Vec::with_capacity(ROWS);
+    for series in 0..cardinality {
+        let host = datasets::host(series);
+        let region = datasets::region(series);
+        for point in 0..points_per_series {
+            hosts.push(host.clone());
+            regions.push(region.clone());
+            usages.push(((series * 31 + point * 17) % 1000) as f64 / 10.0);
+            times.push(datasets::BASE_TIME + point as i64 * datasets::INTERVAL);
+        }
+    }
+
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("host", DataType::Utf8, true),
+        Field::new("region", DataType::Utf8, true),
+        Field::new("usage_user", DataType::Float64, true),
+        Field::new("time", DataType::Int64, false),
+    ]));
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from(
+            hosts.iter().map(String::as_str).collect::<Vec<_>>(),
+        )),
+        Arc::new(StringArray::from(
+            regions.iter().map(String::as_str).collect::<Vec<_>>(),
+        )),
+        Arc::new(Float64Array::from(usages)),
+        Arc::new(Int64Array::from(times)),
+    ];
+    RecordBatch::try_new(schema, columns).unwrap()
+}
+
+fn read_framing(c: &mut Criterion) {
+    let mut group = c.benchmark_group("read_framing");
+    let runtime = Runtime::new().unwrap();
+
+    for &cardinality in &[10, 1_000, 10_000] {
+        let series_sets = runtime.block_on(convert(Arc::new(generate_batch(cardinality))));
+        assert_eq!(series_sets.len(), cardinality);
+        group.throughput(Throughput::Elements(ROWS as u64));
+
+        group.bench_with_input(
+            BenchmarkId::new("series", cardinality),
+            &series_sets,
+            |b, series_sets| {
+                b.iter_batched(
+                    || series_sets.iter().map(copy).collect::<Vec<_>>(),
+                    |items| {
+                        for item in items {
+                            data::series_set_item_to_read_response(item).unwrap();
+                        }
+                    },
+                    BatchSize::LargeInput,
+                )
+            },
+        );
+    }
+
+    group.finish();
+}
+
+/// Splits `batch` into the series sets of its series
+async fn convert(batch: Arc<RecordBatch>) -> Vec<SeriesSet> {
+    let (tx, mut rx) = mpsc::channel(100);
+    let mut converter = SeriesSetConverter::new(tx);
+    let it = Box::pin(SizedRecordBatchStream::new(batch.schema(), vec![batch]));
+
+    tokio::task::spawn(async move {
+        converter
+            .convert(
+                Arc::new(datasets::MEASUREMENT.to_string()),
+                str_vec_to_arc_vec(&["host", "region"]),
+                FieldColumns::from(vec!["usage_user"]),
+                None,
+                it,
+            )
+            .await
+            .unwrap()
+    });
+
+    let mut series_sets = vec![];
+    while let Some(item) = rx.recv().await {
+        if let SeriesSetItem::Data(series_set) = item.unwrap() {
+            series_sets.push(series_set);
+        }
+    }
+    series_sets
+}
+
+/// Returns a series set item of the same series as `series_set`, sharing its
+/// data, to be framed
+fn copy(series_set: &SeriesSet) -> SeriesSetItem {
+    SeriesSetItem::Data(SeriesSet {
+        table_name: Arc::clone(&series_set.table_name),
+        tags: series_set.tags.clone(),
+        field_indexes: series_set.field_indexes.clone(),
+        start_row: series_set.start_row,
+        num_rows: series_set.num_rows,
+        batch: series_set.batch.clone(),
+    })
+}
+
+criterion_group!(benches, read_framing);
+
+criterion_main!(benches);
@@ -1,14 +1,40 @@
-// Include the GIT_HASH, if any, in `GIT_HASH` environment variable at build
-// time
+// Include information about the build in environment variables available at
+// compile time, so that the server can report exactly what is running:
+//
+// GIT_HASH: the commit being built, if any
+// RUSTC_VERSION: the version of the compiler
+// IOX_FEATURES: comma separated list of the enabled cargo features
+// IOX_PROFILE: the build profile (`debug` or `release`)
 //
 // https://stackoverflow.com/questions/43753491/include-git-commit-hash-as-string-into-rust-program
-use std::process::Command;
+use std::{env, process::Command};
+
 fn main() {
     let output = Command::new("git").args(&["rev-parse", "HEAD"]).output();
 
     if let Ok(output) = output {
         if let Ok(git_hash) = String::from_utf8(output.stdout) {
-            println!("cargo:rustc-env=GIT_HASH={}", git_hash);
+            println!("cargo:rustc-env=GIT_HASH={}", git_hash.trim());
+        }
+    }
+
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    if let Ok(output) = Command::new(rustc).arg("--version").output() {
+        if let Ok(version) = String::from_utf8(output.stdout) {
+            println!("cargo:rustc-env=RUSTC_VERSION={}", version.trim());
         }
     }
+
+    let mut features: Vec<_> = env::vars()
+        .filter_map(|(name, _)| {
+            name.strip_prefix("CARGO_FEATURE_")
+                .map(|feature| feature.to_ascii_lowercase().replace('_', "-"))
+        })
+        .collect();
+    features.sort();
+    println!("cargo:rustc-env=IOX_FEATURES={}", features.join(","));
+
+    if let Ok(profile) = env::var("PROFILE") {
+        println!("cargo:rustc-env=IOX_PROFILE={}", profile);
+    }
 }
@@ -2,6 +2,8 @@
 //! based on `DatabaseRules`.
 
 use crate::database_rules::Partitioner;
+use crate::flatbuffer_validation;
+use crate::pool::Pool;
 use crate::TIME_COLUMN_NAME;
 use generated_types::wal as wb;
 use influxdb_line_protocol::{FieldValue, ParsedLine};
@@ -10,7 +12,21 @@ use std::{collections::BTreeMap, fmt};
 
 use chrono::Utc;
 use crc32fast::Hasher;
-use flatbuffers::FlatBufferBuilder;
+use flatbuffers::{FlatBufferBuilder, WIPOffset};
+use once_cell::sync::Lazy;
+use snafu::{ensure, ResultExt, Snafu};
+
+/// Errors checking a replicated write received from another server
+#[derive(Debug, Snafu)]
+pub enum ReplicatedWriteError {
+    #[snafu(display("Invalid replicated write: {}", source))]
+    InvalidFlatbuffer {
+        source: flatbuffer_validation::Error,
+    },
+
+    #[snafu(display("Invalid replicated write: the checksum of its payload doesn't match"))]
+    ChecksumMismatch,
+}
 
 pub fn type_description(value: wb::ColumnValue) -> &'static str {
     use wb::ColumnValue::*;
@@ -33,6 +49,21 @@ pub struct ReplicatedWrite {
 }
 
 impl ReplicatedWrite {
+    /// Returns the write in `data`, received from another server, after
+    /// checking its structure and the checksum of its payload, as reading a
+    /// malformed write may panic or read out of bounds
+    pub fn verified(data: &[u8]) -> Result<Self, ReplicatedWriteError> {
+        flatbuffer_validation::replicated_write(data).context(InvalidFlatbuffer)?;
+        let write = Self::from(data);
+
+        let fb = write.to_fb();
+        let mut hasher = Hasher::new();
+        hasher.update(fb.payload().unwrap_or_default());
+        ensure!(hasher.finalize() == fb.checksum(), ChecksumMismatch);
+
+        Ok(write)
+    }
+
     /// Returns the Flatbuffers struct represented by the raw bytes.
     pub fn to_fb(&self) -> wb::ReplicatedWrite<'_> {
         flatbuffers::get_root::<wb::ReplicatedWrite<'_>>(&self.data)
@@ -160,6 +191,89 @@ impl fmt::Display for ReplicatedWrite {
     }
 }
 
+/// The number of encoders kept in `ENCODERS` between writes
+const MAX_IDLE_ENCODERS: usize = 64;
+
+/// Encoders whose last write was larger than this are dropped rather than
+/// kept, so that a large write doesn't hold on to its buffer
+const MAX_POOLED_ENCODER_BYTES: usize = 1024 * 1024;
+
+/// The encoders of writes, reused rather than allocating a flatbuffer and
+/// vectors of offsets for each write (see [`crate::pool`])
+static ENCODERS: Lazy<Pool<Encoder>> =
+    Lazy::new(|| Pool::new(MAX_IDLE_ENCODERS, Encoder::new, Encoder::recycle));
+
+/// A flatbuffer builder and the vectors of offsets used while encoding lines
+struct Encoder {
+    fbb: FlatBufferBuilder<'static>,
+    /// The rows of the table batch being encoded
+    rows: Vec<WIPOffset<wb::Row<'static>>>,
+    /// The values of the row being encoded
+    row_values: Vec<WIPOffset<wb::Value<'static>>>,
+    /// The size of the last finished buffer
+    encoded: usize,
+}
+
+impl Encoder {
+    fn new() -> Self {
+        Self {
+            fbb: FlatBufferBuilder::new_with_capacity(1024),
+            rows: Vec::new(),
+            row_values: Vec::new(),
+            encoded: 0,
+        }
+    }
+
+    fn recycle(&mut self) -> bool {
+        let keep = self.encoded <= MAX_POOLED_ENCODER_BYTES;
+        self.fbb.reset();
+        self.rows.clear();
+        self.row_values.clear();
+        self.encoded = 0;
+        keep
+    }
+
+    /// Encodes `lines` as a `WriteBufferBatch` of an entry per partition and
+    /// finishes the buffer
+    fn write_batch(
+        &mut self,
+        partition_key_fn: impl Fn(&ParsedLine<'_>) -> String,
+        lines: &[ParsedLine<'_>],
+    ) {
+        // split the lines into collections that go into partitions
+        let mut partition_writes = BTreeMap::new();
+
+        for line in lines {
+            let key = partition_key_fn(line);
+
+            partition_writes
+                .entry(key)
+                .or_insert_with(Vec::new)
+                .push(line);
+        }
+
+        // create a WALEntry for each batch of lines going to a partition (one
+        // WALEntry per partition)
+        let entries = partition_writes
+            .into_iter()
+            .map(|(key, lines)| add_write_entry(self, Some(&key), &lines))
+            .collect::<Vec<_>>();
+
+        let fbb = &mut self.fbb;
+        let entries_vec = fbb.create_vector(&entries);
+
+        let batch = wb::WriteBufferBatch::create(
+            fbb,
+            &wb::WriteBufferBatchArgs {
+                entries: Some(entries_vec),
+            },
+        );
+
+        fbb.finish(batch, None);
+        self.encoded = fbb.finished_data().len();
+    }
+}
+
 pub fn lines_to_replicated_write(
     writer: u32,
     sequence: u64,
@@ -167,20 +281,23 @@ pub fn lines_to_replicated_write(
     partitioner: &impl Partitioner,
 ) -> ReplicatedWrite {
     let default_time = Utc::now();
-    let entry_bytes = split_lines_into_write_entry_partitions(
+    let mut batch = ENCODERS.get();
+    batch.write_batch(
         |line| partitioner.partition_key(line, &default_time).unwrap(),
         lines,
     );
+    let entry_bytes = batch.fbb.finished_data();
 
     let mut hasher = Hasher::new();
-    hasher.update(&entry_bytes);
+    hasher.update(entry_bytes);
     let checksum = hasher.finalize();
 
-    let mut fbb = flatbuffers::FlatBufferBuilder::new_with_capacity(1024);
-    let payload = fbb.create_vector_direct(&entry_bytes);
+    let mut encoder = ENCODERS.get();
+    let fbb = &mut encoder.fbb;
+    let payload = fbb.create_vector_direct(entry_bytes);
 
     let write = wb::ReplicatedWrite::create(
-        &mut fbb,
+        fbb,
         &wb::ReplicatedWriteArgs {
             writer,
             sequence,
@@ -191,57 +308,25 @@ pub fn lines_to_replicated_write(
 
     fbb.finish(write, None);
 
-    let (mut data, idx) = fbb.collapse();
-    ReplicatedWrite {
-        data: data.split_off(idx),
-    }
+    let data = fbb.finished_data().to_vec();
+    encoder.encoded = data.len();
+    ReplicatedWrite { data }
 }
 
 pub fn split_lines_into_write_entry_partitions(
     partition_key_fn: impl Fn(&ParsedLine<'_>) -> String,
     lines: &[ParsedLine<'_>],
 ) -> Vec<u8> {
-    let mut fbb = flatbuffers::FlatBufferBuilder::new_with_capacity(1024);
-
-    // split the lines into collections that go into partitions
-    let mut partition_writes = BTreeMap::new();
-
-    for line in lines {
-        let key = partition_key_fn(line);
-
-        partition_writes
-            .entry(key)
-            .or_insert_with(Vec::new)
-            .push(line);
-    }
-
-    // create a WALEntry for each batch of lines going to a partition (one WALEntry
-    // per partition)
-    let entries = partition_writes
-        .into_iter()
-        .map(|(key, lines)| add_write_entry(&mut fbb, Some(&key), &lines))
-        .collect::<Vec<_>>();
-
-    let entries_vec = fbb.create_vector(&entries);
-
-    let batch = wb::WriteBufferBatch::create(
-        &mut fbb,
-        &wb::WriteBufferBatchArgs {
-            entries: Some(entries_vec),
-        },
-    );
-
-    fbb.finish(batch, None);
-
-    let (mut data, idx) = fbb.collapse();
-    data.split_off(idx)
+    let mut encoder = ENCODERS.get();
+    encoder.write_batch(partition_key_fn, lines);
+    encoder.fbb.finished_data().to_vec()
 }
 
-fn add_write_entry<'a>(
-    fbb: &mut FlatBufferBuilder<'a>,
+fn add_write_entry(
+    encoder: &mut Encoder,
     partition_key: Option<&str>,
     lines: &[&ParsedLine<'_>],
-) -> flatbuffers::WIPOffset<wb::WriteBufferEntry<'a>> {
+) -> WIPOffset<wb::WriteBufferEntry<'static>> {
     // split into tables
     let mut table_batches = BTreeMap::new();
     for line in lines {
@@ -255,10 +340,11 @@ fn add_write_entry<'a>(
     // create TableWriteBatch for each table
     let table_batches = table_batches
         .into_iter()
-        .map(|(name, lines)| add_table_batch(fbb, name, &lines))
+        .map(|(name, lines)| add_table_batch(encoder, name, &lines))
         .collect::<Vec<_>>();
 
     // create write entry
+    let fbb = &mut encoder.fbb;
     let batches_vec = fbb.create_vector(&table_batches);
 
     let args = match partition_key {
@@ -279,19 +365,21 @@ fn add_write_entry<'a>(
     wb::WriteBufferEntry::create(fbb, &args)
 }
 
-fn add_table_batch<'a>(
-    fbb: &mut FlatBufferBuilder<'a>,
+fn add_table_batch(
+    encoder: &mut Encoder,
     name: &str,
     lines: &[&ParsedLine<'_>],
-) -> flatbuffers::WIPOffset<wb::TableWriteBatch<'a>> {
+) -> WIPOffset<wb::TableWriteBatch<'static>> {
     // create Row
-    let rows = lines
-        .iter()
-        .map(|line| add_line(fbb, line))
-        .collect::<Vec<_>>();
+    encoder.rows.clear();
+    for line in lines {
+        let row = add_line(&mut encoder.fbb, &mut encoder.row_values, line);
+        encoder.rows.push(row);
+    }
 
+    let fbb = &mut encoder.fbb;
     let table_name = fbb.create_string(name);
-    let rows = fbb.create_vector(&rows);
+    let rows = fbb.create_vector(&encoder.rows);
 
     wb::TableWriteBatch::create(
         fbb,
@@ -302,11 +390,13 @@ fn add_table_batch<'a>(
     )
 }
 
+/// Encodes `line` as a row, using `row_values` for the offsets of its values
 fn add_line<'a>(
     fbb: &mut FlatBufferBuilder<'a>,
+    row_values: &mut Vec<WIPOffset<wb::Value<'a>>>,
     line: &ParsedLine<'_>,
-) -> flatbuffers::WIPOffset<wb::Row<'a>> {
-    let mut row_values = Vec::new();
+) -> WIPOffset<wb::Row<'a>> {
+    row_values.clear();
 
     if let Some(tags) = &line.series.tag_set {
         for (column, value) in tags {
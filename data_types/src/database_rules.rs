@@ -113,6 +113,11 @@ pub struct DatabaseRules {
     /// in object storage.
     #[serde(default = "MutableBufferConfig::default_option")]
     pub mutable_buffer_config: Option<MutableBufferConfig>,
+
+    /// How long, in seconds, data written to the database is retained. If
+    /// not set, data is retained forever.
+    #[serde(default)]
+    pub retention_period_seconds: Option<u64>,
 }
 
 impl DatabaseRules {
@@ -173,6 +178,7 @@ impl From<DatabaseRules> for management::DatabaseRules {
             query_config: Some(query_config),
             wal_buffer_config: rules.wal_buffer_config.map(Into::into),
             mutable_buffer_config: rules.mutable_buffer_config.map(Into::into),
+            retention_period_seconds: rules.retention_period_seconds.unwrap_or_default(),
         }
     }
 }
@@ -206,6 +212,12 @@ impl TryFrom<management::DatabaseRules> for DatabaseRules {
         let query = proto.query_config.unwrap_or_default();
         let replication = proto.replication_config.unwrap_or_default();
 
+        let retention_period_seconds = if proto.retention_period_seconds == 0 {
+            None
+        } else {
+            Some(proto.retention_period_seconds)
+        };
+
         Ok(Self {
             name: proto.name,
             partition_template,
@@ -219,6 +231,7 @@ impl TryFrom<management::DatabaseRules> for DatabaseRules {
             read_only_partitions: query.read_only_partitions,
             wal_buffer_config,
             mutable_buffer_config,
+            retention_period_seconds,
         })
     }
 }
@@ -1120,6 +1133,24 @@ mod tests {
         // These should be none as preserved on non-protobuf DatabaseRules
         assert!(back.wal_buffer_config.is_none());
         assert!(back.mutable_buffer_config.is_none());
+
+        assert!(rules.retention_period_seconds.is_none());
+        assert_eq!(back.retention_period_seconds, 0);
+    }
+
+    #[test]
+    fn test_database_rules_retention() {
+        let protobuf = management::DatabaseRules {
+            name: "database".to_string(),
+            retention_period_seconds: 3600,
+            ..Default::default()
+        };
+
+        let rules: DatabaseRules = protobuf.clone().try_into().unwrap();
+        let back: management::DatabaseRules = rules.clone().into();
+
+        assert_eq!(rules.retention_period_seconds, Some(3600));
+        assert_eq!(back.retention_period_seconds, 3600);
     }
 
     #[test]
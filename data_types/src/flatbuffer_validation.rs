@@ -0,0 +1,410 @@
+//! Checks of the structure of the flatbuffers received from other servers.
+//!
+//! The `flatbuffers` crate reads a buffer without checking that the offsets
+//! in it are within its bounds, that its strings are UTF-8 or that its
+//! enums and bools hold valid values, so reading a malformed buffer may
+//! panic or read out of bounds. The tables, vectors and strings of a
+//! replicated write are checked with a `Verifier` before it is read, as are
+//! the fields the readers of the write expect to be set.
+
+use std::{convert::TryInto, str};
+
+use generated_types::wal as wb;
+use snafu::{ensure, OptionExt, Snafu};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("{} at {} is out of bounds", what, position))]
+    OutOfBounds { what: &'static str, position: usize },
+
+    #[snafu(display("invalid vtable for the table at {}", position))]
+    InvalidVTable { position: usize },
+
+    #[snafu(display("string at {} is not UTF-8", position))]
+    InvalidUtf8 { position: usize },
+
+    #[snafu(display("invalid {} {} at {}", what, value, position))]
+    InvalidValue {
+        what: &'static str,
+        value: u8,
+        position: usize,
+    },
+
+    #[snafu(display("missing {}", what))]
+    MissingField { what: &'static str },
+
+    #[snafu(display("more than {} tables and vectors", max))]
+    TooManyObjects { max: usize },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Checks the `ReplicatedWrite` in `buf` and the `WriteBufferBatch` of its
+/// payload
+pub fn replicated_write(buf: &[u8]) -> Result<()> {
+    let mut verifier = Verifier::new(buf);
+    let write = verifier.root()?;
+    verifier.scalar(&write, wb::ReplicatedWrite::VT_WRITER, 4)?;
+    verifier.scalar(&write, wb::ReplicatedWrite::VT_SEQUENCE, 8)?;
+    verifier.scalar(&write, wb::ReplicatedWrite::VT_CHECKSUM, 4)?;
+    if let Some(payload) = verifier.bytes(&write, wb::ReplicatedWrite::VT_PAYLOAD)? {
+        write_buffer_batch(payload)?;
+    }
+    Ok(())
+}
+
+fn write_buffer_batch(buf: &[u8]) -> Result<()> {
+    let mut verifier = Verifier::new(buf);
+    let batch = verifier.root()?;
+    for entry in verifier.tables(&batch, wb::WriteBufferBatch::VT_ENTRIES)? {
+        verifier
+            .string(&entry, wb::WriteBufferEntry::VT_PARTITION_KEY)?
+            .context(MissingField {
+                what: "partition key",
+            })?;
+        for table_batch in verifier.tables(&entry, wb::WriteBufferEntry::VT_TABLE_BATCHES)? {
+            verifier.string(&table_batch, wb::TableWriteBatch::VT_NAME)?;
+            for row in verifier.tables(&table_batch, wb::TableWriteBatch::VT_ROWS)? {
+                for value in verifier.tables(&row, wb::Row::VT_VALUES)? {
+                    column_value(&mut verifier, &value)?;
+                }
+            }
+        }
+        if let Some(delete) = verifier.table(&entry, wb::WriteBufferEntry::VT_DELETE)? {
+            verifier.string(&delete, wb::WriteBufferDelete::VT_TABLE_NAME)?;
+            verifier.string(&delete, wb::WriteBufferDelete::VT_PREDICATE)?;
+        }
+    }
+    Ok(())
+}
+
+fn column_value(verifier: &mut Verifier<'_>, value: &Table) -> Result<()> {
+    use wb::ColumnValue::*;
+
+    verifier.string(value, wb::Value::VT_COLUMN)?;
+    // StringValue is the last variant of the union
+    let value_type = verifier.union_type(value, wb::Value::VT_VALUE_TYPE, StringValue as u8)?;
+    if value_type == NONE as u8 {
+        return Ok(());
+    }
+    let value = verifier
+        .table(value, wb::Value::VT_VALUE)?
+        .context(MissingField { what: "value" })?;
+
+    if value_type == TagValue as u8 {
+        verifier
+            .string(&value, wb::TagValue::VT_VALUE)?
+            .context(MissingField { what: "tag value" })?;
+    } else if value_type == StringValue as u8 {
+        verifier
+            .string(&value, wb::StringValue::VT_VALUE)?
+            .context(MissingField {
+                what: "string value",
+            })?;
+    } else if value_type == BoolValue as u8 {
+        verifier.bool(&value, wb::BoolValue::VT_VALUE)?;
+    } else {
+        // the values of the numeric types all take 8 bytes
+        verifier.scalar(&value, wb::I64Value::VT_VALUE, 8)?;
+    }
+    Ok(())
+}
+
+/// A table whose vtable and inline fields are within the buffer
+#[derive(Debug, Clone, Copy)]
+struct Table {
+    position: usize,
+    vtable: usize,
+    vtable_len: usize,
+    table_len: usize,
+}
+
+/// Checks the objects of a flatbuffer as they are visited. As the same
+/// object may be referenced many times, the number of tables and vectors
+/// visited is bounded by the size of the buffer, each taking at least 4
+/// bytes, so that checking a buffer takes time linear in its size.
+#[derive(Debug)]
+struct Verifier<'a> {
+    buf: &'a [u8],
+    visited: usize,
+}
+
+impl<'a> Verifier<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Self { buf, visited: 0 }
+    }
+
+    fn max_objects(&self) -> usize {
+        self.buf.len() / 4
+    }
+
+    fn visit(&mut self) -> Result<()> {
+        self.visited += 1;
+        ensure!(
+            self.visited <= self.max_objects(),
+            TooManyObjects {
+                max: self.max_objects()
+            }
+        );
+        Ok(())
+    }
+
+    /// Returns the `len` bytes at `position`
+    fn slice(&self, position: usize, len: usize, what: &'static str) -> Result<&'a [u8]> {
+        let buf = self.buf;
+        position
+            .checked_add(len)
+            .and_then(|end| buf.get(position..end))
+            .context(OutOfBounds { what, position })
+    }
+
+    fn read_u16(&self, position: usize) -> Result<usize> {
+        let bytes = self.slice(position, 2, "u16")?;
+        Ok(u16::from_le_bytes(bytes.try_into().expect("2 bytes")) as usize)
+    }
+
+    fn read_u32(&self, position: usize) -> Result<usize> {
+        let bytes = self.slice(position, 4, "u32")?;
+        Ok(u32::from_le_bytes(bytes.try_into().expect("4 bytes")) as usize)
+    }
+
+    /// Returns the position the offset at `position` points to
+    fn offset(&self, position: usize) -> Result<usize> {
+        let offset = self.read_u32(position)?;
+        position
+            .checked_add(offset)
+            .filter(|target| *target < self.buf.len())
+            .context(OutOfBounds {
+                what: "offset",
+                position,
+            })
+    }
+
+    fn root(&mut self) -> Result<Table> {
+        let position = self.offset(0)?;
+        self.table_at(position)
+    }
+
+    fn table_at(&mut self, position: usize) -> Result<Table> {
+        self.visit()?;
+        let bytes = self.slice(position, 4, "table")?;
+        let vtable_offset = i32::from_le_bytes(bytes.try_into().expect("4 bytes"));
+        let vtable = (position as i64)
+            .checked_sub(vtable_offset.into())
+            .filter(|vtable| *vtable >= 0)
+            .context(InvalidVTable { position })? as usize;
+
+        let vtable_len = self.read_u16(vtable)?;
+        let table_len = self.read_u16(vtable + 2)?;
+        ensure!(
+            vtable_len >= 4 && vtable_len % 2 == 0 && table_len >= 4,
+            InvalidVTable { position }
+        );
+        self.slice(vtable, vtable_len, "vtable")?;
+        self.slice(position, table_len, "table")?;
+
+        Ok(Table {
+            position,
+            vtable,
+            vtable_len,
+            table_len,
+        })
+    }
+
+    /// Returns the position of the field of `table` with the vtable offset
+    /// `field`, if it is set, checking that its `size` bytes are within the
+    /// table
+    fn field(&self, table: &Table, field: u16, size: usize) -> Result<Option<usize>> {
+        let field = field as usize;
+        if field + 2 > table.vtable_len {
+            return Ok(None);
+        }
+        let offset = self.read_u16(table.vtable + field)?;
+        if offset == 0 {
+            return Ok(None);
+        }
+        let position = table.position + offset;
+        ensure!(
+            offset + size <= table.table_len,
+            OutOfBounds {
+                what: "field",
+                position
+            }
+        );
+        Ok(Some(position))
+    }
+
+    fn scalar(&self, table: &Table, field: u16, size: usize) -> Result<()> {
+        self.field(table, field, size).map(|_| ())
+    }
+
+    fn bool(&self, table: &Table, field: u16) -> Result<()> {
+        if let Some(position) = self.field(table, field, 1)? {
+            let value = self.buf[position];
+            ensure!(
+                value <= 1,
+                InvalidValue {
+                    what: "bool",
+                    value,
+                    position
+                }
+            );
+        }
+        Ok(())
+    }
+
+    /// Returns the type of a union, checking it is at most `max`, the last
+    /// variant. Unset, it is 0, the `NONE` variant.
+    fn union_type(&self, table: &Table, field: u16, max: u8) -> Result<u8> {
+        match self.field(table, field, 1)? {
+            Some(position) => {
+                let value = self.buf[position];
+                ensure!(
+                    value <= max,
+                    InvalidValue {
+                        what: "union type",
+                        value,
+                        position
+                    }
+                );
+                Ok(value)
+            }
+            None => Ok(0),
+        }
+    }
+
+    fn table(&mut self, table: &Table, field: u16) -> Result<Option<Table>> {
+        match self.field(table, field, 4)? {
+            Some(position) => {
+                let target = self.offset(position)?;
+                self.table_at(target).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the position of the first element of a vector of elements of
+    /// `element_size` bytes and its length, checking they are in the buffer
+    fn vector(
+        &mut self,
+        table: &Table,
+        field: u16,
+        element_size: usize,
+    ) -> Result<Option<(usize, usize)>> {
+        let position = match self.field(table, field, 4)? {
+            Some(position) => position,
+            None => return Ok(None),
+        };
+        self.visit()?;
+        let vector = self.offset(position)?;
+        let len = self.read_u32(vector)?;
+        let size = len.checked_mul(element_size).context(OutOfBounds {
+            what: "vector",
+            position: vector,
+        })?;
+        self.slice(vector + 4, size, "vector")?;
+        Ok(Some((vector + 4, len)))
+    }
+
+    fn bytes(&mut self, table: &Table, field: u16) -> Result<Option<&'a [u8]>> {
+        Ok(match self.vector(table, field, 1)? {
+            Some((start, len)) => Some(&self.buf[start..start + len]),
+            None => None,
+        })
+    }
+
+    fn string(&mut self, table: &Table, field: u16) -> Result<Option<&'a str>> {
+        match self.vector(table, field, 1)? {
+            Some((start, len)) => str::from_utf8(&self.buf[start..start + len])
+                .ok()
+                .context(InvalidUtf8 { position: start })
+                .map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the tables of a vector of tables, none if it is unset
+    fn tables(&mut self, table: &Table, field: u16) -> Result<Vec<Table>> {
+        let (start, len) = match self.vector(table, field, 4)? {
+            Some(vector) => vector,
+            None => return Ok(vec![]),
+        };
+        (0..len)
+            .map(|i| {
+                let target = self.offset(start + 4 * i)?;
+                self.table_at(target)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        data::{lines_to_replicated_write, ReplicatedWrite, ReplicatedWriteError},
+        database_rules::DatabaseRules,
+    };
+    use influxdb_line_protocol::parse_lines;
+
+    fn write() -> ReplicatedWrite {
+        let lp = "cpu,host=a usage=0.5,count=3i,ok=true,msg=\"up\" 10\nmem,host=b free=2u 20";
+        let lines: Vec<_> = parse_lines(lp).map(|l| l.unwrap()).collect();
+        lines_to_replicated_write(1, 2, &lines, &DatabaseRules::new())
+    }
+
+    #[test]
+    fn valid_write() {
+        let write = write();
+        let verified = ReplicatedWrite::verified(write.bytes()).unwrap();
+        assert_eq!(verified, write);
+    }
+
+    #[test]
+    fn malformed_writes() {
+        let data = write().bytes().clone();
+        assert!(replicated_write(&data[..data.len() / 2]).is_err());
+
+        // however the write is truncated or whichever byte is changed, a
+        // write that passes the checks can be read
+        for len in 0..data.len() {
+            if replicated_write(&data[..len]).is_ok() {
+                ReplicatedWrite::from(&data[..len]).to_string();
+            }
+        }
+        for position in 0..data.len() {
+            for value in &[0, 1, 0x7f, 0xff] {
+                let mut data = data.clone();
+                data[position] = *value;
+                if replicated_write(&data).is_ok() {
+                    ReplicatedWrite::from(data.as_slice()).to_string();
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn checksum_mismatch() {
+        let write = write();
+        let fb = write.to_fb();
+        let mut fbb = flatbuffers::FlatBufferBuilder::new();
+        let payload = fbb.create_vector_direct(fb.payload().unwrap());
+        let corrupted = wb::ReplicatedWrite::create(
+            &mut fbb,
+            &wb::ReplicatedWriteArgs {
+                writer: fb.writer(),
+                sequence: fb.sequence(),
+                checksum: fb.checksum().wrapping_add(1),
+                payload: Some(payload),
+            },
+        );
+        fbb.finish(corrupted, None);
+
+        let err = ReplicatedWrite::verified(fbb.finished_data()).unwrap_err();
+        assert!(
+            matches!(err, ReplicatedWriteError::ChecksumMismatch),
+            "{}",
+            err
+        );
+    }
+}
@@ -24,3 +24,82 @@ pub struct WalMetadataResponse {
 pub struct ListDatabasesResponse {
     pub names: Vec<String>,
 }
+
+/// A retention rule of a bucket in the InfluxDB 2.x /buckets API
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RetentionRule {
+    /// Always `expire`, the only type of rule supported
+    #[serde(rename = "type", default = "RetentionRule::expire_type")]
+    pub rule_type: String,
+
+    /// How long data is kept for; 0 keeps data forever
+    pub every_seconds: u64,
+}
+
+impl RetentionRule {
+    fn expire_type() -> String {
+        "expire".to_string()
+    }
+
+    /// Returns the retention rules describing a retention period of
+    /// `retention_period_seconds`, where `None` means forever
+    pub fn from_retention_period(retention_period_seconds: Option<u64>) -> Vec<Self> {
+        vec![Self {
+            rule_type: Self::expire_type(),
+            every_seconds: retention_period_seconds.unwrap_or_default(),
+        }]
+    }
+
+    /// Returns the retention period described by `rules`, where `None`
+    /// means forever
+    pub fn retention_period(rules: &[Self]) -> Option<u64> {
+        rules
+            .iter()
+            .find(|rule| rule.rule_type == "expire")
+            .map(|rule| rule.every_seconds)
+            .filter(|&seconds| seconds > 0)
+    }
+}
+
+/// A bucket in the InfluxDB 2.x /buckets API. Used as the body of the
+/// request to create a bucket and in responses describing buckets.
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Bucket {
+    /// The org the bucket belongs to. IOx has no org ids so the `orgID`
+    /// sent by InfluxDB 2.x clients is treated as the org name.
+    #[serde(alias = "orgID")]
+    pub org: String,
+
+    pub name: String,
+
+    #[serde(default)]
+    pub retention_rules: Vec<RetentionRule>,
+}
+
+/// Body of the request to update a bucket
+#[derive(Debug, Clone, Serialize, Deserialize, Default, Eq, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PatchBucketRequest {
+    /// If set, replaces the retention rules of the bucket
+    #[serde(default)]
+    pub retention_rules: Option<Vec<RetentionRule>>,
+}
+
+/// Body of the response to listing buckets
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct ListBucketsResponse {
+    pub buckets: Vec<Bucket>,
+
+    #[serde(default)]
+    pub links: PageLinks,
+}
+
+/// Links to the pages adjacent to a page of a listing
+#[derive(Debug, Clone, Serialize, Deserialize, Default, Eq, PartialEq)]
+pub struct PageLinks {
+    /// The path and query of the next page, if there are more results
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next: Option<String>,
+}
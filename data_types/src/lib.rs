@@ -26,6 +26,8 @@ pub mod error;
 pub mod http;
 pub mod names;
 pub mod partition_metadata;
+pub mod pool;
+pub mod redaction;
 pub mod schema;
 pub mod selection;
 pub mod wal;
@@ -34,3 +36,4 @@ mod database_name;
 pub use database_name::*;
 
 pub(crate) mod field_validation;
+pub(crate) mod flatbuffer_validation;
@@ -1,7 +1,7 @@
 use std::borrow::Cow;
 
 use crate::{DatabaseName, DatabaseNameError};
-use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, NON_ALPHANUMERIC};
 use snafu::{ResultExt, Snafu};
 
 #[derive(Debug, Snafu)]
@@ -29,6 +29,21 @@ pub fn org_and_bucket_to_database<'a, O: AsRef<str>, B: AsRef<str>>(
     DatabaseName::new(db_name).context(InvalidDatabaseName)
 }
 
+/// The inverse of [`org_and_bucket_to_database`], mapping a database name
+/// back to the InfluxDB 2.X org & bucket it was created for.
+///
+/// Returns `None` if `db_name` was not created from an org & bucket.
+pub fn database_to_org_and_bucket(db_name: &str) -> Option<(String, String)> {
+    let mut parts = db_name.splitn(2, '_');
+    let org = parts.next()?;
+    let bucket = parts.next()?;
+
+    let org = percent_decode_str(org).decode_utf8().ok()?;
+    let bucket = percent_decode_str(bucket).decode_utf8().ok()?;
+
+    Some((org.into_owned(), bucket.into_owned()))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -72,4 +87,22 @@ mod tests {
         let got = org_and_bucket_to_database("org!", "bucket").unwrap();
         assert_eq!(got.as_str(), "org%21_bucket");
     }
+
+    #[test]
+    fn test_database_to_org_and_bucket() {
+        let cases = vec![
+            ("org", "bucket"),
+            ("my_org", "my_bucket"),
+            ("my%5Forg_", "bucket?"),
+        ];
+        for (org, bucket) in cases {
+            let db_name = org_and_bucket_to_database(org, bucket).unwrap();
+            assert_eq!(
+                database_to_org_and_bucket(db_name.as_str()),
+                Some((org.to_string(), bucket.to_string()))
+            );
+        }
+
+        assert_eq!(database_to_org_and_bucket("no-separator"), None);
+    }
 }
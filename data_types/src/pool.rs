@@ -0,0 +1,137 @@
+//! A pool of objects reused across requests, such as the buffers of the
+//! write path.
+//!
+//! Under a steady rate of writes, each request would otherwise allocate and
+//! free the same buffers: the body it reads, the flatbuffer it encodes the
+//! lines into and the vectors of offsets of the rows. The pool keeps the
+//! buffers of finished requests, cleared but with their capacity, for the
+//! next requests to take. It keeps at most `max_idle` of them, and drops
+//! those its recycle function rejects, so that a single large request
+//! doesn't hold on to its memory for ever.
+use parking_lot::Mutex;
+use std::{
+    fmt,
+    ops::{Deref, DerefMut},
+};
+
+/// A pool of objects, as described in the module docs
+pub struct Pool<T> {
+    idle: Mutex<Vec<T>>,
+    max_idle: usize,
+    /// Creates an object when none is idle
+    create: fn() -> T,
+    /// Clears an object given back to the pool, returning false if it
+    /// should be dropped rather than reused
+    recycle: fn(&mut T) -> bool,
+}
+
+impl<T> fmt::Debug for Pool<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Pool")
+            .field("idle", &self.idle())
+            .field("max_idle", &self.max_idle)
+            .finish()
+    }
+}
+
+impl<T> Pool<T> {
+    pub fn new(max_idle: usize, create: fn() -> T, recycle: fn(&mut T) -> bool) -> Self {
+        Self {
+            idle: Mutex::new(Vec::with_capacity(max_idle)),
+            max_idle,
+            create,
+            recycle,
+        }
+    }
+
+    /// Takes an idle object, or creates one if none is, which goes back to
+    /// the pool when dropped
+    pub fn get(&self) -> Pooled<'_, T> {
+        let item = self.idle.lock().pop().unwrap_or_else(self.create);
+        Pooled {
+            pool: self,
+            item: Some(item),
+        }
+    }
+
+    /// Returns the number of objects waiting to be reused
+    pub fn idle(&self) -> usize {
+        self.idle.lock().len()
+    }
+
+    fn put(&self, mut item: T) {
+        if (self.recycle)(&mut item) {
+            let mut idle = self.idle.lock();
+            if idle.len() < self.max_idle {
+                idle.push(item);
+            }
+        }
+    }
+}
+
+/// An object taken from a `Pool`, given back to it when dropped
+#[derive(Debug)]
+pub struct Pooled<'a, T> {
+    pool: &'a Pool<T>,
+    /// Only None once dropped
+    item: Option<T>,
+}
+
+impl<T> Deref for Pooled<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.item.as_ref().expect("pooled item taken")
+    }
+}
+
+impl<T> DerefMut for Pooled<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.item.as_mut().expect("pooled item taken")
+    }
+}
+
+impl<T> Drop for Pooled<'_, T> {
+    fn drop(&mut self) {
+        if let Some(item) = self.item.take() {
+            self.pool.put(item);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reuse() {
+        // buffers of more than 8 bytes are dropped
+        let pool: Pool<Vec<u8>> = Pool::new(2, Vec::new, |buf| {
+            buf.clear();
+            buf.capacity() <= 8
+        });
+
+        let mut buf = pool.get();
+        buf.extend_from_slice(b"abc");
+        let ptr = buf.as_ptr();
+        drop(buf);
+        assert_eq!(pool.idle(), 1);
+
+        // the buffer is reused, cleared
+        let buf = pool.get();
+        assert!(buf.is_empty());
+        assert_eq!(buf.as_ptr(), ptr);
+        assert_eq!(pool.idle(), 0);
+        drop(buf);
+
+        let mut large = pool.get();
+        large.extend_from_slice(&[0; 100]);
+        drop(large);
+        assert_eq!(pool.idle(), 0);
+
+        // at most max_idle are kept
+        let bufs = vec![pool.get(), pool.get(), pool.get()];
+        drop(bufs);
+        assert_eq!(pool.idle(), 2);
+    }
+}
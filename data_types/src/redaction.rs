@@ -0,0 +1,191 @@
+//! Redaction of data values in logs, traces and error messages.
+//!
+//! Tag values and field contents may hold personal data that deployments
+//! don't want to end up in their logs. When redaction is enabled, the
+//! values formatted through [`Redacted`] are replaced either by
+//! `<redacted>` or by a hash of the value, which still lets the messages
+//! about the same value be correlated. Measurement, tag and field names are
+//! not redacted.
+//!
+//! The redaction mode is set for the whole process at startup, as values
+//! are formatted into messages all over the server.
+
+use std::{
+    collections::hash_map::DefaultHasher,
+    fmt,
+    hash::{Hash, Hasher},
+    str::FromStr,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+/// The mode of the process, as a `Redaction` discriminant
+static MODE: AtomicU8 = AtomicU8::new(Redaction::Off as u8);
+
+/// How data values are shown in logs, traces and error messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Redaction {
+    /// Values are shown as they are
+    Off,
+    /// Values are replaced by a hash of them. Hashes are only stable for a
+    /// given build of the server.
+    Hash,
+    /// Values are replaced by `<redacted>`
+    Redact,
+}
+
+impl Default for Redaction {
+    fn default() -> Self {
+        Self::Off
+    }
+}
+
+impl Redaction {
+    /// Returns the mode of the process
+    pub fn current() -> Self {
+        match MODE.load(Ordering::Relaxed) {
+            m if m == Self::Hash as u8 => Self::Hash,
+            m if m == Self::Redact as u8 => Self::Redact,
+            _ => Self::Off,
+        }
+    }
+
+    /// Makes `self` the mode of the process
+    pub fn install(self) {
+        MODE.store(self as u8, Ordering::Relaxed)
+    }
+
+    /// Whether values are hidden in this mode
+    pub fn is_enabled(self) -> bool {
+        self != Self::Off
+    }
+
+    /// Formats `value` as shown in this mode
+    fn fmt_value(self, value: &dyn fmt::Display, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Off => value.fmt(f),
+            Self::Hash => {
+                let mut hasher = DefaultHasher::new();
+                value.to_string().hash(&mut hasher);
+                write!(f, "<hash:{:016x}>", hasher.finish())
+            }
+            Self::Redact => f.write_str("<redacted>"),
+        }
+    }
+
+    /// Returns `value` as shown in this mode
+    pub fn value(self, value: impl fmt::Display) -> String {
+        struct Wrapper<T>(Redaction, T);
+
+        impl<T: fmt::Display> fmt::Display for Wrapper<T> {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                self.0.fmt_value(&self.1, f)
+            }
+        }
+        Wrapper(self, value).to_string()
+    }
+
+    /// Returns the message of the line protocol parsing error `error`, with
+    /// the values it quotes shown as in this mode
+    pub fn line_protocol_error(self, error: &influxdb_line_protocol::Error) -> String {
+        use influxdb_line_protocol::Error::*;
+        match error {
+            IntegerValueInvalid { value, .. } => {
+                format!("Unable to parse integer value '{}'", self.value(value))
+            }
+            FloatValueInvalid { value, .. } => format!(
+                "Unable to parse floating-point value '{}'",
+                self.value(value)
+            ),
+            TimestampValueInvalid { value, .. } => {
+                format!("Unable to parse timestamp value '{}'", self.value(value))
+            }
+            CannotParseEntireLine { trailing_content } => format!(
+                "Could not parse entire line. Found trailing content: '{}'",
+                self.value(trailing_content)
+            ),
+            _ => error.to_string(),
+        }
+    }
+}
+
+impl FromStr for Redaction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "off" => Ok(Self::Off),
+            "hash" => Ok(Self::Hash),
+            "redact" => Ok(Self::Redact),
+            _ => Err(format!(
+                "Invalid redaction mode '{}'. Valid options: off, hash, redact",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Redaction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Off => write!(f, "off"),
+            Self::Hash => write!(f, "hash"),
+            Self::Redact => write!(f, "redact"),
+        }
+    }
+}
+
+/// Formats the data value it wraps for Display as shown in the mode of the
+/// process
+///
+/// For example:
+/// `error!("Dictionary lookup error for value {}", Redacted(value))`
+#[derive(Debug, Clone, Copy)]
+pub struct Redacted<T>(pub T);
+
+impl<T: fmt::Display> fmt::Display for Redacted<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Redaction::current().fmt_value(&self.0, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values() {
+        assert_eq!(Redaction::Off.value("server01"), "server01");
+        assert_eq!(Redaction::Redact.value("server01"), "<redacted>");
+
+        let hash = Redaction::Hash.value("server01");
+        assert!(hash.starts_with("<hash:"));
+        assert!(!hash.contains("server01"));
+        assert_eq!(hash, Redaction::Hash.value(String::from("server01")));
+        assert_ne!(hash, Redaction::Hash.value("server02"));
+    }
+
+    #[test]
+    fn line_protocol_errors() {
+        let error =
+            influxdb_line_protocol::parse_lines("cpu,host=a usage=99999999999999999999i 10")
+                .next()
+                .unwrap()
+                .unwrap_err();
+        assert_eq!(
+            Redaction::Off.line_protocol_error(&error),
+            error.to_string()
+        );
+        let redacted = Redaction::Redact.line_protocol_error(&error);
+        assert!(redacted.contains("<redacted>"), "{}", redacted);
+        assert!(!redacted.contains("9999"), "{}", redacted);
+    }
+
+    #[test]
+    fn modes() {
+        assert_eq!("Hash".parse::<Redaction>().unwrap(), Redaction::Hash);
+        assert_eq!("redact".parse::<Redaction>().unwrap(), Redaction::Redact);
+        assert!("mask".parse::<Redaction>().is_err());
+        assert!(!Redaction::default().is_enabled());
+        assert_eq!(Redaction::Redact.to_string(), "redact");
+    }
+}
@@ -24,10 +24,14 @@ fn main() -> Result<()> {
 /// - `influxdata.platform.storage.rs`
 /// - `com.github.influxdata.idpe.storage.read.rs`
 /// - `influxdata.iox.management.v1.rs`
+/// - `influxdata.iox.write.v1.rs`
+/// - `influxdata.iox.cluster.v1.rs`
 fn generate_grpc_types(root: &Path) -> Result<()> {
     let storage_path = root.join("influxdata/platform/storage");
     let idpe_path = root.join("com/github/influxdata/idpe/storage/read");
     let management_path = root.join("influxdata/iox/management/v1");
+    let write_path = root.join("influxdata/iox/write/v1");
+    let cluster_path = root.join("influxdata/iox/cluster/v1");
     let grpc_path = root.join("grpc/health/v1");
 
     let proto_files = vec![
@@ -40,6 +44,8 @@ fn generate_grpc_types(root: &Path) -> Result<()> {
         management_path.join("base_types.proto"),
         management_path.join("database_rules.proto"),
         management_path.join("service.proto"),
+        write_path.join("service.proto"),
+        cluster_path.join("service.proto"),
         grpc_path.join("service.proto"),
     ];
 
@@ -33,6 +33,18 @@ mod pb {
                     include!(concat!(env!("OUT_DIR"), "/influxdata.iox.management.v1.rs"));
                 }
             }
+
+            pub mod write {
+                pub mod v1 {
+                    include!(concat!(env!("OUT_DIR"), "/influxdata.iox.write.v1.rs"));
+                }
+            }
+
+            pub mod cluster {
+                pub mod v1 {
+                    include!(concat!(env!("OUT_DIR"), "/influxdata.iox.cluster.v1.rs"));
+                }
+            }
         }
     }
 
@@ -71,6 +83,10 @@ pub const STORAGE_SERVICE: &str = "influxdata.platform.storage.Storage";
 pub const IOX_TESTING_SERVICE: &str = "influxdata.platform.storage.IOxTesting";
 /// gRPC Arrow Flight Service
 pub const ARROW_SERVICE: &str = "arrow.flight.protocol.FlightService";
+/// gRPC IOx Write Service
+pub const WRITE_SERVICE: &str = "influxdata.iox.write.v1.WriteService";
+/// gRPC IOx Cluster Service
+pub const CLUSTER_SERVICE: &str = "influxdata.iox.cluster.v1.ClusterService";
 
 pub use pb::com::github::influxdata::idpe::storage::read::*;
 pub use pb::influxdata::platform::storage::*;
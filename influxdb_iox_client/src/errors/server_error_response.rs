@@ -11,6 +11,8 @@ use reqwest::Response;
 /// generic "server error" response.
 #[derive(Debug, serde::Deserialize)]
 pub struct ServerErrorResponse {
+    // older servers sent the message as "error"
+    #[serde(rename = "message", alias = "error")]
     error: String,
     error_code: Option<u32>,
     http_status: Option<u16>,
@@ -31,7 +33,7 @@ impl std::fmt::Display for ServerErrorResponse {
 impl std::error::Error for ServerErrorResponse {}
 
 impl ServerErrorResponse {
-    /// Try and parse a JSON "error" field from the [reqwest::Response].
+    /// Try and parse a JSON error body from the [reqwest::Response].
     pub(crate) async fn from_response(r: Response) -> Self {
         let status = r.status().as_u16();
         match r.json::<ServerErrorResponse>().await {
@@ -70,6 +72,22 @@ mod tests {
         assert_eq!(response.http_status, None);
     }
 
+    #[test]
+    fn test_parse_influx_error_response() {
+        let body = serde_json::json!({
+            "code": "invalid",
+            "message": "something terrible",
+            "line": 3,
+            "error_code": 42
+        })
+        .to_string();
+
+        let response: ServerErrorResponse = serde_json::from_str(&body).unwrap();
+
+        assert_eq!(response.error, "something terrible");
+        assert_eq!(response.error_code, Some(42));
+    }
+
     #[test]
     fn test_parse_generic_error_no_code() {
         let body = serde_json::json!({"error": "something terrible"}).to_string();
@@ -141,7 +141,7 @@ impl nom::error::ParseError<&str> for Error {
 ///
 /// assert_eq!(timestamp, Some(1590488773254420000));
 /// ```
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ParsedLine<'a> {
     pub series: Series<'a>,
     pub field_set: FieldSet<'a>,
@@ -231,7 +231,7 @@ impl<'a> Display for ParsedLine<'a> {
 
 /// Represents the identifier of a series (measurement, tagset) for
 /// line protocol data
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Series<'a> {
     raw_input: &'a str,
     pub measurement: EscapedStr<'a>,
@@ -471,7 +471,18 @@ impl PartialEq<String> for EscapedStr<'_> {
 }
 
 pub fn parse_lines(input: &str) -> impl Iterator<Item = Result<ParsedLine<'_>>> {
-    split_lines(input).filter_map(|line| {
+    parse_lines_numbered(input).map(|(_, res)| res)
+}
+
+/// Parses `input` like [`parse_lines`], along with the number of the line,
+/// counting from 1, each result was parsed from. Lines with quoted newlines
+/// are numbered by the line they start on.
+pub fn parse_lines_numbered(input: &str) -> impl Iterator<Item = (usize, Result<ParsedLine<'_>>)> {
+    let mut next_line_number = 1;
+    split_lines(input).filter_map(move |line| {
+        let line_number = next_line_number;
+        next_line_number += 1 + line.matches('\n').count();
+
         let i = trim_leading(line);
 
         if i.is_empty() {
@@ -499,7 +510,7 @@ pub fn parse_lines(input: &str) -> impl Iterator<Item = Result<ParsedLine<'_>>>
         if let Some(Err(r)) = &res {
             debug!("Error parsing line: '{}'. Error was {:?}", line, r);
         }
-        res
+        res.map(|res| (line_number, res))
     })
 }
 
@@ -1171,6 +1182,15 @@ mod test {
         super::parse_lines(s).collect()
     }
 
+    #[test]
+    fn parse_numbered() {
+        let input = "\nfoo value=1i\n\nfoo value=\"a\nb\"\nfoo value=\nfoo value=2i";
+        let numbered: Vec<_> = super::parse_lines_numbered(input)
+            .map(|(line_number, res)| (line_number, res.is_ok()))
+            .collect();
+        assert_eq!(numbered, vec![(2, true), (4, true), (6, false), (7, true)]);
+    }
+
     #[test]
     fn parse_empty() -> Result {
         let input = "";
@@ -1,7 +1,13 @@
 use super::simple8b;
 use integer_encoding::*;
+use std::convert::TryInto;
 use std::error::Error;
 
+// The number of values decoded at once. Converting a chunk of values before
+// summing them up lets the compiler vectorize the conversion, while the
+// running sum itself has to go one value at a time.
+const LANES: usize = 8;
+
 // Encoding describes the type of encoding used by an encoded timestamp block.
 enum Encoding {
     Uncompressed = 0,
@@ -168,18 +174,42 @@ fn decode_uncompressed(src: &[u8], dst: &mut Vec<i64>) -> Result<(), Box<dyn Err
     if dst.capacity() < count {
         dst.reserve_exact(count - dst.capacity());
     }
-    let mut i = 0;
-    let mut prev = 0;
-    let mut buf: [u8; 8] = [0; 8];
-    while i < src.len() {
-        buf.copy_from_slice(&src[i..i + 8]);
-        prev += i64::from_be_bytes(buf);
-        dst.push(prev); // N.B - signed integer...
-        i += 8;
+
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        if is_x86_feature_detected!("avx2") {
+            // Safety: the CPU supports AVX2
+            unsafe { push_be_deltas_avx2(src, dst) };
+            return Ok(());
+        }
     }
+    push_be_deltas(src, dst);
     Ok(())
 }
 
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+unsafe fn push_be_deltas_avx2(src: &[u8], dst: &mut Vec<i64>) {
+    push_be_deltas(src, dst)
+}
+
+// push_be_deltas pushes the running sum of the big endian deltas in src, whose
+// length is a multiple of 8, into dst.
+#[inline(always)]
+fn push_be_deltas(src: &[u8], dst: &mut Vec<i64>) {
+    let mut prev = 0;
+    let mut chunk = [0_i64; LANES];
+    for bytes in src.chunks(8 * LANES) {
+        for (v, b) in chunk.iter_mut().zip(bytes.chunks_exact(8)) {
+            *v = i64::from_be_bytes(b.try_into().expect("8 bytes"));
+        }
+        for v in &chunk[..bytes.len() / 8] {
+            prev += v;
+            dst.push(prev); // N.B - signed integer...
+        }
+    }
+}
+
 // decode_rle decodes an RLE encoded slice containing only unsigned into the
 // destination vector.
 fn decode_rle(src: &[u8], dst: &mut Vec<i64>) -> Result<(), Box<dyn Error>> {
@@ -233,22 +263,42 @@ fn decode_simple8b(src: &[u8], dst: &mut Vec<i64>) -> Result<(), Box<dyn Error>>
     dst.push(i64::from_be_bytes(buf));
 
     simple8b::decode(&src[9..], &mut res);
-    let mut next = dst[dst.len() - 1];
-    if scaler > 1 {
-        // TODO(edd): fix this. It's copying, which is slowwwwwwwww.
-        for v in &res {
-            next += (v * scaler) as i64;
-            dst.push(next);
+    let first = dst[dst.len() - 1];
+    dst.reserve(res.len());
+
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        if is_x86_feature_detected!("avx2") {
+            // Safety: the CPU supports AVX2
+            unsafe { push_scaled_deltas_avx2(first, &res, scaler, dst) };
+            return Ok(());
         }
-        return Ok(());
     }
+    push_scaled_deltas(first, &res, scaler, dst);
+    Ok(())
+}
 
-    // TODO(edd): fix this. It's copying, which is slowwwwwwwww.
-    for v in &res {
-        next += *v as i64;
-        dst.push(next);
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+unsafe fn push_scaled_deltas_avx2(first: i64, deltas: &[u64], scaler: u64, dst: &mut Vec<i64>) {
+    push_scaled_deltas(first, deltas, scaler, dst)
+}
+
+// push_scaled_deltas pushes the running sum of first and the deltas, each
+// multiplied by scaler, into dst.
+#[inline(always)]
+fn push_scaled_deltas(first: i64, deltas: &[u64], scaler: u64, dst: &mut Vec<i64>) {
+    let mut next = first;
+    let mut chunk = [0_i64; LANES];
+    for deltas in deltas.chunks(LANES) {
+        for (v, delta) in chunk.iter_mut().zip(deltas) {
+            *v = delta.wrapping_mul(scaler) as i64;
+        }
+        for v in &chunk[..deltas.len()] {
+            next = next.wrapping_add(*v);
+            dst.push(next);
+        }
     }
-    Ok(())
 }
 
 #[cfg(test)]
@@ -380,4 +430,32 @@ mod tests {
             assert_eq!(got, exp, "{}", test.name);
         }
     }
+
+    #[test]
+    fn decode_partial_chunks() {
+        // block lengths around multiples of the number of values decoded at
+        // once, with the uncompressed and the scaled simple8b encodings
+        for len in 1..=(3 * LANES + 1) {
+            let scaled: Vec<i64> = (0..len as i64).map(|i| i * i * 1000).collect();
+            // deltas too large for simple8b
+            let uncompressed: Vec<i64> = (0..len as i64)
+                .map(|i| (i % 2) * (simple8b::MAX_VALUE as i64 * 2))
+                .collect();
+
+            for (input, encoding) in &[
+                (scaled, Encoding::Simple8b as u8),
+                (uncompressed, Encoding::Uncompressed as u8),
+            ] {
+                let mut dst = vec![];
+                encode(input, &mut dst).expect("failed to encode");
+                if len > 2 {
+                    assert_eq!(dst[0] >> 4, *encoding);
+                }
+
+                let mut got = vec![];
+                decode(&dst, &mut got).expect("failed to decode");
+                assert_eq!(&got, input, "{} values", len);
+            }
+        }
+    }
 }
@@ -0,0 +1,55 @@
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use data_types::{
+    data::lines_to_replicated_write,
+    database_rules::{DatabaseRules, PartitionTemplate, TemplatePart},
+};
+use influxdb_line_protocol::parse_lines;
+use mutable_buffer::MutableBufferDb;
+use query::Database;
+use test_helpers::datasets;
+use tokio::runtime::Runtime;
+
+/// The number of lines of each dataset
+const LINES: usize = 10_000;
+
+fn ingest(c: &mut Criterion) {
+    let mut group = c.benchmark_group("mutable_buffer_ingest");
+    let runtime = Runtime::new().unwrap();
+
+    // one partition per table, so that the writes aren't split by time
+    let rules = DatabaseRules {
+        partition_template: PartitionTemplate {
+            parts: vec![TemplatePart::Table],
+        },
+        ..Default::default()
+    };
+
+    for &cardinality in &[10, 1_000, 10_000] {
+        let lp = datasets::line_protocol(cardinality, LINES / cardinality);
+        let lines: Vec<_> = parse_lines(&lp).map(|l| l.unwrap()).collect();
+        let write = lines_to_replicated_write(0, 0, &lines, &rules);
+        group.throughput(Throughput::Elements(LINES as u64));
+
+        group.bench_with_input(
+            BenchmarkId::new("series", cardinality),
+            &write,
+            |b, write| {
+                b.iter_batched(
+                    || MutableBufferDb::new("bench"),
+                    |db| {
+                        runtime.block_on(db.store_replicated_write(write)).unwrap();
+                        // dropped once the measurement is over
+                        db
+                    },
+                    BatchSize::SmallInput,
+                )
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, ingest);
+
+criterion_main!(benches);
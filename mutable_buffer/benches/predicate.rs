@@ -0,0 +1,115 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use data_types::{
+    data::lines_to_replicated_write,
+    database_rules::{DatabaseRules, PartitionTemplate, TemplatePart},
+    selection::Selection,
+};
+use influxdb_line_protocol::parse_lines;
+use mutable_buffer::{chunk::Chunk, MutableBufferDb};
+use query::{
+    predicate::{PredicateBuilder, TimestampRange},
+    Database,
+};
+use std::sync::Arc;
+use test_helpers::datasets;
+use tokio::runtime::Runtime;
+
+/// The number of lines of each dataset
+const LINES: usize = 10_000;
+
+/// Returns a chunk holding the dataset of `cardinality` series
+fn load_chunk(cardinality: usize) -> Arc<Chunk> {
+    let rules = DatabaseRules {
+        partition_template: PartitionTemplate {
+            parts: vec![TemplatePart::Table],
+        },
+        ..Default::default()
+    };
+    let lp = datasets::line_protocol(cardinality, LINES / cardinality);
+    let lines: Vec<_> = parse_lines(&lp).map(|l| l.unwrap()).collect();
+    let write = lines_to_replicated_write(0, 0, &lines, &rules);
+
+    let db = MutableBufferDb::new("bench");
+    Runtime::new()
+        .unwrap()
+        .block_on(db.store_replicated_write(&write))
+        .unwrap();
+    db.rollover_partition(datasets::MEASUREMENT).unwrap()
+}
+
+fn predicate(c: &mut Criterion) {
+    let mut group = c.benchmark_group("mutable_buffer_predicate");
+
+    for &cardinality in &[10, 1_000, 10_000] {
+        let chunk = load_chunk(cardinality);
+
+        // the rows of a single series
+        let host = datasets::host(cardinality / 2);
+        group.bench_with_input(
+            BenchmarkId::new("host_equality", cardinality),
+            &chunk,
+            |b, chunk| {
+                b.iter(|| {
+                    let mut batches = Vec::new();
+                    chunk
+                        .table_to_arrow_filtered(
+                            &mut batches,
+                            datasets::MEASUREMENT,
+                            Selection::All,
+                            None,
+                            &[("host", host.as_str())],
+                        )
+                        .unwrap();
+                    assert_eq!(batches[0].num_rows(), LINES / cardinality);
+                })
+            },
+        );
+
+        // the rows of a tenth of the series in the first half of the time
+        // range
+        let region = datasets::region(1);
+        let end = datasets::BASE_TIME + (LINES / cardinality / 2) as i64 * datasets::INTERVAL;
+        let range = TimestampRange::new(datasets::BASE_TIME, end);
+        group.bench_with_input(
+            BenchmarkId::new("region_equality_and_range", cardinality),
+            &chunk,
+            |b, chunk| {
+                b.iter(|| {
+                    let mut batches = Vec::new();
+                    chunk
+                        .table_to_arrow_filtered(
+                            &mut batches,
+                            datasets::MEASUREMENT,
+                            Selection::All,
+                            Some(range),
+                            &[("region", region.as_str())],
+                        )
+                        .unwrap();
+                })
+            },
+        );
+
+        // the distinct values of a tag, as listed by tag_values requests
+        let predicate = PredicateBuilder::new().table(datasets::MEASUREMENT).build();
+        group.bench_with_input(
+            BenchmarkId::new("tag_values", cardinality),
+            &chunk,
+            |b, chunk| {
+                b.iter(|| {
+                    let chunk_predicate = chunk.compile_predicate(&predicate).unwrap();
+                    let values = chunk
+                        .tag_column_values(datasets::MEASUREMENT, "host", &chunk_predicate)
+                        .unwrap()
+                        .unwrap();
+                    assert_eq!(values.len(), cardinality);
+                })
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, predicate);
+
+criterion_main!(benches);
@@ -0,0 +1,115 @@
+//! Contains a bump allocator for the string field values of a chunk.
+//!
+//! Rather than allocating a `String` for each string value written, a chunk
+//! copies the values into large blocks, which are only freed along with the
+//! chunk. Writing a value appends it to the current block, and dropping a
+//! chunk frees its few blocks at once rather than every value one by one.
+
+/// The size of the first block values are copied into, in bytes. Each
+/// block is twice as large as the previous one, up to `MAX_BLOCK_SIZE`, so
+/// that chunks with few string values stay small. Larger values get a block
+/// of their own.
+const MIN_BLOCK_SIZE: usize = 1024;
+const MAX_BLOCK_SIZE: usize = 64 * 1024;
+
+/// A string value copied into a `StringArena`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArenaStr {
+    block: u32,
+    start: u32,
+    len: u32,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct StringArena {
+    /// The blocks holding the values. A block is never grown past its
+    /// capacity, so the values it holds never move.
+    blocks: Vec<String>,
+}
+
+impl StringArena {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Copies `value` into the arena, returning a reference to the copy
+    pub fn alloc(&mut self, value: &str) -> ArenaStr {
+        let fits = self
+            .blocks
+            .last()
+            .map_or(false, |block| block.capacity() - block.len() >= value.len());
+        if !fits {
+            let block_size = self.blocks.last().map_or(MIN_BLOCK_SIZE, |block| {
+                (block.capacity() * 2).min(MAX_BLOCK_SIZE)
+            });
+            self.blocks
+                .push(String::with_capacity(block_size.max(value.len())));
+        }
+
+        let block_id = self.blocks.len() - 1;
+        let block = &mut self.blocks[block_id];
+        let start = block.len();
+        block.push_str(value);
+
+        ArenaStr {
+            block: block_id as u32,
+            start: start as u32,
+            len: value.len() as u32,
+        }
+    }
+
+    /// Returns the value `value` refers to
+    pub fn get(&self, value: ArenaStr) -> &str {
+        let start = value.start as usize;
+        &self.blocks[value.block as usize][start..start + value.len as usize]
+    }
+
+    /// The memory allocated for the blocks of the arena, in bytes
+    pub fn size(&self) -> usize {
+        self.blocks.iter().map(|block| block.capacity()).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alloc() {
+        let mut arena = StringArena::new();
+        assert_eq!(arena.size(), 0);
+
+        let foo = arena.alloc("foo");
+        let empty = arena.alloc("");
+        assert_eq!(arena.size(), MIN_BLOCK_SIZE);
+
+        // a value larger than the next block gets a block of its own, and
+        // the values after it a new block
+        let large = "x".repeat(MAX_BLOCK_SIZE + 1);
+        let large_ref = arena.alloc(&large);
+        let bar = arena.alloc("bar");
+        assert_eq!(arena.blocks.len(), 3);
+
+        assert_eq!(arena.get(foo), "foo");
+        assert_eq!(arena.get(empty), "");
+        assert_eq!(arena.get(large_ref), large);
+        assert_eq!(arena.get(bar), "bar");
+
+        // the blocks grow up to the maximum size
+        for _ in 0..100 {
+            arena.alloc(&"y".repeat(1000));
+        }
+        assert!(arena
+            .blocks
+            .iter()
+            .all(|block| block.len() <= MAX_BLOCK_SIZE + 1));
+        assert_eq!(arena.blocks.last().unwrap().capacity(), MAX_BLOCK_SIZE);
+
+        // copies keep referring to the same values
+        let mut copy = arena.clone();
+        let baz = copy.alloc("baz");
+        assert_eq!(copy.get(foo), "foo");
+        assert_eq!(copy.get(bar), "bar");
+        assert_eq!(copy.get(baz), "baz");
+    }
+}
@@ -12,10 +12,14 @@ use arrow_deps::{
 
 use chrono::{DateTime, Utc};
 use generated_types::wal as wb;
+use once_cell::sync::OnceCell;
 use std::collections::{BTreeSet, HashMap, HashSet};
 
 use data_types::{
-    partition_metadata::TableSummary, schema::Schema, selection::Selection, TIME_COLUMN_NAME,
+    partition_metadata::{Statistics, TableSummary},
+    schema::Schema,
+    selection::Selection,
+    TIME_COLUMN_NAME,
 };
 
 use query::{
@@ -25,7 +29,7 @@ use query::{
 };
 
 use crate::{
-    column::Column,
+    arena::StringArena,
     dictionary::{Dictionary, Error as DictionaryError},
     table::Table,
 };
@@ -143,15 +147,19 @@ pub struct Chunk {
 
     /// Time at which this chunk was closed and became immutable (no
     /// new data was written after this time). Note this is not the
-    /// same as the timestamps on the data itself
-    pub time_closed: Option<DateTime<Utc>>,
+    /// same as the timestamps on the data itself. Set through a shared
+    /// reference, as queries may still hold the chunk when it is closed.
+    time_closed: OnceCell<DateTime<Utc>>,
 
     /// `dictionary` maps &str -> u32. The u32s are used in place of String or
     /// str to avoid slow string operations. The same dictionary is used for
     /// table names, tag names, tag values, and column names.
-    // TODO: intern string field values too?
     pub dictionary: Dictionary,
 
+    /// Holds the string field values written to the chunk, in large blocks
+    /// rather than a String per value
+    pub arena: StringArena,
+
     /// map of the dictionary ID for the table name to the table
     pub tables: HashMap<u32, Table>,
 }
@@ -251,15 +259,23 @@ impl Chunk {
         Self {
             id,
             dictionary: Dictionary::new(),
+            arena: StringArena::new(),
             tables: HashMap::new(),
             time_of_first_write: None,
             time_of_last_write: None,
-            time_closed: None,
+            time_closed: OnceCell::new(),
         }
     }
 
+    /// Writes the rows of `entry` to the chunk. The entry is checked before
+    /// any of it is written, so that it is either written entirely or, if
+    /// it fails, not at all.
     pub fn write_entry(&mut self, entry: &wb::WriteBufferEntry<'_>) -> Result<()> {
         if let Some(table_batches) = entry.table_batches() {
+            for batch in &table_batches {
+                self.validate_table_batch(&batch)?;
+            }
+
             let now = Utc::now();
             if self.time_of_first_write.is_none() {
                 self.time_of_first_write = Some(now);
@@ -274,6 +290,21 @@ impl Chunk {
         Ok(())
     }
 
+    fn validate_table_batch(&mut self, batch: &wb::TableWriteBatch<'_>) -> Result<()> {
+        let table_name = batch.name().context(TableWriteWithoutName)?;
+        let table_id = self.dictionary.lookup_value_or_insert(table_name);
+
+        if let Some(rows) = batch.rows() {
+            let result = match self.tables.get(&table_id) {
+                Some(table) => table.validate_rows(&mut self.dictionary, &rows),
+                None => Table::new(table_id).validate_rows(&mut self.dictionary, &rows),
+            };
+            result.context(TableWrite { table_name })?;
+        }
+
+        Ok(())
+    }
+
     fn write_table_batch(&mut self, batch: &wb::TableWriteBatch<'_>) -> Result<()> {
         let table_name = batch.name().context(TableWriteWithoutName)?;
         let table_id = self.dictionary.lookup_value_or_insert(table_name);
@@ -285,7 +316,7 @@ impl Chunk {
 
         if let Some(rows) = batch.rows() {
             table
-                .append_rows(&mut self.dictionary, &rows)
+                .append_rows(&mut self.dictionary, &mut self.arena, &rows)
                 .context(TableWrite { table_name })?;
         }
 
@@ -293,9 +324,17 @@ impl Chunk {
     }
 
     /// Mark the chunk as closed
-    pub fn mark_closed(&mut self) {
-        assert!(self.time_closed.is_none());
-        self.time_closed = Some(Utc::now())
+    pub fn mark_closed(&self) {
+        assert!(
+            self.time_closed.set(Utc::now()).is_ok(),
+            "chunk {} closed twice",
+            self.id
+        );
+    }
+
+    /// Returns the time at which this chunk was closed, if it is closed
+    pub fn time_closed(&self) -> Option<DateTime<Utc>> {
+        self.time_closed.get().copied()
     }
 
     /// Return all the names of the tables names in this chunk that match
@@ -352,7 +391,7 @@ impl Chunk {
         if let Some(table) = self.tables.get(&table_name_id) {
             for (&column_id, column) in &table.columns {
                 let column_matches_predicate = table
-                    .column_matches_predicate(&column, chunk_predicate)
+                    .column_matches_predicate(column_id, &column, chunk_predicate)
                     .context(NamedTableError { table_name })?;
 
                 if column_matches_predicate {
@@ -452,7 +491,7 @@ impl Chunk {
             .column(column_id)
             .context(NamedTableError { table_name })?;
 
-        if let Column::Tag(column, _) = column {
+        if column.is_tag() {
             // if we have a timestamp predicate, find all values
             // where the timestamp is within range. Otherwise take
             // all values.
@@ -461,8 +500,9 @@ impl Chunk {
             // ids *before* looking up Strings
             let column_value_ids: BTreeSet<u32> = match chunk_predicate.range {
                 None => {
-                    // take all non-null values
-                    column.iter().filter_map(|&s| s).collect()
+                    // take all the values having a posting list, rather
+                    // than scanning the column
+                    table.tag_value_ids(column_id).collect()
                 }
                 Some(range) => {
                     // filter out all values that don't match the timestmap
@@ -470,10 +510,11 @@ impl Chunk {
                         .column_i64(chunk_predicate.time_column_id)
                         .context(NamedTableError { table_name })?;
 
-                    column
-                        .iter()
+                    table
+                        .tag_values(column_id)
+                        .into_iter()
                         .zip(time_column.iter())
-                        .filter_map(|(&column_value_id, &timestamp_value)| {
+                        .filter_map(|(column_value_id, &timestamp_value)| {
                             if range.contains_opt(timestamp_value) {
                                 column_value_id
                             } else {
@@ -629,6 +670,27 @@ impl Chunk {
         Ok(())
     }
 
+    /// Like `table_to_arrow`, but only converts the rows whose time is
+    /// within `range` and whose tags have the values in `tag_values`. See
+    /// `Table::to_arrow_filtered` for details.
+    pub fn table_to_arrow_filtered(
+        &self,
+        dst: &mut Vec<RecordBatch>,
+        table_name: &str,
+        selection: Selection<'_>,
+        range: Option<TimestampRange>,
+        tag_values: &[(&str, &str)],
+    ) -> Result<()> {
+        if let Some(table) = self.table(table_name)? {
+            dst.push(
+                table
+                    .to_arrow_filtered(&self, selection, range, tag_values)
+                    .context(NamedTableError { table_name })?,
+            );
+        }
+        Ok(())
+    }
+
     /// Returns a vec of the summary statistics of the tables in this chunk
     pub fn table_stats(&self) -> Result<Vec<TableSummary>> {
         let mut stats = Vec::with_capacity(self.tables.len());
@@ -655,6 +717,79 @@ impl Chunk {
         Ok(stats)
     }
 
+    /// Returns the minimum and maximum timestamps across all tables in this
+    /// chunk, or None if it has no rows
+    pub fn time_range(&self) -> Result<Option<(i64, i64)>> {
+        let range = self
+            .table_stats()?
+            .iter()
+            .filter_map(|table| match table.column(TIME_COLUMN_NAME) {
+                Some(column) => match &column.stats {
+                    Statistics::I64(stats) => Some((stats.min, stats.max)),
+                    _ => None,
+                },
+                None => None,
+            })
+            .fold(None, |range, (min, max)| match range {
+                Some((range_min, range_max)) => Some((min.min(range_min), max.max(range_max))),
+                None => Some((min, max)),
+            });
+
+        Ok(range)
+    }
+
+    /// Returns the number of rows across all tables in this chunk
+    pub fn row_count(&self) -> usize {
+        self.tables.values().map(|table| table.row_count()).sum()
+    }
+
+    /// Returns the table name and the (tag key, tag value) pairs, sorted by
+    /// tag key, of each distinct series in this chunk
+    pub fn series(&self) -> Result<Vec<(&str, Vec<(&str, &str)>)>> {
+        let mut series = vec![];
+
+        for (&table_id, table) in &self.tables {
+            let table_name =
+                self.dictionary
+                    .lookup_id(table_id)
+                    .context(TableIdNotFoundInDictionary {
+                        table_id,
+                        chunk: self.id,
+                    })?;
+
+            // series ids are assigned densely, starting at 0
+            for series_id in 0..table.series_count() as u64 {
+                let mut tags: Vec<_> = table
+                    .series_key(series_id)
+                    .context(NamedTableError { table_name })?
+                    .iter()
+                    .map(|&(column_id, value_id)| {
+                        let tag_key = self.dictionary.lookup_id(column_id).context(
+                            ColumnIdNotFoundInDictionary {
+                                column_id,
+                                chunk: self.id,
+                            },
+                        )?;
+                        let tag_value = self.dictionary.lookup_id(value_id).context(
+                            InternalColumnValueIdNotFoundInDictionary {
+                                value_id,
+                                chunk_id: self.id,
+                            },
+                        )?;
+                        Ok((tag_key, tag_value))
+                    })
+                    .collect::<Result<_>>()?;
+                // series keys are sorted by tag key id, which is specific to
+                // this chunk
+                tags.sort_unstable();
+
+                series.push((table_name, tags));
+            }
+        }
+
+        Ok(series)
+    }
+
     /// Returns the named table, or None if no such table exists in this chunk
     fn table(&self, table_name: &str) -> Result<Option<&Table>> {
         let table_id = self.dictionary.lookup_value(table_name);
@@ -703,10 +838,10 @@ impl Chunk {
     }
 
     /// Return the approximate memory size of the chunk, in bytes including the
-    /// dictionary, tables, and their rows.
+    /// dictionary, string arena, tables, and their rows.
     pub fn size(&self) -> usize {
         let data_size = self.tables.values().fold(0, |acc, val| acc + val.size());
-        data_size + self.dictionary.size
+        data_size + self.dictionary.size + self.arena.size()
     }
 }
 
@@ -782,6 +917,13 @@ impl ExpressionVisitor for SupportVisitor {
         match expr {
             Expr::Literal(..) => Ok(Recursion::Continue(self)),
             Expr::Column(..) => Ok(Recursion::Continue(self)),
+            // such as the regular expression matches of reads, evaluated
+            // by DataFusion
+            Expr::ScalarUDF { .. } => Ok(Recursion::Continue(self)),
+            // the negated sub-expressions of read predicates
+            Expr::Not(..) => Ok(Recursion::Continue(self)),
+            // the fields of arithmetic in read predicates, cast to floats
+            Expr::Cast { .. } => Ok(Recursion::Continue(self)),
             Expr::BinaryExpr { op, .. } => {
                 match op {
                     Operator::Eq
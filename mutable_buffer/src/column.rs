@@ -1,7 +1,7 @@
 use generated_types::wal as wb;
-use snafu::Snafu;
+use snafu::{ensure, Snafu};
 
-use crate::dictionary::Dictionary;
+use crate::arena::{ArenaStr, StringArena};
 use data_types::{data::type_description, partition_metadata::StatValues};
 
 use arrow_deps::arrow::datatypes::DataType as ArrowDataType;
@@ -29,19 +29,22 @@ pub enum Error {
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
 /// Stores the actual data for columns in a chunk along with summary
-/// statistics
+/// statistics. String values are held in the string arena of the chunk.
+/// The values of tag columns are held once per series, in the series keys
+/// of their table (see `Table::tag_values`), so a tag column only counts
+/// its rows.
 #[derive(Debug, Clone)]
 pub enum Column {
     F64(Vec<Option<f64>>, StatValues<f64>),
     I64(Vec<Option<i64>>, StatValues<i64>),
-    String(Vec<Option<String>>, StatValues<String>),
+    String(Vec<Option<ArenaStr>>, StatValues<String>),
     Bool(Vec<Option<bool>>, StatValues<bool>),
-    Tag(Vec<Option<u32>>, StatValues<String>),
+    Tag(usize, StatValues<String>),
 }
 
 impl Column {
     pub fn with_value(
-        dictionary: &mut Dictionary,
+        arena: &mut StringArena,
         capacity: usize,
         value: wb::Value<'_>,
     ) -> Result<Self> {
@@ -73,7 +76,7 @@ impl Column {
                     .value()
                     .expect("string must be present");
                 let mut vals = vec![None; capacity];
-                vals.push(Some(val.to_string()));
+                vals.push(Some(arena.alloc(val)));
                 Self::String(vals, StatValues::new(val.to_string()))
             }
             BoolValue => {
@@ -91,10 +94,7 @@ impl Column {
                     .expect("tag value should be present")
                     .value()
                     .expect("tag value must have string value");
-                let mut vals = vec![None; capacity];
-                let id = dictionary.lookup_value_or_insert(val);
-                vals.push(Some(id));
-                Self::Tag(vals, StatValues::new(val.to_string()))
+                Self::Tag(capacity + 1, StatValues::new(val.to_string()))
             }
             _ => {
                 return UnknownColumnType {
@@ -111,7 +111,7 @@ impl Column {
             Self::I64(v, _) => v.len(),
             Self::String(v, _) => v.len(),
             Self::Bool(v, _) => v.len(),
-            Self::Tag(v, _) => v.len(),
+            Self::Tag(len, _) => *len,
         }
     }
 
@@ -140,13 +140,12 @@ impl Column {
         }
     }
 
-    pub fn push(&mut self, dictionary: &mut Dictionary, value: &wb::Value<'_>) -> Result<()> {
+    pub fn push(&mut self, arena: &mut StringArena, value: &wb::Value<'_>) -> Result<()> {
         let inserted = match self {
-            Self::Tag(vals, stats) => match value.value_as_tag_value() {
+            Self::Tag(len, stats) => match value.value_as_tag_value() {
                 Some(tag) => {
                     let tag_value = tag.value().expect("tag must have string value");
-                    let id = dictionary.lookup_value_or_insert(tag_value);
-                    vals.push(Some(id));
+                    *len += 1;
                     StatValues::update_string(stats, tag_value);
                     true
                 }
@@ -155,7 +154,7 @@ impl Column {
             Self::String(vals, stats) => match value.value_as_string_value() {
                 Some(str_val) => {
                     let str_val = str_val.value().expect("string must have value");
-                    vals.push(Some(str_val.to_string()));
+                    vals.push(Some(arena.alloc(str_val)));
                     StatValues::update_string(stats, str_val);
                     true
                 }
@@ -226,9 +225,9 @@ impl Column {
                     v.push(None);
                 }
             }
-            Self::Tag(v, _) => {
-                if v.len() == len {
-                    v.push(None);
+            Self::Tag(tag_len, _) => {
+                if *tag_len == len {
+                    *tag_len += 1;
                 }
             }
         }
@@ -279,9 +278,11 @@ impl Column {
     }
 
     /// The approximate memory size of the data in the column. Note that
-    /// the space taken for the tag string values is represented in
-    /// the dictionary size in the chunk that holds the table that has this
-    /// column. The size returned here is only for their identifiers.
+    /// the space taken for the tag and string values is represented in
+    /// the dictionary and string arena sizes in the chunk that holds the
+    /// table that has this column, and that of the series keys holding the
+    /// ids of the tag values in the size of the table. The size returned
+    /// here is only for the identifiers of the string values.
     pub fn size(&self) -> usize {
         match self {
             Self::F64(v, stats) => {
@@ -293,20 +294,45 @@ impl Column {
             Self::Bool(v, stats) => {
                 mem::size_of::<Option<bool>>() * v.len() + mem::size_of_val(&stats)
             }
-            Self::Tag(v, stats) => {
-                mem::size_of::<Option<u32>>() * v.len() + mem::size_of_val(&stats)
-            }
+            Self::Tag(_, stats) => mem::size_of_val(&stats),
             Self::String(v, stats) => {
-                let string_bytes_size = v
-                    .iter()
-                    .fold(0, |acc, val| acc + val.as_ref().map_or(0, |s| s.len()));
-                let vec_pointer_sizes = mem::size_of::<Option<String>>() * v.len();
-                string_bytes_size + vec_pointer_sizes + mem::size_of_val(&stats)
+                mem::size_of::<Option<ArenaStr>>() * v.len() + mem::size_of_val(&stats)
             }
         }
     }
 }
 
+/// Returns the type description of the column created from a value of
+/// `value_type`, or the error creating it fails with
+pub fn new_column_type(value_type: wb::ColumnValue) -> Result<&'static str> {
+    use wb::ColumnValue::*;
+
+    match value_type {
+        F64Value => Ok("f64"),
+        I64Value => Ok("i64"),
+        StringValue => Ok("String"),
+        BoolValue => Ok("bool"),
+        TagValue => Ok("tag"),
+        _ => UnknownColumnType {
+            inserted_value_type: type_description(value_type),
+        }
+        .fail(),
+    }
+}
+
+/// Returns the error pushing a value of `value_type` to a column of type
+/// `column_type` fails with, if any
+pub fn check_type(column_type: &'static str, value_type: wb::ColumnValue) -> Result<()> {
+    ensure!(
+        new_column_type(value_type).ok() == Some(column_type),
+        TypeMismatch {
+            existing_column_type: column_type,
+            inserted_value_type: type_description(value_type),
+        }
+    );
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -387,16 +413,15 @@ mod tests {
         let boolcol = Column::Bool(vec![Some(true)], StatValues::new(true));
         assert_eq!(9, boolcol.size());
 
-        let tagcol = Column::Tag(
-            vec![Some(1), Some(1), Some(1), Some(1)],
-            StatValues::new("foo".to_string()),
-        );
-        assert_eq!(40, tagcol.size());
+        // the values of tags are held by the series of the table
+        let tagcol = Column::Tag(4, StatValues::new("foo".to_string()));
+        assert_eq!(8, tagcol.size());
 
+        let mut arena = StringArena::new();
         let stringcol = Column::String(
-            vec![Some("foo".to_string()), Some("hello world".to_string())],
+            vec![Some(arena.alloc("foo")), Some(arena.alloc("hello world"))],
             StatValues::new("foo".to_string()),
         );
-        assert_eq!(70, stringcol.size());
+        assert_eq!(40, stringcol.size());
     }
 }
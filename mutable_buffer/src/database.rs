@@ -15,10 +15,10 @@ use crate::column::Column;
 use crate::table::Table;
 use crate::{
     chunk::{Chunk, ChunkPredicate},
-    partition::Partition,
+    partition::{Partition, PartitionStats},
 };
 
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::sync::Arc;
 
 use arrow_deps::datafusion::error::DataFusionError;
@@ -27,7 +27,7 @@ use crate::dictionary::Error as DictionaryError;
 
 use async_trait::async_trait;
 use data_types::database_rules::Order;
-use snafu::{ResultExt, Snafu};
+use snafu::{OptionExt, ResultExt, Snafu};
 use std::sync::RwLock;
 
 #[derive(Debug, Snafu)]
@@ -60,6 +60,19 @@ pub enum Error {
         source: crate::partition::Error,
     },
 
+    #[snafu(display(
+        "Error rolling over chunk of partition '{}': {}",
+        partition_key,
+        source
+    ))]
+    RollingOverChunk {
+        partition_key: String,
+        source: crate::partition::Error,
+    },
+
+    #[snafu(display("Error computing statistics of partition: {}", source))]
+    ComputingPartitionStats { source: crate::partition::Error },
+
     #[snafu(display("replicated write from writer {} missing payload", writer))]
     MissingPayload { writer: u32 },
 }
@@ -129,11 +142,41 @@ impl MutableBufferDb {
         Ok(())
     }
 
+    /// Stores the entries of `write` for the partitions `partition_keys`,
+    /// ignoring its entries for other partitions
+    pub fn store_replicated_write_partitions(
+        &self,
+        write: &ReplicatedWrite,
+        partition_keys: &BTreeSet<String>,
+    ) -> Result<()> {
+        let batch = write.write_buffer_batch().context(MissingPayload {
+            writer: write.to_fb().writer(),
+        })?;
+        if let Some(entries) = batch.entries() {
+            for entry in entries {
+                let key = entry
+                    .partition_key()
+                    .expect("partition key should have been inserted");
+                if !partition_keys.contains(key) {
+                    continue;
+                }
+
+                let partition = self.get_partition(key);
+                let mut partition = partition.write().expect("mutex poisoned");
+                partition.write_entry(&entry)?
+            }
+        }
+
+        Ok(())
+    }
+
     /// Rolls over the active chunk in this partititon
     pub fn rollover_partition(&self, partition_key: &str) -> Result<Arc<Chunk>> {
         let partition = self.get_partition(partition_key);
         let mut partition = partition.write().expect("mutex poisoned");
-        Ok(partition.rollover_chunk())
+        partition
+            .rollover_chunk()
+            .context(RollingOverChunk { partition_key })
     }
 
     /// return the specified chunk from the partition
@@ -161,6 +204,29 @@ impl MutableBufferDb {
             .remove(partition_key)
     }
 
+    /// Returns statistics about the data in the partition `partition_key`,
+    /// or None if there is no such partition
+    pub fn partition_stats(&self, partition_key: &str) -> Result<Option<PartitionStats>> {
+        let partition = self
+            .partitions
+            .read()
+            .expect("mutex poisoned")
+            .get(partition_key)
+            .cloned();
+
+        let stats = match partition {
+            Some(partition) => Some(
+                partition
+                    .read()
+                    .expect("mutex poisoned")
+                    .stats()
+                    .context(ComputingPartitionStats)?,
+            ),
+            None => None,
+        };
+        Ok(stats)
+    }
+
     /// The approximate size in memory of all data in the mutable buffer, in
     /// bytes
     pub fn size(&self) -> usize {
@@ -407,10 +473,15 @@ impl MutableBufferDb {
     /// returns false
     fn accept<V: Visitor>(&self, filter: &mut ChunkTableFilter, visitor: &mut V) -> Result<()> {
         for partition in self.partition_snapshot().into_iter() {
-            let partition = partition.read().expect("mutex poisoned");
-
-            if filter.should_visit_partition(&partition)? {
-                for chunk in partition.iter() {
+            // visit snapshots of the chunks, so writes to the partition
+            // don't wait for the visit
+            let (partition_key, chunks) = {
+                let partition = partition.read().expect("mutex poisoned");
+                (partition.key().to_string(), partition.chunks())
+            };
+
+            if filter.should_visit_partition(&partition_key)? {
+                for chunk in &chunks {
                     visitor.pre_visit_chunk(chunk)?;
                     filter.pre_visit_chunk(chunk)?;
 
@@ -509,9 +580,9 @@ impl ChunkTableFilter {
     }
 
     /// If returns false, skips visiting partition
-    fn should_visit_partition(&mut self, partition: &Partition) -> Result<bool> {
+    fn should_visit_partition(&mut self, key: &str) -> Result<bool> {
         match &self.predicate.partition_key {
-            Some(partition_key) => Ok(partition.key() == partition_key),
+            Some(partition_key) => Ok(key == partition_key),
             None => Ok(true),
         }
     }
@@ -935,7 +1006,7 @@ mod tests {
         let lines: Vec<_> = parse_lines(&lp_data).map(|l| l.unwrap()).collect();
         write_lines(&db, &lines).await;
 
-        assert_eq!(429, db.size());
+        assert_eq!(533, db.size());
     }
 
     #[tokio::test]
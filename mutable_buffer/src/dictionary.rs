@@ -1,25 +1,35 @@
 //! Contains a structure to map from strings to u32 symbols based on
 //! string interning.
+use crate::intern::intern;
+use data_types::redaction::Redacted;
 use snafu::{OptionExt, Snafu};
-use string_interner::{
-    backend::StringBackend, DefaultHashBuilder, DefaultSymbol, StringInterner, Symbol,
-};
+use std::{collections::HashMap, sync::Arc};
 
 #[derive(Debug, Snafu)]
 pub enum Error {
     #[snafu(display("Dictionary lookup error on id {}", id))]
     DictionaryIdLookupError { id: u32 },
 
-    #[snafu(display("Dictionary lookup error for value {}", value))]
+    #[snafu(display("Dictionary lookup error for value {}", Redacted(value)))]
     DictionaryValueLookupError { value: String },
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
+/// `Dictionary` assigns a dense u32 id to each distinct string of a
+/// chunk. The strings themselves are shared with the dictionaries of the
+/// other chunks through the process wide pool of the `intern` module.
 #[derive(Debug, Clone)]
 pub struct Dictionary {
-    interner: StringInterner<DefaultSymbol, StringBackend<DefaultSymbol>, DefaultHashBuilder>,
-    /// the approximate memory size of the dictionary
+    /// value --> id
+    ids: HashMap<Arc<str>, u32>,
+
+    /// id --> value (the id is the index)
+    values: Vec<Arc<str>>,
+
+    /// the approximate memory size of the dictionary. The strings are
+    /// counted in full even though they are shared, so that the size of a
+    /// chunk doesn't depend on the other chunks.
     pub size: usize,
 }
 
@@ -32,7 +42,8 @@ impl Default for Dictionary {
 impl Dictionary {
     pub fn new() -> Self {
         Self {
-            interner: StringInterner::new(),
+            ids: HashMap::new(),
+            values: Vec::new(),
             size: 0,
         }
     }
@@ -43,7 +54,12 @@ impl Dictionary {
         self.id(value).unwrap_or_else(|| {
             self.size += value.len();
             self.size += std::mem::size_of::<u32>();
-            symbol_to_u32(self.interner.get_or_intern(value))
+
+            let id = self.values.len() as u32;
+            let value = intern(value);
+            self.ids.insert(Arc::clone(&value), id);
+            self.values.push(value);
+            id
         })
     }
 
@@ -58,24 +74,19 @@ impl Dictionary {
     /// if any. No error is returned to avoid an allocation when no value is
     /// present
     pub fn id(&self, value: &str) -> Option<u32> {
-        self.interner.get(value).map(symbol_to_u32)
+        self.ids.get(value).copied()
     }
 
     /// Returns the str in self.dictionary that corresponds to `id`,
     /// if any. Returns an error if no such id is found
     pub fn lookup_id(&self, id: u32) -> Result<&str> {
-        let symbol =
-            Symbol::try_from_usize(id as usize).expect("to be able to convert u32 to symbol");
-        self.interner
-            .resolve(symbol)
+        self.values
+            .get(id as usize)
+            .map(|value| value.as_ref())
             .context(DictionaryIdLookupError { id })
     }
 }
 
-fn symbol_to_u32(sym: DefaultSymbol) -> u32 {
-    sym.to_usize() as u32
-}
-
 #[cfg(test)]
 mod test {
     use crate::dictionary::Dictionary;
@@ -90,4 +101,24 @@ mod test {
         d.lookup_value_or_insert("foo");
         assert_eq!(39, d.size);
     }
+
+    #[test]
+    fn shares_values() {
+        let mut a = Dictionary::new();
+        let mut b = Dictionary::new();
+        let a_id = a.lookup_value_or_insert("us-west");
+        b.lookup_value_or_insert("us-east");
+        let b_id = b.lookup_value_or_insert("us-west");
+        assert_eq!((a_id, b_id), (0, 1));
+
+        assert_eq!(a.lookup_id(a_id).unwrap(), "us-west");
+        assert_eq!(b.lookup_id(b_id).unwrap(), "us-west");
+        assert!(a.lookup_id(1).is_err());
+
+        // both dictionaries refer to the copy held by the pool
+        assert!(std::ptr::eq(
+            a.lookup_id(a_id).unwrap(),
+            b.lookup_id(b_id).unwrap()
+        ));
+    }
 }
@@ -0,0 +1,132 @@
+//! Contains the process wide pool interning the strings of the chunk
+//! dictionaries.
+//!
+//! The same tag keys and values (host names, regions, ...) are written to
+//! every chunk of every partition, so rather than each chunk dictionary
+//! holding its own copy of a string, the dictionaries share the copy held by
+//! the pool. Two strings interned by the pool are equal if and only if they
+//! are the same pointer.
+//!
+//! The pool is split into shards, each behind its own lock, so that writes to
+//! different chunks rarely wait for each other. Strings no longer referred to
+//! by any dictionary are removed from a shard whenever it has doubled in size
+//! since the last time it was swept.
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use std::{
+    collections::{hash_map::DefaultHasher, HashSet},
+    hash::{Hash, Hasher},
+    sync::Arc,
+};
+
+/// The number of shards of the pool
+const SHARDS: usize = 16;
+
+/// The number of strings a shard holds before it is first swept
+const MIN_SWEEP_LEN: usize = 1024;
+
+static POOL: Lazy<StringPool> = Lazy::new(StringPool::new);
+
+/// Returns the copy of `value` held by the process wide pool
+pub fn intern(value: &str) -> Arc<str> {
+    POOL.intern(value)
+}
+
+#[derive(Debug)]
+struct Shard {
+    values: HashSet<Arc<str>>,
+    /// The number of strings at which the shard is next swept
+    sweep_len: usize,
+}
+
+#[derive(Debug)]
+pub struct StringPool {
+    shards: Vec<Mutex<Shard>>,
+}
+
+impl Default for StringPool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StringPool {
+    pub fn new() -> Self {
+        let shards = (0..SHARDS)
+            .map(|_| {
+                Mutex::new(Shard {
+                    values: HashSet::new(),
+                    sweep_len: MIN_SWEEP_LEN,
+                })
+            })
+            .collect();
+        Self { shards }
+    }
+
+    /// Returns the copy of `value` held by the pool, adding one if there is
+    /// none yet
+    pub fn intern(&self, value: &str) -> Arc<str> {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        let mut shard = self.shards[hasher.finish() as usize % SHARDS].lock();
+
+        if let Some(interned) = shard.values.get(value) {
+            return Arc::clone(interned);
+        }
+
+        if shard.values.len() >= shard.sweep_len {
+            // The values only the pool refers to can't be cloned but through
+            // the pool, which is locked
+            shard.values.retain(|value| Arc::strong_count(value) > 1);
+            shard.sweep_len = (shard.values.len() * 2).max(MIN_SWEEP_LEN);
+        }
+
+        let interned: Arc<str> = Arc::from(value);
+        shard.values.insert(Arc::clone(&interned));
+        interned
+    }
+
+    /// Returns the number of strings held by the pool
+    pub fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.lock().values.len())
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn interns_once() {
+        let pool = StringPool::new();
+        assert!(pool.is_empty());
+
+        let a = pool.intern("server01");
+        let b = pool.intern("server01");
+        let c = pool.intern("server02");
+        assert!(Arc::ptr_eq(&a, &b));
+        assert!(!Arc::ptr_eq(&a, &c));
+        assert_eq!(&*c, "server02");
+        assert_eq!(pool.len(), 2);
+    }
+
+    #[test]
+    fn sweeps_unused_values() {
+        let pool = StringPool::new();
+        let kept = pool.intern("kept");
+        for i in 0..SHARDS * MIN_SWEEP_LEN * 4 {
+            pool.intern(&format!("value{}", i));
+        }
+
+        // the values no longer used were dropped as the shards grew
+        assert!(pool.len() < SHARDS * MIN_SWEEP_LEN * 4);
+        assert!(Arc::ptr_eq(&kept, &pool.intern("kept")));
+    }
+}
@@ -43,10 +43,32 @@
 //! and stops taking writes. Any new writes to the same partition will
 //! create a new active open chunk.
 //!
+//! Queries read snapshots of the open chunk, which is never changed while
+//! some query still reads it: such a write closes the chunk and goes to a
+//! new open chunk instead, so that long running queries neither block
+//! writes nor see some of the rows of a write but not the others, and the
+//! chunk is never copied. A write entry is checked before
+//! any of it is applied, so it is either applied entirely or not at all.
+//!
 //! Note: Strings in the mutable buffer are dictionary encoded (via
 //! string interning) to reduce memory usage. This dictionary encoding
 //! is done on a per-Chunk basis, so that as soon as the chunk is
-//! closed the corresponding dictionary also becomes immutable
+//! closed the corresponding dictionary also becomes immutable. The
+//! strings themselves are shared by the dictionaries of all chunks
+//! through a process wide pool (see the `intern` module), so a tag value
+//! written to many chunks is only stored once.
+//!
+//! String field values are copied into large blocks owned by their chunk
+//! (see the `arena` module) rather than allocated one by one, which makes
+//! writing them cheaper and lets a dropped chunk free them all at once.
+//!
+//! Each distinct set of tag values (series key) in a table is further
+//! interned into a u64 series id, and every row records the id of the
+//! series it belongs to instead of its tag values, which are only stored
+//! once per series (see the `series` module). The ids of the series having
+//! each tag value are kept in posting lists (see the `postings` module), so
+//! the rows matching some tag values are found without looking at every
+//! row.
 
 #![deny(rust_2018_idioms)]
 #![warn(
@@ -57,11 +79,16 @@
     clippy::clone_on_ref_ptr
 )]
 
+mod arena;
 pub mod chunk;
 mod column;
 pub mod database;
 mod dictionary;
+mod intern;
 mod partition;
+mod postings;
+mod series;
+mod series_index;
 mod table;
 
 // Allow restore chunks to be used outside of this crate (for
@@ -1,9 +1,13 @@
 //! Holds one or more Chunks.
 
+use chrono::{DateTime, Utc};
 use generated_types::wal as wb;
 use std::{collections::BTreeMap, sync::Arc, time::Instant};
 
-use crate::chunk::{Chunk, Error as ChunkError};
+use crate::{
+    chunk::{Chunk, Error as ChunkError},
+    series_index::SeriesIndex,
+};
 
 use data_types::partition_metadata::TableSummary;
 use snafu::{ResultExt, Snafu};
@@ -54,6 +58,28 @@ pub enum Error {
 
     #[snafu(display("Chunk error {}", source))]
     SummariesChunkError { source: ChunkError },
+
+    #[snafu(display(
+        "Error computing statistics of partition with key '{}' in mutable buffer: {}",
+        partition_key,
+        source
+    ))]
+    ComputingStats {
+        partition_key: String,
+        source: ChunkError,
+    },
+
+    #[snafu(display(
+        "Error indexing the series of chunk '{}' of partition with key '{}' in mutable buffer: {}",
+        chunk_id,
+        partition_key,
+        source
+    ))]
+    IndexingSeries {
+        partition_key: String,
+        chunk_id: u32,
+        source: ChunkError,
+    },
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -64,7 +90,13 @@ pub struct Partition {
     key: String,
 
     /// The currently active, open Chunk; All new writes go to this chunk
-    open_chunk: Chunk,
+    ///
+    /// Snapshots of the open chunk share it with the partition. A write
+    /// while a snapshot of the chunk is still in use closes the chunk and
+    /// goes to a new one rather than changing it (or copying it), so that
+    /// queries keep reading the chunk as it was when they started without
+    /// holding up writes.
+    open_chunk: Arc<Chunk>,
 
     /// Closed chunks which can no longer be written
     /// key: chunk_id, value: Chunk
@@ -75,6 +107,10 @@ pub struct Partition {
     /// creation order
     closed_chunks: BTreeMap<u32, Arc<Chunk>>,
 
+    /// The distinct series across the closed chunks, so that counting them
+    /// only requires going through the series of the open chunk
+    series: SeriesIndex,
+
     /// Responsible for assigning ids to chunks. Eventually, this might
     /// need to start at a number other than 0.
     id_generator: u32,
@@ -93,7 +129,7 @@ impl Partition {
         let mut id_generator = 0;
 
         let key: String = key.into();
-        let open_chunk = Chunk::new(id_generator);
+        let open_chunk = Arc::new(Chunk::new(id_generator));
         id_generator += 1;
 
         let now = Instant::now();
@@ -101,6 +137,7 @@ impl Partition {
             key,
             open_chunk,
             closed_chunks: BTreeMap::new(),
+            series: SeriesIndex::new(),
             id_generator,
             created_at: now,
             last_write_at: now,
@@ -115,7 +152,12 @@ impl Partition {
                 .expect("partition key should be present"),
             self.key
         );
-        self.open_chunk
+        // a query still reads the open chunk, which must stay as it is
+        if Arc::get_mut(&mut self.open_chunk).is_none() {
+            self.rollover_chunk()?;
+        }
+        Arc::get_mut(&mut self.open_chunk)
+            .expect("new open chunk is not shared")
             .write_entry(entry)
             .with_context(|| WritingChunkData {
                 partition_key: entry.partition_key().unwrap(),
@@ -158,11 +200,7 @@ impl Partition {
 
     /// Get a snapshot of the currently open chunk (that can be queried)
     fn open_chunk_snapshot(&self) -> Arc<Chunk> {
-        // TODO the performance if cloning the chunk is terrible
-        // Proper performance is tracked in
-        // https://github.com/influxdata/influxdb_iox/issues/635
-        let open_chunk_snapshot = self.open_chunk.clone();
-        Arc::new(open_chunk_snapshot)
+        Arc::clone(&self.open_chunk)
     }
 
     /// Close the currently open chunk and create a new open
@@ -173,24 +211,30 @@ impl Partition {
     ///
     /// Queries will continue to see data in the specified chunk until
     /// it is dropped.
-    pub fn rollover_chunk(&mut self) -> Arc<Chunk> {
+    pub fn rollover_chunk(&mut self) -> Result<Arc<Chunk>> {
         let chunk_id = self.id_generator;
         self.id_generator += 1;
-        let mut chunk = Chunk::new(chunk_id);
-        std::mem::swap(&mut chunk, &mut self.open_chunk);
+        let chunk = std::mem::replace(&mut self.open_chunk, Arc::new(Chunk::new(chunk_id)));
         chunk.mark_closed();
-        let chunk = Arc::new(chunk);
         if !chunk.is_empty() {
+            let series = chunk.series().context(IndexingSeries {
+                partition_key: &self.key,
+                chunk_id: chunk.id(),
+            })?;
+            for (table_name, tags) in series {
+                self.series.insert(table_name, &tags, chunk.id());
+            }
+
             let existing_value = self.closed_chunks.insert(chunk.id(), Arc::clone(&chunk));
             assert!(existing_value.is_none());
         }
-        chunk
+        Ok(chunk)
     }
 
     /// Drop the specified chunk for the partition, returning a reference to the
     /// chunk
     pub fn drop_chunk(&mut self, chunk_id: u32) -> Result<Arc<Chunk>> {
-        self.closed_chunks.remove(&chunk_id).ok_or_else(|| {
+        let chunk = self.closed_chunks.get(&chunk_id).ok_or_else(|| {
             let partition_key = self.key.clone();
             if self.open_chunk.id() == chunk_id {
                 Error::DropOpenChunk {
@@ -205,7 +249,22 @@ impl Partition {
                     valid_chunk_ids,
                 }
             }
-        })
+        })?;
+
+        // the chunk is only removed once its series are, so that it is kept
+        // if they can't be listed
+        let series = chunk.series().context(IndexingSeries {
+            partition_key: &self.key,
+            chunk_id,
+        })?;
+        for (table_name, tags) in series {
+            self.series.remove(table_name, &tags, chunk_id);
+        }
+
+        Ok(self
+            .closed_chunks
+            .remove(&chunk_id)
+            .expect("chunk was found"))
     }
 
     /// Return the partition key shared by all data stored in this
@@ -241,6 +300,51 @@ impl Partition {
 
         Ok(summaries)
     }
+
+    /// Returns statistics about the data in all chunks of this partition
+    pub fn stats(&self) -> Result<PartitionStats> {
+        // the closed chunks are indexed, so only the series of the open
+        // chunk are looked up
+        let open_series = self.open_chunk.series().context(ComputingStats {
+            partition_key: &self.key,
+        })?;
+        let new_series = open_series
+            .iter()
+            .filter(|(table_name, tags)| !self.series.contains(table_name, tags))
+            .count();
+
+        let mut stats = PartitionStats {
+            series_count: self.series.len() + new_series,
+            open_chunk_size: self.open_chunk.size(),
+            ..Default::default()
+        };
+
+        for chunk in self.iter() {
+            stats.chunk_count += 1;
+            stats.row_count += chunk.row_count();
+            stats.size += chunk.size();
+            stats.last_write_time = stats.last_write_time.max(chunk.time_of_last_write);
+        }
+
+        Ok(stats)
+    }
+}
+
+/// Statistics about the data in a partition
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct PartitionStats {
+    /// The number of distinct series across all chunks
+    pub series_count: usize,
+    /// The number of rows across all chunks
+    pub row_count: usize,
+    /// The number of chunks, including the open chunk
+    pub chunk_count: usize,
+    /// The approximate size in bytes of the open chunk
+    pub open_chunk_size: usize,
+    /// The approximate size in bytes of all chunks
+    pub size: usize,
+    /// The most recent time data was written to any chunk
+    pub last_write_time: Option<DateTime<Utc>>,
 }
 
 /// information on chunks for this partition
@@ -283,7 +387,7 @@ impl<'a> Iterator for ChunkIter<'a> {
             .or_else(|| {
                 if !self.visited_open {
                     self.visited_open = true;
-                    Some(&partition.open_chunk)
+                    Some(partition.open_chunk.as_ref())
                 } else {
                     None
                 }
@@ -332,14 +436,14 @@ mod tests {
         println!("rolling over chunk");
 
         // now rollover chunk, and expected results should be the same
-        let chunk = partition.rollover_chunk();
+        let chunk = partition.rollover_chunk().unwrap();
         assert_eq!(partition.closed_chunks.len(), 1);
         assert_table_eq!(expected, &dump_table(&partition, "h2o"));
         assert_eq!(row_count("h2o", &chunk), 2);
 
         // calling rollover chunk again is ok; It is returned but not added to the
         // closed chunk list
-        let chunk = partition.rollover_chunk();
+        let chunk = partition.rollover_chunk().unwrap();
         assert_eq!(partition.closed_chunks.len(), 1);
         assert_table_eq!(expected, &dump_table(&partition, "h2o"));
         assert_eq!(row_count("h2o", &chunk), 0);
@@ -359,7 +463,7 @@ mod tests {
         .await;
 
         // now rollover chunk
-        let chunk = partition.rollover_chunk();
+        let chunk = partition.rollover_chunk().unwrap();
         assert_eq!(partition.closed_chunks.len(), 1);
         assert_eq!(row_count("h2o", &chunk), 2);
 
@@ -388,7 +492,7 @@ mod tests {
         assert_table_eq!(expected, &dump_table(&partition, "h2o"));
 
         // now rollover chunk again
-        let chunk = partition.rollover_chunk();
+        let chunk = partition.rollover_chunk().unwrap();
         assert_eq!(partition.closed_chunks.len(), 2);
         assert_eq!(row_count("h2o", &chunk), 3);
         assert_table_eq!(expected, &dump_table(&partition, "h2o"));
@@ -429,7 +533,7 @@ mod tests {
         assert_table_eq!(expected_o2, &dump_table(&partition, "o2"));
 
         // now rollover chunk again
-        let chunk = partition.rollover_chunk();
+        let chunk = partition.rollover_chunk().unwrap();
         assert_eq!(partition.closed_chunks.len(), 1);
         assert_eq!(row_count("h2o", &chunk), 1);
         assert_eq!(row_count("o2", &chunk), 2);
@@ -443,17 +547,17 @@ mod tests {
         let mut partition = Partition::new("a_key");
 
         // When the chunk is rolled over, it gets id 0
-        let chunk = partition.rollover_chunk();
+        let chunk = partition.rollover_chunk().unwrap();
         assert_eq!(chunk.id(), 0);
 
         load_data(&mut partition, &["h2o,state=MA,city=Boston temp=70.4 100"]).await;
 
-        let chunk = partition.rollover_chunk();
+        let chunk = partition.rollover_chunk().unwrap();
         assert_eq!(chunk.id(), 1);
 
         load_data(&mut partition, &["h2o,state=MA,city=Boston temp=71.4 200"]).await;
 
-        let chunk = partition.rollover_chunk();
+        let chunk = partition.rollover_chunk().unwrap();
         assert_eq!(chunk.id(), 2);
 
         assert_eq!(all_ids_with_data(&partition), vec![1, 2]);
@@ -474,7 +578,7 @@ mod tests {
         .await;
 
         // When the chunk is rolled over
-        partition.rollover_chunk();
+        partition.rollover_chunk().unwrap();
 
         load_data(&mut partition, &["h2o,state=MA,city=Boston temp=71.4 100"]).await;
 
@@ -514,13 +618,13 @@ mod tests {
 
         // Given data loaded into three chunks (two closed)
         load_data(&mut partition, &["h2o,state=MA,city=Boston temp=70.4 100"]).await;
-        partition.rollover_chunk();
+        partition.rollover_chunk().unwrap();
 
         load_data(&mut partition, &["h2o,state=MA,city=Boston temp=72.4 200"]).await;
-        partition.rollover_chunk();
+        partition.rollover_chunk().unwrap();
 
         load_data(&mut partition, &["h2o,state=MA,city=Boston temp=71.4 300"]).await;
-        partition.rollover_chunk();
+        partition.rollover_chunk().unwrap();
 
         let expected = &[
             "+--------+-------+------+------+",
@@ -564,7 +668,7 @@ mod tests {
         );
 
         load_data(&mut partition, &["h2o,state=MA,city=Boston temp=70.4 100"]).await;
-        partition.rollover_chunk();
+        partition.rollover_chunk().unwrap();
         partition.drop_chunk(0).unwrap(); // drop is ok
                                           // can't drop again
         let e = partition.drop_chunk(0).unwrap_err();
@@ -593,7 +697,7 @@ mod tests {
         let after_data_load = Utc::now();
 
         // When the chunk is rolled over
-        let chunk = partition.rollover_chunk();
+        let chunk = partition.rollover_chunk().unwrap();
         let after_rollover = Utc::now();
 
         println!("start: {:?}, after_partition_creation: {:?}, after_data_load: {:?}, after_rollover: {:?}",
@@ -605,8 +709,8 @@ mod tests {
         assert!(after_partition_creation < chunk.time_of_first_write.unwrap());
         assert!(chunk.time_of_first_write.unwrap() < after_data_load);
         assert!(chunk.time_of_first_write.unwrap() == chunk.time_of_last_write.unwrap());
-        assert!(after_data_load < chunk.time_closed.unwrap());
-        assert!(chunk.time_closed.unwrap() < after_rollover);
+        assert!(after_data_load < chunk.time_closed().unwrap());
+        assert!(chunk.time_closed().unwrap() < after_rollover);
     }
 
     #[tokio::test]
@@ -619,7 +723,7 @@ mod tests {
 
         load_data(&mut partition, &["o2,state=MA,city=Boston temp=72.4 200"]).await;
         let after_data_load_2 = Utc::now();
-        let chunk = partition.rollover_chunk();
+        let chunk = partition.rollover_chunk().unwrap();
 
         assert!(chunk.time_of_first_write.unwrap() < after_data_load_1);
         assert!(chunk.time_of_first_write.unwrap() < chunk.time_of_last_write.unwrap());
@@ -631,12 +735,12 @@ mod tests {
         let mut partition = Partition::new("a_key");
         let after_partition_creation = Utc::now();
 
-        let chunk = partition.rollover_chunk();
+        let chunk = partition.rollover_chunk().unwrap();
         let after_rollover = Utc::now();
         assert!(chunk.time_of_first_write.is_none());
         assert!(chunk.time_of_last_write.is_none());
-        assert!(after_partition_creation < chunk.time_closed.unwrap());
-        assert!(chunk.time_closed.unwrap() < after_rollover);
+        assert!(after_partition_creation < chunk.time_closed().unwrap());
+        assert!(chunk.time_closed().unwrap() < after_rollover);
     }
 
     #[tokio::test]
@@ -647,13 +751,13 @@ mod tests {
         // Call load data but don't write any actual data (aka it was an empty write)
         load_data(&mut partition, &[""]).await;
 
-        let chunk = partition.rollover_chunk();
+        let chunk = partition.rollover_chunk().unwrap();
         let after_rollover = Utc::now();
 
         assert!(chunk.time_of_first_write.is_none());
         assert!(chunk.time_of_last_write.is_none());
-        assert!(after_partition_creation < chunk.time_closed.unwrap());
-        assert!(chunk.time_closed.unwrap() < after_rollover);
+        assert!(after_partition_creation < chunk.time_closed().unwrap());
+        assert!(chunk.time_closed().unwrap() < after_rollover);
     }
 
     #[tokio::test]
@@ -665,12 +769,12 @@ mod tests {
         assert_eq!(chunk_ids(&partition), vec![0]);
 
         // roll the chunk over to make a new one
-        let chunk = partition.rollover_chunk();
+        let chunk = partition.rollover_chunk().unwrap();
         assert_eq!(chunk.id(), 0);
         assert_eq!(chunk_ids(&partition), vec![0, 1]);
 
         load_data(&mut partition, &["h2o,state=MA,city=Boston temp=70.4 200"]).await;
-        let chunk = partition.rollover_chunk();
+        let chunk = partition.rollover_chunk().unwrap();
         assert_eq!(chunk.id(), 1);
         assert_eq!(chunk_ids(&partition), vec![0, 1, 2]);
 
@@ -708,7 +812,7 @@ mod tests {
             "Unknown chunk '1' of partition with key 'a_key' in mutable buffer"
         );
 
-        let chunk = partition.rollover_chunk();
+        let chunk = partition.rollover_chunk().unwrap();
         assert_table_eq!(expected0, &dump_chunk_table(&chunk, "h2o"));
         assert_table_eq!(
             expected0,
@@ -758,7 +862,7 @@ mod tests {
         // can't drop same partition twice
 
         load_data(&mut partition, &["h2o,state=MA,city=Boston temp=70.4 100"]).await;
-        partition.rollover_chunk();
+        partition.rollover_chunk().unwrap();
 
         let res = partition.drop_chunk(0);
         assert!(res.is_ok(), ":{:?}", res);
@@ -784,47 +888,50 @@ mod tests {
         let chunk0_snapshot0 = partition.get_chunk(0).unwrap();
         assert_table_eq!(expected0, &dump_chunk_table(&chunk0_snapshot0, "h2o"));
 
-        // load a second row in
+        // load a second row in while the snapshot is in use: the chunk is
+        // closed as it is and the row goes to a new chunk
         load_data(&mut partition, &["h2o,state=MA,city=Boston temp=72.4 200"]).await;
+        assert_eq!(chunk_ids(&partition), vec![0, 1]);
 
         let expected1 = &[
             "+--------+-------+------+------+",
             "| city   | state | temp | time |",
             "+--------+-------+------+------+",
-            "| Boston | MA    | 71.4 | 100  |",
             "| Boston | MA    | 72.4 | 200  |",
             "+--------+-------+------+------+",
         ];
-        let chunk0_snapshot1 = partition.get_chunk(0).unwrap();
-        // old data is not changed
+        // old data is not changed, nor copied
         assert_table_eq!(expected0, &dump_chunk_table(&chunk0_snapshot0, "h2o"));
-        assert_table_eq!(expected1, &dump_chunk_table(&chunk0_snapshot1, "h2o"));
+        let chunk0 = partition.get_chunk(0).unwrap();
+        assert!(Arc::ptr_eq(&chunk0, &chunk0_snapshot0));
+        assert!(chunk0.time_closed().is_some());
+        assert_table_eq!(
+            expected1,
+            &dump_chunk_table(&partition.get_chunk(1).unwrap(), "h2o")
+        );
 
-        // load a third row in
+        // load a third row in with no snapshot of the open chunk in use: it
+        // is written to the open chunk
         load_data(&mut partition, &["h2o,state=MA,city=Boston temp=73.4 300"]).await;
+        assert_eq!(chunk_ids(&partition), vec![0, 1]);
 
         let expected2 = &[
             "+--------+-------+------+------+",
             "| city   | state | temp | time |",
             "+--------+-------+------+------+",
-            "| Boston | MA    | 71.4 | 100  |",
             "| Boston | MA    | 72.4 | 200  |",
             "| Boston | MA    | 73.4 | 300  |",
             "+--------+-------+------+------+",
         ];
-        let chunk0_snapshot2 = partition.get_chunk(0).unwrap();
-        // old data is not changed
-        assert_table_eq!(expected0, &dump_chunk_table(&chunk0_snapshot0, "h2o"));
-        assert_table_eq!(expected1, &dump_chunk_table(&chunk0_snapshot1, "h2o"));
-        assert_table_eq!(expected2, &dump_chunk_table(&chunk0_snapshot2, "h2o"));
+        let chunk1_snapshot0 = partition.get_chunk(1).unwrap();
+        assert_table_eq!(expected2, &dump_chunk_table(&chunk1_snapshot0, "h2o"));
 
         // even after rollover the snapshots produce the same results:
-        let chunk0_rollover = partition.rollover_chunk();
+        let chunk1_rollover = partition.rollover_chunk().unwrap();
+        assert!(Arc::ptr_eq(&chunk1_rollover, &chunk1_snapshot0));
         // old data remains unchanged
         assert_table_eq!(expected0, &dump_chunk_table(&chunk0_snapshot0, "h2o"));
-        assert_table_eq!(expected1, &dump_chunk_table(&chunk0_snapshot1, "h2o"));
-        assert_table_eq!(expected2, &dump_chunk_table(&chunk0_snapshot2, "h2o"));
-        assert_table_eq!(expected2, &dump_chunk_table(&chunk0_rollover, "h2o"));
+        assert_table_eq!(expected2, &dump_chunk_table(&chunk1_snapshot0, "h2o"));
     }
 
     #[tokio::test]
@@ -832,15 +939,15 @@ mod tests {
         let mut partition = Partition::new("a_key");
 
         load_data(&mut partition, &["h2o,state=MA,city=Boston temp=71.4 100"]).await;
-        assert_eq!(136, partition.size());
+        assert_eq!(176, partition.size());
 
         // should increase by less because we're not adding to the dictionary
         load_data(&mut partition, &["h2o,state=MA,city=Boston temp=71.4 100"]).await;
-        assert_eq!(184, partition.size());
+        assert_eq!(216, partition.size());
 
         // make sure it increases by the lesser amount
         load_data(&mut partition, &["h2o,state=MA,city=Boston temp=71.4 100"]).await;
-        assert_eq!(232, partition.size());
+        assert_eq!(256, partition.size());
 
         // make sure a new table makes it increase by more
         load_data(
@@ -848,15 +955,15 @@ mod tests {
             &["another,state=MA,city=Boston temp=71.4 100"],
         )
         .await;
-        assert_eq!(323, partition.size());
+        assert_eq!(387, partition.size());
 
         // now roll the chunk and make sure writing into a new chunk will
         // increase by the same initial amount
-        let chunk = partition.rollover_chunk();
-        assert_eq!(323, chunk.size());
+        let chunk = partition.rollover_chunk().unwrap();
+        assert_eq!(387, chunk.size());
 
         load_data(&mut partition, &["h2o,state=MA,city=Boston temp=71.4 100"]).await;
-        assert_eq!(459, partition.size());
+        assert_eq!(563, partition.size());
     }
 
     #[tokio::test]
@@ -868,7 +975,7 @@ mod tests {
             &["cpu,host=a,region=west usage=21.1,system=55.5,num=10 10"],
         )
         .await;
-        partition.rollover_chunk();
+        partition.rollover_chunk().unwrap();
         load_data(
             &mut partition,
             &[
@@ -887,6 +994,48 @@ mod tests {
         assert!(summary.table("mem").is_some());
     }
 
+    #[tokio::test]
+    async fn partition_stats() {
+        let mut partition = Partition::new("a_key");
+        assert_eq!(partition.stats().unwrap().series_count, 0);
+
+        load_data(
+            &mut partition,
+            &[
+                "cpu,host=a,region=west usage=21.1 10",
+                "cpu,host=a,region=west usage=21.2 20",
+                "cpu,host=b,region=west usage=21.3 30",
+            ],
+        )
+        .await;
+        partition.rollover_chunk().unwrap();
+        // the tag keys may be added to the new chunk's dictionary in a
+        // different order
+        load_data(
+            &mut partition,
+            &[
+                "cpu,region=west,host=a usage=21.4 40",
+                "mem,host=a bytes=23423 50",
+            ],
+        )
+        .await;
+
+        let stats = partition.stats().unwrap();
+        assert_eq!(stats.series_count, 3);
+        assert_eq!(stats.row_count, 5);
+        assert_eq!(stats.chunk_count, 2);
+        assert_eq!(stats.size, partition.size());
+        assert_eq!(stats.open_chunk_size, partition.open_chunk.size());
+        assert_eq!(
+            stats.last_write_time,
+            partition.open_chunk.time_of_last_write
+        );
+
+        // the series only in the dropped chunk are no longer counted
+        partition.drop_chunk(0).unwrap();
+        assert_eq!(partition.stats().unwrap().series_count, 2);
+    }
+
     #[tokio::test]
     async fn write_updates_last_write_at() {
         let before_create = Instant::now();
@@ -907,6 +1056,44 @@ mod tests {
         assert!(last_write_prev < partition.last_write_at);
     }
 
+    #[tokio::test]
+    async fn test_chunk_snapshot_shared_until_write() {
+        let mut partition = Partition::new("a_key");
+        load_data(&mut partition, &["h2o,state=MA temp=71.4 100"]).await;
+
+        // snapshots taken between writes are the same chunk
+        let snapshot = partition.get_chunk(0).unwrap();
+        assert!(Arc::ptr_eq(&snapshot, &partition.get_chunk(0).unwrap()));
+        drop(snapshot);
+
+        // a write failing part way through is not applied at all
+        let err = write_lines(
+            &mut partition,
+            &["cpu usage=1 100", "h2o,state=MA temp=\"hot\" 200"],
+        )
+        .unwrap_err();
+        assert!(
+            err.to_string().contains("Unable to insert String type"),
+            "{}",
+            err
+        );
+        let after_error = partition.get_chunk(0).unwrap();
+        assert_eq!(row_count("h2o", &after_error), 1);
+        assert_eq!(row_count("cpu", &after_error), 0);
+
+        // a write doesn't change the snapshots taken before it, and goes to
+        // a new chunk instead
+        load_data(&mut partition, &["h2o,state=MA temp=72.4 200"]).await;
+        assert_eq!(row_count("h2o", &after_error), 1);
+        assert_eq!(row_count("h2o", &partition.get_chunk(1).unwrap()), 1);
+        drop(after_error);
+
+        // once the snapshots are dropped, writes go to the open chunk
+        load_data(&mut partition, &["h2o,state=MA temp=73.4 300"]).await;
+        assert_eq!(chunk_ids(&partition), vec![0, 1]);
+        assert_eq!(row_count("h2o", &partition.get_chunk(1).unwrap()), 2);
+    }
+
     fn row_count(table_name: &str, chunk: &Chunk) -> u32 {
         let stats = chunk.table_stats().unwrap();
         for s in &stats {
@@ -919,6 +1106,11 @@ mod tests {
 
     /// Load the specified rows of line protocol data into this partition
     async fn load_data(partition: &mut Partition, lp_data: &[&str]) {
+        write_lines(partition, lp_data).unwrap()
+    }
+
+    /// Writes the specified rows of line protocol data to this partition
+    fn write_lines(partition: &mut Partition, lp_data: &[&str]) -> Result<()> {
         let lp_string = lp_data.to_vec().join("\n");
 
         let lines: Vec<_> = parse_lines(&lp_string).map(|l| l.unwrap()).collect();
@@ -933,8 +1125,9 @@ mod tests {
                 .expect("partition key should have been inserted");
             assert_eq!(key, partition.key());
 
-            partition.write_entry(&entry).unwrap()
+            partition.write_entry(&entry)?
         }
+        Ok(())
     }
 
     fn dump_table(partition: &Partition, table_name: &str) -> Vec<RecordBatch> {
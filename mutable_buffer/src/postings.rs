@@ -0,0 +1,106 @@
+//! Contains the posting lists of the tag values of a table: for each (tag
+//! key id, tag value id) pair, the ids of the series having that value.
+//!
+//! The lists are roaring bitmaps rather than sorted vectors of ids. The
+//! series ids of a table are dense, so the bitmaps stay small even for the
+//! values of high cardinality tags, and intersecting the lists of several
+//! tag values works on whole words of ids at a time. The rows matching a set
+//! of tag values are then found by checking the series id of each row
+//! against the intersection, rather than comparing each tag column row by
+//! row.
+use croaring::Bitmap;
+use std::{collections::HashMap, mem};
+
+/// The approximate number of bytes taken by each series id of a list
+const ID_SIZE: usize = mem::size_of::<u32>();
+
+#[derive(Debug, Clone, Default)]
+pub struct Postings {
+    /// (tag key id, tag value id) --> the ids of the series with that value
+    lists: HashMap<(u32, u32), Bitmap>,
+
+    /// the approximate memory size of the lists
+    pub size: usize,
+}
+
+impl Postings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the series `series_id` to the lists of the (tag key id, tag
+    /// value id) pairs of its series key
+    pub fn insert(&mut self, key: &[(u32, u32)], series_id: u64) {
+        for &pair in key {
+            let size = &mut self.size;
+            let list = self.lists.entry(pair).or_insert_with(|| {
+                *size += mem::size_of::<(u32, u32)>();
+                Bitmap::create()
+            });
+            list.add(series_id as u32);
+            self.size += ID_SIZE;
+        }
+    }
+
+    /// Returns the ids of the series having the value `value_id` for the tag
+    /// `tag_id`, if any
+    pub fn get(&self, tag_id: u32, value_id: u32) -> Option<&Bitmap> {
+        self.lists.get(&(tag_id, value_id))
+    }
+
+    /// Returns the ids of the series having all of the (tag key id, tag
+    /// value id) `pairs`, which must not be empty
+    pub fn intersection(&self, pairs: &[(u32, u32)]) -> Bitmap {
+        let mut lists = Vec::with_capacity(pairs.len());
+        for (tag_id, value_id) in pairs {
+            match self.get(*tag_id, *value_id) {
+                Some(list) => lists.push(list),
+                None => return Bitmap::create(),
+            }
+        }
+
+        // starting from the smallest list keeps the intermediate results
+        // small
+        lists.sort_unstable_by_key(|list| list.cardinality());
+        let mut series = lists[0].clone();
+        for list in &lists[1..] {
+            series.and_inplace(list);
+        }
+        series
+    }
+
+    /// Returns the ids of the values of the tag `tag_id` that some series
+    /// has, in no particular order
+    pub fn values(&self, tag_id: u32) -> impl Iterator<Item = u32> + '_ {
+        self.lists
+            .keys()
+            .filter(move |(id, _)| *id == tag_id)
+            .map(|(_, value_id)| *value_id)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn intersection() {
+        // tags 1 and 3, with values 10, 11 and 30, 31
+        let mut postings = Postings::new();
+        postings.insert(&[(1, 10), (3, 30)], 0);
+        postings.insert(&[(1, 10), (3, 31)], 1);
+        postings.insert(&[(1, 11), (3, 30)], 2);
+        postings.insert(&[(3, 31)], 3);
+        assert_eq!(postings.size, 4 * 8 + 7 * 4);
+
+        let ids = |bitmap: Bitmap| bitmap.iter().collect::<Vec<_>>();
+        assert_eq!(ids(postings.intersection(&[(1, 10)])), vec![0, 1]);
+        assert_eq!(ids(postings.intersection(&[(1, 10), (3, 31)])), vec![1]);
+        assert!(postings.intersection(&[(1, 11), (3, 31)]).is_empty());
+        assert!(postings.intersection(&[(1, 12), (3, 31)]).is_empty());
+
+        let mut values: Vec<_> = postings.values(1).collect();
+        values.sort_unstable();
+        assert_eq!(values, vec![10, 11]);
+    }
+}
@@ -0,0 +1,127 @@
+//! Contains a structure to map from series keys (the set of tag
+//! key/value pairs of a row) to u64 series ids and back again.
+use snafu::{OptionExt, Snafu};
+use std::{collections::HashMap, mem, sync::Arc};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Series dictionary lookup error on id {}", id))]
+    SeriesIdLookupError { id: u64 },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// A series key is the list of (tag key id, tag value id) pairs of a
+/// row, sorted by tag key id. The ids come from the chunk
+/// dictionary, so a series key is only meaningful within the chunk
+/// that created it.
+pub type SeriesKey = Vec<(u32, u32)>;
+
+/// `SeriesDictionary` assigns a dense u64 id to each distinct series
+/// key seen in a table so that rows refer to their series by a single
+/// integer. The tag values of the rows are only stored in the series keys
+/// of the dictionary, once per series, and posting lists and comparisons
+/// of series work on the ids rather than on the full set of tag values.
+#[derive(Debug, Clone, Default)]
+pub struct SeriesDictionary {
+    /// series key --> series id, sharing the keys of `keys`
+    ids: HashMap<Arc<[(u32, u32)]>, u64>,
+
+    /// series id --> series key (the id is the index)
+    keys: Vec<Arc<[(u32, u32)]>>,
+
+    /// the approximate memory size of the dictionary
+    pub size: usize,
+}
+
+impl SeriesDictionary {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the id corresponding to the series key, adding an
+    /// entry for the key if it is not yet present in the dictionary.
+    /// `key` must already be sorted by tag key id.
+    pub fn lookup_key_or_insert(&mut self, key: SeriesKey) -> u64 {
+        if let Some(id) = self.id(&key) {
+            return id;
+        }
+
+        let id = self.keys.len() as u64;
+        self.size += mem::size_of::<(u32, u32)>() * key.len();
+        self.size += mem::size_of::<u64>();
+        let key: Arc<[(u32, u32)]> = key.into();
+        self.ids.insert(Arc::clone(&key), id);
+        self.keys.push(key);
+        id
+    }
+
+    /// Returns the id that corresponds to the series `key`, if any.
+    /// Does not add the key to the dictionary.
+    pub fn id(&self, key: &[(u32, u32)]) -> Option<u64> {
+        self.ids.get(key).copied()
+    }
+
+    /// Returns the series key that corresponds to `id`, if any.
+    /// Returns an error if no such id is found
+    pub fn lookup_id(&self, id: u64) -> Result<&[(u32, u32)]> {
+        self.keys
+            .get(id as usize)
+            .map(|key| key.as_ref())
+            .context(SeriesIdLookupError { id })
+    }
+
+    /// Returns the number of distinct series in the dictionary
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::series::SeriesDictionary;
+
+    #[test]
+    fn assigns_dense_ids() {
+        let mut d = SeriesDictionary::new();
+        assert!(d.is_empty());
+
+        let a = d.lookup_key_or_insert(vec![(1, 2), (3, 4)]);
+        let b = d.lookup_key_or_insert(vec![(1, 5), (3, 4)]);
+        let c = d.lookup_key_or_insert(vec![]);
+        assert_eq!((a, b, c), (0, 1, 2));
+
+        // the same key always maps to the same id
+        assert_eq!(a, d.lookup_key_or_insert(vec![(1, 2), (3, 4)]));
+        assert_eq!(3, d.len());
+
+        assert_eq!(Some(b), d.id(&[(1, 5), (3, 4)]));
+        assert_eq!(None, d.id(&[(1, 5)]));
+    }
+
+    #[test]
+    fn lookup_id() {
+        let mut d = SeriesDictionary::new();
+        let id = d.lookup_key_or_insert(vec![(1, 2), (3, 4)]);
+
+        assert_eq!(&[(1, 2), (3, 4)], d.lookup_id(id).unwrap());
+
+        let err = d.lookup_id(id + 1).unwrap_err();
+        assert_eq!(err.to_string(), "Series dictionary lookup error on id 1");
+    }
+
+    #[test]
+    fn tracks_size() {
+        let mut d = SeriesDictionary::new();
+        d.lookup_key_or_insert(vec![(1, 2), (3, 4)]);
+        assert_eq!(24, d.size);
+        d.lookup_key_or_insert(vec![(1, 2)]);
+        assert_eq!(40, d.size);
+        d.lookup_key_or_insert(vec![(1, 2), (3, 4)]);
+        assert_eq!(40, d.size);
+    }
+}
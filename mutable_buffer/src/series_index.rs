@@ -0,0 +1,117 @@
+//! Contains the index of the distinct series stored in the closed chunks of
+//! a partition.
+//!
+//! The series ids of a table are only meaningful within its chunk, so the
+//! index identifies a series by its table name and (tag key, tag value)
+//! pairs, and records the ids of the chunks holding it. A series is removed
+//! from the index once the last chunk holding it is dropped.
+//!
+//! The index only covers closed chunks, which are indexed as they are
+//! closed, so that writes to the open chunk don't pay for it. It is only
+//! changed with the partition locked for writing, so it is a plain map.
+use crate::intern::intern;
+use std::{collections::HashMap, sync::Arc};
+
+/// The table name followed by the tag keys and values of a series, sorted by
+/// tag key
+type SeriesName = Vec<Arc<str>>;
+
+#[derive(Debug, Default)]
+pub struct SeriesIndex {
+    /// series --> the ids of the chunks holding it, in ascending order
+    series: HashMap<SeriesName, Vec<u32>>,
+}
+
+impl SeriesIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records that the chunk `chunk_id` holds the series with the
+    /// (tag key, tag value) pairs `tags` of the table `table_name`
+    pub fn insert(&mut self, table_name: &str, tags: &[(&str, &str)], chunk_id: u32) {
+        let chunk_ids = self
+            .series
+            .entry(series_name(table_name, tags))
+            .or_default();
+        if !chunk_ids.contains(&chunk_id) {
+            chunk_ids.push(chunk_id);
+            chunk_ids.sort_unstable();
+        }
+    }
+
+    /// Records that the chunk `chunk_id` no longer holds the series, removing
+    /// the series if no other chunk holds it
+    pub fn remove(&mut self, table_name: &str, tags: &[(&str, &str)], chunk_id: u32) {
+        let name = series_name(table_name, tags);
+        if let Some(chunk_ids) = self.series.get_mut(&name) {
+            chunk_ids.retain(|&id| id != chunk_id);
+            if chunk_ids.is_empty() {
+                self.series.remove(&name);
+            }
+        }
+    }
+
+    /// Whether any chunk holds the series
+    pub fn contains(&self, table_name: &str, tags: &[(&str, &str)]) -> bool {
+        self.series.contains_key(&series_name(table_name, tags))
+    }
+
+    /// Returns the ids of the chunks holding the series, in ascending order
+    pub fn chunk_ids(&self, table_name: &str, tags: &[(&str, &str)]) -> Vec<u32> {
+        self.series
+            .get(&series_name(table_name, tags))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Returns the number of distinct series in the index
+    pub fn len(&self) -> usize {
+        self.series.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.series.is_empty()
+    }
+}
+
+fn series_name(table_name: &str, tags: &[(&str, &str)]) -> SeriesName {
+    let mut name = Vec::with_capacity(1 + tags.len() * 2);
+    name.push(intern(table_name));
+    for (key, value) in tags {
+        name.push(intern(key));
+        name.push(intern(value));
+    }
+    name
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn insert_and_remove() {
+        let mut index = SeriesIndex::new();
+        assert!(index.is_empty());
+
+        index.insert("cpu", &[("host", "a")], 0);
+        index.insert("cpu", &[("host", "a")], 0);
+        index.insert("cpu", &[("host", "a")], 1);
+        index.insert("cpu", &[("host", "b")], 1);
+        index.insert("mem", &[("host", "a")], 1);
+        assert_eq!(index.len(), 3);
+        assert_eq!(index.chunk_ids("cpu", &[("host", "a")]), vec![0, 1]);
+        assert!(index.contains("mem", &[("host", "a")]));
+        assert!(!index.contains("mem", &[("host", "b")]));
+
+        // a series is kept until the last chunk holding it is dropped
+        index.remove("cpu", &[("host", "a")], 0);
+        assert_eq!(index.chunk_ids("cpu", &[("host", "a")]), vec![1]);
+        assert_eq!(index.len(), 3);
+
+        index.remove("cpu", &[("host", "a")], 1);
+        index.remove("mem", &[("host", "b")], 1);
+        assert!(index.chunk_ids("cpu", &[("host", "a")]).is_empty());
+        assert_eq!(index.len(), 2);
+    }
+}
@@ -5,19 +5,25 @@ use query::{
     func::window::make_window_bound_expr,
     group_by::{Aggregate, WindowDuration},
     plan::seriesset::SeriesSetPlan,
+    predicate::TimestampRange,
 };
 
 use std::{
-    collections::{BTreeMap, BTreeSet},
+    collections::{hash_map::Entry, BTreeMap, BTreeSet, HashMap},
+    mem,
+    ops::Range,
     sync::Arc,
 };
 
 use crate::{
+    arena::StringArena,
     chunk::ChunkIdSet,
     chunk::{Chunk, ChunkPredicate},
     column,
     column::Column,
     dictionary::{Dictionary, Error as DictionaryError},
+    postings::Postings,
+    series::{Error as SeriesError, SeriesDictionary, SeriesKey},
 };
 use data_types::{
     partition_metadata::{ColumnSummary, Statistics},
@@ -145,6 +151,13 @@ pub enum Error {
 
     #[snafu(display("Column {} not found in table {}", id, table_id))]
     ColumnIdNotFound { id: u32, table_id: u32 },
+
+    #[snafu(display("Series {} not found in table {}: {}", series_id, table_id, source))]
+    SeriesIdNotFound {
+        series_id: u64,
+        table_id: u32,
+        source: SeriesError,
+    },
 }
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
@@ -155,6 +168,17 @@ pub struct Table {
 
     /// Map of column id from the chunk dictionary to the column
     pub columns: BTreeMap<u32, Column>,
+
+    /// Maps the distinct tag sets (series keys) of this table's rows
+    /// to u64 series ids. The tag values of the rows are only held here,
+    /// once per series.
+    series: SeriesDictionary,
+
+    /// The series id of each row, in row order
+    series_ids: Vec<u64>,
+
+    /// The ids of the series having each tag value
+    postings: Postings,
 }
 
 type ArcStringVec = Vec<Arc<String>>;
@@ -164,15 +188,22 @@ impl Table {
         Self {
             id,
             columns: BTreeMap::new(),
+            series: SeriesDictionary::new(),
+            series_ids: Vec::new(),
+            postings: Postings::new(),
         }
     }
 
+    /// Appends the values of a row, returning its series key. The series
+    /// id of the row is assigned by `push_series_ids`.
     fn append_row(
         &mut self,
         dictionary: &mut Dictionary,
+        arena: &mut StringArena,
         values: &flatbuffers::Vector<'_, flatbuffers::ForwardsUOffset<wb::Value<'_>>>,
-    ) -> Result<()> {
+    ) -> Result<SeriesKey> {
         let row_count = self.row_count();
+        let mut series_key = Vec::new();
 
         // insert new columns and validate existing ones
         for value in values {
@@ -181,13 +212,17 @@ impl Table {
                 .context(ColumnNameNotInRow { table: self.id })?;
             let column_id = dictionary.lookup_value_or_insert(column_name);
 
+            if let Some(tag_value) = value.value_as_tag_value().and_then(|v| v.value()) {
+                series_key.push((column_id, dictionary.lookup_value_or_insert(tag_value)));
+            }
+
             let column = match self.columns.get_mut(&column_id) {
                 Some(col) => col,
                 None => {
                     // Add the column and make all values for existing rows None
                     self.columns.insert(
                         column_id,
-                        Column::with_value(dictionary, row_count, value)
+                        Column::with_value(arena, row_count, value)
                             .context(CreatingFromWal { column: column_id })?,
                     );
 
@@ -195,7 +230,7 @@ impl Table {
                 }
             };
 
-            column.push(dictionary, &value).context(ColumnError {
+            column.push(arena, &value).context(ColumnError {
                 column: column_name,
             })?;
         }
@@ -205,7 +240,44 @@ impl Table {
             col.push_none_if_len_equal(row_count);
         }
 
-        Ok(())
+        series_key.sort_unstable();
+        Ok(series_key)
+    }
+
+    /// Pushes the series ids of rows appended in a batch, given their
+    /// series keys in row order. The rows are sorted by series key so that
+    /// each distinct key of the batch is looked up in the series dictionary
+    /// once, rather than once per row. New series get their ids in the
+    /// order of their first row, as if their rows were added one by one.
+    fn push_series_ids(&mut self, mut keys: Vec<SeriesKey>) {
+        let mut rows: Vec<usize> = (0..keys.len()).collect();
+        // stable, so the first row of each series comes first
+        rows.sort_by(|a, b| keys[*a].cmp(&keys[*b]));
+
+        // the runs of `rows` with the same series key, in the order of
+        // their first row
+        let mut runs: Vec<Range<usize>> = vec![];
+        let mut run_start = 0;
+        for i in 1..=rows.len() {
+            if i == rows.len() || keys[rows[i]] != keys[rows[run_start]] {
+                runs.push(run_start..i);
+                run_start = i;
+            }
+        }
+        runs.sort_unstable_by_key(|run| rows[run.start]);
+
+        let start = self.series_ids.len();
+        self.series_ids.resize(start + keys.len(), 0);
+        for run in runs {
+            let key = mem::take(&mut keys[rows[run.start]]);
+            if self.series.id(&key).is_none() {
+                self.postings.insert(&key, self.series.len() as u64);
+            }
+            let series_id = self.series.lookup_key_or_insert(key);
+            for row in &rows[run] {
+                self.series_ids[start + row] = series_id;
+            }
+        }
     }
 
     pub fn row_count(&self) -> usize {
@@ -216,11 +288,68 @@ impl Table {
             .unwrap_or(0)
     }
 
-    /// The approximate memory size of the data in the table, in bytes. Note
-    /// that the space taken for the tag string values is represented in the
-    /// dictionary size in the chunk that holds the table.
+    /// The approximate memory size of the data in the table, in bytes,
+    /// including the series ids of its rows, the series keys holding their
+    /// tag values and the posting lists of the tag values. Note that the space
+    /// taken for the tag and string values is represented in the dictionary
+    /// and string arena sizes in the chunk that holds the table.
     pub fn size(&self) -> usize {
-        self.columns.values().fold(0, |acc, v| acc + v.size())
+        let data_size = self.columns.values().fold(0, |acc, v| acc + v.size());
+        let series_size = mem::size_of::<u64>() * self.series_ids.len();
+        data_size + series_size + self.series.size + self.postings.size
+    }
+
+    /// Returns the number of distinct series (tag sets) in this table
+    pub fn series_count(&self) -> usize {
+        self.series.len()
+    }
+
+    /// Returns the series id of each row in this table, in row order
+    pub fn series_ids(&self) -> &[u64] {
+        &self.series_ids
+    }
+
+    /// Returns the id of the value of the tag `column_id` of each row, in
+    /// row order, `None` for the rows without the tag. The values are
+    /// looked up in the series key of each row's series.
+    pub(crate) fn tag_values(&self, column_id: u32) -> Vec<Option<u32>> {
+        // the value of each series, looked up once rather than per row
+        let series_values: Vec<Option<u32>> = (0..self.series.len() as u64)
+            .map(|series_id| {
+                let key = self
+                    .series
+                    .lookup_id(series_id)
+                    .expect("series ids are dense");
+                key.binary_search_by_key(&column_id, |(tag, _)| *tag)
+                    .ok()
+                    .map(|index| key[index].1)
+            })
+            .collect();
+
+        let mut values: Vec<Option<u32>> = self
+            .series_ids
+            .iter()
+            .map(|series_id| series_values[*series_id as usize])
+            .collect();
+        // rows appended before an error are counted by the columns without
+        // having a series id
+        values.resize(self.row_count(), None);
+        values
+    }
+
+    /// Returns the ids of the values some row of this table has for the tag
+    /// `column_id`, in no particular order
+    pub fn tag_value_ids(&self, column_id: u32) -> impl Iterator<Item = u32> + '_ {
+        self.postings.values(column_id)
+    }
+
+    /// Returns the (tag key id, tag value id) pairs, sorted by tag key
+    /// id, that make up the series with `series_id`
+    pub fn series_key(&self, series_id: u64) -> Result<&[(u32, u32)]> {
+        self.series.lookup_id(series_id).context(SeriesIdNotFound {
+            series_id,
+            table_id: self.id,
+        })
     }
 
     /// Returns a reference to the specified column
@@ -249,11 +378,65 @@ impl Table {
     pub fn append_rows(
         &mut self,
         dictionary: &mut Dictionary,
+        arena: &mut StringArena,
         rows: &flatbuffers::Vector<'_, flatbuffers::ForwardsUOffset<wb::Row<'_>>>,
     ) -> Result<()> {
+        let mut keys = Vec::with_capacity(rows.len());
+        let mut result = Ok(());
         for row in rows {
             if let Some(values) = row.values() {
-                self.append_row(dictionary, &values)?;
+                match self.append_row(dictionary, arena, &values) {
+                    Ok(key) => keys.push(key),
+                    Err(e) => {
+                        result = Err(e);
+                        break;
+                    }
+                }
+            }
+        }
+
+        // the rows appended before an error keep their series ids
+        self.push_series_ids(keys);
+        result
+    }
+
+    /// Returns the error appending `rows` to the table would fail with, if
+    /// any, without changing the table. The names of the columns are added
+    /// to the dictionary.
+    pub fn validate_rows<'a>(
+        &self,
+        dictionary: &mut Dictionary,
+        rows: &flatbuffers::Vector<'a, flatbuffers::ForwardsUOffset<wb::Row<'a>>>,
+    ) -> Result<()> {
+        // the type of the columns the rows add, by name
+        let mut new_columns: HashMap<&'a str, &'static str> = HashMap::new();
+
+        for row in rows {
+            let values = match row.values() {
+                Some(values) => values,
+                None => continue,
+            };
+            for value in &values {
+                let column_name = value
+                    .column()
+                    .context(ColumnNameNotInRow { table: self.id })?;
+                let column_id = dictionary.lookup_value_or_insert(column_name);
+
+                let column_type = match self.columns.get(&column_id) {
+                    Some(column) => column.type_description(),
+                    None => match new_columns.entry(column_name) {
+                        Entry::Occupied(column_type) => *column_type.get(),
+                        Entry::Vacant(entry) => {
+                            let column_type = column::new_column_type(value.value_type())
+                                .context(CreatingFromWal { column: column_id })?;
+                            entry.insert(column_type);
+                            continue;
+                        }
+                    },
+                };
+                column::check_type(column_type, value.value_type()).context(ColumnError {
+                    column: column_name,
+                })?;
             }
         }
 
@@ -358,7 +541,8 @@ impl Table {
         // TODO avoid materializing all the columns here (ideally
         // DataFusion can prune some of them out)
         let selection = self.all_columns_selection(chunk)?;
-        let data = self.to_arrow_impl(chunk, &selection)?;
+        let rows = self.matching_rows(chunk, chunk_predicate.range, &[]);
+        let data = self.to_arrow_impl(chunk, &selection, rows.as_deref())?;
 
         let schema = data.schema();
 
@@ -470,17 +654,7 @@ impl Table {
             agg_exprs,
             field_columns,
         } = AggExprs::new(agg, field_columns, |col_name| {
-            let column_id =
-                chunk
-                    .dictionary
-                    .id(col_name)
-                    .context(ColumnNameNotFoundInDictionary {
-                        column_name: col_name,
-                        chunk: chunk.id,
-                    })?;
-            let column = self.column(column_id)?;
-
-            Ok(column.data_type())
+            self.column_data_type(col_name, chunk)
         })?;
 
         let sort_exprs = group_exprs
@@ -514,7 +688,7 @@ impl Table {
     /// rows for a particular series (groups where all tags are the
     /// same) occur together in the plan
     ///
-    /// Equivalent to this SQL query
+    /// Equivalent to this SQL query for 'aggregates': sum, count, mean
     ///
     /// SELECT tag1, ... tagN,
     ///   window_bound(time, every, offset) as time,
@@ -527,6 +701,20 @@ impl Table {
     ///   tag1, ... tagN,
     ///   window_bound(time, every, offset) as time
     ///
+    /// For 'selector' functions: first, last, min, max, each field is
+    /// instead paired with the timestamp of the selected value, as in
+    /// `aggregate_series_set_plan`, so the points keep their original time
+    /// rather than the window bound:
+    ///
+    /// SELECT tag1, ... tagN,
+    ///   window_bound(time, every, offset) as time,
+    ///   agg_function(_val1) as _value1
+    ///   agg_function(time) as time_value1
+    ///   ..
+    ///   agg_function(_valN) as _valueN
+    ///   agg_function(time) as time_valueN
+    /// ...
+    ///
     /// The created plan looks like:
     ///
     ///  OrderBy(gby: tag columns, window_function; agg: aggregate(field)
@@ -557,11 +745,26 @@ impl Table {
             make_window_bound_expr(col(TIME_COLUMN_NAME), every, offset).alias(TIME_COLUMN_NAME);
         group_exprs.push(window_bound);
 
-        // aggregate each field
-        let agg_exprs = field_columns
-            .iter()
-            .map(|field_name| make_agg_expr(agg, field_name))
-            .collect::<Result<Vec<_>>>()?;
+        // aggregate each field, with the time of the selected value for
+        // selectors
+        let (agg_exprs, field_columns) = match agg {
+            Aggregate::First | Aggregate::Last | Aggregate::Min | Aggregate::Max => {
+                let AggExprs {
+                    agg_exprs,
+                    field_columns,
+                } = AggExprs::new(agg, field_columns, |col_name| {
+                    self.column_data_type(col_name, chunk)
+                })?;
+                (agg_exprs, field_columns)
+            }
+            _ => {
+                let agg_exprs = field_columns
+                    .iter()
+                    .map(|field_name| make_agg_expr(agg, field_name))
+                    .collect::<Result<Vec<_>>>()?;
+                (agg_exprs, field_columns.into())
+            }
+        };
 
         // sort by the group by expressions as well
         let sort_exprs = group_exprs
@@ -578,7 +781,7 @@ impl Table {
         // and finally create the plan
         let plan = plan_builder.build().context(BuildingPlan)?;
 
-        Ok(SeriesSetPlan::new_from_shared_timestamp(
+        Ok(SeriesSetPlan::new(
             self.table_name(chunk),
             plan,
             tag_columns,
@@ -586,6 +789,21 @@ impl Table {
         ))
     }
 
+    /// Returns the type of the column named `column_name`
+    fn column_data_type(&self, column_name: &str, chunk: &Chunk) -> Result<ArrowDataType> {
+        let column_id =
+            chunk
+                .dictionary
+                .id(column_name)
+                .context(ColumnNameNotFoundInDictionary {
+                    column_name,
+                    chunk: chunk.id,
+                })?;
+        let column = self.column(column_id)?;
+
+        Ok(column.data_type())
+    }
+
     // Returns (tag_columns, field_columns) vectors with the names of
     // all tag and field columns, respectively, after any predicates
     // have been applied. The vectors are sorted by lexically by name.
@@ -689,7 +907,83 @@ impl Table {
             Selection::All => self.all_columns_selection(chunk),
             Selection::Some(cols) => self.specific_columns_selection(chunk, cols),
         }?;
-        self.to_arrow_impl(chunk, &selection)
+        self.to_arrow_impl(chunk, &selection, None)
+    }
+
+    /// Converts the rows of this table whose time is within `range` and
+    /// whose tags have the values in `tag_values` to an arrow record batch.
+    ///
+    /// The tags are compared by their dictionary ids rather than their
+    /// strings: the series having all of the values are found from the
+    /// posting lists of the values, and the rows of these series are kept.
+    /// Only the tag values of the rows that pass are looked up and copied
+    /// into the batch. Pairs naming a column that is not a tag of this
+    /// table are ignored.
+    pub fn to_arrow_filtered(
+        &self,
+        chunk: &Chunk,
+        selection: Selection<'_>,
+        range: Option<TimestampRange>,
+        tag_values: &[(&str, &str)],
+    ) -> Result<RecordBatch> {
+        let selection = match selection {
+            Selection::All => self.all_columns_selection(chunk),
+            Selection::Some(cols) => self.specific_columns_selection(chunk, cols),
+        }?;
+        let rows = self.matching_rows(chunk, range, tag_values);
+        self.to_arrow_impl(chunk, &selection, rows.as_deref())
+    }
+
+    /// Returns the indexes of the rows passing `range` and `tag_values`, as
+    /// described on `to_arrow_filtered`, or None if there is no filter
+    fn matching_rows(
+        &self,
+        chunk: &Chunk,
+        range: Option<TimestampRange>,
+        tag_values: &[(&str, &str)],
+    ) -> Option<Vec<usize>> {
+        if range.is_none() && tag_values.is_empty() {
+            return None;
+        }
+
+        let column = |name: &str| {
+            chunk
+                .dictionary
+                .id(name)
+                .and_then(|column_id| self.columns.get(&column_id))
+        };
+        let mut keep = vec![true; self.row_count()];
+
+        if let (Some(range), Some(Column::I64(times, _))) = (range, column(TIME_COLUMN_NAME)) {
+            for (keep, time) in keep.iter_mut().zip(times) {
+                *keep = *keep && range.contains_opt(*time);
+            }
+        }
+
+        let mut pairs = Vec::with_capacity(tag_values.len());
+        for (tag, value) in tag_values {
+            if let Some(Column::Tag(..)) = column(tag) {
+                // a value that isn't in the dictionary matches no rows
+                let tag_id = chunk.dictionary.id(tag).expect("tag column in dictionary");
+                match chunk.dictionary.id(value) {
+                    Some(value_id) => pairs.push((tag_id, value_id)),
+                    None => return Some(vec![]),
+                }
+            }
+        }
+        if !pairs.is_empty() {
+            let series = self.postings.intersection(&pairs);
+            for (keep, series_id) in keep.iter_mut().zip(&self.series_ids) {
+                *keep = *keep && series.contains(*series_id as u32);
+            }
+        }
+
+        Some(
+            keep.iter()
+                .enumerate()
+                .filter_map(|(row, keep)| if *keep { Some(row) } else { None })
+                .collect(),
+        )
     }
 
     pub fn schema(&self, chunk: &Chunk, selection: Selection<'_>) -> Result<Schema> {
@@ -727,37 +1021,41 @@ impl Table {
         schema_builder.build().context(InternalSchema)
     }
 
-    /// Converts this table to an arrow record batch,
+    /// Converts this table to an arrow record batch, keeping only the rows
+    /// at the indexes in `rows` if set
     ///
     /// requested columns with index are tuples of column_name, column_index
     fn to_arrow_impl(
         &self,
         chunk: &Chunk,
         selection: &TableColSelection<'_>,
+        rows: Option<&[usize]>,
     ) -> Result<RecordBatch> {
         let mut columns = Vec::with_capacity(selection.cols.len());
+        let num_rows = rows.map_or_else(|| self.row_count(), |rows| rows.len());
 
         for col in &selection.cols {
             let column = self.column(col.column_id)?;
 
             let array = match column {
                 Column::String(vals, _) => {
-                    let mut builder = StringBuilder::with_capacity(vals.len(), vals.len() * 10);
+                    let mut builder = StringBuilder::with_capacity(num_rows, num_rows * 10);
 
-                    for v in vals {
+                    for v in select_rows(vals, rows) {
                         match v {
                             None => builder.append_null(),
-                            Some(s) => builder.append_value(s),
+                            Some(s) => builder.append_value(chunk.arena.get(*s)),
                         }
                         .context(ArrowError {})?;
                     }
 
                     Arc::new(builder.finish()) as ArrayRef
                 }
-                Column::Tag(vals, _) => {
-                    let mut builder = StringBuilder::with_capacity(vals.len(), vals.len() * 10);
+                Column::Tag(..) => {
+                    let mut builder = StringBuilder::with_capacity(num_rows, num_rows * 10);
+                    let vals = self.tag_values(col.column_id);
 
-                    for v in vals {
+                    for v in select_rows(&vals, rows) {
                         match v {
                             None => builder.append_null(),
                             Some(value_id) => {
@@ -776,27 +1074,27 @@ impl Table {
                     Arc::new(builder.finish()) as ArrayRef
                 }
                 Column::F64(vals, _) => {
-                    let mut builder = Float64Builder::new(vals.len());
+                    let mut builder = Float64Builder::new(num_rows);
 
-                    for v in vals {
+                    for v in select_rows(vals, rows) {
                         builder.append_option(*v).context(ArrowError {})?;
                     }
 
                     Arc::new(builder.finish()) as ArrayRef
                 }
                 Column::I64(vals, _) => {
-                    let mut builder = Int64Builder::new(vals.len());
+                    let mut builder = Int64Builder::new(num_rows);
 
-                    for v in vals {
+                    for v in select_rows(vals, rows) {
                         builder.append_option(*v).context(ArrowError {})?;
                     }
 
                     Arc::new(builder.finish()) as ArrayRef
                 }
                 Column::Bool(vals, _) => {
-                    let mut builder = BooleanBuilder::new(vals.len());
+                    let mut builder = BooleanBuilder::new(num_rows);
 
-                    for v in vals {
+                    for v in select_rows(vals, rows) {
                         builder.append_option(*v).context(ArrowError {})?;
                     }
 
@@ -888,10 +1186,11 @@ impl Table {
         true
     }
 
-    /// returns true if there are any rows in column that are non-null
-    /// and within the timestamp range specified by pred
+    /// returns true if there are any rows in column `column_id` that are
+    /// non-null and within the timestamp range specified by pred
     pub(crate) fn column_matches_predicate(
         &self,
+        column_id: u32,
         column: &Column,
         chunk_predicate: &ChunkPredicate,
     ) -> Result<bool> {
@@ -900,7 +1199,9 @@ impl Table {
             Column::I64(v, _) => self.column_value_matches_predicate(v, chunk_predicate),
             Column::String(v, _) => self.column_value_matches_predicate(v, chunk_predicate),
             Column::Bool(v, _) => self.column_value_matches_predicate(v, chunk_predicate),
-            Column::Tag(v, _) => self.column_value_matches_predicate(v, chunk_predicate),
+            Column::Tag(..) => {
+                self.column_value_matches_predicate(&self.tag_values(column_id), chunk_predicate)
+            }
         }
     }
 
@@ -955,6 +1256,17 @@ impl Table {
     }
 }
 
+/// Returns the values of `vals` at the indexes in `rows`, or all of them
+fn select_rows<'a, T>(
+    vals: &'a [T],
+    rows: Option<&'a [usize]>,
+) -> Box<dyn Iterator<Item = &'a T> + 'a> {
+    match rows {
+        Some(rows) => Box::new(rows.iter().map(move |&row| &vals[row])),
+        None => Box::new(vals.iter()),
+    }
+}
+
 /// Reorders tag_columns so that its prefix matches exactly
 /// prefix_columns. Returns an error if there are duplicates, or other
 /// untoward inputs
@@ -1064,7 +1376,11 @@ impl AggExprs {
         F: Fn(&str) -> Result<ArrowDataType>,
     {
         match agg {
-            Aggregate::Sum | Aggregate::Count | Aggregate::Mean => {
+            Aggregate::Sum
+            | Aggregate::Count
+            | Aggregate::CountDistinct
+            | Aggregate::Mean
+            | Aggregate::Percentile(_) => {
                 //  agg_function(_val1) as _value1
                 //  ...
                 //  agg_function(_valN) as _valueN
@@ -1209,7 +1525,7 @@ mod tests {
             "h2o,state=MA,city=Boston temp=72.4 250",
         ];
 
-        write_lines_to_table(&mut table, dictionary, lp_lines);
+        write_lines_to_table(&mut table, dictionary, &mut chunk.arena, lp_lines);
 
         let state_symbol = dictionary.id("state").unwrap();
         let new_symbol = dictionary.lookup_value_or_insert("not_a_columns");
@@ -1251,18 +1567,109 @@ mod tests {
             "h2o,state=MA,city=Boston temp=72.4 250",
         ];
 
-        write_lines_to_table(&mut table, dictionary, lp_lines.clone());
-        assert_eq!(128, table.size());
+        write_lines_to_table(&mut table, dictionary, &mut chunk.arena, lp_lines.clone());
+        assert_eq!(160, table.size());
 
-        // doesn't double because of the stats overhead
-        write_lines_to_table(&mut table, dictionary, lp_lines.clone());
-        assert_eq!(224, table.size());
+        // doesn't double because of the stats and series key overhead
+        write_lines_to_table(&mut table, dictionary, &mut chunk.arena, lp_lines.clone());
+        assert_eq!(240, table.size());
 
         // now make sure it increased by the same amount minus stats overhead
-        write_lines_to_table(&mut table, dictionary, lp_lines);
+        write_lines_to_table(&mut table, dictionary, &mut chunk.arena, lp_lines);
         assert_eq!(320, table.size());
     }
 
+    #[test]
+    fn table_series_ids() {
+        let mut chunk = Chunk::new(42);
+        let dictionary = &mut chunk.dictionary;
+        let mut table = Table::new(dictionary.lookup_value_or_insert("h2o"));
+
+        let lp_lines = vec![
+            "h2o,state=MA,city=Boston temp=70.4 100",
+            "h2o,city=Boston,state=MA temp=72.4 250",
+            "h2o,state=CA,city=LA temp=90.0 200",
+            "h2o,state=MA temp=71.4 300",
+            "h2o temp=70.0 350",
+            "h2o,state=CA,city=LA temp=91.0 400",
+        ];
+
+        write_lines_to_table(&mut table, dictionary, &mut chunk.arena, lp_lines);
+
+        // tag order in the line does not matter
+        assert_eq!(table.series_ids(), &[0, 0, 1, 2, 3, 1]);
+        assert_eq!(table.series_count(), 4);
+
+        let state = dictionary.id("state").unwrap();
+        let city = dictionary.id("city").unwrap();
+        let ca = dictionary.id("CA").unwrap();
+        let la = dictionary.id("LA").unwrap();
+        let mut expected = vec![(state, ca), (city, la)];
+        expected.sort_unstable();
+        assert_eq!(table.series_key(1).unwrap(), expected.as_slice());
+
+        let ma = dictionary.id("MA").unwrap();
+        assert_eq!(table.series_key(2).unwrap(), &[(state, ma)]);
+        assert!(table.series_key(3).unwrap().is_empty());
+
+        let err = table.series_key(4).unwrap_err().to_string();
+        assert!(err.contains("Series 4 not found in table"), "{}", err);
+    }
+
+    #[test]
+    fn table_series_ids_across_batches() {
+        let mut chunk = Chunk::new(42);
+        let dictionary = &mut chunk.dictionary;
+        let mut table = Table::new(dictionary.lookup_value_or_insert("h2o"));
+
+        let lp_lines = vec!["h2o,state=MA temp=70.4 100", "h2o,state=CA temp=72.4 250"];
+        write_lines_to_table(&mut table, dictionary, &mut chunk.arena, lp_lines);
+
+        // the series of a batch are looked up once, in any order, but new
+        // series are numbered by their first row
+        let lp_lines = vec![
+            "h2o,state=NY temp=70.1 300",
+            "h2o,state=CA temp=72.1 350",
+            "h2o,state=NY temp=70.2 400",
+            "h2o,state=MA temp=72.2 450",
+            "h2o,state=AZ temp=72.3 500",
+        ];
+        write_lines_to_table(&mut table, dictionary, &mut chunk.arena, lp_lines);
+
+        assert_eq!(table.series_ids(), &[0, 1, 2, 1, 2, 0, 3]);
+        assert_eq!(table.series_count(), 4);
+        assert_eq!(table.row_count(), 7);
+    }
+
+    #[test]
+    fn table_tag_values() {
+        let mut chunk = Chunk::new(42);
+        let dictionary = &mut chunk.dictionary;
+        let mut table = Table::new(dictionary.lookup_value_or_insert("h2o"));
+
+        let lp_lines = vec!["h2o temp=70.0 100", "h2o,state=MA temp=70.4 200"];
+        write_lines_to_table(&mut table, dictionary, &mut chunk.arena, lp_lines);
+
+        let lp_lines = vec![
+            "h2o,state=CA,city=LA temp=90.0 300",
+            "h2o,state=MA temp=71.4 400",
+        ];
+        write_lines_to_table(&mut table, dictionary, &mut chunk.arena, lp_lines);
+
+        // the values of each row are read back from the key of its series,
+        // including for rows written before the tag column existed
+        let state = dictionary.id("state").unwrap();
+        let city = dictionary.id("city").unwrap();
+        let ma = dictionary.id("MA").unwrap();
+        let ca = dictionary.id("CA").unwrap();
+        let la = dictionary.id("LA").unwrap();
+        assert_eq!(
+            table.tag_values(state),
+            vec![None, Some(ma), Some(ca), Some(ma)]
+        );
+        assert_eq!(table.tag_values(city), vec![None, None, Some(la), None]);
+    }
+
     #[test]
     fn test_matches_table_name_predicate() {
         let mut chunk = Chunk::new(42);
@@ -1273,7 +1680,7 @@ mod tests {
             "h2o,state=MA,city=Boston temp=70.4 100",
             "h2o,state=MA,city=Boston temp=72.4 250",
         ];
-        write_lines_to_table(&mut table, dictionary, lp_lines);
+        write_lines_to_table(&mut table, dictionary, &mut chunk.arena, lp_lines);
 
         let h2o_symbol = dictionary.id("h2o").unwrap();
 
@@ -1303,7 +1710,7 @@ mod tests {
             "h2o,state=MA,city=Boston temp=70.4,awesomeness=1000 100",
             "h2o,state=MA,city=Boston temp=72.4,awesomeness=2000 250",
         ];
-        write_lines_to_table(&mut table, dictionary, lp_lines);
+        write_lines_to_table(&mut table, dictionary, &mut chunk.arena, lp_lines);
 
         let state_symbol = dictionary.id("state").unwrap();
         let temp_symbol = dictionary.id("temp").unwrap();
@@ -1349,7 +1756,7 @@ mod tests {
             "h2o,state=MA,city=Boston float_field=70.4,int_field=8i,bool_field=t,string_field=\"foo\" 100",
         ];
 
-        write_lines_to_table(&mut table, dictionary, lp_lines);
+        write_lines_to_table(&mut table, dictionary, &mut chunk.arena, lp_lines);
 
         let selection = Selection::All;
         let actual_schema = table.schema(&chunk, selection).unwrap();
@@ -1379,7 +1786,7 @@ mod tests {
 
         let lp_lines = vec!["h2o,state=MA,city=Boston float_field=70.4 100"];
 
-        write_lines_to_table(&mut table, dictionary, lp_lines);
+        write_lines_to_table(&mut table, dictionary, &mut chunk.arena, lp_lines);
 
         let selection = Selection::Some(&["float_field"]);
         let actual_schema = table.schema(&chunk, selection).unwrap();
@@ -1395,6 +1802,63 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_to_arrow_filtered() {
+        let mut chunk = Chunk::new(42);
+        let dictionary = &mut chunk.dictionary;
+        let mut table = Table::new(dictionary.lookup_value_or_insert("table_name"));
+
+        let lp_lines = vec![
+            "h2o,state=MA,city=Boston temp=70.4 100",
+            "h2o,state=MA,city=Boston temp=72.4 250",
+            "h2o,state=CA,city=LA temp=90.0 200",
+            "h2o,state=MA,city=Kingston temp=60.1 300",
+            "h2o,state=MA temp=60.2 350",
+        ];
+
+        write_lines_to_table(&mut table, dictionary, &mut chunk.arena, lp_lines);
+
+        let filtered = |range: Option<TimestampRange>, tag_values: &[(&str, &str)]| {
+            table
+                .to_arrow_filtered(&chunk, Selection::All, range, tag_values)
+                .unwrap()
+        };
+        let to_strings = |range: Option<TimestampRange>, tag_values: &[(&str, &str)]| {
+            pretty_format_batches(&[filtered(range, tag_values)])
+                .unwrap()
+                .trim()
+                .split('\n')
+                .map(|s| s.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        let expected = vec![
+            "+--------+-------+------+------+",
+            "| city   | state | temp | time |",
+            "+--------+-------+------+------+",
+            "| Boston | MA    | 72.4 | 250  |",
+            "+--------+-------+------+------+",
+        ];
+        let range = Some(TimestampRange::new(200, 300));
+        assert_eq!(to_strings(range, &[("state", "MA")]), expected);
+
+        let expected = vec![
+            "+----------+-------+------+------+",
+            "| city     | state | temp | time |",
+            "+----------+-------+------+------+",
+            "| Kingston | MA    | 60.1 | 300  |",
+            "|          | MA    | 60.2 | 350  |",
+            "+----------+-------+------+------+",
+        ];
+        let range = Some(TimestampRange::new(300, 400));
+        assert_eq!(to_strings(range, &[]), expected);
+
+        // values not in the dictionary match nothing, and non tag
+        // columns are left alone
+        assert_eq!(filtered(None, &[("state", "NY")]).num_rows(), 0);
+        assert_eq!(filtered(None, &[("temp", "NY")]).num_rows(), 5);
+    }
+
     #[tokio::test]
     async fn test_series_set_plan() {
         let mut chunk = Chunk::new(42);
@@ -1408,7 +1872,7 @@ mod tests {
             "h2o,state=CA,city=LA temp=90.0 350",
         ];
 
-        write_lines_to_table(&mut table, dictionary, lp_lines);
+        write_lines_to_table(&mut table, dictionary, &mut chunk.arena, lp_lines);
 
         let predicate = PredicateBuilder::default().build();
         let chunk_predicate = chunk.compile_predicate(&predicate).unwrap();
@@ -1456,7 +1920,7 @@ mod tests {
             "h2o,state=MA,city=Boston temp=70.5,other=5.0 250",
         ];
 
-        write_lines_to_table(&mut table, dictionary, lp_lines);
+        write_lines_to_table(&mut table, dictionary, &mut chunk.arena, lp_lines);
 
         let predicate = PredicateBuilder::default().build();
         let chunk_predicate = chunk.compile_predicate(&predicate).unwrap();
@@ -1504,7 +1968,7 @@ mod tests {
             "h2o,state=CA,city=LA temp=90.0 350",
         ];
 
-        write_lines_to_table(&mut table, dictionary, lp_lines);
+        write_lines_to_table(&mut table, dictionary, &mut chunk.arena, lp_lines);
 
         let predicate = PredicateBuilder::default()
             .add_expr(col("city").eq(lit("LA")))
@@ -1904,7 +2368,7 @@ mod tests {
             "h2o,state=CA,city=LA temp=94.0 500",
         ];
 
-        write_lines_to_table(&mut table, dictionary, lp_lines);
+        write_lines_to_table(&mut table, dictionary, &mut chunk.arena, lp_lines);
 
         let predicate = PredicateBuilder::default()
             // city=Boston or city=LA
@@ -1944,6 +2408,63 @@ mod tests {
         assert_eq!(expected, results, "expected output");
     }
 
+    #[tokio::test]
+    async fn test_grouped_window_series_set_plan_selector() {
+        let mut chunk = Chunk::new(42);
+        let dictionary = &mut chunk.dictionary;
+        let mut table = Table::new(dictionary.lookup_value_or_insert("table_name"));
+
+        let lp_lines = vec![
+            "h2o,state=MA,city=Boston temp=70.0 100",
+            "h2o,state=MA,city=Boston temp=72.0 200",
+            "h2o,state=MA,city=Boston temp=71.0 300",
+            "h2o,state=MA,city=Boston temp=73.0 400",
+            "h2o,state=CA,city=LA temp=90.0 100",
+            "h2o,state=CA,city=LA temp=91.0 200",
+            "h2o,state=CA,city=LA temp=92.0 300",
+        ];
+
+        write_lines_to_table(&mut table, dictionary, &mut chunk.arena, lp_lines);
+
+        let predicate = PredicateBuilder::default().build();
+        let chunk_predicate = chunk.compile_predicate(&predicate).unwrap();
+
+        let agg = Aggregate::Max;
+        let every = WindowDuration::from_nanoseconds(200);
+        let offset = WindowDuration::from_nanoseconds(0);
+
+        let plan = table
+            .window_grouped_series_set_plan(&chunk_predicate, agg, &every, &offset, &chunk)
+            .expect("creating the grouped_series set plan");
+
+        assert_eq!(plan.tag_columns, *str_vec_to_arc_vec(&["city", "state"]));
+        assert_eq!(
+            plan.field_columns,
+            FieldColumns::DifferentTimestamp(vec![(
+                Arc::new("temp".to_string()),
+                Arc::new("time_temp".to_string())
+            )])
+        );
+
+        // run the created plan, ensuring the output is as expected
+        let results = run_plan(plan.plan).await;
+
+        // the selected values keep their time, not the window bound
+        let expected = vec![
+            "+--------+-------+------+------+-----------+",
+            "| city   | state | time | temp | time_temp |",
+            "+--------+-------+------+------+-----------+",
+            "| Boston | MA    | 200  | 70   | 100       |",
+            "| Boston | MA    | 400  | 72   | 200       |",
+            "| Boston | MA    | 600  | 73   | 400       |",
+            "| LA     | CA    | 200  | 90   | 100       |",
+            "| LA     | CA    | 400  | 92   | 300       |",
+            "+--------+-------+------+------+-----------+",
+        ];
+
+        assert_eq!(expected, results, "expected output");
+    }
+
     #[tokio::test]
     async fn test_grouped_window_series_set_plan_months() {
         let mut chunk = Chunk::new(42);
@@ -1957,7 +2478,7 @@ mod tests {
             "h2o,state=MA,city=Boston temp=73.0 1585785600000000000", // 2020-04-02T00:00:00Z
         ];
 
-        write_lines_to_table(&mut table, dictionary, lp_lines);
+        write_lines_to_table(&mut table, dictionary, &mut chunk.arena, lp_lines);
 
         let predicate = PredicateBuilder::default().build();
         let chunk_predicate = chunk.compile_predicate(&predicate).unwrap();
@@ -2093,7 +2614,12 @@ mod tests {
     }
 
     ///  Insert the line protocol lines in `lp_lines` into this table
-    fn write_lines_to_table(table: &mut Table, dictionary: &mut Dictionary, lp_lines: Vec<&str>) {
+    fn write_lines_to_table(
+        table: &mut Table,
+        dictionary: &mut Dictionary,
+        arena: &mut StringArena,
+        lp_lines: Vec<&str>,
+    ) {
         let lp_data = lp_lines.join("\n");
 
         let lines: Vec<_> = parse_lines(&lp_data).map(|l| l.unwrap()).collect();
@@ -2108,7 +2634,7 @@ mod tests {
             for batch in table_batches {
                 let rows = batch.rows().expect("Had rows in the batch");
                 table
-                    .append_rows(dictionary, &rows)
+                    .append_rows(dictionary, arena, &rows)
                     .expect("Appended the row");
             }
         }
@@ -2131,7 +2657,7 @@ mod tests {
             let dictionary = &mut chunk.dictionary;
             let mut table = Table::new(dictionary.lookup_value_or_insert("table_name"));
 
-            write_lines_to_table(&mut table, dictionary, lp_lines);
+            write_lines_to_table(&mut table, dictionary, &mut chunk.arena, lp_lines);
             Self { chunk, table }
         }
 
@@ -3,6 +3,7 @@
 use crate::{
     buffer::slurp_stream_tempfile,
     path::{cloud::CloudPath, DELIMITER},
+    secrets::{names, SecretsProvider},
     ListResult, ObjectMeta, ObjectStoreApi,
 };
 use async_trait::async_trait;
@@ -13,11 +14,11 @@ use futures::{
     Stream, StreamExt, TryStreamExt,
 };
 use rusoto_core::ByteStream;
-use rusoto_credential::ChainProvider;
+use rusoto_credential::{AwsCredentials, ChainProvider, CredentialsError, ProvideAwsCredentials};
 use rusoto_s3::S3;
 use snafu::{futures::TryStreamExt as _, OptionExt, ResultExt, Snafu};
 use std::convert::TryFrom;
-use std::{fmt, io};
+use std::{fmt, io, sync::Arc};
 
 /// A specialized `Result` for object store-related errors
 pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -98,6 +99,18 @@ pub enum Error {
 
     #[snafu(display("Unable to buffer data into temporary file, Error: {}", source))]
     UnableToBufferStream { source: std::io::Error },
+
+    #[snafu(display("Invalid AWS region {}: {}", region, source))]
+    InvalidRegion {
+        region: String,
+        source: rusoto_core::region::ParseRegionError,
+    },
+}
+
+/// Parses the name of an Amazon region, such as `us-east-2`, to connect to
+/// with [`AmazonS3::new`] or [`AmazonS3::new_with_secrets`].
+pub fn region(name: &str) -> Result<rusoto_core::Region> {
+    name.parse().context(InvalidRegion { region: name })
 }
 
 /// Configuration for connecting to [Amazon S3](https://aws.amazon.com/s3/).
@@ -295,6 +308,23 @@ impl AmazonS3 {
         }
     }
 
+    /// Configure a connection to Amazon S3 like `new`, with the credentials
+    /// read from `secrets` for each request, so that rotated credentials are
+    /// used as soon as `secrets` provides them.
+    pub fn new_with_secrets(
+        region: rusoto_core::Region,
+        bucket_name: impl Into<String>,
+        secrets: Arc<dyn SecretsProvider>,
+    ) -> Self {
+        let http_client = rusoto_core::request::HttpClient::new()
+            .expect("Current implementation of rusoto_core has no way for this to fail");
+        let credentials_provider = SecretsCredentials { secrets };
+        Self {
+            client: rusoto_s3::S3Client::new_with(http_client, credentials_provider, region),
+            bucket_name: bucket_name.into(),
+        }
+    }
+
     /// List objects with the given prefix and a set delimiter of `/`. Returns
     /// common prefixes (directories) in addition to object metadata. Optionally
     /// takes a continuation token for paging.
@@ -367,6 +397,39 @@ impl AmazonS3 {
     }
 }
 
+/// Provides the S3 credentials from a `SecretsProvider`
+#[derive(Debug)]
+struct SecretsCredentials {
+    secrets: Arc<dyn SecretsProvider>,
+}
+
+impl SecretsCredentials {
+    async fn secret(&self, name: &str) -> Result<Option<String>, CredentialsError> {
+        self.secrets
+            .secret(name)
+            .await
+            .map(|secret| secret.map(|secret| secret.expose().to_string()))
+            .map_err(|e| CredentialsError::new(e.to_string()))
+    }
+}
+
+#[async_trait]
+impl ProvideAwsCredentials for SecretsCredentials {
+    async fn credentials(&self) -> Result<AwsCredentials, CredentialsError> {
+        let missing = |name| CredentialsError::new(format!("Secret {} is not set", name));
+        let key = self
+            .secret(names::AWS_ACCESS_KEY_ID)
+            .await?
+            .ok_or_else(|| missing(names::AWS_ACCESS_KEY_ID))?;
+        let secret = self
+            .secret(names::AWS_SECRET_ACCESS_KEY)
+            .await?
+            .ok_or_else(|| missing(names::AWS_SECRET_ACCESS_KEY))?;
+        let token = self.secret(names::AWS_SESSION_TOKEN).await?;
+        Ok(AwsCredentials::new(key, secret, token, None))
+    }
+}
+
 impl Error {
     #[cfg(test)]
     fn s3_error_due_to_credentials(&self) -> bool {
@@ -23,13 +23,14 @@ pub mod disk;
 pub mod gcp;
 pub mod memory;
 pub mod path;
+pub mod secrets;
 
 use aws::AmazonS3;
 use azure::MicrosoftAzure;
 use disk::File;
 use gcp::GoogleCloudStorage;
 use memory::InMemory;
-use path::ObjectStorePath;
+use path::{parsed::DirsAndFileName, ObjectStorePath};
 
 use async_trait::async_trait;
 use bytes::Bytes;
@@ -113,6 +114,24 @@ impl ObjectStore {
     pub fn new_microsoft_azure(azure: MicrosoftAzure) -> Self {
         Self(ObjectStoreIntegration::MicrosoftAzure(Box::new(azure)))
     }
+
+    /// Returns the location in this object store of `location`, a location
+    /// in any object store, e.g. to copy an object between object stores.
+    pub fn convert_path(&self, location: &path::Path) -> path::Path {
+        self.path_from_parsed(location.clone().into())
+    }
+
+    /// Returns the location in this object store of the parsed `location`
+    pub fn path_from_parsed(&self, location: DirsAndFileName) -> path::Path {
+        use ObjectStoreIntegration::*;
+        match &self.0 {
+            AmazonS3(_) => path::Path::AmazonS3(location.into()),
+            GoogleCloudStorage(_) => path::Path::GoogleCloudStorage(location.into()),
+            InMemory(_) => path::Path::InMemory(location),
+            File(_) => path::Path::File(location.into()),
+            MicrosoftAzure(_) => path::Path::MicrosoftAzure(location.into()),
+        }
+    }
 }
 
 #[async_trait]
@@ -668,6 +687,21 @@ mod tests {
         }
     }
 
+    #[test]
+    fn convert_path() {
+        let memory = ObjectStore::new_in_memory(InMemory::new());
+        let file = ObjectStore::new_file(File::new("/tmp"));
+
+        let mut location = memory.new_path();
+        location.push_all_dirs(&["1", "mydb"]);
+        location.set_file_name("rules.json");
+
+        let converted = file.convert_path(&location);
+        assert!(matches!(converted, path::Path::File(_)));
+        assert_eq!(converted.display(), "1/mydb/rules.json");
+        assert_eq!(memory.convert_path(&converted), location);
+    }
+
     // Tests TODO:
     // GET nonexisting location (in_memory/file)
     // DELETE nonexisting location
@@ -118,6 +118,29 @@ impl DirsAndFileName {
         Some(parts)
     }
 
+    /// Whether `self` is in the directory `dir` or one of its subdirectories.
+    /// Unlike `prefix_matches`, whole directory names are compared, so
+    /// `foo/bar2/x` is not in `foo/bar`. Ignores any `file_name` part of
+    /// `dir`.
+    pub fn is_in_dir(&self, dir: &Self) -> bool {
+        self.directories.starts_with(&dir.directories)
+    }
+
+    /// Returns `self` moved from the directory `from` to the directory `to`,
+    /// keeping its place within them, or None if it isn't in `from`
+    pub fn move_dir(&self, from: &Self, to: &Self) -> Option<Self> {
+        if !self.is_in_dir(from) {
+            return None;
+        }
+
+        let mut moved = to.clone();
+        moved
+            .directories
+            .extend_from_slice(&self.directories[from.directories.len()..]);
+        moved.file_name = self.file_name.clone();
+        Some(moved)
+    }
+
     /// Add a `PathPart` to the end of the path's directories.
     pub(crate) fn push_part_as_dir(&mut self, part: &PathPart) {
         self.directories.push(part.to_owned());
@@ -292,4 +315,31 @@ mod tests {
             needle
         );
     }
+
+    #[test]
+    fn is_in_dir() {
+        let mut dir = DirsAndFileName::default();
+        dir.push_all_dirs(&["1", "a_b"]);
+
+        let mut path = dir.clone();
+        path.push_dir("data");
+        path.set_file_name("x.segment");
+        assert!(path.is_in_dir(&dir));
+
+        let mut sibling = DirsAndFileName::default();
+        sibling.push_all_dirs(&["1", "a_b2", "data"]);
+        sibling.set_file_name("x.segment");
+        assert!(sibling.prefix_matches(&dir));
+        assert!(!sibling.is_in_dir(&dir));
+
+        assert!(!dir.is_in_dir(&path));
+
+        let mut to = DirsAndFileName::default();
+        to.push_all_dirs(&["1", "c_b"]);
+        let mut expected = to.clone();
+        expected.push_dir("data");
+        expected.set_file_name("x.segment");
+        assert_eq!(path.move_dir(&dir, &to), Some(expected));
+        assert_eq!(sibling.move_dir(&dir, &to), None);
+    }
 }
@@ -0,0 +1,302 @@
+//! This module contains the providers of secrets, such as object store
+//! credentials, that are better not passed on the command line.
+//!
+//! Secrets are looked up by name each time they are needed, so that rotated
+//! secrets are used without a restart. A [`Secret`] hides its value from
+//! `Debug` and `Display`, so that it doesn't end up in logs.
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use serde::Deserialize;
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+use std::{collections::HashMap, fmt, path::PathBuf, sync::Mutex};
+
+/// A specialized `Result` for secrets-related errors
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// A specialized `Error` for secrets-related errors
+#[derive(Debug, Snafu)]
+#[allow(missing_docs)]
+pub enum Error {
+    #[snafu(display("Unable to read secret {} from {:?}: {}", name, path, source))]
+    ReadingSecretFile {
+        name: String,
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Invalid secret name {:?}", name))]
+    InvalidSecretName { name: String },
+
+    #[snafu(display("Error requesting instance metadata from {}: {}", url, source))]
+    RequestingMetadata { url: String, source: reqwest::Error },
+
+    #[snafu(display("Invalid instance metadata from {}: {}", url, reason))]
+    InvalidMetadata { url: String, reason: String },
+}
+
+/// A secret value, which is redacted when formatted
+#[derive(Clone, PartialEq, Eq)]
+pub struct Secret(String);
+
+impl Secret {
+    /// Wraps `value`
+    pub fn new(value: impl Into<String>) -> Self {
+        Self(value.into())
+    }
+
+    /// Returns the secret value, which must not be logged
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Secret(<redacted>)")
+    }
+}
+
+impl fmt::Display for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("<redacted>")
+    }
+}
+
+/// The names of the secrets holding the credentials of Amazon S3
+pub mod names {
+    /// The access key id of Amazon S3
+    pub const AWS_ACCESS_KEY_ID: &str = "aws_access_key_id";
+    /// The secret access key of Amazon S3
+    pub const AWS_SECRET_ACCESS_KEY: &str = "aws_secret_access_key";
+    /// The session token of temporary Amazon S3 credentials, if any
+    pub const AWS_SESSION_TOKEN: &str = "aws_session_token";
+}
+
+/// Looks up secrets by their lowercase names, such as those in [`names`]
+#[async_trait]
+pub trait SecretsProvider: fmt::Debug + Send + Sync + 'static {
+    /// Returns the current value of the secret `name`, `None` if the
+    /// provider doesn't have it
+    async fn secret(&self, name: &str) -> Result<Option<Secret>>;
+}
+
+/// Reads each secret from the environment variable with its name in
+/// uppercase, e.g. `aws_access_key_id` from `AWS_ACCESS_KEY_ID`
+#[derive(Debug, Default, Clone, Copy)]
+pub struct EnvSecrets;
+
+#[async_trait]
+impl SecretsProvider for EnvSecrets {
+    async fn secret(&self, name: &str) -> Result<Option<Secret>> {
+        Ok(std::env::var(name.to_uppercase()).ok().map(Secret))
+    }
+}
+
+/// Reads each secret from the file with its name in a directory, as mounted
+/// by Kubernetes and Docker secrets. The files are read each time, so
+/// rewriting a file rotates the secret.
+#[derive(Debug, Clone)]
+pub struct FileSecrets {
+    dir: PathBuf,
+}
+
+impl FileSecrets {
+    /// Reads the secrets from the files in `dir`
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+}
+
+#[async_trait]
+impl SecretsProvider for FileSecrets {
+    async fn secret(&self, name: &str) -> Result<Option<Secret>> {
+        // a name must not reach outside of the directory
+        ensure!(
+            !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'),
+            InvalidSecretName { name }
+        );
+
+        let path = self.dir.join(name);
+        match tokio::fs::read_to_string(&path).await {
+            Ok(value) => Ok(Some(Secret(
+                value.trim_end_matches(&['\r', '\n'][..]).into(),
+            ))),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(source) => Err(Error::ReadingSecretFile {
+                name: name.into(),
+                path,
+                source,
+            }),
+        }
+    }
+}
+
+/// The address of the EC2 instance metadata service
+const AWS_METADATA_ENDPOINT: &str = "http://169.254.169.254";
+
+/// Credentials are fetched again this long before they expire
+const AWS_METADATA_REFRESH_MARGIN_MINUTES: i64 = 5;
+
+/// Provides the [`names`] of the Amazon S3 credentials of the IAM role of the
+/// EC2 instance the server runs on, from its instance metadata service
+/// (IMDSv2). The credentials are cached until shortly before they expire.
+#[derive(Debug)]
+pub struct AwsInstanceMetadata {
+    client: reqwest::Client,
+    endpoint: String,
+    cached: Mutex<Option<(DateTime<Utc>, HashMap<&'static str, Secret>)>>,
+}
+
+impl Default for AwsInstanceMetadata {
+    fn default() -> Self {
+        Self::new(AWS_METADATA_ENDPOINT)
+    }
+}
+
+/// The role credentials returned by the instance metadata service
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct AwsRoleCredentials {
+    access_key_id: String,
+    secret_access_key: String,
+    token: String,
+    expiration: String,
+}
+
+impl AwsInstanceMetadata {
+    /// Fetches the credentials from the instance metadata service at
+    /// `endpoint`
+    pub fn new(endpoint: impl Into<String>) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            endpoint: endpoint.into(),
+            cached: Default::default(),
+        }
+    }
+
+    async fn fetch(&self) -> Result<(DateTime<Utc>, HashMap<&'static str, Secret>)> {
+        let url = format!("{}/latest/api/token", self.endpoint);
+        let session_token = self
+            .client
+            .put(&url)
+            .header("X-aws-ec2-metadata-token-ttl-seconds", "60")
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .context(RequestingMetadata { url: &url })?
+            .text()
+            .await
+            .context(RequestingMetadata { url: &url })?;
+
+        let url = format!(
+            "{}/latest/meta-data/iam/security-credentials/",
+            self.endpoint
+        );
+        let roles = self.get(&url, &session_token).await?;
+        let role = roles.lines().next().context(InvalidMetadata {
+            url: &url,
+            reason: "the instance has no IAM role",
+        })?;
+
+        let url = format!("{}{}", url, role);
+        let credentials = self.get(&url, &session_token).await?;
+        let credentials: AwsRoleCredentials =
+            serde_json::from_str(&credentials).map_err(|e| Error::InvalidMetadata {
+                url: url.clone(),
+                reason: e.to_string(),
+            })?;
+        let expiration = DateTime::parse_from_rfc3339(&credentials.expiration)
+            .map_err(|e| Error::InvalidMetadata {
+                url: url.clone(),
+                reason: e.to_string(),
+            })?
+            .with_timezone(&Utc);
+
+        let mut secrets = HashMap::new();
+        secrets.insert(names::AWS_ACCESS_KEY_ID, Secret(credentials.access_key_id));
+        secrets.insert(
+            names::AWS_SECRET_ACCESS_KEY,
+            Secret(credentials.secret_access_key),
+        );
+        secrets.insert(names::AWS_SESSION_TOKEN, Secret(credentials.token));
+        Ok((expiration, secrets))
+    }
+
+    async fn get(&self, url: &str, session_token: &str) -> Result<String> {
+        self.client
+            .get(url)
+            .header("X-aws-ec2-metadata-token", session_token)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .context(RequestingMetadata { url })?
+            .text()
+            .await
+            .context(RequestingMetadata { url })
+    }
+}
+
+#[async_trait]
+impl SecretsProvider for AwsInstanceMetadata {
+    async fn secret(&self, name: &str) -> Result<Option<Secret>> {
+        let refresh_after = Utc::now() + Duration::minutes(AWS_METADATA_REFRESH_MARGIN_MINUTES);
+        {
+            let cached = self.cached.lock().expect("mutex poisoned");
+            if let Some((expiration, secrets)) = &*cached {
+                if *expiration > refresh_after {
+                    return Ok(secrets.get(name).cloned());
+                }
+            }
+        }
+
+        // concurrent lookups may fetch the credentials more than once, which
+        // is harmless
+        let (expiration, secrets) = self.fetch().await?;
+        let secret = secrets.get(name).cloned();
+        *self.cached.lock().expect("mutex poisoned") = Some((expiration, secrets));
+        Ok(secret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redaction() {
+        let secret = Secret::new("hunter2");
+        assert_eq!(secret.expose(), "hunter2");
+        assert_eq!(format!("{:?}", secret), "Secret(<redacted>)");
+        assert_eq!(format!("{}", secret), "<redacted>");
+        assert_eq!(format!("{:?}", Some(&secret)), "Some(Secret(<redacted>))");
+    }
+
+    #[tokio::test]
+    async fn files() {
+        let dir = tempfile::tempdir().unwrap();
+        let secrets = FileSecrets::new(dir.path());
+        assert_eq!(
+            secrets.secret(names::AWS_ACCESS_KEY_ID).await.unwrap(),
+            None
+        );
+
+        let path = dir.path().join(names::AWS_ACCESS_KEY_ID);
+        std::fs::write(&path, "first\n").unwrap();
+        assert_eq!(
+            secrets.secret(names::AWS_ACCESS_KEY_ID).await.unwrap(),
+            Some(Secret::new("first"))
+        );
+
+        // the rotated secret is read without recreating the provider
+        std::fs::write(&path, "second").unwrap();
+        assert_eq!(
+            secrets.secret(names::AWS_ACCESS_KEY_ID).await.unwrap(),
+            Some(Secret::new("second"))
+        );
+
+        for name in &["../etc/passwd", "", "a/b"] {
+            let err = secrets.secret(name).await.unwrap_err();
+            assert!(matches!(err, Error::InvalidSecretName { .. }), "{}", name);
+        }
+    }
+}
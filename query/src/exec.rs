@@ -1,19 +1,41 @@
 //! This module handles the manipulation / execution of storage
 //! plans. This is currently implemented using DataFusion, and this
 //! interface abstracts away many of the details
+//!
+//! Plans are run in tasks of their own. Each call to the `Executor` threads
+//! a cancellation token through the tasks it spawns, which is cancelled when
+//! the future returned by the call is dropped, e.g. because the client
+//! disconnected or the query timed out, so that the tasks stop scanning and
+//! free their memory rather than running to completion.
+//!
+//! The plans of each call also share a memory budget, see the `memory`
+//! module, and fail once they use more memory than the executor allows.
+//!
+//! Once configured with `set_query_threads`, the tasks running the plans
+//! are spawned on a pool of threads of their own, see the `task` module,
+//! rather than on the runtime of the caller.
 pub(crate) mod context;
 mod counters;
 pub mod field;
 pub mod fieldlist;
+pub mod memory;
 mod schema_pivot;
 pub mod seriesset;
 pub mod stringset;
-
-use std::sync::Arc;
+pub mod task;
+pub mod topn;
+
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
 
 use arrow_deps::{
     arrow::record_batch::RecordBatch,
-    datafusion::{self, logical_plan::LogicalPlan},
+    datafusion::{self, logical_plan::LogicalPlan, physical_plan::ExecutionPlan},
 };
 use counters::ExecutionCounters;
 
@@ -21,9 +43,22 @@ use context::IOxExecutionContext;
 use schema_pivot::SchemaPivotNode;
 
 use fieldlist::{FieldList, IntoFieldList};
+use futures::StreamExt;
+use memory::MemoryTracker;
+use parking_lot::RwLock;
 use seriesset::{Error as SeriesSetError, SeriesSetConverter, SeriesSetItem};
 use stringset::{IntoStringSet, StringSetRef};
-use tokio::sync::mpsc::{self, error::SendError};
+use task::DedicatedExecutor;
+use tokio::{
+    sync::{
+        mpsc::{self, error::SendError},
+        Semaphore,
+    },
+    task::JoinHandle,
+};
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_util::sync::CancellationToken;
+use topn::TopN;
 
 use snafu::{ResultExt, Snafu};
 
@@ -74,6 +109,9 @@ pub enum Error {
     #[snafu(display("Error converting results to SeriesSet: {}", source))]
     SeriesSetConversion { source: seriesset::Error },
 
+    #[snafu(display("Error selecting the top series: {}", source))]
+    TopSeriesSelection { source: topn::Error },
+
     #[snafu(display("Internal error creating FieldList: {}", source))]
     FieldListConversion { source: fieldlist::Error },
 
@@ -84,6 +122,9 @@ pub enum Error {
 
     #[snafu(display("Joining execution task: {}", source))]
     JoinError { source: tokio::task::JoinError },
+
+    #[snafu(display("Query cancelled"))]
+    Cancelled {},
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -93,6 +134,15 @@ pub type Result<T, E = Error> = std::result::Result<T, E>;
 #[derive(Debug, Default)]
 pub struct Executor {
     counters: Arc<ExecutionCounters>,
+    /// The number of bytes each query may use, unlimited if 0
+    memory_limit: AtomicUsize,
+    /// The number of bytes used by the queries running
+    memory_used: Arc<AtomicUsize>,
+    /// The number of plans of a query run at a time, the number of cores
+    /// if 0
+    scan_concurrency: AtomicUsize,
+    /// The pool the plans run on, the runtime of the caller if None
+    query_pool: RwLock<Option<Arc<DedicatedExecutor>>>,
 }
 
 impl Executor {
@@ -100,6 +150,62 @@ impl Executor {
         Self::default()
     }
 
+    /// Limits the memory each query may use to `limit` bytes, or removes
+    /// the limit if None. Applies to the queries started from now on.
+    pub fn set_memory_limit(&self, limit: Option<usize>) {
+        self.memory_limit
+            .store(limit.unwrap_or(0), Ordering::Relaxed);
+    }
+
+    /// The number of bytes each query may use, if limited
+    pub fn memory_limit(&self) -> Option<usize> {
+        match self.memory_limit.load(Ordering::Relaxed) {
+            0 => None,
+            limit => Some(limit),
+        }
+    }
+
+    /// The number of bytes used by the queries running, as counted by their
+    /// memory trackers
+    pub fn memory_used(&self) -> usize {
+        self.memory_used.load(Ordering::Relaxed)
+    }
+
+    /// Limits the number of plans of a query run at a time to `limit`, or
+    /// to the number of cores if None. Applies to the queries started from
+    /// now on.
+    pub fn set_scan_concurrency(&self, limit: Option<usize>) {
+        self.scan_concurrency
+            .store(limit.unwrap_or(0), Ordering::Relaxed);
+    }
+
+    /// The number of plans of a query run at a time
+    pub fn scan_concurrency(&self) -> usize {
+        match self.scan_concurrency.load(Ordering::Relaxed) {
+            0 => num_cpus::get(),
+            limit => limit,
+        }
+    }
+
+    /// Runs the plans of the queries started from now on on a pool of
+    /// `threads` threads of their own, or as many as there are cores if 0,
+    /// so that CPU bound scans don't hold up the runtime of the server
+    pub fn set_query_threads(&self, threads: usize) {
+        let threads = match threads {
+            0 => num_cpus::get(),
+            threads => threads,
+        };
+        *self.query_pool.write() = Some(Arc::new(DedicatedExecutor::new("iox-query", threads)));
+    }
+
+    /// The number of threads of the pool the plans run on, if any
+    pub fn query_threads(&self) -> Option<usize> {
+        self.query_pool
+            .read()
+            .as_ref()
+            .map(|pool| pool.num_threads())
+    }
+
     /// Executes this plan and returns the resulting set of strings
     pub async fn to_string_set(&self, plan: StringSetPlan) -> Result<StringSetRef> {
         match plan {
@@ -117,6 +223,12 @@ impl Executor {
     ///
     /// The SeriesSets are guaranteed to come back ordered by table_name
     ///
+    /// At most `scan_concurrency` plans, e.g. the scans of the partitions of
+    /// a large time range, run at a time. The series sets of the plans of a
+    /// table are sent as they are produced, interleaving the plans, except
+    /// for grouped plans, whose series sets are sent a plan after the other
+    /// so that each group stays together.
+    ///
     /// Note that the returned future resolves (e.g. "returns") once
     /// all plans have been sent to `tx`. This means that the future
     /// will not resolve if there is nothing hooked up receiving
@@ -136,57 +248,60 @@ impl Executor {
         // sort by table name and send the results to separate
         // channels
         plans.sort_by(|a, b| a.table_name.cmp(&b.table_name));
-        let mut rx_channels = Vec::new(); // sorted by table names
 
-        // Run the plans in parallel
-        let handles = plans
-            .into_iter()
-            .map(|plan| {
-                // TODO run these on some executor other than the main tokio pool (maybe?)
-                let ctx = self.new_context();
-                let (plan_tx, plan_rx) = mpsc::channel(1);
-                rx_channels.push(plan_rx);
-
-                tokio::task::spawn(async move {
-                    let SeriesSetPlan {
-                        table_name,
-                        plan,
-                        tag_columns,
-                        field_columns,
-                        num_prefix_tag_group_columns,
-                    } = plan;
-
-                    let tag_columns = Arc::new(tag_columns);
-
-                    let physical_plan = ctx
-                        .prepare_plan(&plan)
-                        .await
-                        .context(DataFusionPhysicalPlanning)?;
-
-                    let it = ctx
-                        .execute(physical_plan)
-                        .await
-                        .context(SeriesSetExecution)?;
-
-                    SeriesSetConverter::new(plan_tx)
-                        .convert(
-                            table_name,
-                            tag_columns,
-                            field_columns,
-                            num_prefix_tag_group_columns,
-                            it,
-                        )
-                        .await
-                        .context(SeriesSetConversion)?;
+        let cancel = CancelOnDrop::default();
+        let memory = self.new_memory_tracker();
+
+        // The channels of the plans, in runs sent together: the ungrouped
+        // plans of a table, or a single grouped plan
+        let mut runs: Vec<Vec<mpsc::Receiver<_>>> = vec![];
+        let mut interleaved_table = None;
+        let mut scans = Vec::with_capacity(plans.len());
+        for plan in plans {
+            let (plan_tx, plan_rx) = mpsc::channel(1);
+            let interleaved = plan.num_prefix_tag_group_columns.is_none();
+            match runs.last_mut() {
+                Some(run)
+                    if interleaved && interleaved_table.as_ref() == Some(&plan.table_name) =>
+                {
+                    run.push(plan_rx)
+                }
+                _ => runs.push(vec![plan_rx]),
+            }
+            interleaved_table = if interleaved {
+                Some(Arc::clone(&plan.table_name))
+            } else {
+                None
+            };
+
+            let ctx = self.context_with_memory(Arc::clone(&memory));
+            scans.push((ctx, plan, plan_tx));
+        }
 
-                    Ok(())
-                })
-            })
-            .collect::<Vec<_>>();
+        // Start the plans in the order their results are sent, each once a
+        // scan slot is free, so the plans being sent always get to run
+        let slots = Arc::new(Semaphore::new(self.scan_concurrency()));
+        let token = cancel.0.clone();
+        let started = spawn_cancellable(self.pool().as_deref(), &cancel.0, async move {
+            let mut handles = Vec::with_capacity(scans.len());
+            for (ctx, plan, plan_tx) in scans {
+                let slot = Arc::clone(&slots)
+                    .acquire_owned()
+                    .await
+                    .expect("scan slots are never closed");
+                // spawned on the runtime of the dispatcher, i.e. the pool
+                handles.push(spawn_cancellable(None, &token, async move {
+                    let _slot = slot;
+                    run_series_set_plan(ctx, plan, plan_tx).await
+                }));
+            }
+            Ok(handles)
+        });
 
-        // transfer data from the rx streams in order
-        for mut rx in rx_channels {
-            while let Some(r) = rx.recv().await {
+        // transfer data from the rx streams, a run after the other
+        for run in runs {
+            let mut run = futures::stream::select_all(run.into_iter().map(ReceiverStream::new));
+            while let Some(r) = run.next().await {
                 tx.send(r)
                     .await
                     .map_err(|e| Error::SendingDuringConversion {
@@ -197,24 +312,75 @@ impl Executor {
 
         // now, wait for all the values to resolve so we can report
         // any errors
-        for join_handle in handles {
+        for join_handle in started.await.context(JoinError)?? {
             join_handle.await.context(JoinError)??;
         }
         Ok(())
     }
 
+    /// Executes the embedded plans like `to_series_set`, but only sends
+    /// the points of the series ranked in the top (or bottom) N of each
+    /// window by `top`. All the series sets are collected before they can
+    /// be ranked.
+    pub async fn to_top_series_set(
+        &self,
+        series_set_plans: SeriesSetPlans,
+        top: TopN,
+        tx: mpsc::Sender<Result<SeriesSetItem, SeriesSetError>>,
+    ) -> Result<()> {
+        let (all_tx, mut all_rx) = mpsc::channel(4);
+        let collect = async move {
+            let mut sets = vec![];
+            while let Some(item) = all_rx.recv().await {
+                match item? {
+                    SeriesSetItem::Data(set) => sets.push(set),
+                    // windowed series aren't grouped
+                    SeriesSetItem::GroupStart(_) => {}
+                }
+            }
+            Ok::<_, SeriesSetError>(sets)
+        };
+        let (executed, collected) =
+            futures::join!(self.to_series_set(series_set_plans, all_tx), collect);
+
+        // a conversion error makes the execution fail, so report it first
+        let sets = match collected {
+            Ok(sets) => sets,
+            Err(e) => {
+                return tx
+                    .send(Err(e))
+                    .await
+                    .map_err(|e| Error::SendingDuringConversion {
+                        source: Box::new(e),
+                    })
+            }
+        };
+        executed?;
+
+        for set in top.select(sets).context(TopSeriesSelection)? {
+            tx.send(Ok(SeriesSetItem::Data(set))).await.map_err(|e| {
+                Error::SendingDuringConversion {
+                    source: Box::new(e),
+                }
+            })?
+        }
+        Ok(())
+    }
+
     /// Executes `plan` and return the resulting FieldList
     pub async fn to_field_list(&self, plan: FieldListPlan) -> Result<FieldList> {
         let FieldListPlan { plans } = plan;
+        let cancel = CancelOnDrop::default();
+        let memory = self.new_memory_tracker();
+        let pool = self.pool();
 
         // Run the plans in parallel
         let handles = plans
             .into_iter()
             .map(|plan| {
-                let counters = Arc::clone(&self.counters);
+                let ctx = self.context_with_memory(Arc::clone(&memory));
 
-                tokio::task::spawn(async move {
-                    let ctx = IOxExecutionContext::new(counters);
+                spawn_cancellable(pool.as_deref(), &cancel.0, async move {
                     let physical_plan = ctx
                         .prepare_plan(&plan)
                         .await
@@ -249,20 +415,54 @@ impl Executor {
         self.run_logical_plans(vec![plan]).await
     }
 
+    /// Runs the physical `plan`, e.g. planned by the SQL frontend from a
+    /// context created by `new_context`, and collects its results
+    pub async fn collect(&self, plan: Arc<dyn ExecutionPlan>) -> Result<Vec<RecordBatch>> {
+        let cancel = CancelOnDrop::default();
+        spawn_cancellable(self.pool().as_deref(), &cancel.0, async move {
+            datafusion::physical_plan::collect(plan)
+                .await
+                .context(DataFusionExecution)
+        })
+        .await
+        .context(JoinError)?
+    }
+
     /// Create a new execution context, suitable for executing a new query
     pub fn new_context(&self) -> IOxExecutionContext {
-        IOxExecutionContext::new(Arc::clone(&self.counters))
+        self.context_with_memory(self.new_memory_tracker())
+    }
+
+    /// Returns the tracker of the memory of a new query
+    fn new_memory_tracker(&self) -> Arc<MemoryTracker> {
+        Arc::new(MemoryTracker::with_total(
+            self.memory_limit(),
+            Arc::clone(&self.memory_used),
+        ))
+    }
+
+    /// Creates an execution context for a plan of the query whose memory
+    /// is counted by `memory`
+    fn context_with_memory(&self, memory: Arc<MemoryTracker>) -> IOxExecutionContext {
+        IOxExecutionContext::new(Arc::clone(&self.counters), memory)
+    }
+
+    /// The pool the plans of a query started now run on, if any
+    fn pool(&self) -> Option<Arc<DedicatedExecutor>> {
+        self.query_pool.read().as_ref().map(Arc::clone)
     }
 
     /// plans and runs the plans in parallel and collects the results
     /// run each plan in parallel and collect the results
     async fn run_logical_plans(&self, plans: Vec<LogicalPlan>) -> Result<Vec<RecordBatch>> {
+        let cancel = CancelOnDrop::default();
+        let memory = self.new_memory_tracker();
+        let pool = self.pool();
         let value_futures = plans
             .into_iter()
             .map(|plan| {
-                let ctx = self.new_context();
-                // TODO run these on some executor other than the main tokio pool
-                tokio::task::spawn(async move {
+                let ctx = self.context_with_memory(Arc::clone(&memory));
+                spawn_cancellable(pool.as_deref(), &cancel.0, async move {
                     let physical_plan = ctx
                         .prepare_plan(&plan)
                         .await
@@ -285,6 +485,79 @@ impl Executor {
         Ok(results)
     }
 }
+/// Cancels its token when dropped, along with the future of the `Executor`
+/// call holding it
+#[derive(Debug, Default)]
+struct CancelOnDrop(CancellationToken);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.cancel();
+    }
+}
+
+/// Spawns `task` on `pool`, or on the current runtime if None, which is
+/// dropped as soon as `token` is cancelled, failing with `Cancelled`
+fn spawn_cancellable<T, F>(
+    pool: Option<&DedicatedExecutor>,
+    token: &CancellationToken,
+    task: F,
+) -> JoinHandle<Result<T>>
+where
+    T: Send + 'static,
+    F: Future<Output = Result<T>> + Send + 'static,
+{
+    let token = token.clone();
+    let task = async move {
+        tokio::select! {
+            result = task => result,
+            _ = token.cancelled() => Cancelled.fail(),
+        }
+    };
+    match pool {
+        Some(pool) => pool.spawn(task),
+        None => tokio::task::spawn(task),
+    }
+}
+
+/// Runs the series set `plan`, sending its series sets to `tx`
+async fn run_series_set_plan(
+    ctx: IOxExecutionContext,
+    plan: SeriesSetPlan,
+    tx: mpsc::Sender<Result<SeriesSetItem, SeriesSetError>>,
+) -> Result<()> {
+    let SeriesSetPlan {
+        table_name,
+        plan,
+        tag_columns,
+        field_columns,
+        num_prefix_tag_group_columns,
+    } = plan;
+
+    let tag_columns = Arc::new(tag_columns);
+
+    let physical_plan = ctx
+        .prepare_plan(&plan)
+        .await
+        .context(DataFusionPhysicalPlanning)?;
+
+    let it = ctx
+        .execute(physical_plan)
+        .await
+        .context(SeriesSetExecution)?;
+
+    SeriesSetConverter::new(tx)
+        .convert(
+            table_name,
+            tag_columns,
+            field_columns,
+            num_prefix_tag_group_columns,
+            it,
+        )
+        .await
+        .context(SeriesSetConversion)
+}
+
 /// Create a SchemaPivot node which  an arbitrary input like
 ///  ColA | ColB | ColC
 /// ------+------+------
@@ -309,6 +582,7 @@ pub fn make_schema_pivot(input: LogicalPlan) -> LogicalPlan {
 mod tests {
     use arrow_deps::{
         arrow::{
+            array::Float64Array,
             array::Int64Array,
             array::StringArray,
             array::StringBuilder,
@@ -321,6 +595,18 @@ mod tests {
 
     use super::*;
 
+    #[tokio::test]
+    async fn cancel_on_drop() {
+        let cancel = CancelOnDrop::default();
+        let pending = spawn_cancellable(None, &cancel.0, futures::future::pending::<Result<()>>());
+        let ready = spawn_cancellable(None, &cancel.0, async { Ok(1) });
+        assert_eq!(ready.await.unwrap().unwrap(), 1);
+
+        drop(cancel);
+        let result = pending.await.unwrap();
+        assert!(matches!(result, Err(Error::Cancelled {})));
+    }
+
     #[tokio::test]
     async fn executor_known_string_set_plan_ok() -> Result<()> {
         let expected_strings = to_set(&["Foo", "Bar"]);
@@ -472,6 +758,112 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn executor_memory_limit() {
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Utf8, true)]));
+        let data = to_string_array(&["foo", "bar", "baz", "foo"]);
+        let batch = RecordBatch::try_new(Arc::clone(&schema), vec![data])
+            .expect("created new record batch");
+        let size = memory::batch_memory_size(&batch);
+        let plan = make_plan(schema, vec![batch]);
+
+        let executor = Executor::new();
+        executor.set_memory_limit(Some(size));
+        let results = executor.run_logical_plan(plan.clone()).await.unwrap();
+        assert_eq!(results.len(), 1);
+
+        executor.set_memory_limit(Some(size - 1));
+        let e = executor.run_logical_plan(plan.clone()).await.unwrap_err();
+        assert!(e.to_string().contains("memory limit exceeded"), "{}", e);
+
+        executor.set_memory_limit(None);
+        executor.run_logical_plan(plan).await.unwrap();
+
+        // the memory of the queries is given back once they end
+        assert_eq!(executor.memory_used(), 0);
+    }
+
+    #[tokio::test]
+    async fn executor_series_set_concurrent_plans() {
+        let plans = || {
+            SeriesSetPlans::from(vec![
+                make_series_set_plan("mem", "a"),
+                make_series_set_plan("cpu", "a"),
+                make_series_set_plan("cpu", "b"),
+                make_series_set_plan("cpu", "c"),
+            ])
+        };
+
+        // a single plan at a time, and as many as there are cores
+        let executor = Executor::new();
+        for concurrency in &[Some(1), None] {
+            executor.set_scan_concurrency(*concurrency);
+            let items = run_series_set_plans(&executor, plans()).await;
+
+            let series: Vec<_> = items
+                .iter()
+                .map(|item| match item {
+                    SeriesSetItem::Data(set) => {
+                        (set.table_name.to_string(), set.tags[0].1.to_string())
+                    }
+                    SeriesSetItem::GroupStart(_) => panic!("unexpected group"),
+                })
+                .collect();
+            assert_eq!(series.len(), 4);
+            // the tables are in order, but not necessarily the plans of a table
+            let mut cpu: Vec<_> = series[..3]
+                .iter()
+                .map(|(table, tag)| {
+                    assert_eq!(table, "cpu");
+                    tag.as_str()
+                })
+                .collect();
+            cpu.sort_unstable();
+            assert_eq!(cpu, vec!["a", "b", "c"]);
+            assert_eq!(series[3], ("mem".to_string(), "a".to_string()));
+        }
+
+        // the series sets of grouped plans stay after the start of their group
+        executor.set_scan_concurrency(Some(1));
+        let plans = SeriesSetPlans::from(vec![
+            make_series_set_plan("cpu", "a").grouped(1),
+            make_series_set_plan("cpu", "b").grouped(1),
+        ]);
+        let items = run_series_set_plans(&executor, plans).await;
+        let tags: Vec<_> = items
+            .iter()
+            .map(|item| match item {
+                SeriesSetItem::Data(set) => format!("data {}", set.tags[0].1),
+                SeriesSetItem::GroupStart(group) => format!("group {}", group.tags[0].1),
+            })
+            .collect();
+        assert_eq!(tags, vec!["group a", "data a", "group b", "data b"]);
+    }
+
+    #[tokio::test]
+    async fn executor_query_threads() -> Result<()> {
+        let executor = Executor::new();
+        assert_eq!(executor.query_threads(), None);
+        executor.set_query_threads(2);
+        assert_eq!(executor.query_threads(), Some(2));
+
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Utf8, true)]));
+        let data = to_string_array(&["foo", "bar", "foo"]);
+        let batch = RecordBatch::try_new(Arc::clone(&schema), vec![data])
+            .expect("created new record batch");
+        let plan: StringSetPlan = vec![make_plan(schema, vec![batch])].into();
+        let results = executor.to_string_set(plan).await?;
+        assert_eq!(results, to_set(&["foo", "bar"]));
+
+        let plans = SeriesSetPlans::from(vec![
+            make_series_set_plan("cpu", "a"),
+            make_series_set_plan("cpu", "b"),
+        ]);
+        assert_eq!(run_series_set_plans(&executor, plans).await.len(), 2);
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn make_schema_pivot_is_planned() -> Result<()> {
         // Test that all the planning logic is wired up and that we
@@ -514,6 +906,47 @@ mod tests {
         Arc::new(builder.finish())
     }
 
+    /// Runs `plans`, returning the series set items they produced
+    async fn run_series_set_plans(
+        executor: &Executor,
+        plans: SeriesSetPlans,
+    ) -> Vec<SeriesSetItem> {
+        let (tx, mut rx) = mpsc::channel(10);
+        executor.to_series_set(plans, tx).await.unwrap();
+
+        let mut items = vec![];
+        while let Some(item) = rx.recv().await {
+            items.push(item.unwrap());
+        }
+        items
+    }
+
+    /// Returns a plan producing a single point of the series of `table`
+    /// with the tag `tag` set to `value`
+    fn make_series_set_plan(table: &str, value: &str) -> SeriesSetPlan {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("tag", DataType::Utf8, true),
+            Field::new("value", DataType::Float64, true),
+            Field::new("time", DataType::Int64, false),
+        ]));
+        let batch = RecordBatch::try_new(
+            Arc::clone(&schema),
+            vec![
+                to_string_array(&[value]),
+                Arc::new(Float64Array::from(vec![1.0])),
+                Arc::new(Int64Array::from(vec![1000])),
+            ],
+        )
+        .expect("created new record batch");
+
+        SeriesSetPlan::new_from_shared_timestamp(
+            Arc::new(table.to_string()),
+            make_plan(schema, vec![batch]),
+            vec![Arc::new("tag".to_string())],
+            vec![Arc::new("value".to_string())],
+        )
+    }
+
     // creates a DataFusion plan that reads the RecordBatches into memory
     fn make_plan(schema: SchemaRef, data: Vec<RecordBatch>) -> LogicalPlan {
         let projection = None;
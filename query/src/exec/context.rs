@@ -18,7 +18,13 @@ use arrow_deps::{
     },
 };
 
-use crate::exec::schema_pivot::{SchemaPivotExec, SchemaPivotNode};
+use crate::{
+    exec::{
+        memory::{self, MemoryTracker},
+        schema_pivot::{SchemaPivotExec, SchemaPivotNode},
+    },
+    func::{cardinality, percentile},
+};
 
 use tracing::debug;
 
@@ -74,12 +80,13 @@ impl ExtensionPlanner for IOxExtensionPlanner {
 
 /// This is an execution context for planning in IOx.
 /// It wraps a DataFusion execution context and incudes
-/// statistical counters.
+/// statistical counters and the memory budget of the query.
 ///
 /// Eventually we envision this as also managing resources
 /// and providing visibility into what plans are running
 pub struct IOxExecutionContext {
     counters: Arc<ExecutionCounters>,
+    memory: Arc<MemoryTracker>,
     inner: ExecutionContext,
 }
 
@@ -87,14 +94,16 @@ impl fmt::Debug for IOxExecutionContext {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("IOxExecutionContext")
             .field("counters", &self.counters)
+            .field("memory", &self.memory)
             .field("inner", &"<DataFusion ExecutionContext>")
             .finish()
     }
 }
 
 impl IOxExecutionContext {
-    /// Create an ExecutionContext suitable for executing DataFusion plans
-    pub fn new(counters: Arc<ExecutionCounters>) -> Self {
+    /// Create an ExecutionContext suitable for executing DataFusion plans,
+    /// whose memory is counted by `memory`
+    pub fn new(counters: Arc<ExecutionCounters>, memory: Arc<MemoryTracker>) -> Self {
         const BATCH_SIZE: usize = 1000;
 
         // TBD: Should we be reusing an execution context across all executions?
@@ -102,9 +111,16 @@ impl IOxExecutionContext {
             .with_batch_size(BATCH_SIZE)
             .with_query_planner(Arc::new(IOxQueryPlanner {}));
 
-        let inner = ExecutionContext::with_config(config);
+        let mut inner = ExecutionContext::with_config(config);
+        inner.register_udaf(percentile::approx_percentile());
+        inner.register_udaf(percentile::approx_median());
+        inner.register_udaf(cardinality::count_distinct());
 
-        Self { counters, inner }
+        Self {
+            counters,
+            memory,
+            inner,
+        }
     }
 
     /// returns a reference to the inner datafusion execution context
@@ -140,7 +156,8 @@ impl IOxExecutionContext {
             plan.display_graphviz(),
         );
 
-        self.inner.create_physical_plan(&plan)
+        let plan = self.inner.create_physical_plan(&plan)?;
+        memory::track_buffered(plan, &self.memory)
     }
 
     /// Executes the logical plan using DataFusion and produces RecordBatches
@@ -149,7 +166,8 @@ impl IOxExecutionContext {
 
         debug!("Running plan, physical:\n{:?}", physical_plan);
 
-        collect(physical_plan).await
+        // the results are all held in memory
+        collect(memory::track_output(physical_plan, &self.memory)).await
     }
 
     /// Executes the physical plan and produces a RecordBatchStream to stream
@@ -0,0 +1,300 @@
+//! Accounting of the memory used by each query against a budget, so that a
+//! query needing more memory than allowed fails with a clear error rather
+//! than growing the process until it runs out of memory.
+//!
+//! DataFusion doesn't report the memory its operators allocate, so the
+//! memory of a query is estimated from the record batches that stay in
+//! memory while it runs: the inputs of the operators which buffer all
+//! their input (sorts and the build side of hash joins), the state output
+//! by aggregations and the results collected in memory. The memory is
+//! counted as the batches are produced and is not given back until the
+//! query ends. The memory of the queries running is also added up across
+//! queries, so that the server can account for it.
+
+use std::{
+    any::Any,
+    fmt,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+};
+
+use arrow_deps::{
+    arrow::{
+        datatypes::SchemaRef,
+        error::{ArrowError, Result as ArrowResult},
+        record_batch::RecordBatch,
+    },
+    datafusion::{
+        error::Result as DataFusionResult,
+        physical_plan::{
+            hash_aggregate::HashAggregateExec, hash_join::HashJoinExec, sort::SortExec,
+            ExecutionPlan, Partitioning, RecordBatchStream, SendableRecordBatchStream,
+        },
+    },
+};
+use async_trait::async_trait;
+use futures::{Stream, StreamExt};
+use snafu::Snafu;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display(
+        "Query memory limit exceeded: the query needed more than the {} bytes allowed",
+        limit
+    ))]
+    LimitExceeded { limit: usize },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The memory used by a query so far, and how much it may use
+#[derive(Debug, Default)]
+pub struct MemoryTracker {
+    /// The number of bytes the query may use, unlimited if None
+    limit: Option<usize>,
+    used: AtomicUsize,
+    /// The memory used by all the queries running, this one included
+    total: Arc<AtomicUsize>,
+}
+
+impl MemoryTracker {
+    pub fn new(limit: Option<usize>) -> Self {
+        Self::with_total(limit, Default::default())
+    }
+
+    /// Creates the tracker of a query whose memory is also added to `total`
+    /// until the tracker is dropped
+    pub fn with_total(limit: Option<usize>, total: Arc<AtomicUsize>) -> Self {
+        Self {
+            limit,
+            used: AtomicUsize::new(0),
+            total,
+        }
+    }
+
+    /// Records that the query uses `bytes` more, failing if that takes it
+    /// over its limit
+    pub fn grow(&self, bytes: usize) -> Result<()> {
+        self.total.fetch_add(bytes, Ordering::Relaxed);
+        let used = self.used.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        match self.limit {
+            Some(limit) if used > limit => LimitExceeded { limit }.fail(),
+            _ => Ok(()),
+        }
+    }
+
+    /// The number of bytes used so far
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for MemoryTracker {
+    fn drop(&mut self) {
+        self.total.fetch_sub(self.used(), Ordering::Relaxed);
+    }
+}
+
+/// Returns the memory taken by the arrays of `batch`
+pub fn batch_memory_size(batch: &RecordBatch) -> usize {
+    batch
+        .columns()
+        .iter()
+        .map(|column| column.get_array_memory_size())
+        .sum()
+}
+
+/// Returns a copy of `plan` in which the batches buffered by its operators,
+/// as described in the module docs, are counted by `tracker`
+pub fn track_buffered(
+    plan: Arc<dyn ExecutionPlan>,
+    tracker: &Arc<MemoryTracker>,
+) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+    let children = plan.children();
+    if children.is_empty() {
+        return Ok(plan);
+    }
+
+    let buffers_input = plan.as_any().is::<SortExec>();
+    // the left input is collected into the hash table
+    let buffers_left = plan.as_any().is::<HashJoinExec>();
+    let outputs_state = plan.as_any().is::<HashAggregateExec>();
+
+    let children = children
+        .into_iter()
+        .enumerate()
+        .map(|(index, child)| {
+            let child = track_buffered(child, tracker)?;
+            Ok(if buffers_input || (buffers_left && index == 0) {
+                track_output(child, tracker)
+            } else {
+                child
+            })
+        })
+        .collect::<DataFusionResult<Vec<_>>>()?;
+
+    let plan = plan.with_new_children(children)?;
+    Ok(if outputs_state {
+        track_output(plan, tracker)
+    } else {
+        plan
+    })
+}
+
+/// Returns `plan` with the batches it outputs counted by `tracker`
+pub fn track_output(
+    plan: Arc<dyn ExecutionPlan>,
+    tracker: &Arc<MemoryTracker>,
+) -> Arc<dyn ExecutionPlan> {
+    Arc::new(MemoryTrackingExec {
+        input: plan,
+        tracker: Arc::clone(tracker),
+    })
+}
+
+/// Passes on the batches of its input, counting their memory
+struct MemoryTrackingExec {
+    input: Arc<dyn ExecutionPlan>,
+    tracker: Arc<MemoryTracker>,
+}
+
+impl fmt::Debug for MemoryTrackingExec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MemoryTrackingExec")
+            .field("input", &self.input)
+            .finish()
+    }
+}
+
+#[async_trait]
+impl ExecutionPlan for MemoryTrackingExec {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        self.input.schema()
+    }
+
+    fn output_partitioning(&self) -> Partitioning {
+        self.input.output_partitioning()
+    }
+
+    fn children(&self) -> Vec<Arc<dyn ExecutionPlan>> {
+        vec![Arc::clone(&self.input)]
+    }
+
+    fn with_new_children(
+        &self,
+        children: Vec<Arc<dyn ExecutionPlan>>,
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        assert_eq!(children.len(), 1, "MemoryTrackingExec has one input");
+        Ok(track_output(Arc::clone(&children[0]), &self.tracker))
+    }
+
+    async fn execute(&self, partition: usize) -> DataFusionResult<SendableRecordBatchStream> {
+        let input = self.input.execute(partition).await?;
+        Ok(Box::pin(MemoryTrackingStream {
+            input,
+            tracker: Arc::clone(&self.tracker),
+        }))
+    }
+}
+
+struct MemoryTrackingStream {
+    input: SendableRecordBatchStream,
+    tracker: Arc<MemoryTracker>,
+}
+
+impl RecordBatchStream for MemoryTrackingStream {
+    fn schema(&self) -> SchemaRef {
+        self.input.schema()
+    }
+}
+
+impl Stream for MemoryTrackingStream {
+    type Item = ArrowResult<RecordBatch>;
+
+    fn poll_next(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        let batch = self.input.poll_next_unpin(cx);
+        batch.map(|batch| {
+            batch.map(|batch| {
+                batch.and_then(|batch| {
+                    self.tracker
+                        .grow(batch_memory_size(&batch))
+                        .map_err(|e| ArrowError::ExternalError(Box::new(e)))?;
+                    Ok(batch)
+                })
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_deps::{
+        arrow::{
+            array::Int64Array,
+            datatypes::{DataType, Field, Schema},
+        },
+        datafusion::physical_plan::{collect, memory::MemoryExec},
+    };
+
+    #[test]
+    fn limit() {
+        let tracker = MemoryTracker::new(Some(100));
+        tracker.grow(60).unwrap();
+        tracker.grow(40).unwrap();
+        assert_eq!(tracker.used(), 100);
+        let e = tracker.grow(1).unwrap_err();
+        assert!(e.to_string().contains("memory limit exceeded"), "{}", e);
+
+        let unlimited = MemoryTracker::new(None);
+        unlimited.grow(usize::MAX / 2).unwrap();
+    }
+
+    #[test]
+    fn total() {
+        let total = Arc::new(AtomicUsize::new(0));
+        let a = MemoryTracker::with_total(Some(100), Arc::clone(&total));
+        let b = MemoryTracker::with_total(None, Arc::clone(&total));
+        a.grow(60).unwrap();
+        b.grow(200).unwrap();
+        a.grow(50).unwrap_err();
+        assert_eq!(total.load(Ordering::Relaxed), 310);
+
+        // the memory of a query is given back when it ends
+        drop(a);
+        assert_eq!(total.load(Ordering::Relaxed), 200);
+    }
+
+    #[tokio::test]
+    async fn tracked_plan() {
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int64, false)]));
+        let batch = RecordBatch::try_new(
+            Arc::clone(&schema),
+            vec![Arc::new(Int64Array::from(vec![1, 2, 3]))],
+        )
+        .unwrap();
+        let size = batch_memory_size(&batch);
+        let plan: Arc<dyn ExecutionPlan> =
+            Arc::new(MemoryExec::try_new(&[vec![batch.clone(), batch]], schema, None).unwrap());
+
+        let tracker = Arc::new(MemoryTracker::new(None));
+        collect(track_output(Arc::clone(&plan), &tracker))
+            .await
+            .unwrap();
+        assert_eq!(tracker.used(), 2 * size);
+
+        let tracker = Arc::new(MemoryTracker::new(Some(size)));
+        let e = collect(track_output(plan, &tracker)).await.unwrap_err();
+        assert!(e.to_string().contains("memory limit exceeded"), "{}", e);
+    }
+}
@@ -0,0 +1,106 @@
+//! A pool of threads of its own for running CPU bound work, such as the
+//! sorting, decompression and aggregation done by the plans of queries.
+//!
+//! The pool is a tokio runtime, as DataFusion plans are futures, separate
+//! from the runtime of the server: a query scanning lots of data keeps the
+//! threads of the pool busy, but not those handling requests and writes.
+//! The runtime is owned by a thread of its own, which drops it once the
+//! `DedicatedExecutor` is dropped, so that it is never dropped from within
+//! an async context.
+use std::future::Future;
+
+use tokio::{
+    runtime::{Builder, Handle},
+    sync::oneshot,
+    task::JoinHandle,
+};
+
+#[derive(Debug)]
+pub struct DedicatedExecutor {
+    name: String,
+    num_threads: usize,
+    handle: Handle,
+    /// Stops the runtime when dropped
+    _shutdown: oneshot::Sender<()>,
+}
+
+impl DedicatedExecutor {
+    /// Starts a runtime of `num_threads` threads named after `name`
+    pub fn new(name: &str, num_threads: usize) -> Self {
+        let (handle_tx, handle_rx) = std::sync::mpsc::channel();
+        let (shutdown, shutdown_rx) = oneshot::channel::<()>();
+
+        let thread_name = name.to_string();
+        std::thread::Builder::new()
+            .name(format!("{} driver", name))
+            .spawn(move || {
+                let runtime = Builder::new_multi_thread()
+                    .enable_all()
+                    .thread_name(thread_name)
+                    .worker_threads(num_threads)
+                    .build()
+                    .expect("Creating the tokio runtime of a dedicated executor");
+                handle_tx
+                    .send(runtime.handle().clone())
+                    .expect("Sending the handle of a dedicated executor");
+
+                // runs until the sender is dropped along with the executor
+                runtime.block_on(async move {
+                    shutdown_rx.await.ok();
+                });
+            })
+            .expect("Spawning the thread of a dedicated executor");
+
+        let handle = handle_rx
+            .recv()
+            .expect("Receiving the handle of a dedicated executor");
+
+        Self {
+            name: name.to_string(),
+            num_threads,
+            handle,
+            _shutdown: shutdown,
+        }
+    }
+
+    /// Runs `task` on the threads of the executor. The task is dropped if
+    /// the executor is dropped before it completes.
+    pub fn spawn<T>(&self, task: T) -> JoinHandle<T::Output>
+    where
+        T: Future + Send + 'static,
+        T::Output: Send + 'static,
+    {
+        self.handle.spawn(task)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn num_threads(&self) -> usize {
+        self.num_threads
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn runs_on_its_threads() {
+        let executor = DedicatedExecutor::new("test-pool", 2);
+        assert_eq!(executor.num_threads(), 2);
+
+        let thread_name = executor
+            .spawn(async { std::thread::current().name().map(ToString::to_string) })
+            .await
+            .unwrap();
+        assert_eq!(thread_name.as_deref(), Some("test-pool"));
+
+        // dropping the executor from an async context doesn't panic, and
+        // its pending tasks are dropped
+        let pending = executor.spawn(futures::future::pending::<()>());
+        drop(executor);
+        assert!(pending.await.is_err());
+    }
+}
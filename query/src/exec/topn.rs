@@ -0,0 +1,310 @@
+//! Selection of the top or bottom N series of each window of a windowed
+//! aggregate, e.g. the 5 hosts with the highest max cpu usage in each
+//! window, so that only the selected points are returned rather than all
+//! the series for the client to sort.
+//!
+//! The points of the series of each measurement and field are ranked by
+//! value within their window, and only the N highest (or lowest) are kept.
+//! Series left without points are dropped.
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use arrow_deps::arrow::{
+    array::{Array, ArrayRef, Float64Array, Int64Array, UInt32Array},
+    compute::kernels::{cast::cast, take::take},
+    datatypes::DataType,
+    error::ArrowError,
+    record_batch::RecordBatch,
+};
+use snafu::{ResultExt, Snafu};
+
+use crate::func::window::{Duration, Window};
+
+use super::seriesset::SeriesSet;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Can not rank the series of field {} of type {:?}", field, data_type))]
+    UnrankableField { field: String, data_type: DataType },
+
+    #[snafu(display("Error selecting the points of the top series: {}", source))]
+    SelectingPoints { source: ArrowError },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Whether the series with the highest or lowest values are selected
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Ranking {
+    Top,
+    Bottom,
+}
+
+/// Selects the `n` series with the highest or lowest values in each window
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TopN {
+    pub ranking: Ranking,
+    pub n: usize,
+    /// The duration of the windows in nanoseconds when the points are
+    /// selected points, which keep their own time, so that they are ranked
+    /// against the other points of their window. `None` if the points are
+    /// at the times of their windows, as for aggregates.
+    pub selector_every: Option<i64>,
+}
+
+/// A point of a series, identified by its series set, field and row
+#[derive(Debug, Clone, Copy)]
+struct Candidate {
+    value: f64,
+    set: usize,
+    field: usize,
+    row: usize,
+}
+
+impl TopN {
+    /// Returns `sets` with only the points ranked in the top (or bottom)
+    /// `n` of their window. The series sets are returned in the same order,
+    /// without the ones left without points.
+    pub fn select(&self, sets: Vec<SeriesSet>) -> Result<Vec<SeriesSet>> {
+        let window = self.selector_every.map(|every| {
+            Window::new(
+                Duration::from_nsecs(every),
+                Duration::from_nsecs(0),
+                Duration::from_nsecs(0),
+            )
+        });
+
+        // the points of each measurement, field and window
+        let mut windows: HashMap<(Arc<String>, String, i64), Vec<Candidate>> = HashMap::new();
+        for (set_index, set) in sets.iter().enumerate() {
+            let schema = set.batch.schema();
+            for (field_index, indexes) in set.field_indexes.as_slice().iter().enumerate() {
+                let field = schema.field(indexes.value_index);
+                let values = as_f64(set.batch.column(indexes.value_index), field.name())?;
+                let values = values
+                    .as_any()
+                    .downcast_ref::<Float64Array>()
+                    .expect("cast to Float64");
+                let times = set
+                    .batch
+                    .column(indexes.timestamp_index)
+                    .as_any()
+                    .downcast_ref::<Int64Array>()
+                    .expect("timestamps are Int64");
+
+                for row in set.start_row..set.start_row + set.num_rows {
+                    if values.is_null(row) {
+                        continue;
+                    }
+                    let time = match &window {
+                        Some(window) => window.get_earliest_bounds(times.value(row)).stop,
+                        None => times.value(row),
+                    };
+                    let key = (Arc::clone(&set.table_name), field.name().clone(), time);
+                    windows.entry(key).or_default().push(Candidate {
+                        value: values.value(row),
+                        set: set_index,
+                        field: field_index,
+                        row,
+                    });
+                }
+            }
+        }
+
+        let mut selected = HashSet::new();
+        for candidates in windows.values_mut() {
+            // the sort is stable, so ties are broken by the series order,
+            // and NaN ranks last either way
+            candidates.sort_by(|a, b| match (a.value.is_nan(), b.value.is_nan()) {
+                (false, false) => match self.ranking {
+                    Ranking::Top => b.value.partial_cmp(&a.value).expect("not NaN"),
+                    Ranking::Bottom => a.value.partial_cmp(&b.value).expect("not NaN"),
+                },
+                (a_nan, b_nan) => a_nan.cmp(&b_nan),
+            });
+            selected.extend(
+                candidates
+                    .iter()
+                    .take(self.n)
+                    .map(|c| (c.set, c.field, c.row)),
+            );
+        }
+
+        let mut result = vec![];
+        for (set_index, set) in sets.into_iter().enumerate() {
+            if let Some(set) = keep_selected(set, |field, row| {
+                selected.contains(&(set_index, field, row))
+            })
+            .context(SelectingPoints)?
+            {
+                result.push(set);
+            }
+        }
+        Ok(result)
+    }
+}
+
+/// Returns the rows of `set` with the values of the fields for which
+/// `is_selected(field, row)` is false set to null, `None` if none are
+/// selected
+fn keep_selected(
+    set: SeriesSet,
+    is_selected: impl Fn(usize, usize) -> bool,
+) -> Result<Option<SeriesSet>, ArrowError> {
+    let rows = set.start_row..set.start_row + set.num_rows;
+    let field_count = set.field_indexes.as_slice().len();
+    let any_selected = rows
+        .clone()
+        .any(|row| (0..field_count).any(|field| is_selected(field, row)));
+    if !any_selected {
+        return Ok(None);
+    }
+
+    let mut columns: Vec<ArrayRef> = set
+        .batch
+        .columns()
+        .iter()
+        .map(|column| column.slice(set.start_row, set.num_rows))
+        .collect();
+    for (field, indexes) in set.field_indexes.as_slice().iter().enumerate() {
+        // taking null rows makes null values
+        let taken: UInt32Array = rows
+            .clone()
+            .map(|row| {
+                if is_selected(field, row) {
+                    Some(row as u32)
+                } else {
+                    None
+                }
+            })
+            .collect();
+        let values = set.batch.column(indexes.value_index);
+        columns[indexes.value_index] = take(values.as_ref(), &taken, None)?;
+    }
+
+    Ok(Some(SeriesSet {
+        start_row: 0,
+        batch: RecordBatch::try_new(set.batch.schema(), columns)?,
+        ..set
+    }))
+}
+
+/// Casts the numbers of `array`, the values of `field`, to floats
+fn as_f64(array: &ArrayRef, field: &str) -> Result<ArrayRef> {
+    match array.data_type() {
+        DataType::Float64 => Ok(Arc::clone(array)),
+        DataType::Int64 | DataType::UInt64 => {
+            cast(array, &DataType::Float64).context(SelectingPoints)
+        }
+        data_type => UnrankableField {
+            field,
+            data_type: data_type.clone(),
+        }
+        .fail(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exec::field::FieldIndexes;
+    use arrow_deps::arrow::datatypes::{Field, Schema};
+
+    /// A series set of the `usage` field of cpu for `host`
+    fn series_set(host: &str, points: &[(i64, f64)]) -> SeriesSet {
+        let schema = Schema::new(vec![
+            Field::new("usage", DataType::Float64, true),
+            Field::new("time", DataType::Int64, false),
+        ]);
+        let values = Float64Array::from(points.iter().map(|(_, value)| *value).collect::<Vec<_>>());
+        let times = Int64Array::from(points.iter().map(|(time, _)| *time).collect::<Vec<_>>());
+        let batch = RecordBatch::try_new(Arc::new(schema), vec![Arc::new(values), Arc::new(times)])
+            .unwrap();
+        SeriesSet {
+            table_name: Arc::new("cpu".to_string()),
+            tags: vec![(Arc::new("host".to_string()), Arc::new(host.to_string()))],
+            field_indexes: FieldIndexes::from_timestamp_and_value_indexes(1, &[0]),
+            start_row: 0,
+            num_rows: points.len(),
+            batch,
+        }
+    }
+
+    /// Returns the host and remaining points of each series set
+    fn points(sets: Vec<SeriesSet>) -> Vec<(String, Vec<(i64, f64)>)> {
+        sets.into_iter()
+            .map(|set| {
+                let values = set.batch.column(0);
+                let values = values.as_any().downcast_ref::<Float64Array>().unwrap();
+                let times = set.batch.column(1);
+                let times = times.as_any().downcast_ref::<Int64Array>().unwrap();
+                let points = (set.start_row..set.start_row + set.num_rows)
+                    .filter(|&row| values.is_valid(row))
+                    .map(|row| (times.value(row), values.value(row)))
+                    .collect();
+                (set.tags[0].1.to_string(), points)
+            })
+            .collect()
+    }
+
+    fn sets() -> Vec<SeriesSet> {
+        vec![
+            series_set("a", &[(10, 1.0), (20, 5.0)]),
+            series_set("b", &[(10, 3.0), (20, 2.0)]),
+            series_set("c", &[(10, 2.0), (20, 4.0)]),
+        ]
+    }
+
+    #[test]
+    fn top_and_bottom() {
+        let top = TopN {
+            ranking: Ranking::Top,
+            n: 2,
+            selector_every: None,
+        };
+        assert_eq!(
+            points(top.select(sets()).unwrap()),
+            vec![
+                ("a".to_string(), vec![(20, 5.0)]),
+                ("b".to_string(), vec![(10, 3.0)]),
+                ("c".to_string(), vec![(10, 2.0), (20, 4.0)]),
+            ]
+        );
+
+        let bottom = TopN {
+            ranking: Ranking::Bottom,
+            n: 1,
+            selector_every: None,
+        };
+        assert_eq!(
+            points(bottom.select(sets()).unwrap()),
+            vec![
+                ("a".to_string(), vec![(10, 1.0)]),
+                ("b".to_string(), vec![(20, 2.0)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn selected_points() {
+        // selected points keep their own time within windows of 10
+        let sets = vec![
+            series_set("a", &[(12, 1.0), (25, 5.0)]),
+            series_set("b", &[(18, 3.0), (21, 2.0)]),
+        ];
+        let top = TopN {
+            ranking: Ranking::Top,
+            n: 1,
+            selector_every: Some(10),
+        };
+        assert_eq!(
+            points(top.select(sets).unwrap()),
+            vec![
+                ("a".to_string(), vec![(25, 5.0)]),
+                ("b".to_string(), vec![(18, 3.0)]),
+            ]
+        );
+    }
+}
@@ -0,0 +1,168 @@
+//! Vectorized evaluation of simple predicates on Arrow record batches.
+//!
+//! Rather than looping over the rows of a batch, the predicates are
+//! evaluated a column at a time with the Arrow compute kernels, producing
+//! a boolean mask which is then used to filter all the columns at once.
+
+use std::sync::Arc;
+
+use arrow_deps::arrow::{
+    array::{Array, ArrayRef, BooleanArray, Int64Array, StringArray},
+    compute::kernels::{
+        boolean::and,
+        cast::cast,
+        comparison::{eq_utf8_scalar, gt_eq_scalar, lt_scalar},
+        filter::filter_record_batch,
+    },
+    datatypes::DataType,
+    error::Result as ArrowResult,
+    record_batch::RecordBatch,
+};
+use data_types::TIME_COLUMN_NAME;
+
+use crate::predicate::TimestampRange;
+
+/// Returns a mask of the rows of `batch` whose time is within `range`, or
+/// `None` if `batch` has no time column
+pub fn time_range_mask(
+    batch: &RecordBatch,
+    range: TimestampRange,
+) -> ArrowResult<Option<BooleanArray>> {
+    let index = match batch.schema().index_of(TIME_COLUMN_NAME) {
+        Ok(index) => index,
+        Err(_) => return Ok(None),
+    };
+    let times = as_i64(batch.column(index))?;
+    let times = times
+        .as_any()
+        .downcast_ref::<Int64Array>()
+        .expect("cast to Int64");
+
+    let mask = and(
+        &gt_eq_scalar(times, range.start)?,
+        &lt_scalar(times, range.end)?,
+    )?;
+    Ok(Some(null_as_false(mask)))
+}
+
+/// Returns a mask of the rows of `array` equal to `value`, comparing
+/// arrays of other types than strings, such as dictionary encoded tags,
+/// by their string representation. Null rows are never equal.
+pub fn string_eq_mask(array: &ArrayRef, value: &str) -> ArrowResult<BooleanArray> {
+    let strings = match array.data_type() {
+        DataType::Utf8 => Arc::clone(array),
+        _ => cast(array, &DataType::Utf8)?,
+    };
+    let strings = strings
+        .as_any()
+        .downcast_ref::<StringArray>()
+        .expect("cast to Utf8");
+
+    Ok(null_as_false(eq_utf8_scalar(strings, value)?))
+}
+
+/// Keeps the rows of `batch` set in `mask`, returning `batch` itself if
+/// that is all of them
+pub fn filter_batch(batch: RecordBatch, mask: &BooleanArray) -> ArrowResult<RecordBatch> {
+    if count_set(mask) == batch.num_rows() {
+        Ok(batch)
+    } else {
+        filter_record_batch(&batch, mask)
+    }
+}
+
+/// Keeps the rows of `batch` whose time is within `range`. Batches without
+/// a time column are returned as is.
+pub fn filter_time_range(batch: RecordBatch, range: TimestampRange) -> ArrowResult<RecordBatch> {
+    match time_range_mask(&batch, range)? {
+        Some(mask) => filter_batch(batch, &mask),
+        None => Ok(batch),
+    }
+}
+
+/// Returns the number of rows set in `mask`, which has no nulls
+fn count_set(mask: &BooleanArray) -> usize {
+    mask.values()
+        .count_set_bits_offset(mask.offset(), mask.len())
+}
+
+/// The time column is either nanosecond timestamps or plain integers
+fn as_i64(times: &ArrayRef) -> ArrowResult<ArrayRef> {
+    match times.data_type() {
+        DataType::Int64 => Ok(Arc::clone(times)),
+        _ => cast(times, &DataType::Int64),
+    }
+}
+
+/// Replaces the nulls of `mask`, from comparisons with null values, with
+/// false so that the filter kernel never keeps them
+fn null_as_false(mask: BooleanArray) -> BooleanArray {
+    if mask.null_count() == 0 {
+        return mask;
+    }
+    let values: Vec<bool> = (0..mask.len())
+        .map(|row| mask.is_valid(row) && mask.value(row))
+        .collect();
+    BooleanArray::from(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_deps::arrow::{
+        array::DictionaryArray,
+        datatypes::{Field, Int32Type, Schema},
+    };
+
+    fn batch() -> RecordBatch {
+        let schema = Schema::new(vec![
+            Field::new("host", DataType::Utf8, true),
+            Field::new(TIME_COLUMN_NAME, DataType::Int64, false),
+        ]);
+        RecordBatch::try_new(
+            Arc::new(schema),
+            vec![
+                Arc::new(StringArray::from(vec![
+                    Some("a"),
+                    None,
+                    Some("b"),
+                    Some("a"),
+                ])),
+                Arc::new(Int64Array::from(vec![100, 200, 300, 400])),
+            ],
+        )
+        .unwrap()
+    }
+
+    fn values(mask: &BooleanArray) -> Vec<bool> {
+        (0..mask.len()).map(|row| mask.value(row)).collect()
+    }
+
+    #[test]
+    fn time_range() {
+        let batch = batch();
+        let range = TimestampRange::new(200, 400);
+        let mask = time_range_mask(&batch, range).unwrap().unwrap();
+        assert_eq!(values(&mask), vec![false, true, true, false]);
+
+        assert_eq!(
+            filter_time_range(batch.clone(), range).unwrap().num_rows(),
+            2
+        );
+        let range = TimestampRange::new(0, 1000);
+        assert_eq!(filter_time_range(batch, range).unwrap().num_rows(), 4);
+    }
+
+    #[test]
+    fn string_eq() {
+        let batch = batch();
+        let mask = string_eq_mask(batch.column(0), "a").unwrap();
+        assert_eq!(mask.null_count(), 0);
+        assert_eq!(values(&mask), vec![true, false, false, true]);
+
+        let dictionary: DictionaryArray<Int32Type> = vec!["a", "b", "a"].into_iter().collect();
+        let dictionary: ArrayRef = Arc::new(dictionary);
+        let mask = string_eq_mask(&dictionary, "a").unwrap();
+        assert_eq!(values(&mask), vec![true, false, true]);
+    }
+}
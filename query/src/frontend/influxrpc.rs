@@ -28,6 +28,7 @@ use crate::{
     },
     predicate::{Predicate, PredicateBuilder},
     provider::ProviderBuilder,
+    pruning,
     util::schema_has_all_expr_columns,
     Database, PartitionChunk,
 };
@@ -756,10 +757,12 @@ impl InfluxRPCPlanner {
 
         debug!(partition_keys=?partition_keys, "Considering partition keys");
 
+        let mut considered = 0;
         for key in partition_keys {
             // TODO prune partitions somehow
             let partition_chunks = database.chunks(&key);
             for chunk in partition_chunks {
+                considered += 1;
                 let could_pass_predicate = chunk
                     .could_pass_predicate(predicate)
                     .map_err(|e| Box::new(e) as _)
@@ -776,6 +779,8 @@ impl InfluxRPCPlanner {
                 }
             }
         }
+
+        pruning::METRICS.record(considered, considered - db_chunks.len());
         Ok(db_chunks)
     }
 }
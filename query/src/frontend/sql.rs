@@ -84,42 +84,55 @@ impl SQLQueryPlanner {
         database: &D,
         query: &str,
         executor: &Executor,
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        self.query_union(&[database], query, executor).await
+    }
+
+    /// Plan a SQL query against the data in all of `databases`, each table
+    /// holding the rows of the tables of that name in any of them
+    pub async fn query_union<D: Database + 'static>(
+        &self,
+        databases: &[&D],
+        query: &str,
+        executor: &Executor,
     ) -> Result<Arc<dyn ExecutionPlan>> {
         let mut ctx = executor.new_context();
 
         // figure out the table names that appear in the sql
         let table_names = table_names(query)?;
 
-        let partition_keys = database
-            .partition_keys()
-            .map_err(|e| Box::new(e) as _)
-            .context(GettingDatabasePartition)?;
-
         // Register a table provider for each table so DataFusion
         // knows what the schema of that table is and how to obtain
         // its data when needed.
         for table_name in &table_names {
             let mut builder = ProviderBuilder::new(table_name);
 
-            for partition_key in &partition_keys {
-                for chunk in database.chunks(partition_key) {
-                    if chunk.has_table(table_name) {
-                        let chunk_id = chunk.id();
-                        let chunk_table_schema = chunk
-                            .table_schema(table_name, Selection::All)
-                            .await
-                            .map_err(|e| Box::new(e) as _)
-                            .context(GettingTableSchema {
-                                table_name,
-                                chunk_id,
-                            })?;
-
-                        builder = builder.add_chunk(chunk, chunk_table_schema).context(
-                            AddingChunkToProvider {
-                                table_name,
-                                chunk_id,
-                            },
-                        )?
+            for database in databases {
+                let partition_keys = database
+                    .partition_keys()
+                    .map_err(|e| Box::new(e) as _)
+                    .context(GettingDatabasePartition)?;
+
+                for partition_key in &partition_keys {
+                    for chunk in database.chunks(partition_key) {
+                        if chunk.has_table(table_name) {
+                            let chunk_id = chunk.id();
+                            let chunk_table_schema = chunk
+                                .table_schema(table_name, Selection::All)
+                                .await
+                                .map_err(|e| Box::new(e) as _)
+                                .context(GettingTableSchema {
+                                    table_name,
+                                    chunk_id,
+                                })?;
+
+                            builder = builder.add_chunk(chunk, chunk_table_schema).context(
+                                AddingChunkToProvider {
+                                    table_name,
+                                    chunk_id,
+                                },
+                            )?
+                        }
                     }
                 }
             }
@@ -147,7 +160,8 @@ fn table_names(query: &str) -> Result<Vec<String>> {
     let mut tables = vec![];
 
     let dialect = GenericDialect {};
-    let ast = Parser::parse_sql(&dialect, query).context(InvalidSqlQuery { query })?;
+    let ast =
+        Parser::parse_sql(&dialect, strip_explain(query)).context(InvalidSqlQuery { query })?;
 
     for statement in ast {
         match statement {
@@ -171,3 +185,37 @@ fn table_names(query: &str) -> Result<Vec<String>> {
     }
     Ok(tables)
 }
+
+/// Returns the query explained by `query` if it is an `EXPLAIN [VERBOSE]`
+/// statement, which DataFusion plans itself, otherwise `query`
+fn strip_explain(query: &str) -> &str {
+    let mut rest = query.trim_start();
+    for keyword in &["EXPLAIN", "VERBOSE"] {
+        let len = keyword.len();
+        let is_keyword = rest
+            .get(..len)
+            .map_or(false, |word| word.eq_ignore_ascii_case(keyword))
+            && rest[len..].starts_with(char::is_whitespace);
+        if !is_keyword {
+            break;
+        }
+        rest = rest[len..].trim_start();
+    }
+    rest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_table_names() {
+        assert_eq!(table_names("SELECT * FROM cpu").unwrap(), vec!["cpu"]);
+        assert_eq!(
+            table_names("explain verbose\nSELECT * FROM cpu").unwrap(),
+            vec!["cpu"]
+        );
+        // VERBOSE alone is not a statement
+        table_names("VERBOSE SELECT * FROM cpu").unwrap_err();
+    }
+}
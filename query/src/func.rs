@@ -1,3 +1,6 @@
 //! Special IOx functions used in DataFusion plans
+pub mod cardinality;
+pub mod percentile;
+pub mod regex;
 pub mod selectors;
 pub mod window;
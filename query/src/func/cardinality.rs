@@ -0,0 +1,460 @@
+//! Implementation of the `count_distinct` aggregate, the number of
+//! distinct values of a column such as the cardinality of a tag.
+//!
+//! Values are counted exactly, by keeping a hash of each distinct value,
+//! until there are more than `EXACT_LIMIT` of them. The count is then
+//! estimated with a HyperLogLog sketch, whose memory is fixed whatever the
+//! number of values, at the cost of a small relative error (about 1%).
+//! Both the sets and the sketches of separate partitions can be merged,
+//! so the aggregate can be computed in parallel.
+//!
+//! See "HyperLogLog: the analysis of a near-optimal cardinality estimation
+//! algorithm" by Philippe Flajolet, Éric Fusy, Olivier Gandouet and
+//! Frédéric Meunier.
+use std::{
+    collections::{hash_map::DefaultHasher, HashSet},
+    fmt::Write,
+    hash::{Hash, Hasher},
+    sync::Arc,
+};
+
+use arrow_deps::{
+    arrow::{
+        array::{
+            Array, ArrayRef, BooleanArray, Float64Array, Int64Array, StringArray, UInt64Array,
+        },
+        compute::kernels::cast::cast,
+        datatypes::DataType,
+    },
+    datafusion::{
+        error::{DataFusionError, Result as DataFusionResult},
+        physical_plan::{
+            aggregates::{AccumulatorFunctionImplementation, StateTypeFunction},
+            functions::{ReturnTypeFunction, Signature},
+            udaf::AggregateUDF,
+            Accumulator,
+        },
+        scalar::ScalarValue,
+    },
+};
+
+/// The name of the SQL function `count_distinct(value)`
+pub const COUNT_DISTINCT: &str = "count_distinct";
+
+/// The number of distinct values counted exactly before switching to a
+/// sketch
+const EXACT_LIMIT: usize = 4096;
+
+/// The number of bits of the hashes used to select the register of a
+/// sketch, which has 2^PRECISION registers
+const PRECISION: u32 = 14;
+
+/// The number of registers of a sketch
+const REGISTERS: usize = 1 << PRECISION;
+
+/// Returns a DataFusion user defined aggregate function counting the
+/// distinct non null values of its input, of any type:
+///
+/// count_distinct(column) -> UInt64
+pub fn count_distinct() -> AggregateUDF {
+    let input_signature = Signature::Any(1);
+
+    let state_type = Arc::new(vec![DataType::Utf8]);
+    let state_type_factory: StateTypeFunction = Arc::new(move |_| Ok(Arc::clone(&state_type)));
+
+    let factory: AccumulatorFunctionImplementation =
+        Arc::new(|| Ok(Box::new(DistinctAccumulator::default())));
+
+    let return_type = Arc::new(DataType::UInt64);
+    let return_type_func: ReturnTypeFunction = Arc::new(move |_| Ok(Arc::clone(&return_type)));
+
+    AggregateUDF::new(
+        COUNT_DISTINCT,
+        &input_signature,
+        &return_type_func,
+        &factory,
+        &state_type_factory,
+    )
+}
+
+/// Accumulates the distinct values of a group. Its state is the set of
+/// values, serialized as a string.
+#[derive(Debug, Default)]
+struct DistinctAccumulator {
+    values: DistinctSet,
+}
+
+impl Accumulator for DistinctAccumulator {
+    fn state(&self) -> DataFusionResult<Vec<ScalarValue>> {
+        Ok(vec![ScalarValue::Utf8(Some(self.values.to_state()))])
+    }
+
+    fn update(&mut self, _values: &[ScalarValue]) -> DataFusionResult<()> {
+        unreachable!("Should only be calling update_batch for performance reasons");
+    }
+
+    fn merge(&mut self, _states: &[ScalarValue]) -> DataFusionResult<()> {
+        unreachable!("Should only be calling merge_batch for performance reasons");
+    }
+
+    fn evaluate(&self) -> DataFusionResult<ScalarValue> {
+        Ok(ScalarValue::UInt64(Some(self.values.count())))
+    }
+
+    fn update_batch(&mut self, values: &[ArrayRef]) -> DataFusionResult<()> {
+        if values.len() != 1 {
+            return Err(DataFusionError::Internal(format!(
+                "Internal error: Expected 1 argument to count_distinct but got {}",
+                values.len()
+            )));
+        }
+        for hash in value_hashes(&values[0])? {
+            self.values.insert(hash);
+        }
+        Ok(())
+    }
+
+    fn merge_batch(&mut self, states: &[ArrayRef]) -> DataFusionResult<()> {
+        if states.len() != 1 {
+            return Err(DataFusionError::Internal(format!(
+                "Internal error: Expected 1 state column for count_distinct but got {}",
+                states.len()
+            )));
+        }
+        let sets = states[0]
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .ok_or_else(|| {
+                DataFusionError::Internal("count_distinct state is not a string".to_string())
+            })?;
+        for row in 0..sets.len() {
+            if sets.is_valid(row) {
+                let set = DistinctSet::from_state(sets.value(row)).ok_or_else(|| {
+                    DataFusionError::Internal(format!(
+                        "invalid count_distinct state: {}",
+                        sets.value(row)
+                    ))
+                })?;
+                self.values.merge(set);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the hashes of the non null values of `array`. Dictionary
+/// encoded columns, such as tags, are hashed by their string values.
+fn value_hashes(array: &ArrayRef) -> DataFusionResult<Vec<u64>> {
+    fn hashes(array: &dyn Array, hash_row: impl Fn(usize) -> u64) -> Vec<u64> {
+        (0..array.len())
+            .filter(|&row| array.is_valid(row))
+            .map(hash_row)
+            .collect()
+    }
+
+    let array = match array.data_type() {
+        DataType::Dictionary(_, _) => cast(array, &DataType::Utf8)?,
+        DataType::Timestamp(_, _) => cast(array, &DataType::Int64)?,
+        _ => Arc::clone(array),
+    };
+    let any = array.as_any();
+
+    if let Some(array) = any.downcast_ref::<StringArray>() {
+        Ok(hashes(array, |row| hash(&array.value(row))))
+    } else if let Some(array) = any.downcast_ref::<Int64Array>() {
+        Ok(hashes(array, |row| hash(&array.value(row))))
+    } else if let Some(array) = any.downcast_ref::<UInt64Array>() {
+        Ok(hashes(array, |row| hash(&array.value(row))))
+    } else if let Some(array) = any.downcast_ref::<Float64Array>() {
+        Ok(hashes(array, |row| hash(&array.value(row).to_bits())))
+    } else if let Some(array) = any.downcast_ref::<BooleanArray>() {
+        Ok(hashes(array, |row| hash(&array.value(row))))
+    } else {
+        Err(DataFusionError::NotImplemented(format!(
+            "count_distinct is not supported for {:?}",
+            array.data_type()
+        )))
+    }
+}
+
+/// The hashes only need to be the same within the process, which
+/// `DefaultHasher::new` guarantees
+fn hash<T: Hash>(value: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// The distinct values of a column, identified by their hashes: an exact
+/// set while small, a HyperLogLog sketch once large. See the module docs.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DistinctSet {
+    Exact(HashSet<u64>),
+    /// The registers of the sketch, the highest rank of the hashes which
+    /// selected each of them
+    Sketch(Vec<u8>),
+}
+
+impl Default for DistinctSet {
+    fn default() -> Self {
+        Self::Exact(HashSet::new())
+    }
+}
+
+impl DistinctSet {
+    /// Adds the value with the given hash
+    pub fn insert(&mut self, hash: u64) {
+        let full = match self {
+            Self::Exact(hashes) => {
+                hashes.insert(hash);
+                hashes.len() > EXACT_LIMIT
+            }
+            Self::Sketch(registers) => {
+                add_to_sketch(registers, hash);
+                false
+            }
+        };
+        if full {
+            self.convert_to_sketch();
+        }
+    }
+
+    /// Adds all the values of `other`
+    pub fn merge(&mut self, other: Self) {
+        match other {
+            Self::Exact(hashes) => {
+                for hash in hashes {
+                    self.insert(hash);
+                }
+            }
+            Self::Sketch(other) => {
+                self.convert_to_sketch();
+                if let Self::Sketch(registers) = self {
+                    for (register, other) in registers.iter_mut().zip(other) {
+                        *register = (*register).max(other);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns the number of distinct values, exact while the set is
+    /// small and estimated once it is a sketch
+    pub fn count(&self) -> u64 {
+        match self {
+            Self::Exact(hashes) => hashes.len() as u64,
+            Self::Sketch(registers) => estimate(registers),
+        }
+    }
+
+    /// Serializes the set as either "e:<hash>,<hash>,..." or
+    /// "s:<registers>", in hexadecimal
+    pub fn to_state(&self) -> String {
+        match self {
+            Self::Exact(hashes) => {
+                let hashes: Vec<_> = hashes.iter().map(|hash| format!("{:x}", hash)).collect();
+                format!("e:{}", hashes.join(","))
+            }
+            Self::Sketch(registers) => {
+                let mut state = String::with_capacity(2 + 2 * registers.len());
+                state.push_str("s:");
+                for register in registers {
+                    write!(state, "{:02x}", register).expect("writing to a string");
+                }
+                state
+            }
+        }
+    }
+
+    /// Parses a set serialized by `to_state`
+    pub fn from_state(state: &str) -> Option<Self> {
+        if let Some(hashes) = state.strip_prefix("e:") {
+            hashes
+                .split(',')
+                .filter(|hash| !hash.is_empty())
+                .map(|hash| u64::from_str_radix(hash, 16).ok())
+                .collect::<Option<_>>()
+                .map(Self::Exact)
+        } else if let Some(registers) = state.strip_prefix("s:") {
+            if registers.len() != 2 * REGISTERS || !registers.is_ascii() {
+                return None;
+            }
+            (0..REGISTERS)
+                .map(|i| u8::from_str_radix(&registers[2 * i..2 * i + 2], 16).ok())
+                .collect::<Option<_>>()
+                .map(Self::Sketch)
+        } else {
+            None
+        }
+    }
+
+    /// Turns an exact set into a sketch of the same values
+    fn convert_to_sketch(&mut self) {
+        if let Self::Exact(hashes) = self {
+            let mut registers = vec![0; REGISTERS];
+            for &hash in hashes.iter() {
+                add_to_sketch(&mut registers, hash);
+            }
+            *self = Self::Sketch(registers);
+        }
+    }
+}
+
+/// The first PRECISION bits of `hash` select a register, which keeps the
+/// highest rank, the position of the first set bit, of the rest of the
+/// hashes selecting it
+fn add_to_sketch(registers: &mut [u8], hash: u64) {
+    let index = (hash >> (64 - PRECISION)) as usize;
+    // the extra bit bounds the rank when the rest of the hash is all zeros
+    let rest = (hash << PRECISION) | (1 << (PRECISION - 1));
+    let rank = rest.leading_zeros() as u8 + 1;
+    registers[index] = registers[index].max(rank);
+}
+
+/// The HyperLogLog estimate of the number of distinct hashes added to
+/// `registers`, counting the empty registers instead for small numbers
+fn estimate(registers: &[u8]) -> u64 {
+    let m = registers.len() as f64;
+    let alpha = 0.7213 / (1.0 + 1.079 / m);
+    let sum: f64 = registers
+        .iter()
+        .map(|&register| 2f64.powi(-(register as i32)))
+        .sum();
+    let raw = alpha * m * m / sum;
+
+    let empty = registers.iter().filter(|&&register| register == 0).count();
+    let estimate = if raw <= 2.5 * m && empty > 0 {
+        m * (m / empty as f64).ln()
+    } else {
+        raw
+    };
+    estimate.round() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_deps::{
+        arrow::{
+            array::DictionaryArray,
+            datatypes::{Field, Int32Type, Schema},
+            record_batch::RecordBatch,
+        },
+        datafusion::{datasource::MemTable, logical_plan::col, prelude::ExecutionContext},
+    };
+
+    fn set_of(values: impl Iterator<Item = u64>) -> DistinctSet {
+        let mut set = DistinctSet::default();
+        for value in values {
+            set.insert(hash(&value));
+        }
+        set
+    }
+
+    fn assert_close(actual: u64, expected: u64) {
+        let error = (actual as f64 - expected as f64).abs() / expected as f64;
+        assert!(error < 0.05, "{} is not close to {}", actual, expected);
+    }
+
+    #[test]
+    fn small_sets_are_exact() {
+        let set = set_of((0..1000).chain(0..1000));
+        assert!(matches!(set, DistinctSet::Exact(_)));
+        assert_eq!(set.count(), 1000);
+        assert_eq!(DistinctSet::default().count(), 0);
+    }
+
+    #[test]
+    fn large_sets_are_close() {
+        let set = set_of((0..100_000).chain(50_000..150_000));
+        assert!(matches!(set, DistinctSet::Sketch(_)));
+        assert_close(set.count(), 150_000);
+
+        // just over the exact limit, the sketch counts empty registers
+        let set = set_of(0..EXACT_LIMIT as u64 + 1);
+        assert_close(set.count(), EXACT_LIMIT as u64 + 1);
+    }
+
+    #[test]
+    fn merge_and_state() {
+        let small = set_of(0..10);
+        let large = set_of(0..50_000);
+
+        let state = small.to_state();
+        assert_eq!(DistinctSet::from_state(&state), Some(small.clone()));
+        let state = large.to_state();
+        assert_eq!(DistinctSet::from_state(&state), Some(large.clone()));
+        assert_eq!(DistinctSet::from_state("s:00"), None);
+        assert_eq!(DistinctSet::from_state("x"), None);
+
+        let mut merged = small.clone();
+        merged.merge(set_of(5..20));
+        assert_eq!(merged.count(), 20);
+
+        let mut merged = small;
+        merged.merge(large.clone());
+        assert_eq!(merged, large);
+
+        let mut merged = set_of(40_000..100_000);
+        merged.merge(large);
+        assert_close(merged.count(), 100_000);
+    }
+
+    #[tokio::test]
+    async fn test_count_distinct() {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new(
+                "tag",
+                DataType::Dictionary(Box::new(DataType::Int32), Box::new(DataType::Utf8)),
+                true,
+            ),
+            Field::new("i64_value", DataType::Int64, true),
+        ]));
+
+        // define data in two partitions
+        let tags: DictionaryArray<Int32Type> =
+            vec![Some("a"), Some("b"), None].into_iter().collect();
+        let batch1 = RecordBatch::try_new(
+            Arc::clone(&schema),
+            vec![
+                Arc::new(tags),
+                Arc::new(Int64Array::from(vec![Some(1), Some(2), None])),
+            ],
+        )
+        .unwrap();
+        let tags: DictionaryArray<Int32Type> =
+            vec![Some("b"), Some("c"), Some("a")].into_iter().collect();
+        let batch2 = RecordBatch::try_new(
+            Arc::clone(&schema),
+            vec![
+                Arc::new(tags),
+                Arc::new(Int64Array::from(vec![Some(2), Some(2), Some(1)])),
+            ],
+        )
+        .unwrap();
+
+        let provider =
+            MemTable::try_new(Arc::clone(&schema), vec![vec![batch1], vec![batch2]]).unwrap();
+        let mut ctx = ExecutionContext::new();
+        ctx.register_table("t", Arc::new(provider));
+
+        let aggs = vec![
+            count_distinct().call(vec![col("tag")]),
+            count_distinct().call(vec![col("i64_value")]),
+        ];
+        let df = ctx.table("t").unwrap().aggregate(&[], &aggs).unwrap();
+        let record_batches = df.collect().await.unwrap();
+        assert_eq!(record_batches.len(), 1);
+
+        let actual: Vec<_> = record_batches[0]
+            .columns()
+            .iter()
+            .map(|column| {
+                let column = column.as_any().downcast_ref::<UInt64Array>().unwrap();
+                assert_eq!(column.len(), 1);
+                column.value(0)
+            })
+            .collect();
+        assert_eq!(actual, vec![3, 2]);
+    }
+}
@@ -0,0 +1,523 @@
+//! Implementation of approximate percentile aggregates using t-digests
+//!
+//! A t-digest summarizes a distribution as a list of centroids (the mean
+//! and number of the values they stand for) which are kept small near
+//! the ends of the distribution and allowed to grow in the middle. This
+//! bounds its memory while keeping the extreme percentiles, like the
+//! 99th of latencies, accurate. Digests of separate partitions can be
+//! merged, so the aggregates can be computed in parallel.
+//!
+//! See "Computing Extremely Accurate Quantiles Using t-Digests" by Ted
+//! Dunning and Otmar Ertl.
+use std::{fmt::Debug, sync::Arc};
+
+use arrow_deps::{
+    arrow::{
+        array::{Array, ArrayRef, Float64Array, StringArray},
+        compute::kernels::cast::cast,
+        datatypes::DataType,
+    },
+    datafusion::{
+        error::{DataFusionError, Result as DataFusionResult},
+        physical_plan::{
+            aggregates::{AccumulatorFunctionImplementation, StateTypeFunction},
+            functions::{ReturnTypeFunction, Signature},
+            udaf::AggregateUDF,
+            Accumulator,
+        },
+        scalar::ScalarValue,
+    },
+};
+
+/// The name of the SQL function `approx_percentile(value, percentile)`
+pub const APPROX_PERCENTILE: &str = "approx_percentile";
+
+/// The name of the SQL function `approx_median(value)`
+pub const APPROX_MEDIAN: &str = "approx_median";
+
+/// Bounds the number of centroids of a digest to a small multiple of this
+const COMPRESSION: f64 = 100.0;
+
+/// The number of values added to a digest before they are merged into its
+/// centroids
+const BUFFER_SIZE: usize = 500;
+
+/// Returns a DataFusion user defined aggregate function computing an
+/// approximation of a percentile, between 0 and 100, of its input:
+///
+/// approx_percentile(value_column, percentile) -> Float64
+///
+/// The percentile is read from the first row of the second argument,
+/// which is typically a literal
+pub fn approx_percentile() -> AggregateUDF {
+    make_uda(APPROX_PERCENTILE, None)
+}
+
+/// Returns a DataFusion user defined aggregate function computing an
+/// approximation of the median of its input:
+///
+/// approx_median(value_column) -> Float64
+pub fn approx_median() -> AggregateUDF {
+    make_uda(APPROX_MEDIAN, Some(50.0))
+}
+
+/// Factory function for the percentile UDAs, which take the percentile as
+/// an argument unless it is `fixed`
+fn make_uda(name: &'static str, fixed: Option<f64>) -> AggregateUDF {
+    let num_args = if fixed.is_some() { 1 } else { 2 };
+    // numeric values are cast to floats
+    let input_signature = Signature::Uniform(num_args, vec![DataType::Float64]);
+
+    let state_type = Arc::new(vec![DataType::Utf8, DataType::Float64]);
+    let state_type_factory: StateTypeFunction = Arc::new(move |_| Ok(Arc::clone(&state_type)));
+
+    let factory: AccumulatorFunctionImplementation =
+        Arc::new(move || Ok(Box::new(PercentileAccumulator::new(fixed))));
+
+    let return_type = Arc::new(DataType::Float64);
+    let return_type_func: ReturnTypeFunction = Arc::new(move |_| Ok(Arc::clone(&return_type)));
+
+    AggregateUDF::new(
+        name,
+        &input_signature,
+        &return_type_func,
+        &factory,
+        &state_type_factory,
+    )
+}
+
+/// Accumulates the values of a group into a digest. Its state is the
+/// digest, serialized as a string, and the percentile.
+#[derive(Debug)]
+struct PercentileAccumulator {
+    digest: TDigest,
+    /// The percentile computed, between 0 and 100, None until read from
+    /// the input
+    percentile: Option<f64>,
+}
+
+impl PercentileAccumulator {
+    fn new(percentile: Option<f64>) -> Self {
+        Self {
+            digest: TDigest::default(),
+            percentile,
+        }
+    }
+
+    /// Sets the percentile from the first valid row of `percentiles`, if
+    /// not already set
+    fn set_percentile(&mut self, percentiles: &ArrayRef) -> DataFusionResult<()> {
+        if self.percentile.is_some() {
+            return Ok(());
+        }
+        let percentiles = as_f64(percentiles)?;
+        let percentiles = percentiles
+            .as_any()
+            .downcast_ref::<Float64Array>()
+            .expect("cast to Float64");
+
+        if let Some(row) = (0..percentiles.len()).find(|&row| percentiles.is_valid(row)) {
+            let percentile = percentiles.value(row);
+            if !(0.0..=100.0).contains(&percentile) {
+                return Err(DataFusionError::Execution(format!(
+                    "percentile must be between 0 and 100, got {}",
+                    percentile
+                )));
+            }
+            self.percentile = Some(percentile);
+        }
+        Ok(())
+    }
+}
+
+impl Accumulator for PercentileAccumulator {
+    fn state(&self) -> DataFusionResult<Vec<ScalarValue>> {
+        Ok(vec![
+            ScalarValue::Utf8(Some(self.digest.to_state())),
+            ScalarValue::Float64(self.percentile),
+        ])
+    }
+
+    fn update(&mut self, _values: &[ScalarValue]) -> DataFusionResult<()> {
+        unreachable!("Should only be calling update_batch for performance reasons");
+    }
+
+    fn merge(&mut self, _states: &[ScalarValue]) -> DataFusionResult<()> {
+        unreachable!("Should only be calling merge_batch for performance reasons");
+    }
+
+    fn evaluate(&self) -> DataFusionResult<ScalarValue> {
+        let percentile = self.percentile.unwrap_or(50.0);
+        Ok(ScalarValue::Float64(
+            self.digest.clone().quantile(percentile / 100.0),
+        ))
+    }
+
+    fn update_batch(&mut self, values: &[ArrayRef]) -> DataFusionResult<()> {
+        let (value_arr, percentile_arr) = match values {
+            [] => return Ok(()),
+            [value_arr] => (value_arr, None),
+            [value_arr, percentile_arr] => (value_arr, Some(percentile_arr)),
+            _ => {
+                return Err(DataFusionError::Internal(format!(
+                    "Internal error: Expected 1 or 2 arguments to percentile function but got {}",
+                    values.len()
+                )))
+            }
+        };
+        if let Some(percentile_arr) = percentile_arr {
+            self.set_percentile(percentile_arr)?;
+        }
+
+        let value_arr = as_f64(value_arr)?;
+        let value_arr = value_arr
+            .as_any()
+            .downcast_ref::<Float64Array>()
+            .expect("cast to Float64");
+        for row in 0..value_arr.len() {
+            if value_arr.is_valid(row) {
+                self.digest.add(value_arr.value(row));
+            }
+        }
+        Ok(())
+    }
+
+    fn merge_batch(&mut self, states: &[ArrayRef]) -> DataFusionResult<()> {
+        if states.len() != 2 {
+            return Err(DataFusionError::Internal(format!(
+                "Internal error: Expected 2 state columns for percentile function but got {}",
+                states.len()
+            )));
+        }
+        self.set_percentile(&states[1])?;
+
+        let digests = states[0]
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .ok_or_else(|| {
+                DataFusionError::Internal("percentile state is not a string".to_string())
+            })?;
+        for row in 0..digests.len() {
+            if digests.is_valid(row) {
+                let digest = TDigest::from_state(digests.value(row)).ok_or_else(|| {
+                    DataFusionError::Internal(format!(
+                        "invalid percentile state: {}",
+                        digests.value(row)
+                    ))
+                })?;
+                self.digest.merge(digest);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Casts the numbers of `array` to floats
+fn as_f64(array: &ArrayRef) -> DataFusionResult<ArrayRef> {
+    match array.data_type() {
+        DataType::Float64 => Ok(Arc::clone(array)),
+        _ => Ok(cast(array, &DataType::Float64)?),
+    }
+}
+
+/// A cluster of `weight` values averaging `mean`
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Centroid {
+    mean: f64,
+    weight: f64,
+}
+
+/// The t-digest of a list of values, see the module docs
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TDigest {
+    /// Sorted by mean once compressed
+    centroids: Vec<Centroid>,
+    /// Values added since the last compression
+    buffer: Vec<f64>,
+    min: f64,
+    max: f64,
+}
+
+impl TDigest {
+    /// Adds `value` to the distribution. NaNs are ignored.
+    pub fn add(&mut self, value: f64) {
+        if value.is_nan() {
+            return;
+        }
+        if self.is_empty() {
+            self.min = value;
+            self.max = value;
+        } else {
+            self.min = self.min.min(value);
+            self.max = self.max.max(value);
+        }
+        self.buffer.push(value);
+        if self.buffer.len() >= BUFFER_SIZE {
+            self.compress();
+        }
+    }
+
+    /// Adds the values summarized by `other` to the distribution
+    pub fn merge(&mut self, other: Self) {
+        if other.is_empty() {
+            return;
+        }
+        if self.is_empty() {
+            self.min = other.min;
+            self.max = other.max;
+        } else {
+            self.min = self.min.min(other.min);
+            self.max = self.max.max(other.max);
+        }
+        self.centroids.extend(other.centroids);
+        self.buffer.extend(other.buffer);
+        self.compress();
+    }
+
+    /// Returns true if no values were added
+    pub fn is_empty(&self) -> bool {
+        self.centroids.is_empty() && self.buffer.is_empty()
+    }
+
+    /// Returns the approximate value below which the fraction `q`, between
+    /// 0 and 1, of the values fall, or None if there are no values
+    pub fn quantile(&mut self, q: f64) -> Option<f64> {
+        self.compress();
+        let total: f64 = self.centroids.iter().map(|c| c.weight).sum();
+        if self.centroids.is_empty() {
+            return None;
+        }
+
+        // interpolate between the centers of the centroids, taking the
+        // minimum and maximum as the ends of the distribution
+        let target = q.max(0.0).min(1.0) * total;
+        let (mut position, mut value) = (0.0, self.min);
+        let mut cumulative = 0.0;
+        for centroid in &self.centroids {
+            let center = cumulative + centroid.weight / 2.0;
+            if target < center {
+                return Some(interpolate(position, value, center, centroid.mean, target));
+            }
+            position = center;
+            value = centroid.mean;
+            cumulative += centroid.weight;
+        }
+        Some(interpolate(position, value, total, self.max, target))
+    }
+
+    /// Merges the buffered values and the centroids into as few centroids
+    /// as the size bound allows: centroids may stand for more values the
+    /// closer they are to the median
+    fn compress(&mut self) {
+        if self.buffer.is_empty() {
+            return;
+        }
+        let mut centroids = std::mem::take(&mut self.centroids);
+        centroids.extend(
+            self.buffer
+                .drain(..)
+                .map(|mean| Centroid { mean, weight: 1.0 }),
+        );
+        centroids.sort_by(|a, b| a.mean.partial_cmp(&b.mean).expect("NaNs are not added"));
+
+        let total: f64 = centroids.iter().map(|c| c.weight).sum();
+        let mut merged = Vec::with_capacity(centroids.len());
+        let mut centroids = centroids.into_iter();
+        let mut current = centroids.next().expect("buffer was not empty");
+        let mut weight_before = 0.0;
+
+        for centroid in centroids {
+            let q0 = weight_before / total;
+            let q2 = (weight_before + current.weight + centroid.weight) / total;
+            let limit = 4.0 * total * (q0 * (1.0 - q0)).min(q2 * (1.0 - q2)) / COMPRESSION;
+
+            if current.weight + centroid.weight <= limit {
+                let weight = current.weight + centroid.weight;
+                current.mean += (centroid.mean - current.mean) * centroid.weight / weight;
+                current.weight = weight;
+            } else {
+                weight_before += current.weight;
+                merged.push(current);
+                current = centroid;
+            }
+        }
+        merged.push(current);
+        self.centroids = merged;
+    }
+
+    /// Serializes the digest as `min,max;mean:weight,...`
+    fn to_state(&self) -> String {
+        let mut digest = self.clone();
+        digest.compress();
+        let centroids = digest
+            .centroids
+            .iter()
+            .map(|c| format!("{}:{}", c.mean, c.weight))
+            .collect::<Vec<_>>()
+            .join(",");
+        format!("{},{};{}", digest.min, digest.max, centroids)
+    }
+
+    /// Parses a digest serialized by `to_state`
+    fn from_state(state: &str) -> Option<Self> {
+        let mut parts = state.splitn(2, ';');
+        let mut bounds = parts.next()?.splitn(2, ',');
+        let min = bounds.next()?.parse().ok()?;
+        let max = bounds.next()?.parse().ok()?;
+        let centroids = parts
+            .next()?
+            .split(',')
+            .filter(|c| !c.is_empty())
+            .map(|c| {
+                let mut parts = c.splitn(2, ':');
+                let mean = parts.next()?.parse().ok()?;
+                let weight = parts.next()?.parse().ok()?;
+                Some(Centroid { mean, weight })
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        Some(Self {
+            centroids,
+            buffer: vec![],
+            min,
+            max,
+        })
+    }
+}
+
+/// Returns the value at `target` on the line from `(x0, y0)` to `(x1, y1)`
+fn interpolate(x0: f64, y0: f64, x1: f64, y1: f64, target: f64) -> f64 {
+    if x1 <= x0 {
+        return y1;
+    }
+    y0 + (y1 - y0) * (target - x0) / (x1 - x0)
+}
+
+#[cfg(test)]
+mod test {
+    use arrow_deps::{
+        arrow::{
+            array::Int64Array,
+            datatypes::{Field, Schema},
+            record_batch::RecordBatch,
+        },
+        datafusion::{datasource::MemTable, prelude::*},
+    };
+
+    use super::*;
+
+    #[test]
+    fn small_digests_are_exact() {
+        let mut digest = TDigest::default();
+        assert_eq!(digest.quantile(0.5), None);
+
+        for v in &[5.0, 1.0, 4.0, 2.0, 3.0] {
+            digest.add(*v);
+        }
+        assert_eq!(digest.quantile(0.0), Some(1.0));
+        assert_eq!(digest.quantile(0.5), Some(3.0));
+        assert_eq!(digest.quantile(1.0), Some(5.0));
+
+        digest.add(f64::NAN);
+        digest.add(6.0);
+        assert_eq!(digest.quantile(0.5), Some(3.5));
+    }
+
+    #[test]
+    fn large_digests_are_close() {
+        let mut digest = TDigest::default();
+        for v in 0..100_000 {
+            digest.add(v as f64);
+        }
+        assert!(digest.centroids.len() < 10 * COMPRESSION as usize);
+
+        for &q in &[0.01, 0.5, 0.9, 0.99, 0.999] {
+            let actual = digest.quantile(q).unwrap();
+            let expected = q * 100_000.0;
+            assert!(
+                (actual - expected).abs() < 100_000.0 * 0.005,
+                "q {}: expected about {}, got {}",
+                q,
+                expected,
+                actual
+            );
+        }
+    }
+
+    #[test]
+    fn merge_and_state() {
+        let mut evens = TDigest::default();
+        let mut odds = TDigest::default();
+        for v in 0..1000 {
+            if v % 2 == 0 {
+                evens.add(v as f64)
+            } else {
+                odds.add(v as f64)
+            }
+        }
+
+        let state = odds.to_state();
+        let mut odds = TDigest::from_state(&state).unwrap();
+        assert_eq!(odds.to_state(), state);
+        assert_eq!(odds.quantile(0.0), Some(1.0));
+        assert_eq!(odds.quantile(1.0), Some(999.0));
+
+        evens.merge(odds);
+        let median = evens.quantile(0.5).unwrap();
+        assert!((median - 499.5).abs() < 5.0, "{}", median);
+
+        assert!(TDigest::from_state("1,2;3").is_none());
+        assert_eq!(TDigest::from_state("0,0;"), Some(TDigest::default()));
+    }
+
+    #[tokio::test]
+    async fn test_approx_percentile() {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("f64_value", DataType::Float64, true),
+            Field::new("i64_value", DataType::Int64, true),
+        ]));
+
+        // define data in two partitions
+        let batch1 = RecordBatch::try_new(
+            Arc::clone(&schema),
+            vec![
+                Arc::new(Float64Array::from(vec![Some(2.0), Some(4.0), None])),
+                Arc::new(Int64Array::from(vec![Some(20), Some(40), None])),
+            ],
+        )
+        .unwrap();
+        let batch2 = RecordBatch::try_new(
+            Arc::clone(&schema),
+            vec![
+                Arc::new(Float64Array::from(vec![Some(1.0), Some(5.0), Some(3.0)])),
+                Arc::new(Int64Array::from(vec![Some(10), Some(50), Some(30)])),
+            ],
+        )
+        .unwrap();
+
+        let provider =
+            MemTable::try_new(Arc::clone(&schema), vec![vec![batch1], vec![batch2]]).unwrap();
+        let mut ctx = ExecutionContext::new();
+        ctx.register_table("t", Arc::new(provider));
+
+        let aggs = vec![
+            approx_median().call(vec![col("f64_value")]),
+            approx_percentile().call(vec![col("i64_value"), lit(100.0)]),
+            approx_percentile().call(vec![col("f64_value"), lit(25.0)]),
+        ];
+        let df = ctx.table("t").unwrap().aggregate(&[], &aggs).unwrap();
+        let record_batches = df.collect().await.unwrap();
+        assert_eq!(record_batches.len(), 1);
+
+        let actual: Vec<_> = record_batches[0]
+            .columns()
+            .iter()
+            .map(|column| {
+                let column = column.as_any().downcast_ref::<Float64Array>().unwrap();
+                assert_eq!(column.len(), 1);
+                column.value(0)
+            })
+            .collect();
+        assert_eq!(actual, vec![3.0, 50.0, 1.75]);
+    }
+}
@@ -0,0 +1,125 @@
+//! Implementation of the regular expression matching of tag values used
+//! by predicates such as `host =~ /^server-\d+$/` or `region !~ /west/`.
+//!
+//! The regular expression is compiled once, when the predicate is built,
+//! and the function matching it is created for that expression alone.
+use std::sync::Arc;
+
+use arrow_deps::{
+    arrow::{
+        array::{Array, ArrayRef, BooleanArray, StringArray},
+        compute::kernels::cast::cast,
+        datatypes::DataType,
+    },
+    datafusion::{
+        error::Result as DataFusionResult,
+        logical_plan::Expr,
+        physical_plan::{
+            functions::{make_scalar_function, ReturnTypeFunction, Signature},
+            udf::ScalarUDF,
+        },
+    },
+};
+use regex::Regex;
+
+/// The name of the function matching a regular expression
+pub const REGEX_MATCH: &str = "regex_match";
+
+/// The name of the function not matching a regular expression
+pub const REGEX_NOT_MATCH: &str = "regex_not_match";
+
+/// Returns an expression which is true for the values of `input` that
+/// match `regex`, or with `matches` false, that don't. The values are
+/// compared as strings, and null values are neither.
+pub fn regex_match_expr(input: Expr, regex: Regex, matches: bool) -> Expr {
+    let name = if matches {
+        REGEX_MATCH
+    } else {
+        REGEX_NOT_MATCH
+    };
+
+    let func = make_scalar_function(move |args: &[ArrayRef]| regex_match(args, &regex, matches));
+    let return_type: ReturnTypeFunction = Arc::new(|_| Ok(Arc::new(DataType::Boolean)));
+
+    // tags may be dictionary encoded, so any type is cast to strings
+    let udf = ScalarUDF::new(name, &Signature::Any(1), &return_type, &func);
+    udf.call(vec![input])
+}
+
+fn regex_match(args: &[ArrayRef], regex: &Regex, matches: bool) -> DataFusionResult<ArrayRef> {
+    // this is guaranteed by DataFusion based on the function's signature.
+    assert_eq!(args.len(), 1);
+
+    let strings = match args[0].data_type() {
+        DataType::Utf8 => Arc::clone(&args[0]),
+        _ => cast(&args[0], &DataType::Utf8)?,
+    };
+    let strings = strings
+        .as_any()
+        .downcast_ref::<StringArray>()
+        .expect("cast to Utf8");
+
+    let results: Vec<_> = (0..strings.len())
+        .map(|row| {
+            if strings.is_valid(row) {
+                Some(regex.is_match(strings.value(row)) == matches)
+            } else {
+                None
+            }
+        })
+        .collect();
+    Ok(Arc::new(BooleanArray::from(results)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_deps::arrow::{array::DictionaryArray, datatypes::Int32Type};
+
+    fn values(array: ArrayRef) -> Vec<Option<bool>> {
+        let array = array.as_any().downcast_ref::<BooleanArray>().unwrap();
+        (0..array.len())
+            .map(|row| {
+                if array.is_valid(row) {
+                    Some(array.value(row))
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_regex_match() {
+        let regex = Regex::new(r"^server-\d+$").unwrap();
+        let input: ArrayRef = Arc::new(StringArray::from(vec![
+            Some("server-1"),
+            None,
+            Some("server-a"),
+            Some("a server-2"),
+        ]));
+
+        let matched = regex_match(&[Arc::clone(&input)], &regex, true).unwrap();
+        assert_eq!(
+            values(matched),
+            vec![Some(true), None, Some(false), Some(false)]
+        );
+        let not_matched = regex_match(&[input], &regex, false).unwrap();
+        assert_eq!(
+            values(not_matched),
+            vec![Some(false), None, Some(true), Some(true)]
+        );
+    }
+
+    #[test]
+    fn test_regex_match_dictionary() {
+        // unanchored expressions match anywhere in the value
+        let regex = Regex::new("west").unwrap();
+        let input: DictionaryArray<Int32Type> =
+            vec!["us-west", "us-east", "us-west"].into_iter().collect();
+        let input: ArrayRef = Arc::new(input);
+
+        let matched = regex_match(&[input], &regex, true).unwrap();
+        assert_eq!(values(matched), vec![Some(true), Some(false), Some(true)]);
+    }
+}
@@ -5,7 +5,7 @@
 use arrow_deps::datafusion::logical_plan::Expr;
 use snafu::Snafu;
 
-use crate::func::window;
+use crate::func::{cardinality::count_distinct, percentile::approx_percentile, window};
 
 #[derive(Debug, Snafu)]
 pub enum Error {
@@ -61,6 +61,14 @@ pub enum Aggregate {
     /// Aggregate: Average (geometric mean) column's value
     Mean,
 
+    /// Aggregate: Approximation of the given percentile, between 0 and
+    /// 100, of the column's values, e.g. 50 for the median
+    Percentile(f64),
+
+    /// Aggregate: Number of distinct values of the column, estimated for
+    /// large numbers
+    CountDistinct,
+
     /// No grouping is applied
     None,
 }
@@ -117,7 +125,7 @@ pub enum WindowDuration {
 impl Aggregate {
     /// Create the appropriate DataFusion expression for this aggregate
     pub fn to_datafusion_expr(&self, input: Expr) -> Result<Expr> {
-        use arrow_deps::datafusion::logical_plan::{avg, count, max, min, sum};
+        use arrow_deps::datafusion::logical_plan::{avg, count, lit, max, min, sum};
         match self {
             Self::Sum => Ok(sum(input)),
             Self::Count => Ok(count(input)),
@@ -126,6 +134,10 @@ impl Aggregate {
             Self::First => AggregateNotSupported { agg: "First" }.fail(),
             Self::Last => AggregateNotSupported { agg: "Last" }.fail(),
             Self::Mean => Ok(avg(input)),
+            Self::CountDistinct => Ok(count_distinct().call(vec![input])),
+            Self::Percentile(percentile) => {
+                Ok(approx_percentile().call(vec![input, lit(*percentile)]))
+            }
             Self::None => AggregateNotSupported { agg: "None" }.fail(),
         }
     }
@@ -17,12 +17,14 @@ use plan::seriesset::SeriesSetPlans;
 use std::{fmt::Debug, sync::Arc};
 
 pub mod exec;
+pub mod filter;
 pub mod frontend;
 pub mod func;
 pub mod group_by;
 pub mod plan;
 pub mod predicate;
 pub mod provider;
+pub mod pruning;
 pub mod util;
 
 use self::{group_by::GroupByAndAggregate, predicate::Predicate};
@@ -96,7 +98,9 @@ pub trait PartitionChunk: Debug + Send + Sync {
     /// skipped entirely. If true is returned, there still may not be
     /// rows that match.
     ///
-    /// This is used during query planning to skip including entire chunks
+    /// This is used during query planning to skip including entire
+    /// chunks, typically by checking their statistics with
+    /// `pruning::could_pass`
     fn could_pass_predicate(&self, _predicate: &Predicate) -> Result<bool, Self::Error> {
         Ok(true)
     }
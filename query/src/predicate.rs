@@ -5,9 +5,16 @@
 
 use std::collections::BTreeSet;
 
-use arrow_deps::datafusion::logical_plan::Expr;
+use arrow_deps::datafusion::{
+    logical_plan::{Expr, Operator},
+    scalar::ScalarValue,
+};
+use data_types::TIME_COLUMN_NAME;
 
-use crate::util::{make_range_expr, AndExprBuilder};
+use crate::{
+    pruning,
+    util::{make_range_expr, AndExprBuilder},
+};
 
 /// Specifies a continuous range of nanosecond timestamps. Timestamp
 /// predicates are so common and critical to performance of timeseries
@@ -37,6 +44,87 @@ impl TimestampRange {
     pub fn contains_opt(&self, v: Option<i64>) -> bool {
         Some(true) == v.map(|ts| self.contains(ts))
     }
+
+    /// Returns the range of times allowed by the comparisons of the time
+    /// column with integer literals in `exprs`, which are AND'ed
+    /// together, or None if there are no such comparisons
+    pub fn from_exprs(exprs: &[Expr]) -> Option<Self> {
+        let mut range = None;
+        for expr in exprs {
+            restrict_range(&mut range, expr);
+        }
+        range
+    }
+}
+
+/// Narrows `range` to the times allowed by `expr`
+fn restrict_range(range: &mut Option<TimestampRange>, expr: &Expr) {
+    let (left, op, right) = match expr {
+        Expr::BinaryExpr { left, op, right } => (left.as_ref(), *op, right.as_ref()),
+        _ => return,
+    };
+    let (op, time) = match (left, op, right) {
+        (_, Operator::And, _) => {
+            restrict_range(range, left);
+            restrict_range(range, right);
+            return;
+        }
+        (Expr::Column(column), op, Expr::Literal(ScalarValue::Int64(Some(time))))
+            if column == TIME_COLUMN_NAME =>
+        {
+            (op, *time)
+        }
+        (Expr::Literal(ScalarValue::Int64(Some(time))), op, Expr::Column(column))
+            if column == TIME_COLUMN_NAME =>
+        {
+            match pruning::flip(op) {
+                Some(op) => (op, *time),
+                None => return,
+            }
+        }
+        _ => return,
+    };
+
+    let (start, end) = match op {
+        Operator::Eq => (time, time.saturating_add(1)),
+        Operator::Gt => (time.saturating_add(1), i64::MAX),
+        Operator::GtEq => (time, i64::MAX),
+        Operator::Lt => (i64::MIN, time),
+        Operator::LtEq => (i64::MIN, time.saturating_add(1)),
+        _ => return,
+    };
+    *range = Some(match range {
+        Some(range) => TimestampRange::new(range.start.max(start), range.end.min(end)),
+        None => TimestampRange::new(start, end),
+    });
+}
+
+/// Returns the `(column, value)` pairs of the comparisons of a column
+/// being equal to a string literal in `exprs`, which are AND'ed together
+pub fn string_equalities(exprs: &[Expr]) -> Vec<(&str, &str)> {
+    let mut equalities = vec![];
+    for expr in exprs {
+        collect_string_equalities(expr, &mut equalities);
+    }
+    equalities
+}
+
+fn collect_string_equalities<'a>(expr: &'a Expr, equalities: &mut Vec<(&'a str, &'a str)>) {
+    if let Expr::BinaryExpr { left, op, right } = expr {
+        match (left.as_ref(), op, right.as_ref()) {
+            (_, Operator::And, _) => {
+                collect_string_equalities(left, equalities);
+                collect_string_equalities(right, equalities);
+            }
+            (Expr::Column(column), Operator::Eq, Expr::Literal(value))
+            | (Expr::Literal(value), Operator::Eq, Expr::Column(column)) => {
+                if let ScalarValue::Utf8(Some(value)) = value {
+                    equalities.push((column.as_str(), value.as_str()))
+                }
+            }
+            _ => {}
+        }
+    }
 }
 
 /// Represents a parsed predicate for evaluation by the
@@ -237,6 +325,52 @@ mod tests {
         assert!(!range.contains(201));
     }
 
+    #[test]
+    fn test_timestamp_range_from_exprs() {
+        use arrow_deps::datafusion::prelude::{col, lit};
+
+        assert_eq!(TimestampRange::from_exprs(&[]), None);
+        assert_eq!(
+            TimestampRange::from_exprs(&[col("host").eq(lit("a"))]),
+            None
+        );
+        assert_eq!(
+            TimestampRange::from_exprs(&[
+                col("time").gt(lit(100i64)),
+                col("host").eq(lit("a")).and(col("time").lt_eq(lit(200i64))),
+            ]),
+            Some(TimestampRange::new(101, 201))
+        );
+        assert_eq!(
+            TimestampRange::from_exprs(&[lit(100i64).lt_eq(col("time"))]),
+            Some(TimestampRange::new(100, i64::MAX))
+        );
+        // only conjunctions restrict the range
+        assert_eq!(
+            TimestampRange::from_exprs(&[col("time")
+                .gt(lit(100i64))
+                .or(col("time").lt(lit(50i64)))]),
+            None
+        );
+    }
+
+    #[test]
+    fn test_string_equalities() {
+        use arrow_deps::datafusion::prelude::{col, lit};
+
+        let exprs = vec![
+            col("host").eq(lit("a")),
+            lit("b").eq(col("region")).and(col("time").gt(lit(100i64))),
+            col("cpu").eq(lit("c")).or(col("cpu").eq(lit("d"))),
+            col("usage").eq(lit(1.0)),
+            col("state").not_eq(lit("e")),
+        ];
+        assert_eq!(
+            string_equalities(&exprs),
+            vec![("host", "a"), ("region", "b")]
+        );
+    }
+
     #[test]
     fn test_timestamp_range_contains_opt() {
         let range = TimestampRange::new(100, 200);
@@ -12,11 +12,17 @@ use arrow_deps::{
         error::{DataFusionError, Result as DataFusionResult},
         logical_plan::Expr,
         physical_plan::ExecutionPlan,
+        prelude::{col, lit},
     },
 };
-use data_types::schema::{builder::SchemaMerger, Schema};
+use data_types::schema::{builder::SchemaMerger, InfluxColumnType, Schema};
 
-use crate::{predicate::Predicate, util::project_schema, PartitionChunk};
+use crate::{
+    predicate::{string_equalities, PredicateBuilder, TimestampRange},
+    pruning,
+    util::project_schema,
+    PartitionChunk,
+};
 
 use snafu::{ResultExt, Snafu};
 
@@ -176,6 +182,14 @@ impl<C: PartitionChunk + 'static> ChunkTableProvider<C> {
     pub fn arrow_schema(&self) -> ArrowSchemaRef {
         self.iox_schema.as_arrow()
     }
+
+    /// Returns true if `column` is a tag of this table
+    fn is_tag(&self, column: &str) -> bool {
+        match self.iox_schema.find_index_of(column) {
+            Some(index) => matches!(self.iox_schema.field(index).0, Some(InfluxColumnType::Tag)),
+            None => false,
+        }
+    }
 }
 
 impl<C: PartitionChunk + 'static> TableProvider for ChunkTableProvider<C> {
@@ -192,16 +206,54 @@ impl<C: PartitionChunk + 'static> TableProvider for ChunkTableProvider<C> {
         &self,
         projection: &Option<Vec<usize>>,
         _batch_size: usize,
-        _filters: &[Expr],
+        filters: &[Expr],
     ) -> std::result::Result<Arc<dyn ExecutionPlan>, DataFusionError> {
-        // TODO Here is where predicate pushdown will happen.  To make
-        // predicate push down happen, the provider need need to
-        // create a Predicate from the Expr .
-        //
         // Note that `filters` don't actually need to be evaluated in
         // the scan for the plans to be correct, they are an extra
-        // optimization for providers which can offer them
-        let predicate = Predicate::default();
+        // optimization for providers which can offer them.
+        //
+        // For now the time range they allow and the values they require
+        // of tags are pushed down to the chunks, which can evaluate them
+        // on their encoded data before materializing any rows.
+        // TODO push down the other filters
+        let predicate = string_equalities(filters)
+            .into_iter()
+            .filter(|(column, _)| self.is_tag(column))
+            .fold(
+                PredicateBuilder::new().timestamp_range_option(TimestampRange::from_exprs(filters)),
+                |builder, (tag, value)| builder.add_expr(col(tag).eq(lit(value))),
+            )
+            .build();
+
+        // They are also used to skip the chunks whose statistics show
+        // they have no matching rows
+        let pruning_predicate = filters
+            .iter()
+            .fold(
+                PredicateBuilder::new().table(self.table_name.as_str()),
+                |builder, filter| builder.add_expr(filter.clone()),
+            )
+            .build();
+
+        let mut chunk_and_infos = Vec::with_capacity(self.chunk_and_infos.len());
+        for chunk_info in &self.chunk_and_infos {
+            let could_pass = chunk_info
+                .chunk
+                .could_pass_predicate(&pruning_predicate)
+                .map_err(|e| {
+                    DataFusionError::Execution(format!(
+                        "Error checking predicate for table {} chunk {}: {}",
+                        self.table_name,
+                        chunk_info.chunk.id(),
+                        e
+                    ))
+                })?;
+            if could_pass {
+                chunk_and_infos.push(chunk_info.clone());
+            }
+        }
+        let chunks_pruned = self.chunk_and_infos.len() - chunk_and_infos.len();
+        pruning::METRICS.record(self.chunk_and_infos.len(), chunks_pruned);
 
         // Figure out the schema of the requested output
         let scan_schema = project_schema(self.arrow_schema(), projection);
@@ -209,7 +261,8 @@ impl<C: PartitionChunk + 'static> TableProvider for ChunkTableProvider<C> {
         let plan = IOxReadFilterNode::new(
             Arc::clone(&self.table_name),
             scan_schema,
-            self.chunk_and_infos.clone(),
+            chunk_and_infos,
+            chunks_pruned,
             predicate,
         );
 
@@ -225,6 +278,8 @@ impl<C: PartitionChunk + 'static> TableProvider for ChunkTableProvider<C> {
         &self,
         _filter: &Expr,
     ) -> DataFusionResult<TableProviderFilterPushDown> {
-        Ok(TableProviderFilterPushDown::Unsupported)
+        // Filters are used for pruning chunks, but DataFusion must still
+        // apply them to the rows of the chunks that are scanned
+        Ok(TableProviderFilterPushDown::Inexact)
     }
 }
@@ -1,12 +1,14 @@
 //! Implementation of a DataFusion PhysicalPlan node across partition chunks
 
-use std::sync::Arc;
+use std::{fmt, sync::Arc};
 
 use arrow_deps::{
     arrow::datatypes::SchemaRef,
     datafusion::{
         error::DataFusionError,
-        physical_plan::{ExecutionPlan, Partitioning, SendableRecordBatchStream},
+        physical_plan::{
+            common::SizedRecordBatchStream, ExecutionPlan, Partitioning, SendableRecordBatchStream,
+        },
     },
 };
 use data_types::{schema::Schema, selection::Selection};
@@ -18,13 +20,14 @@ use async_trait::async_trait;
 use super::{adapter::SchemaAdapterStream, ChunkInfo};
 
 /// Implements the DataFusion physical plan interface
-#[derive(Debug)]
 pub(crate) struct IOxReadFilterNode<C: PartitionChunk + 'static> {
     table_name: Arc<String>,
     /// The desired output schema (includes selection_
     /// note that the chunk may not have all these columns.
     schema: SchemaRef,
     chunk_and_infos: Vec<ChunkInfo<C>>,
+    /// The number of chunks of the table skipped using their statistics
+    chunks_pruned: usize,
     predicate: Predicate,
 }
 
@@ -33,17 +36,40 @@ impl<C: PartitionChunk + 'static> IOxReadFilterNode<C> {
         table_name: Arc<String>,
         schema: SchemaRef,
         chunk_and_infos: Vec<ChunkInfo<C>>,
+        chunks_pruned: usize,
         predicate: Predicate,
     ) -> Self {
         Self {
             table_name,
             schema,
             chunk_and_infos,
+            chunks_pruned,
             predicate,
         }
     }
 }
 
+/// Summarizes the chunks rather than printing their contents, as this is
+/// what shows up in the physical plan of `EXPLAIN VERBOSE`
+impl<C: PartitionChunk + 'static> fmt::Debug for IOxReadFilterNode<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IOxReadFilterNode")
+            .field("table_name", &self.table_name)
+            .field("schema", &self.schema)
+            .field(
+                "chunk_ids",
+                &self
+                    .chunk_and_infos
+                    .iter()
+                    .map(|info| info.chunk.id())
+                    .collect::<Vec<_>>(),
+            )
+            .field("chunks_pruned", &self.chunks_pruned)
+            .field("predicate", &self.predicate)
+            .finish()
+    }
+}
+
 #[async_trait]
 impl<C: PartitionChunk + 'static> ExecutionPlan for IOxReadFilterNode<C> {
     fn as_any(&self) -> &dyn std::any::Any {
@@ -55,7 +81,9 @@ impl<C: PartitionChunk + 'static> ExecutionPlan for IOxReadFilterNode<C> {
     }
 
     fn output_partitioning(&self) -> Partitioning {
-        Partitioning::UnknownPartitioning(self.chunk_and_infos.len())
+        // a single empty partition if all the chunks were pruned, as
+        // DataFusion doesn't expect plans without partitions
+        Partitioning::UnknownPartitioning(self.chunk_and_infos.len().max(1))
     }
 
     fn children(&self) -> Vec<Arc<dyn ExecutionPlan>> {
@@ -75,6 +103,7 @@ impl<C: PartitionChunk + 'static> ExecutionPlan for IOxReadFilterNode<C> {
             table_name: Arc::clone(&self.table_name),
             schema: Arc::clone(&self.schema),
             chunk_and_infos: self.chunk_and_infos.clone(),
+            chunks_pruned: self.chunks_pruned,
             predicate: self.predicate.clone(),
         };
 
@@ -85,6 +114,13 @@ impl<C: PartitionChunk + 'static> ExecutionPlan for IOxReadFilterNode<C> {
         &self,
         partition: usize,
     ) -> arrow_deps::datafusion::error::Result<SendableRecordBatchStream> {
+        if self.chunk_and_infos.is_empty() {
+            return Ok(Box::pin(SizedRecordBatchStream::new(
+                Arc::clone(&self.schema),
+                vec![],
+            )));
+        }
+
         let fields = self.schema.fields();
         let selection_cols = fields.iter().map(|f| f.name() as &str).collect::<Vec<_>>();
 
@@ -0,0 +1,259 @@
+//! Pruning of chunks using their statistics: a chunk whose time range and
+//! column min/max values show that none of its rows can pass a predicate
+//! is skipped entirely instead of being scanned.
+//!
+//! The checks are conservative: any expression that can not be evaluated
+//! on the statistics alone is assumed to possibly pass.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use arrow_deps::datafusion::{
+    logical_plan::{Expr, Operator},
+    scalar::ScalarValue,
+};
+use data_types::{
+    partition_metadata::{ColumnSummary, StatValues, Statistics, TableSummary},
+    TIME_COLUMN_NAME,
+};
+
+use crate::predicate::{Predicate, TimestampRange};
+
+/// Process wide counts of the chunks considered and pruned by the query
+/// planners
+#[derive(Debug)]
+pub struct PruningMetrics {
+    chunks_considered: AtomicU64,
+    chunks_pruned: AtomicU64,
+}
+
+/// The pruning counts of all the queries planned by this process
+pub static METRICS: PruningMetrics = PruningMetrics::new();
+
+impl PruningMetrics {
+    const fn new() -> Self {
+        Self {
+            chunks_considered: AtomicU64::new(0),
+            chunks_pruned: AtomicU64::new(0),
+        }
+    }
+
+    /// Records that `considered` chunks were checked against a predicate,
+    /// of which `pruned` were skipped
+    pub fn record(&self, considered: usize, pruned: usize) {
+        self.chunks_considered
+            .fetch_add(considered as u64, Ordering::Relaxed);
+        self.chunks_pruned
+            .fetch_add(pruned as u64, Ordering::Relaxed);
+    }
+
+    /// The number of chunks checked against a predicate so far
+    pub fn chunks_considered(&self) -> u64 {
+        self.chunks_considered.load(Ordering::Relaxed)
+    }
+
+    /// The number of chunks skipped so far
+    pub fn chunks_pruned(&self) -> u64 {
+        self.chunks_pruned.load(Ordering::Relaxed)
+    }
+}
+
+/// Returns false if the statistics in `summary` show that no row of the
+/// table can pass `predicate`, true if some might
+pub fn could_pass(summary: &TableSummary, predicate: &Predicate) -> bool {
+    if !predicate.should_include_table(&summary.name) {
+        return false;
+    }
+
+    if let Some(range) = predicate.range {
+        if !could_pass_range(summary, range) {
+            return false;
+        }
+    }
+
+    predicate
+        .exprs
+        .iter()
+        .all(|expr| could_pass_expr(summary, expr))
+}
+
+/// Returns a summary of a table with only a time column, ranging from
+/// `min` to `max`, for chunks whose only statistics are their time range
+pub fn time_range_summary(table_name: impl Into<String>, min: i64, max: i64) -> TableSummary {
+    let mut summary = TableSummary::new(table_name);
+    summary.columns.push(ColumnSummary {
+        name: TIME_COLUMN_NAME.to_string(),
+        stats: Statistics::I64(StatValues { min, max, count: 0 }),
+    });
+    summary
+}
+
+fn could_pass_range(summary: &TableSummary, range: TimestampRange) -> bool {
+    match summary.column(TIME_COLUMN_NAME).map(|c| &c.stats) {
+        Some(Statistics::I64(stats)) => stats.min < range.end && stats.max >= range.start,
+        _ => true,
+    }
+}
+
+fn could_pass_expr(summary: &TableSummary, expr: &Expr) -> bool {
+    match expr {
+        Expr::BinaryExpr {
+            left,
+            op: Operator::And,
+            right,
+        } => could_pass_expr(summary, left) && could_pass_expr(summary, right),
+        Expr::BinaryExpr {
+            left,
+            op: Operator::Or,
+            right,
+        } => could_pass_expr(summary, left) || could_pass_expr(summary, right),
+        Expr::BinaryExpr { left, op, right } => match (left.as_ref(), right.as_ref()) {
+            (Expr::Column(column), Expr::Literal(value)) => {
+                could_pass_comparison(summary, column, *op, value)
+            }
+            // `5 < x` is `x > 5`
+            (Expr::Literal(value), Expr::Column(column)) => match flip(*op) {
+                Some(op) => could_pass_comparison(summary, column, op, value),
+                None => true,
+            },
+            _ => true,
+        },
+        _ => true,
+    }
+}
+
+/// Returns the operator giving the same result with its operands swapped
+pub(crate) fn flip(op: Operator) -> Option<Operator> {
+    match op {
+        Operator::Eq => Some(Operator::Eq),
+        Operator::NotEq => Some(Operator::NotEq),
+        Operator::Lt => Some(Operator::Gt),
+        Operator::LtEq => Some(Operator::GtEq),
+        Operator::Gt => Some(Operator::Lt),
+        Operator::GtEq => Some(Operator::LtEq),
+        _ => None,
+    }
+}
+
+/// Returns false if no value of `column` can satisfy `column op value`
+fn could_pass_comparison(
+    summary: &TableSummary,
+    column: &str,
+    op: Operator,
+    value: &ScalarValue,
+) -> bool {
+    let stats = match summary.column(column) {
+        Some(column) => &column.stats,
+        None => return true,
+    };
+
+    match (stats, value) {
+        (Statistics::I64(s), ScalarValue::Int64(Some(v))) => {
+            could_pass_values(&s.min, &s.max, op, v)
+        }
+        (Statistics::U64(s), ScalarValue::UInt64(Some(v))) => {
+            could_pass_values(&s.min, &s.max, op, v)
+        }
+        // NaN doesn't order, so statistics including it can't prune
+        (Statistics::F64(s), ScalarValue::Float64(Some(v)))
+            if !s.min.is_nan() && !s.max.is_nan() =>
+        {
+            could_pass_values(&s.min, &s.max, op, v)
+        }
+        (Statistics::Bool(s), ScalarValue::Boolean(Some(v))) => {
+            could_pass_values(&s.min, &s.max, op, v)
+        }
+        (Statistics::String(s), ScalarValue::Utf8(Some(v))) => {
+            could_pass_values(&s.min.as_str(), &s.max.as_str(), op, &v.as_str())
+        }
+        _ => true,
+    }
+}
+
+/// Returns false if no value `x` between `min` and `max` can satisfy
+/// `x op v`
+fn could_pass_values<T: PartialOrd>(min: &T, max: &T, op: Operator, v: &T) -> bool {
+    match op {
+        Operator::Eq => min <= v && v <= max,
+        Operator::NotEq => !(min == v && max == v),
+        Operator::Lt => min < v,
+        Operator::LtEq => min <= v,
+        Operator::Gt => max > v,
+        Operator::GtEq => max >= v,
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::predicate::PredicateBuilder;
+    use arrow_deps::datafusion::prelude::{col, lit};
+
+    fn summary() -> TableSummary {
+        let mut summary = time_range_summary("cpu", 100, 200);
+        summary.columns.push(ColumnSummary {
+            name: "host".to_string(),
+            stats: Statistics::String(StatValues {
+                min: "a".to_string(),
+                max: "c".to_string(),
+                count: 3,
+            }),
+        });
+        summary.columns.push(ColumnSummary {
+            name: "usage".to_string(),
+            stats: Statistics::F64(StatValues {
+                min: 1.0,
+                max: 2.0,
+                count: 3,
+            }),
+        });
+        summary
+    }
+
+    fn passes(predicate: PredicateBuilder) -> bool {
+        could_pass(&summary(), &predicate.build())
+    }
+
+    #[test]
+    fn time_range() {
+        assert!(passes(PredicateBuilder::new()));
+        assert!(passes(PredicateBuilder::new().timestamp_range(200, 300)));
+        assert!(passes(PredicateBuilder::new().timestamp_range(0, 101)));
+        assert!(!passes(PredicateBuilder::new().timestamp_range(201, 300)));
+        // the end of the range is exclusive
+        assert!(!passes(PredicateBuilder::new().timestamp_range(0, 100)));
+    }
+
+    #[test]
+    fn tables() {
+        assert!(passes(PredicateBuilder::new().table("cpu")));
+        assert!(!passes(PredicateBuilder::new().table("mem")));
+    }
+
+    #[test]
+    fn comparisons() {
+        let passes_expr = |expr: Expr| passes(PredicateBuilder::new().add_expr(expr));
+
+        assert!(passes_expr(col("host").eq(lit("b"))));
+        assert!(!passes_expr(col("host").eq(lit("d"))));
+        assert!(passes_expr(col("host").not_eq(lit("a"))));
+        assert!(!passes_expr(col("usage").gt(lit(2.0))));
+        assert!(passes_expr(col("usage").gt_eq(lit(2.0))));
+        assert!(!passes_expr(col("time").lt(lit(100i64))));
+        assert!(passes_expr(col("time").lt_eq(lit(100i64))));
+        // literal on the left
+        assert!(!passes_expr(lit(100i64).gt(col("time"))));
+
+        assert!(!passes_expr(
+            col("host").eq(lit("b")).and(col("usage").lt(lit(1.0)))
+        ));
+        assert!(passes_expr(
+            col("host").eq(lit("d")).or(col("usage").lt_eq(lit(1.0)))
+        ));
+
+        // unknown columns, mismatched types and other expressions can't prune
+        assert!(passes_expr(col("region").eq(lit("d"))));
+        assert!(passes_expr(col("usage").gt(lit(5))));
+        assert!(passes_expr(Expr::Not(Box::new(col("host").eq(lit("d"))))));
+    }
+}
@@ -6,6 +6,7 @@ use rand::prelude::*;
 use arrow_deps::arrow::datatypes::*;
 use read_buffer::benchmarks::Fixed;
 use read_buffer::benchmarks::FixedNull;
+use read_buffer::benchmarks::{Operator, RowIDs};
 
 const ROWS: [usize; 5] = [10, 100, 1_000, 10_000, 60_000];
 const CHUNKS: [Chunks; 4] = [
@@ -220,6 +221,41 @@ fn benchmark_plain_sum(
     group.finish();
 }
 
+fn encoding_row_ids_filter(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encoding_fixed_row_ids_filter");
+    for &num_rows in &ROWS {
+        // random values, so matching rows come in short runs
+        let mut rnd = thread_rng();
+        let values: Vec<i64> = (0..num_rows).map(|_| rnd.gen_range(0, 100)).collect();
+        let encoding = Fixed::<i64>::from(values.as_slice());
+
+        group.throughput(Throughput::Bytes((num_rows * size_of::<i64>()) as u64));
+        for (name, op) in &[("eq", Operator::Equal), ("gt", Operator::GT)] {
+            group.bench_with_input(
+                BenchmarkId::from_parameter(format!("{:?}_{}", num_rows, name)),
+                op,
+                |b, op| {
+                    b.iter(|| encoding.row_ids_filter(50, op, RowIDs::new_vector()));
+                },
+            );
+        }
+        group.bench_with_input(
+            BenchmarkId::from_parameter(format!("{:?}_range", num_rows)),
+            &num_rows,
+            |b, _| {
+                b.iter(|| {
+                    encoding.row_ids_filter_range(
+                        (25, &Operator::GTE),
+                        (75, &Operator::LT),
+                        RowIDs::new_vector(),
+                    )
+                });
+            },
+        );
+    }
+    group.finish();
+}
+
 // results in about 50% rows being requested.
 fn gen_even_chunk(rows: usize) -> Vec<u32> {
     (0..rows as u32).filter(|x| x % 2 == 0).collect()
@@ -265,5 +301,5 @@ fn gen_random_10_percent(rows: usize) -> Vec<u32> {
     input
 }
 
-criterion_group!(benches, encoding_sum,);
+criterion_group!(benches, encoding_sum, encoding_row_ids_filter,);
 criterion_main!(benches);
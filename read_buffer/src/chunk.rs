@@ -121,6 +121,21 @@ impl Chunk {
             .contains_key(table_name)
     }
 
+    /// The minimum and maximum timestamps across all tables in this chunk,
+    /// or `None` if the chunk has no tables.
+    pub fn time_range(&self) -> Option<(i64, i64)> {
+        self.chunk_data
+            .read()
+            .unwrap()
+            .data
+            .values()
+            .filter_map(|table| table.time_range())
+            .fold(None, |range, (min, max)| match range {
+                Some((range_min, range_max)) => Some((min.min(range_min), max.max(range_max))),
+                None => Some((min, max)),
+            })
+    }
+
     /// Returns true if there are no tables under this chunk.
     pub fn is_empty(&self) -> bool {
         self.chunk_data.read().unwrap().data.len() == 0
@@ -384,6 +399,30 @@ mod test {
         assert_eq!(chunk.tables(), 0);
     }
 
+    #[test]
+    fn time_range() {
+        let columns = vec![("time", ColumnType::create_time(&[1_i64, 2, 3, 4, 5, 6]))]
+            .into_iter()
+            .map(|(k, v)| (k.to_owned(), v))
+            .collect();
+        let rg = RowGroup::new(6, columns);
+        let table = Table::new("table_1", rg);
+        let mut chunk = Chunk::new(22, table);
+        assert_eq!(chunk.time_range(), Some((1, 6)));
+
+        let columns = vec![("time", ColumnType::create_time(&[-3_i64, 2]))]
+            .into_iter()
+            .map(|(k, v)| (k.to_owned(), v))
+            .collect();
+        let rg = RowGroup::new(2, columns);
+        chunk.upsert_table("table_2", rg);
+        assert_eq!(chunk.time_range(), Some((-3, 6)));
+
+        chunk.drop_table("table_1");
+        chunk.drop_table("table_2");
+        assert_eq!(chunk.time_range(), None);
+    }
+
     #[test]
     fn table_names() {
         let columns = vec![
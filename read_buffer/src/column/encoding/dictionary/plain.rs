@@ -1,10 +1,6 @@
-#[cfg(all(
-    any(target_arch = "x86", target_arch = "x86_64"),
-    target_feature = "avx2"
-))]
 #[cfg(target_arch = "x86")]
 use std::arch::x86::*;
-#[cfg(all(target_arch = "x86_64", target_feature = "avx2"))]
+#[cfg(target_arch = "x86_64")]
 use std::arch::x86_64::*;
 
 use std::collections::BTreeSet;
@@ -186,19 +182,17 @@ impl Plain {
             // N.B(edd): this is specifically split out like this
             // (with the duplication on the looping) so that a specialised
             // function with some SIMD intrinsics can be used for the more
-            // common == case.
+            // common == case, when the CPU supports them.
             match op {
                 cmp::Operator::Equal => {
-                    #[cfg(all(
-                        any(target_arch = "x86", target_arch = "x86_64"),
-                        target_feature = "avx2"
-                    ))]
-                    return self.row_ids_equal_simd(encoded_id, dst);
-
-                    #[cfg(any(
-                        not(any(target_arch = "x86", target_arch = "x86_64")),
-                        not(target_feature = "avx2")
-                    ))]
+                    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+                    {
+                        if is_x86_feature_detected!("avx2") {
+                            // Safety: the CPU supports AVX2
+                            return unsafe { self.row_ids_equal_simd(encoded_id, dst) };
+                        }
+                    }
+
                     for (i, next) in self.encoded_data.iter().enumerate() {
                         if next == &encoded_id {
                             // N.B(edd): adding individual values like this to a bitset
@@ -235,12 +229,9 @@ impl Plain {
         dst
     }
 
-    #[cfg(all(
-        any(target_arch = "x86", target_arch = "x86_64"),
-        target_feature = "avx2"
-    ))]
     // An optimised method for finding all row ids within the column encoding
-    // where the row contains the provided encoded id.
+    // where the row contains the provided encoded id. It must only be called
+    // when the CPU supports AVX2.
     //
     // This approach uses SIMD intrinsics to compare two registers with eight
     // lanes in parallel using a single CPU instruction. One register is packed
@@ -256,45 +247,45 @@ impl Plain {
     //
     // For cases where one or more of the eight values does match the encoded
     // id the exact matching row(s) is/are determined by examining the register.
-    fn row_ids_equal_simd(&self, encoded_id: u32, mut dst: RowIDs) -> RowIDs {
-        unsafe {
-            // Pack an 8-lane register containing the encoded id we want to find.
-            let id_register = _mm256_set1_epi32(encoded_id as i32);
-
-            let mut i = 0_u32; // Track the total index.
-            self.encoded_data.chunks_exact(8).for_each(|chunk| {
-                // Load the entries we want to compare against into a SIMD register.
-                let entries = _mm256_loadu_si256(chunk.as_ptr() as *const __m256i);
-
-                // Compares the 32-bit packed values and returns a register
-                // with the comparison results.
-                let res = _mm256_cmpeq_epi32(entries, id_register);
-
-                // Since we only care about determining if every result is 0
-                // we can just unpack into two 128-bit integers and check they're
-                // both zero.
-                let (a, b) = std::mem::transmute::<_, (u128, u128)>(res);
-                if a != 0 || b != 0 {
-                    // slow path
-                    // One or more of the rows checked contains the encoded id.
-                    for (j, v) in chunk.iter().enumerate() {
-                        if v == &encoded_id {
-                            dst.add(i + j as u32);
-                        }
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    #[target_feature(enable = "avx2")]
+    unsafe fn row_ids_equal_simd(&self, encoded_id: u32, mut dst: RowIDs) -> RowIDs {
+        // Pack an 8-lane register containing the encoded id we want to find.
+        let id_register = _mm256_set1_epi32(encoded_id as i32);
+
+        let mut i = 0_u32; // Track the total index.
+        for chunk in self.encoded_data.chunks_exact(8) {
+            // Load the entries we want to compare against into a SIMD register.
+            let entries = _mm256_loadu_si256(chunk.as_ptr() as *const __m256i);
+
+            // Compares the 32-bit packed values and returns a register
+            // with the comparison results.
+            let res = _mm256_cmpeq_epi32(entries, id_register);
+
+            // Since we only care about determining if every result is 0
+            // we can just unpack into two 128-bit integers and check they're
+            // both zero.
+            let (a, b) = std::mem::transmute::<_, (u128, u128)>(res);
+            if a != 0 || b != 0 {
+                // slow path
+                // One or more of the rows checked contains the encoded id.
+                for (j, v) in chunk.iter().enumerate() {
+                    if v == &encoded_id {
+                        dst.add(i + j as u32);
                     }
                 }
+            }
 
-                i += 8;
-            });
+            i += 8;
+        }
 
-            // If encoded_data.len() % 8 != 0 then there will be remaining rows
-            // to check.
-            let rem = self.encoded_data.len() % 8;
-            let all_rows = self.num_rows() as usize;
-            for i in (all_rows - rem)..all_rows {
-                if self.encoded_data[i] == encoded_id {
-                    dst.add(i as u32);
-                }
+        // If encoded_data.len() % 8 != 0 then there will be remaining rows
+        // to check.
+        let rem = self.encoded_data.len() % 8;
+        let all_rows = self.num_rows() as usize;
+        for i in (all_rows - rem)..all_rows {
+            if self.encoded_data[i] == encoded_id {
+                dst.add(i as u32);
             }
         }
         dst
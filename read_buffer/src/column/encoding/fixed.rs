@@ -302,35 +302,7 @@ where
             desired = false; // != operator
         }
 
-        let mut found = false;
-        let mut count = 0;
-        for (i, next) in self.values.iter().enumerate() {
-            let cmp_result = next == value;
-
-            if cmp_result != desired && found {
-                let (min, max) = (i as u32 - count as u32, i as u32);
-                dst.add_range(min, max);
-                found = false;
-                count = 0;
-                continue;
-            } else if cmp_result != desired {
-                continue;
-            }
-
-            if !found {
-                found = true;
-            }
-            count += 1;
-        }
-
-        // add any remaining range.
-        if found {
-            let (min, max) = (
-                (self.values.len()) as u32 - count as u32,
-                (self.values.len()) as u32,
-            );
-            dst.add_range(min, max);
-        }
+        add_matching_rows(&self.values, |next| (next == value) == desired, &mut dst);
         dst
     }
 
@@ -346,35 +318,7 @@ where
     {
         dst.clear();
 
-        let mut found = false;
-        let mut count = 0;
-        for (i, next) in self.values.iter().enumerate() {
-            let cmp_result = op(next, value);
-
-            if !cmp_result && found {
-                let (min, max) = (i as u32 - count as u32, i as u32);
-                dst.add_range(min, max);
-                found = false;
-                count = 0;
-                continue;
-            } else if !cmp_result {
-                continue;
-            }
-
-            if !found {
-                found = true;
-            }
-            count += 1;
-        }
-
-        // add any remaining range.
-        if found {
-            let (min, max) = (
-                (self.values.len()) as u32 - count as u32,
-                (self.values.len()) as u32,
-            );
-            dst.add_range(min, max);
-        }
+        add_matching_rows(&self.values, |next| op(next, value), &mut dst);
         dst
     }
 
@@ -438,42 +382,100 @@ where
         let left_op = left.1;
         let right_op = right.1;
 
-        let mut found = false;
-        let mut count = 0;
-        for (i, next) in self.values.iter().enumerate() {
-            let left_cmp_result = next.partial_cmp(left.0);
-            let right_cmp_result = next.partial_cmp(right.0);
-
-            let left_result_no =
-                left_cmp_result != Some(left_op.0) && left_cmp_result != Some(left_op.1);
-            let right_result_no =
-                right_cmp_result != Some(right_op.0) && right_cmp_result != Some(right_op.1);
-
-            if (left_result_no || right_result_no) && found {
-                let (min, max) = (i as u32 - count as u32, i as u32);
-                dst.add_range(min, max);
-                found = false;
-                count = 0;
-                continue;
-            } else if left_result_no || right_result_no {
-                continue;
-            }
+        add_matching_rows(
+            &self.values,
+            |next| {
+                let left_cmp_result = next.partial_cmp(left.0);
+                let right_cmp_result = next.partial_cmp(right.0);
 
-            if !found {
-                found = true;
-            }
-            count += 1;
+                let left_result_no =
+                    left_cmp_result != Some(left_op.0) && left_cmp_result != Some(left_op.1);
+                let right_result_no =
+                    right_cmp_result != Some(right_op.0) && right_cmp_result != Some(right_op.1);
+
+                !(left_result_no || right_result_no)
+            },
+            &mut dst,
+        );
+        dst
+    }
+}
+
+// The number of rows whose predicate results are packed into a bitmask at
+// once by `add_matching_rows`.
+const MASK_ROWS: usize = 64;
+
+// Adds the ids of the rows whose values satisfy `pred` to `dst`, collecting up
+// ranges of matching rows to add them in bulk.
+//
+// The predicate is evaluated a chunk of rows at a time into a bitmask, which
+// lets the compiler vectorize the comparisons, and the ranges of matching rows
+// are then read off the bitmask. A build of it using AVX2 instructions is used
+// when the CPU supports them.
+fn add_matching_rows<T, F>(values: &[T], pred: F, dst: &mut RowIDs)
+where
+    F: Fn(&T) -> bool,
+{
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        if is_x86_feature_detected!("avx2") {
+            // Safety: the CPU supports AVX2
+            return unsafe { add_matching_rows_avx2(values, pred, dst) };
         }
+    }
+    add_matching_rows_chunked(values, pred, dst)
+}
 
-        // add any remaining range.
-        if found {
-            let (min, max) = (
-                (self.values.len()) as u32 - count as u32,
-                (self.values.len()) as u32,
-            );
-            dst.add_range(min, max);
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+unsafe fn add_matching_rows_avx2<T, F>(values: &[T], pred: F, dst: &mut RowIDs)
+where
+    F: Fn(&T) -> bool,
+{
+    add_matching_rows_chunked(values, pred, dst)
+}
+
+#[inline(always)]
+fn add_matching_rows_chunked<T, F>(values: &[T], pred: F, dst: &mut RowIDs)
+where
+    F: Fn(&T) -> bool,
+{
+    // the first row of the range of matching rows being collected, if any
+    let mut start = None;
+    for (chunk_i, chunk) in values.chunks(MASK_ROWS).enumerate() {
+        let mut mask = 0_u64;
+        for (i, next) in chunk.iter().enumerate() {
+            mask |= (pred(next) as u64) << i;
+        }
+
+        let base = (chunk_i * MASK_ROWS) as u32;
+        let len = chunk.len() as u32;
+        let mut i = 0;
+        while i < len {
+            let rest = mask >> i;
+            match start {
+                // skip to the end of the matching rows
+                Some(from) => {
+                    i += (!rest).trailing_zeros().min(len - i);
+                    if i < len {
+                        dst.add_range(from, base + i);
+                        start = None;
+                    }
+                }
+                // skip to the next matching row
+                None => {
+                    i += rest.trailing_zeros().min(len - i);
+                    if i < len {
+                        start = Some(base + i);
+                    }
+                }
+            }
         }
-        dst
+    }
+
+    // add any remaining range.
+    if let Some(from) = start {
+        dst.add_range(from, values.len() as u32);
     }
 }
 
@@ -885,4 +887,41 @@ mod test {
         );
         assert_eq!(dst.unwrap_vector(), &vec![1, 2, 4]);
     }
+
+    #[test]
+    fn row_ids_filter_many_rows() {
+        // runs of matching rows crossing the chunks the predicate is
+        // evaluated over
+        let values: Vec<i64> = (0..300).map(|i| (i / 50) % 2).collect();
+        let v: Fixed<i64> = Fixed {
+            values: values.clone(),
+        };
+        let expected = |f: fn(i64) -> bool| {
+            (0..values.len() as u32)
+                .filter(|&i| f(values[i as usize]))
+                .collect::<Vec<_>>()
+        };
+
+        let dst = v.row_ids_filter(1, &Operator::Equal, RowIDs::new_vector());
+        assert_eq!(dst.unwrap_vector(), &expected(|x| x == 1));
+
+        let dst = v.row_ids_filter(1, &Operator::NotEqual, RowIDs::new_bitmap());
+        assert_eq!(dst.to_vec(), expected(|x| x != 1));
+
+        let dst = v.row_ids_filter(0, &Operator::GT, RowIDs::new_vector());
+        assert_eq!(dst.unwrap_vector(), &expected(|x| x > 0));
+
+        let dst = v.row_ids_filter_range(
+            (0, &Operator::GTE),
+            (1, &Operator::LT),
+            RowIDs::new_vector(),
+        );
+        assert_eq!(dst.unwrap_vector(), &expected(|x| x == 0));
+
+        let dst = v.row_ids_filter(2, &Operator::Equal, RowIDs::new_vector());
+        assert!(dst.is_empty());
+
+        let dst = v.row_ids_filter(2, &Operator::LT, RowIDs::new_vector());
+        assert_eq!(dst.unwrap_vector(), &(0..300).collect::<Vec<_>>());
+    }
 }
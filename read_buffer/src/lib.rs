@@ -179,6 +179,41 @@ impl Database {
             .unwrap_or_default()
     }
 
+    /// Returns the minimum and maximum timestamps in the given chunk, or
+    /// `None` if no such chunk exists or it has no tables.
+    pub fn chunk_time_range(&self, partition_key: &str, chunk_id: u32) -> Option<(i64, i64)> {
+        self.data
+            .read()
+            .unwrap()
+            .partitions
+            .get(partition_key)
+            .and_then(|partition| partition.chunk_time_range(chunk_id))
+    }
+
+    /// Returns the number of rows in the given partition. Returns 0 if no
+    /// partition with the given key exists
+    pub fn partition_rows(&self, partition_key: &str) -> u64 {
+        self.data
+            .read()
+            .unwrap()
+            .partitions
+            .get(partition_key)
+            .map(|partition| partition.rows())
+            .unwrap_or_default()
+    }
+
+    /// Returns the estimated size in bytes of the given partition. Returns 0
+    /// if no partition with the given key exists
+    pub fn partition_size(&self, partition_key: &str) -> u64 {
+        self.data
+            .read()
+            .unwrap()
+            .partitions
+            .get(partition_key)
+            .map(|partition| partition.size())
+            .unwrap_or_default()
+    }
+
     /// Returns the total estimated size in bytes of the database.
     pub fn size(&self) -> u64 {
         let base_size = std::mem::size_of::<Self>();
@@ -592,6 +627,16 @@ impl Partition {
         self.data.read().unwrap().chunks.keys().cloned().collect()
     }
 
+    /// Return the time range of the specified chunk, if it exists
+    fn chunk_time_range(&self, chunk_id: u32) -> Option<(i64, i64)> {
+        self.data
+            .read()
+            .unwrap()
+            .chunks
+            .get(&chunk_id)
+            .and_then(|chunk| chunk.time_range())
+    }
+
     /// Determines the total number of tables under all chunks within the
     /// partition. Useful for tests but not something that is highly performant.
     fn tables(&self) -> usize {
@@ -875,6 +920,9 @@ mod test {
             assert_eq!(partition.rows(), 12);
             assert_eq!(partition.row_groups(), 4);
         }
+        assert_eq!(db.partition_rows("hour_1"), 12);
+        assert_eq!(db.partition_rows("hour_2"), 0);
+        assert_eq!(db.partition_size("hour_2"), 0);
 
         {
             let partition_data = db.data.read().unwrap();
@@ -0,0 +1,315 @@
+//! Anti-entropy repair between the replicas of a database.
+//!
+//! Peers replicating their writes to each other hold the same databases,
+//! but a peer that was down or unreachable misses the writes accepted
+//! meanwhile, as does one a write quorum was reached without. To find and
+//! repair such divergence, each database records the writes each of its
+//! partitions holds, by the writer id and sequence number they were
+//! accepted with, which a server doesn't reuse after restarting. As a
+//! writer's sequence numbers are consecutive, those of each writer are held
+//! as ranges, so that the record stays small however many writes a
+//! partition holds. The writes of a partition are summarized as digests of
+//! the writes of each writer within ranges of `RANGE_SIZE` sequence
+//! numbers: their count and the combination of the hashes of their
+//! sequence numbers, which doesn't depend on the order writes arrived in.
+//!
+//! Each round, a server gets the digests of each of its peers and sends a
+//! peer again the writes of the ranges whose digests differ from its own,
+//! taken from its WAL buffer. A server receiving a write only stores it in
+//! the partitions not holding it yet, so resending writes is harmless, and
+//! as every server repairs its peers the writes missing on either side end
+//! up on both. Writes no longer in the WAL buffer of any peer can't be
+//! repaired.
+use data_types::data::ReplicatedWrite;
+use generated_types::influxdata::iox::cluster::v1 as proto;
+use parking_lot::Mutex;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// The number of sequence numbers of a writer summarized by one digest
+pub const RANGE_SIZE: u64 = 1000;
+
+/// The writes of one writer a partition holds within a range of sequence
+/// numbers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WriteDigest {
+    pub partition_key: String,
+    pub writer_id: u32,
+    /// The first sequence number of the range
+    pub range_start: u64,
+    /// The number of writes held within the range
+    pub count: u64,
+    /// The combined hash of the sequence numbers of the writes held
+    pub hash: u64,
+}
+
+impl WriteDigest {
+    /// The key identifying the range the digest summarizes
+    fn key(&self) -> (&str, u32, u64) {
+        (&self.partition_key, self.writer_id, self.range_start)
+    }
+}
+
+impl From<proto::WriteDigest> for WriteDigest {
+    fn from(digest: proto::WriteDigest) -> Self {
+        Self {
+            partition_key: digest.partition_key,
+            writer_id: digest.writer_id,
+            range_start: digest.range_start,
+            count: digest.count,
+            hash: digest.hash,
+        }
+    }
+}
+
+impl From<WriteDigest> for proto::WriteDigest {
+    fn from(digest: WriteDigest) -> Self {
+        Self {
+            partition_key: digest.partition_key,
+            writer_id: digest.writer_id,
+            range_start: digest.range_start,
+            count: digest.count,
+            hash: digest.hash,
+        }
+    }
+}
+
+/// A range of sequence numbers of a writer to repair in a partition
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Divergence {
+    pub partition_key: String,
+    pub writer_id: u32,
+    pub range_start: u64,
+}
+
+impl Divergence {
+    /// Whether the write `writer_id`/`sequence` falls within the range
+    pub fn contains(&self, writer_id: u32, sequence: u64) -> bool {
+        self.writer_id == writer_id
+            && (self.range_start..self.range_start + RANGE_SIZE).contains(&sequence)
+    }
+}
+
+/// The writes each partition of a database holds, as described in the
+/// module docs
+#[derive(Debug, Default)]
+pub struct WriteLog {
+    /// The sequence numbers of the writes of each writer, by partition
+    partitions: Mutex<BTreeMap<String, BTreeMap<u32, Sequences>>>,
+}
+
+impl WriteLog {
+    /// Returns the partitions among `partition_keys` not holding the write
+    /// `writer_id`/`sequence`
+    pub fn missing<'a>(
+        &self,
+        writer_id: u32,
+        sequence: u64,
+        partition_keys: impl IntoIterator<Item = &'a str>,
+    ) -> BTreeSet<String> {
+        let partitions = self.partitions.lock();
+        partition_keys
+            .into_iter()
+            .filter(|key| {
+                partitions
+                    .get(*key)
+                    .and_then(|writers| writers.get(&writer_id))
+                    .map_or(true, |sequences| !sequences.contains(sequence))
+            })
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    /// Records that the partitions `partition_keys` hold the write
+    /// `writer_id`/`sequence`
+    pub fn record<'a>(
+        &self,
+        writer_id: u32,
+        sequence: u64,
+        partition_keys: impl IntoIterator<Item = &'a str>,
+    ) {
+        let mut partitions = self.partitions.lock();
+        for key in partition_keys {
+            partitions
+                .entry(key.to_string())
+                .or_default()
+                .entry(writer_id)
+                .or_default()
+                .insert(sequence);
+        }
+    }
+
+    /// Forgets the partitions not among `partition_keys`, e.g. once their
+    /// data has been dropped
+    pub fn retain(&self, partition_keys: &[String]) {
+        self.partitions
+            .lock()
+            .retain(|key, _| partition_keys.contains(key));
+    }
+
+    /// Returns the digests of the writes of every partition
+    pub fn digests(&self) -> Vec<WriteDigest> {
+        let partitions = self.partitions.lock();
+        let mut digests = vec![];
+        for (partition_key, writers) in partitions.iter() {
+            let mut ranges: BTreeMap<(u32, u64), (u64, u64)> = BTreeMap::new();
+            for (writer_id, sequences) in writers {
+                for sequence in sequences.iter() {
+                    let range = ranges
+                        .entry((*writer_id, sequence - sequence % RANGE_SIZE))
+                        .or_default();
+                    range.0 += 1;
+                    range.1 ^= hash(sequence);
+                }
+            }
+            digests.extend(
+                ranges
+                    .into_iter()
+                    .map(|((writer_id, range_start), (count, hash))| WriteDigest {
+                        partition_key: partition_key.clone(),
+                        writer_id,
+                        range_start,
+                        count,
+                        hash,
+                    }),
+            );
+        }
+        digests
+    }
+}
+
+/// A set of sequence numbers, held as the ranges of consecutive ones
+#[derive(Debug, Default)]
+struct Sequences {
+    /// The last sequence number of each range, by its first
+    ranges: BTreeMap<u64, u64>,
+}
+
+impl Sequences {
+    fn contains(&self, sequence: u64) -> bool {
+        self.ranges
+            .range(..=sequence)
+            .next_back()
+            .map_or(false, |(_, last)| *last >= sequence)
+    }
+
+    fn insert(&mut self, sequence: u64) {
+        if self.contains(sequence) {
+            return;
+        }
+
+        // joins the ranges ending just before and starting just after it
+        let first = match self.ranges.range(..sequence).next_back() {
+            Some((first, last)) if *last + 1 == sequence => *first,
+            _ => sequence,
+        };
+        let last = sequence
+            .checked_add(1)
+            .and_then(|next| self.ranges.remove(&next))
+            .unwrap_or(sequence);
+        self.ranges.insert(first, last);
+    }
+
+    fn iter(&self) -> impl Iterator<Item = u64> + '_ {
+        self.ranges.iter().flat_map(|(first, last)| *first..=*last)
+    }
+}
+
+/// Returns the ranges of `local` digests that differ from or are missing
+/// in the `remote` digests of a peer
+pub fn divergences(local: &[WriteDigest], remote: &[WriteDigest]) -> Vec<Divergence> {
+    let remote: BTreeMap<_, _> = remote
+        .iter()
+        .map(|digest| (digest.key(), (digest.count, digest.hash)))
+        .collect();
+    local
+        .iter()
+        .filter(|digest| remote.get(&digest.key()) != Some(&(digest.count, digest.hash)))
+        .map(|digest| Divergence {
+            partition_key: digest.partition_key.clone(),
+            writer_id: digest.writer_id,
+            range_start: digest.range_start,
+        })
+        .collect()
+}
+
+/// Returns the keys of the partitions `write` has entries for
+pub fn partition_keys(write: &ReplicatedWrite) -> BTreeSet<String> {
+    write
+        .write_buffer_batch()
+        .and_then(|batch| batch.entries())
+        .map(|entries| {
+            entries
+                .iter()
+                .filter_map(|entry| entry.partition_key())
+                .map(ToString::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Hashes a sequence number, the same way on every server (splitmix64)
+fn hash(sequence: u64) -> u64 {
+    let mut z = sequence.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn digests() {
+        let local = WriteLog::default();
+        local.record(1, 1, vec!["p1", "p2"]);
+        local.record(1, 2, vec!["p1"]);
+        local.record(1, 1500, vec!["p1"]);
+        local.record(2, 7, vec!["p2"]);
+        assert_eq!(
+            local.missing(1, 2, vec!["p1", "p2", "p3"]),
+            vec!["p2".to_string(), "p3".to_string()]
+                .into_iter()
+                .collect()
+        );
+
+        let digests = local.digests();
+        assert_eq!(digests.len(), 4);
+        assert_eq!(digests[0].count, 2);
+        assert_eq!(digests[1].range_start, 1000);
+
+        // writes arriving in a different order have the same digests
+        let remote = WriteLog::default();
+        remote.record(1, 1500, vec!["p1"]);
+        remote.record(2, 7, vec!["p2"]);
+        remote.record(1, 2, vec!["p1"]);
+        remote.record(1, 1, vec!["p2"]);
+        assert_eq!(
+            divergences(&local.digests(), &remote.digests()),
+            vec![Divergence {
+                partition_key: "p1".to_string(),
+                writer_id: 1,
+                range_start: 0
+            }]
+        );
+        // the peer finds the same range diverging
+        assert_eq!(divergences(&remote.digests(), &local.digests()).len(), 1);
+
+        remote.record(1, 1, vec!["p1"]);
+        assert!(divergences(&local.digests(), &remote.digests()).is_empty());
+
+        // consecutive writes are held as one range
+        for sequence in 3..1500 {
+            local.record(1, sequence, vec!["p1"]);
+        }
+        assert_eq!(local.partitions.lock()["p1"][&1].ranges.len(), 1);
+        let counts: Vec<_> = local.digests().iter().map(|d| d.count).collect();
+        assert_eq!(counts[..2], [999, 501]);
+        assert!(local.missing(1, 1501, vec!["p1"]).contains("p1"));
+
+        local.retain(&["p2".to_string()]);
+        assert_eq!(local.digests().len(), 2);
+        let proto = proto::WriteDigest::from(digests[0].clone());
+        assert_eq!(WriteDigest::from(proto), digests[0]);
+    }
+}
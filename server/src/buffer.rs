@@ -15,7 +15,10 @@ use std::{
     sync::Arc,
 };
 
-use crate::tracker::{TrackedFutureExt, TrackerRegistry};
+use crate::{
+    encryption::{self, Encryption},
+    tracker::{TrackedFutureExt, TrackerRegistry},
+};
 use bytes::Bytes;
 use chrono::{DateTime, Utc};
 use crc32fast::Hasher;
@@ -71,6 +74,25 @@ pub enum Error {
 
     #[snafu(display("the flatbuffers Segment is invalid"))]
     InvalidFlatbuffersSegment,
+
+    #[snafu(display(
+        "writes from sequence {} are no longer in the buffer, oldest available sequence is {}",
+        sequence,
+        oldest_sequence
+    ))]
+    SequenceDropped { sequence: u64, oldest_sequence: u64 },
+
+    #[snafu(display("unable to persist segment id {}: {}", segment_id, source))]
+    UnableToPersistSegment {
+        segment_id: u64,
+        source: object_store::Error,
+    },
+
+    #[snafu(display("unable to encrypt segment id {}: {}", segment_id, source))]
+    UnableToEncryptSegment {
+        segment_id: u64,
+        source: encryption::Error,
+    },
 }
 
 #[derive(Debug, Clone)]
@@ -83,6 +105,11 @@ pub type Result<T, E = Error> = std::result::Result<T, E>;
 
 /// An in-memory buffer of a write ahead log. It is split up into segments,
 /// which can be persisted to object storage.
+///
+/// Every write accepted by the buffer is assigned a buffer-wide sequence
+/// number, starting at 1 and increasing by one per write, so that
+/// consumers (see [`BufferConsumer`]) can follow the stream of writes in
+/// the order they were accepted.
 #[derive(Debug)]
 pub struct Buffer {
     max_size: u64,
@@ -106,7 +133,7 @@ impl Buffer {
             segment_size,
             persist,
             rollover_behavior,
-            open_segment: Segment::new(1),
+            open_segment: Segment::new(1, FIRST_SEQUENCE),
             current_size: 0,
             closed_segments: vec![],
         }
@@ -161,22 +188,102 @@ impl Buffer {
         self.current_size += write_size;
         self.open_segment.append(write)?;
         if self.open_segment.size > self.segment_size {
-            let next_id = self.open_segment.id + 1;
-            let segment = mem::replace(&mut self.open_segment, Segment::new(next_id));
-            let segment = Arc::new(segment);
-
-            self.closed_segments.push(Arc::clone(&segment));
-            closed_segment = Some(segment);
+            closed_segment = self.close_open_segment();
         }
 
         Ok(closed_segment)
     }
 
+    /// Closes the open segment, if it holds any writes, so that it can be
+    /// persisted before it fills up (e.g. on shutdown). Returns the closed
+    /// segment.
+    pub fn close_open_segment(&mut self) -> Option<Arc<Segment>> {
+        if self.open_segment.writes.is_empty() {
+            return None;
+        }
+
+        let next_id = self.open_segment.id + 1;
+        let next_sequence = self.open_segment.next_sequence();
+        let segment = mem::replace(&mut self.open_segment, Segment::new(next_id, next_sequence));
+        let segment = Arc::new(segment);
+
+        self.closed_segments.push(Arc::clone(&segment));
+        Some(segment)
+    }
+
+    /// Whether a write of `writer_id` with the sequence number `sequence`
+    /// can be appended, i.e. the open segment holds no later write of the
+    /// writer
+    pub fn in_order(&self, writer_id: WriterId, sequence: u64) -> bool {
+        self.open_segment
+            .writers
+            .get(&writer_id)
+            .map_or(true, |summary| summary.end_sequence < sequence)
+    }
+
     /// Returns the current size of the buffer.
     pub fn size(&self) -> u64 {
         self.current_size
     }
 
+    /// Returns the sequence number assigned to the most recently accepted
+    /// write, if any write has been accepted.
+    pub fn last_sequence(&self) -> Option<u64> {
+        let next_sequence = self.open_segment.next_sequence();
+        if next_sequence == FIRST_SEQUENCE {
+            None
+        } else {
+            Some(next_sequence - 1)
+        }
+    }
+
+    /// Returns the sequence number of the oldest write still held in
+    /// memory by the buffer (or the sequence the next write will be
+    /// assigned if the buffer holds no writes).
+    pub fn oldest_sequence(&self) -> u64 {
+        self.closed_segments
+            .first()
+            .map(|s| s.first_sequence)
+            .unwrap_or(self.open_segment.first_sequence)
+    }
+
+    /// Returns up to `max` writes with a sequence number greater than or
+    /// equal to `sequence`, in sequence order. Returns an error if writes
+    /// at `sequence` have already been dropped from the buffer; if their
+    /// segments were persisted they can still be read from object storage.
+    pub fn writes_from(&self, sequence: u64, max: usize) -> Result<Vec<SequencedWrite>> {
+        let oldest_sequence = self.oldest_sequence();
+        ensure!(
+            sequence >= oldest_sequence,
+            SequenceDropped {
+                sequence,
+                oldest_sequence
+            }
+        );
+
+        let writes = self
+            .closed_segments
+            .iter()
+            .map(|s| s.as_ref())
+            .chain(std::iter::once(&self.open_segment))
+            .filter(|s| s.next_sequence() > sequence)
+            .flat_map(|s| s.sequenced_writes())
+            .skip_while(|w| w.sequence < sequence)
+            .take(max)
+            .collect();
+
+        Ok(writes)
+    }
+
+    /// Returns the writes held in memory, oldest first
+    pub fn writes(&self) -> impl Iterator<Item = &Arc<ReplicatedWrite>> + '_ {
+        self.closed_segments
+            .iter()
+            .map(|s| s.as_ref())
+            .chain(std::iter::once(&self.open_segment))
+            .flat_map(|s| s.writes.iter())
+    }
+
     /// Returns any replicated writes from the given writer ID and sequence
     /// number onward. This will include writes from other writers. The
     /// given writer ID and sequence are to identify from what point to
@@ -262,6 +369,57 @@ impl Buffer {
     }
 }
 
+/// The sequence number assigned to the first write accepted by a `Buffer`
+const FIRST_SEQUENCE: u64 = 1;
+
+/// A write accepted by a `Buffer` along with the buffer-wide sequence number
+/// that was assigned to it.
+#[derive(Debug, Clone)]
+pub struct SequencedWrite {
+    pub sequence: u64,
+    pub write: Arc<ReplicatedWrite>,
+}
+
+/// Tracks the position of a downstream consumer (replication, change
+/// data capture, subscriptions, ...) in the stream of writes accepted by a
+/// `Buffer`. Each call to `next` returns the writes the consumer has not
+/// yet seen and advances its position past them.
+#[derive(Debug, Clone, Copy)]
+pub struct BufferConsumer {
+    next_sequence: u64,
+}
+
+impl Default for BufferConsumer {
+    fn default() -> Self {
+        Self::new(FIRST_SEQUENCE)
+    }
+}
+
+impl BufferConsumer {
+    /// Creates a consumer that will start reading at `next_sequence`
+    pub fn new(next_sequence: u64) -> Self {
+        Self { next_sequence }
+    }
+
+    /// Returns the sequence number of the next write this consumer will read
+    pub fn next_sequence(&self) -> u64 {
+        self.next_sequence
+    }
+
+    /// Returns up to `max` writes from `buffer` that this consumer has not
+    /// yet read. Returns an error if the consumer has fallen so far behind
+    /// that the writes it needs were dropped from the buffer, in which case
+    /// its position is left unchanged.
+    pub fn next(&mut self, buffer: &Buffer, max: usize) -> Result<Vec<SequencedWrite>> {
+        let writes = buffer.writes_from(self.next_sequence, max)?;
+        if let Some(last) = writes.last() {
+            self.next_sequence = last.sequence + 1;
+        }
+
+        Ok(writes)
+    }
+}
+
 impl From<&WalBufferConfig> for Buffer {
     fn from(config: &WalBufferConfig) -> Self {
         Self::new(
@@ -279,6 +437,9 @@ impl From<&WalBufferConfig> for Buffer {
 pub struct Segment {
     pub(crate) id: u64,
     size: u64,
+    // Buffer sequence number of the first write in this segment. The write
+    // at index i in `writes` has sequence number `first_sequence + i`
+    first_sequence: u64,
     pub writes: Vec<Arc<ReplicatedWrite>>,
     writers: BTreeMap<WriterId, WriterSummary>,
     // Time this segment was initialized
@@ -288,10 +449,11 @@ pub struct Segment {
 }
 
 impl Segment {
-    fn new(id: u64) -> Self {
+    fn new(id: u64, first_sequence: u64) -> Self {
         Self {
             id,
             size: 0,
+            first_sequence,
             writes: vec![],
             writers: BTreeMap::new(),
             created_at: Utc::now(),
@@ -299,10 +461,11 @@ impl Segment {
         }
     }
 
-    fn new_with_capacity(id: u64, capacity: usize) -> Self {
+    fn new_with_capacity(id: u64, first_sequence: u64, capacity: usize) -> Self {
         Self {
             id,
             size: 0,
+            first_sequence,
             writes: Vec::with_capacity(capacity),
             writers: BTreeMap::new(),
             created_at: Utc::now(),
@@ -324,6 +487,23 @@ impl Segment {
         Ok(())
     }
 
+    /// Returns the buffer sequence number the next write appended to this
+    /// segment will be assigned
+    pub fn next_sequence(&self) -> u64 {
+        self.first_sequence + self.writes.len() as u64
+    }
+
+    /// Returns the writes in this segment along with their buffer sequence
+    /// numbers
+    pub fn sequenced_writes(&self) -> impl Iterator<Item = SequencedWrite> + '_ {
+        (self.first_sequence..)
+            .zip(self.writes.iter())
+            .map(|(sequence, write)| SequencedWrite {
+                sequence,
+                write: Arc::clone(write),
+            })
+    }
+
     // checks that the sequence numbers in this segment are monotonically
     // increasing. Also keeps track of the starting and ending sequence numbers
     // and if any were missing.
@@ -373,28 +553,43 @@ impl Segment {
     }
 
     /// Spawns a tokio task that will continuously try to persist the bytes to
-    /// the given object store location.
+    /// the given object store location, encrypted with `encryption` if set.
     pub fn persist_bytes_in_background(
         &self,
         reg: &TrackerRegistry<SegmentPersistenceTask>,
         writer_id: u32,
         db_name: &DatabaseName<'_>,
         store: Arc<ObjectStore>,
+        encryption: Option<Arc<Encryption>>,
     ) -> Result<()> {
         let data = self.to_file_bytes(writer_id)?;
         let location = database_object_store_path(writer_id, db_name, &store);
         let location = object_store_path_for_segment(&location, self.id)?;
+        let segment_id = self.id;
 
         let task_meta = SegmentPersistenceTask {
             writer_id,
             location: location.clone(),
         };
 
-        let len = data.len();
-        let mut stream_data = std::io::Result::Ok(data.clone());
-
         tokio::task::spawn(
             async move {
+                let data = loop {
+                    match encryption::encrypt_if_enabled(encryption.as_deref(), data.clone()).await
+                    {
+                        Ok(data) => break data,
+                        Err(err) => {
+                            error!("error encrypting segment id {}: {}", segment_id, err);
+                            tokio::time::sleep(tokio::time::Duration::from_secs(
+                                super::STORE_ERROR_PAUSE_SECONDS,
+                            ))
+                            .await;
+                        }
+                    }
+                };
+                let len = data.len();
+                let mut stream_data = std::io::Result::Ok(data.clone());
+
                 while let Err(err) = store
                     .put(
                         &location,
@@ -420,6 +615,48 @@ impl Segment {
         Ok(())
     }
 
+    /// Writes the segment to the given object store location, encrypted
+    /// with `encryption` if set, waiting for the write to complete. Unlike
+    /// `persist_bytes_in_background` this makes a single attempt and returns
+    /// any error to the caller.
+    pub async fn persist_bytes(
+        &self,
+        writer_id: u32,
+        db_name: &DatabaseName<'_>,
+        store: &ObjectStore,
+        encryption: Option<&Encryption>,
+    ) -> Result<()> {
+        let data = self.to_file_bytes(writer_id)?;
+        let data = encryption::encrypt_if_enabled(encryption, data)
+            .await
+            .context(UnableToEncryptSegment {
+                segment_id: self.id,
+            })?;
+        let location = database_object_store_path(writer_id, db_name, store);
+        let location = object_store_path_for_segment(&location, self.id)?;
+
+        let len = data.len();
+        let stream_data = std::io::Result::Ok(data);
+        store
+            .put(
+                &location,
+                futures::stream::once(async move { stream_data }),
+                Some(len),
+            )
+            .await
+            .context(UnableToPersistSegment {
+                segment_id: self.id,
+            })?;
+
+        self.set_persisted(SegmentPersistence {
+            location: location.display().to_string(),
+            time: Utc::now(),
+        });
+        info!("persisted data to {}", location.display());
+
+        Ok(())
+    }
+
     /// converts the segment to its flatbuffer bytes
     fn fb_bytes(&self, writer_id: u32) -> Vec<u8> {
         let mut fbb = flatbuffers::FlatBufferBuilder::new_with_capacity(
@@ -446,6 +683,7 @@ impl Segment {
                 id: self.id,
                 writer_id,
                 writes: Some(writes),
+                first_sequence: self.first_sequence,
             },
         );
 
@@ -515,7 +753,8 @@ impl Segment {
         let fb_segment = flatbuffers::get_root::<wal::Segment<'_>>(&data);
 
         let writes = fb_segment.writes().context(InvalidFlatbuffersSegment)?;
-        let mut segment = Self::new_with_capacity(fb_segment.id(), writes.len());
+        let mut segment =
+            Self::new_with_capacity(fb_segment.id(), fb_segment.first_sequence(), writes.len());
         for w in writes {
             let data = w.payload().context(InvalidFlatbuffersSegment)?;
             let rw = ReplicatedWrite {
@@ -563,6 +802,41 @@ fn object_store_path_for_segment<P: ObjectStorePath>(root_path: &P, segment_id:
     Ok(path)
 }
 
+/// Returns the id of the segment stored at `path`, or `None` if it isn't
+/// the path of a segment
+pub(crate) fn segment_id_from_path<P: ObjectStorePath>(path: &P) -> Option<u64> {
+    let path = path.display();
+    let mut parts = path.rsplit(object_store::path::DELIMITER);
+
+    let hundreds_place = parts.next()?.strip_suffix(SEGMENT_FILE_EXTENSION)?;
+    let thousands_place = parts.next()?;
+    let millions_place = parts.next()?;
+    if parts.next()? != WAL_DIR {
+        return None;
+    }
+
+    let mut segment_id = 0;
+    for place in &[millions_place, thousands_place, hundreds_place] {
+        if place.len() != 3 {
+            return None;
+        }
+        segment_id = segment_id * 1_000 + place.parse::<u64>().ok()?;
+    }
+    Some(segment_id)
+}
+
+/// Builds the path under which the WAL segments of the database
+/// `database_name` written by `writer_id` are stored
+pub(crate) fn object_store_path_for_wal(
+    writer_id: u32,
+    database_name: &DatabaseName<'_>,
+    store: &ObjectStore,
+) -> object_store::path::Path {
+    let mut path = database_object_store_path(writer_id, database_name, store);
+    path.push_dir(WAL_DIR);
+    path
+}
+
 // base location in object store for a given database name
 fn database_object_store_path(
     writer_id: u32,
@@ -579,6 +853,7 @@ fn database_object_store_path(
 mod tests {
     use super::*;
     use data_types::{data::lines_to_replicated_write, database_rules::DatabaseRules};
+    use futures::TryStreamExt;
     use influxdb_line_protocol::parse_lines;
     use object_store::memory::InMemory;
 
@@ -856,9 +1131,72 @@ mod tests {
         assert!(buf.append(write).is_err());
     }
 
+    #[test]
+    fn close_open_segment() {
+        let max = 1 << 16;
+        let segment = 1 << 16;
+        let mut buf = Buffer::new(max, segment, WalBufferRollover::ReturnError, false);
+
+        // nothing to close in an empty segment
+        assert!(buf.close_open_segment().is_none());
+
+        buf.append(lp_to_replicated_write(1, 1, "cpu val=1 10"))
+            .unwrap();
+        buf.append(lp_to_replicated_write(1, 2, "cpu val=1 10"))
+            .unwrap();
+
+        let segment = buf.close_open_segment().unwrap();
+        assert_eq!(1, segment.id);
+        assert_eq!(2, segment.writes.len());
+        assert_eq!(1, buf.closed_segments.len());
+        assert!(buf.close_open_segment().is_none());
+
+        // sequence numbers carry on in the next segment
+        buf.append(lp_to_replicated_write(1, 3, "cpu val=1 10"))
+            .unwrap();
+        assert_eq!(2, buf.open_segment.id);
+        assert_eq!(Some(3), buf.last_sequence());
+    }
+
+    #[tokio::test]
+    async fn segment_persist_bytes() {
+        let store = ObjectStore::new_in_memory(InMemory::new());
+        let db_name = DatabaseName::new("mydb").unwrap();
+
+        let mut segment = Segment::new(23, 1);
+        segment
+            .append(lp_to_replicated_write(1, 1, "cpu val=1 10"))
+            .unwrap();
+        assert!(segment.persisted().is_none());
+
+        segment
+            .persist_bytes(1, &db_name, &store, None)
+            .await
+            .unwrap();
+
+        let mut path = store.new_path();
+        path.push_all_dirs(&["1", "mydb", "wal", "000", "000"]);
+        path.set_file_name("023.segment");
+        assert_eq!(
+            segment.persisted().unwrap().location,
+            path.display().to_string()
+        );
+
+        let data = store
+            .get(&path)
+            .await
+            .unwrap()
+            .map_ok(|b| bytes::BytesMut::from(&b[..]))
+            .try_concat()
+            .await
+            .unwrap();
+        let recovered_segment = Segment::from_file_bytes(&data).unwrap();
+        assert_eq!(segment.writes, recovered_segment.writes);
+    }
+
     #[test]
     fn segment_keeps_writer_summaries() {
-        let mut segment = Segment::new(1);
+        let mut segment = Segment::new(1, 1);
         let write = lp_to_replicated_write(1, 1, "cpu val=1 10");
         segment.append(write).unwrap();
         let write = lp_to_replicated_write(2, 1, "cpu val=1 10");
@@ -914,6 +1252,27 @@ mod tests {
         assert_eq!(segment_path, expected_segment_path);
     }
 
+    #[test]
+    fn segment_id_from_object_store_path() {
+        let storage = ObjectStore::new_in_memory(InMemory::new());
+        let db_name = DatabaseName::new("mydb").unwrap();
+        let base_path = database_object_store_path(1, &db_name, &storage);
+
+        for id in &[1, 23, 20_003, 45_010_105] {
+            let segment_path = object_store_path_for_segment(&base_path, *id).unwrap();
+            assert_eq!(segment_id_from_path(&segment_path), Some(*id));
+        }
+
+        let mut rules_path = base_path.clone();
+        rules_path.set_file_name("rules.json");
+        assert_eq!(segment_id_from_path(&rules_path), None);
+
+        let mut other_path = object_store_path_for_wal(1, &db_name, &storage);
+        other_path.push_dir("000");
+        other_path.set_file_name("001.segment");
+        assert_eq!(segment_id_from_path(&other_path), None);
+    }
+
     #[test]
     fn object_store_path_for_segment_out_of_bounds() {
         let storage = ObjectStore::new_in_memory(InMemory::new());
@@ -932,7 +1291,7 @@ mod tests {
     #[test]
     fn segment_serialize_deserialize() {
         let id = 1;
-        let mut segment = Segment::new(id);
+        let mut segment = Segment::new(id, 5);
         let writer_id = 2;
         segment
             .append(lp_to_replicated_write(writer_id, 0, "foo val=1 123"))
@@ -946,9 +1305,97 @@ mod tests {
 
         assert_eq!(segment.id, recovered_segment.id);
         assert_eq!(segment.size, recovered_segment.size);
+        assert_eq!(segment.first_sequence, recovered_segment.first_sequence);
         assert_eq!(segment.writes, recovered_segment.writes);
     }
 
+    #[test]
+    fn assigns_buffer_sequence_numbers() {
+        let max = 1 << 16;
+        let segment = 1;
+        let mut buf = Buffer::new(max, segment, WalBufferRollover::ReturnError, false);
+        assert_eq!(None, buf.last_sequence());
+        assert_eq!(1, buf.oldest_sequence());
+
+        // writes from different writers share the buffer sequence
+        buf.append(lp_to_replicated_write(1, 1, "cpu val=1 10"))
+            .unwrap();
+        buf.append(lp_to_replicated_write(2, 7, "cpu val=2 10"))
+            .unwrap();
+        buf.append(lp_to_replicated_write(1, 2, "cpu val=3 10"))
+            .unwrap();
+        assert_eq!(Some(3), buf.last_sequence());
+        assert_eq!(1, buf.oldest_sequence());
+
+        let writes = buf.writes_from(2, 10).unwrap();
+        let sequences: Vec<_> = writes.iter().map(|w| w.sequence).collect();
+        assert_eq!(vec![2, 3], sequences);
+        assert_eq!((2, 7), writes[0].write.writer_and_sequence());
+        assert_eq!((1, 2), writes[1].write.writer_and_sequence());
+
+        assert!(buf.writes_from(4, 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn consumer_follows_writes() {
+        let max = 1 << 16;
+        let segment = 1;
+        let mut buf = Buffer::new(max, segment, WalBufferRollover::ReturnError, false);
+        let mut consumer = BufferConsumer::default();
+
+        assert!(consumer.next(&buf, 10).unwrap().is_empty());
+        assert_eq!(1, consumer.next_sequence());
+
+        for i in 1..=3 {
+            buf.append(lp_to_replicated_write(1, i, "cpu val=1 10"))
+                .unwrap();
+        }
+
+        let writes = consumer.next(&buf, 2).unwrap();
+        let sequences: Vec<_> = writes.iter().map(|w| w.sequence).collect();
+        assert_eq!(vec![1, 2], sequences);
+        assert_eq!(3, consumer.next_sequence());
+
+        buf.append(lp_to_replicated_write(1, 4, "cpu val=1 10"))
+            .unwrap();
+
+        let writes = consumer.next(&buf, 10).unwrap();
+        let sequences: Vec<_> = writes.iter().map(|w| w.sequence).collect();
+        assert_eq!(vec![3, 4], sequences);
+        assert_eq!(5, consumer.next_sequence());
+    }
+
+    #[test]
+    fn consumer_errors_when_writes_dropped() {
+        let max = 600;
+        let segment = 1;
+        let mut buf = Buffer::new(max, segment, WalBufferRollover::DropOldSegment, false);
+        let mut consumer = BufferConsumer::default();
+
+        for i in 1..=3 {
+            buf.append(lp_to_replicated_write(1, i, "cpu val=1 10"))
+                .unwrap();
+        }
+
+        // the first segment was dropped to make room for the third write
+        assert_eq!(2, buf.oldest_sequence());
+
+        let err = consumer.next(&buf, 10).unwrap_err();
+        assert!(matches!(
+            err,
+            Error::SequenceDropped {
+                sequence: 1,
+                oldest_sequence: 2
+            }
+        ));
+        assert_eq!(1, consumer.next_sequence());
+
+        let mut consumer = BufferConsumer::new(buf.oldest_sequence());
+        let writes = consumer.next(&buf, 10).unwrap();
+        let sequences: Vec<_> = writes.iter().map(|w| w.sequence).collect();
+        assert_eq!(vec![2, 3], sequences);
+    }
+
     fn lp_to_replicated_write(
         writer_id: u32,
         sequence_number: u64,
@@ -0,0 +1,274 @@
+//! Replication of the catalog between the members of a cluster.
+//!
+//! The administrative state of a server, its org and token catalogs and
+//! the rules of its databases, is changed through whichever member of the
+//! cluster receives the request. To keep it the same on every member, each
+//! change made on a server with cluster membership enabled is recorded in
+//! its catalog log as an entry holding the new state of the item changed:
+//! an org, a token, the rules of a database, the deletion of an org, token or
+//! database, the renaming of a database, or a delete of some of its data.
+//! Orgs and tokens are keyed by their ids, which are unique across the
+//! cluster, so orgs and tokens created on different members never supersede
+//! each other. Entries are
+//! versioned with Lamport timestamps: a change is given a counter one above the
+//! highest the server has seen, with the writer id of the server breaking ties,
+//! so a change made with knowledge of another is the more recent, and
+//! concurrent changes are ordered the same way everywhere.
+//!
+//! A server sends the entry of a change to the members it knows to be alive
+//! as soon as it is made, and exchanges its whole log with the members it
+//! gossips with each round, so members that were down catch up. A server
+//! applies the entries more recent than its own for the same items, and
+//! persists its log so versions survive restarts.
+//!
+//! The deletion of an org or token is final: its entry is kept as a
+//! tombstone that supersedes every other change to the org or token,
+//! whatever their versions, so a revoked token can't be brought back by a
+//! concurrent change made on another member.
+use crate::{db::tombstone::DeleteRecord, orgs::Org, tokens::StoredToken};
+use data_types::database_rules::DatabaseRules;
+use generated_types::influxdata::iox::cluster::v1 as proto;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+pub(crate) const CATALOG_LOG_FILE_NAME: &str = "catalog_log.json";
+
+/// The version of a catalog entry, as described in the module docs
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version {
+    pub counter: u64,
+    /// The writer id of the server that made the change
+    pub writer_id: u32,
+}
+
+/// A change to the catalog, as the new state of the item changed
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum CatalogChange {
+    Org { org: Org },
+    OrgDeleted { id: String },
+    Token { token: StoredToken },
+    TokenDeleted { id: String },
+    Database { rules: DatabaseRules },
+    DatabaseDeleted { name: String },
+    DatabaseRenamed { from: String, to: String },
+    Delete { name: String, record: DeleteRecord },
+}
+
+impl CatalogChange {
+    /// Identifies the item changed: changes to the same item supersede
+    /// each other
+    fn key(&self) -> String {
+        match self {
+            Self::Org { org } => format!("org/{}", org.id),
+            Self::OrgDeleted { id } => format!("org/{}", id),
+            Self::Token { token } => format!("token/{}", token.token.id),
+            Self::TokenDeleted { id } => format!("token/{}", id),
+            Self::Database { rules } => format!("database/{}", rules.name),
+            Self::DatabaseDeleted { name } => format!("database/{}", name),
+            // supersedes the changes to the database under its old name
+            Self::DatabaseRenamed { from, .. } => format!("database/{}", from),
+            // each delete is kept, rather than superseding the others
+            Self::Delete { name, record } => format!(
+                "delete/{}/{}/{}",
+                name,
+                record.issued_by,
+                record.issued_at.timestamp_nanos()
+            ),
+        }
+    }
+
+    /// Whether the change is the final deletion of its item, superseding
+    /// any other change to it
+    fn is_tombstone(&self) -> bool {
+        matches!(self, Self::OrgDeleted { .. } | Self::TokenDeleted { .. })
+    }
+}
+
+/// A versioned change to the catalog
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CatalogEntry {
+    pub version: Version,
+    pub(crate) change: CatalogChange,
+}
+
+impl CatalogEntry {
+    /// Converts an entry sent by another member, returning `None` if its
+    /// change can't be parsed, e.g. as it comes from a newer version
+    pub fn from_proto(entry: proto::CatalogEntry) -> Option<Self> {
+        Some(Self {
+            version: Version {
+                counter: entry.counter,
+                writer_id: entry.writer_id,
+            },
+            change: serde_json::from_slice(&entry.change).ok()?,
+        })
+    }
+}
+
+impl From<CatalogEntry> for proto::CatalogEntry {
+    fn from(entry: CatalogEntry) -> Self {
+        Self {
+            counter: entry.version.counter,
+            writer_id: entry.version.writer_id,
+            change: serde_json::to_vec(&entry.change).expect("catalog changes are serializable"),
+        }
+    }
+}
+
+/// The most recent entry of each item of the catalog, as persisted
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub(crate) struct CatalogLog {
+    entries: BTreeMap<String, CatalogEntry>,
+    /// The highest counter seen
+    counter: u64,
+}
+
+impl CatalogLog {
+    /// Records `change`, made on the server with writer id `writer_id`,
+    /// returning its entry. If its item has been deleted for good
+    /// meanwhile, the tombstone is kept and returned instead.
+    pub(crate) fn record(&mut self, writer_id: u32, change: CatalogChange) -> CatalogEntry {
+        if let Some(current) = self.entries.get(&change.key()) {
+            if current.change.is_tombstone() && !change.is_tombstone() {
+                return current.clone();
+            }
+        }
+
+        self.counter += 1;
+        let entry = CatalogEntry {
+            version: Version {
+                counter: self.counter,
+                writer_id,
+            },
+            change,
+        };
+        self.entries.insert(entry.change.key(), entry.clone());
+        entry
+    }
+
+    /// Whether `entry` is more recent than the entry of its item, or is a
+    /// tombstone superseding it
+    pub(crate) fn is_newer(&self, entry: &CatalogEntry) -> bool {
+        let current = match self.entries.get(&entry.change.key()) {
+            Some(current) => current,
+            None => return true,
+        };
+        match (current.change.is_tombstone(), entry.change.is_tombstone()) {
+            (true, false) => false,
+            (false, true) => true,
+            _ => entry.version > current.version,
+        }
+    }
+
+    /// Records `entry`, received from another member, if it is more recent
+    /// than the entry of its item
+    pub(crate) fn insert(&mut self, entry: CatalogEntry) {
+        self.counter = self.counter.max(entry.version.counter);
+        if self.is_newer(&entry) {
+            self.entries.insert(entry.change.key(), entry);
+        }
+    }
+
+    /// Returns the entries of every item
+    pub(crate) fn entries(&self) -> Vec<CatalogEntry> {
+        self.entries.values().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn deleted(name: &str) -> CatalogChange {
+        CatalogChange::DatabaseDeleted {
+            name: name.to_string(),
+        }
+    }
+
+    #[test]
+    fn versions() {
+        let mut local = CatalogLog::default();
+        let first = local.record(1, deleted("foo"));
+        assert_eq!(first.version.counter, 1);
+
+        // a change made with knowledge of another supersedes it
+        let mut remote = CatalogLog::default();
+        remote.insert(first.clone());
+        let second = remote.record(2, deleted("foo"));
+        assert_eq!(second.version.counter, 2);
+        assert!(local.is_newer(&second));
+        local.insert(second.clone());
+        assert!(!local.is_newer(&first));
+        assert_eq!(local.entries(), vec![second.clone()]);
+
+        // concurrent changes are ordered by writer id
+        let mut other = CatalogLog::default();
+        other.insert(first);
+        let concurrent = other.record(3, deleted("foo"));
+        assert_eq!(concurrent.version.counter, 2);
+        assert!(local.is_newer(&concurrent));
+        assert!(!CatalogLog {
+            entries: vec![("database/foo".to_string(), concurrent.clone())]
+                .into_iter()
+                .collect(),
+            counter: 2,
+        }
+        .is_newer(&second));
+
+        // changes to other items are independent
+        assert_eq!(local.record(1, deleted("bar")).version.counter, 3);
+        assert_eq!(local.entries().len(), 2);
+
+        let proto = proto::CatalogEntry::from(concurrent.clone());
+        assert_eq!(CatalogEntry::from_proto(proto), Some(concurrent));
+    }
+
+    #[test]
+    fn tombstones() {
+        let org = |name: &str| CatalogChange::Org {
+            org: Org {
+                id: "0000000100000001".to_string(),
+                name: name.to_string(),
+                limits: Default::default(),
+            },
+        };
+        let org_deleted = || CatalogChange::OrgDeleted {
+            id: "0000000100000001".to_string(),
+        };
+
+        let mut local = CatalogLog::default();
+        let created = local.record(1, org("company"));
+        let mut remote = CatalogLog::default();
+        remote.insert(created.clone());
+
+        // a deletion supersedes a concurrent change with a higher version
+        let deleted = local.record(1, org_deleted());
+        assert_eq!(deleted.version.counter, 2);
+        let renamed = remote.record(2, org("renamed"));
+        assert!(renamed.version > deleted.version);
+        assert!(remote.is_newer(&deleted));
+        remote.insert(deleted.clone());
+        assert!(!local.is_newer(&renamed));
+        local.insert(renamed);
+        assert_eq!(local.entries(), vec![deleted.clone()]);
+        assert_eq!(remote.entries(), vec![deleted.clone()]);
+
+        // changes recorded after the deletion keep the tombstone
+        assert_eq!(remote.record(2, org("again")), deleted);
+        assert_eq!(remote.entries(), vec![deleted]);
+
+        // orgs with different ids are independent
+        local.record(
+            2,
+            CatalogChange::Org {
+                org: Org {
+                    id: "0000000200000001".to_string(),
+                    name: "company".to_string(),
+                    limits: Default::default(),
+                },
+            },
+        );
+        assert_eq!(local.entries().len(), 2);
+    }
+}
@@ -0,0 +1,528 @@
+//! Cluster membership through gossip.
+//!
+//! Every server taking part advertises itself to the others as a member:
+//! the address of its gRPC API, its writer id, its role and the databases
+//! it holds, the shards of the cluster's data it owns. Each gossip round, a
+//! server bumps its heartbeat and exchanges its view of the members with a
+//! few others, taken in turn among the members it knows and the seed
+//! servers it was configured with. Both keep the most recent state of each
+//! member, so news of a member spreads through the cluster within a number
+//! of rounds logarithmic in its size, and servers only need to be given a
+//! seed or two rather than the list of every other server.
+//!
+//! A member whose heartbeat hasn't increased for `SUSPECT_ROUNDS` rounds is
+//! suspected to be down, and considered dead after `DEAD_ROUNDS`. Dead
+//! members are no longer gossiped, and are forgotten after `FORGET_ROUNDS`
+//! unless they come back. A member restarting starts a new incarnation,
+//! whose heartbeats supersede those of the previous one.
+//!
+//! With a [`Quorum`] configured, a server protects the cluster from split
+//! brain: a server cut off from most of the cluster would otherwise keep
+//! accepting writes and catalog changes the rest of the cluster doesn't
+//! see, and the two sides would diverge. A server that sees fewer than a
+//! majority of the expected members of the cluster alive, itself included,
+//! has lost the quorum, and either rejects writes and catalog changes until
+//! it regains it, or only warns that it has lost it, as configured. Reads
+//! are still served, flagged as possibly stale.
+use generated_types::influxdata::iox::cluster::v1 as proto;
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    fmt,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+/// The number of members a server gossips with each round, besides seeds
+/// it doesn't know as members
+pub const FANOUT: usize = 3;
+
+/// The rounds without a heartbeat after which a member is suspected down
+const SUSPECT_ROUNDS: u32 = 5;
+
+/// The rounds without a heartbeat after which a member is considered dead
+const DEAD_ROUNDS: u32 = 20;
+
+/// The rounds without a heartbeat after which a member is forgotten
+const FORGET_ROUNDS: u32 = 60;
+
+/// What a member does in the cluster
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Stores the writes it receives
+    Storage,
+    /// Routes the writes it receives to storage servers
+    Router,
+    /// Serves queries for the databases of a primary
+    Replica,
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "storage" => Ok(Self::Storage),
+            "router" => Ok(Self::Router),
+            "replica" => Ok(Self::Replica),
+            _ => Err(format!("unknown role '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Storage => write!(f, "storage"),
+            Self::Router => write!(f, "router"),
+            Self::Replica => write!(f, "replica"),
+        }
+    }
+}
+
+/// The state of a member, as gossiped
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct MemberState {
+    /// The address of the member's gRPC API, which identifies it
+    pub address: String,
+    /// The writer id of the member, 0 if not set
+    pub id: u32,
+    pub role: Role,
+    /// The names of the databases the member holds
+    pub databases: Vec<String>,
+    /// When the member started, in milliseconds since the epoch
+    pub incarnation: u64,
+    /// The number of rounds the member has run since it started
+    pub heartbeat: u64,
+}
+
+impl MemberState {
+    /// Whether this state is more recent than `other`, a state of the same
+    /// member
+    fn supersedes(&self, other: &Self) -> bool {
+        (self.incarnation, self.heartbeat) > (other.incarnation, other.heartbeat)
+    }
+
+    /// Converts a gossiped member, returning `None` if its role is unknown
+    pub fn from_proto(member: proto::Member) -> Option<Self> {
+        Some(Self {
+            role: member.role.parse().ok()?,
+            address: member.address,
+            id: member.id,
+            databases: member.databases,
+            incarnation: member.incarnation,
+            heartbeat: member.heartbeat,
+        })
+    }
+}
+
+impl From<MemberState> for proto::Member {
+    fn from(state: MemberState) -> Self {
+        Self {
+            address: state.address,
+            id: state.id,
+            role: state.role.to_string(),
+            databases: state.databases,
+            incarnation: state.incarnation,
+            heartbeat: state.heartbeat,
+        }
+    }
+}
+
+/// Whether a member is up, as seen by this server
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Alive,
+    Suspect,
+    Dead,
+}
+
+/// What a server does while it has lost the quorum
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OnQuorumLoss {
+    /// Rejects writes and catalog changes
+    RejectWrites,
+    /// Keeps accepting writes, only warning that the quorum is lost
+    Warn,
+}
+
+impl FromStr for OnQuorumLoss {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject-writes" => Ok(Self::RejectWrites),
+            "warn" => Ok(Self::Warn),
+            _ => Err(format!("unknown behavior on quorum loss '{}'", s)),
+        }
+    }
+}
+
+/// The split-brain protection of a server, as described in the module docs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quorum {
+    cluster_size: usize,
+    on_loss: OnQuorumLoss,
+}
+
+impl Quorum {
+    /// Requires a majority of the `cluster_size` members of the cluster to
+    /// be alive, doing `on_loss` otherwise
+    pub fn new(cluster_size: usize, on_loss: OnQuorumLoss) -> Self {
+        Self {
+            cluster_size,
+            on_loss,
+        }
+    }
+
+    /// The number of members alive required for the quorum
+    pub fn required(&self) -> usize {
+        self.cluster_size / 2 + 1
+    }
+
+    pub fn on_loss(&self) -> OnQuorumLoss {
+        self.on_loss
+    }
+}
+
+/// Whether a server has the quorum
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+pub struct QuorumStatus {
+    /// The members alive, this server included
+    pub alive: usize,
+    pub required: usize,
+    pub on_loss: OnQuorumLoss,
+}
+
+impl QuorumStatus {
+    pub fn lost(&self) -> bool {
+        self.alive < self.required
+    }
+}
+
+/// A member of the cluster, as seen by this server
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct Member {
+    #[serde(flatten)]
+    pub state: MemberState,
+    pub status: Status,
+    /// The seconds since this server last heard of a new heartbeat of the
+    /// member
+    pub last_heartbeat_seconds: f64,
+    /// Whether the member is this server
+    pub local: bool,
+}
+
+#[derive(Debug)]
+struct Entry {
+    state: MemberState,
+    /// When the state was last superseded
+    updated: Instant,
+}
+
+#[derive(Debug)]
+struct State {
+    members: HashMap<String, Entry>,
+    /// The number of rounds run, to take the members to gossip with in turn
+    rounds: usize,
+}
+
+/// The members of the cluster known to this server, as described in the
+/// module docs
+#[derive(Debug)]
+pub struct Membership {
+    local_address: String,
+    seeds: Vec<String>,
+    interval: Duration,
+    state: Mutex<State>,
+}
+
+impl Membership {
+    /// Creates the membership of the server whose state is `local`,
+    /// gossiping every `interval` and first with `seeds`
+    pub fn new(local: MemberState, seeds: Vec<String>, interval: Duration) -> Self {
+        let local_address = local.address.clone();
+        let seeds = seeds
+            .into_iter()
+            .filter(|seed| *seed != local_address)
+            .collect();
+
+        let mut members = HashMap::new();
+        members.insert(
+            local_address.clone(),
+            Entry {
+                state: local,
+                updated: Instant::now(),
+            },
+        );
+
+        Self {
+            local_address,
+            seeds,
+            interval,
+            state: Mutex::new(State { members, rounds: 0 }),
+        }
+    }
+
+    /// The address this server is known by
+    pub fn local_address(&self) -> &str {
+        &self.local_address
+    }
+
+    /// Starts a gossip round: bumps the heartbeat of this server, whose
+    /// databases are now `databases`, forgets the members dead for long
+    /// enough, and returns the addresses to gossip with this round
+    pub fn start_round(&self, databases: Vec<String>) -> Vec<String> {
+        let now = Instant::now();
+        let mut state = self.state.lock();
+
+        let local = state
+            .members
+            .get_mut(&self.local_address)
+            .expect("the local member is known");
+        local.state.databases = databases;
+        local.state.heartbeat += 1;
+        local.updated = now;
+
+        let forget_after = self.interval * FORGET_ROUNDS;
+        state
+            .members
+            .retain(|_, entry| now.duration_since(entry.updated) < forget_after);
+
+        let mut others: Vec<_> = state
+            .members
+            .iter()
+            .filter(|(address, entry)| {
+                **address != self.local_address && self.status(entry, now) != Status::Dead
+            })
+            .map(|(address, _)| address.clone())
+            .collect();
+        others.sort();
+
+        let mut targets: Vec<_> = self
+            .seeds
+            .iter()
+            .filter(|seed| !others.contains(*seed))
+            .cloned()
+            .collect();
+        if !others.is_empty() {
+            let start = state.rounds * FANOUT;
+            targets.extend(
+                (start..start + FANOUT.min(others.len())).map(|i| others[i % others.len()].clone()),
+            );
+        }
+        state.rounds += 1;
+
+        targets
+    }
+
+    /// Returns the states of the members that aren't dead, to gossip
+    pub fn gossip(&self) -> Vec<MemberState> {
+        let now = Instant::now();
+        self.state
+            .lock()
+            .members
+            .values()
+            .filter(|entry| self.status(entry, now) != Status::Dead)
+            .map(|entry| entry.state.clone())
+            .collect()
+    }
+
+    /// Merges the member states `states` gossiped by another server into
+    /// the view of this server
+    pub fn merge(&self, states: impl IntoIterator<Item = MemberState>) {
+        let now = Instant::now();
+        let mut state = self.state.lock();
+
+        for member in states {
+            // only this server knows its own state
+            if member.address == self.local_address {
+                continue;
+            }
+
+            match state.members.get_mut(&member.address) {
+                Some(entry) if !member.supersedes(&entry.state) => {}
+                Some(entry) => {
+                    entry.state = member;
+                    entry.updated = now;
+                }
+                None => {
+                    state.members.insert(
+                        member.address.clone(),
+                        Entry {
+                            state: member,
+                            updated: now,
+                        },
+                    );
+                }
+            }
+        }
+    }
+
+    /// Returns the members known to this server, sorted by address
+    pub fn members(&self) -> Vec<Member> {
+        self.members_at(Instant::now())
+    }
+
+    /// Returns whether this server has `quorum`, from the members it sees
+    /// alive
+    pub fn quorum_status(&self, quorum: &Quorum) -> QuorumStatus {
+        self.quorum_status_at(quorum, Instant::now())
+    }
+
+    fn quorum_status_at(&self, quorum: &Quorum, now: Instant) -> QuorumStatus {
+        let alive = self
+            .state
+            .lock()
+            .members
+            .values()
+            .filter(|entry| self.status(entry, now) == Status::Alive)
+            .count();
+        QuorumStatus {
+            alive,
+            required: quorum.required(),
+            on_loss: quorum.on_loss(),
+        }
+    }
+
+    fn members_at(&self, now: Instant) -> Vec<Member> {
+        let mut members: Vec<_> = self
+            .state
+            .lock()
+            .members
+            .values()
+            .map(|entry| Member {
+                state: entry.state.clone(),
+                status: self.status(entry, now),
+                last_heartbeat_seconds: now.duration_since(entry.updated).as_secs_f64(),
+                local: entry.state.address == self.local_address,
+            })
+            .collect();
+        members.sort_by(|a, b| a.state.address.cmp(&b.state.address));
+        members
+    }
+
+    /// The status of the member of `entry` at `now`
+    fn status(&self, entry: &Entry, now: Instant) -> Status {
+        if entry.state.address == self.local_address {
+            return Status::Alive;
+        }
+
+        let silence = now.duration_since(entry.updated);
+        if silence >= self.interval * DEAD_ROUNDS {
+            Status::Dead
+        } else if silence >= self.interval * SUSPECT_ROUNDS {
+            Status::Suspect
+        } else {
+            Status::Alive
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn member(address: &str, incarnation: u64, heartbeat: u64) -> MemberState {
+        MemberState {
+            address: address.to_string(),
+            id: 0,
+            role: Role::Storage,
+            databases: vec![],
+            incarnation,
+            heartbeat,
+        }
+    }
+
+    #[test]
+    fn merge() {
+        let interval = Duration::from_secs(1);
+        let membership = Membership::new(member("a:8082", 1, 0), vec![], interval);
+
+        membership.merge(vec![member("b:8082", 1, 5), member("a:8082", 9, 9)]);
+        membership.merge(vec![member("b:8082", 1, 3)]);
+        let members = membership.members();
+        assert_eq!(members.len(), 2);
+        assert!(members[0].local);
+        assert_eq!(members[0].state, member("a:8082", 1, 0));
+        assert_eq!(members[1].state.heartbeat, 5);
+
+        // a restarted member supersedes its previous incarnation
+        membership.merge(vec![member("b:8082", 2, 0)]);
+        assert_eq!(membership.members()[1].state, member("b:8082", 2, 0));
+
+        let mut state = member("c:8082", 1, 1);
+        state.databases = vec!["company_sensors".to_string()];
+        let gossiped = proto::Member::from(state.clone());
+        assert_eq!(gossiped.role, "storage");
+        assert_eq!(MemberState::from_proto(gossiped), Some(state));
+    }
+
+    #[test]
+    fn statuses() {
+        let interval = Duration::from_secs(1);
+        let membership = Membership::new(member("a:8082", 1, 0), vec![], interval);
+        membership.merge(vec![member("b:8082", 1, 1)]);
+
+        let status_after = |rounds| {
+            let members = membership.members_at(Instant::now() + interval * rounds);
+            assert_eq!(members[0].status, Status::Alive);
+            members[1].status
+        };
+        assert_eq!(status_after(0), Status::Alive);
+        assert_eq!(status_after(SUSPECT_ROUNDS), Status::Suspect);
+        assert_eq!(status_after(DEAD_ROUNDS), Status::Dead);
+    }
+
+    #[test]
+    fn quorum() {
+        let interval = Duration::from_secs(1);
+        let membership = Membership::new(member("a:8082", 1, 0), vec![], interval);
+        let quorum = Quorum::new(4, OnQuorumLoss::RejectWrites);
+        assert_eq!(quorum.required(), 3);
+        assert!(membership.quorum_status(&quorum).lost());
+
+        membership.merge(vec![member("b:8082", 1, 1), member("c:8082", 1, 1)]);
+        let status = membership.quorum_status(&quorum);
+        assert_eq!(status.alive, 3);
+        assert!(!status.lost());
+
+        // suspect members don't count
+        let status =
+            membership.quorum_status_at(&quorum, Instant::now() + interval * SUSPECT_ROUNDS);
+        assert_eq!(status.alive, 1);
+        assert!(status.lost());
+
+        assert_eq!("warn".parse(), Ok(OnQuorumLoss::Warn));
+        assert!("ignore".parse::<OnQuorumLoss>().is_err());
+    }
+
+    #[test]
+    fn rounds() {
+        let interval = Duration::from_secs(1);
+        let seeds = vec!["a:8082".to_string(), "seed:8082".to_string()];
+        let membership = Membership::new(member("a:8082", 1, 0), seeds, interval);
+
+        // seeds are contacted until known as members
+        assert_eq!(membership.start_round(vec![]), vec!["seed:8082"]);
+        membership.merge((0..5).map(|i| member(&format!("m{}:8082", i), 1, 1)));
+        membership.merge(vec![member("seed:8082", 1, 1)]);
+
+        let targets = membership.start_round(vec!["db".to_string()]);
+        assert_eq!(targets, vec!["m3:8082", "m4:8082", "seed:8082"]);
+        let targets = membership.start_round(vec![]);
+        assert_eq!(targets, vec!["m0:8082", "m1:8082", "m2:8082"]);
+
+        let local = membership
+            .gossip()
+            .into_iter()
+            .find(|state| state.address == "a:8082")
+            .unwrap();
+        assert_eq!(local.heartbeat, 3);
+        assert_eq!(membership.gossip().len(), 7);
+    }
+}
@@ -0,0 +1,182 @@
+//! On demand compaction of the mutable buffer.
+//!
+//! Compacting a partition rolls over its open mutable buffer chunk and then
+//! moves each closed chunk into the read buffer, which stores it in a
+//! compressed, read optimised format, freeing the memory the chunk held in
+//! the mutable buffer. Compactions run as background jobs, scheduled along
+//! with other background work by the [`scheduler`](crate::scheduler), whose
+//! status can be queried by id while they run and for a while after they
+//! finish. The size of each chunk moved counts against the IO limits of the
+//! background jobs (see the [`throttle`](crate::throttle) module).
+
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use parking_lot::Mutex;
+use query::PartitionChunk;
+use serde::Serialize;
+use tracing::{error, info};
+
+use crate::{
+    db::{self, DBChunk, Db},
+    scheduler::QueuedJob,
+    throttle::IoThrottle,
+};
+
+/// The number of finished jobs whose status is kept
+const MAX_FINISHED_JOBS: usize = 100;
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CompactionState {
+    Running,
+    Completed,
+    Failed,
+}
+
+/// The status of a compaction job
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct CompactionStatus {
+    pub id: u64,
+    pub db_name: String,
+    /// The partitions being compacted
+    pub partition_keys: Vec<String>,
+    pub state: CompactionState,
+    /// The number of chunks the job compacts, known once the partitions
+    /// have been rolled over
+    pub chunks_total: usize,
+    pub chunks_compacted: usize,
+    /// The reason the job failed
+    pub error: Option<String>,
+}
+
+/// The compaction jobs of a server
+#[derive(Debug, Default)]
+pub(crate) struct Compactions {
+    next_id: AtomicU64,
+    jobs: Mutex<BTreeMap<u64, CompactionStatus>>,
+}
+
+impl Compactions {
+    /// Starts a job compacting the partitions `partition_keys` of `db` once
+    /// `job` gets its turn, returning its initial status
+    pub(crate) fn start(
+        self: &Arc<Self>,
+        db_name: String,
+        db: Arc<Db>,
+        partition_keys: Vec<String>,
+        job: QueuedJob,
+    ) -> CompactionStatus {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let status = CompactionStatus {
+            id,
+            db_name,
+            partition_keys: partition_keys.clone(),
+            state: CompactionState::Running,
+            chunks_total: 0,
+            chunks_compacted: 0,
+            error: None,
+        };
+        self.jobs.lock().insert(id, status.clone());
+        info!(id, db_name = %status.db_name, ?partition_keys, "starting compaction");
+
+        let compactions = Arc::clone(self);
+        tokio::spawn(async move {
+            let permit = job.start().await;
+            let result = compactions
+                .run(id, &db, &partition_keys, permit.throttle())
+                .await;
+            compactions.update(id, |status| match result {
+                Ok(()) => {
+                    info!(id, chunks = status.chunks_compacted, "compaction completed");
+                    status.state = CompactionState::Completed;
+                }
+                Err(e) => {
+                    error!(id, error = %e, "compaction failed");
+                    status.state = CompactionState::Failed;
+                    status.error = Some(e.to_string());
+                }
+            });
+            compactions.remove_finished_jobs();
+        });
+
+        status
+    }
+
+    async fn run(
+        &self,
+        id: u64,
+        db: &Db,
+        partition_keys: &[String],
+        throttle: &IoThrottle,
+    ) -> db::Result<()> {
+        let mut chunks = vec![];
+        for partition_key in partition_keys {
+            // chunks up to the one rolled over are closed
+            let last_chunk_id = db.rollover_partition(partition_key).await?.id();
+            chunks.extend(
+                db.mutable_buffer_chunks(partition_key)
+                    .into_iter()
+                    .filter(|chunk| chunk.id() <= last_chunk_id)
+                    .map(|chunk| (partition_key, chunk.id(), chunk_size(&chunk))),
+            );
+        }
+        self.update(id, |status| status.chunks_total = chunks.len());
+
+        for (partition_key, chunk_id, size) in chunks {
+            throttle.consume(size as u64).await;
+            db.load_chunk_to_read_buffer(partition_key, chunk_id)
+                .await?;
+            db.drop_mutable_buffer_chunk(partition_key, chunk_id)
+                .await?;
+            self.update(id, |status| status.chunks_compacted += 1);
+        }
+
+        Ok(())
+    }
+
+    fn update(&self, id: u64, f: impl FnOnce(&mut CompactionStatus)) {
+        if let Some(status) = self.jobs.lock().get_mut(&id) {
+            f(status)
+        }
+    }
+
+    /// Forgets the oldest finished jobs beyond `MAX_FINISHED_JOBS`
+    fn remove_finished_jobs(&self) {
+        let mut jobs = self.jobs.lock();
+        let finished: Vec<_> = jobs
+            .values()
+            .filter(|status| status.state != CompactionState::Running)
+            .map(|status| status.id)
+            .collect();
+        for id in finished
+            .iter()
+            .take(finished.len().saturating_sub(MAX_FINISHED_JOBS))
+        {
+            jobs.remove(id);
+        }
+    }
+
+    /// Returns the status of the job with id `id`
+    pub(crate) fn status(&self, id: u64) -> Option<CompactionStatus> {
+        self.jobs.lock().get(&id).cloned()
+    }
+
+    /// Returns the status of every job, oldest first
+    pub(crate) fn statuses(&self) -> Vec<CompactionStatus> {
+        self.jobs.lock().values().cloned().collect()
+    }
+}
+
+/// Returns the size of the mutable buffer chunk `chunk`
+fn chunk_size(chunk: &DBChunk) -> usize {
+    match chunk {
+        DBChunk::MutableBuffer { chunk } => chunk.size(),
+        _ => 0,
+    }
+}
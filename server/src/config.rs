@@ -14,6 +14,7 @@ use std::{
 };
 
 pub(crate) const DB_RULES_FILE_NAME: &str = "rules.json";
+pub(crate) const DELETES_FILE_NAME: &str = "deletes.json";
 
 /// The Config tracks the configuration od databases and their rules along
 /// with host groups for replication. It is used as an in-memory structure
@@ -60,6 +61,34 @@ impl Config {
         state.databases.get(name).cloned()
     }
 
+    /// Moves the database `from` to the name `to`, returning it
+    pub(crate) fn rename_db(
+        &self,
+        from: &DatabaseName<'static>,
+        to: DatabaseName<'static>,
+    ) -> Result<Arc<Db>> {
+        let mut state = self.state.write().expect("mutex poisoned");
+        if state.reservations.contains(&to) || state.databases.contains_key(&to) {
+            return Err(Error::DatabaseAlreadyExists {
+                db_name: to.to_string(),
+            });
+        }
+
+        let db = state
+            .databases
+            .remove(from)
+            .ok_or_else(|| Error::DatabaseNotFound {
+                db_name: from.to_string(),
+            })?;
+        state.databases.insert(to, Arc::clone(&db));
+        Ok(db)
+    }
+
+    pub(crate) fn remove_db(&self, name: &DatabaseName<'static>) -> Option<Arc<Db>> {
+        let mut state = self.state.write().expect("mutex poisoned");
+        state.databases.remove(name)
+    }
+
     pub(crate) fn create_host_group(&self, host_group: HostGroup) {
         let mut state = self.state.write().expect("mutex poisoned");
         state
@@ -102,6 +131,18 @@ pub fn object_store_path_for_database_config<P: ObjectStorePath>(
     path
 }
 
+/// Returns the location of the deletes of the database `name`, next to its
+/// rules
+pub fn object_store_path_for_database_deletes<P: ObjectStorePath>(
+    root: &P,
+    name: &DatabaseName<'_>,
+) -> P {
+    let mut path = root.clone();
+    path.push_dir(name.to_string());
+    path.set_file_name(DELETES_FILE_NAME);
+    path
+}
+
 #[derive(Default, Debug)]
 struct ConfigState {
     reservations: BTreeSet<DatabaseName<'static>>,
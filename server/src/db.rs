@@ -2,14 +2,15 @@
 //! instances of the mutable buffer, read buffer, and object store
 
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, BTreeSet},
     sync::{
         atomic::{AtomicU64, Ordering},
-        Arc,
+        Arc, RwLock,
     },
 };
 
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use data_types::{data::ReplicatedWrite, database_rules::DatabaseRules, selection::Selection};
 use mutable_buffer::MutableBufferDb;
 use parking_lot::Mutex;
@@ -18,14 +19,20 @@ use read_buffer::Database as ReadBufferDb;
 use serde::{Deserialize, Serialize};
 use snafu::{OptionExt, ResultExt, Snafu};
 
-use crate::buffer::Buffer;
+use crate::{
+    anti_entropy::{WriteDigest, WriteLog},
+    buffer::Buffer,
+};
 
 use tracing::info;
 
 mod chunk;
-use chunk::DBChunk;
+pub use chunk::DBChunk;
 pub mod pred;
+pub mod predicate;
 mod streams;
+pub mod tombstone;
+use tombstone::{DeletePredicate, DeleteRecord, Tombstone};
 
 #[derive(Debug, Snafu)]
 pub enum Error {
@@ -65,17 +72,32 @@ pub enum Error {
 
     #[snafu(display("Error dropping data from read buffer: {}", source))]
     ReadBufferDrop { source: read_buffer::Error },
+
+    #[snafu(display("Error computing statistics of mutable buffer: {}", source))]
+    MutableBufferStats {
+        source: mutable_buffer::database::Error,
+    },
 }
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
 const STARTING_SEQUENCE: u64 = 1;
 
+/// Returns the first sequence number of the writes to a database opened
+/// now: the time in microseconds since the Unix epoch. The sequence numbers
+/// of a server then keep increasing across restarts, as long as it averages
+/// less than a write per microsecond, so that replicas don't mistake the
+/// writes it accepts after a restart for those it accepted before.
+fn starting_sequence() -> u64 {
+    let micros = Utc::now().timestamp_nanos() / 1_000;
+    (micros.max(0) as u64).max(STARTING_SEQUENCE)
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 /// This is the main IOx Database object. It is the root object of any
 /// specific InfluxDB IOx instance
 pub struct Db {
     #[serde(flatten)]
-    pub rules: DatabaseRules,
+    rules: RwLock<DatabaseRules>,
 
     #[serde(skip)]
     /// The (optional) mutable buffer stores incoming writes. If a
@@ -96,6 +118,56 @@ pub struct Db {
 
     #[serde(skip)]
     sequence: AtomicU64,
+
+    #[serde(skip)]
+    /// Deletes, which hide rows of the chunks that existed when they were
+    /// issued
+    tombstones: RwLock<Vec<Arc<Tombstone>>>,
+
+    #[serde(skip)]
+    /// The records of the deletes, as persisted
+    deletes: Mutex<Deletes>,
+
+    #[serde(skip)]
+    /// The number of chunks of each partition written to object storage
+    persisted_chunks: Mutex<BTreeMap<String, usize>>,
+
+    #[serde(skip)]
+    /// The writes each partition holds, compared between replicas by
+    /// anti-entropy repair
+    pub write_log: WriteLog,
+}
+
+/// The deletes of a database
+#[derive(Debug, Default)]
+struct Deletes {
+    applied: Vec<DeleteRecord>,
+    /// Restored at startup, waiting for the writes issued before them to be
+    /// replayed
+    pending: Vec<DeleteRecord>,
+}
+
+/// How much data a partition holds and where it lives
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct PartitionStatus {
+    pub key: String,
+    /// The number of distinct series in the mutable buffer
+    pub series_count: usize,
+    /// The number of points in the mutable buffer and the read buffer
+    pub point_count: u64,
+    /// The approximate size in bytes of the open mutable buffer chunk
+    pub open_chunk_size: usize,
+    pub mutable_buffer_chunk_count: usize,
+    /// The approximate size in bytes of all mutable buffer chunks
+    pub mutable_buffer_size: usize,
+    pub read_buffer_chunk_count: usize,
+    /// The approximate size in bytes of all read buffer chunks
+    pub read_buffer_size: u64,
+    /// The most recent time data was written to the mutable buffer
+    pub last_write_time: Option<DateTime<Utc>>,
+    /// The number of chunks written to object storage since the server
+    /// started
+    pub persisted_chunk_count: usize,
 }
 impl Db {
     pub fn new(
@@ -106,15 +178,32 @@ impl Db {
     ) -> Self {
         let wal_buffer = wal_buffer.map(Mutex::new);
         let read_buffer = Arc::new(read_buffer);
+        let rules = RwLock::new(rules);
         Self {
             rules,
             mutable_buffer,
             read_buffer,
             wal_buffer,
-            sequence: AtomicU64::new(STARTING_SEQUENCE),
+            sequence: AtomicU64::new(starting_sequence()),
+            tombstones: Default::default(),
+            deletes: Default::default(),
+            persisted_chunks: Default::default(),
+            write_log: Default::default(),
         }
     }
 
+    /// Returns a copy of the rules of this database
+    pub fn rules(&self) -> DatabaseRules {
+        self.rules.read().expect("mutex poisoned").clone()
+    }
+
+    /// Replaces the rules of this database. Note that the mutable buffer
+    /// and WAL buffer are created with the database, so enabling or
+    /// disabling them on an existing database has no effect.
+    pub fn set_rules(&self, rules: DatabaseRules) {
+        *self.rules.write().expect("mutex poisoned") = rules;
+    }
+
     /// Rolls over the active chunk in the database's specified partition
     pub async fn rollover_partition(&self, partition_key: &str) -> Result<Arc<DBChunk>> {
         if let Some(local_store) = self.mutable_buffer.as_ref() {
@@ -230,15 +319,215 @@ impl Db {
         ))
     }
 
+    /// Deletes the rows matching `predicate`. The open chunk of every
+    /// partition is rolled over so that the delete only applies to data
+    /// written before it.
+    pub async fn delete(&self, predicate: DeletePredicate) -> Result<()> {
+        let mut last_chunk_ids = BTreeMap::new();
+
+        if let Some(mutable_buffer) = self.mutable_buffer.as_ref() {
+            for partition_key in mutable_buffer.partition_keys().context(MutableBufferRead)? {
+                let chunk = self.rollover_partition(&partition_key).await?;
+                last_chunk_ids.insert(partition_key, chunk.id());
+            }
+        }
+
+        for partition_key in self.read_buffer.partition_keys() {
+            if let Some(&id) = self.read_buffer.chunk_ids(&partition_key).iter().max() {
+                let last = last_chunk_ids.entry(partition_key).or_insert(id);
+                *last = (*last).max(id);
+            }
+        }
+
+        let tombstone = Arc::new(Tombstone::new(predicate, last_chunk_ids));
+        self.tombstones
+            .write()
+            .expect("mutex poisoned")
+            .push(tombstone);
+
+        Ok(())
+    }
+
+    /// Applies the delete of `record`, issued on this or another server,
+    /// unless it has been already. Returns whether it was applied.
+    pub async fn apply_delete(&self, record: DeleteRecord) -> Result<bool> {
+        if self
+            .deletes
+            .lock()
+            .applied
+            .iter()
+            .any(|r| r.is_same(&record))
+        {
+            return Ok(false);
+        }
+        self.delete(record.predicate.clone()).await?;
+
+        let mut deletes = self.deletes.lock();
+        deletes.pending.retain(|r| !r.is_same(&record));
+        deletes.applied.push(record);
+        Ok(true)
+    }
+
+    /// Records the deletes persisted before a restart, to be applied as the
+    /// writes issued before them are replayed
+    pub fn restore_deletes(&self, records: Vec<DeleteRecord>) {
+        self.deletes.lock().pending = records;
+    }
+
+    /// Returns the deletes restored at startup that are not yet applied
+    pub fn pending_deletes(&self) -> Vec<DeleteRecord> {
+        self.deletes.lock().pending.clone()
+    }
+
+    /// Returns the records of all the deletes of this database, applied or
+    /// not
+    pub fn delete_records(&self) -> Vec<DeleteRecord> {
+        let deletes = self.deletes.lock();
+        deletes
+            .applied
+            .iter()
+            .chain(&deletes.pending)
+            .cloned()
+            .collect()
+    }
+
+    /// Drops the closed chunks whose rows are all older than `cutoff`, in
+    /// nanoseconds since the epoch, from the mutable buffer and the read
+    /// buffer. Used to enforce the retention period of the database.
+    ///
+    /// Returns the number of chunks dropped
+    pub async fn drop_expired_chunks(&self, cutoff: i64) -> Result<usize> {
+        let mut dropped = 0;
+        for partition_key in self.buffered_partition_keys()? {
+            dropped += self
+                .drop_expired_partition_chunks(&partition_key, cutoff)
+                .await?;
+        }
+        Ok(dropped)
+    }
+
+    /// Like `drop_expired_chunks`, for the partition `partition_key` only
+    pub async fn drop_expired_partition_chunks(
+        &self,
+        partition_key: &str,
+        cutoff: i64,
+    ) -> Result<usize> {
+        let mut dropped = 0;
+
+        if let Some(mutable_buffer) = self.mutable_buffer.as_ref() {
+            for chunk in mutable_buffer.chunks(partition_key) {
+                // the open chunk may still receive newer writes
+                if chunk.time_closed().is_none() {
+                    continue;
+                }
+                let time_range = chunk.time_range().context(MutableBufferChunk)?;
+                if matches!(time_range, Some((_, max)) if max < cutoff) {
+                    self.drop_mutable_buffer_chunk(partition_key, chunk.id())
+                        .await?;
+                    dropped += 1;
+                }
+            }
+        }
+
+        for chunk_id in self.read_buffer.chunk_ids(partition_key) {
+            let time_range = self.read_buffer.chunk_time_range(partition_key, chunk_id);
+            if matches!(time_range, Some((_, max)) if max < cutoff) {
+                self.drop_read_buffer_chunk(partition_key, chunk_id).await?;
+                dropped += 1;
+            }
+        }
+
+        Ok(dropped)
+    }
+
+    /// Returns the keys of the partitions with chunks in the mutable buffer
+    /// or the read buffer, sorted
+    pub fn buffered_partition_keys(&self) -> Result<Vec<String>> {
+        let mut partition_keys: BTreeSet<_> =
+            self.read_buffer.partition_keys().into_iter().collect();
+        if let Some(mutable_buffer) = self.mutable_buffer.as_ref() {
+            partition_keys.extend(mutable_buffer.partition_keys().context(MutableBufferRead)?);
+        }
+        Ok(partition_keys.into_iter().collect())
+    }
+
+    /// Returns the digests of the writes each partition holds, forgetting
+    /// the partitions whose data has been dropped
+    pub fn write_digests(&self) -> Result<Vec<WriteDigest>> {
+        self.write_log.retain(&self.buffered_partition_keys()?);
+        Ok(self.write_log.digests())
+    }
+
+    /// Records that a chunk of the partition `partition_key` has been
+    /// written to object storage
+    pub fn record_persisted_chunk(&self, partition_key: &str) {
+        *self
+            .persisted_chunks
+            .lock()
+            .entry(partition_key.to_string())
+            .or_default() += 1;
+    }
+
+    /// Returns the status of each partition in the mutable buffer or the
+    /// read buffer, ordered by partition key
+    pub fn partition_statuses(&self) -> Result<Vec<PartitionStatus>> {
+        let mut partition_keys: BTreeSet<_> =
+            self.read_buffer.partition_keys().into_iter().collect();
+        if let Some(mutable_buffer) = self.mutable_buffer.as_ref() {
+            partition_keys.extend(mutable_buffer.partition_keys().context(MutableBufferRead)?);
+        }
+
+        partition_keys
+            .into_iter()
+            .map(|key| self.partition_status(key))
+            .collect()
+    }
+
+    fn partition_status(&self, key: String) -> Result<PartitionStatus> {
+        let mutable_buffer_stats = match self.mutable_buffer.as_ref() {
+            Some(mutable_buffer) => mutable_buffer
+                .partition_stats(&key)
+                .context(MutableBufferStats)?,
+            None => None,
+        }
+        .unwrap_or_default();
+        let read_buffer_rows = self.read_buffer.partition_rows(&key);
+
+        Ok(PartitionStatus {
+            series_count: mutable_buffer_stats.series_count,
+            point_count: mutable_buffer_stats.row_count as u64 + read_buffer_rows,
+            open_chunk_size: mutable_buffer_stats.open_chunk_size,
+            mutable_buffer_chunk_count: mutable_buffer_stats.chunk_count,
+            mutable_buffer_size: mutable_buffer_stats.size,
+            read_buffer_chunk_count: self.read_buffer.chunk_ids(&key).len(),
+            read_buffer_size: self.read_buffer.partition_size(&key),
+            last_write_time: mutable_buffer_stats.last_write_time,
+            persisted_chunk_count: self
+                .persisted_chunks
+                .lock()
+                .get(&key)
+                .copied()
+                .unwrap_or_default(),
+            key,
+        })
+    }
+
     /// Returns the next write sequence number
     pub fn next_sequence(&self) -> u64 {
         self.sequence.fetch_add(1, Ordering::SeqCst)
     }
 
+    /// Makes the writes accepted from now on follow `sequence`, that of a
+    /// write of this server replayed at startup, in case the clock went back
+    /// since it was accepted
+    pub fn sequence_after(&self, sequence: u64) {
+        self.sequence.fetch_max(sequence + 1, Ordering::SeqCst);
+    }
+
     /// Drops partitions from the mutable buffer if it is over size
     pub fn check_size_and_drop_partitions(&self) -> Result<()> {
-        if let (Some(db), Some(config)) = (&self.mutable_buffer, &self.rules.mutable_buffer_config)
-        {
+        let rules = self.rules.read().expect("mutex poisoned");
+        if let (Some(db), Some(config)) = (&self.mutable_buffer, &rules.mutable_buffer_config) {
             let mut size = db.size();
             if size > config.buffer_size {
                 let mut partitions = db.partitions_sorted_by(&config.partition_drop_order);
@@ -265,7 +554,7 @@ impl Db {
 
 impl PartialEq for Db {
     fn eq(&self, other: &Self) -> bool {
-        self.rules == other.rules
+        self.rules() == other.rules()
     }
 }
 impl Eq for Db {}
@@ -290,8 +579,20 @@ impl Database for Db {
             .map(|chunk| (chunk.id(), chunk))
             .collect();
 
+        let tombstones = self.tombstones.read().expect("mutex poisoned");
+
         // inserting into the map will have removed any dupes
-        chunks.into_iter().map(|(_id, chunk)| chunk).collect()
+        chunks
+            .into_iter()
+            .map(|(id, chunk)| {
+                let tombstones = tombstones
+                    .iter()
+                    .filter(|t| t.applies_to(partition_key, id))
+                    .cloned()
+                    .collect();
+                DBChunk::with_tombstones(chunk, tombstones)
+            })
+            .collect()
     }
 
     // Note that most of the functions below will eventually be removed from
@@ -393,6 +694,140 @@ mod tests {
         assert_table_eq!(expected, &batches);
     }
 
+    #[tokio::test]
+    async fn delete() {
+        let db = make_db();
+        let mut writer = TestLPWriter::default();
+        writer
+            .write_lp_string(
+                &db,
+                "cpu,host=a bar=1 10\ncpu,host=b bar=2 20\nmem,host=a bar=3 10",
+            )
+            .await
+            .unwrap();
+
+        let predicate =
+            DeletePredicate::try_new(0, 15, r#"_measurement="cpu" AND host="a""#).unwrap();
+        db.delete(predicate).await.unwrap();
+
+        // data written after the delete is not affected by it
+        writer
+            .write_lp_string(&db, "cpu,host=a bar=4 12")
+            .await
+            .unwrap();
+
+        let expected = vec![
+            "+-----+------+------+",
+            "| bar | host | time |",
+            "+-----+------+------+",
+            "| 4   | a    | 12   |",
+            "| 2   | b    | 20   |",
+            "+-----+------+------+",
+        ];
+        let batches = run_query(&db, "select * from cpu order by time").await;
+        assert_table_eq!(expected, &batches);
+
+        // the columns needed to apply the delete need not be selected
+        let expected = vec![
+            "+-----+", "| bar |", "+-----+", "| 2   |", "| 4   |", "+-----+",
+        ];
+        let batches = run_query(&db, "select bar from cpu order by bar").await;
+        assert_table_eq!(expected, &batches);
+
+        let expected = vec![
+            "+-----+------+------+",
+            "| bar | host | time |",
+            "+-----+------+------+",
+            "| 3   | a    | 10   |",
+            "+-----+------+------+",
+        ];
+        let batches = run_query(&db, "select * from mem").await;
+        assert_table_eq!(expected, &batches);
+    }
+
+    #[tokio::test]
+    async fn partition_statuses() {
+        let db = make_db();
+        let mut writer = TestLPWriter::default();
+        let partition_key = "1970-01-01T00";
+
+        writer
+            .write_lp_string(&db, "cpu,host=a bar=1 10\ncpu,host=b bar=2 20")
+            .await
+            .unwrap();
+        let mb_chunk = db.rollover_partition(partition_key).await.unwrap();
+        db.load_chunk_to_read_buffer(partition_key, mb_chunk.id())
+            .await
+            .unwrap();
+        writer
+            .write_lp_string(&db, "cpu,host=a bar=3 30")
+            .await
+            .unwrap();
+        db.record_persisted_chunk(partition_key);
+
+        let statuses = db.partition_statuses().unwrap();
+        assert_eq!(statuses.len(), 1);
+        let status = &statuses[0];
+        assert_eq!(status.key, partition_key);
+        assert_eq!(status.series_count, 2);
+        // the rollover chunk is in both buffers
+        assert_eq!(status.point_count, 5);
+        assert_eq!(status.mutable_buffer_chunk_count, 2);
+        assert_eq!(status.read_buffer_chunk_count, 1);
+        assert!(status.open_chunk_size > 0);
+        assert!(status.mutable_buffer_size > status.open_chunk_size);
+        assert!(status.read_buffer_size > 0);
+        assert!(status.last_write_time.is_some());
+        assert_eq!(status.persisted_chunk_count, 1);
+    }
+
+    #[tokio::test]
+    async fn drop_expired_chunks() {
+        let db = make_db();
+        let mut writer = TestLPWriter::default();
+        let partition_key = "1970-01-01T00";
+
+        writer.write_lp_string(&db, "cpu bar=1 10").await.unwrap();
+        let mb_chunk = db.rollover_partition(partition_key).await.unwrap();
+        db.load_chunk_to_read_buffer(partition_key, mb_chunk.id())
+            .await
+            .unwrap();
+        db.drop_mutable_buffer_chunk(partition_key, mb_chunk.id())
+            .await
+            .unwrap();
+
+        writer.write_lp_string(&db, "cpu bar=2 20").await.unwrap();
+        db.rollover_partition(partition_key).await.unwrap();
+
+        writer.write_lp_string(&db, "cpu bar=3 30").await.unwrap();
+        writer.write_lp_string(&db, "cpu bar=4 5").await.unwrap();
+
+        assert_eq!(mutable_chunk_ids(&db, partition_key), vec![1, 2]);
+        assert_eq!(read_buffer_chunk_ids(&db, partition_key), vec![0]);
+
+        // nothing is older than the cutoff
+        assert_eq!(db.drop_expired_chunks(10).await.unwrap(), 0);
+
+        // the open chunk is kept even if all its rows are older
+        assert_eq!(db.drop_expired_chunks(31).await.unwrap(), 2);
+        assert_eq!(mutable_chunk_ids(&db, partition_key), vec![2]);
+        assert_eq!(
+            read_buffer_chunk_ids(&db, partition_key),
+            vec![] as Vec<u32>
+        );
+
+        let expected = vec![
+            "+-----+------+",
+            "| bar | time |",
+            "+-----+------+",
+            "| 4   | 5    |",
+            "| 3   | 30   |",
+            "+-----+------+",
+        ];
+        let batches = run_query(&db, "select * from cpu order by time").await;
+        assert_table_eq!(expected, &batches);
+    }
+
     #[tokio::test]
     async fn write_with_rollover() {
         let db = make_db();
@@ -540,7 +975,7 @@ mod tests {
             ..Default::default()
         };
 
-        let mut db = Db::new(
+        let db = Db::new(
             rules,
             Some(MutableBufferDb::new("foo")),
             read_buffer::Database::new(),
@@ -581,7 +1016,9 @@ mod tests {
             order: Order::Desc,
             sort: PartitionSort::LastWriteTime,
         };
-        db.rules.mutable_buffer_config = Some(mbconf);
+        let mut rules = db.rules();
+        rules.mutable_buffer_config = Some(mbconf);
+        db.set_rules(rules);
     }
 
     // run a sql query against the database, returning the results as record batches
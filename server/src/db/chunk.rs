@@ -1,7 +1,7 @@
 use arrow_deps::datafusion::physical_plan::SendableRecordBatchStream;
 use data_types::{schema::Schema, selection::Selection};
 use mutable_buffer::chunk::Chunk as MBChunk;
-use query::{exec::stringset::StringSet, predicate::Predicate, PartitionChunk};
+use query::{exec::stringset::StringSet, predicate::Predicate, pruning, PartitionChunk};
 use read_buffer::Database as ReadBufferDb;
 use snafu::{ResultExt, Snafu};
 
@@ -9,7 +9,8 @@ use std::sync::Arc;
 
 use super::{
     pred::to_read_buffer_predicate,
-    streams::{MutableBufferChunkStream, ReadFilterResultsStream},
+    streams::{MutableBufferChunkStream, ReadFilterResultsStream, TombstoneFilterStream},
+    tombstone::Tombstone,
 };
 
 use async_trait::async_trait;
@@ -58,6 +59,11 @@ pub enum DBChunk {
         chunk_id: u32,
     },
     ParquetFile, // TODO add appropriate type here
+    /// A chunk with rows hidden by deletes
+    WithTombstones {
+        chunk: Arc<DBChunk>,
+        tombstones: Vec<Arc<Tombstone>>,
+    },
 }
 
 impl DBChunk {
@@ -79,6 +85,15 @@ impl DBChunk {
             partition_key,
         })
     }
+
+    /// Hides the rows of `chunk` deleted by `tombstones`
+    pub fn with_tombstones(chunk: Arc<Self>, tombstones: Vec<Arc<Tombstone>>) -> Arc<Self> {
+        if tombstones.is_empty() {
+            chunk
+        } else {
+            Arc::new(Self::WithTombstones { chunk, tombstones })
+        }
+    }
 }
 
 #[async_trait]
@@ -90,6 +105,7 @@ impl PartitionChunk for DBChunk {
             Self::MutableBuffer { chunk } => chunk.id(),
             Self::ReadBuffer { chunk_id, .. } => *chunk_id,
             Self::ParquetFile => unimplemented!("parquet file not implemented"),
+            Self::WithTombstones { chunk, .. } => chunk.id(),
         }
     }
 
@@ -100,6 +116,7 @@ impl PartitionChunk for DBChunk {
             Self::MutableBuffer { chunk } => chunk.table_stats().context(MutableBufferChunk),
             Self::ReadBuffer { .. } => unimplemented!("read buffer not implemented"),
             Self::ParquetFile => unimplemented!("parquet file not implemented"),
+            Self::WithTombstones { chunk, .. } => chunk.table_stats(),
         }
     }
 
@@ -154,6 +171,8 @@ impl PartitionChunk for DBChunk {
             Self::ParquetFile => {
                 unimplemented!("parquet file not implemented")
             }
+            // the tables may only contain deleted rows
+            Self::WithTombstones { .. } => None,
         };
 
         // Prune out tables that should not be
@@ -215,6 +234,9 @@ impl PartitionChunk for DBChunk {
             DBChunk::ParquetFile => {
                 unimplemented!("parquet file not implemented for table schema")
             }
+            DBChunk::WithTombstones { chunk, .. } => {
+                chunk.table_schema(table_name, selection).await
+            }
         }
     }
 
@@ -232,6 +254,7 @@ impl PartitionChunk for DBChunk {
             Self::ParquetFile => {
                 unimplemented!("parquet file not implemented for has_table")
             }
+            Self::WithTombstones { chunk, .. } => chunk.has_table(table_name),
         }
     }
 
@@ -243,15 +266,17 @@ impl PartitionChunk for DBChunk {
     ) -> Result<SendableRecordBatchStream, Self::Error> {
         match self {
             Self::MutableBuffer { chunk } => {
-                // Note Mutable buffer doesn't support predicate
-                // pushdown (other than pruning out the entire chunk
-                // via `might_pass_predicate)
+                // Note Mutable buffer only supports pushing down the
+                // time range and tag values of the predicate (other
+                // than pruning out the entire chunk via
+                // `could_pass_predicate`)
                 let schema: Schema = self.table_schema(table_name, selection).await?;
 
                 Ok(Box::pin(MutableBufferChunkStream::new(
                     Arc::clone(&chunk),
                     schema.as_arrow(),
                     table_name,
+                    predicate,
                 )))
             }
             Self::ReadBuffer {
@@ -285,30 +310,95 @@ impl PartitionChunk for DBChunk {
             Self::ParquetFile => {
                 unimplemented!("parquet file not implemented for scan_data")
             }
+            Self::WithTombstones { chunk, tombstones } => {
+                let tombstones: Vec<_> = tombstones
+                    .iter()
+                    .filter(|t| t.applies_to_table(table_name))
+                    .cloned()
+                    .collect();
+                if tombstones.is_empty() {
+                    return chunk.read_filter(table_name, predicate, selection).await;
+                }
+
+                // read the columns needed to evaluate the tombstones as
+                // well as the selected ones, removing them again after
+                // filtering
+                let selected = match selection {
+                    Selection::All => {
+                        let stream = chunk
+                            .read_filter(table_name, predicate, Selection::All)
+                            .await?;
+                        return Ok(Box::pin(TombstoneFilterStream::new(
+                            stream, tombstones, None,
+                        )));
+                    }
+                    Selection::Some(selected) => selected,
+                };
+
+                let table_schema = chunk.table_schema(table_name, Selection::All).await?;
+                let mut columns = selected.to_vec();
+                for column in tombstones.iter().flat_map(|t| t.columns()) {
+                    if !columns.contains(&column) && table_schema.find_index_of(column).is_some() {
+                        columns.push(column);
+                    }
+                }
+
+                let stream = chunk
+                    .read_filter(table_name, predicate, Selection::Some(&columns))
+                    .await?;
+                let stream_schema = stream.schema();
+                let projection = selected
+                    .iter()
+                    .map(|column| stream_schema.index_of(column))
+                    .collect::<Result<Vec<_>, _>>()
+                    .context(ArrowConversion)?;
+
+                Ok(Box::pin(TombstoneFilterStream::new(
+                    stream,
+                    tombstones,
+                    Some(projection),
+                )))
+            }
         }
     }
 
-    fn could_pass_predicate(&self, _predicate: &Predicate) -> Result<bool> {
+    fn could_pass_predicate(&self, predicate: &Predicate) -> Result<bool> {
         match self {
-            Self::MutableBuffer { .. } => {
-                // For now, we might get an error if we try and
-                // compile a chunk predicate (e.g. for tables that
-                // don't exist in the chunk) which could signal the
-                // chunk can't pass the predicate.
-
-                // However, we can also get an error if there is some
-                // unsupported operation and we need a way to
-                // distinguish the two cases.
-                Ok(true)
+            Self::MutableBuffer { chunk } => {
+                let summaries = chunk.table_stats().context(MutableBufferChunk)?;
+                Ok(summaries
+                    .iter()
+                    .any(|summary| pruning::could_pass(summary, predicate)))
             }
-            Self::ReadBuffer { .. } => {
-                // TODO: ask Edd how he wants this wired up in the read buffer
-                Ok(true)
+            Self::ReadBuffer {
+                db,
+                partition_key,
+                chunk_id,
+            } => {
+                // the read buffer only keeps the time range of the whole chunk
+                let (min, max) = match db.chunk_time_range(partition_key, *chunk_id) {
+                    Some(range) => range,
+                    None => return Ok(true),
+                };
+                Ok(match &predicate.table_names {
+                    Some(table_names) => table_names.iter().any(|table_name| {
+                        self.has_table(table_name)
+                            && pruning::could_pass(
+                                &pruning::time_range_summary(table_name.as_str(), min, max),
+                                predicate,
+                            )
+                    }),
+                    None => {
+                        pruning::could_pass(&pruning::time_range_summary("", min, max), predicate)
+                    }
+                })
             }
             Self::ParquetFile => {
                 // TODO proper filtering for parquet files
                 Ok(true)
             }
+            // deleted rows can only make the chunk less likely to pass
+            Self::WithTombstones { chunk, .. } => chunk.could_pass_predicate(predicate),
         }
     }
 
@@ -348,6 +438,8 @@ impl PartitionChunk for DBChunk {
             Self::ParquetFile => {
                 unimplemented!("parquet file not implemented for column_names")
             }
+            // the columns may only have values in deleted rows
+            Self::WithTombstones { .. } => Ok(None),
         }
     }
 
@@ -384,6 +476,8 @@ impl PartitionChunk for DBChunk {
             Self::ParquetFile => {
                 unimplemented!("parquet file not implemented for column_values")
             }
+            // the values may only be in deleted rows
+            Self::WithTombstones { .. } => Ok(None),
         }
     }
 }
@@ -0,0 +1,794 @@
+//! The grammar of the predicates of deletes and reads.
+//!
+//! Deletes take the predicates of the InfluxDB 2.0 delete API, a
+//! conjunction of tag equalities, which reads extend as described by
+//! [`ReadPredicate`].
+
+use snafu::{ensure, Snafu};
+
+/// The pseudo column naming the measurement (table) in predicates
+const MEASUREMENT_COLUMN_NAME: &str = "_measurement";
+
+/// The error of a missing operator in a read predicate
+const READ_OPS_EXPECTED: &str =
+    "expected '=', '=~' or '!~', or to compare fields '!=', '<', '<=', '>' or '>='";
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display(
+        "Invalid delete predicate '{}' at position {}: {}",
+        predicate,
+        position,
+        reason
+    ))]
+    InvalidPredicate {
+        predicate: String,
+        position: usize,
+        reason: String,
+    },
+
+    #[snafu(display(
+        "Invalid field expression '{}' at position {}: {}",
+        expression,
+        position,
+        reason
+    ))]
+    InvalidFieldExpression {
+        expression: String,
+        position: usize,
+        reason: String,
+    },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// How a comparison of a predicate matches the values of its column
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ComparisonOp {
+    /// `column="value"`
+    Equal,
+    /// `column=~/pattern/`, values matching the regular expression
+    RegexMatch,
+    /// `column!~/pattern/`, values not matching the regular expression
+    RegexNotMatch,
+}
+
+/// A comparison of a column of a predicate to a value, or to the pattern
+/// of a regular expression
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Comparison {
+    pub column: String,
+    pub op: ComparisonOp,
+    pub value: String,
+}
+
+/// An arithmetic operator of a field expression
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArithmeticOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+}
+
+/// An arithmetic expression of the fields of a row, such as
+/// `value * 8 / 1024` or `usage_user + usage_system`
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldExpr {
+    Column(String),
+    Number(f64),
+    Binary {
+        left: Box<FieldExpr>,
+        op: ArithmeticOp,
+        right: Box<FieldExpr>,
+    },
+}
+
+impl FieldExpr {
+    /// Returns the names of the columns this expression is computed from
+    pub fn columns(&self) -> Vec<&str> {
+        match self {
+            Self::Column(column) => vec![column.as_str()],
+            Self::Number(_) => vec![],
+            Self::Binary { left, right, .. } => {
+                let mut columns = left.columns();
+                columns.extend(right.columns());
+                columns
+            }
+        }
+    }
+}
+
+/// How a comparison of field expressions compares their values
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldComparisonOp {
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+}
+
+/// A comparison of the values of two field expressions, such as
+/// `usage_user + usage_system > 90`
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldComparison {
+    pub left: FieldExpr,
+    pub op: FieldComparisonOp,
+    pub right: FieldExpr,
+}
+
+/// A logical combination of the comparisons of a predicate
+#[derive(Debug, Clone, PartialEq)]
+pub enum PredicateExpr {
+    Comparison(Comparison),
+    FieldComparison(FieldComparison),
+    And(Box<PredicateExpr>, Box<PredicateExpr>),
+    Or(Box<PredicateExpr>, Box<PredicateExpr>),
+    Not(Box<PredicateExpr>),
+}
+
+impl PredicateExpr {
+    /// Splits the expressions joined by the outermost `AND`s
+    pub fn into_conjuncts(self) -> Vec<Self> {
+        match self {
+            Self::And(left, right) => {
+                let mut conjuncts = left.into_conjuncts();
+                conjuncts.extend(right.into_conjuncts());
+                conjuncts
+            }
+            expr => vec![expr],
+        }
+    }
+
+    /// Joins `conjuncts` with `AND`, `None` if there are none
+    fn from_conjuncts(conjuncts: impl IntoIterator<Item = Self>) -> Option<Self> {
+        conjuncts
+            .into_iter()
+            .fold(None, |expr, conjunct| match expr {
+                Some(expr) => Some(Self::And(Box::new(expr), Box::new(conjunct))),
+                None => Some(conjunct),
+            })
+    }
+
+    /// Whether any of the comparisons of this expression are on `column`
+    fn compares(&self, column: &str) -> bool {
+        match self {
+            Self::Comparison(comparison) => comparison.column == column,
+            Self::FieldComparison(comparison) => comparison
+                .left
+                .columns()
+                .into_iter()
+                .chain(comparison.right.columns())
+                .any(|c| c == column),
+            Self::And(left, right) | Self::Or(left, right) => {
+                left.compares(column) || right.compares(column)
+            }
+            Self::Not(expr) => expr.compares(column),
+        }
+    }
+}
+
+/// A predicate restricting reads, in the syntax of delete predicates
+/// extended with:
+///
+/// * regular expressions: `column=~/pattern/` and `column!~/pattern/` compare
+///   the values of a tag to a regular expression, in which `\/` stands for `/`
+/// * `OR` and `NOT`, binding less and more tightly than `AND` respectively, and
+///   parentheses to group comparisons
+/// * comparisons of field expressions with `=`, `!=`, `<`, `<=`, `>` or `>=`,
+///   such as `usage_user + usage_system > 90`, where the expressions are
+///   columns and numbers joined by `+`, `-`, `*` and `/`. A column compared
+///   with `=` to a double quoted string is a tag comparison.
+///
+/// `_measurement` can only restrict the whole read to a table, so must be
+/// joined to the rest of the predicate by `AND`.
+///
+/// Column names containing operators or parentheses must be double quoted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReadPredicate {
+    /// If set, only rows of this table are read
+    pub table_name: Option<String>,
+
+    /// If set, only rows passing this expression are read
+    pub expr: Option<PredicateExpr>,
+}
+
+impl ReadPredicate {
+    pub fn try_new(predicate: &str) -> Result<Self> {
+        parse_predicate(predicate, true)
+    }
+}
+
+/// Parses an arithmetic expression of the fields of a row such as
+/// `value * 8 / 1024`, in the syntax of the field comparisons of read
+/// predicates
+pub fn parse_field_expr(expression: &str) -> Result<FieldExpr> {
+    let mut parser = Parser {
+        predicate: expression,
+        position: 0,
+        read: true,
+    };
+
+    let parse = |parser: &mut Parser<'_>| {
+        parser.skip_whitespace();
+        let expr = parser.field_expr()?;
+        if !parser.is_done() {
+            return parser.error("expected '+', '-', '*' or '/'");
+        }
+        if expr.columns().contains(&MEASUREMENT_COLUMN_NAME) {
+            parser.position = 0;
+            return parser.error("_measurement is not a field");
+        }
+        Ok(expr)
+    };
+
+    parse(&mut parser).map_err(|e| match e {
+        Error::InvalidPredicate {
+            position, reason, ..
+        } => Error::InvalidFieldExpression {
+            expression: expression.to_string(),
+            position,
+            reason,
+        },
+        e => e,
+    })
+}
+
+// parses `column="value" AND ...`, and if `read` is set the extensions
+// of read predicates
+pub(crate) fn parse_predicate(predicate: &str, read: bool) -> Result<ReadPredicate> {
+    let mut parser = Parser {
+        predicate,
+        position: 0,
+        read,
+    };
+
+    parser.skip_whitespace();
+    if parser.is_done() {
+        return Ok(ReadPredicate {
+            table_name: None,
+            expr: None,
+        });
+    }
+
+    let expr = parser.or_expr()?;
+    if !parser.is_done() {
+        return parser.error(if read {
+            "expected 'AND' or 'OR'"
+        } else {
+            "expected 'AND'"
+        });
+    }
+
+    // the conjuncts on the measurement restrict the table read
+    let mut table_name = None;
+    let mut conjuncts = vec![];
+    for conjunct in expr.into_conjuncts() {
+        match conjunct {
+            PredicateExpr::Comparison(comparison)
+                if comparison.column == MEASUREMENT_COLUMN_NAME =>
+            {
+                ensure!(
+                    table_name.is_none(),
+                    InvalidPredicate {
+                        predicate,
+                        position: 0usize,
+                        reason: "_measurement may only be specified once",
+                    }
+                );
+                table_name = Some(comparison.value);
+            }
+            conjunct => {
+                ensure!(
+                    !conjunct.compares(MEASUREMENT_COLUMN_NAME),
+                    InvalidPredicate {
+                        predicate,
+                        position: 0usize,
+                        reason: "_measurement can only be joined to the predicate with AND",
+                    }
+                );
+                conjuncts.push(conjunct);
+            }
+        }
+    }
+
+    Ok(ReadPredicate {
+        table_name,
+        expr: PredicateExpr::from_conjuncts(conjuncts),
+    })
+}
+
+struct Parser<'a> {
+    predicate: &'a str,
+    position: usize,
+    /// Whether the extensions of read predicates are parsed
+    read: bool,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.predicate[self.position..]
+    }
+
+    fn is_done(&self) -> bool {
+        self.rest().is_empty()
+    }
+
+    fn error<T>(&self, reason: impl Into<String>) -> Result<T> {
+        InvalidPredicate {
+            predicate: self.predicate,
+            position: self.position,
+            reason: reason.into(),
+        }
+        .fail()
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    fn expect(&mut self, token: &str) -> Result<()> {
+        if !self.rest().starts_with(token) {
+            return self.error(format!("expected '{}'", token));
+        }
+        self.position += token.len();
+        Ok(())
+    }
+
+    // consumes a case insensitive keyword if next, which must be followed
+    // by whitespace or, in read predicates, a parenthesis
+    fn keyword(&mut self, keyword: &str) -> bool {
+        let rest = self.rest();
+        let matches = rest.len() > keyword.len()
+            && rest.is_char_boundary(keyword.len())
+            && rest[..keyword.len()].eq_ignore_ascii_case(keyword)
+            && rest[keyword.len()..]
+                .starts_with(|c: char| c.is_whitespace() || (self.read && c == '('));
+        if matches {
+            self.position += keyword.len();
+        }
+        matches
+    }
+
+    // expressions joined by OR, which binds less tightly than AND
+    fn or_expr(&mut self) -> Result<PredicateExpr> {
+        let mut expr = self.and_expr()?;
+        loop {
+            self.skip_whitespace();
+            if !(self.read && self.keyword("or")) {
+                return Ok(expr);
+            }
+            self.skip_whitespace();
+            expr = PredicateExpr::Or(Box::new(expr), Box::new(self.and_expr()?));
+        }
+    }
+
+    fn and_expr(&mut self) -> Result<PredicateExpr> {
+        let mut expr = self.unary_expr()?;
+        loop {
+            self.skip_whitespace();
+            if !self.keyword("and") {
+                return Ok(expr);
+            }
+            self.skip_whitespace();
+            expr = PredicateExpr::And(Box::new(expr), Box::new(self.unary_expr()?));
+        }
+    }
+
+    // a comparison, or in read predicates a negated or parenthesized
+    // expression
+    fn unary_expr(&mut self) -> Result<PredicateExpr> {
+        if self.read && self.keyword("not") {
+            self.skip_whitespace();
+            return Ok(PredicateExpr::Not(Box::new(self.unary_expr()?)));
+        }
+        if self.read && self.rest().starts_with('(') {
+            self.position += 1;
+            self.skip_whitespace();
+            let expr = self.or_expr()?;
+            self.skip_whitespace();
+            self.expect(")")?;
+            return Ok(expr);
+        }
+        self.comparison()
+    }
+
+    // a comparison of a tag, or in read predicates of field expressions
+    fn comparison(&mut self) -> Result<PredicateExpr> {
+        if !self.read {
+            return self.tag_comparison().map(PredicateExpr::Comparison);
+        }
+
+        let start = self.position;
+        let left = self.field_expr()?;
+        self.skip_whitespace();
+        if matches!(left, FieldExpr::Column(_)) && self.at_tag_op() {
+            self.position = start;
+            return self.tag_comparison().map(PredicateExpr::Comparison);
+        }
+
+        let op = self.field_op()?;
+        self.skip_whitespace();
+        let right = self.field_expr()?;
+        let comparison = FieldComparison { left, op, right };
+        if PredicateExpr::FieldComparison(comparison.clone()).compares(MEASUREMENT_COLUMN_NAME) {
+            self.position = start;
+            return self.error("_measurement can only be compared with '='");
+        }
+        Ok(PredicateExpr::FieldComparison(comparison))
+    }
+
+    // whether the operator next compares a tag: to a regular expression, or
+    // with `=` to a string
+    fn at_tag_op(&self) -> bool {
+        let rest = self.rest();
+        rest.starts_with("=~")
+            || rest.starts_with("!~")
+            || (rest.starts_with('=') && rest[1..].trim_start().starts_with('"'))
+    }
+
+    fn tag_comparison(&mut self) -> Result<Comparison> {
+        let column = self.column()?;
+        self.skip_whitespace();
+        let op = self.op()?;
+        self.skip_whitespace();
+
+        let value = match op {
+            ComparisonOp::Equal => self.quoted()?,
+            _ if column == MEASUREMENT_COLUMN_NAME => {
+                return self.error("_measurement can only be compared with '='")
+            }
+            ComparisonOp::RegexMatch | ComparisonOp::RegexNotMatch => self.pattern()?,
+        };
+        Ok(Comparison { column, op, value })
+    }
+
+    // a column name, either bare or double quoted. In read predicates, bare
+    // names end at arithmetic and comparison operators.
+    fn column(&mut self) -> Result<String> {
+        if self.rest().starts_with('"') {
+            return self.quoted();
+        }
+
+        let rest = self.rest();
+        let terminators = if self.read { "=!()<>+-*/" } else { "=!()" };
+        let len = rest
+            .find(|c: char| c.is_whitespace() || terminators.contains(c))
+            .unwrap_or_else(|| rest.len());
+        if len == 0 {
+            return self.error("expected a column name");
+        }
+        self.position += len;
+        Ok(rest[..len].to_string())
+    }
+
+    // the operator of a comparison, `=`, or in read predicates `=~` or `!~`
+    fn op(&mut self) -> Result<ComparisonOp> {
+        let rest = self.rest();
+        let (op, len) = if rest.starts_with("=~") {
+            (ComparisonOp::RegexMatch, 2)
+        } else if rest.starts_with("!~") {
+            (ComparisonOp::RegexNotMatch, 2)
+        } else if rest.starts_with('=') {
+            (ComparisonOp::Equal, 1)
+        } else if self.read {
+            return self.error(READ_OPS_EXPECTED);
+        } else {
+            return self.error("expected '='");
+        };
+        if op != ComparisonOp::Equal && !self.read {
+            return self.error("regular expressions are only supported when reading");
+        }
+        self.position += len;
+        Ok(op)
+    }
+
+    // the operator of a comparison of field expressions
+    fn field_op(&mut self) -> Result<FieldComparisonOp> {
+        let rest = self.rest();
+        let (op, len) = if rest.starts_with("!=") {
+            (FieldComparisonOp::NotEqual, 2)
+        } else if rest.starts_with("<=") {
+            (FieldComparisonOp::LessEqual, 2)
+        } else if rest.starts_with(">=") {
+            (FieldComparisonOp::GreaterEqual, 2)
+        } else if rest.starts_with('<') {
+            (FieldComparisonOp::Less, 1)
+        } else if rest.starts_with('>') {
+            (FieldComparisonOp::Greater, 1)
+        } else if rest.starts_with('=') {
+            (FieldComparisonOp::Equal, 1)
+        } else {
+            return self.error(READ_OPS_EXPECTED);
+        };
+        self.position += len;
+        Ok(op)
+    }
+
+    // terms joined by `+` and `-`, which bind less tightly than `*` and `/`
+    fn field_expr(&mut self) -> Result<FieldExpr> {
+        let mut expr = self.field_term()?;
+        loop {
+            self.skip_whitespace();
+            let op = match self.rest().chars().next() {
+                Some('+') => ArithmeticOp::Add,
+                Some('-') => ArithmeticOp::Subtract,
+                _ => return Ok(expr),
+            };
+            self.position += 1;
+            self.skip_whitespace();
+            expr = FieldExpr::Binary {
+                left: Box::new(expr),
+                op,
+                right: Box::new(self.field_term()?),
+            };
+        }
+    }
+
+    // operands joined by `*` and `/`
+    fn field_term(&mut self) -> Result<FieldExpr> {
+        let mut expr = self.operand()?;
+        loop {
+            self.skip_whitespace();
+            let op = match self.rest().chars().next() {
+                Some('*') => ArithmeticOp::Multiply,
+                Some('/') => ArithmeticOp::Divide,
+                _ => return Ok(expr),
+            };
+            self.position += 1;
+            self.skip_whitespace();
+            expr = FieldExpr::Binary {
+                left: Box::new(expr),
+                op,
+                right: Box::new(self.operand()?),
+            };
+        }
+    }
+
+    // a column, or a number such as `8`, `-0.5` or `1e3`
+    fn operand(&mut self) -> Result<FieldExpr> {
+        let rest = self.rest();
+        if !rest.starts_with(|c: char| c.is_ascii_digit() || c == '.' || c == '-') {
+            return self.column().map(FieldExpr::Column);
+        }
+
+        let bytes = rest.as_bytes();
+        let mut len = 0;
+        while len < bytes.len() {
+            // signs lead the number or its exponent
+            let sign = matches!(bytes[len], b'-' | b'+')
+                && (len == 0 || matches!(bytes[len - 1], b'e' | b'E'));
+            if !(bytes[len].is_ascii_digit() || matches!(bytes[len], b'.' | b'e' | b'E') || sign) {
+                break;
+            }
+            len += 1;
+        }
+        match rest[..len].parse() {
+            Ok(number) => {
+                self.position += len;
+                Ok(FieldExpr::Number(number))
+            }
+            Err(_) => self.error("expected a number"),
+        }
+    }
+
+    // the pattern of a regular expression between slashes, in which `\/`
+    // stands for `/` and other escapes are kept for the regular expression
+    fn pattern(&mut self) -> Result<String> {
+        self.expect("/")?;
+
+        let mut pattern = String::new();
+        let mut chars = self.rest().char_indices().peekable();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '/' => {
+                    self.position += i + 1;
+                    return Ok(pattern);
+                }
+                '\\' if matches!(chars.peek(), Some((_, '/'))) => {
+                    chars.next();
+                    pattern.push('/');
+                }
+                c => pattern.push(c),
+            }
+        }
+
+        self.position = self.predicate.len();
+        self.error("unterminated regular expression")
+    }
+
+    // a double quoted string, in which `\` escapes the next character
+    fn quoted(&mut self) -> Result<String> {
+        self.expect("\"")?;
+
+        let mut value = String::new();
+        let mut chars = self.rest().char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.position += i + 1;
+                    return Ok(value);
+                }
+                '\\' => match chars.next() {
+                    Some((_, c)) => value.push(c),
+                    None => break,
+                },
+                c => value.push(c),
+            }
+        }
+
+        self.position = self.predicate.len();
+        self.error("unterminated string")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_predicates() {
+        let p = ReadPredicate::try_new(
+            r#"_measurement="cpu" AND host =~ /^server-\d+$/ and region!~/us\/west/ AND "a b"="c""#,
+        )
+        .unwrap();
+        assert_eq!(p.table_name, Some("cpu".to_string()));
+        let comparison = |column: &str, op, value: &str| Comparison {
+            column: column.to_string(),
+            op,
+            value: value.to_string(),
+        };
+        let equal = |column: &str, value: &str| {
+            PredicateExpr::Comparison(comparison(column, ComparisonOp::Equal, value))
+        };
+        assert_eq!(
+            p.expr.unwrap().into_conjuncts(),
+            vec![
+                PredicateExpr::Comparison(comparison(
+                    "host",
+                    ComparisonOp::RegexMatch,
+                    r"^server-\d+$"
+                )),
+                PredicateExpr::Comparison(comparison(
+                    "region",
+                    ComparisonOp::RegexNotMatch,
+                    "us/west"
+                )),
+                equal("a b", "c"),
+            ]
+        );
+
+        // NOT binds more tightly than AND, which binds more tightly than OR
+        let p = ReadPredicate::try_new(
+            r#"_measurement="cpu" AND (a="1" OR b="2" AND NOT c="3" or not(d="4" OR e="5"))"#,
+        )
+        .unwrap();
+        assert_eq!(p.table_name, Some("cpu".to_string()));
+        let or = |left, right| PredicateExpr::Or(Box::new(left), Box::new(right));
+        let and = |left, right| PredicateExpr::And(Box::new(left), Box::new(right));
+        let not = |expr| PredicateExpr::Not(Box::new(expr));
+        assert_eq!(
+            p.expr.unwrap(),
+            or(
+                or(equal("a", "1"), and(equal("b", "2"), not(equal("c", "3")))),
+                not(or(equal("d", "4"), equal("e", "5")))
+            )
+        );
+
+        let p = ReadPredicate::try_new(r#"( a="1" OR b="2" ) AND c="3""#).unwrap();
+        assert_eq!(
+            p.expr.unwrap(),
+            and(or(equal("a", "1"), equal("b", "2")), equal("c", "3"))
+        );
+
+        // field comparisons, with * binding more tightly than +
+        let p = ReadPredicate::try_new(
+            r#"host="a" AND usage_user + usage_system*2 >= 90 AND "free mem" != -1e3"#,
+        )
+        .unwrap();
+        let column = |name: &str| FieldExpr::Column(name.to_string());
+        let binary = |left, op, right| FieldExpr::Binary {
+            left: Box::new(left),
+            op,
+            right: Box::new(right),
+        };
+        let compare =
+            |left, op, right| PredicateExpr::FieldComparison(FieldComparison { left, op, right });
+        assert_eq!(
+            p.expr.unwrap().into_conjuncts(),
+            vec![
+                equal("host", "a"),
+                compare(
+                    binary(
+                        column("usage_user"),
+                        ArithmeticOp::Add,
+                        binary(
+                            column("usage_system"),
+                            ArithmeticOp::Multiply,
+                            FieldExpr::Number(2.0)
+                        )
+                    ),
+                    FieldComparisonOp::GreaterEqual,
+                    FieldExpr::Number(90.0)
+                ),
+                compare(
+                    column("free mem"),
+                    FieldComparisonOp::NotEqual,
+                    FieldExpr::Number(-1000.0)
+                ),
+            ]
+        );
+
+        let cases = vec![
+            (r#"host"#, "expected '=', '=~' or '!~'"),
+            (r#"(host="a""#, "expected ')'"),
+            (r#"host="a" region="b""#, "expected 'AND' or 'OR'"),
+            (r#"NOT"#, "expected '='"),
+            (
+                r#"host="a" OR _measurement="cpu""#,
+                "_measurement can only be joined to the predicate with AND",
+            ),
+            (r#"host=~"a""#, "expected '/'"),
+            (r#"host!~/a"#, "unterminated regular expression"),
+            (
+                r#"_measurement=~/cpu/"#,
+                "_measurement can only be compared with '='",
+            ),
+            (r#"usage >"#, "expected a column name"),
+            (r#"usage + 1"#, "or to compare fields"),
+            (r#"usage > -x"#, "expected a number"),
+            (
+                r#"_measurement > 1"#,
+                "_measurement can only be compared with '='",
+            ),
+        ];
+        for (predicate, expected) in cases {
+            let err = ReadPredicate::try_new(predicate).unwrap_err();
+            assert!(
+                err.to_string().contains(expected),
+                "predicate '{}': expected '{}' in '{}'",
+                predicate,
+                expected,
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn field_exprs() {
+        let expr = parse_field_expr(" value * 8 / 1024 ").unwrap();
+        assert_eq!(
+            expr,
+            FieldExpr::Binary {
+                left: Box::new(FieldExpr::Binary {
+                    left: Box::new(FieldExpr::Column("value".to_string())),
+                    op: ArithmeticOp::Multiply,
+                    right: Box::new(FieldExpr::Number(8.0)),
+                }),
+                op: ArithmeticOp::Divide,
+                right: Box::new(FieldExpr::Number(1024.0)),
+            }
+        );
+        assert_eq!(expr.columns(), vec!["value"]);
+
+        let cases = vec![
+            ("value *", "expected a column name"),
+            ("value 8", "expected '+', '-', '*' or '/'"),
+            ("_measurement", "_measurement is not a field"),
+        ];
+        for (expression, expected) in cases {
+            let err = parse_field_expr(expression).unwrap_err();
+            assert!(matches!(err, Error::InvalidFieldExpression { .. }));
+            assert!(
+                err.to_string().contains(expected),
+                "expression '{}': expected '{}' in '{}'",
+                expression,
+                expected,
+                err
+            );
+        }
+    }
+}
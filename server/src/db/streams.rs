@@ -6,16 +6,21 @@ use arrow_deps::{
         error::{ArrowError, Result as ArrowResult},
         record_batch::RecordBatch,
     },
-    datafusion::physical_plan::RecordBatchStream,
+    datafusion::physical_plan::{RecordBatchStream, SendableRecordBatchStream},
 };
 use data_types::selection::Selection;
+use futures::{FutureExt, StreamExt};
 use mutable_buffer::chunk::Chunk as MBChunk;
+use query::predicate::{string_equalities, Predicate, TimestampRange};
 use read_buffer::ReadFilterResults;
 
+use super::tombstone::{filter_deleted, project, project_schema, Tombstone};
+
 use std::{
     sync::Arc,
     task::{Context, Poll},
 };
+use tokio::task::JoinHandle;
 
 use snafu::{ResultExt, Snafu};
 
@@ -41,6 +46,10 @@ pub(crate) struct MutableBufferChunkStream {
     schema: SchemaRef,
     chunk: Arc<MBChunk>,
     table_name: Arc<String>,
+    /// Only rows within this range are returned, if set
+    range: Option<TimestampRange>,
+    /// Only rows whose tags have these values are returned
+    tag_values: Vec<(String, String)>,
 
     /// Vector of record batches to send in reverse order (send data[len-1]
     /// next) Is None until the first call to poll_next
@@ -48,11 +57,23 @@ pub(crate) struct MutableBufferChunkStream {
 }
 
 impl MutableBufferChunkStream {
-    pub fn new(chunk: Arc<MBChunk>, schema: SchemaRef, table_name: impl Into<String>) -> Self {
+    pub fn new(
+        chunk: Arc<MBChunk>,
+        schema: SchemaRef,
+        table_name: impl Into<String>,
+        predicate: &Predicate,
+    ) -> Self {
+        let tag_values = string_equalities(&predicate.exprs)
+            .into_iter()
+            .map(|(tag, value)| (tag.to_string(), value.to_string()))
+            .collect();
+
         Self {
             chunk,
             schema,
             table_name: Arc::new(table_name.into()),
+            range: predicate.range,
+            tag_values,
             data: None,
         }
     }
@@ -71,10 +92,23 @@ impl MutableBufferChunkStream {
                 .map(|f| f.name() as &str)
                 .collect::<Vec<_>>();
             let selection = Selection::Some(&selected_cols);
+            let tag_values = self
+                .tag_values
+                .iter()
+                .map(|(tag, value)| (tag.as_str(), value.as_str()))
+                .collect::<Vec<_>>();
 
+            // the rows are filtered while converting them, so that the tag
+            // values of the rows filtered out are never materialized
             let mut data = Vec::new();
             self.chunk
-                .table_to_arrow(&mut data, self.table_name.as_ref(), selection)
+                .table_to_arrow_filtered(
+                    &mut data,
+                    self.table_name.as_ref(),
+                    selection,
+                    self.range,
+                    &tag_values,
+                )
                 .context(GettingTableData {
                     table_name: self.table_name.as_ref(),
                     chunk_id: self.chunk.id(),
@@ -110,36 +144,217 @@ impl futures::Stream for MutableBufferChunkStream {
     // TODO is there a useful size_hint to pass?
 }
 
-/// Adapter which will take a ReadFilterResults and make it an async stream
-pub struct ReadFilterResultsStream {
-    read_results: ReadFilterResults,
+/// Adapter which will take a ReadFilterResults and make it an async stream.
+///
+/// The results are read ahead: while the query processes a record batch,
+/// the next one is already being decoded from the row groups of the chunk
+/// on a blocking thread, so that scanning a chunk doesn't alternate between
+/// decoding and processing its data. Only one batch is read ahead, so that
+/// a slow query doesn't hold the decoded data of the whole chunk.
+pub struct ReadFilterResultsStream<R = ReadFilterResults> {
     schema: SchemaRef,
+    /// Decodes the next batch, handing the results back along with it.
+    /// None once all the batches were returned.
+    next: Option<JoinHandle<(R, Option<RecordBatch>)>>,
 }
 
-impl ReadFilterResultsStream {
-    pub fn new(read_results: ReadFilterResults, schema: SchemaRef) -> Self {
+impl<R> ReadFilterResultsStream<R>
+where
+    R: Iterator<Item = RecordBatch> + Send + 'static,
+{
+    /// Creates the stream, starting to decode the first batch of
+    /// `read_results` right away
+    pub fn new(read_results: R, schema: SchemaRef) -> Self {
         Self {
-            read_results,
             schema,
+            next: Some(read_ahead(read_results)),
         }
     }
 }
 
-impl RecordBatchStream for ReadFilterResultsStream {
+/// Decodes the next batch of `read_results` on a blocking thread
+fn read_ahead<R>(mut read_results: R) -> JoinHandle<(R, Option<RecordBatch>)>
+where
+    R: Iterator<Item = RecordBatch> + Send + 'static,
+{
+    tokio::task::spawn_blocking(move || {
+        let batch = read_results.next();
+        (read_results, batch)
+    })
+}
+
+impl<R> RecordBatchStream for ReadFilterResultsStream<R>
+where
+    R: Iterator<Item = RecordBatch> + Send + 'static,
+{
     fn schema(&self) -> SchemaRef {
         Arc::clone(&self.schema)
     }
 }
 
-impl futures::Stream for ReadFilterResultsStream {
+impl<R> futures::Stream for ReadFilterResultsStream<R>
+where
+    R: Iterator<Item = RecordBatch> + Send + 'static,
+{
     type Item = ArrowResult<RecordBatch>;
 
     fn poll_next(
         mut self: std::pin::Pin<&mut Self>,
-        _: &mut Context<'_>,
+        cx: &mut Context<'_>,
     ) -> Poll<Option<Self::Item>> {
-        Poll::Ready(Ok(self.read_results.next()).transpose())
+        let next = match self.next.as_mut() {
+            Some(next) => next,
+            None => return Poll::Ready(None),
+        };
+
+        let result = futures::ready!(next.poll_unpin(cx));
+        self.next = None;
+        Poll::Ready(match result {
+            Ok((read_results, Some(batch))) => {
+                self.next = Some(read_ahead(read_results));
+                Some(Ok(batch))
+            }
+            Ok((_, None)) => None,
+            Err(e) => Some(Err(ArrowError::ExternalError(Box::new(e)))),
+        })
     }
 
     // TODO is there a useful size_hint to pass?
 }
+
+/// Adapter which removes the rows hidden by tombstones from the record
+/// batches of another stream, optionally keeping only some of the columns
+/// (those at the indices in `projection`) afterwards
+pub(crate) struct TombstoneFilterStream {
+    inner: SendableRecordBatchStream,
+    tombstones: Vec<Arc<Tombstone>>,
+    projection: Option<Vec<usize>>,
+    schema: SchemaRef,
+}
+
+impl TombstoneFilterStream {
+    pub fn new(
+        inner: SendableRecordBatchStream,
+        tombstones: Vec<Arc<Tombstone>>,
+        projection: Option<Vec<usize>>,
+    ) -> Self {
+        let schema = inner.schema();
+        let schema = match &projection {
+            Some(projection) => Arc::new(project_schema(&schema, projection)),
+            None => schema,
+        };
+
+        Self {
+            inner,
+            tombstones,
+            projection,
+            schema,
+        }
+    }
+
+    fn filter(&self, batch: RecordBatch) -> ArrowResult<RecordBatch> {
+        let batch = filter_deleted(batch, &self.tombstones)?;
+        match &self.projection {
+            Some(projection) => project(&batch, projection),
+            None => Ok(batch),
+        }
+    }
+}
+
+impl RecordBatchStream for TombstoneFilterStream {
+    fn schema(&self) -> SchemaRef {
+        Arc::clone(&self.schema)
+    }
+}
+
+impl futures::Stream for TombstoneFilterStream {
+    type Item = ArrowResult<RecordBatch>;
+
+    fn poll_next(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        let batch = self.inner.poll_next_unpin(cx);
+        batch.map(|batch| batch.map(|batch| batch.and_then(|batch| self.filter(batch))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_deps::arrow::{
+        array::{ArrayRef, Int64Array},
+        datatypes::{DataType, Field, Schema},
+    };
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    /// Yields `count` batches, counting how many were decoded
+    struct CountingResults {
+        schema: SchemaRef,
+        count: i64,
+        decoded: Arc<AtomicUsize>,
+    }
+
+    impl Iterator for CountingResults {
+        type Item = RecordBatch;
+
+        fn next(&mut self) -> Option<RecordBatch> {
+            let decoded = self.decoded.load(Ordering::SeqCst) as i64;
+            if decoded == self.count {
+                return None;
+            }
+            self.decoded.fetch_add(1, Ordering::SeqCst);
+
+            let array = Arc::new(Int64Array::from(vec![decoded])) as ArrayRef;
+            Some(RecordBatch::try_new(Arc::clone(&self.schema), vec![array]).unwrap())
+        }
+    }
+
+    /// Waits for the background decoding of the batches to settle
+    async fn decoded_after_settling(decoded: &AtomicUsize) -> usize {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        decoded.load(Ordering::SeqCst)
+    }
+
+    #[tokio::test]
+    async fn read_filter_results_are_read_ahead() {
+        let schema = Arc::new(Schema::new(vec![Field::new("v", DataType::Int64, false)]));
+        let decoded = Arc::new(AtomicUsize::new(0));
+        let mut stream = ReadFilterResultsStream::new(
+            CountingResults {
+                schema: Arc::clone(&schema),
+                count: 3,
+                decoded: Arc::clone(&decoded),
+            },
+            schema,
+        );
+
+        // the first batch is decoded before it is asked for
+        assert_eq!(decoded_after_settling(&decoded).await, 1);
+
+        // and the next one while the query processes it, but no more
+        let first = stream.next().await.unwrap().unwrap();
+        assert_eq!(decoded_after_settling(&decoded).await, 2);
+
+        let mut values = vec![first];
+        while let Some(batch) = stream.next().await {
+            values.push(batch.unwrap());
+        }
+        let values: Vec<_> = values
+            .iter()
+            .map(|batch| {
+                batch
+                    .column(0)
+                    .as_any()
+                    .downcast_ref::<Int64Array>()
+                    .unwrap()
+                    .value(0)
+            })
+            .collect();
+        assert_eq!(values, vec![0, 1, 2]);
+        assert!(stream.next().await.is_none());
+    }
+}
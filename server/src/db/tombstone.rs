@@ -0,0 +1,313 @@
+//! Tombstones record deletes of data from a database.
+//!
+//! Chunks can not be modified in place, so rather than removing data a
+//! delete records a tombstone that hides the matching rows of the chunks
+//! that existed when the delete was issued. Data written afterwards, even if
+//! it matches the delete, is not affected.
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use arrow_deps::arrow::{
+    array::{ArrayRef, BooleanArray},
+    compute::kernels::boolean::{and, not, or},
+    datatypes::Schema as ArrowSchema,
+    error::Result as ArrowResult,
+    record_batch::RecordBatch,
+};
+use chrono::{DateTime, Utc};
+use data_types::TIME_COLUMN_NAME;
+use query::{filter, predicate::TimestampRange};
+use serde::{Deserialize, Serialize};
+use snafu::{ensure, ResultExt, Snafu};
+
+use super::predicate::{self, parse_predicate, PredicateExpr, ReadPredicate};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Invalid delete time range: start {} is after stop {}", start, stop))]
+    InvalidTimeRange { start: i64, stop: i64 },
+
+    #[snafu(display("{}", source))]
+    InvalidPredicate { source: predicate::Error },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The rows to delete: those in a time range, optionally restricted to a
+/// single table and to rows with specific tag values.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeletePredicate {
+    /// Timestamps of the rows to delete, in nanoseconds
+    pub range: (i64, i64),
+
+    /// If set, only rows of this table are deleted
+    pub table_name: Option<String>,
+
+    /// Only rows where each of these columns has the given value are
+    /// deleted
+    pub tags: Vec<(String, String)>,
+}
+
+impl DeletePredicate {
+    /// Creates a predicate deleting the rows with timestamps between
+    /// `start` and `stop` (both inclusive) matching `predicate`.
+    ///
+    /// `predicate` uses the syntax of the InfluxDB 2.0 delete API: zero or
+    /// more `column="value"` comparisons joined by `AND`, where the
+    /// `_measurement` column restricts the delete to a table. An empty
+    /// predicate deletes all rows in the time range.
+    pub fn try_new(start: i64, stop: i64, predicate: &str) -> Result<Self> {
+        ensure!(start <= stop, InvalidTimeRange { start, stop });
+
+        let ReadPredicate { table_name, expr } =
+            parse_predicate(predicate, false).context(InvalidPredicate)?;
+        // without reads' extensions, the predicate is a conjunction of
+        // equalities
+        let tags = expr
+            .map(PredicateExpr::into_conjuncts)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|expr| match expr {
+                PredicateExpr::Comparison(comparison) => {
+                    Some((comparison.column, comparison.value))
+                }
+                _ => None,
+            })
+            .collect();
+
+        Ok(Self {
+            range: (start, stop),
+            table_name,
+            tags,
+        })
+    }
+
+    fn timestamp_range(&self) -> TimestampRange {
+        let (start, stop) = self.range;
+        TimestampRange::new(start, stop.saturating_add(1))
+    }
+}
+
+/// A delete as issued to a database, persisted and sent to the other
+/// members of the cluster so that it applies again when writes are replayed
+/// at startup, and to the replicas of the data it deletes
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeleteRecord {
+    pub predicate: DeletePredicate,
+
+    /// The id of the server the delete was issued on
+    pub issued_by: u32,
+
+    pub issued_at: DateTime<Utc>,
+
+    /// For each partition of the write buffer, the offset of its next write
+    /// when the delete was issued. When replaying, the delete applies once
+    /// the writes before these offsets are replayed.
+    pub write_buffer_offsets: BTreeMap<u32, i64>,
+}
+
+impl DeleteRecord {
+    /// Whether `other` records the same delete, e.g. received again from
+    /// another member
+    pub fn is_same(&self, other: &Self) -> bool {
+        self.issued_by == other.issued_by && self.issued_at == other.issued_at
+    }
+}
+
+/// A delete, along with the chunks it applies to
+#[derive(Debug)]
+pub struct Tombstone {
+    predicate: DeletePredicate,
+
+    /// For each partition, the id of the last chunk that existed when the
+    /// delete was issued
+    last_chunk_ids: BTreeMap<String, u32>,
+}
+
+impl Tombstone {
+    pub fn new(predicate: DeletePredicate, last_chunk_ids: BTreeMap<String, u32>) -> Self {
+        Self {
+            predicate,
+            last_chunk_ids,
+        }
+    }
+
+    pub fn predicate(&self) -> &DeletePredicate {
+        &self.predicate
+    }
+
+    /// Returns true if this tombstone hides rows of the chunk
+    pub fn applies_to(&self, partition_key: &str, chunk_id: u32) -> bool {
+        matches!(self.last_chunk_ids.get(partition_key), Some(&last) if chunk_id <= last)
+    }
+
+    /// Returns true if this tombstone may hide rows of `table_name`
+    pub fn applies_to_table(&self, table_name: &str) -> bool {
+        match &self.predicate.table_name {
+            Some(name) => name == table_name,
+            None => true,
+        }
+    }
+
+    /// The columns needed to evaluate this tombstone
+    pub fn columns(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(TIME_COLUMN_NAME).chain(
+            self.predicate
+                .tags
+                .iter()
+                .map(|(column, _)| column.as_str()),
+        )
+    }
+}
+
+/// Removes the rows hidden by any of `tombstones` from `batch`, which must
+/// contain the columns needed to evaluate them. Tombstones that compare a
+/// column not in `batch` do not hide any rows, as the column is null.
+pub(crate) fn filter_deleted(
+    batch: RecordBatch,
+    tombstones: &[Arc<Tombstone>],
+) -> ArrowResult<RecordBatch> {
+    let schema = batch.schema();
+    // the time column is needed to evaluate any tombstone
+    schema.index_of(TIME_COLUMN_NAME)?;
+
+    // the rows hidden by any of the tombstones so far
+    let mut deleted: Option<BooleanArray> = None;
+    for tombstone in tombstones {
+        let predicate = tombstone.predicate();
+
+        let tag_columns: Option<Vec<(&ArrayRef, &str)>> = predicate
+            .tags
+            .iter()
+            .map(|(column, value)| {
+                schema
+                    .index_of(column)
+                    .ok()
+                    .map(|index| (batch.column(index), value.as_str()))
+            })
+            .collect();
+        let tag_columns = match tag_columns {
+            Some(tag_columns) => tag_columns,
+            None => continue,
+        };
+
+        let mut matches = filter::time_range_mask(&batch, predicate.timestamp_range())?
+            .expect("batch has a time column");
+        for (column, value) in tag_columns {
+            matches = and(&matches, &filter::string_eq_mask(column, value)?)?;
+        }
+
+        deleted = Some(match deleted {
+            Some(deleted) => or(&deleted, &matches)?,
+            None => matches,
+        });
+    }
+
+    match deleted {
+        Some(deleted) => filter::filter_batch(batch, &not(&deleted)?),
+        None => Ok(batch),
+    }
+}
+
+/// Returns the schema of the fields at `indices` of `schema`
+pub(crate) fn project_schema(schema: &ArrowSchema, indices: &[usize]) -> ArrowSchema {
+    let fields = indices.iter().map(|&i| schema.field(i).clone()).collect();
+    ArrowSchema::new_with_metadata(fields, schema.metadata().clone())
+}
+
+/// Returns the columns at `indices` of `batch`
+pub(crate) fn project(batch: &RecordBatch, indices: &[usize]) -> ArrowResult<RecordBatch> {
+    let schema = project_schema(&batch.schema(), indices);
+    let columns = indices
+        .iter()
+        .map(|&i| Arc::clone(batch.column(i)))
+        .collect();
+
+    RecordBatch::try_new(Arc::new(schema), columns)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(tags: &[(&str, &str)]) -> Vec<(String, String)> {
+        tags.iter()
+            .map(|(c, v)| (c.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn parse_predicates() {
+        let p = DeletePredicate::try_new(1, 10, "").unwrap();
+        assert_eq!(p.range, (1, 10));
+        assert_eq!(p.table_name, None);
+        assert!(p.tags.is_empty());
+
+        let p = DeletePredicate::try_new(1, 10, r#"_measurement="cpu""#).unwrap();
+        assert_eq!(p.table_name, Some("cpu".to_string()));
+        assert!(p.tags.is_empty());
+
+        let p = DeletePredicate::try_new(
+            1,
+            10,
+            r#" _measurement = "cpu" AND host="a \"b\"" and "region name"="us west" "#,
+        )
+        .unwrap();
+        assert_eq!(p.table_name, Some("cpu".to_string()));
+        assert_eq!(
+            p.tags,
+            tags(&[("host", r#"a "b""#), ("region name", "us west")])
+        );
+    }
+
+    #[test]
+    fn invalid_predicates() {
+        let cases = vec![
+            (r#"host"#, "expected '='"),
+            (r#"host=a"#, "expected '\"'"),
+            (r#"host="a"#, "unterminated string"),
+            (r#"host="a" region="b""#, "expected 'AND'"),
+            (r#"host="a" OR region="b""#, "expected 'AND'"),
+            (r#"host="a" AND"#, "expected 'AND'"),
+            (r#"="a""#, "expected a column name"),
+            (
+                r#"_measurement="cpu" AND _measurement="mem""#,
+                "_measurement may only be specified once",
+            ),
+            (
+                r#"host=~/a/"#,
+                "regular expressions are only supported when reading",
+            ),
+        ];
+
+        for (predicate, expected) in cases {
+            let err = DeletePredicate::try_new(1, 10, predicate).unwrap_err();
+            assert!(
+                err.to_string().contains(expected),
+                "predicate '{}': expected '{}' in '{}'",
+                predicate,
+                expected,
+                err
+            );
+        }
+
+        let err = DeletePredicate::try_new(10, 1, "").unwrap_err();
+        assert!(matches!(err, Error::InvalidTimeRange { .. }));
+    }
+
+    #[test]
+    fn tombstone_applies_to() {
+        let predicate = DeletePredicate::try_new(1, 10, r#"_measurement="cpu""#).unwrap();
+        let mut last_chunk_ids = BTreeMap::new();
+        last_chunk_ids.insert("1970-01-01T00".to_string(), 1);
+        let tombstone = Tombstone::new(predicate, last_chunk_ids);
+
+        assert!(tombstone.applies_to("1970-01-01T00", 0));
+        assert!(tombstone.applies_to("1970-01-01T00", 1));
+        assert!(!tombstone.applies_to("1970-01-01T00", 2));
+        assert!(!tombstone.applies_to("1970-01-01T01", 0));
+
+        assert!(tombstone.applies_to_table("cpu"));
+        assert!(!tombstone.applies_to_table("mem"));
+    }
+}
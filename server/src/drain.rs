@@ -0,0 +1,130 @@
+//! Draining a server before it is terminated, e.g. for a rolling upgrade.
+//!
+//! Once a drain starts the server rejects writes, then persists what it
+//! holds only in memory: the open segments of its WAL buffers are written to
+//! object storage, and the partitions with data in their open chunk are
+//! snapshotted. It then releases the partition leases it holds, so other
+//! servers take over the compaction and retention of those partitions
+//! without waiting for the leases to expire. The server is drained, and
+//! safe to terminate, once all of it succeeded. A failed drain can be
+//! started again; the server keeps rejecting writes until it is restarted.
+use chrono::{DateTime, Utc};
+use parking_lot::Mutex;
+use serde::Serialize;
+
+/// The phase a drain is in
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DrainPhase {
+    /// Persisting the WAL buffers and the open chunks
+    Persisting,
+    /// Releasing the partition leases of the server
+    ReleasingLeases,
+    /// Done: the server can be terminated
+    Drained,
+    /// Stopped by an error, leaving data not persisted
+    Failed,
+}
+
+/// The status of a drain, as reported by the admin API
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct DrainStatus {
+    pub phase: DrainPhase,
+    pub started: DateTime<Utc>,
+    pub finished: Option<DateTime<Utc>>,
+    /// The number of partitions to snapshot
+    pub partitions: usize,
+    /// The number of partitions snapshotted so far
+    pub partitions_persisted: usize,
+    pub leases_released: usize,
+    pub error: Option<String>,
+    /// Whether the server can be terminated without losing data
+    pub safe_to_terminate: bool,
+}
+
+/// The drain of a server, if started
+#[derive(Debug, Default)]
+pub(crate) struct Drain {
+    status: Mutex<Option<DrainStatus>>,
+}
+
+impl Drain {
+    /// Starts a drain unless one is running or done, returning its status
+    pub(crate) fn start(&self) -> Option<DrainStatus> {
+        let mut status = self.status.lock();
+        if let Some(current) = status.as_ref() {
+            if current.phase != DrainPhase::Failed {
+                return None;
+            }
+        }
+        let started = DrainStatus {
+            phase: DrainPhase::Persisting,
+            started: Utc::now(),
+            finished: None,
+            partitions: 0,
+            partitions_persisted: 0,
+            leases_released: 0,
+            error: None,
+            safe_to_terminate: false,
+        };
+        *status = Some(started.clone());
+        Some(started)
+    }
+
+    /// Whether a drain has been started, so writes are rejected
+    pub(crate) fn started(&self) -> bool {
+        self.status.lock().is_some()
+    }
+
+    pub(crate) fn status(&self) -> Option<DrainStatus> {
+        self.status.lock().clone()
+    }
+
+    /// Updates the status of the running drain
+    pub(crate) fn update(&self, f: impl FnOnce(&mut DrainStatus)) {
+        if let Some(status) = self.status.lock().as_mut() {
+            f(status)
+        }
+    }
+
+    /// Records the end of the running drain, with the error stopping it if
+    /// any
+    pub(crate) fn finish(&self, error: Option<String>) {
+        self.update(|status| {
+            status.finished = Some(Utc::now());
+            status.safe_to_terminate = error.is_none();
+            status.phase = match error {
+                Some(_) => DrainPhase::Failed,
+                None => DrainPhase::Drained,
+            };
+            status.error = error;
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drain() {
+        let drain = Drain::default();
+        assert!(!drain.started());
+        assert!(drain.start().is_some());
+        assert!(drain.started());
+        assert!(drain.start().is_none());
+
+        // a failed drain can be started again
+        drain.finish(Some("boom".to_string()));
+        let status = drain.status().unwrap();
+        assert_eq!(status.phase, DrainPhase::Failed);
+        assert!(!status.safe_to_terminate);
+        assert_eq!(drain.start().unwrap().error, None);
+
+        drain.finish(None);
+        let status = drain.status().unwrap();
+        assert_eq!(status.phase, DrainPhase::Drained);
+        assert!(status.safe_to_terminate);
+        assert!(drain.start().is_none());
+    }
+}
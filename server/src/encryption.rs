@@ -0,0 +1,465 @@
+//! This module contains the envelope encryption of the chunk files and WAL
+//! segments written to object storage, so that data in a shared store is
+//! protected even without encryption by the store itself.
+//!
+//! Each file is encrypted with AES-256-GCM under a new random data key,
+//! which is itself encrypted ("wrapped") with a key encryption key and
+//! stored in the header of the file. The key encryption keys are read from
+//! a secret of the secrets provider, which holds comma separated, hex
+//! encoded 256 bit keys, the first of which encrypts new files. To rotate
+//! keys, a new key is put first and the old ones kept, so that the files
+//! they encrypted can still be read. The keys are kept in memory and read
+//! again periodically, as well as when a file encrypted with a key that
+//! isn't known yet, such as by another server, is read.
+//!
+//! Once encryption is enabled, files that are not encrypted are rejected,
+//! unless plaintext files are explicitly allowed while the files written
+//! before encryption was enabled are migrated.
+//!
+//! An encrypted file starts with [`MAGIC`] and a version byte, followed by
+//! the id of the key encryption key (the first 8 bytes of its SHA-256), the
+//! nonce and the wrapped data key, and then the nonce and the encrypted
+//! data. The header is authenticated along with the data.
+
+use std::{sync::Arc, time::Duration};
+
+use bytes::Bytes;
+use object_store::secrets::{self, SecretsProvider};
+use parking_lot::RwLock;
+use ring::{
+    aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN},
+    rand::{SecureRandom, SystemRandom},
+};
+use sha2::{Digest, Sha256};
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+use tracing::error;
+
+/// The bytes encrypted files start with
+pub const MAGIC: &[u8] = b"IOXE";
+
+/// The version of the layout of encrypted files
+const VERSION: u8 = 1;
+
+/// The length of keys, both key encryption keys and data keys
+const KEY_LEN: usize = 32;
+
+/// The length of the ids of key encryption keys
+const KEY_ID_LEN: usize = 8;
+
+/// The length of the authentication tag appended to encrypted data
+const TAG_LEN: usize = 16;
+
+/// The length of the header: the magic bytes, the version, the key id, and
+/// the nonce and wrapped data key
+const HEADER_LEN: usize = MAGIC.len() + 1 + KEY_ID_LEN + NONCE_LEN + KEY_LEN + TAG_LEN;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Unable to read encryption keys from secret {}: {}", name, source))]
+    ReadingKeys {
+        name: String,
+        source: secrets::Error,
+    },
+
+    #[snafu(display("No encryption keys in secret {}", name))]
+    MissingKeys { name: String },
+
+    #[snafu(display(
+        "Encryption key {} in secret {} is not a hex encoded 256 bit key",
+        position,
+        name
+    ))]
+    InvalidKey { name: String, position: usize },
+
+    #[snafu(display("Error encrypting data"))]
+    Encrypting,
+
+    #[snafu(display("Data is not encrypted"))]
+    NotEncrypted,
+
+    #[snafu(display("Encrypted data found, but encryption is not configured"))]
+    EncryptionNotConfigured,
+
+    #[snafu(display("Unencrypted data found, but plaintext data is not allowed"))]
+    PlaintextNotAllowed,
+
+    #[snafu(display(
+        "Data was encrypted with key {}, which is not in secret {}",
+        key_id,
+        name
+    ))]
+    UnknownKey { key_id: String, name: String },
+
+    #[snafu(display("Unable to decrypt data, which is corrupt or was tampered with"))]
+    Decrypting,
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// A key encryption key
+#[derive(Debug)]
+struct Key {
+    id: [u8; KEY_ID_LEN],
+    key: LessSafeKey,
+}
+
+impl Key {
+    fn parse(hex_key: &str) -> Option<Self> {
+        let bytes = hex::decode(hex_key).ok()?;
+        let key = UnboundKey::new(&AES_256_GCM, &bytes).ok()?;
+
+        let mut id = [0; KEY_ID_LEN];
+        id.copy_from_slice(&Sha256::digest(&bytes)[..KEY_ID_LEN]);
+        Some(Self {
+            id,
+            key: LessSafeKey::new(key),
+        })
+    }
+}
+
+/// Encrypts and decrypts files as described in the module docs, with the
+/// keys in the secret `secret_name` of `secrets`
+#[derive(Debug)]
+pub struct Encryption {
+    secrets: Arc<dyn SecretsProvider>,
+    secret_name: String,
+    rng: SystemRandom,
+    /// The keys last read from the secret
+    keys: RwLock<Option<Arc<Vec<Key>>>>,
+    /// Whether files that are not encrypted can be read
+    allow_plaintext: bool,
+}
+
+impl Encryption {
+    pub fn new(secrets: Arc<dyn SecretsProvider>, secret_name: impl Into<String>) -> Self {
+        Self {
+            secrets,
+            secret_name: secret_name.into(),
+            rng: SystemRandom::new(),
+            keys: Default::default(),
+            allow_plaintext: false,
+        }
+    }
+
+    /// Allows reading files that are not encrypted, written before
+    /// encryption was enabled
+    pub fn with_plaintext_allowed(self) -> Self {
+        Self {
+            allow_plaintext: true,
+            ..self
+        }
+    }
+
+    /// Reads the keys from the secret again, so that rotated keys are used.
+    /// Returns an error if they can't be read or are invalid, keeping the
+    /// current keys, so that a misconfiguration is found on startup rather
+    /// than on the first write.
+    pub async fn refresh_keys(&self) -> Result<()> {
+        let keys = self.read_keys().await?;
+        *self.keys.write() = Some(Arc::new(keys));
+        Ok(())
+    }
+
+    /// Returns `data` encrypted with a new data key, wrapped with the first
+    /// key of the secret
+    pub async fn encrypt(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let keys = self.keys().await?;
+        let key = &keys[0];
+
+        let mut data_key = [0; KEY_LEN];
+        self.rng
+            .fill(&mut data_key)
+            .map_err(|_| Error::Encrypting)?;
+        let mut wrapped_key = data_key.to_vec();
+        let wrap_nonce = self.seal(&key.key, &key.id, &mut wrapped_key)?;
+
+        let mut encrypted = Vec::with_capacity(HEADER_LEN + NONCE_LEN + data.len() + TAG_LEN);
+        encrypted.extend_from_slice(MAGIC);
+        encrypted.push(VERSION);
+        encrypted.extend_from_slice(&key.id);
+        encrypted.extend_from_slice(&wrap_nonce);
+        encrypted.extend_from_slice(&wrapped_key);
+
+        let data_key = UnboundKey::new(&AES_256_GCM, &data_key).map_err(|_| Error::Encrypting)?;
+        let mut ciphertext = data.to_vec();
+        let data_nonce = self.seal(&LessSafeKey::new(data_key), &encrypted, &mut ciphertext)?;
+        encrypted.extend_from_slice(&data_nonce);
+        encrypted.extend_from_slice(&ciphertext);
+        Ok(encrypted)
+    }
+
+    /// Returns the data `encrypted` by [`encrypt`](Self::encrypt), with any
+    /// key of the secret
+    pub async fn decrypt(&self, encrypted: &[u8]) -> Result<Vec<u8>> {
+        ensure!(
+            is_encrypted(encrypted) && encrypted.len() >= HEADER_LEN + NONCE_LEN + TAG_LEN,
+            NotEncrypted
+        );
+        let (header, rest) = encrypted.split_at(HEADER_LEN);
+        let (key_id, wrapped) = header[MAGIC.len() + 1..].split_at(KEY_ID_LEN);
+        let (wrap_nonce, wrapped_key) = wrapped.split_at(NONCE_LEN);
+        let (data_nonce, ciphertext) = rest.split_at(NONCE_LEN);
+
+        let mut keys = self.keys().await?;
+        if !keys.iter().any(|key| key.id == key_id) {
+            // the key may have been added to the secret since it was read
+            self.refresh_keys().await?;
+            keys = self.keys().await?;
+        }
+        let key = keys
+            .iter()
+            .find(|key| key.id == key_id)
+            .context(UnknownKey {
+                key_id: hex::encode(key_id),
+                name: &self.secret_name,
+            })?;
+
+        let mut data_key = wrapped_key.to_vec();
+        let data_key = open(&key.key, wrap_nonce, key_id, &mut data_key)?;
+        let data_key = UnboundKey::new(&AES_256_GCM, data_key).map_err(|_| Error::Decrypting)?;
+
+        let mut data = ciphertext.to_vec();
+        let len = open(&LessSafeKey::new(data_key), data_nonce, header, &mut data)?.len();
+        data.truncate(len);
+        Ok(data)
+    }
+
+    /// Returns the key encryption keys, reading them from the secret if
+    /// they weren't yet
+    async fn keys(&self) -> Result<Arc<Vec<Key>>> {
+        let keys = self.keys.read().clone();
+        match keys {
+            Some(keys) => Ok(keys),
+            None => {
+                self.refresh_keys().await?;
+                Ok(self.keys.read().clone().expect("keys were just read"))
+            }
+        }
+    }
+
+    /// Reads the key encryption keys from the secret
+    async fn read_keys(&self) -> Result<Vec<Key>> {
+        let name = &self.secret_name;
+        let secret = self
+            .secrets
+            .secret(name)
+            .await
+            .context(ReadingKeys { name })?
+            .context(MissingKeys { name })?;
+
+        let keys = secret
+            .expose()
+            .split(',')
+            .map(str::trim)
+            .filter(|key| !key.is_empty())
+            .enumerate()
+            .map(|(position, key)| Key::parse(key).context(InvalidKey { name, position }))
+            .collect::<Result<Vec<_>>>()?;
+        ensure!(!keys.is_empty(), MissingKeys { name });
+        Ok(keys)
+    }
+
+    /// Encrypts `in_out` in place with `key` and a random nonce, which is
+    /// returned
+    fn seal(&self, key: &LessSafeKey, aad: &[u8], in_out: &mut Vec<u8>) -> Result<[u8; NONCE_LEN]> {
+        let mut nonce = [0; NONCE_LEN];
+        self.rng.fill(&mut nonce).map_err(|_| Error::Encrypting)?;
+        key.seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::from(aad), in_out)
+            .map_err(|_| Error::Encrypting)?;
+        Ok(nonce)
+    }
+}
+
+/// Decrypts `in_out` in place, returning the decrypted part
+fn open<'a>(
+    key: &LessSafeKey,
+    nonce: &[u8],
+    aad: &[u8],
+    in_out: &'a mut [u8],
+) -> Result<&'a mut [u8]> {
+    let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| Error::Decrypting)?;
+    key.open_in_place(nonce, Aad::from(aad), in_out)
+        .map_err(|_| Error::Decrypting)
+}
+
+/// Reads the keys of `encryption` from the secret every `interval`. Runs
+/// forever; errors are logged and the previous keys kept.
+pub async fn refresh_keys_periodically(encryption: Arc<Encryption>, interval: Duration) {
+    let mut interval = tokio::time::interval(interval);
+    // the first tick completes immediately, and the keys were just read
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+        if let Err(e) = encryption.refresh_keys().await {
+            error!(
+                "Error refreshing the encryption keys, keeping the current ones: {}",
+                e
+            );
+        }
+    }
+}
+
+/// Whether `data` was encrypted by an [`Encryption`]
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(MAGIC) && data.get(MAGIC.len()) == Some(&VERSION)
+}
+
+/// Returns `data` encrypted with `encryption`, or as is if there is none
+pub async fn encrypt_if_enabled(encryption: Option<&Encryption>, data: Bytes) -> Result<Bytes> {
+    match encryption {
+        Some(encryption) => encryption.encrypt(&data).await.map(Bytes::from),
+        None => Ok(data),
+    }
+}
+
+/// Returns `data` decrypted with `encryption` if it's encrypted. Data that
+/// isn't is only returned as is without encryption, or if `encryption`
+/// allows plaintext files while migrating.
+pub async fn decrypt_if_encrypted(encryption: Option<&Encryption>, data: Bytes) -> Result<Bytes> {
+    if !is_encrypted(&data) {
+        ensure!(
+            encryption.map_or(true, |encryption| encryption.allow_plaintext),
+            PlaintextNotAllowed
+        );
+        return Ok(data);
+    }
+    let encryption = encryption.context(EncryptionNotConfigured)?;
+    encryption.decrypt(&data).await.map(Bytes::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object_store::secrets::Secret;
+    use parking_lot::Mutex;
+
+    #[derive(Debug, Default)]
+    struct TestSecrets(Mutex<Option<String>>);
+
+    impl TestSecrets {
+        fn set(&self, keys: &str) {
+            *self.0.lock() = Some(keys.to_string());
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl SecretsProvider for TestSecrets {
+        async fn secret(&self, name: &str) -> secrets::Result<Option<Secret>> {
+            assert_eq!(name, "encryption_keys");
+            Ok(self.0.lock().clone().map(Secret::new))
+        }
+    }
+
+    const FIRST_KEY: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+    const SECOND_KEY: &str = "202122232425262728292a2b2c2d2e2f303132333435363738393a3b3c3d3e3f";
+
+    #[tokio::test]
+    async fn round_trip() {
+        let secrets = Arc::new(TestSecrets::default());
+        let encryption = Encryption::new(Arc::clone(&secrets) as _, "encryption_keys");
+        let data = b"cpu,host=A usage=0.5 1";
+
+        let err = encryption.refresh_keys().await.unwrap_err();
+        assert!(matches!(err, Error::MissingKeys { .. }));
+
+        secrets.set(FIRST_KEY);
+        encryption.refresh_keys().await.unwrap();
+        let encrypted = encryption.encrypt(data).await.unwrap();
+        assert!(is_encrypted(&encrypted));
+        assert!(!encrypted.windows(data.len()).any(|w| w == &data[..]));
+        assert_eq!(encryption.decrypt(&encrypted).await.unwrap(), &data[..]);
+
+        // the same data is encrypted differently each time
+        assert_ne!(encryption.encrypt(data).await.unwrap(), encrypted);
+
+        // the keys are only read again when refreshed
+        secrets.set(&format!("{}, {}", SECOND_KEY, FIRST_KEY));
+        let not_rotated = encryption.encrypt(data).await.unwrap();
+        assert_eq!(
+            not_rotated[..MAGIC.len() + 1 + KEY_ID_LEN],
+            encrypted[..MAGIC.len() + 1 + KEY_ID_LEN]
+        );
+
+        // after rotating, new data is encrypted with the new key and data
+        // encrypted with the old one can still be read
+        encryption.refresh_keys().await.unwrap();
+        let rotated = encryption.encrypt(data).await.unwrap();
+        assert_ne!(rotated[..HEADER_LEN], encrypted[..HEADER_LEN]);
+        assert_eq!(encryption.decrypt(&encrypted).await.unwrap(), &data[..]);
+        assert_eq!(encryption.decrypt(&rotated).await.unwrap(), &data[..]);
+
+        secrets.set(SECOND_KEY);
+        encryption.refresh_keys().await.unwrap();
+        let err = encryption.decrypt(&encrypted).await.unwrap_err();
+        assert!(matches!(err, Error::UnknownKey { .. }));
+
+        let mut tampered = rotated.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        let err = encryption.decrypt(&tampered).await.unwrap_err();
+        assert!(matches!(err, Error::Decrypting));
+
+        let err = encryption.decrypt(data).await.unwrap_err();
+        assert!(matches!(err, Error::NotEncrypted));
+
+        for keys in &["not hex", "0011", ""] {
+            secrets.set(keys);
+            assert!(encryption.refresh_keys().await.is_err(), "{}", keys);
+        }
+    }
+
+    #[tokio::test]
+    async fn unknown_keys_are_read_again() {
+        let secrets = Arc::new(TestSecrets::default());
+        secrets.set(FIRST_KEY);
+        let reader = Encryption::new(Arc::clone(&secrets) as _, "encryption_keys");
+        reader.refresh_keys().await.unwrap();
+
+        // another server encrypts data with a key added since the keys
+        // were read
+        let other_secrets = Arc::new(TestSecrets::default());
+        other_secrets.set(SECOND_KEY);
+        let writer = Encryption::new(other_secrets as _, "encryption_keys");
+        let data = b"cpu,host=A usage=0.5 1";
+        let encrypted = writer.encrypt(data).await.unwrap();
+
+        secrets.set(&format!("{}, {}", SECOND_KEY, FIRST_KEY));
+        assert_eq!(reader.decrypt(&encrypted).await.unwrap(), &data[..]);
+    }
+
+    #[tokio::test]
+    async fn optional() {
+        let data = Bytes::from_static(b"PAR1");
+        let encrypted = encrypt_if_enabled(None, data.clone()).await.unwrap();
+        assert_eq!(encrypted, data);
+        let decrypted = decrypt_if_encrypted(None, data.clone()).await.unwrap();
+        assert_eq!(decrypted, data);
+
+        let secrets = TestSecrets::default();
+        secrets.set(FIRST_KEY);
+        let encryption = Encryption::new(Arc::new(secrets), "encryption_keys");
+        let encrypted = encrypt_if_enabled(Some(&encryption), data.clone())
+            .await
+            .unwrap();
+        let err = decrypt_if_encrypted(None, encrypted.clone())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::EncryptionNotConfigured));
+        let decrypted = decrypt_if_encrypted(Some(&encryption), encrypted)
+            .await
+            .unwrap();
+        assert_eq!(decrypted, data);
+
+        // plaintext data is rejected once encryption is enabled, unless
+        // allowed while migrating
+        let err = decrypt_if_encrypted(Some(&encryption), data.clone())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::PlaintextNotAllowed));
+        let encryption = encryption.with_plaintext_allowed();
+        let decrypted = decrypt_if_encrypted(Some(&encryption), data.clone())
+            .await
+            .unwrap();
+        assert_eq!(decrypted, data);
+    }
+}
@@ -0,0 +1,213 @@
+//! Hinted handoff of the writes replicated to unavailable peers.
+//!
+//! A peer failing to store a replicated write, e.g. because it is down or
+//! unreachable, would otherwise miss it for good. With hinted handoff
+//! enabled, the server keeps such a write in memory as a hint for the peer,
+//! and periodically sends a peer its hints again, oldest first, until it
+//! has stored them. A peer stores a write it already holds only in the
+//! partitions missing it, so delivering a hint the peer got after all is
+//! harmless.
+//!
+//! Hints are bounded: those older than the maximum age are dropped, as are
+//! the oldest hints of a peer once its hints take more than the maximum
+//! size. Anti-entropy repair may still recover the dropped writes.
+use data_types::data::ReplicatedWrite;
+use parking_lot::Mutex;
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+/// A write a peer has yet to store
+#[derive(Debug, Clone)]
+pub struct Hint {
+    /// Identifies the hint among those of its peer
+    id: u64,
+    pub db_name: String,
+    pub write: Arc<ReplicatedWrite>,
+    created: Instant,
+}
+
+impl Hint {
+    fn size(&self) -> usize {
+        self.write.bytes().len()
+    }
+}
+
+/// The hints of a peer, oldest first, and their total size
+#[derive(Debug, Default)]
+struct PeerHints {
+    hints: VecDeque<Hint>,
+    size: usize,
+}
+
+impl PeerHints {
+    fn pop_front(&mut self) -> Option<Hint> {
+        let hint = self.hints.pop_front()?;
+        self.size -= hint.size();
+        Some(hint)
+    }
+}
+
+/// The hints of each peer, as described in the module docs
+#[derive(Debug)]
+pub struct HintedHandoff {
+    max_size: usize,
+    max_age: Duration,
+    peers: Mutex<BTreeMap<String, PeerHints>>,
+    next_id: AtomicU64,
+    dropped: AtomicU64,
+}
+
+impl HintedHandoff {
+    /// Keeps up to `max_size` bytes of writes per peer, for up to `max_age`
+    pub fn new(max_size: usize, max_age: Duration) -> Self {
+        Self {
+            max_size,
+            max_age,
+            peers: Default::default(),
+            next_id: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Keeps `write` to the database `db_name` for `peer`, which failed to
+    /// store it, dropping the oldest hints of the peer if they take too
+    /// much space
+    pub fn add(&self, peer: &str, db_name: &str, write: Arc<ReplicatedWrite>) {
+        let hint = Hint {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            db_name: db_name.to_string(),
+            write,
+            created: Instant::now(),
+        };
+
+        let mut peers = self.peers.lock();
+        let hints = peers.entry(peer.to_string()).or_default();
+        hints.size += hint.size();
+        hints.hints.push_back(hint);
+        while hints.size > self.max_size && hints.pop_front().is_some() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        if hints.hints.is_empty() {
+            peers.remove(peer);
+        }
+    }
+
+    /// Returns the peers with hints
+    pub fn peers(&self) -> Vec<String> {
+        self.peers.lock().keys().cloned().collect()
+    }
+
+    /// Returns the oldest hint of `peer`, dropping the expired ones
+    pub fn next(&self, peer: &str) -> Option<Hint> {
+        let mut peers = self.peers.lock();
+        let hints = peers.get_mut(peer)?;
+        while hints
+            .hints
+            .front()
+            .map_or(false, |hint| hint.created.elapsed() > self.max_age)
+        {
+            hints.pop_front();
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        let hint = hints.hints.front().cloned();
+        if hint.is_none() {
+            peers.remove(peer);
+        }
+        hint
+    }
+
+    /// Forgets `hint` once `peer` has stored it. It is the oldest hint of
+    /// the peer unless it has been dropped meanwhile.
+    pub fn delivered(&self, peer: &str, hint: &Hint) {
+        let mut peers = self.peers.lock();
+        if let Some(hints) = peers.get_mut(peer) {
+            if hints.hints.front().map(|h| h.id) == Some(hint.id) {
+                hints.pop_front();
+            }
+            if hints.hints.is_empty() {
+                peers.remove(peer);
+            }
+        }
+    }
+
+    /// Returns the number of hints of each peer with hints
+    pub fn pending(&self) -> Vec<(String, usize)> {
+        self.peers
+            .lock()
+            .iter()
+            .map(|(peer, hints)| (peer.clone(), hints.hints.len()))
+            .collect()
+    }
+
+    /// The number of bytes of the writes kept for all peers
+    pub fn size(&self) -> usize {
+        self.peers.lock().values().map(|hints| hints.size).sum()
+    }
+
+    /// The number of hints dropped as too old or too many
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use data_types::{data::lines_to_replicated_write, database_rules::DatabaseRules};
+    use influxdb_line_protocol::parse_lines;
+
+    fn write(sequence: u64) -> Arc<ReplicatedWrite> {
+        let lines: Vec<_> = parse_lines("cpu bar=1 10").map(|l| l.unwrap()).collect();
+        Arc::new(lines_to_replicated_write(
+            1,
+            sequence,
+            &lines,
+            &DatabaseRules::new(),
+        ))
+    }
+
+    #[test]
+    fn hints() {
+        let size = write(1).bytes().len();
+        let hints = HintedHandoff::new(2 * size, Duration::from_secs(3600));
+        hints.add("peer1", "db", write(1));
+        hints.add("peer1", "db", write(2));
+        hints.add("peer2", "db", write(1));
+        assert_eq!(
+            hints.peers(),
+            vec!["peer1".to_string(), "peer2".to_string()]
+        );
+
+        // the oldest hint is dropped to make room
+        hints.add("peer1", "db", write(3));
+        assert_eq!(hints.dropped(), 1);
+        let hint = hints.next("peer1").unwrap();
+        assert_eq!(hint.write.writer_and_sequence().1, 2);
+
+        // a hint dropped meanwhile isn't mistaken for the next one
+        hints.add("peer1", "db", write(4));
+        hints.delivered("peer1", &hint);
+        assert_eq!(
+            hints.next("peer1").unwrap().write.writer_and_sequence().1,
+            3
+        );
+
+        hints.delivered("peer2", &hints.next("peer2").unwrap());
+        assert!(hints.next("peer2").is_none());
+        assert_eq!(hints.pending(), vec![("peer1".to_string(), 2)]);
+
+        // expired hints are dropped
+        let hints = HintedHandoff::new(2 * size, Duration::from_secs(0));
+        hints.add("peer1", "db", write(1));
+        std::thread::sleep(Duration::from_millis(1));
+        assert!(hints.next("peer1").is_none());
+        assert!(hints.peers().is_empty());
+        assert_eq!(hints.dropped(), 1);
+    }
+}
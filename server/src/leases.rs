@@ -0,0 +1,151 @@
+//! Election of a single owner for the background work on each partition.
+//!
+//! Servers sharing an object store and holding the same databases, such as
+//! the peers writes are replicated to, would otherwise all compact the same
+//! partitions and drop the same expired data. With partition leases enabled
+//! a server only does so for the partitions whose lease it holds. Leases
+//! are stored in the shared object store, outside the directory of any one
+//! server: the lease of a partition names the writer id of its owner and
+//! when it expires. A server takes a partition's lease if it is free or
+//! expired, and renews it while it holds it; the partitions whose lease is
+//! held by another server are left to that server.
+//!
+//! Object stores don't offer conditional writes, so after writing a lease a
+//! server reads it back, and only considers itself the owner if its own
+//! lease is still there. This narrows the window in which two servers
+//! taking a lease at the same time could both believe they hold it, but
+//! doesn't close it, so leases are best effort: they keep servers from
+//! routinely doing the same work, not from ever doing it.
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, time::Duration};
+
+/// The directory of the object store leases are stored in
+pub(crate) const LEASES_DIR: &str = "leases";
+
+/// A lease on the background work of a partition, as stored
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) struct Lease {
+    /// The writer id of the server holding the lease
+    pub owner: u32,
+    pub expires: DateTime<Utc>,
+}
+
+impl Lease {
+    /// Whether the lease keeps the server `id` from taking it at `now`
+    pub(crate) fn excludes(&self, id: u32, now: DateTime<Utc>) -> bool {
+        self.owner != id && self.expires > now
+    }
+}
+
+/// Who owns the background work of a partition
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ownership {
+    /// This server holds the partition's lease
+    Owned,
+    /// The server with this writer id holds the partition's lease
+    HeldBy(u32),
+}
+
+/// The partition leases of a server, as described in the module docs
+#[derive(Debug)]
+pub struct PartitionLeases {
+    duration: Duration,
+    /// When the leases this server holds expire, by database and partition
+    held: Mutex<HashMap<(String, String), DateTime<Utc>>>,
+}
+
+impl PartitionLeases {
+    /// Takes leases lasting `duration`
+    pub fn new(duration: Duration) -> Self {
+        Self {
+            duration,
+            held: Default::default(),
+        }
+    }
+
+    /// How long a lease lasts
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    /// Returns the lease this server would take on a partition at `now`
+    pub(crate) fn lease(&self, id: u32, now: DateTime<Utc>) -> Lease {
+        let duration = ChronoDuration::from_std(self.duration).unwrap_or_else(|_| {
+            // longer than chrono can represent, i.e. forever
+            ChronoDuration::max_value()
+        });
+        Lease {
+            owner: id,
+            expires: now
+                .checked_add_signed(duration)
+                .unwrap_or(chrono::MAX_DATETIME),
+        }
+    }
+
+    /// Whether this server holds the lease of `partition_key` in `db_name`
+    /// for long enough at `now` not to renew it yet, which it does once
+    /// half of the lease has gone by
+    pub(crate) fn holds(&self, db_name: &str, partition_key: &str, now: DateTime<Utc>) -> bool {
+        let half = ChronoDuration::from_std(self.duration / 2).unwrap_or_else(|_| {
+            // longer than chrono can represent, i.e. forever
+            ChronoDuration::max_value()
+        });
+        self.held
+            .lock()
+            .get(&(db_name.to_string(), partition_key.to_string()))
+            .map_or(false, |expires| {
+                now.checked_add_signed(half)
+                    .map_or(false, |renew_at| renew_at < *expires)
+            })
+    }
+
+    /// Returns the partitions whose lease this server holds, by database
+    pub(crate) fn held(&self) -> Vec<(String, String)> {
+        self.held.lock().keys().cloned().collect()
+    }
+
+    /// Records whether this server holds the lease of `partition_key` in
+    /// `db_name`, until `expires` if so
+    pub(crate) fn record(
+        &self,
+        db_name: &str,
+        partition_key: &str,
+        expires: Option<DateTime<Utc>>,
+    ) {
+        let key = (db_name.to_string(), partition_key.to_string());
+        let mut held = self.held.lock();
+        match expires {
+            Some(expires) => held.insert(key, expires),
+            None => held.remove(&key),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn leases() {
+        let leases = PartitionLeases::new(Duration::from_secs(60));
+        let now = Utc::now();
+
+        let lease = leases.lease(1, now);
+        assert_eq!(lease.expires, now + ChronoDuration::seconds(60));
+        assert!(!lease.excludes(1, now));
+        assert!(lease.excludes(2, now));
+        assert!(!lease.excludes(2, now + ChronoDuration::seconds(60)));
+
+        assert!(!leases.holds("db", "p", now));
+        leases.record("db", "p", Some(lease.expires));
+        assert!(leases.holds("db", "p", now));
+        assert!(!leases.holds("db", "other", now));
+        // renewed once half of the lease has gone by
+        assert!(!leases.holds("db", "p", now + ChronoDuration::seconds(30)));
+
+        leases.record("db", "p", None);
+        assert!(!leases.holds("db", "p", now));
+    }
+}
@@ -67,40 +67,109 @@
     clippy::clone_on_ref_ptr
 )]
 
+pub mod anti_entropy;
 pub mod buffer;
+pub mod catalog;
+pub mod cluster;
+pub mod compaction;
 mod config;
 pub mod db;
+pub mod drain;
+pub mod encryption;
+pub mod hints;
+pub mod leases;
+pub mod memory;
+pub mod orgs;
+pub mod quotas;
+pub mod rate_limits;
+pub mod rebalance;
+pub mod region;
+pub mod replica;
+pub mod replication;
+pub mod router;
+pub mod scheduler;
 pub mod snapshot;
+pub mod throttle;
+pub mod tokens;
 mod tracker;
+pub mod write_buffer;
 
 #[cfg(test)]
 mod query_tests;
 
-use std::sync::{
-    atomic::{AtomicU32, Ordering},
-    Arc,
+use std::{
+    collections::{BTreeMap, HashMap},
+    convert::TryFrom,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
 };
 
 use crate::{
+    anti_entropy::WriteDigest,
     buffer::SegmentPersistenceTask,
-    config::{object_store_path_for_database_config, Config, DB_RULES_FILE_NAME},
-    db::Db,
+    catalog::{CatalogChange, CatalogEntry, CatalogLog, CATALOG_LOG_FILE_NAME},
+    cluster::{MemberState, Membership, OnQuorumLoss, Quorum, QuorumStatus, Status},
+    compaction::{CompactionState, CompactionStatus, Compactions},
+    config::{
+        object_store_path_for_database_config, object_store_path_for_database_deletes, Config,
+        DB_RULES_FILE_NAME, DELETES_FILE_NAME,
+    },
+    db::{
+        tombstone::{DeletePredicate, DeleteRecord},
+        DBChunk, Db, PartitionStatus,
+    },
+    drain::{Drain, DrainPhase, DrainStatus},
+    encryption::Encryption,
+    hints::HintedHandoff,
+    leases::{Lease, Ownership, PartitionLeases, LEASES_DIR},
+    memory::{MemoryTracker, Subsystem},
+    orgs::{Org, OrgCatalog, ORGS_FILE_NAME},
+    quotas::{BucketUsage, Check, OrgUsage, Quotas, Resource, SoftLimits, Usage},
+    rate_limits::{QueryPermit, RateLimiter, RateLimits, Subject},
+    rebalance::{MovePhase, ShardMove, ShardMoves, SHARD_OWNERS_FILE_NAME},
+    region::RegionReplication,
+    replica::ReadReplica,
+    replication::PeerReplication,
+    router::{ShardRange, ShardRouter},
+    scheduler::JobScheduler,
+    snapshot::Snapshot,
+    throttle::IoLimits,
+    tokens::{Token, TokenCatalog, TokenRequest, TOKENS_FILE_NAME},
     tracker::TrackerRegistry,
+    write_buffer::{
+        Checkpoint, PartitionReplay, ReplayProgress, WriteBuffer, CHECKPOINT_FILE_NAME,
+    },
 };
 use data_types::{
     data::{lines_to_replicated_write, ReplicatedWrite},
     database_rules::{DatabaseRules, HostGroup, HostGroupId, MatchTables},
+    names::{database_to_org_and_bucket, org_and_bucket_to_database, OrgBucketMappingError},
     {DatabaseName, DatabaseNameError},
 };
 use influxdb_line_protocol::ParsedLine;
-use object_store::{path::ObjectStorePath, ObjectStore, ObjectStoreApi};
+use object_store::{
+    path::{parsed::DirsAndFileName, ObjectStorePath},
+    ObjectStore, ObjectStoreApi,
+};
 use query::{exec::Executor, Database, DatabaseStore};
 
 use async_trait::async_trait;
 use bytes::Bytes;
-use futures::stream::TryStreamExt;
-use snafu::{OptionExt, ResultExt, Snafu};
-use tracing::error;
+use chrono::Utc;
+use futures::stream::{FuturesUnordered, StreamExt, TryStreamExt};
+use generated_types::influxdata::iox::{
+    cluster::v1::{
+        cluster_service_client::ClusterServiceClient, GetWriteDigestsRequest, GossipRequest,
+        HandOffShardRequest, SyncCatalogRequest,
+    },
+    write::v1::{write_service_client::WriteServiceClient, ReplicateWriteRequest, WriteRequest},
+};
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+use tonic::transport::{Channel, ClientTlsConfig, Endpoint};
+use tracing::{debug, error, info, warn};
 
 type DatabaseError = Box<dyn std::error::Error + Send + Sync + 'static>;
 
@@ -130,6 +199,127 @@ pub enum Error {
     },
     #[snafu(display("error replicating to remote: {}", source))]
     ErrorReplicating { source: DatabaseError },
+    #[snafu(display("invalid address of remote server {}: {}", server, source))]
+    InvalidRemoteAddress {
+        server: String,
+        source: tonic::transport::Error,
+    },
+    #[snafu(display("unable to connect to remote server {}: {}", server, source))]
+    ConnectingToRemote {
+        server: String,
+        source: tonic::transport::Error,
+    },
+    #[snafu(display("remote server {} failed to store write: {}", server, source))]
+    RemoteWriteFailed {
+        server: String,
+        source: tonic::Status,
+    },
+    #[snafu(display(
+        "write stored on {} of the {} servers required: {}",
+        stored,
+        quorum,
+        errors
+    ))]
+    WriteQuorumNotReached {
+        stored: usize,
+        quorum: usize,
+        errors: String,
+    },
+    #[snafu(display(
+        "write failed on {} of the {} shards it was routed to: {}",
+        failed,
+        shards,
+        errors
+    ))]
+    ShardWriteFailed {
+        failed: usize,
+        shards: usize,
+        errors: String,
+    },
+    #[snafu(display(
+        "server is a read replica of server {} and doesn't accept writes",
+        primary_id
+    ))]
+    ReadReplicaWrite { primary_id: u32 },
+    #[snafu(display("server is draining and doesn't accept writes"))]
+    Draining,
+    #[snafu(display(
+        "server lost the quorum of the cluster, seeing {} of the {} members required, \
+         and doesn't accept writes",
+        alive,
+        required
+    ))]
+    QuorumLost { alive: usize, required: usize },
+    #[snafu(display(
+        "snapshot of partition {} of database {} failed while draining",
+        partition_key,
+        db_name
+    ))]
+    DrainSnapshotFailed {
+        db_name: String,
+        partition_key: String,
+    },
+    #[snafu(display("error reading WAL segment {}: {}", location, source))]
+    ReadingSegment {
+        location: String,
+        source: buffer::Error,
+    },
+    #[snafu(display("error decrypting WAL segment {}: {}", location, source))]
+    DecryptingSegment {
+        location: String,
+        source: encryption::Error,
+    },
+    #[snafu(display("unable to write to the write buffer: {}", source))]
+    WriteBufferFailed { source: write_buffer::Error },
+    #[snafu(display("cluster membership is not enabled on this server"))]
+    ClusterDisabled,
+    #[snafu(display("gossip with remote server {} failed: {}", server, source))]
+    GossipFailed {
+        server: String,
+        source: tonic::Status,
+    },
+    #[snafu(display(
+        "unable to get write digests from remote server {}: {}",
+        server,
+        source
+    ))]
+    RemoteDigestsFailed {
+        server: String,
+        source: tonic::Status,
+    },
+    #[snafu(display("unable to sync catalog with remote server {}: {}", server, source))]
+    RemoteCatalogFailed {
+        server: String,
+        source: tonic::Status,
+    },
+    #[snafu(display("catalog sync with {} failed: {}", server, source))]
+    CatalogSyncFailed {
+        server: String,
+        source: DatabaseError,
+    },
+    #[snafu(display("anti-entropy repair with {} failed: {}", server, source))]
+    RepairFailed {
+        server: String,
+        source: DatabaseError,
+    },
+    #[snafu(display("server doesn't route writes to shards"))]
+    RoutingDisabled,
+    #[snafu(display("unable to move shard: {}", source))]
+    InvalidShardMove { source: router::Error },
+    #[snafu(display("shard {} is already owned by {}", shard, backend))]
+    ShardAlreadyOwned { shard: String, backend: String },
+    #[snafu(display("shard {} is already being moved", shard))]
+    ShardMoveRunning { shard: String },
+    #[snafu(display("hand-off of shard to {} failed: {}", server, source))]
+    ShardHandOffFailed {
+        server: String,
+        source: DatabaseError,
+    },
+    #[snafu(display("unable to hand off shard to remote server {}: {}", server, source))]
+    RemoteHandOffFailed {
+        server: String,
+        source: tonic::Status,
+    },
     #[snafu(display("unable to use server until id is set"))]
     IdNotSet,
     #[snafu(display("error serializing configuration {}", source))]
@@ -142,12 +332,66 @@ pub enum Error {
     DatabaseAlreadyExists { db_name: String },
     #[snafu(display("error appending to wal buffer: {}", source))]
     WalError { source: buffer::Error },
+    #[snafu(display(
+        "error persisting the open WAL segments of {} databases: {}",
+        failed,
+        errors
+    ))]
+    PersistingOpenSegments { failed: usize, errors: String },
+    #[snafu(display("org already exists: {}", name))]
+    OrgAlreadyExists { name: String },
+    #[snafu(display("org not found: {}", id))]
+    OrgNotFound { id: String },
+    #[snafu(display("org {} still has buckets", name))]
+    OrgHasBuckets { name: String },
+    #[snafu(display("invalid org name for its buckets: {}", source))]
+    InvalidOrgName { source: OrgBucketMappingError },
+    #[snafu(display("token not found: {}", id))]
+    TokenNotFound { id: String },
+    #[snafu(display("invalid token: {}", reason))]
+    InvalidToken { reason: String },
+    #[snafu(display("partition {} not found in database {}", partition_key, db_name))]
+    PartitionNotFound {
+        db_name: String,
+        partition_key: String,
+    },
+    #[snafu(display(
+        "partition {} of database {} is leased by server {}",
+        partition_key,
+        db_name,
+        owner
+    ))]
+    PartitionLeaseHeld {
+        db_name: String,
+        partition_key: String,
+        owner: u32,
+    },
+    #[snafu(display("error snapshotting partition: {}", source))]
+    SnapshottingPartition { source: snapshot::Error },
+    #[snafu(display("org {} is over its {} quota: {} of {}", org, resource, usage, limit))]
+    QuotaExceeded {
+        org: String,
+        resource: Resource,
+        usage: u64,
+        limit: u64,
+    },
+    #[snafu(display("{} is over its rate limit of {}", subject, limit))]
+    RateLimited {
+        subject: Subject,
+        limit: String,
+        retry_after: Duration,
+    },
+    #[snafu(display("{}", source))]
+    MemoryLimitReached { source: memory::Error },
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
 const STORE_ERROR_PAUSE_SECONDS: u64 = 100;
 
+/// The number of messages read at a time when replaying the write buffer
+const REPLAY_BATCH_SIZE: usize = 1000;
+
 /// `Server` is the container struct for how servers store data internally, as
 /// well as how they communicate with other servers. Each server will have one
 /// of these structs, which keeps track of all replication and query rules.
@@ -159,6 +403,42 @@ pub struct Server<M: ConnectionManager> {
     pub store: Arc<ObjectStore>,
     executor: Arc<Executor>,
     segment_persistence_registry: TrackerRegistry<SegmentPersistenceTask>,
+    // held while the catalog is persisted, so that concurrent changes are
+    // not lost
+    orgs: tokio::sync::Mutex<OrgCatalog>,
+    // the token catalog is read without waiting by each authenticated
+    // request, so changes are serialized by a separate lock
+    tokens: parking_lot::RwLock<TokenCatalog>,
+    tokens_update: tokio::sync::Mutex<()>,
+    /// Held while changing and persisting the owners of the shards
+    shard_owners_update: tokio::sync::Mutex<()>,
+    /// Held while changing and persisting the rules of a database, so that
+    /// concurrent updates are not lost
+    db_rules_update: tokio::sync::Mutex<()>,
+    /// Held while applying and persisting a delete, so that the deletes
+    /// persisted are those applied
+    deletes_update: tokio::sync::Mutex<()>,
+    shard_moves: ShardMoves,
+    catalog_log: tokio::sync::Mutex<CatalogLog>,
+    compactions: Arc<Compactions>,
+    scheduler: Arc<JobScheduler>,
+    quotas: parking_lot::RwLock<Quotas>,
+    soft_limits: parking_lot::Mutex<SoftLimits>,
+    rate_limiter: RateLimiter,
+    memory: Arc<MemoryTracker>,
+    encryption: parking_lot::RwLock<Option<Arc<Encryption>>>,
+    peer_replication: parking_lot::RwLock<Option<Arc<PeerReplication>>>,
+    hinted_handoff: parking_lot::RwLock<Option<Arc<HintedHandoff>>>,
+    region_replication: parking_lot::RwLock<Option<Arc<RegionReplication>>>,
+    read_replica: parking_lot::RwLock<Option<Arc<ReadReplica>>>,
+    shard_router: parking_lot::RwLock<Option<Arc<ShardRouter>>>,
+    membership: parking_lot::RwLock<Option<Arc<Membership>>>,
+    quorum: parking_lot::RwLock<Option<Quorum>>,
+    quorum_lost: AtomicBool,
+    partition_leases: parking_lot::RwLock<Option<Arc<PartitionLeases>>>,
+    write_buffer: parking_lot::RwLock<Option<Arc<dyn WriteBuffer>>>,
+    replay: ReplayProgress,
+    drain: Drain,
 }
 
 impl<M: ConnectionManager> Server<M> {
@@ -170,6 +450,33 @@ impl<M: ConnectionManager> Server<M> {
             connection_manager: Arc::new(connection_manager),
             executor: Arc::new(Executor::new()),
             segment_persistence_registry: TrackerRegistry::new(),
+            orgs: tokio::sync::Mutex::new(OrgCatalog::default()),
+            tokens: Default::default(),
+            tokens_update: Default::default(),
+            shard_owners_update: Default::default(),
+            db_rules_update: Default::default(),
+            deletes_update: Default::default(),
+            shard_moves: Default::default(),
+            catalog_log: Default::default(),
+            compactions: Default::default(),
+            scheduler: Default::default(),
+            quotas: Default::default(),
+            soft_limits: Default::default(),
+            rate_limiter: Default::default(),
+            memory: Default::default(),
+            encryption: Default::default(),
+            peer_replication: Default::default(),
+            hinted_handoff: Default::default(),
+            region_replication: Default::default(),
+            read_replica: Default::default(),
+            shard_router: Default::default(),
+            membership: Default::default(),
+            quorum: Default::default(),
+            quorum_lost: AtomicBool::new(false),
+            partition_leases: Default::default(),
+            write_buffer: Default::default(),
+            replay: Default::default(),
+            drain: Default::default(),
         }
     }
 
@@ -194,22 +501,236 @@ impl<M: ConnectionManager> Server<M> {
     pub async fn create_database(
         &self,
         db_name: impl Into<String>,
+        rules: DatabaseRules,
+    ) -> Result<()> {
+        let orgs = self.orgs.lock().await;
+        self.create_database_holding_orgs(db_name.into(), rules, orgs)
+            .await
+    }
+
+    /// Creates `db_name`, the database of a bucket of the org named `org`,
+    /// which must be in the org catalog
+    pub async fn create_bucket(
+        &self,
+        org: &str,
+        db_name: impl Into<String>,
+        rules: DatabaseRules,
+    ) -> Result<()> {
+        let orgs = self.orgs.lock().await;
+        ensure!(orgs.find(org).is_some(), OrgNotFound { id: org });
+        self.create_database_holding_orgs(db_name.into(), rules, orgs)
+            .await
+    }
+
+    // creates a database while holding the org catalog, so that an org can't
+    // be deleted or renamed while the database of one of its buckets is
+    // being created
+    async fn create_database_holding_orgs(
+        &self,
+        name: String,
         mut rules: DatabaseRules,
+        orgs: tokio::sync::MutexGuard<'_, OrgCatalog>,
     ) -> Result<()> {
         // Return an error if this server hasn't yet been setup with an id
         self.require_id()?;
+        self.require_quorum()?;
 
-        let name = db_name.into();
         let db_name = DatabaseName::new(name.clone()).context(InvalidDatabaseName)?;
         rules.name = name;
 
         let db_reservation = self.config.create_db(db_name, rules)?;
 
-        let data =
-            Bytes::from(serde_json::to_vec(&db_reservation.db.rules).context(ErrorSerializing)?);
+        let rules = db_reservation.db.rules();
+        self.persist_database_rules(&db_reservation.name, &rules)
+            .await?;
+
+        db_reservation.commit();
+        drop(orgs);
+
+        self.replicate_catalog_change(CatalogChange::Database { rules })
+            .await;
+        Ok(())
+    }
+
+    /// Updates the rules of an existing database, persisting them to object
+    /// storage. `update` is called with a copy of the current rules to
+    /// modify. Returns the updated rules.
+    pub async fn update_db_rules<F>(
+        &self,
+        db_name: &DatabaseName<'_>,
+        update: F,
+    ) -> Result<DatabaseRules>
+    where
+        F: FnOnce(&mut DatabaseRules) + Send,
+    {
+        self.require_id()?;
+        self.require_quorum()?;
+
+        let db = self.config.db(db_name).context(DatabaseNotFound {
+            db_name: db_name.to_string(),
+        })?;
+
+        let _update = self.db_rules_update.lock().await;
+        let mut rules = db.rules();
+        update(&mut rules);
+
+        self.persist_database_rules(db_name, &rules).await?;
+        db.set_rules(rules.clone());
+
+        self.replicate_catalog_change(CatalogChange::Database {
+            rules: rules.clone(),
+        })
+        .await;
+        Ok(rules)
+    }
+
+    /// Removes a database from the server and deletes everything stored for
+    /// it in object storage (its rules, WAL segments and snapshots).
+    pub async fn delete_database(&self, db_name: &DatabaseName<'static>) -> Result<()> {
+        self.require_id()?;
+        self.require_quorum()?;
+
+        if self.config.db(db_name).is_none() {
+            return DatabaseNotFound {
+                db_name: db_name.to_string(),
+            }
+            .fail();
+        }
+
+        self.remove_database(db_name).await?;
+
+        self.replicate_catalog_change(CatalogChange::DatabaseDeleted {
+            name: db_name.to_string(),
+        })
+        .await;
+        Ok(())
+    }
+
+    // deletes everything stored for a database, then removes it from the
+    // config
+    async fn remove_database(&self, db_name: &DatabaseName<'static>) -> Result<()> {
+        let rules_location = object_store_path_for_database_config(&self.root_path()?, db_name);
+        let mut prefix = self.root_path()?;
+        prefix.push_dir(db_name.to_string());
+        let dir = DirsAndFileName::from(prefix.clone());
+
+        // listing matches the last directory of the prefix as a string, so
+        // also finds the objects of databases whose names start with this
+        // one's
+        let locations: Vec<_> = self
+            .store
+            .list(Some(&prefix))
+            .await
+            .context(StoreError)?
+            .try_concat()
+            .await
+            .context(StoreError)?
+            .into_iter()
+            .filter(|location| DirsAndFileName::from(location.clone()).is_in_dir(&dir))
+            .collect();
+
+        // Delete the rules last so that the database is still loaded on
+        // restart if deleting its data fails part way through
+        for location in locations.iter().filter(|l| **l != rules_location) {
+            self.store.delete(location).await.context(StoreError)?;
+        }
+        self.store
+            .delete(&rules_location)
+            .await
+            .context(StoreError)?;
+
+        self.config.remove_db(db_name);
+
+        Ok(())
+    }
+
+    // renames the database `from` to `to`, moving everything stored for it
+    // in object storage. Writes go on to the database under its new name
+    // while its objects are moved, and its rules are moved last, so that it
+    // is still loaded under its old name if moving fails part way through.
+    async fn rename_database(
+        &self,
+        from: &DatabaseName<'static>,
+        to: DatabaseName<'static>,
+    ) -> Result<()> {
+        let _update = self.db_rules_update.lock().await;
+        let db = self.config.rename_db(from, to.clone())?;
+        let mut rules = db.rules();
+        rules.name = to.to_string();
+        db.set_rules(rules.clone());
+
+        let root = self.root_path()?;
+        let old_rules_location = object_store_path_for_database_config(&root, from);
+        let mut leases = self.store.new_path();
+        leases.push_dir(LEASES_DIR);
+
+        // the database's own directory, and those of its snapshots and leases
+        for base in vec![root, self.store.new_path(), leases] {
+            let mut from_dir = base.clone();
+            from_dir.push_dir(from.to_string());
+            let mut to_dir = base;
+            to_dir.push_dir(to.to_string());
+            self.move_objects(&from_dir, &to_dir, &old_rules_location)
+                .await?;
+        }
+
+        self.persist_database_rules(&to, &rules).await?;
+        self.store
+            .delete(&old_rules_location)
+            .await
+            .context(StoreError)
+    }
+
+    // moves the objects in the directory `from` to the same places in the
+    // directory `to`, except for `except`
+    async fn move_objects(
+        &self,
+        from: &object_store::path::Path,
+        to: &object_store::path::Path,
+        except: &object_store::path::Path,
+    ) -> Result<()> {
+        let (from, to) = (
+            DirsAndFileName::from(from.clone()),
+            DirsAndFileName::from(to.clone()),
+        );
+        let locations = self
+            .store
+            .list(Some(&self.store.path_from_parsed(from.clone())))
+            .await
+            .context(StoreError)?
+            .try_concat()
+            .await
+            .context(StoreError)?;
+
+        for location in locations.iter().filter(|l| *l != except) {
+            let moved = match DirsAndFileName::from(location.clone()).move_dir(&from, &to) {
+                Some(moved) => self.store.path_from_parsed(moved),
+                None => continue,
+            };
+            let data = get_store_bytes(location, &self.store).await?.freeze();
+            let len = data.len();
+            self.store
+                .put(
+                    &moved,
+                    futures::stream::once(async move { std::io::Result::Ok(data) }),
+                    Some(len),
+                )
+                .await
+                .context(StoreError)?;
+            self.store.delete(location).await.context(StoreError)?;
+        }
+        Ok(())
+    }
+
+    // writes the rules of a database to object storage
+    async fn persist_database_rules(
+        &self,
+        db_name: &DatabaseName<'_>,
+        rules: &DatabaseRules,
+    ) -> Result<()> {
+        let data = Bytes::from(serde_json::to_vec(rules).context(ErrorSerializing)?);
         let len = data.len();
-        let location =
-            object_store_path_for_database_config(&self.root_path()?, &db_reservation.name);
+        let location = object_store_path_for_database_config(&self.root_path()?, db_name);
 
         let stream_data = std::io::Result::Ok(data);
         self.store
@@ -219,13 +740,91 @@ impl<M: ConnectionManager> Server<M> {
                 Some(len),
             )
             .await
-            .context(StoreError)?;
+            .context(StoreError)
+    }
 
-        db_reservation.commit();
+    /// Deletes the rows of the database `db_name` matching `predicate`. The
+    /// delete is persisted next to the rules of the database, so that it
+    /// applies again as writes are replayed at startup, and is sent to the
+    /// other members of the cluster, to apply to their replicas.
+    pub async fn delete(
+        &self,
+        db_name: &DatabaseName<'_>,
+        predicate: DeletePredicate,
+    ) -> Result<()> {
+        let id = self.require_id()?;
+        let db = self.config.db(db_name).context(DatabaseNotFound {
+            db_name: db_name.as_str(),
+        })?;
+
+        // writes racing with the delete may be replayed before or after it,
+        // as they could have been made either way
+        let record = DeleteRecord {
+            predicate,
+            issued_by: id,
+            issued_at: Utc::now(),
+            write_buffer_offsets: self.write_buffer_offsets().await?,
+        };
+        self.apply_delete(db_name, &db, record.clone()).await?;
 
+        self.replicate_catalog_change(CatalogChange::Delete {
+            name: db_name.to_string(),
+            record,
+        })
+        .await;
         Ok(())
     }
 
+    // applies the delete of `record` to `db`, persisting the deletes of the
+    // database unless it was applied already
+    async fn apply_delete(
+        &self,
+        db_name: &DatabaseName<'_>,
+        db: &Db,
+        record: DeleteRecord,
+    ) -> Result<()> {
+        let _update = self.deletes_update.lock().await;
+        let applied = db
+            .apply_delete(record)
+            .await
+            .map_err(|e| Box::new(e) as DatabaseError)
+            .context(UnknownDatabaseError {})?;
+        if !applied {
+            return Ok(());
+        }
+
+        let records = db.delete_records();
+        let data = Bytes::from(serde_json::to_vec(&records).context(ErrorSerializing)?);
+        let len = data.len();
+        let location = object_store_path_for_database_deletes(&self.root_path()?, db_name);
+
+        let stream_data = std::io::Result::Ok(data);
+        self.store
+            .put(
+                &location,
+                futures::stream::once(async move { stream_data }),
+                Some(len),
+            )
+            .await
+            .context(StoreError)
+    }
+
+    // returns the offset of the next write of each partition of the write
+    // buffer, none if there is no write buffer
+    async fn write_buffer_offsets(&self) -> Result<BTreeMap<u32, i64>> {
+        let mut offsets = BTreeMap::new();
+        if let Some(write_buffer) = self.write_buffer() {
+            for partition in 0..write_buffer.partitions() {
+                let (_, end) = write_buffer
+                    .watermarks(partition)
+                    .await
+                    .context(WriteBufferFailed)?;
+                offsets.insert(partition, end);
+            }
+        }
+        Ok(offsets)
+    }
+
     // base location in object store for this writer
     fn root_path(&self) -> Result<object_store::path::Path> {
         let id = self.require_id()?;
@@ -236,8 +835,9 @@ impl<M: ConnectionManager> Server<M> {
     }
 
     /// Loads the database configurations based on the databases in the
-    /// object store. Any databases in the config already won't be
-    /// replaced.
+    /// object store, along with the org and token catalogs. Any databases in
+    /// the config already won't be replaced, and prefixes without database
+    /// rules are skipped.
     pub async fn load_database_configs(&self) -> Result<()> {
         // get the database names from the object store prefixes
         // TODO: update object store to pull back all common prefixes by
@@ -248,16 +848,74 @@ impl<M: ConnectionManager> Server<M> {
             .await
             .context(StoreError)?;
 
+        let orgs_location = self.orgs_path()?;
+        if list_result
+            .objects
+            .iter()
+            .any(|object| object.location == orgs_location)
+        {
+            let data = get_store_bytes(&orgs_location, &self.store).await?;
+            *self.orgs.lock().await = serde_json::from_slice(&data).context(ErrorDeserializing)?;
+        }
+
+        let tokens_location = self.tokens_path()?;
+        if list_result
+            .objects
+            .iter()
+            .any(|object| object.location == tokens_location)
+        {
+            let data = get_store_bytes(&tokens_location, &self.store).await?;
+            *self.tokens.write() = serde_json::from_slice(&data).context(ErrorDeserializing)?;
+        }
+
+        let catalog_log_location = self.catalog_log_path()?;
+        if list_result
+            .objects
+            .iter()
+            .any(|object| object.location == catalog_log_location)
+        {
+            let data = get_store_bytes(&catalog_log_location, &self.store).await?;
+            *self.catalog_log.lock().await =
+                serde_json::from_slice(&data).context(ErrorDeserializing)?;
+        }
+
         let handles: Vec<_> = list_result
             .common_prefixes
             .into_iter()
-            .map(|mut path| {
+            .map(|prefix| {
                 let store = Arc::clone(&self.store);
                 let config = Arc::clone(&self.config);
 
+                let mut path = prefix.clone();
                 path.set_file_name(DB_RULES_FILE_NAME);
+                let mut deletes_path = prefix.clone();
+                deletes_path.set_file_name(DELETES_FILE_NAME);
 
                 tokio::task::spawn(async move {
+                    // a prefix without rules isn't a database, e.g. one
+                    // whose deletion stopped part way through
+                    let has_deletes = loop {
+                        match store.list_with_delimiter(&prefix).await {
+                            Ok(list) if list.objects.iter().any(|o| o.location == path) => {
+                                break list.objects.iter().any(|o| o.location == deletes_path)
+                            }
+                            Ok(_) => {
+                                warn!("no database config {:?} in object store, skipping", path);
+                                return;
+                            }
+                            Err(e) => {
+                                error!(
+                                    "error listing database prefix {:?} in object store: {}",
+                                    prefix, e
+                                );
+                                tokio::time::sleep(tokio::time::Duration::from_secs(
+                                    STORE_ERROR_PAUSE_SECONDS,
+                                ))
+                                .await;
+                            }
+                        }
+                    };
+
                     let mut res = get_store_bytes(&path, &store).await;
                     while let Err(e) = &res {
                         error!(
@@ -281,7 +939,12 @@ impl<M: ConnectionManager> Server<M> {
                             Err(e) => error!("error parsing name {} from rules: {}", rules.name, e),
                             Ok(name) => match config.create_db(name, rules) {
                                 Err(e) => error!("error adding database to config: {}", e),
-                                Ok(handle) => handle.commit(),
+                                Ok(handle) => {
+                                    if has_deletes {
+                                        restore_deletes(&store, &deletes_path, &handle.db).await;
+                                    }
+                                    handle.commit()
+                                }
                             },
                         },
                     }
@@ -291,9 +954,230 @@ impl<M: ConnectionManager> Server<M> {
 
         futures::future::join_all(handles).await;
 
+        // without a write buffer to replay, the deletes restored apply to the
+        // data as loaded
+        if self.write_buffer().is_none() {
+            for (db_name, db, record) in self.pending_deletes() {
+                self.apply_delete(&db_name, &db, record).await?;
+            }
+        }
+
         Ok(())
     }
 
+    /// Returns the orgs in the org catalog
+    pub async fn orgs(&self) -> Vec<Org> {
+        self.orgs.lock().await.orgs().to_vec()
+    }
+
+    /// Returns the org with id `id` from the org catalog
+    pub async fn org(&self, id: &str) -> Option<Org> {
+        self.orgs.lock().await.get(id).cloned()
+    }
+
+    /// Adds an org named `name` to the org catalog, returning it
+    pub async fn create_org(&self, name: impl Into<String>) -> Result<Org> {
+        let name = name.into();
+        let writer_id = self.require_id()?;
+        self.update_orgs(|catalog| catalog.create(writer_id, name))
+            .await
+    }
+
+    /// Renames the org with id `id`. As buckets are stored under the name of
+    /// their org, the databases of its buckets are renamed with it.
+    pub async fn rename_org(&self, id: &str, name: impl Into<String>) -> Result<Org> {
+        self.require_quorum()?;
+        let mut orgs = self.orgs.lock().await;
+
+        let mut catalog = orgs.clone();
+        let old_name = catalog.get(id).context(OrgNotFound { id })?.name.clone();
+        let org = catalog.rename(id, name.into())?;
+
+        // map all the buckets before renaming any of them
+        let renames = bucket_databases(&self.config, &old_name)
+            .into_iter()
+            .map(|db_name| {
+                let (_, bucket) =
+                    database_to_org_and_bucket(db_name.as_str()).expect("bucket database");
+                let new_name: DatabaseName<'static> =
+                    org_and_bucket_to_database(&org.name, bucket).context(InvalidOrgName)?;
+                Ok((db_name, new_name))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        for (db_name, new_name) in renames {
+            self.rename_database(&db_name, new_name.clone()).await?;
+            self.replicate_catalog_change(CatalogChange::DatabaseRenamed {
+                from: db_name.to_string(),
+                to: new_name.to_string(),
+            })
+            .await;
+        }
+
+        self.persist_orgs(&catalog).await?;
+        *orgs = catalog;
+        drop(orgs);
+
+        self.replicate_catalog_change(CatalogChange::Org { org: org.clone() })
+            .await;
+        Ok(org)
+    }
+
+    /// Removes the org with id `id` from the org catalog. Orgs that have
+    /// buckets can not be deleted.
+    pub async fn delete_org(&self, id: &str) -> Result<Org> {
+        // checked while holding the org catalog, which creating a database
+        // waits for
+        let config = &self.config;
+        self.update_orgs(|catalog| {
+            let org = catalog.get(id).context(OrgNotFound { id })?;
+            if !bucket_databases(config, &org.name).is_empty() {
+                return OrgHasBuckets { name: &org.name }.fail();
+            }
+            catalog.remove(id)
+        })
+        .await
+    }
+
+    /// Replaces the rate limits of the org with id `id`. See the
+    /// [`rate_limits`] module for details.
+    pub async fn set_org_rate_limits(&self, id: &str, limits: RateLimits) -> Result<Org> {
+        self.update_orgs(|catalog| catalog.set_limits(id, limits))
+            .await
+    }
+
+    // applies `update`, which changes or removes the org it returns, to a
+    // copy of the org catalog, persisting and then keeping the copy if it
+    // succeeds
+    async fn update_orgs<F>(&self, update: F) -> Result<Org>
+    where
+        F: FnOnce(&mut OrgCatalog) -> Result<Org> + Send,
+    {
+        self.require_quorum()?;
+        let mut orgs = self.orgs.lock().await;
+
+        let mut catalog = orgs.clone();
+        let org = update(&mut catalog)?;
+        let change = match catalog.get(&org.id) {
+            Some(org) => CatalogChange::Org { org: org.clone() },
+            None => CatalogChange::OrgDeleted { id: org.id.clone() },
+        };
+
+        self.persist_orgs(&catalog).await?;
+        *orgs = catalog;
+        drop(orgs);
+
+        self.replicate_catalog_change(change).await;
+        Ok(org)
+    }
+
+    // writes the org catalog to object storage
+    async fn persist_orgs(&self, catalog: &OrgCatalog) -> Result<()> {
+        let location = self.orgs_path()?;
+        let data = Bytes::from(serde_json::to_vec(catalog).context(ErrorSerializing)?);
+        let len = data.len();
+        let stream_data = std::io::Result::Ok(data);
+        self.store
+            .put(
+                &location,
+                futures::stream::once(async move { stream_data }),
+                Some(len),
+            )
+            .await
+            .context(StoreError)
+    }
+
+    // location of the org catalog in object storage
+    fn orgs_path(&self) -> Result<object_store::path::Path> {
+        let mut path = self.root_path()?;
+        path.set_file_name(ORGS_FILE_NAME);
+        Ok(path)
+    }
+
+    /// Returns the tokens in the token catalog
+    pub fn tokens(&self) -> Vec<Token> {
+        self.tokens.read().tokens()
+    }
+
+    /// Returns the token with id `id` from the token catalog
+    pub fn token(&self, id: &str) -> Option<Token> {
+        self.tokens.read().get(id).cloned()
+    }
+
+    /// Adds a token as described by `request` to the token catalog,
+    /// returning it along with its secret. Only a hash of the secret is
+    /// stored, so it can not be retrieved later.
+    pub async fn create_token(&self, request: TokenRequest) -> Result<(Token, String)> {
+        let writer_id = self.require_id()?;
+        self.update_tokens(|catalog| catalog.create(writer_id, request))
+            .await
+    }
+
+    /// Removes the token with id `id` from the token catalog
+    pub async fn delete_token(&self, id: &str) -> Result<Token> {
+        let (token, ()) = self
+            .update_tokens(|catalog| Ok((catalog.remove(id)?, ())))
+            .await?;
+        Ok(token)
+    }
+
+    /// Returns the token of the token catalog whose secret is `secret`, if
+    /// any
+    pub fn authenticate_token(&self, secret: &str) -> Option<Token> {
+        self.tokens.read().authenticate(secret).cloned()
+    }
+
+    // applies `update`, which adds or removes the token it returns, to a
+    // copy of the token catalog, persisting and then keeping the copy if it
+    // succeeds
+    async fn update_tokens<F, T>(&self, update: F) -> Result<(Token, T)>
+    where
+        F: FnOnce(&mut TokenCatalog) -> Result<(Token, T)> + Send,
+    {
+        self.require_quorum()?;
+        let update_lock = self.tokens_update.lock().await;
+
+        let mut catalog = self.tokens.read().clone();
+        let (token, result) = update(&mut catalog)?;
+        let change = match catalog.stored(&token.id) {
+            Some(stored) => CatalogChange::Token {
+                token: stored.clone(),
+            },
+            None => CatalogChange::TokenDeleted {
+                id: token.id.clone(),
+            },
+        };
+
+        self.persist_tokens(&catalog).await?;
+        *self.tokens.write() = catalog;
+        drop(update_lock);
+
+        self.replicate_catalog_change(change).await;
+        Ok((token, result))
+    }
+
+    // writes the token catalog to object storage
+    async fn persist_tokens(&self, catalog: &TokenCatalog) -> Result<()> {
+        let location = self.tokens_path()?;
+        let data = Bytes::from(serde_json::to_vec(catalog).context(ErrorSerializing)?);
+        let len = data.len();
+        let stream_data = std::io::Result::Ok(data);
+        self.store
+            .put(
+                &location,
+                futures::stream::once(async move { stream_data }),
+                Some(len),
+            )
+            .await
+            .context(StoreError)
+    }
+
+    // location of the token catalog in object storage
+    fn tokens_path(&self) -> Result<object_store::path::Path> {
+        let mut path = self.root_path()?;
+        path.set_file_name(TOKENS_FILE_NAME);
+        Ok(path)
+    }
+
     /// Creates a host group with a set of connection strings to hosts. These
     /// host connection strings should be something that the connection
     /// manager can use to return a remote server to work with.
@@ -310,8 +1194,21 @@ impl<M: ConnectionManager> Server<M> {
     /// `ReplicatedWrite`, which is then replicated to other servers based
     /// on the configuration of the `db`. This is step #1 from the crate
     /// level documentation.
+    ///
+    /// Servers routing writes send the lines on to their backends instead.
     pub async fn write_lines(&self, db_name: &str, lines: &[ParsedLine<'_>]) -> Result<()> {
+        if self.drain.started() {
+            return Draining.fail();
+        }
+        self.require_quorum()?;
+
+        let router = self.shard_router.read().clone();
+        if let Some(router) = router {
+            return self.route_lines(&router, db_name, lines).await;
+        }
+
         let id = self.require_id()?;
+        self.require_writable()?;
 
         let db_name = DatabaseName::new(db_name).context(InvalidDatabaseName)?;
         let db = self
@@ -319,28 +1216,222 @@ impl<M: ConnectionManager> Server<M> {
             .db(&db_name)
             .context(DatabaseNotFound { db_name: &*db_name })?;
 
+        if let Some((org, _)) = database_to_org_and_bucket(db_name.as_str()) {
+            self.check_quotas(&org)?;
+        }
+        self.check_memory()?;
+
         let sequence = db.next_sequence();
-        let write = lines_to_replicated_write(id, sequence, lines, &db.rules);
+        let rules = db.rules();
+
+        let write_buffer = self.write_buffer.read().clone();
+        if let Some(write_buffer) = write_buffer {
+            self.produce_to_write_buffer(
+                write_buffer.as_ref(),
+                &db_name,
+                id,
+                sequence,
+                lines,
+                &rules,
+            )
+            .await?;
+        }
+
+        let write = lines_to_replicated_write(id, sequence, lines, &rules);
 
-        self.handle_replicated_write(&db_name, &db, write).await?;
+        let write = self.handle_replicated_write(&db_name, &db, write).await?;
+
+        let replication = self.peer_replication.read().clone();
+        if let Some(replication) = replication {
+            self.replicate_to_peers(&replication, &db_name, write)
+                .await?;
+        }
 
         Ok(())
     }
 
-    pub async fn handle_replicated_write(
+    /// Produces `lines`, the write `sequence` of this server to `db_name`,
+    /// to `write_buffer`, sharded across its partitions by series. Returns
+    /// once every shard is durably stored.
+    async fn produce_to_write_buffer(
         &self,
+        write_buffer: &dyn WriteBuffer,
         db_name: &DatabaseName<'_>,
-        db: &Db,
-        write: ReplicatedWrite,
+        id: u32,
+        sequence: u64,
+        lines: &[ParsedLine<'_>],
+        rules: &DatabaseRules,
     ) -> Result<()> {
-        if let Some(buf) = &db.mutable_buffer {
-            buf.store_replicated_write(&write)
-                .await
-                .map_err(|e| Box::new(e) as DatabaseError)
-                .context(UnknownDatabaseError {})?;
+        let partitions = write_buffer.partitions();
+        let mut shards: BTreeMap<u32, Vec<ParsedLine<'_>>> = BTreeMap::new();
+        for line in lines {
+            shards
+                .entry(write_buffer::partition(line, partitions))
+                .or_default()
+                .push(line.clone());
+        }
+
+        let writes = shards.into_iter().map(|(partition, lines)| async move {
+            let write = lines_to_replicated_write(id, sequence, &lines, rules);
+            write_buffer.produce(db_name, partition, &write).await
+        });
+        futures::future::try_join_all(writes)
+            .await
+            .context(WriteBufferFailed)?;
+
+        Ok(())
+    }
+
+    /// Sends each of `lines` to the backend of `router` its series is routed
+    /// to, returning once every backend has stored its lines
+    async fn route_lines(
+        &self,
+        router: &ShardRouter,
+        db_name: &str,
+        lines: &[ParsedLine<'_>],
+    ) -> Result<()> {
+        let db_name = DatabaseName::new(db_name).context(InvalidDatabaseName)?;
+        let db_name = &db_name;
+
+        let shards = router.shard_lines(lines, Utc::now().timestamp_nanos());
+        let shard_count = shards.len();
+        let writes = shards.into_iter().map(|(backend, lp)| async move {
+            let remote = self
+                .connection_manager
+                .remote_server(&backend)
+                .await
+                .map_err(|e| format!("{}: {}", backend, e))?;
+            remote
+                .write_lines(db_name, &lp)
+                .await
+                .map_err(|e| format!("{}: {}", backend, e))
+        });
+
+        let errors: Vec<_> = futures::future::join_all(writes)
+            .await
+            .into_iter()
+            .filter_map(Result::err)
+            .collect();
+        if !errors.is_empty() {
+            warn!(%db_name, ?errors, "Write not stored on every shard");
+            return ShardWriteFailed {
+                failed: errors.len(),
+                shards: shard_count,
+                errors: errors.join(", "),
+            }
+            .fail();
+        }
+
+        Ok(())
+    }
+
+    /// Stores `write`, accepted by the peer replicating its writes to this
+    /// server, in the database `db_name`. It is not replicated any further.
+    pub async fn store_peer_write(&self, db_name: &str, write: ReplicatedWrite) -> Result<()> {
+        self.require_id()?;
+        self.require_writable()?;
+        self.check_memory()?;
+
+        let db_name = DatabaseName::new(db_name).context(InvalidDatabaseName)?;
+        let db = self
+            .config
+            .db(&db_name)
+            .context(DatabaseNotFound { db_name: &*db_name })?;
+
+        self.store_replicated_write(&db_name, &db, write).await?;
+
+        Ok(())
+    }
+
+    /// Returns an error if the server is a read replica, which only stores
+    /// the writes of its primary, or is draining
+    fn require_writable(&self) -> Result<()> {
+        if self.drain.started() {
+            return Draining.fail();
+        }
+        match self.read_replica() {
+            Some(replica) => ReadReplicaWrite {
+                primary_id: replica.primary_id(),
+            }
+            .fail(),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns an error if the server lost the quorum of the cluster and is
+    /// configured to reject writes and catalog changes until it regains it.
+    /// See the [`cluster`] module for details.
+    fn require_quorum(&self) -> Result<()> {
+        match self.quorum_status() {
+            Some(status) if status.lost() && status.on_loss == OnQuorumLoss::RejectWrites => {
+                QuorumLost {
+                    alive: status.alive,
+                    required: status.required,
+                }
+                .fail()
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Stores `write` in `db` and replicates it as configured by the rules
+    /// of `db`, returning it once stored
+    pub async fn handle_replicated_write(
+        &self,
+        db_name: &DatabaseName<'_>,
+        db: &Db,
+        write: ReplicatedWrite,
+    ) -> Result<Arc<ReplicatedWrite>> {
+        let write = self.store_replicated_write(db_name, db, write).await?;
+
+        let rules = db.rules();
+        for host_group_id in &rules.replication {
+            self.replicate_to_host_group(host_group_id, db_name, &write)
+                .await?;
+        }
+
+        for subscription in &rules.subscriptions {
+            match subscription.matcher.tables {
+                MatchTables::All => {
+                    self.replicate_to_host_group(&subscription.host_group_id, db_name, &write)
+                        .await?
+                }
+                MatchTables::Table(_) => unimplemented!(),
+                MatchTables::Regex(_) => unimplemented!(),
+            }
         }
 
+        Ok(write)
+    }
+
+    /// Stores `write` in the mutable buffer and the WAL buffer of `db`. The
+    /// partitions already holding the write, such as when it is sent again
+    /// by anti-entropy repair, are skipped.
+    async fn store_replicated_write(
+        &self,
+        db_name: &DatabaseName<'_>,
+        db: &Db,
+        write: ReplicatedWrite,
+    ) -> Result<Arc<ReplicatedWrite>> {
+        let (writer_id, sequence) = write.writer_and_sequence();
+        let partition_keys = anti_entropy::partition_keys(&write);
+        let missing = db.write_log.missing(
+            writer_id,
+            sequence,
+            partition_keys.iter().map(String::as_str),
+        );
         let write = Arc::new(write);
+        if missing.is_empty() && !partition_keys.is_empty() {
+            return Ok(write);
+        }
+
+        if let Some(buf) = &db.mutable_buffer {
+            buf.store_replicated_write_partitions(&write, &missing)
+                .map_err(|e| Box::new(e) as DatabaseError)
+                .context(UnknownDatabaseError {})?;
+        }
+        db.write_log
+            .record(writer_id, sequence, missing.iter().map(String::as_str));
 
         if let Some(wal_buffer) = &db.wal_buffer {
             let persist;
@@ -348,6 +1439,14 @@ impl<M: ConnectionManager> Server<M> {
                 let mut wal_buffer = wal_buffer.lock();
                 persist = wal_buffer.persist;
 
+                // a write partly stored already is in the WAL buffer, and
+                // repaired writes older than the last write of their writer
+                // can't be appended to it
+                if missing.len() < partition_keys.len() || !wal_buffer.in_order(writer_id, sequence)
+                {
+                    return Ok(write);
+                }
+
                 // TODO: address this issue?
                 // the mutable buffer and the wal buffer have different locking mechanisms,
                 // which means that it's possible for a mutable buffer write to
@@ -367,28 +1466,87 @@ impl<M: ConnectionManager> Server<M> {
                             writer_id,
                             db_name,
                             store,
+                            self.encryption(),
                         )
                         .context(WalError)?;
                 }
             }
         }
 
-        for host_group_id in &db.rules.replication {
-            self.replicate_to_host_group(host_group_id, db_name, &write)
-                .await?;
+        Ok(write)
+    }
+
+    /// Sends `write` to every peer of `replication`, returning once enough
+    /// of them have stored it to reach the write quorum, or failing if they
+    /// haven't within the replication timeout. The peers yet to confirm the
+    /// write keep receiving it in the background. With hinted handoff, the
+    /// write is kept for the peers failing to store it.
+    async fn replicate_to_peers(
+        &self,
+        replication: &PeerReplication,
+        db_name: &DatabaseName<'_>,
+        write: Arc<ReplicatedWrite>,
+    ) -> Result<()> {
+        let hints = self.hinted_handoff.read().clone();
+        let mut errors = vec![];
+        let mut pending = FuturesUnordered::new();
+        for peer in replication.peers() {
+            let remote = match self.connection_manager.remote_server(peer).await {
+                Ok(remote) => remote,
+                Err(e) => {
+                    if let Some(hints) = &hints {
+                        hints.add(peer, db_name.as_str(), Arc::clone(&write));
+                    }
+                    errors.push(format!("{}: {}", peer, e));
+                    continue;
+                }
+            };
+            let peer = peer.clone();
+            let db_name = db_name.to_string();
+            let write = Arc::clone(&write);
+            let hints = hints.clone();
+            pending.push(tokio::spawn(async move {
+                let result = remote.replicate(&db_name, &write).await;
+                if let (Err(_), Some(hints)) = (&result, hints) {
+                    hints.add(&peer, &db_name, write);
+                }
+                result.map_err(|e| format!("{}: {}", peer, e))
+            }));
         }
 
-        for subscription in &db.rules.subscriptions {
-            match subscription.matcher.tables {
-                MatchTables::All => {
-                    self.replicate_to_host_group(&subscription.host_group_id, db_name, &write)
-                        .await?
+        let required = replication.peer_acks();
+        let mut acks = 0;
+        let deadline = tokio::time::sleep(replication.timeout());
+        tokio::pin!(deadline);
+        while acks < required {
+            // the peers yet to confirm the write when the deadline passes
+            // keep receiving it in the background
+            let next = tokio::select! {
+                next = pending.next() => next,
+                _ = &mut deadline => {
+                    errors.push(format!("timed out after {:?}", replication.timeout()));
+                    None
+                }
+            };
+            match next {
+                Some(Ok(Ok(()))) => acks += 1,
+                Some(Ok(Err(e))) => errors.push(e),
+                Some(Err(e)) => errors.push(e.to_string()),
+                None => {
+                    warn!(%db_name, ?errors, "Write quorum not reached");
+                    return WriteQuorumNotReached {
+                        stored: acks + 1,
+                        quorum: replication.quorum(),
+                        errors: errors.join(", "),
+                    }
+                    .fail();
                 }
-                MatchTables::Table(_) => unimplemented!(),
-                MatchTables::Regex(_) => unimplemented!(),
             }
         }
 
+        if !errors.is_empty() {
+            warn!(%db_name, ?errors, "Write not replicated to every peer");
+        }
         Ok(())
     }
 
@@ -429,140 +1587,2217 @@ impl<M: ConnectionManager> Server<M> {
         Ok(())
     }
 
+    /// Closes the open WAL buffer segment of every database configured to
+    /// store its segments and persists it to object storage, then waits for
+    /// any segment persistence still running in the background. Called on
+    /// shutdown so writes held only in memory are not lost. A database whose
+    /// segment fails to persist doesn't stop the others from persisting.
+    pub async fn persist_open_wal_segments(&self) -> Result<()> {
+        let writer_id = self.require_id()?;
+        let encryption = self.encryption();
+        let mut errors = vec![];
+
+        for db_name in self.config.db_names_sorted() {
+            let db = match self.config.db(&db_name) {
+                Some(db) => db,
+                None => continue,
+            };
+
+            let segment = match &db.wal_buffer {
+                Some(wal_buffer) => {
+                    let mut wal_buffer = wal_buffer.lock();
+                    if !wal_buffer.persist {
+                        continue;
+                    }
+                    wal_buffer.close_open_segment()
+                }
+                None => continue,
+            };
+
+            if let Some(segment) = segment {
+                if let Err(e) = segment
+                    .persist_bytes(writer_id, &db_name, &self.store, encryption.as_deref())
+                    .await
+                {
+                    error!(%db_name, "error persisting open WAL segment: {}", e);
+                    errors.push(format!("{}: {}", db_name, e));
+                }
+            }
+        }
+
+        self.segment_persistence_registry.idle().await;
+
+        ensure!(
+            errors.is_empty(),
+            PersistingOpenSegments {
+                failed: errors.len(),
+                errors: errors.join(", "),
+            }
+        );
+        Ok(())
+    }
+
     pub async fn db(&self, name: &DatabaseName<'_>) -> Option<Arc<Db>> {
         self.config.db(name)
     }
 
     pub async fn db_rules(&self, name: &DatabaseName<'_>) -> Option<DatabaseRules> {
-        self.config.db(name).map(|d| d.rules.clone())
+        self.config.db(name).map(|d| d.rules())
     }
-}
 
-#[async_trait]
-impl<M> DatabaseStore for Server<M>
-where
-    M: ConnectionManager + std::fmt::Debug + Send + Sync,
-{
-    type Database = Db;
-    type Error = Error;
+    /// Rolls over the open chunk of the partition `partition_key` in the
+    /// database `db_name` and starts writing it to object storage. The
+    /// returned snapshot can be used to follow the progress of the write.
+    pub async fn snapshot_partition(
+        &self,
+        db_name: &DatabaseName<'_>,
+        partition_key: &str,
+    ) -> Result<Arc<Snapshot<DBChunk>>> {
+        let (snapshot, _) = self.start_snapshot(db_name, partition_key).await?;
+        Ok(snapshot)
+    }
 
-    async fn db_names_sorted(&self) -> Vec<String> {
-        self.config
-            .db_names_sorted()
-            .iter()
-            .map(|i| i.clone().into())
-            .collect()
+    // starts a snapshot as `snapshot_partition` does, also returning a
+    // receiver notified once it has been written successfully
+    async fn start_snapshot(
+        &self,
+        db_name: &DatabaseName<'_>,
+        partition_key: &str,
+    ) -> Result<(Arc<Snapshot<DBChunk>>, tokio::sync::oneshot::Receiver<()>)> {
+        let db = self.config.db(db_name).context(DatabaseNotFound {
+            db_name: db_name.to_string(),
+        })?;
+
+        require_partition(&db, db_name, partition_key)?;
+
+        let chunk = db
+            .rollover_partition(partition_key)
+            .await
+            .map_err(|e| Box::new(e) as DatabaseError)
+            .context(UnknownDatabaseError {})?;
+
+        let mut metadata_path = self.store.new_path();
+        metadata_path.push_dir(db_name.to_string());
+        let mut data_path = metadata_path.clone();
+        metadata_path.push_dir("meta");
+        data_path.push_all_dirs(&["data", partition_key]);
+
+        let (notify, written) = tokio::sync::oneshot::channel();
+        let snapshot = snapshot::snapshot_chunk(
+            metadata_path,
+            data_path,
+            Arc::clone(&self.store),
+            self.encryption(),
+            partition_key,
+            chunk,
+            Some(self.scheduler.enqueue(scheduler::tenant(db_name.as_str()))),
+            Some(notify),
+        )
+        .context(SnapshottingPartition)?;
+
+        // only sent once the snapshot has been written successfully
+        let (persisted, persisted_rx) = tokio::sync::oneshot::channel();
+        let partition_key = partition_key.to_string();
+        tokio::spawn(async move {
+            if written.await.is_ok() {
+                db.record_persisted_chunk(&partition_key);
+                let _ = persisted.send(());
+            }
+        });
+
+        Ok((snapshot, persisted_rx))
     }
 
-    async fn db(&self, name: &str) -> Option<Arc<Self::Database>> {
-        if let Ok(name) = DatabaseName::new(name) {
-            return self.db(&name).await;
+    /// Starts draining the server, returning the initial status of the
+    /// drain, or `None` if a drain is already running or done. From now on
+    /// the server rejects writes; `drain` must then be called to persist
+    /// its data. See the [`drain`] module for details.
+    pub fn start_drain(&self) -> Option<DrainStatus> {
+        let status = self.drain.start();
+        if status.is_some() {
+            info!("draining server");
         }
+        status
+    }
 
-        None
+    /// Returns the status of the drain of the server, if started
+    pub fn drain_status(&self) -> Option<DrainStatus> {
+        self.drain.status()
     }
 
-    // TODO: refactor usages of this to use the Server rather than this trait and to
-    //       explicitly create a database.
-    async fn db_or_create(&self, name: &str) -> Result<Arc<Self::Database>, Self::Error> {
-        let db_name = DatabaseName::new(name.to_string()).context(InvalidDatabaseName)?;
+    /// Runs the drain started by `start_drain`, recording its progress and
+    /// its outcome in its status
+    pub async fn drain(&self) -> Result<()> {
+        let result = self.run_drain().await;
+        match &result {
+            Ok(()) => info!("server drained, safe to terminate"),
+            Err(e) => error!(%e, "error draining server"),
+        }
+        self.drain
+            .finish(result.as_ref().err().map(ToString::to_string));
+        result
+    }
 
-        let db = match self.db(&db_name).await {
-            Some(db) => db,
-            None => {
-                self.create_database(name, DatabaseRules::new()).await?;
-                self.db(&db_name).await.expect("db not inserted")
+    async fn run_drain(&self) -> Result<()> {
+        self.persist_open_wal_segments().await?;
+
+        let mut partitions = vec![];
+        for db_name in self.config.db_names_sorted() {
+            let db = match self.config.db(&db_name) {
+                Some(db) => db,
+                None => continue,
+            };
+            for status in db
+                .partition_statuses()
+                .map_err(|e| Box::new(e) as DatabaseError)
+                .context(UnknownDatabaseError {})?
+            {
+                if status.open_chunk_size > 0 {
+                    partitions.push((db_name.clone(), status.key));
+                }
             }
-        };
+        }
+        self.drain
+            .update(|status| status.partitions = partitions.len());
+
+        for (db_name, partition_key) in partitions {
+            let (_, persisted) = self.start_snapshot(&db_name, &partition_key).await?;
+            persisted.await.ok().context(DrainSnapshotFailed {
+                db_name: db_name.as_str(),
+                partition_key,
+            })?;
+            self.drain.update(|status| status.partitions_persisted += 1);
+        }
 
-        Ok(db)
+        self.drain
+            .update(|status| status.phase = DrainPhase::ReleasingLeases);
+        if let Some(leases) = self.partition_leases() {
+            let id = self.require_id()?;
+            for (db_name, partition_key) in leases.held() {
+                let (dir, location) = self.lease_path(&db_name, &partition_key);
+                if let Some(lease) = self.read_lease(&dir, &location).await? {
+                    if lease.owner == id {
+                        self.store.delete(&location).await.context(StoreError)?;
+                    }
+                }
+                leases.record(&db_name, &partition_key, None);
+                self.drain.update(|status| status.leases_released += 1);
+            }
+        }
+
+        Ok(())
     }
 
-    fn executor(&self) -> Arc<Executor> {
-        Arc::clone(&self.executor)
+    /// Returns the status of each partition of the database `db_name`
+    pub async fn partition_statuses(
+        &self,
+        db_name: &DatabaseName<'_>,
+    ) -> Result<Vec<PartitionStatus>> {
+        let db = self.config.db(db_name).context(DatabaseNotFound {
+            db_name: db_name.to_string(),
+        })?;
+
+        db.partition_statuses()
+            .map_err(|e| Box::new(e) as DatabaseError)
+            .context(UnknownDatabaseError {})
     }
-}
 
-/// The `Server` will ask the `ConnectionManager` for connections to a specific
-/// remote server. These connections can be used to communicate with other
-/// servers. This is implemented as a trait for dependency injection in testing.
-#[async_trait]
-pub trait ConnectionManager {
-    type Error: std::error::Error + Send + Sync + 'static;
+    /// Sets the encryption of the chunk files and WAL segments written to
+    /// object storage from now on. See the [`encryption`] module for
+    /// details.
+    pub fn set_encryption(&self, encryption: Option<Arc<Encryption>>) {
+        *self.encryption.write() = encryption;
+    }
 
-    type RemoteServer: RemoteServer + Send + Sync + 'static;
+    /// Returns the encryption of the files written to object storage, if
+    /// enabled
+    pub fn encryption(&self) -> Option<Arc<Encryption>> {
+        self.encryption.read().clone()
+    }
 
-    async fn remote_server(&self, connect: &str) -> Result<Arc<Self::RemoteServer>, Self::Error>;
-}
+    /// Sets the quotas limiting the series and bytes stored by each org. See
+    /// the [`quotas`] module for details.
+    pub fn set_quotas(&self, quotas: Quotas) {
+        *self.quotas.write() = quotas;
+    }
 
-/// The `RemoteServer` represents the API for replicating, subscribing, and
-/// querying other servers.
-#[async_trait]
-pub trait RemoteServer {
-    type Error: std::error::Error + Send + Sync + 'static;
+    /// Returns the quotas applied to each org
+    pub fn quotas(&self) -> Quotas {
+        *self.quotas.read()
+    }
 
-    /// Sends a replicated write to a remote server. This is step #2 from the
-    /// diagram.
-    async fn replicate(
-        &self,
-        db: &str,
-        replicated_write: &ReplicatedWrite,
-    ) -> Result<(), Self::Error>;
-}
+    /// Returns the series and bytes stored by each bucket of the org `org`
+    pub fn org_usage(&self, org: &str) -> Result<OrgUsage> {
+        let mut usage = OrgUsage::default();
+        for db_name in self.config.db_names_sorted() {
+            let bucket = match database_to_org_and_bucket(db_name.as_str()) {
+                Some((db_org, bucket)) if db_org == org => bucket,
+                _ => continue,
+            };
+            let db = match self.config.db(&db_name) {
+                Some(db) => db,
+                None => continue,
+            };
 
-/// The connection manager maps a host identifier to a remote server.
-#[derive(Debug)]
-pub struct ConnectionManagerImpl {}
+            let mut bucket_usage = Usage::default();
+            for status in db
+                .partition_statuses()
+                .map_err(|e| Box::new(e) as DatabaseError)
+                .context(UnknownDatabaseError {})?
+            {
+                bucket_usage += Usage {
+                    series: status.series_count as u64,
+                    bytes: status.mutable_buffer_size as u64 + status.read_buffer_size,
+                };
+            }
+            usage.total += bucket_usage;
+            usage.buckets.push(BucketUsage {
+                bucket,
+                usage: bucket_usage,
+            });
+        }
+        Ok(usage)
+    }
 
-#[async_trait]
-impl ConnectionManager for ConnectionManagerImpl {
-    type Error = Error;
-    type RemoteServer = RemoteServerImpl;
+    /// Returns an error if `org` is over a hard limit of the quotas, logging
+    /// a warning when it first goes over a soft limit
+    fn check_quotas(&self, org: &str) -> Result<()> {
+        let quotas = self.quotas();
+        if !quotas.enabled() {
+            return Ok(());
+        }
+
+        let usage = self.org_usage(org)?.total;
+        let (check, crossed) = self.soft_limits.lock().check(&quotas, org, usage);
+        for (resource, usage, limit) in crossed {
+            warn!(org, %resource, usage, limit, "Org is over the soft limit of its quota");
+        }
 
-    async fn remote_server(&self, _connect: &str) -> Result<Arc<Self::RemoteServer>, Self::Error> {
-        unimplemented!()
+        match check {
+            Check::Allowed => Ok(()),
+            Check::Exceeded {
+                resource,
+                usage,
+                limit,
+            } => QuotaExceeded {
+                org,
+                resource,
+                usage,
+                limit,
+            }
+            .fail(),
+        }
     }
-}
 
-/// An implementation for communicating with other IOx servers. This should
-/// be moved into and implemented in an influxdb_iox_client create at a later
-/// date.
-#[derive(Debug)]
-pub struct RemoteServerImpl {}
+    /// Counts a write of `points` points by `token`, if it isn't one of the
+    /// tokens configured at startup, to `org` against their rate limits,
+    /// returning an error if either is over them
+    pub async fn check_write_rate(
+        &self,
+        token: Option<&Token>,
+        org: &str,
+        points: usize,
+    ) -> Result<()> {
+        let subjects = self.rate_limit_subjects(token, Some(org)).await;
+        if subjects.is_empty() {
+            return Ok(());
+        }
+        self.rate_limiter
+            .check_write(&subjects, points as u64, Instant::now())
+    }
 
-#[async_trait]
-impl RemoteServer for RemoteServerImpl {
-    type Error = Error;
+    /// Counts a query by `token` in `org` against their limits of
+    /// concurrent queries, until the returned permit is dropped. Queries not
+    /// within an org, such as those of databases not backing a bucket, are
+    /// only subject to the limits of the token.
+    pub async fn start_query(
+        &self,
+        token: Option<&Token>,
+        org: Option<&str>,
+    ) -> Result<QueryPermit> {
+        self.check_memory()?;
+        let subjects = self.rate_limit_subjects(token, org).await;
+        self.rate_limiter.start_query(&subjects, Instant::now())
+    }
 
-    async fn replicate(
+    // the rate limits that apply to a request by `token` in `org`
+    async fn rate_limit_subjects(
         &self,
-        _db: &str,
-        _replicated_write: &ReplicatedWrite,
-    ) -> Result<(), Self::Error> {
-        unimplemented!()
+        token: Option<&Token>,
+        org: Option<&str>,
+    ) -> Vec<(Subject, RateLimits)> {
+        let mut subjects = vec![];
+        if let Some(token) = token {
+            subjects.push((Subject::Token(token.id.clone()), token.limits));
+        }
+        if let Some(org) = org {
+            let orgs = self.orgs.lock().await;
+            if let Some(org) = orgs.find(org) {
+                subjects.push((Subject::Org(org.name.clone()), org.limits));
+            }
+        }
+        subjects.retain(|(_, limits)| !limits.is_unlimited());
+        subjects
     }
-}
 
-// get bytes from the location in object store
-async fn get_store_bytes(
-    location: &object_store::path::Path,
-    store: &ObjectStore,
-) -> Result<bytes::BytesMut> {
-    let b = store
-        .get(location)
-        .await
-        .context(StoreError)?
-        .map_ok(|b| bytes::BytesMut::from(&b[..]))
-        .try_concat()
-        .await
-        .context(StoreError)?;
+    /// Limits the memory of the process to `limit` bytes, or removes the
+    /// limit if None. See the [`memory`] module for details.
+    pub fn set_memory_limit(&self, limit: Option<u64>) {
+        self.memory.set_limit(limit);
+    }
 
-    Ok(b)
-}
+    /// Returns the tracker of the memory held by the server, e.g. for the
+    /// caches to account for what they hold
+    pub fn memory_tracker(&self) -> Arc<MemoryTracker> {
+        Arc::clone(&self.memory)
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Measures the memory held by the databases, the hinted writes and the
+    /// queries running, returning the tracker recording it
+    pub fn measure_memory(&self) -> &MemoryTracker {
+        let mut mutable_buffer = 0;
+        let mut read_buffer = 0;
+        let mut wal_buffers = 0;
+        for db_name in self.config.db_names_sorted() {
+            if let Some(db) = self.config.db(&db_name) {
+                mutable_buffer += db.mutable_buffer.as_ref().map_or(0, |buf| buf.size()) as u64;
+                read_buffer += db.read_buffer.size();
+                wal_buffers += db.wal_buffer.as_ref().map_or(0, |buf| buf.lock().size());
+            }
+        }
+        let hinted_writes = self.hinted_handoff().map_or(0, |hints| hints.size()) as u64;
+
+        self.memory.set(Subsystem::MutableBuffer, mutable_buffer);
+        self.memory.set(Subsystem::ReadBuffer, read_buffer);
+        self.memory.set(Subsystem::WalBuffers, wal_buffers);
+        self.memory.set(Subsystem::HintedWrites, hinted_writes);
+        self.memory
+            .set(Subsystem::Queries, self.executor.memory_used() as u64);
+        &self.memory
+    }
+
+    /// Measures the memory held by the server like `measure_memory`, and
+    /// starts compacting the mutable buffer if it is under memory pressure.
+    /// Measuring goes through all the data, so it is done periodically
+    /// rather than for every write and query, which are checked against the
+    /// last measure.
+    pub async fn update_memory_usage(&self) {
+        if self.memory.limit().is_none() {
+            return;
+        }
+
+        if self.measure_memory().under_pressure() {
+            self.relieve_memory_pressure().await;
+        }
+    }
+
+    /// Returns an error if the server was at its memory limit when last
+    /// measured by `update_memory_usage`
+    fn check_memory(&self) -> Result<()> {
+        self.memory.check().context(MemoryLimitReached)
+    }
+
+    /// Starts compacting the mutable buffer of every database into the
+    /// read buffer, unless compactions are running already
+    async fn relieve_memory_pressure(&self) {
+        let compacting = self
+            .compactions
+            .statuses()
+            .iter()
+            .any(|status| status.state == CompactionState::Running);
+        if compacting {
+            return;
+        }
+
+        warn!(
+            used = self.memory.total(),
+            limit = ?self.memory.limit(),
+            "Server is under memory pressure, compacting the mutable buffer"
+        );
+        for db_name in self.config.db_names_sorted() {
+            let has_data = self
+                .config
+                .db(&db_name)
+                .and_then(|db| db.mutable_buffer.as_ref().map(|buf| buf.size() > 0))
+                .unwrap_or(false);
+            if !has_data {
+                continue;
+            }
+            if let Err(e) = self.compact(&db_name, None).await {
+                warn!(%db_name, error = %e, "Compaction relieving memory pressure failed");
+            }
+        }
+    }
+
+    /// Starts a job compacting the partition `partition_key` of the database
+    /// `db_name`, or all of its partitions if `None`, returning the job's
+    /// initial status. See the [`compaction`] module for details. With
+    /// partition leases, only the partitions this server owns are
+    /// compacted.
+    pub async fn compact(
+        &self,
+        db_name: &DatabaseName<'_>,
+        partition_key: Option<&str>,
+    ) -> Result<CompactionStatus> {
+        let db = self.config.db(db_name).context(DatabaseNotFound {
+            db_name: db_name.to_string(),
+        })?;
+
+        let partition_keys = match partition_key {
+            Some(partition_key) => {
+                require_partition(&db, db_name, partition_key)?;
+                if let Ownership::HeldBy(owner) = self
+                    .partition_ownership(db_name.as_str(), partition_key)
+                    .await?
+                {
+                    return PartitionLeaseHeld {
+                        db_name: db_name.to_string(),
+                        partition_key,
+                        owner,
+                    }
+                    .fail();
+                }
+                vec![partition_key.to_string()]
+            }
+            None => {
+                self.owned_partitions(db_name.as_str(), partition_keys(&db)?)
+                    .await?
+            }
+        };
+
+        let job = self.scheduler.enqueue(scheduler::tenant(db_name.as_str()));
+        Ok(self
+            .compactions
+            .start(db_name.to_string(), db, partition_keys, job))
+    }
+
+    /// Replicates the writes accepted from now on to peers as configured by
+    /// `replication`. See the [`replication`] module for details.
+    pub fn set_peer_replication(&self, replication: PeerReplication) {
+        *self.peer_replication.write() = Some(Arc::new(replication));
+    }
+
+    /// Keeps the writes peers fail to store in `hints`, for
+    /// `deliver_hints` to send them later. See the [`hints`] module for
+    /// details.
+    pub fn set_hinted_handoff(&self, hints: HintedHandoff) {
+        *self.hinted_handoff.write() = Some(Arc::new(hints));
+    }
+
+    /// Returns the hints kept for the peers, if hinted handoff is enabled
+    pub fn hinted_handoff(&self) -> Option<Arc<HintedHandoff>> {
+        self.hinted_handoff.read().clone()
+    }
+
+    /// Mirrors the object store to a secondary object store, such as a
+    /// bucket in another region, through `replication`. See the [`region`]
+    /// module for details.
+    pub fn set_region_replication(&self, replication: RegionReplication) {
+        *self.region_replication.write() = Some(Arc::new(replication));
+    }
+
+    /// Returns the replication to a secondary object store, if enabled
+    pub fn region_replication(&self) -> Option<Arc<RegionReplication>> {
+        self.region_replication.read().clone()
+    }
+
+    /// Makes the server route the writes it receives to the backends of
+    /// `router` instead of storing them
+    pub fn set_shard_router(&self, router: ShardRouter) {
+        *self.shard_router.write() = Some(Arc::new(router));
+    }
+
+    /// Returns the router of the writes the server receives, if it routes
+    /// them
+    pub fn shard_router(&self) -> Option<Arc<ShardRouter>> {
+        self.shard_router.read().clone()
+    }
+
+    /// Starts moving the ownership of the shard `shard` of the router to
+    /// the backend `to`, returning the initial status of the move; `move_shard`
+    /// must then be called to run it. A failed move started again replays
+    /// the writes of the same source. See the [`rebalance`] module for
+    /// details.
+    pub fn start_shard_move(&self, shard: u64, to: &str) -> Result<ShardMove> {
+        let router = self.shard_router().context(RoutingDisabled)?;
+        let current = router.shard(shard).context(InvalidShardMove)?;
+        router.with_owner(shard, to).context(InvalidShardMove)?;
+
+        let from = match self.shard_moves.get(shard) {
+            Some(last) if last.phase == MovePhase::Failed && last.to == to => last.from,
+            _ => current.owner,
+        };
+        let shard_id = format!("{:016x}", shard);
+        ensure!(
+            from != to,
+            ShardAlreadyOwned {
+                shard: &shard_id,
+                backend: to
+            }
+        );
+
+        let status = self
+            .shard_moves
+            .start(shard, &from, to)
+            .context(ShardMoveRunning { shard: &shard_id })?;
+        info!(shard = %shard_id, %from, %to, "moving shard");
+        Ok(status)
+    }
+
+    /// Returns the status of the last move of the shard `shard`, if any
+    pub fn shard_move(&self, shard: u64) -> Option<ShardMove> {
+        self.shard_moves.get(shard)
+    }
+
+    /// Returns the status of the last move of every shard moved since the
+    /// server started
+    pub fn shard_moves(&self) -> Vec<ShardMove> {
+        self.shard_moves.all()
+    }
+
+    /// Runs the move of `shard` started by `start_shard_move`, recording
+    /// its progress and its outcome in its status
+    pub async fn move_shard(&self, shard: u64) -> Result<()> {
+        let result = self.run_shard_move(shard).await;
+        match &result {
+            Ok(()) => info!(shard = %format!("{:016x}", shard), "shard moved"),
+            Err(e) => error!(shard = %format!("{:016x}", shard), %e, "error moving shard"),
+        }
+        self.shard_moves
+            .finish(shard, result.as_ref().err().map(ToString::to_string));
+        result
+    }
+
+    async fn run_shard_move(&self, shard: u64) -> Result<()> {
+        let status = self.shard_moves.get(shard).expect("shard move started");
+
+        let range = {
+            let _update_lock = self.shard_owners_update.lock().await;
+            let router = self.shard_router().context(RoutingDisabled)?;
+            let rerouted = router
+                .with_owner(shard, &status.to)
+                .context(InvalidShardMove)?;
+            self.persist_shard_owners(&rerouted).await?;
+            let range = rerouted.shard(shard).context(InvalidShardMove)?.range;
+            self.set_shard_router(rerouted);
+            range
+        };
+
+        self.shard_moves
+            .update(shard, |status| status.phase = MovePhase::Replaying);
+        let remote = self
+            .connection_manager
+            .remote_server(&status.from)
+            .await
+            .map_err(|e| Box::new(e) as DatabaseError)
+            .context(ShardHandOffFailed {
+                server: &status.from,
+            })?;
+        let lines = remote
+            .hand_off_shard(range, &status.to)
+            .await
+            .map_err(|e| Box::new(e) as DatabaseError)
+            .context(ShardHandOffFailed {
+                server: &status.from,
+            })?;
+        self.shard_moves
+            .update(shard, |status| status.lines_replayed = lines);
+
+        Ok(())
+    }
+
+    /// Replays the writes to the series of `range` the WAL buffers of the
+    /// databases of this server hold into the server `target`, which now
+    /// owns them, returning the number of lines replayed. See the
+    /// [`rebalance`] module for details.
+    pub async fn hand_off_shard(&self, range: ShardRange, target: &str) -> Result<u64> {
+        self.require_id()?;
+
+        let remote = self
+            .connection_manager
+            .remote_server(target)
+            .await
+            .map_err(|e| Box::new(e) as DatabaseError)
+            .context(ShardHandOffFailed { server: target })?;
+
+        let mut replayed = 0;
+        for db_name in self.config.db_names_sorted() {
+            let db = match self.config.db(&db_name) {
+                Some(db) => db,
+                None => continue,
+            };
+            let writes: Vec<_> = match &db.wal_buffer {
+                Some(wal_buffer) => wal_buffer.lock().writes().cloned().collect(),
+                None => {
+                    warn!(%db_name, "database has no WAL buffer, its shard data is not handed off");
+                    continue;
+                }
+            };
+
+            let lines: Vec<_> = writes
+                .iter()
+                .flat_map(|write| rebalance::lines_in_range(write, &range))
+                .collect();
+            for batch in lines.chunks(rebalance::REPLAY_BATCH_LINES) {
+                remote
+                    .write_lines(&db_name, &batch.join("\n"))
+                    .await
+                    .map_err(|e| Box::new(e) as DatabaseError)
+                    .context(ShardHandOffFailed { server: target })?;
+                replayed += batch.len() as u64;
+            }
+        }
+
+        info!(%target, lines = replayed, "handed off shard");
+        Ok(replayed)
+    }
+
+    /// Routes the shards moved by this or another router sharing the object
+    /// store to their recorded owners. Owners that are no longer backends
+    /// are skipped.
+    pub async fn load_shard_owners(&self) -> Result<()> {
+        let router = match self.shard_router() {
+            Some(router) => router,
+            None => return Ok(()),
+        };
+        let _update_lock = self.shard_owners_update.lock().await;
+
+        let location = self.shard_owners_path();
+        let list_result = self
+            .store
+            .list_with_delimiter(&self.store.new_path())
+            .await
+            .context(StoreError)?;
+        if !list_result
+            .objects
+            .iter()
+            .any(|object| object.location == location)
+        {
+            return Ok(());
+        }
+        let data = get_store_bytes(&location, &self.store).await?;
+        let owners: BTreeMap<String, String> =
+            serde_json::from_slice(&data).context(ErrorDeserializing)?;
+
+        let mut loaded = ShardRouter::new(router.backends().to_vec()).context(InvalidShardMove)?;
+        for (shard, owner) in owners {
+            let moved = u64::from_str_radix(&shard, 16)
+                .ok()
+                .and_then(|shard| loaded.with_owner(shard, &owner).ok());
+            match moved {
+                Some(moved) => loaded = moved,
+                None => warn!(%shard, %owner, "ignoring the unknown owner of a shard"),
+            }
+        }
+        if loaded.moved_shards() != router.moved_shards() {
+            info!(
+                moved = loaded.moved_shards().len(),
+                "loaded the owners of the shards"
+            );
+            self.set_shard_router(loaded);
+        }
+
+        Ok(())
+    }
+
+    // writes the owners of the moved shards of `router` to object storage
+    async fn persist_shard_owners(&self, router: &ShardRouter) -> Result<()> {
+        let owners: BTreeMap<_, _> = router
+            .moved_shards()
+            .into_iter()
+            .map(|(shard, owner)| (format!("{:016x}", shard), owner))
+            .collect();
+        let data = Bytes::from(serde_json::to_vec(&owners).context(ErrorSerializing)?);
+        let len = data.len();
+        let stream_data = std::io::Result::Ok(data);
+        self.store
+            .put(
+                &self.shard_owners_path(),
+                futures::stream::once(async move { stream_data }),
+                Some(len),
+            )
+            .await
+            .context(StoreError)
+    }
+
+    // location of the owners of the moved shards in object storage, shared
+    // by every router
+    fn shard_owners_path(&self) -> object_store::path::Path {
+        let mut path = self.store.new_path();
+        path.set_file_name(SHARD_OWNERS_FILE_NAME);
+        path
+    }
+
+    /// Makes the server take part in the cluster membership `membership`,
+    /// gossiping with the other members through `gossip_round`
+    pub fn set_membership(&self, membership: Membership) {
+        *self.membership.write() = Some(Arc::new(membership));
+    }
+
+    /// Returns the cluster membership of the server, if enabled
+    pub fn membership(&self) -> Option<Arc<Membership>> {
+        self.membership.read().clone()
+    }
+
+    /// Makes the server protect the cluster from split brain as configured
+    /// by `quorum`, once cluster membership is enabled
+    pub fn set_quorum(&self, quorum: Quorum) {
+        *self.quorum.write() = Some(quorum);
+    }
+
+    /// Returns whether the server has the quorum of the cluster, if
+    /// membership and a quorum are configured
+    pub fn quorum_status(&self) -> Option<QuorumStatus> {
+        let quorum = (*self.quorum.read())?;
+        let membership = self.membership()?;
+        Some(membership.quorum_status(&quorum))
+    }
+
+    /// Runs a gossip round, exchanging this server's view of the cluster's
+    /// members with the members and seeds due this round. Members that
+    /// can't be reached are skipped; they are considered down once they
+    /// stop sending heartbeats.
+    pub async fn gossip_round(&self) -> Result<()> {
+        let membership = self.membership().context(ClusterDisabled)?;
+
+        let databases = self
+            .config
+            .db_names_sorted()
+            .into_iter()
+            .map(|name| name.to_string())
+            .collect();
+        let targets = membership.start_round(databases);
+        let view = membership.gossip();
+
+        let exchanges = targets.into_iter().map(|target| {
+            let view = view.clone();
+            async move {
+                let result = match self.connection_manager.remote_server(&target).await {
+                    Ok(remote) => remote.gossip(view).await.map_err(|e| e.to_string()),
+                    Err(e) => Err(e.to_string()),
+                };
+                (target, result)
+            }
+        });
+
+        let mut reached = vec![];
+        for (target, result) in futures::future::join_all(exchanges).await {
+            match result {
+                Ok(states) => {
+                    membership.merge(states);
+                    reached.push(target);
+                }
+                Err(e) => debug!(%target, error = %e, "Gossip failed"),
+            }
+        }
+
+        if let Some(status) = self.quorum_status() {
+            let lost = status.lost();
+            if self.quorum_lost.swap(lost, Ordering::Relaxed) != lost {
+                if lost {
+                    error!(
+                        alive = status.alive,
+                        required = status.required,
+                        on_loss = ?status.on_loss,
+                        "Lost the quorum of the cluster"
+                    );
+                } else {
+                    info!(alive = status.alive, "Regained the quorum of the cluster");
+                }
+            }
+        }
+
+        // exchange the catalog with the members gossiped with, for those
+        // that missed changes to catch up
+        let entries = self.catalog_log.lock().await.entries();
+        for target in reached {
+            if let Err(e) = self.sync_catalog_with(&target, entries.clone()).await {
+                debug!(%target, error = %e, "Catalog sync failed");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Applies the catalog entries `entries`, sent by another member of the
+    /// cluster, that are more recent than those of this server, and returns
+    /// all the entries of this server. See the [`catalog`] module for
+    /// details.
+    pub async fn sync_catalog(&self, entries: Vec<CatalogEntry>) -> Result<Vec<CatalogEntry>> {
+        self.membership().context(ClusterDisabled)?;
+        self.apply_catalog_entries(entries).await?;
+        Ok(self.catalog_log.lock().await.entries())
+    }
+
+    /// Records `change`, just made on this server, in the catalog log and
+    /// sends it to the members of the cluster known to be alive, if cluster
+    /// membership is enabled. The change has been made already, so errors
+    /// are only logged: members that miss it catch up on later gossip
+    /// rounds.
+    async fn replicate_catalog_change(&self, change: CatalogChange) {
+        let membership = match self.membership() {
+            Some(membership) => membership,
+            None => return,
+        };
+        let id = match self.require_id() {
+            Ok(id) => id,
+            Err(_) => return,
+        };
+
+        let entry = {
+            let mut log = self.catalog_log.lock().await;
+            let entry = log.record(id, change);
+            if let Err(e) = self.persist_catalog_log(&log).await {
+                error!(error = %e, "Unable to persist the catalog log");
+            }
+            entry
+        };
+
+        let targets = membership
+            .members()
+            .into_iter()
+            .filter(|member| !member.local && member.status == Status::Alive)
+            .map(|member| member.state.address);
+        let syncs = targets.map(|target| {
+            let entries = vec![entry.clone()];
+            async move {
+                let result = self.sync_catalog_with(&target, entries).await;
+                (target, result)
+            }
+        });
+        for (target, result) in futures::future::join_all(syncs).await {
+            if let Err(e) = result {
+                warn!(%target, error = %e, "Unable to send catalog change");
+            }
+        }
+    }
+
+    // sends `entries` to the member `target`, applying the entries it
+    // returns
+    async fn sync_catalog_with(&self, target: &str, entries: Vec<CatalogEntry>) -> Result<()> {
+        let remote = self
+            .connection_manager
+            .remote_server(target)
+            .await
+            .map_err(|e| Box::new(e) as DatabaseError)
+            .context(CatalogSyncFailed { server: target })?;
+        let entries = remote
+            .sync_catalog(entries)
+            .await
+            .map_err(|e| Box::new(e) as DatabaseError)
+            .context(CatalogSyncFailed { server: target })?;
+        self.apply_catalog_entries(entries).await?;
+        Ok(())
+    }
+
+    // applies the entries more recent than those of the catalog log,
+    // returning the number applied. An entry failing to apply is skipped,
+    // to be received again later.
+    async fn apply_catalog_entries(&self, entries: Vec<CatalogEntry>) -> Result<usize> {
+        let mut log = self.catalog_log.lock().await;
+        let mut applied = 0;
+        for entry in entries {
+            if !log.is_newer(&entry) {
+                continue;
+            }
+            match self.apply_catalog_change(entry.change.clone()).await {
+                Ok(()) => {
+                    log.insert(entry);
+                    applied += 1;
+                }
+                Err(e) => {
+                    warn!(version = ?entry.version, error = %e, "Unable to apply catalog change")
+                }
+            }
+        }
+
+        if applied > 0 {
+            self.persist_catalog_log(&log).await?;
+            info!(
+                changes = applied,
+                "Applied catalog changes from the cluster"
+            );
+        }
+        Ok(applied)
+    }
+
+    // makes `change`, made on another member, without replicating it
+    async fn apply_catalog_change(&self, change: CatalogChange) -> Result<()> {
+        match change {
+            CatalogChange::Org { org } => {
+                let mut orgs = self.orgs.lock().await;
+                let mut catalog = orgs.clone();
+                catalog.upsert(org);
+                self.persist_orgs(&catalog).await?;
+                *orgs = catalog;
+            }
+            CatalogChange::OrgDeleted { id } => {
+                let mut orgs = self.orgs.lock().await;
+                let mut catalog = orgs.clone();
+                if catalog.remove(&id).is_ok() {
+                    self.persist_orgs(&catalog).await?;
+                    *orgs = catalog;
+                }
+            }
+            CatalogChange::Token { token } => {
+                let _update = self.tokens_update.lock().await;
+                let mut catalog = self.tokens.read().clone();
+                catalog.upsert(token);
+                self.persist_tokens(&catalog).await?;
+                *self.tokens.write() = catalog;
+            }
+            CatalogChange::TokenDeleted { id } => {
+                let _update = self.tokens_update.lock().await;
+                let mut catalog = self.tokens.read().clone();
+                if catalog.remove(&id).is_ok() {
+                    self.persist_tokens(&catalog).await?;
+                    *self.tokens.write() = catalog;
+                }
+            }
+            CatalogChange::Database { rules } => {
+                let db_name = DatabaseName::new(rules.name.clone()).context(InvalidDatabaseName)?;
+                let _update = self.db_rules_update.lock().await;
+                match self.config.db(&db_name) {
+                    Some(db) => {
+                        self.persist_database_rules(&db_name, &rules).await?;
+                        db.set_rules(rules);
+                    }
+                    None => {
+                        let db_reservation = self.config.create_db(db_name, rules)?;
+                        self.persist_database_rules(
+                            &db_reservation.name,
+                            &db_reservation.db.rules(),
+                        )
+                        .await?;
+                        db_reservation.commit();
+                    }
+                }
+            }
+            CatalogChange::DatabaseDeleted { name } => {
+                let db_name = DatabaseName::new(name).context(InvalidDatabaseName)?;
+                if self.config.db(&db_name).is_some() {
+                    self.remove_database(&db_name).await?;
+                }
+            }
+            CatalogChange::DatabaseRenamed { from, to } => {
+                let from = DatabaseName::new(from).context(InvalidDatabaseName)?;
+                let to = DatabaseName::new(to).context(InvalidDatabaseName)?;
+                if self.config.db(&from).is_some() {
+                    self.rename_database(&from, to).await?;
+                }
+            }
+            CatalogChange::Delete { name, record } => {
+                let db_name = DatabaseName::new(name).context(InvalidDatabaseName)?;
+                if let Some(db) = self.config.db(&db_name) {
+                    self.apply_delete(&db_name, &db, record).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // writes the catalog log to object storage
+    async fn persist_catalog_log(&self, log: &CatalogLog) -> Result<()> {
+        let location = self.catalog_log_path()?;
+        let data = Bytes::from(serde_json::to_vec(log).context(ErrorSerializing)?);
+        let len = data.len();
+        let stream_data = std::io::Result::Ok(data);
+        self.store
+            .put(
+                &location,
+                futures::stream::once(async move { stream_data }),
+                Some(len),
+            )
+            .await
+            .context(StoreError)
+    }
+
+    // location of the catalog log in object storage
+    fn catalog_log_path(&self) -> Result<object_store::path::Path> {
+        let mut path = self.root_path()?;
+        path.set_file_name(CATALOG_LOG_FILE_NAME);
+        Ok(path)
+    }
+
+    /// Returns the digests of the writes each partition of `db_name` holds,
+    /// which its replicas compare to repair each other. See the
+    /// [`anti_entropy`] module for details.
+    pub fn write_digests(&self, db_name: &str) -> Result<Vec<WriteDigest>> {
+        let db_name = DatabaseName::new(db_name).context(InvalidDatabaseName)?;
+        let db = self
+            .config
+            .db(&db_name)
+            .context(DatabaseNotFound { db_name: &*db_name })?;
+
+        db.write_digests()
+            .map_err(|e| Box::new(e) as DatabaseError)
+            .context(UnknownDatabaseError {})
+    }
+
+    /// Sends every peer with hints its hinted writes, oldest first. A peer
+    /// failing to store a write keeps it and the later ones for the next
+    /// round, without holding back the other peers. Returns the number of
+    /// writes delivered.
+    pub async fn deliver_hints(&self) -> usize {
+        let hints = match self.hinted_handoff.read().clone() {
+            Some(hints) => hints,
+            None => return 0,
+        };
+
+        let mut delivered = 0;
+        for peer in hints.peers() {
+            let remote = match self.connection_manager.remote_server(&peer).await {
+                Ok(remote) => remote,
+                Err(e) => {
+                    debug!(%peer, error = %e, "Peer still unavailable for hinted writes");
+                    continue;
+                }
+            };
+
+            let mut sent = 0;
+            while let Some(hint) = hints.next(&peer) {
+                if let Err(e) = remote.replicate(&hint.db_name, &hint.write).await {
+                    debug!(%peer, error = %e, "Peer still unavailable for hinted writes");
+                    break;
+                }
+                hints.delivered(&peer, &hint);
+                sent += 1;
+            }
+            if sent > 0 {
+                info!(%peer, writes = sent, "Delivered hinted writes");
+            }
+            delivered += sent;
+        }
+
+        delivered
+    }
+
+    /// Runs a round of anti-entropy repair: compares the writes of each
+    /// database with those of every peer writes are replicated to, and
+    /// sends a peer again the writes of the WAL buffer within the ranges
+    /// that differ. A peer failing to be repaired doesn't hold back the
+    /// others. Returns the number of writes sent.
+    pub async fn repair_round(&self) -> Result<usize> {
+        let replication = match self.peer_replication.read().clone() {
+            Some(replication) => replication,
+            None => return Ok(0),
+        };
+        self.require_id()?;
+
+        let mut sent = 0;
+        for db_name in self.config.db_names_sorted() {
+            let db = match self.config.db(&db_name) {
+                Some(db) => db,
+                None => continue,
+            };
+            let digests = db
+                .write_digests()
+                .map_err(|e| Box::new(e) as DatabaseError)
+                .context(UnknownDatabaseError {})?;
+
+            for peer in replication.peers() {
+                match self.repair_peer(peer, &db_name, &db, &digests).await {
+                    Ok(writes) => sent += writes,
+                    Err(e) => warn!(%peer, %db_name, error = %e, "Anti-entropy repair failed"),
+                }
+            }
+        }
+
+        Ok(sent)
+    }
+
+    /// Sends `peer` the writes of `db` within the ranges whose `digests`
+    /// differ from its own, returning the number of writes sent
+    async fn repair_peer(
+        &self,
+        peer: &str,
+        db_name: &DatabaseName<'_>,
+        db: &Db,
+        digests: &[WriteDigest],
+    ) -> Result<usize> {
+        let remote = self
+            .connection_manager
+            .remote_server(peer)
+            .await
+            .map_err(|e| Box::new(e) as DatabaseError)
+            .context(RepairFailed { server: peer })?;
+        let remote_digests = remote
+            .write_digests(db_name)
+            .await
+            .map_err(|e| Box::new(e) as DatabaseError)
+            .context(RepairFailed { server: peer })?;
+
+        let divergences = anti_entropy::divergences(digests, &remote_digests);
+        if divergences.is_empty() {
+            return Ok(0);
+        }
+        let writes: Vec<_> = match &db.wal_buffer {
+            Some(wal_buffer) => wal_buffer
+                .lock()
+                .writes()
+                .filter(|write| {
+                    let (writer_id, sequence) = write.writer_and_sequence();
+                    let partition_keys = anti_entropy::partition_keys(write);
+                    divergences.iter().any(|divergence| {
+                        divergence.contains(writer_id, sequence)
+                            && partition_keys.contains(&divergence.partition_key)
+                    })
+                })
+                .cloned()
+                .collect(),
+            None => vec![],
+        };
+
+        for write in &writes {
+            remote
+                .replicate(db_name, write)
+                .await
+                .map_err(|e| Box::new(e) as DatabaseError)
+                .context(RepairFailed { server: peer })?;
+        }
+        info!(
+            %peer,
+            %db_name,
+            ranges = divergences.len(),
+            writes = writes.len(),
+            "Repaired diverging writes"
+        );
+
+        Ok(writes.len())
+    }
+
+    /// Merges the member states `states` gossiped by another server into
+    /// the view of this server, returning the view
+    pub fn receive_gossip(&self, states: Vec<MemberState>) -> Result<Vec<MemberState>> {
+        let membership = self.membership().context(ClusterDisabled)?;
+        membership.merge(states);
+        Ok(membership.gossip())
+    }
+
+    /// Elects a single owner among the servers sharing the object store for
+    /// the compaction and retention of each partition, through `leases`.
+    /// See the [`leases`] module for details.
+    pub fn set_partition_leases(&self, leases: PartitionLeases) {
+        *self.partition_leases.write() = Some(Arc::new(leases));
+    }
+
+    /// Returns the partition leases of the server, if enabled
+    pub fn partition_leases(&self) -> Option<Arc<PartitionLeases>> {
+        self.partition_leases.read().clone()
+    }
+
+    /// Returns who owns the background work on the partition
+    /// `partition_key` of `db_name`, taking or renewing its lease for this
+    /// server if it is free, expired or already held by it. Every partition
+    /// is owned by this server without partition leases.
+    pub async fn partition_ownership(
+        &self,
+        db_name: &str,
+        partition_key: &str,
+    ) -> Result<Ownership> {
+        let leases = match self.partition_leases() {
+            Some(leases) => leases,
+            None => return Ok(Ownership::Owned),
+        };
+        let id = self.require_id()?;
+        let now = Utc::now();
+        if leases.holds(db_name, partition_key, now) {
+            return Ok(Ownership::Owned);
+        }
+
+        let (dir, location) = self.lease_path(db_name, partition_key);
+        if let Some(lease) = self.read_lease(&dir, &location).await? {
+            if lease.excludes(id, now) {
+                leases.record(db_name, partition_key, None);
+                return Ok(Ownership::HeldBy(lease.owner));
+            }
+        }
+
+        let lease = leases.lease(id, now);
+        let data = Bytes::from(serde_json::to_vec(&lease).context(ErrorSerializing)?);
+        let len = data.len();
+        let stream_data = std::io::Result::Ok(data);
+        self.store
+            .put(
+                &location,
+                futures::stream::once(async move { stream_data }),
+                Some(len),
+            )
+            .await
+            .context(StoreError)?;
+
+        // another server may have taken the lease at the same time
+        match self.read_lease(&dir, &location).await? {
+            Some(read) if read != lease => {
+                leases.record(db_name, partition_key, None);
+                Ok(Ownership::HeldBy(read.owner))
+            }
+            _ => {
+                debug!(%db_name, %partition_key, expires = %lease.expires, "took partition lease");
+                leases.record(db_name, partition_key, Some(lease.expires));
+                Ok(Ownership::Owned)
+            }
+        }
+    }
+
+    /// Returns the partitions out of `partition_keys` of `db_name` this
+    /// server owns
+    async fn owned_partitions(
+        &self,
+        db_name: &str,
+        partition_keys: Vec<String>,
+    ) -> Result<Vec<String>> {
+        let mut owned = vec![];
+        for partition_key in partition_keys {
+            match self.partition_ownership(db_name, &partition_key).await? {
+                Ownership::Owned => owned.push(partition_key),
+                Ownership::HeldBy(owner) => {
+                    debug!(%db_name, %partition_key, owner, "partition leased by another server")
+                }
+            }
+        }
+        Ok(owned)
+    }
+
+    /// Returns the directory of the leases of the partitions of `db_name`,
+    /// and the location of the lease of `partition_key`
+    fn lease_path(
+        &self,
+        db_name: &str,
+        partition_key: &str,
+    ) -> (object_store::path::Path, object_store::path::Path) {
+        let mut dir = self.store.new_path();
+        dir.push_dir(LEASES_DIR);
+        dir.push_dir(db_name);
+        let mut location = dir.clone();
+        location.set_file_name(format!("{}.json", partition_key));
+        (dir, location)
+    }
+
+    /// Reads the lease at `location` in `dir`, if there is one
+    async fn read_lease(
+        &self,
+        dir: &object_store::path::Path,
+        location: &object_store::path::Path,
+    ) -> Result<Option<Lease>> {
+        let list_result = self
+            .store
+            .list_with_delimiter(dir)
+            .await
+            .context(StoreError)?;
+        if !list_result
+            .objects
+            .iter()
+            .any(|object| &object.location == location)
+        {
+            return Ok(None);
+        }
+
+        let data = get_store_bytes(location, &self.store).await?;
+        Ok(Some(
+            serde_json::from_slice(&data).context(ErrorDeserializing)?,
+        ))
+    }
+
+    /// Produces the writes accepted from now on to `write_buffer` before
+    /// storing them. See the [`write_buffer`] module for details.
+    pub fn set_write_buffer(&self, write_buffer: Arc<dyn WriteBuffer>) {
+        *self.write_buffer.write() = Some(write_buffer);
+    }
+
+    /// Returns the write buffer of the server, if any
+    pub fn write_buffer(&self) -> Option<Arc<dyn WriteBuffer>> {
+        self.write_buffer.read().clone()
+    }
+
+    /// Replays the writes this server produced to its write buffer since
+    /// its checkpoint into the mutable buffer of their databases, which
+    /// must have been loaded. Each partition is read up to its end as of
+    /// the start of the replay. The deletes persisted before the restart are
+    /// applied in between, once the writes issued before them are replayed.
+    /// If replay fails, calling this again resumes it where it stopped.
+    ///
+    /// Returns the number of writes replayed
+    pub async fn replay_write_buffer(&self) -> Result<u64> {
+        let write_buffer = match self.write_buffer() {
+            Some(write_buffer) => write_buffer,
+            None => return Ok(0),
+        };
+        let id = self.require_id()?;
+        let checkpoint = self.read_write_buffer_checkpoint().await?;
+
+        for partition in 0..write_buffer.partitions() {
+            if self.replay.get(partition).is_none() {
+                let (oldest, end) = write_buffer
+                    .watermarks(partition)
+                    .await
+                    .context(WriteBufferFailed)?;
+                let start = checkpoint
+                    .offsets
+                    .get(&partition)
+                    .map_or(oldest, |offset| (*offset).max(oldest));
+                self.replay.start(partition, start, end);
+            }
+        }
+
+        // the deletes restored at startup apply once the writes issued
+        // before them are replayed, so that they hide the same rows as
+        // before the restart
+        let mut replayed = 0;
+        for (db_name, db, record) in self.pending_deletes() {
+            for partition in 0..write_buffer.partitions() {
+                let until = record
+                    .write_buffer_offsets
+                    .get(&partition)
+                    .copied()
+                    .unwrap_or(i64::MIN);
+                replayed += self
+                    .replay_partition(write_buffer.as_ref(), id, partition, until)
+                    .await?;
+            }
+            self.apply_delete(&db_name, &db, record).await?;
+        }
+        for partition in 0..write_buffer.partitions() {
+            replayed += self
+                .replay_partition(write_buffer.as_ref(), id, partition, i64::MAX)
+                .await?;
+        }
+
+        info!(replayed, "replayed the write buffer");
+        Ok(replayed)
+    }
+
+    // replays the writes of this server, with id `id`, in `partition` of
+    // the write buffer up to the offset `until`, or the end of the replay
+    // if it is sooner. Returns the number of writes replayed.
+    async fn replay_partition(
+        &self,
+        write_buffer: &dyn WriteBuffer,
+        id: u32,
+        partition: u32,
+        until: i64,
+    ) -> Result<u64> {
+        let progress = self.replay.get(partition).expect("replay started");
+        let end = progress.end.min(until);
+
+        let mut replayed = 0;
+        let mut next = progress.next;
+        while next < end {
+            let writes = write_buffer
+                .fetch(partition, next, REPLAY_BATCH_SIZE)
+                .await
+                .context(WriteBufferFailed)?;
+            if writes.is_empty() {
+                // the remaining messages can't be read, e.g. if they
+                // have been deleted meanwhile
+                break;
+            }
+
+            let mut batch_replayed = 0;
+            for buffered in writes {
+                if buffered.offset >= end {
+                    break;
+                }
+                next = buffered.offset + 1;
+                if buffered.write.writer_and_sequence().0 != id {
+                    continue;
+                }
+                let db = match DatabaseName::new(buffered.db_name.as_str())
+                    .ok()
+                    .and_then(|db_name| self.config.db(&db_name))
+                {
+                    Some(db) => db,
+                    None => continue,
+                };
+                let mutable_buffer = match db.mutable_buffer.as_ref() {
+                    Some(mutable_buffer) => mutable_buffer,
+                    None => continue,
+                };
+                mutable_buffer
+                    .store_replicated_write(&buffered.write)
+                    .await
+                    .map_err(|e| Box::new(e) as DatabaseError)
+                    .context(UnknownDatabaseError {})?;
+                let (writer_id, sequence) = buffered.write.writer_and_sequence();
+                db.sequence_after(sequence);
+                let partition_keys = anti_entropy::partition_keys(&buffered.write);
+                db.write_log.record(
+                    writer_id,
+                    sequence,
+                    partition_keys.iter().map(String::as_str),
+                );
+                batch_replayed += 1;
+            }
+            self.replay.advance(partition, next, batch_replayed);
+            replayed += batch_replayed;
+        }
+        self.replay.advance(partition, end.max(next), 0);
+        Ok(replayed)
+    }
+
+    // returns the deletes restored at startup that are not yet applied,
+    // with their databases, in the order they were issued
+    fn pending_deletes(&self) -> Vec<(DatabaseName<'static>, Arc<Db>, DeleteRecord)> {
+        let mut pending: Vec<_> = self
+            .config
+            .db_names_sorted()
+            .into_iter()
+            .filter_map(|db_name| {
+                let db = self.config.db(&db_name)?;
+                Some((db_name, db))
+            })
+            .flat_map(|(db_name, db)| {
+                db.pending_deletes()
+                    .into_iter()
+                    .map(move |record| (db_name.clone(), Arc::clone(&db), record))
+            })
+            .collect();
+        pending.sort_by_key(|(_, _, record)| record.issued_at);
+        pending
+    }
+
+    /// Returns the progress of the replay of each partition of the write
+    /// buffer, once started
+    pub fn write_buffer_replay(&self) -> Vec<PartitionReplay> {
+        self.replay.partitions()
+    }
+
+    /// Moves the checkpoint of the write buffer forward to the first writes
+    /// still within the retention period of every database, returning it.
+    /// The checkpoint doesn't move while a database keeps its data forever.
+    pub async fn checkpoint_write_buffer(&self) -> Result<Option<Checkpoint>> {
+        let write_buffer = match self.write_buffer() {
+            Some(write_buffer) => write_buffer,
+            None => return Ok(None),
+        };
+
+        let mut longest_retention = None;
+        for db_name in self.config.db_names_sorted() {
+            let seconds = match self
+                .config
+                .db(&db_name)
+                .and_then(|db| db.rules().retention_period_seconds)
+            {
+                Some(seconds) => seconds,
+                None => return Ok(None),
+            };
+            longest_retention = longest_retention.max(Some(seconds));
+        }
+        let horizon = match longest_retention
+            .and_then(|seconds| chrono::Duration::from_std(Duration::from_secs(seconds)).ok())
+            .and_then(|retention| Utc::now().checked_sub_signed(retention))
+        {
+            Some(horizon) => horizon,
+            None => return Ok(None),
+        };
+
+        let mut checkpoint = self.read_write_buffer_checkpoint().await?;
+        for partition in 0..write_buffer.partitions() {
+            let offset = match write_buffer
+                .offset_for_time(partition, horizon)
+                .await
+                .context(WriteBufferFailed)?
+            {
+                Some(offset) => offset,
+                None => {
+                    write_buffer
+                        .watermarks(partition)
+                        .await
+                        .context(WriteBufferFailed)?
+                        .1
+                }
+            };
+            checkpoint.advance(partition, offset);
+        }
+
+        let data = Bytes::from(serde_json::to_vec(&checkpoint).context(ErrorSerializing)?);
+        let len = data.len();
+        let stream_data = std::io::Result::Ok(data);
+        self.store
+            .put(
+                &self.write_buffer_checkpoint_path()?,
+                futures::stream::once(async move { stream_data }),
+                Some(len),
+            )
+            .await
+            .context(StoreError)?;
+
+        Ok(Some(checkpoint))
+    }
+
+    /// Reads the checkpoint of the write buffer, empty if there is none
+    async fn read_write_buffer_checkpoint(&self) -> Result<Checkpoint> {
+        let location = self.write_buffer_checkpoint_path()?;
+        let list_result = self
+            .store
+            .list_with_delimiter(&self.root_path()?)
+            .await
+            .context(StoreError)?;
+        if !list_result
+            .objects
+            .iter()
+            .any(|object| object.location == location)
+        {
+            return Ok(Checkpoint::default());
+        }
+
+        let data = get_store_bytes(&location, &self.store).await?;
+        serde_json::from_slice(&data).context(ErrorDeserializing)
+    }
+
+    // location of the checkpoint of the write buffer in object storage
+    fn write_buffer_checkpoint_path(&self) -> Result<object_store::path::Path> {
+        let mut path = self.root_path()?;
+        path.set_file_name(CHECKPOINT_FILE_NAME);
+        Ok(path)
+    }
+
+    /// Makes the server a read replica following its primary through
+    /// `sync_from_primary`, rejecting writes from then on
+    pub fn set_read_replica(&self, replica: ReadReplica) {
+        *self.read_replica.write() = Some(Arc::new(replica));
+    }
+
+    /// Returns the primary the server follows, if it is a read replica
+    pub fn read_replica(&self) -> Option<Arc<ReadReplica>> {
+        self.read_replica.read().clone()
+    }
+
+    /// Brings a read replica up to date with its primary: loads the rules of
+    /// the primary's databases and stores the writes of the WAL segments the
+    /// primary persisted since the last call. Returns the number of writes
+    /// stored, none if the server isn't a read replica.
+    pub async fn sync_from_primary(&self) -> Result<usize> {
+        let replica = match self.read_replica() {
+            Some(replica) => replica,
+            None => return Ok(0),
+        };
+        self.require_id()?;
+
+        let mut primary_root = self.store.new_path();
+        primary_root.push_dir(replica.primary_id().to_string());
+        let list_result = self
+            .store
+            .list_with_delimiter(&primary_root)
+            .await
+            .context(StoreError)?;
+
+        // a database failing to sync doesn't hold back the others
+        let mut stored = 0;
+        for mut path in list_result.common_prefixes {
+            path.set_file_name(DB_RULES_FILE_NAME);
+            match self.sync_database_from_primary(&replica, &path).await {
+                Ok(writes) => stored += writes,
+                Err(e) => error!(
+                    "error following database {} of the primary: {}",
+                    path.display(),
+                    e
+                ),
+            }
+        }
+
+        Ok(stored)
+    }
+
+    /// Loads the rules of a database of the primary from `rules_location`
+    /// and stores the writes of its segments not stored yet
+    async fn sync_database_from_primary(
+        &self,
+        replica: &ReadReplica,
+        rules_location: &object_store::path::Path,
+    ) -> Result<usize> {
+        let data = get_store_bytes(rules_location, &self.store).await?;
+        let rules: DatabaseRules = serde_json::from_slice(&data).context(ErrorDeserializing)?;
+        let db_name = DatabaseName::new(rules.name.clone()).context(InvalidDatabaseName)?;
+
+        // the rules are kept in memory only, like the writes
+        let db = match self.config.db(&db_name) {
+            Some(db) => {
+                db.set_rules(rules);
+                db
+            }
+            None => {
+                let handle = self.config.create_db(db_name.clone(), rules)?;
+                let db = Arc::clone(&handle.db);
+                handle.commit();
+                info!(%db_name, "Following database of the primary");
+                db
+            }
+        };
+
+        let wal_location =
+            buffer::object_store_path_for_wal(replica.primary_id(), &db_name, &self.store);
+        let locations: HashMap<_, _> = self
+            .store
+            .list(Some(&wal_location))
+            .await
+            .context(StoreError)?
+            .try_concat()
+            .await
+            .context(StoreError)?
+            .into_iter()
+            .filter_map(|location| Some((buffer::segment_id_from_path(&location)?, location)))
+            .collect();
+
+        let ids = locations.keys().copied().collect();
+        let mut stored = 0;
+        for id in replica.segments_to_apply(db_name.as_str(), ids) {
+            let location = &locations[&id];
+            let data = get_store_bytes(location, &self.store).await?;
+            let data =
+                encryption::decrypt_if_encrypted(self.encryption().as_deref(), data.freeze())
+                    .await
+                    .context(DecryptingSegment {
+                        location: location.display(),
+                    })?;
+            let segment = buffer::Segment::from_file_bytes(&data).context(ReadingSegment {
+                location: location.display(),
+            })?;
+
+            if let Some(buf) = &db.mutable_buffer {
+                for write in &segment.writes {
+                    buf.store_replicated_write(write)
+                        .await
+                        .map_err(|e| Box::new(e) as DatabaseError)
+                        .context(UnknownDatabaseError {})?;
+                }
+            }
+            stored += segment.writes.len();
+            replica.set_applied(db_name.as_str(), id);
+        }
+
+        Ok(stored)
+    }
+
+    /// Sets the number of compactions and snapshots run at a time. See the
+    /// [`scheduler`] module for details.
+    pub fn set_max_background_jobs(&self, max_running: usize) {
+        self.scheduler.set_max_running(max_running);
+    }
+
+    /// Limits the IO of compactions and snapshots to `limits`. See the
+    /// [`throttle`] module for details.
+    pub fn set_background_io_limits(&self, limits: IoLimits) {
+        self.scheduler.set_io_limits(limits);
+    }
+
+    /// Returns the scheduler of the server's background jobs, e.g. to report
+    /// how many are waiting for each tenant
+    pub fn job_scheduler(&self) -> &JobScheduler {
+        &self.scheduler
+    }
+
+    /// Returns the status of the compaction job with id `id`, if it is
+    /// running or finished recently
+    pub fn compaction(&self, id: u64) -> Option<CompactionStatus> {
+        self.compactions.status(id)
+    }
+
+    /// Returns the status of the running and recently finished compaction
+    /// jobs, oldest first
+    pub fn compactions(&self) -> Vec<CompactionStatus> {
+        self.compactions.statuses()
+    }
+
+    /// Drops the data older than the retention period of every database
+    /// that has one. Only whole closed chunks are dropped, so data may be
+    /// kept for a while after it expires. With partition leases, only the
+    /// partitions this server owns are considered.
+    ///
+    /// Returns the number of chunks dropped
+    pub async fn drop_expired_data(&self) -> Result<usize> {
+        let now = Utc::now().timestamp_nanos();
+        let mut dropped = 0;
+
+        for db_name in self.config.db_names_sorted() {
+            let db = match self.config.db(&db_name) {
+                Some(db) => db,
+                None => continue,
+            };
+            let retention_period = match db.rules().retention_period_seconds {
+                Some(seconds) => Duration::from_secs(seconds),
+                None => continue,
+            };
+
+            let retention_period = i64::try_from(retention_period.as_nanos()).unwrap_or(i64::MAX);
+            let cutoff = now.saturating_sub(retention_period);
+            let mut chunks = 0;
+            let partition_keys = db
+                .buffered_partition_keys()
+                .map_err(|e| Box::new(e) as DatabaseError)
+                .context(UnknownDatabaseError {})?;
+            for partition_key in self
+                .owned_partitions(db_name.as_str(), partition_keys)
+                .await?
+            {
+                chunks += db
+                    .drop_expired_partition_chunks(&partition_key, cutoff)
+                    .await
+                    .map_err(|e| Box::new(e) as DatabaseError)
+                    .context(UnknownDatabaseError {})?;
+            }
+            if chunks > 0 {
+                info!(%db_name, chunks, "dropped chunks past the retention period");
+            }
+            dropped += chunks;
+        }
+
+        Ok(dropped)
+    }
+}
+
+fn partition_keys(db: &Db) -> Result<Vec<String>> {
+    db.partition_keys()
+        .map_err(|e| Box::new(e) as DatabaseError)
+        .context(UnknownDatabaseError {})
+}
+
+/// Returns the names of the databases of the buckets of the org named `org`
+fn bucket_databases(config: &Config, org: &str) -> Vec<DatabaseName<'static>> {
+    config
+        .db_names_sorted()
+        .into_iter()
+        .filter(|db_name| {
+            matches!(database_to_org_and_bucket(db_name.as_str()), Some((db_org, _)) if db_org == org)
+        })
+        .collect()
+}
+
+/// Returns an error if `db` has no partition `partition_key`
+fn require_partition(db: &Db, db_name: &DatabaseName<'_>, partition_key: &str) -> Result<()> {
+    if partition_keys(db)?.iter().any(|key| key == partition_key) {
+        Ok(())
+    } else {
+        PartitionNotFound {
+            db_name: db_name.to_string(),
+            partition_key,
+        }
+        .fail()
+    }
+}
+
+#[async_trait]
+impl<M> DatabaseStore for Server<M>
+where
+    M: ConnectionManager + std::fmt::Debug + Send + Sync,
+{
+    type Database = Db;
+    type Error = Error;
+
+    async fn db_names_sorted(&self) -> Vec<String> {
+        self.config
+            .db_names_sorted()
+            .iter()
+            .map(|i| i.clone().into())
+            .collect()
+    }
+
+    async fn db(&self, name: &str) -> Option<Arc<Self::Database>> {
+        if let Ok(name) = DatabaseName::new(name) {
+            return self.db(&name).await;
+        }
+
+        None
+    }
+
+    // TODO: refactor usages of this to use the Server rather than this trait and to
+    //       explicitly create a database.
+    async fn db_or_create(&self, name: &str) -> Result<Arc<Self::Database>, Self::Error> {
+        let db_name = DatabaseName::new(name.to_string()).context(InvalidDatabaseName)?;
+
+        let db = match self.db(&db_name).await {
+            Some(db) => db,
+            None => {
+                self.create_database(name, DatabaseRules::new()).await?;
+                self.db(&db_name).await.expect("db not inserted")
+            }
+        };
+
+        Ok(db)
+    }
+
+    fn executor(&self) -> Arc<Executor> {
+        Arc::clone(&self.executor)
+    }
+}
+
+/// The `Server` will ask the `ConnectionManager` for connections to a specific
+/// remote server. These connections can be used to communicate with other
+/// servers. This is implemented as a trait for dependency injection in testing.
+#[async_trait]
+pub trait ConnectionManager {
+    type Error: std::error::Error + Send + Sync + 'static;
+
+    type RemoteServer: RemoteServer + Send + Sync + 'static;
+
+    async fn remote_server(&self, connect: &str) -> Result<Arc<Self::RemoteServer>, Self::Error>;
+}
+
+/// The `RemoteServer` represents the API for replicating, subscribing, and
+/// querying other servers.
+#[async_trait]
+pub trait RemoteServer {
+    type Error: std::error::Error + Send + Sync + 'static;
+
+    /// Sends a replicated write to a remote server. This is step #2 from the
+    /// diagram.
+    async fn replicate(
+        &self,
+        db: &str,
+        replicated_write: &ReplicatedWrite,
+    ) -> Result<(), Self::Error>;
+
+    /// Writes line protocol to a remote server, which handles it like any
+    /// write it receives. Used by servers routing writes.
+    async fn write_lines(&self, db: &str, lp: &str) -> Result<(), Self::Error>;
+
+    /// Exchanges views of the cluster's members with a remote server,
+    /// sending `states` and returning those it knows
+    async fn gossip(&self, states: Vec<MemberState>) -> Result<Vec<MemberState>, Self::Error>;
+
+    /// Returns the digests of the writes each partition of the database
+    /// `db` holds on a remote server
+    async fn write_digests(&self, db: &str) -> Result<Vec<WriteDigest>, Self::Error>;
+
+    /// Sends catalog entries to a remote server, which applies those more
+    /// recent than its own and returns all of its entries
+    async fn sync_catalog(
+        &self,
+        entries: Vec<CatalogEntry>,
+    ) -> Result<Vec<CatalogEntry>, Self::Error>;
+
+    /// Makes a remote server replay the writes to the series of `range` it
+    /// holds into the server `target`, returning the number of lines
+    /// replayed
+    async fn hand_off_shard(&self, range: ShardRange, target: &str) -> Result<u64, Self::Error>;
+}
+
+/// The connection manager maps a host identifier, the address of the gRPC
+/// API of the server, to a remote server.
+#[derive(Debug, Default)]
+pub struct ConnectionManagerImpl {
+    /// The `authorization` header sent to remote servers, if they require
+    /// an API token
+    authorization: Option<AuthorizationValue>,
+    /// How long connecting to a remote server may take
+    connect_timeout: Option<Duration>,
+    /// How long each request to a remote server may take
+    request_timeout: Option<Duration>,
+    /// The TLS settings of the connections to the remote servers given as
+    /// `https://` URLs
+    tls: Option<ClientTlsConfig>,
+    remotes: parking_lot::Mutex<HashMap<String, Arc<RemoteServerImpl>>>,
+}
+
+type AuthorizationValue = tonic::metadata::MetadataValue<tonic::metadata::Ascii>;
+
+impl ConnectionManagerImpl {
+    /// Creates a connection manager sending the API token `token` to the
+    /// remote servers
+    pub fn new(token: Option<&str>) -> Result<Self> {
+        let authorization = match token {
+            Some(token) => {
+                Some(
+                    format!("Token {}", token)
+                        .parse()
+                        .map_err(|_| Error::InvalidToken {
+                            reason: "the token sent to remote servers must be printable ASCII"
+                                .to_string(),
+                        })?,
+                )
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            authorization,
+            ..Default::default()
+        })
+    }
+
+    /// Bounds the time connecting to a remote server, and each request to
+    /// it, may take
+    pub fn with_timeouts(self, connect_timeout: Duration, request_timeout: Duration) -> Self {
+        Self {
+            connect_timeout: Some(connect_timeout),
+            request_timeout: Some(request_timeout),
+            ..self
+        }
+    }
+
+    /// Connects to the remote servers given as `https://` URLs with `tls`
+    pub fn with_tls(self, tls: ClientTlsConfig) -> Self {
+        Self {
+            tls: Some(tls),
+            ..self
+        }
+    }
+}
+
+#[async_trait]
+impl ConnectionManager for ConnectionManagerImpl {
+    type Error = Error;
+    type RemoteServer = RemoteServerImpl;
+
+    async fn remote_server(&self, connect: &str) -> Result<Arc<Self::RemoteServer>, Self::Error> {
+        let mut remotes = self.remotes.lock();
+        if let Some(remote) = remotes.get(connect) {
+            return Ok(Arc::clone(remote));
+        }
+
+        // servers are given as host:port or as URLs
+        let url = if connect.contains("://") {
+            connect.to_string()
+        } else {
+            format!("http://{}", connect)
+        };
+        let mut endpoint =
+            Endpoint::from_shared(url.clone()).context(InvalidRemoteAddress { server: connect })?;
+        if let Some(timeout) = self.request_timeout {
+            endpoint = endpoint.timeout(timeout);
+        }
+        if let (Some(tls), true) = (&self.tls, url.starts_with("https://")) {
+            endpoint = endpoint
+                .tls_config(tls.clone())
+                .context(InvalidRemoteAddress { server: connect })?;
+        }
+
+        let remote = Arc::new(RemoteServerImpl {
+            server: connect.to_string(),
+            authorization: self.authorization.clone(),
+            endpoint,
+            connect_timeout: self.connect_timeout,
+            channel: Default::default(),
+        });
+        remotes.insert(connect.to_string(), Arc::clone(&remote));
+        Ok(remote)
+    }
+}
+
+/// An implementation for communicating with other IOx servers over their
+/// gRPC write and cluster services. The channel connects on first use, and
+/// reconnects after connection failures.
+#[derive(Debug)]
+pub struct RemoteServerImpl {
+    server: String,
+    authorization: Option<AuthorizationValue>,
+    endpoint: Endpoint,
+    connect_timeout: Option<Duration>,
+    channel: tokio::sync::OnceCell<Channel>,
+}
+
+impl RemoteServerImpl {
+    /// Returns the channel to the server, connecting if it isn't yet
+    async fn channel(&self) -> Result<Channel> {
+        let channel = self
+            .channel
+            .get_or_try_init(|| async {
+                let mut http = hyper::client::HttpConnector::new();
+                http.enforce_http(false);
+                http.set_nodelay(true);
+                // applies to the reconnections of the channel too
+                http.set_connect_timeout(self.connect_timeout);
+                self.endpoint
+                    .connect_with_connector(http)
+                    .await
+                    .context(ConnectingToRemote {
+                        server: &self.server,
+                    })
+            })
+            .await?;
+        Ok(channel.clone())
+    }
+
+    async fn client(&self) -> Result<WriteServiceClient<Channel>> {
+        Ok(WriteServiceClient::new(self.channel().await?))
+    }
+
+    async fn cluster_client(&self) -> Result<ClusterServiceClient<Channel>> {
+        Ok(ClusterServiceClient::new(self.channel().await?))
+    }
+
+    /// Wraps `message` in a request sending the API token, if any
+    fn request<T>(&self, message: T) -> tonic::Request<T> {
+        let mut request = tonic::Request::new(message);
+        if let Some(authorization) = &self.authorization {
+            request
+                .metadata_mut()
+                .insert("authorization", authorization.clone());
+        }
+        request
+    }
+}
+
+#[async_trait]
+impl RemoteServer for RemoteServerImpl {
+    type Error = Error;
+
+    async fn replicate(
+        &self,
+        db: &str,
+        replicated_write: &ReplicatedWrite,
+    ) -> Result<(), Self::Error> {
+        let request = self.request(ReplicateWriteRequest {
+            db_name: db.to_string(),
+            replicated_write: replicated_write.bytes().clone(),
+        });
+
+        self.client()
+            .await?
+            .replicate_write(request)
+            .await
+            .context(RemoteWriteFailed {
+                server: &self.server,
+            })?;
+
+        Ok(())
+    }
+
+    async fn write_lines(&self, db: &str, lp: &str) -> Result<(), Self::Error> {
+        let request = self.request(WriteRequest {
+            db_name: db.to_string(),
+            lp_data: lp.to_string(),
+        });
+
+        self.client()
+            .await?
+            .write(request)
+            .await
+            .context(RemoteWriteFailed {
+                server: &self.server,
+            })?;
+
+        Ok(())
+    }
+
+    async fn gossip(&self, states: Vec<MemberState>) -> Result<Vec<MemberState>, Self::Error> {
+        let request = self.request(GossipRequest {
+            members: states.into_iter().map(Into::into).collect(),
+        });
+
+        let response =
+            self.cluster_client()
+                .await?
+                .gossip(request)
+                .await
+                .context(GossipFailed {
+                    server: &self.server,
+                })?;
+
+        Ok(response
+            .into_inner()
+            .members
+            .into_iter()
+            .filter_map(MemberState::from_proto)
+            .collect())
+    }
+
+    async fn write_digests(&self, db: &str) -> Result<Vec<WriteDigest>, Self::Error> {
+        let request = self.request(GetWriteDigestsRequest {
+            db_name: db.to_string(),
+        });
+
+        let response = self
+            .cluster_client()
+            .await?
+            .get_write_digests(request)
+            .await
+            .context(RemoteDigestsFailed {
+                server: &self.server,
+            })?;
+
+        Ok(response
+            .into_inner()
+            .digests
+            .into_iter()
+            .map(Into::into)
+            .collect())
+    }
+
+    async fn sync_catalog(
+        &self,
+        entries: Vec<CatalogEntry>,
+    ) -> Result<Vec<CatalogEntry>, Self::Error> {
+        let request = self.request(SyncCatalogRequest {
+            entries: entries.into_iter().map(Into::into).collect(),
+        });
+
+        let response = self
+            .cluster_client()
+            .await?
+            .sync_catalog(request)
+            .await
+            .context(RemoteCatalogFailed {
+                server: &self.server,
+            })?;
+
+        Ok(response
+            .into_inner()
+            .entries
+            .into_iter()
+            .filter_map(CatalogEntry::from_proto)
+            .collect())
+    }
+
+    async fn hand_off_shard(&self, range: ShardRange, target: &str) -> Result<u64, Self::Error> {
+        let request = self.request(HandOffShardRequest {
+            start: range.start,
+            end: range.end,
+            target: target.to_string(),
+        });
+
+        let response = self
+            .cluster_client()
+            .await?
+            .hand_off_shard(request)
+            .await
+            .context(RemoteHandOffFailed {
+                server: &self.server,
+            })?;
+
+        Ok(response.into_inner().lines)
+    }
+}
+
+// reads the deletes persisted at `location` into `db`, to be applied as
+// writes are replayed
+async fn restore_deletes(store: &ObjectStore, location: &object_store::path::Path, db: &Db) {
+    let mut res = get_store_bytes(location, store).await;
+    while let Err(e) = &res {
+        error!(
+            "error getting deletes {:?} from object store: {}",
+            location, e
+        );
+        tokio::time::sleep(tokio::time::Duration::from_secs(STORE_ERROR_PAUSE_SECONDS)).await;
+        res = get_store_bytes(location, store).await;
+    }
+
+    match serde_json::from_slice(&res.unwrap()) {
+        Ok(records) => db.restore_deletes(records),
+        Err(e) => error!("error parsing deletes {:?} from store: {}", location, e),
+    }
+}
+
+// get bytes from the location in object store
+async fn get_store_bytes(
+    location: &object_store::path::Path,
+    store: &ObjectStore,
+) -> Result<bytes::BytesMut> {
+    let b = store
+        .get(location)
+        .await
+        .context(StoreError)?
+        .map_ok(|b| bytes::BytesMut::from(&b[..]))
+        .try_concat()
+        .await
+        .context(StoreError)?;
+
+    Ok(b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
     use crate::buffer::Segment;
     use arrow_deps::{assert_table_eq, datafusion::physical_plan::collect};
     use async_trait::async_trait;
@@ -575,203 +3810,1965 @@ mod tests {
     use object_store::{memory::InMemory, path::ObjectStorePath};
     use parking_lot::Mutex;
     use query::frontend::sql::SQLQueryPlanner;
-    use snafu::Snafu;
-    use std::collections::BTreeMap;
+    use snafu::{ensure, Snafu};
+    use std::{collections::BTreeMap, sync::atomic::AtomicBool};
+
+    type TestError = Box<dyn std::error::Error + Send + Sync + 'static>;
+    type Result<T = (), E = TestError> = std::result::Result<T, E>;
+
+    #[tokio::test]
+    async fn server_api_calls_return_error_with_no_id_set() -> Result {
+        let manager = TestConnectionManager::new();
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let mut server = Server::new(manager, store);
+
+        let rules = DatabaseRules::new();
+        let resp = server.create_database("foo", rules).await.unwrap_err();
+        assert!(matches!(resp, Error::IdNotSet));
+
+        let lines = parsed_lines("cpu foo=1 10");
+        let resp = server.write_lines("foo", &lines).await.unwrap_err();
+        assert!(matches!(resp, Error::IdNotSet));
+
+        let resp = server
+            .create_host_group("group1".to_string(), vec!["serverA".to_string()])
+            .await
+            .unwrap_err();
+        assert!(matches!(resp, Error::IdNotSet));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn create_database_persists_rules() {
+        let manager = TestConnectionManager::new();
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let server = Server::new(manager, Arc::clone(&store));
+        server.set_id(1);
+
+        let name = "bananas";
+
+        let rules = DatabaseRules {
+            partition_template: PartitionTemplate {
+                parts: vec![TemplatePart::TimeFormat("YYYY-MM".to_string())],
+            },
+            name: name.to_string(),
+            ..Default::default()
+        };
+
+        // Create a database
+        server
+            .create_database(name, rules.clone())
+            .await
+            .expect("failed to create database");
+
+        let mut rules_path = server.store.new_path();
+        rules_path.push_all_dirs(&["1", name]);
+        rules_path.set_file_name("rules.json");
+
+        let read_data = server
+            .store
+            .get(&rules_path)
+            .await
+            .unwrap()
+            .map_ok(|b| bytes::BytesMut::from(&b[..]))
+            .try_concat()
+            .await
+            .unwrap();
+
+        let read_data = std::str::from_utf8(&*read_data).unwrap();
+        let read_rules = serde_json::from_str::<DatabaseRules>(read_data).unwrap();
+
+        assert_eq!(rules, read_rules);
+
+        let db2 = "db_awesome";
+        server
+            .create_database(db2, DatabaseRules::new())
+            .await
+            .expect("failed to create 2nd db");
+
+        store.list_with_delimiter(&store.new_path()).await.unwrap();
+
+        let manager = TestConnectionManager::new();
+        let server2 = Server::new(manager, store);
+        server2.set_id(1);
+        server2.load_database_configs().await.unwrap();
+
+        let _ = server2.db(&DatabaseName::new(db2).unwrap()).await.unwrap();
+        let _ = server2.db(&DatabaseName::new(name).unwrap()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn load_database_configs_skips_prefixes_without_rules() -> Result {
+        let manager = TestConnectionManager::new();
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let server = Server::new(manager, Arc::clone(&store));
+        server.set_id(1);
+        server.create_database("foo", DatabaseRules::new()).await?;
+
+        // data left behind by a database whose rules are gone
+        let mut location = store.new_path();
+        location.push_all_dirs(&["1", "orphan", "data"]);
+        location.set_file_name("segment");
+        let data = Bytes::from("data");
+        let len = data.len();
+        store
+            .put(
+                &location,
+                futures::stream::once(async move { std::io::Result::Ok(data) }),
+                Some(len),
+            )
+            .await
+            .unwrap();
+
+        let manager = TestConnectionManager::new();
+        let server2 = Server::new(manager, store);
+        server2.set_id(1);
+        tokio::time::timeout(
+            tokio::time::Duration::from_secs(5),
+            server2.load_database_configs(),
+        )
+        .await
+        .expect("loading finished")?;
+
+        assert_eq!(server2.db_names_sorted().await, vec!["foo"]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn update_db_rules_persists_rules() -> Result {
+        let manager = TestConnectionManager::new();
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let server = Server::new(manager, Arc::clone(&store));
+        server.set_id(1);
+
+        let name = DatabaseName::new("bananas").unwrap();
+        server
+            .create_database("bananas", DatabaseRules::new())
+            .await?;
+
+        let rules = server
+            .update_db_rules(&name, |rules| rules.retention_period_seconds = Some(3600))
+            .await?;
+        assert_eq!(rules.retention_period_seconds, Some(3600));
+        assert_eq!(server.db_rules(&name).await.unwrap(), rules);
+
+        // the updated rules are loaded by a new server
+        let server2 = Server::new(TestConnectionManager::new(), store);
+        server2.set_id(1);
+        server2.load_database_configs().await?;
+        assert_eq!(server2.db_rules(&name).await.unwrap(), rules);
+
+        let missing = DatabaseName::new("apples").unwrap();
+        let err = server.update_db_rules(&missing, |_| {}).await.unwrap_err();
+        assert!(matches!(err, Error::DatabaseNotFound { .. }));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn delete_database() -> Result {
+        let manager = TestConnectionManager::new();
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let server = Server::new(manager, Arc::clone(&store));
+        server.set_id(1);
+
+        let name = DatabaseName::new("bananas").unwrap();
+        server
+            .create_database("bananas", DatabaseRules::new())
+            .await?;
+        server
+            .create_database("apples", DatabaseRules::new())
+            .await?;
+
+        server.delete_database(&name).await?;
+        assert!(server.db(&name).await.is_none());
+        assert_eq!(server.db_names_sorted().await, vec!["apples"]);
+
+        // the database is not loaded again
+        let server2 = Server::new(TestConnectionManager::new(), store);
+        server2.set_id(1);
+        server2.load_database_configs().await?;
+        assert_eq!(server2.db_names_sorted().await, vec!["apples"]);
+
+        let err = server.delete_database(&name).await.unwrap_err();
+        assert!(matches!(err, Error::DatabaseNotFound { .. }));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn delete_database_keeps_databases_sharing_its_prefix() -> Result {
+        let manager = TestConnectionManager::new();
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let server = Server::new(manager, Arc::clone(&store));
+        server.set_id(1);
+
+        server.create_database("a_b", DatabaseRules::new()).await?;
+        server.create_database("a_b2", DatabaseRules::new()).await?;
+
+        server
+            .delete_database(&DatabaseName::new("a_b").unwrap())
+            .await?;
+
+        let mut prefix = store.new_path();
+        prefix.push_all_dirs(&["1", "a_b2"]);
+        let remaining = store.list(Some(&prefix)).await?.try_concat().await?;
+        assert_eq!(remaining.len(), 1);
+
+        let server2 = Server::new(TestConnectionManager::new(), store);
+        server2.set_id(1);
+        server2.load_database_configs().await?;
+        assert_eq!(server2.db_names_sorted().await, vec!["a_b2"]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn concurrent_rules_updates_are_not_lost() -> Result {
+        let manager = TestConnectionManager::new();
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let server = Server::new(manager, Arc::clone(&store));
+        server.set_id(1);
+        server.create_database("foo", DatabaseRules::new()).await?;
+
+        let name = DatabaseName::new("foo").unwrap();
+        let (retention, queue_size) = futures::join!(
+            server.update_db_rules(&name, |rules| rules.retention_period_seconds = Some(3600)),
+            server.update_db_rules(&name, |rules| rules.replication_queue_max_size = 7),
+        );
+        retention?;
+        queue_size?;
+
+        let server2 = Server::new(TestConnectionManager::new(), store);
+        server2.set_id(1);
+        server2.load_database_configs().await?;
+        let rules = server2.db(&name).await.unwrap().rules();
+        assert_eq!(rules.retention_period_seconds, Some(3600));
+        assert_eq!(rules.replication_queue_max_size, 7);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn org_catalog() -> Result {
+        let manager = TestConnectionManager::new();
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let server = Server::new(manager, Arc::clone(&store));
+        server.set_id(1);
+
+        let company = server.create_org("company").await?;
+        let other = server.create_org("other").await?;
+        let err = server.create_org("company").await.unwrap_err();
+        assert!(matches!(err, Error::OrgAlreadyExists { .. }));
+
+        // buckets are created in an existing org
+        let rules = DatabaseRules {
+            retention_period_seconds: Some(3600),
+            ..DatabaseRules::new()
+        };
+        let err = server
+            .create_bucket("missing", "missing_sensors", rules.clone())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::OrgNotFound { .. }));
+        server
+            .create_bucket("company", "company_sensors", rules)
+            .await?;
+        let lines: Vec<_> = parse_lines("cpu bar=1 10").map(|l| l.unwrap()).collect();
+        server.write_lines("company_sensors", &lines).await?;
+
+        // orgs with buckets can not be deleted
+        let err = server.delete_org(&company.id).await.unwrap_err();
+        assert!(matches!(err, Error::OrgHasBuckets { .. }));
+
+        // renaming an org renames the databases of its buckets
+        let err = server.rename_org(&other.id, "company").await.unwrap_err();
+        assert!(matches!(err, Error::OrgAlreadyExists { .. }));
+        let company = server.rename_org(&company.id, "renamed").await?;
+        assert_eq!(company.name, "renamed");
+        assert_eq!(server.db_names_sorted().await, vec!["renamed_sensors"]);
+        let db_name = DatabaseName::new("renamed_sensors").unwrap();
+        let db = server.db(&db_name).await.unwrap();
+        assert_eq!(db.partition_keys()?.len(), 1);
+
+        let other = server.rename_org(&other.id, "another").await?;
+        assert_eq!(other.name, "another");
+        assert_eq!(server.orgs().await, vec![company.clone(), other.clone()]);
+
+        // the catalog is loaded by a new server
+        let server2 = Server::new(TestConnectionManager::new(), store);
+        server2.set_id(1);
+        server2.load_database_configs().await?;
+        assert_eq!(server2.orgs().await, vec![company, other.clone()]);
+        assert_eq!(server2.db_names_sorted().await, vec!["renamed_sensors"]);
+        let rules = server2.db_rules(&db_name).await.unwrap();
+        assert_eq!(rules.name, "renamed_sensors");
+        assert_eq!(rules.retention_period_seconds, Some(3600));
+
+        server2.delete_org(&other.id).await?;
+        assert!(server2.org(&other.id).await.is_none());
+        let err = server2.delete_org(&other.id).await.unwrap_err();
+        assert!(matches!(err, Error::OrgNotFound { .. }));
+
+        Ok(())
+    }
 
-    type TestError = Box<dyn std::error::Error + Send + Sync + 'static>;
-    type Result<T = (), E = TestError> = std::result::Result<T, E>;
+    #[tokio::test]
+    async fn token_catalog() -> Result {
+        use crate::tokens::Scope;
+
+        let manager = TestConnectionManager::new();
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let server = Server::new(manager, Arc::clone(&store));
+        server.set_id(1);
+
+        let (token, secret) = server
+            .create_token(TokenRequest {
+                description: "dashboards".to_string(),
+                scopes: vec![Scope::Read],
+                org: Some("company".to_string()),
+                bucket: None,
+                limits: RateLimits::default(),
+            })
+            .await?;
+        assert_eq!(server.authenticate_token(&secret), Some(token.clone()));
+        assert_eq!(server.tokens(), vec![token.clone()]);
+
+        // the catalog is loaded by a new server
+        let server2 = Server::new(TestConnectionManager::new(), store);
+        server2.set_id(1);
+        server2.load_database_configs().await?;
+        assert_eq!(server2.token(&token.id), Some(token.clone()));
+        assert_eq!(server2.authenticate_token(&secret), Some(token.clone()));
+
+        server2.delete_token(&token.id).await?;
+        assert_eq!(server2.authenticate_token(&secret), None);
+        let err = server2.delete_token(&token.id).await.unwrap_err();
+        assert!(matches!(err, Error::TokenNotFound { .. }));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn snapshot_partition() -> Result {
+        let manager = TestConnectionManager::new();
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let server = Server::new(manager, Arc::clone(&store));
+        server.set_id(1);
+        server.create_database("foo", DatabaseRules::new()).await?;
+
+        let lines: Vec<_> = parse_lines("cpu bar=1 10").map(|l| l.unwrap()).collect();
+        server.write_lines("foo", &lines).await?;
+
+        let db_name = DatabaseName::new("foo").unwrap();
+        let db = server.db(&db_name).await.unwrap();
+        let partition_key = db.partition_keys()?.pop().unwrap();
+
+        let err = server
+            .snapshot_partition(&db_name, "not_a_partition")
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::PartitionNotFound { .. }));
+        let err = server
+            .snapshot_partition(&DatabaseName::new("bar").unwrap(), &partition_key)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::DatabaseNotFound { .. }));
+
+        let snapshot = server.snapshot_partition(&db_name, &partition_key).await?;
+        for _ in 0..50 {
+            if snapshot.finished() {
+                break;
+            }
+            tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
+        }
+        assert!(snapshot.finished());
+
+        // the open chunk was rolled over and written to the object store
+        assert_eq!(db.mutable_buffer_chunks(&partition_key).len(), 2);
+        let mut data_path = store.new_path();
+        data_path.push_all_dirs(&["foo", "data", &partition_key]);
+        let files = store.list(Some(&data_path)).await?.try_concat().await?;
+        assert!(!files.is_empty());
+
+        // the chunk is counted as persisted once its metadata is written too
+        let mut persisted_chunk_count = 0;
+        for _ in 0..50 {
+            persisted_chunk_count =
+                server.partition_statuses(&db_name).await?[0].persisted_chunk_count;
+            if persisted_chunk_count == 1 {
+                break;
+            }
+            tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(persisted_chunk_count, 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn drop_expired_data() -> Result {
+        let manager = TestConnectionManager::new();
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let server = Server::new(manager, store);
+        server.set_id(1);
+
+        let rules = DatabaseRules {
+            retention_period_seconds: Some(3600),
+            ..DatabaseRules::new()
+        };
+        server.create_database("expiring", rules).await?;
+        server
+            .create_database("forever", DatabaseRules::new())
+            .await?;
+
+        // written in 1970, long past the retention period
+        let lines: Vec<_> = parse_lines("cpu bar=1 10").map(|l| l.unwrap()).collect();
+        for db_name in &["expiring", "forever"] {
+            server.write_lines(db_name, &lines).await?;
+            let db = server
+                .db(&DatabaseName::new(*db_name).unwrap())
+                .await
+                .unwrap();
+            let partition_key = db.partition_keys()?.pop().unwrap();
+            db.rollover_partition(&partition_key).await?;
+        }
+
+        assert_eq!(server.drop_expired_data().await?, 1);
+        assert_eq!(server.drop_expired_data().await?, 0);
+
+        let db = server
+            .db(&DatabaseName::new("forever").unwrap())
+            .await
+            .unwrap();
+        let partition_key = db.partition_keys()?.pop().unwrap();
+        assert_eq!(db.mutable_buffer_chunks(&partition_key).len(), 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn partition_leases() -> Result {
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let rules = DatabaseRules {
+            retention_period_seconds: Some(3600),
+            ..DatabaseRules::new()
+        };
+        let lines: Vec<_> = parse_lines("cpu bar=1 10").map(|l| l.unwrap()).collect();
+
+        let mut servers = vec![];
+        for id in 1..=2 {
+            let server = Server::new(TestConnectionManager::new(), Arc::clone(&store));
+            server.set_id(id);
+            server.set_partition_leases(PartitionLeases::new(Duration::from_secs(60)));
+            server.create_database("foo", rules.clone()).await?;
+            server.write_lines("foo", &lines).await?;
+            servers.push(server);
+        }
+        let db_name = DatabaseName::new("foo").unwrap();
+        let db = servers[1].db(&db_name).await.unwrap();
+        let partition_key = db.partition_keys()?.pop().unwrap();
+
+        // the first server to ask takes the lease
+        assert_eq!(
+            servers[0]
+                .partition_ownership("foo", &partition_key)
+                .await?,
+            Ownership::Owned
+        );
+        assert_eq!(
+            servers[1]
+                .partition_ownership("foo", &partition_key)
+                .await?,
+            Ownership::HeldBy(1)
+        );
+        assert_eq!(
+            servers[0]
+                .partition_ownership("foo", &partition_key)
+                .await?,
+            Ownership::Owned
+        );
+
+        let err = servers[1]
+            .compact(&db_name, Some(&partition_key))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::PartitionLeaseHeld { owner: 1, .. }));
+        assert!(servers[1]
+            .compact(&db_name, None)
+            .await?
+            .partition_keys
+            .is_empty());
+
+        // expired data is only dropped by the owner
+        db.rollover_partition(&partition_key).await?;
+        assert_eq!(servers[1].drop_expired_data().await?, 0);
+        servers[0]
+            .db(&db_name)
+            .await
+            .unwrap()
+            .rollover_partition(&partition_key)
+            .await?;
+        assert_eq!(servers[0].drop_expired_data().await?, 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn drain() -> Result {
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let lines: Vec<_> = parse_lines("cpu bar=1 10").map(|l| l.unwrap()).collect();
+
+        let mut servers = vec![];
+        for id in 1..=2 {
+            let server = Server::new(TestConnectionManager::new(), Arc::clone(&store));
+            server.set_id(id);
+            server.set_partition_leases(PartitionLeases::new(Duration::from_secs(60)));
+            server.create_database("foo", DatabaseRules::new()).await?;
+            server.write_lines("foo", &lines).await?;
+            servers.push(server);
+        }
+        let db_name = DatabaseName::new("foo").unwrap();
+        let db = servers[0].db(&db_name).await.unwrap();
+        let partition_key = db.partition_keys()?.pop().unwrap();
+        assert_eq!(
+            servers[0]
+                .partition_ownership("foo", &partition_key)
+                .await?,
+            Ownership::Owned
+        );
+
+        assert!(servers[0].drain_status().is_none());
+        let status = servers[0].start_drain().unwrap();
+        assert_eq!(status.phase, DrainPhase::Persisting);
+        assert!(servers[0].start_drain().is_none());
+        let err = servers[0].write_lines("foo", &lines).await.unwrap_err();
+        assert!(matches!(err, Error::Draining));
+
+        servers[0].drain().await?;
+        let status = servers[0].drain_status().unwrap();
+        assert_eq!(status.phase, DrainPhase::Drained);
+        assert!(status.safe_to_terminate);
+        assert_eq!(status.partitions, 1);
+        assert_eq!(status.partitions_persisted, 1);
+        assert_eq!(status.leases_released, 1);
+        let partition = servers[0]
+            .partition_statuses(&db_name)
+            .await?
+            .pop()
+            .unwrap();
+        assert_eq!(partition.open_chunk_size, 0);
+        assert_eq!(partition.persisted_chunk_count, 1);
+
+        // the other server takes over the partition without waiting
+        assert_eq!(
+            servers[1]
+                .partition_ownership("foo", &partition_key)
+                .await?,
+            Ownership::Owned
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn quotas() -> Result {
+        use crate::quotas::Limit;
+
+        let manager = TestConnectionManager::new();
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let server = Server::new(manager, store);
+        server.set_id(1);
+        server.set_quotas(Quotas {
+            series: Limit {
+                soft: Some(1),
+                hard: Some(2),
+            },
+            ..Default::default()
+        });
+        for db_name in &["company_sensors", "company_other", "other_sensors"] {
+            server
+                .create_database(*db_name, DatabaseRules::new())
+                .await?;
+        }
+
+        let lines = parsed_lines("cpu,host=a bar=1 10");
+        server.write_lines("company_sensors", &lines).await?;
+        let lines = parsed_lines("cpu,host=b bar=1 10");
+        server.write_lines("company_other", &lines).await?;
+
+        let usage = server.org_usage("company")?;
+        assert_eq!(usage.total.series, 2);
+        assert_eq!(usage.buckets.len(), 2);
+        assert_eq!(usage.buckets[0].bucket, "other");
+        assert_eq!(usage.buckets[0].usage.series, 1);
+        assert!(usage.total.bytes > 0);
+
+        // the org is at its hard limit, so further writes are rejected
+        let lines = parsed_lines("cpu,host=c bar=1 10");
+        let err = server
+            .write_lines("company_sensors", &lines)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            Error::QuotaExceeded {
+                resource: Resource::Series,
+                usage: 2,
+                limit: 2,
+                ..
+            }
+        ));
+
+        // while other orgs may still write
+        server.write_lines("other_sensors", &lines).await?;
+
+        server.set_quotas(Quotas::default());
+        server.write_lines("company_sensors", &lines).await?;
+        assert_eq!(server.org_usage("company")?.total.series, 3);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn memory_limit() -> Result {
+        let manager = TestConnectionManager::new();
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let server = Server::new(manager, store);
+        server.set_id(1);
+        server.create_database("foo", DatabaseRules::new()).await?;
+
+        let lines = parsed_lines("cpu,host=a bar=1 10");
+        server.write_lines("foo", &lines).await?;
+
+        // without a limit, the memory isn't measured
+        server.update_memory_usage().await;
+        assert_eq!(server.memory_tracker().total(), 0);
+
+        server.set_memory_limit(Some(u64::MAX));
+        server.update_memory_usage().await;
+        let used = server.memory_tracker().used(Subsystem::MutableBuffer);
+        assert!(used > 0);
+
+        // the server is over its limit, so further writes are rejected
+        server.set_memory_limit(Some(used / 2));
+        let err = server.write_lines("foo", &lines).await.unwrap_err();
+        assert!(matches!(err, Error::MemoryLimitReached { .. }));
+        assert_eq!(server.memory_tracker().rejected(), 1);
+
+        // writes are checked against the last measure rather than measuring
+        // the memory again
+        server.set_memory_limit(None);
+        server.write_lines("foo", &lines).await?;
+        assert_eq!(server.memory_tracker().used(Subsystem::MutableBuffer), used);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rate_limits() -> Result {
+        use std::num::NonZeroU32;
+
+        let manager = TestConnectionManager::new();
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let server = Server::new(manager, store);
+        server.set_id(1);
+        let org = server.create_org("company").await?;
+        let (token, _) = server
+            .create_token(TokenRequest {
+                scopes: vec![tokens::Scope::Read],
+                limits: RateLimits {
+                    concurrent_queries: NonZeroU32::new(2),
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .await?;
+
+        let org = server
+            .set_org_rate_limits(
+                &org.id,
+                RateLimits {
+                    concurrent_queries: NonZeroU32::new(1),
+                    ..Default::default()
+                },
+            )
+            .await?;
+        assert_eq!(server.org(&org.id).await, Some(org));
+
+        let in_org = server.start_query(Some(&token), Some("company")).await?;
+        let err = server
+            .start_query(Some(&token), Some("company"))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            Error::RateLimited {
+                subject: Subject::Org(_),
+                ..
+            }
+        ));
+
+        // the token may run a second query elsewhere, but not a third
+        let _other = server.start_query(Some(&token), Some("other")).await?;
+        let err = server.start_query(Some(&token), None).await.unwrap_err();
+        assert!(matches!(
+            err,
+            Error::RateLimited {
+                subject: Subject::Token(_),
+                ..
+            }
+        ));
+
+        // the configured tokens are only limited by the org
+        server.check_write_rate(None, "company", 1000).await?;
+
+        drop(in_org);
+        server.start_query(None, Some("company")).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn compact() -> Result {
+        use crate::compaction::CompactionState;
+
+        let manager = TestConnectionManager::new();
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let server = Server::new(manager, store);
+        server.set_id(1);
+        server.create_database("foo", DatabaseRules::new()).await?;
+
+        let lines: Vec<_> = parse_lines("cpu bar=1 10").map(|l| l.unwrap()).collect();
+        server.write_lines("foo", &lines).await?;
+
+        let db_name = DatabaseName::new("foo").unwrap();
+        let db = server.db(&db_name).await.unwrap();
+        let partition_key = db.partition_keys()?.pop().unwrap();
+
+        let err = server
+            .compact(&db_name, Some("not_a_partition"))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::PartitionNotFound { .. }));
+
+        let status = server.compact(&db_name, None).await?;
+        assert_eq!(status.partition_keys, vec![partition_key.clone()]);
+        for _ in 0..50 {
+            if server.compaction(status.id).unwrap().state != CompactionState::Running {
+                break;
+            }
+            tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
+        }
+        let status = server.compaction(status.id).unwrap();
+        assert_eq!(status.state, CompactionState::Completed);
+        assert_eq!(status.chunks_total, 1);
+        assert_eq!(status.chunks_compacted, 1);
+        assert_eq!(server.compactions(), vec![status]);
+
+        // only the new open chunk is left in the mutable buffer
+        assert_eq!(db.mutable_buffer_chunks(&partition_key).len(), 1);
+        assert_eq!(db.read_buffer_chunks(&partition_key).len(), 1);
+
+        let planner = SQLQueryPlanner::default();
+        let executor = server.executor();
+        let physical_plan = planner
+            .query(db.as_ref(), "select * from cpu", executor.as_ref())
+            .await
+            .unwrap();
+        let batches = collect(physical_plan).await.unwrap();
+        let expected = vec![
+            "+-----+------+",
+            "| bar | time |",
+            "+-----+------+",
+            "| 1   | 10   |",
+            "+-----+------+",
+        ];
+        assert_table_eq!(expected, &batches);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn duplicate_database_name_rejected() -> Result {
+        // Covers #643
+
+        let manager = TestConnectionManager::new();
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let server = Server::new(manager, store);
+        server.set_id(1);
+
+        let name = "bananas";
+
+        // Create a database
+        server
+            .create_database(name, DatabaseRules::new())
+            .await
+            .expect("failed to create database");
+
+        // Then try and create another with the same name
+        let got = server
+            .create_database(name, DatabaseRules::new())
+            .await
+            .unwrap_err();
+
+        if !matches!(got, Error::DatabaseAlreadyExists {..}) {
+            panic!("expected already exists error");
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn db_names_sorted() -> Result {
+        let manager = TestConnectionManager::new();
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let server = Server::new(manager, store);
+        server.set_id(1);
+
+        let names = vec!["bar", "baz"];
+
+        for name in &names {
+            server
+                .create_database(*name, DatabaseRules::new())
+                .await
+                .expect("failed to create database");
+        }
+
+        let db_names_sorted = server.db_names_sorted().await;
+        assert_eq!(names, db_names_sorted);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn database_name_validation() -> Result {
+        let manager = TestConnectionManager::new();
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let server = Server::new(manager, store);
+        server.set_id(1);
+
+        let reject = vec![
+            "bananas\t",
+            "bananas\"are\u{0099}\"great",
+            "bananas\nfoster",
+        ];
+
+        for name in reject {
+            let got = server
+                .create_database(name, DatabaseRules::new())
+                .await
+                .unwrap_err();
+            if !matches!(got, Error::InvalidDatabaseName { .. }) {
+                panic!("expected invalid name error");
+            }
+        }
+
+        Ok(())
+    }
 
     #[tokio::test]
-    async fn server_api_calls_return_error_with_no_id_set() -> Result {
+    async fn writes_local() -> Result {
         let manager = TestConnectionManager::new();
         let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
-        let mut server = Server::new(manager, store);
+        let server = Server::new(manager, store);
+        server.set_id(1);
+        server.create_database("foo", DatabaseRules::new()).await?;
 
-        let rules = DatabaseRules::new();
-        let resp = server.create_database("foo", rules).await.unwrap_err();
-        assert!(matches!(resp, Error::IdNotSet));
+        let line = "cpu bar=1 10";
+        let lines: Vec<_> = parse_lines(line).map(|l| l.unwrap()).collect();
+        server.write_lines("foo", &lines).await.unwrap();
 
-        let lines = parsed_lines("cpu foo=1 10");
-        let resp = server.write_lines("foo", &lines).await.unwrap_err();
-        assert!(matches!(resp, Error::IdNotSet));
+        let db_name = DatabaseName::new("foo").unwrap();
+        let db = server.db(&db_name).await.unwrap();
 
-        let resp = server
-            .create_host_group("group1".to_string(), vec!["serverA".to_string()])
+        let planner = SQLQueryPlanner::default();
+        let executor = server.executor();
+        let physical_plan = planner
+            .query(db.as_ref(), "select * from cpu", executor.as_ref())
             .await
-            .unwrap_err();
-        assert!(matches!(resp, Error::IdNotSet));
+            .unwrap();
+
+        let batches = collect(physical_plan).await.unwrap();
+        let expected = vec![
+            "+-----+------+",
+            "| bar | time |",
+            "+-----+------+",
+            "| 1   | 10   |",
+            "+-----+------+",
+        ];
+        assert_table_eq!(expected, &batches);
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn create_database_persists_rules() {
-        let manager = TestConnectionManager::new();
+    async fn replicate_to_peers() -> Result {
+        let mut manager = TestConnectionManager::new();
+        let remote = Arc::new(TestRemoteServer::default());
+        manager
+            .remotes
+            .insert("serverA".to_string(), Arc::clone(&remote));
         let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
-        let server = Server::new(manager, Arc::clone(&store));
+        let server = Server::new(manager, store);
         server.set_id(1);
+        server.create_database("foo", DatabaseRules::new()).await?;
 
-        let name = "bananas";
+        // serverB can't be reached, but this server and serverA are a
+        // majority
+        let peers = vec!["serverA".to_string(), "serverB".to_string()];
+        server.set_peer_replication(PeerReplication::new(peers.clone(), None).unwrap());
+        server
+            .write_lines("foo", &parsed_lines("cpu bar=1 10"))
+            .await?;
+        let writes = remote.writes.lock().get("foo").unwrap().clone();
+        assert_eq!(writes.len(), 1);
+
+        server.set_peer_replication(PeerReplication::new(peers, Some(3)).unwrap());
+        let err = server
+            .write_lines("foo", &parsed_lines("cpu bar=2 20"))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(
+                err,
+                Error::WriteQuorumNotReached {
+                    stored: 2,
+                    quorum: 3,
+                    ..
+                }
+            ),
+            "{}",
+            err
+        );
+
+        // a peer that doesn't answer fails the quorum once the timeout
+        // passes
+        remote.stalled.store(true, Ordering::SeqCst);
+        server.set_peer_replication(
+            PeerReplication::new(vec!["serverA".to_string()], Some(2))
+                .unwrap()
+                .with_timeout(Duration::from_millis(50)),
+        );
+        let err = server
+            .write_lines("foo", &parsed_lines("cpu bar=3 30"))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(
+                err,
+                Error::WriteQuorumNotReached {
+                    stored: 1,
+                    quorum: 2,
+                    ..
+                }
+            ),
+            "{}",
+            err
+        );
+        assert!(err.to_string().contains("timed out"), "{}", err);
+
+        // the peer stores the write without replicating it to its own
+        // peers, which it can't reach
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let peer = Server::new(TestConnectionManager::new(), store);
+        peer.set_id(2);
+        peer.create_database("foo", DatabaseRules::new()).await?;
+        peer.set_peer_replication(PeerReplication::new(vec!["serverC".to_string()], None).unwrap());
+        peer.store_peer_write("foo", writes[0].clone()).await?;
+
+        let db = peer.db(&DatabaseName::new("foo").unwrap()).await.unwrap();
+        let planner = SQLQueryPlanner::default();
+        let executor = peer.executor();
+        let physical_plan = planner
+            .query(db.as_ref(), "select * from cpu", executor.as_ref())
+            .await
+            .unwrap();
+        let batches = collect(physical_plan).await.unwrap();
+        let expected = vec![
+            "+-----+------+",
+            "| bar | time |",
+            "+-----+------+",
+            "| 1   | 10   |",
+            "+-----+------+",
+        ];
+        assert_table_eq!(expected, &batches);
+
+        Ok(())
+    }
 
+    #[tokio::test]
+    async fn anti_entropy_repair() -> Result {
+        let mut manager = TestConnectionManager::new();
+        let remote = Arc::new(TestRemoteServer::default());
+        manager
+            .remotes
+            .insert("serverA".to_string(), Arc::clone(&remote));
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let server = Server::new(manager, store);
+        server.set_id(1);
         let rules = DatabaseRules {
-            partition_template: PartitionTemplate {
-                parts: vec![TemplatePart::TimeFormat("YYYY-MM".to_string())],
-            },
-            name: name.to_string(),
-            ..Default::default()
+            wal_buffer_config: Some(WalBufferConfig {
+                buffer_size: 10_000,
+                segment_size: 2000,
+                buffer_rollover: WalBufferRollover::ReturnError,
+                store_segments: false,
+                close_segment_after: None,
+            }),
+            ..DatabaseRules::new()
         };
-
-        // Create a database
+        server.create_database("foo", rules).await?;
         server
-            .create_database(name, rules.clone())
-            .await
-            .expect("failed to create database");
+            .write_lines("foo", &parsed_lines("cpu bar=1 10"))
+            .await?;
+        server
+            .write_lines("foo", &parsed_lines("cpu bar=2 20"))
+            .await?;
 
-        let mut rules_path = server.store.new_path();
-        rules_path.push_all_dirs(&["1", name]);
-        rules_path.set_file_name("rules.json");
+        // serverA missed both writes
+        let peers = vec!["serverA".to_string()];
+        server.set_peer_replication(PeerReplication::new(peers, Some(1)).unwrap());
+        assert_eq!(server.repair_round().await?, 2);
+        let writes = remote.writes.lock().get("foo").unwrap().clone();
+        assert_eq!(writes.len(), 2);
 
-        let read_data = server
-            .store
-            .get(&rules_path)
-            .await
-            .unwrap()
-            .map_ok(|b| bytes::BytesMut::from(&b[..]))
-            .try_concat()
+        // nothing to repair once the digests match
+        *remote.digests.lock() = server.write_digests("foo")?;
+        assert_eq!(server.repair_round().await?, 0);
+
+        // a write sent again is only stored once
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let peer = Server::new(TestConnectionManager::new(), store);
+        peer.set_id(2);
+        peer.create_database("foo", DatabaseRules::new()).await?;
+        peer.store_peer_write("foo", writes[0].clone()).await?;
+        peer.store_peer_write("foo", writes[1].clone()).await?;
+        peer.store_peer_write("foo", writes[0].clone()).await?;
+        assert_eq!(peer.write_digests("foo")?, server.write_digests("foo")?);
+
+        let db = peer.db(&DatabaseName::new("foo").unwrap()).await.unwrap();
+        let planner = SQLQueryPlanner::default();
+        let executor = peer.executor();
+        let physical_plan = planner
+            .query(db.as_ref(), "select * from cpu", executor.as_ref())
             .await
             .unwrap();
+        let batches = collect(physical_plan).await.unwrap();
+        assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 2);
 
-        let read_data = std::str::from_utf8(&*read_data).unwrap();
-        let read_rules = serde_json::from_str::<DatabaseRules>(read_data).unwrap();
+        Ok(())
+    }
 
-        assert_eq!(rules, read_rules);
+    #[tokio::test]
+    async fn peer_writes_stored_after_writer_restarts() -> Result {
+        async fn start_writer(
+            remote: &Arc<TestRemoteServer>,
+        ) -> Result<Server<TestConnectionManager>> {
+            let mut manager = TestConnectionManager::new();
+            manager
+                .remotes
+                .insert("serverA".to_string(), Arc::clone(remote));
+            let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+            let server = Server::new(manager, store);
+            server.set_id(1);
+            server.create_database("foo", DatabaseRules::new()).await?;
+            let peers = vec!["serverA".to_string()];
+            server.set_peer_replication(PeerReplication::new(peers, Some(2)).unwrap());
+            Ok(server)
+        }
 
-        let db2 = "db_awesome";
-        server
-            .create_database(db2, DatabaseRules::new())
+        let remote = Arc::new(TestRemoteServer::default());
+        let writer = start_writer(&remote).await?;
+        writer
+            .write_lines("foo", &parsed_lines("cpu bar=1 10"))
+            .await?;
+        // the writer restarts with the same id
+        let writer = start_writer(&remote).await?;
+        writer
+            .write_lines("foo", &parsed_lines("cpu bar=2 20"))
+            .await?;
+
+        let writes = remote.writes.lock().get("foo").unwrap().clone();
+        assert_eq!(writes.len(), 2);
+        assert_ne!(
+            writes[0].writer_and_sequence(),
+            writes[1].writer_and_sequence()
+        );
+
+        // the peer stores the write accepted after the restart too
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let peer = Server::new(TestConnectionManager::new(), store);
+        peer.set_id(2);
+        peer.create_database("foo", DatabaseRules::new()).await?;
+        for write in writes {
+            peer.store_peer_write("foo", write).await?;
+        }
+
+        let db = peer.db(&DatabaseName::new("foo").unwrap()).await.unwrap();
+        let planner = SQLQueryPlanner::default();
+        let executor = peer.executor();
+        let physical_plan = planner
+            .query(db.as_ref(), "select * from cpu", executor.as_ref())
             .await
-            .expect("failed to create 2nd db");
+            .unwrap();
+        let batches = collect(physical_plan).await.unwrap();
+        let expected = vec![
+            "+-----+------+",
+            "| bar | time |",
+            "+-----+------+",
+            "| 1   | 10   |",
+            "| 2   | 20   |",
+            "+-----+------+",
+        ];
+        assert_table_eq!(expected, &batches);
 
-        store.list_with_delimiter(&store.new_path()).await.unwrap();
+        Ok(())
+    }
 
-        let manager = TestConnectionManager::new();
-        let server2 = Server::new(manager, store);
-        server2.set_id(1);
-        server2.load_database_configs().await.unwrap();
+    #[tokio::test]
+    async fn hinted_handoff() -> Result {
+        let mut manager = TestConnectionManager::new();
+        let remote_a = Arc::new(TestRemoteServer::default());
+        let remote_b = Arc::new(TestRemoteServer::default());
+        manager
+            .remotes
+            .insert("serverA".to_string(), Arc::clone(&remote_a));
+        manager
+            .remotes
+            .insert("serverB".to_string(), Arc::clone(&remote_b));
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let server = Server::new(manager, store);
+        server.set_id(1);
+        server.create_database("foo", DatabaseRules::new()).await?;
+        let peers = vec!["serverA".to_string(), "serverB".to_string()];
+        server.set_peer_replication(PeerReplication::new(peers, None).unwrap());
+        server.set_hinted_handoff(HintedHandoff::new(1 << 20, Duration::from_secs(3600)));
 
-        let _ = server2.db(&DatabaseName::new(db2).unwrap()).await.unwrap();
-        let _ = server2.db(&DatabaseName::new(name).unwrap()).await.unwrap();
+        // serverB is down, but this server and serverA are a majority
+        remote_b.down.store(true, Ordering::SeqCst);
+        server
+            .write_lines("foo", &parsed_lines("cpu bar=1 10"))
+            .await?;
+        server
+            .write_lines("foo", &parsed_lines("cpu bar=2 20"))
+            .await?;
+        let hints = server.hinted_handoff().unwrap();
+        // serverB fails to store the writes in the background
+        while hints.pending() != vec![("serverB".to_string(), 2)] {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(server.deliver_hints().await, 0);
+        assert!(remote_b.writes.lock().get("foo").is_none());
+
+        // serverB gets the writes it missed once it is back, in order
+        remote_b.down.store(false, Ordering::SeqCst);
+        assert_eq!(server.deliver_hints().await, 2);
+        assert!(hints.pending().is_empty());
+        let writes = remote_b.writes.lock().get("foo").unwrap().clone();
+        assert_eq!(writes, *remote_a.writes.lock().get("foo").unwrap());
+        assert_eq!(server.deliver_hints().await, 0);
+
+        Ok(())
     }
 
     #[tokio::test]
-    async fn duplicate_database_name_rejected() -> Result {
-        // Covers #643
+    async fn read_replica() -> Result {
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let primary = Server::new(TestConnectionManager::new(), Arc::clone(&store));
+        primary.set_id(1);
+        let rules = DatabaseRules {
+            wal_buffer_config: Some(WalBufferConfig {
+                buffer_size: 4000,
+                segment_size: 2000,
+                buffer_rollover: WalBufferRollover::ReturnError,
+                store_segments: true,
+                close_segment_after: None,
+            }),
+            ..DatabaseRules::new()
+        };
+        primary.create_database("foo", rules).await?;
+
+        let replica = Server::new(TestConnectionManager::new(), Arc::clone(&store));
+        replica.set_id(2);
+        assert_eq!(replica.sync_from_primary().await?, 0);
+        replica.set_read_replica(ReadReplica::new(1));
+
+        // the database is followed before any segment is persisted
+        primary
+            .write_lines("foo", &parsed_lines("cpu bar=1 10"))
+            .await?;
+        assert_eq!(replica.sync_from_primary().await?, 0);
+        let db_name = DatabaseName::new("foo").unwrap();
+        let db = replica.db(&db_name).await.unwrap();
+
+        primary.persist_open_wal_segments().await?;
+        primary
+            .write_lines("foo", &parsed_lines("cpu bar=2 20"))
+            .await?;
+        primary.persist_open_wal_segments().await?;
+        assert_eq!(replica.sync_from_primary().await?, 2);
+        assert_eq!(replica.sync_from_primary().await?, 0);
+        assert_eq!(
+            replica.read_replica().unwrap().applied_segment("foo"),
+            Some(2)
+        );
 
-        let manager = TestConnectionManager::new();
+        let planner = SQLQueryPlanner::default();
+        let executor = replica.executor();
+        let physical_plan = planner
+            .query(db.as_ref(), "select * from cpu", executor.as_ref())
+            .await
+            .unwrap();
+        let batches = collect(physical_plan).await.unwrap();
+        let expected = vec![
+            "+-----+------+",
+            "| bar | time |",
+            "+-----+------+",
+            "| 1   | 10   |",
+            "| 2   | 20   |",
+            "+-----+------+",
+        ];
+        assert_table_eq!(expected, &batches);
+
+        let err = replica
+            .write_lines("foo", &parsed_lines("cpu bar=3 30"))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(err, Error::ReadReplicaWrite { primary_id: 1 }),
+            "{}",
+            err
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn route_writes() -> Result {
+        let mut manager = TestConnectionManager::new();
+        let backends = vec!["serverA".to_string(), "serverB".to_string()];
+        for backend in &backends {
+            manager
+                .remotes
+                .insert(backend.clone(), Arc::new(TestRemoteServer::default()));
+        }
+        let remotes = manager.remotes.clone();
         let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
         let server = Server::new(manager, store);
-        server.set_id(1);
 
-        let name = "bananas";
+        // routing servers need neither an id nor the database
+        let router = ShardRouter::new(backends).unwrap();
+        server.set_shard_router(router.clone());
+        let lp = (0..20)
+            .map(|i| format!("cpu,host=host{} bar=1 10", i))
+            .collect::<Vec<_>>()
+            .join("\n");
+        server.write_lines("foo", &parsed_lines(&lp)).await?;
+
+        let mut routed = 0;
+        for (backend, remote) in &remotes {
+            let lines = remote.lines.lock();
+            let writes = lines.get("foo").unwrap();
+            assert_eq!(writes.len(), 1);
+            for line in writes[0].lines() {
+                let series = line.split(' ').next().unwrap();
+                assert_eq!(router.backend(series), backend.as_str());
+                routed += 1;
+            }
+        }
+        assert_eq!(routed, 20);
 
-        // Create a database
-        server
-            .create_database(name, DatabaseRules::new())
+        // a backend that can't be reached fails the write
+        let router = ShardRouter::new(vec!["serverA".to_string(), "serverC".to_string()]).unwrap();
+        server.set_shard_router(router);
+        let err = server
+            .write_lines("foo", &parsed_lines(&lp))
             .await
-            .expect("failed to create database");
+            .unwrap_err();
+        assert!(
+            matches!(
+                err,
+                Error::ShardWriteFailed {
+                    failed: 1,
+                    shards: 2,
+                    ..
+                }
+            ),
+            "{}",
+            err
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn move_shard() -> Result {
+        let mut manager = TestConnectionManager::new();
+        let source = Arc::new(TestRemoteServer::default());
+        manager
+            .remotes
+            .insert("serverA".to_string(), Arc::clone(&source));
+        manager
+            .remotes
+            .insert("serverB".to_string(), Arc::new(TestRemoteServer::default()));
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let server = Server::new(manager, Arc::clone(&store));
+        assert!(matches!(
+            server.start_shard_move(1, "serverB"),
+            Err(Error::RoutingDisabled)
+        ));
+
+        let router = ShardRouter::new(vec!["serverA".to_string(), "serverB".to_string()]).unwrap();
+        server.set_shard_router(router.clone());
+        let shard = router
+            .shards()
+            .into_iter()
+            .find(|shard| shard.owner == "serverA")
+            .unwrap();
+        assert!(matches!(
+            server.start_shard_move(shard.id(), "serverA"),
+            Err(Error::ShardAlreadyOwned { .. })
+        ));
+        assert!(matches!(
+            server.start_shard_move(shard.id(), "serverC"),
+            Err(Error::InvalidShardMove { .. })
+        ));
+
+        let status = server.start_shard_move(shard.id(), "serverB")?;
+        assert_eq!(status.phase, MovePhase::Rerouting);
+        assert!(matches!(
+            server.start_shard_move(shard.id(), "serverB"),
+            Err(Error::ShardMoveRunning { .. })
+        ));
+        server.move_shard(shard.id()).await?;
+
+        let status = server.shard_move(shard.id()).unwrap();
+        assert_eq!(status.phase, MovePhase::Moved);
+        assert_eq!(status.lines_replayed, 1);
+        assert_eq!(
+            source.handed_off.lock().clone(),
+            vec![(shard.range, "serverB".to_string())]
+        );
+        let rerouted = server.shard_router().unwrap();
+        assert_eq!(rerouted.shard(shard.id()).unwrap().owner, "serverB");
+
+        // other routers sharing the object store route the same way
+        let other = Server::new(TestConnectionManager::new(), store);
+        other.set_shard_router(router);
+        other.load_shard_owners().await?;
+        assert_eq!(
+            other.shard_router().unwrap().moved_shards(),
+            rerouted.moved_shards()
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn hand_off_shard() -> Result {
+        let mut manager = TestConnectionManager::new();
+        let target = Arc::new(TestRemoteServer::default());
+        manager
+            .remotes
+            .insert("serverB".to_string(), Arc::clone(&target));
+        let server = Server::new(
+            manager,
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        );
+        server.set_id(1);
+        let rules = DatabaseRules {
+            wal_buffer_config: Some(WalBufferConfig {
+                buffer_size: 10_000,
+                segment_size: 2000,
+                buffer_rollover: WalBufferRollover::ReturnError,
+                store_segments: false,
+                close_segment_after: None,
+            }),
+            ..DatabaseRules::new()
+        };
+        server.create_database("foo", rules).await?;
+        let lp = (0..20)
+            .map(|i| format!("cpu,host=host{} bar=1 10", i))
+            .collect::<Vec<_>>()
+            .join("\n");
+        server.write_lines("foo", &parsed_lines(&lp)).await?;
+
+        // only the lines of the shard are replayed
+        let router = ShardRouter::new(vec!["serverA".to_string(), "serverB".to_string()]).unwrap();
+        let mut handed_off = 0;
+        for shard in router.shards() {
+            let sent = target.lines.lock().get("foo").map_or(0, Vec::len);
+            let lines = server.hand_off_shard(shard.range, "serverB").await?;
+            if lines > 0 {
+                let batches = target.lines.lock();
+                let batch = &batches.get("foo").unwrap()[sent];
+                assert_eq!(batch.lines().count() as u64, lines);
+                for line in batch.lines() {
+                    let series = line.split(' ').next().unwrap();
+                    assert!(shard.range.contains(series));
+                }
+            }
+            handed_off += lines;
+        }
+        assert_eq!(handed_off, 20);
+
+        Ok(())
+    }
+
+    #[derive(Debug, Default)]
+    struct TestWriteBuffer {
+        partitions: u32,
+        produced: Mutex<Vec<(String, u32, ReplicatedWrite)>>,
+    }
+
+    #[async_trait]
+    impl WriteBuffer for TestWriteBuffer {
+        fn partitions(&self) -> u32 {
+            self.partitions
+        }
+
+        async fn produce(
+            &self,
+            db_name: &str,
+            partition: u32,
+            write: &ReplicatedWrite,
+        ) -> write_buffer::Result<()> {
+            self.produced.lock().push((
+                db_name.to_string(),
+                partition,
+                ReplicatedWrite::from(write.bytes().as_slice()),
+            ));
+            Ok(())
+        }
 
-        // Then try and create another with the same name
-        let got = server
-            .create_database(name, DatabaseRules::new())
-            .await
-            .unwrap_err();
+        async fn watermarks(&self, partition: u32) -> write_buffer::Result<(i64, i64)> {
+            let produced = self.produced.lock();
+            let end = produced.iter().filter(|(_, p, _)| *p == partition).count();
+            Ok((0, end as i64))
+        }
 
-        if !matches!(got, Error::DatabaseAlreadyExists {..}) {
-            panic!("expected already exists error");
+        async fn offset_for_time(
+            &self,
+            _partition: u32,
+            _time: chrono::DateTime<Utc>,
+        ) -> write_buffer::Result<Option<i64>> {
+            Ok(None)
         }
 
-        Ok(())
+        async fn fetch(
+            &self,
+            partition: u32,
+            offset: i64,
+            max: usize,
+        ) -> write_buffer::Result<Vec<BufferedWrite>> {
+            let produced = self.produced.lock();
+            Ok(produced
+                .iter()
+                .filter(|(_, p, _)| *p == partition)
+                .enumerate()
+                .skip(offset as usize)
+                .take(max)
+                .map(|(offset, (db_name, _, write))| BufferedWrite {
+                    offset: offset as i64,
+                    db_name: db_name.clone(),
+                    write: ReplicatedWrite::from(write.bytes().as_slice()),
+                })
+                .collect())
+        }
     }
 
     #[tokio::test]
-    async fn db_names_sorted() -> Result {
+    async fn write_buffer() -> Result {
         let manager = TestConnectionManager::new();
         let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
         let server = Server::new(manager, store);
         server.set_id(1);
+        server.create_database("foo", DatabaseRules::new()).await?;
 
-        let names = vec!["bar", "baz"];
-
-        for name in &names {
-            server
-                .create_database(*name, DatabaseRules::new())
-                .await
-                .expect("failed to create database");
+        let write_buffer = Arc::new(TestWriteBuffer {
+            partitions: 4,
+            ..Default::default()
+        });
+        server.set_write_buffer(Arc::clone(&write_buffer) as Arc<dyn WriteBuffer>);
+        let lp = (0..20)
+            .map(|i| format!("cpu,host=host{} bar=1 10", i))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let lines = parsed_lines(&lp);
+        server.write_lines("foo", &lines).await?;
+
+        // one write per partition the series are sharded to
+        let mut expected: Vec<_> = lines
+            .iter()
+            .map(|line| write_buffer::partition(line, 4))
+            .collect();
+        expected.sort_unstable();
+        expected.dedup();
+        let produced = write_buffer.produced.lock();
+        let partitions: Vec<_> = produced
+            .iter()
+            .map(|(_, partition, _)| *partition)
+            .collect();
+        assert_eq!(partitions, expected);
+        let sequence = produced[0].2.writer_and_sequence().1;
+        for (db_name, _, write) in produced.iter() {
+            assert_eq!(db_name, "foo");
+            assert!(write.equal_to_writer_and_sequence(1, sequence));
         }
 
-        let db_names_sorted = server.db_names_sorted().await;
-        assert_eq!(names, db_names_sorted);
+        // the write is stored too
+        let db = server.db(&DatabaseName::new("foo").unwrap()).await.unwrap();
+        let partition_key = db.partition_keys()?.pop().unwrap();
+        assert_eq!(db.mutable_buffer_chunks(&partition_key).len(), 1);
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn database_name_validation() -> Result {
-        let manager = TestConnectionManager::new();
+    async fn replay_write_buffer() -> Result {
         let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
-        let server = Server::new(manager, store);
+        let write_buffer = Arc::new(TestWriteBuffer {
+            partitions: 2,
+            ..Default::default()
+        });
+        let lp = (0..20)
+            .map(|i| format!("cpu,host=host{} bar=1 10", i))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let server = Server::new(TestConnectionManager::new(), Arc::clone(&store));
         server.set_id(1);
+        server.create_database("foo", DatabaseRules::new()).await?;
+        server.set_write_buffer(Arc::clone(&write_buffer) as Arc<dyn WriteBuffer>);
+        server.write_lines("foo", &parsed_lines(&lp)).await?;
+        server.write_lines("foo", &parsed_lines(&lp)).await?;
+        // databases keeping their data forever hold the checkpoint back
+        assert_eq!(server.checkpoint_write_buffer().await?, None);
+
+        // a restarted server replays its writes into the mutable buffer
+        let restarted = Server::new(TestConnectionManager::new(), Arc::clone(&store));
+        restarted.set_id(1);
+        restarted.load_database_configs().await?;
+        restarted.set_write_buffer(Arc::clone(&write_buffer) as Arc<dyn WriteBuffer>);
+        let produced = write_buffer.produced.lock().len();
+        assert_eq!(restarted.replay_write_buffer().await?, produced as u64);
+        let replay = restarted.write_buffer_replay();
+        assert_eq!(replay.len(), 2);
+        assert!(replay.iter().all(|partition| partition.remaining() == 0));
+
+        let db = restarted
+            .db(&DatabaseName::new("foo").unwrap())
+            .await
+            .unwrap();
+        let planner = SQLQueryPlanner::default();
+        let executor = restarted.executor();
+        let physical_plan = planner
+            .query(db.as_ref(), "select * from cpu", executor.as_ref())
+            .await
+            .unwrap();
+        let batches = collect(physical_plan).await.unwrap();
+        assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 40);
+
+        // replay is done, and writes of other servers are skipped
+        assert_eq!(restarted.replay_write_buffer().await?, 0);
+        let other = Server::new(TestConnectionManager::new(), Arc::clone(&store));
+        other.set_id(2);
+        other.create_database("foo", DatabaseRules::new()).await?;
+        other.set_write_buffer(Arc::clone(&write_buffer) as Arc<dyn WriteBuffer>);
+        assert_eq!(other.replay_write_buffer().await?, 0);
+
+        // once every database has a retention period, replay starts after
+        // the writes past it
+        server
+            .update_db_rules(&DatabaseName::new("foo").unwrap(), |rules| {
+                rules.retention_period_seconds = Some(3600);
+            })
+            .await?;
+        let checkpoint = server.checkpoint_write_buffer().await?.unwrap();
+        assert_eq!(checkpoint.offsets.values().sum::<i64>(), produced as i64);
+        let restarted = Server::new(TestConnectionManager::new(), Arc::clone(&store));
+        restarted.set_id(1);
+        restarted.load_database_configs().await?;
+        restarted.set_write_buffer(Arc::clone(&write_buffer) as Arc<dyn WriteBuffer>);
+        assert_eq!(restarted.replay_write_buffer().await?, 0);
 
-        let reject = vec![
-            "bananas\t",
-            "bananas\"are\u{0099}\"great",
-            "bananas\nfoster",
-        ];
+        Ok(())
+    }
 
-        for name in reject {
-            let got = server
-                .create_database(name, DatabaseRules::new())
+    #[tokio::test]
+    async fn deletes_survive_restarts_and_reach_peers() -> Result {
+        use crate::cluster::Role;
+
+        let member = |address: &str| MemberState {
+            address: address.to_string(),
+            id: 0,
+            role: Role::Storage,
+            databases: vec![],
+            incarnation: 1,
+            heartbeat: 1,
+        };
+        let count_rows = |db: Arc<Db>, executor: Arc<Executor>| async move {
+            let physical_plan = SQLQueryPlanner::default()
+                .query(db.as_ref(), "select * from cpu", executor.as_ref())
                 .await
-                .unwrap_err();
-            if !matches!(got, Error::InvalidDatabaseName { .. }) {
-                panic!("expected invalid name error");
-            }
-        }
+                .unwrap();
+            let batches = collect(physical_plan).await.unwrap();
+            batches.iter().map(|b| b.num_rows()).sum::<usize>()
+        };
+
+        let mut manager = TestConnectionManager::new();
+        let remote = Arc::new(TestRemoteServer::default());
+        manager
+            .remotes
+            .insert("serverB".to_string(), Arc::clone(&remote));
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let write_buffer = Arc::new(TestWriteBuffer {
+            partitions: 2,
+            ..Default::default()
+        });
+        let server = Server::new(manager, Arc::clone(&store));
+        server.set_id(1);
+        server.set_membership(Membership::new(
+            member("serverA"),
+            vec![],
+            Duration::from_secs(1),
+        ));
+        server.receive_gossip(vec![member("serverB")])?;
+        server.set_write_buffer(Arc::clone(&write_buffer) as Arc<dyn WriteBuffer>);
+        server.create_database("foo", DatabaseRules::new()).await?;
+        let db_name = DatabaseName::new("foo").unwrap();
+
+        let before = "cpu,host=a bar=1 10\ncpu,host=b bar=2 20";
+        server.write_lines("foo", &parsed_lines(before)).await?;
+        let predicate = DeletePredicate::try_new(0, 15, r#"host="a""#).unwrap();
+        server.delete(&db_name, predicate).await?;
+        // written after the delete, so not hidden by it
+        server
+            .write_lines("foo", &parsed_lines("cpu,host=a bar=3 12"))
+            .await?;
+        let db = server.db(&db_name).await.unwrap();
+        assert_eq!(count_rows(db, server.executor()).await, 2);
+
+        // a restarted server applies the delete between the writes it replays
+        let restarted = Server::new(TestConnectionManager::new(), Arc::clone(&store));
+        restarted.set_id(1);
+        restarted.set_write_buffer(Arc::clone(&write_buffer) as Arc<dyn WriteBuffer>);
+        restarted.load_database_configs().await?;
+        restarted.replay_write_buffer().await?;
+        let db = restarted.db(&db_name).await.unwrap();
+        assert_eq!(count_rows(db, restarted.executor()).await, 2);
+
+        // and so does a peer it is sent to, to the data it holds
+        let sent = remote.catalog.lock().clone();
+        let (deletes, others): (Vec<_>, Vec<_>) = sent
+            .into_iter()
+            .partition(|entry| matches!(entry.change, CatalogChange::Delete { .. }));
+        assert_eq!(deletes.len(), 1);
+        let peer = Server::new(
+            TestConnectionManager::new(),
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        );
+        peer.set_id(2);
+        peer.set_membership(Membership::new(
+            member("serverB"),
+            vec![],
+            Duration::from_secs(1),
+        ));
+        peer.sync_catalog(others).await?;
+        peer.write_lines("foo", &parsed_lines(before)).await?;
+        peer.sync_catalog(deletes.clone()).await?;
+        let db = peer.db(&db_name).await.unwrap();
+        assert_eq!(count_rows(Arc::clone(&db), peer.executor()).await, 1);
+
+        // only once, however often it is received
+        peer.sync_catalog(deletes).await?;
+        assert_eq!(db.delete_records().len(), 1);
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn writes_local() -> Result {
-        let manager = TestConnectionManager::new();
+    async fn gossip() -> Result {
+        use crate::cluster::{Role, Status};
+
+        let member = |address: &str, databases: Vec<String>| MemberState {
+            address: address.to_string(),
+            id: 0,
+            role: Role::Storage,
+            databases,
+            incarnation: 1,
+            heartbeat: 1,
+        };
+
+        let mut manager = TestConnectionManager::new();
+        let seed = Arc::new(TestRemoteServer {
+            members: vec![member("serverA", vec![]), member("serverB", vec![])],
+            ..Default::default()
+        });
+        manager
+            .remotes
+            .insert("serverA".to_string(), Arc::clone(&seed));
         let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
         let server = Server::new(manager, store);
         server.set_id(1);
         server.create_database("foo", DatabaseRules::new()).await?;
 
-        let line = "cpu bar=1 10";
-        let lines: Vec<_> = parse_lines(line).map(|l| l.unwrap()).collect();
-        server.write_lines("foo", &lines).await.unwrap();
+        assert!(matches!(
+            server.gossip_round().await,
+            Err(Error::ClusterDisabled)
+        ));
+        server.set_membership(Membership::new(
+            member("serverL", vec![]),
+            vec!["serverA".to_string()],
+            Duration::from_secs(1),
+        ));
+
+        server.gossip_round().await?;
+        let gossiped = seed.gossiped.lock().clone();
+        assert_eq!(gossiped.len(), 1);
+        assert_eq!(gossiped[0].address, "serverL");
+        assert_eq!(gossiped[0].databases, vec!["foo".to_string()]);
+        assert_eq!(gossiped[0].heartbeat, 2);
+
+        let members = server.membership().unwrap().members();
+        let addresses: Vec<_> = members.iter().map(|m| m.state.address.as_str()).collect();
+        assert_eq!(addresses, vec!["serverA", "serverB", "serverL"]);
+        assert!(members.iter().all(|m| m.status == Status::Alive));
+
+        // serverB can't be reached, which doesn't fail the round
+        server.gossip_round().await?;
+
+        let view = server.receive_gossip(vec![member("serverC", vec![])])?;
+        assert_eq!(view.len(), 4);
 
-        let db_name = DatabaseName::new("foo").unwrap();
-        let db = server.db(&db_name).await.unwrap();
+        Ok(())
+    }
 
-        let planner = SQLQueryPlanner::default();
-        let executor = server.executor();
-        let physical_plan = planner
-            .query(db.as_ref(), "select * from cpu", executor.as_ref())
-            .await
+    #[tokio::test]
+    async fn replicate_catalog() -> Result {
+        use crate::cluster::Role;
+
+        let member = |address: &str| MemberState {
+            address: address.to_string(),
+            id: 0,
+            role: Role::Storage,
+            databases: vec![],
+            incarnation: 1,
+            heartbeat: 1,
+        };
+
+        let mut manager = TestConnectionManager::new();
+        let remote = Arc::new(TestRemoteServer::default());
+        manager
+            .remotes
+            .insert("serverB".to_string(), Arc::clone(&remote));
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let server = Server::new(manager, Arc::clone(&store));
+        server.set_id(1);
+        server.set_membership(Membership::new(
+            member("serverA"),
+            vec![],
+            Duration::from_secs(1),
+        ));
+        server.receive_gossip(vec![member("serverB")])?;
+
+        // changes are sent to the members as they are made
+        let org = server.create_org("myorg").await?;
+        server
+            .create_database("myorg_mybucket", DatabaseRules::new())
+            .await?;
+        let sent = remote.catalog.lock().clone();
+        let counters: Vec<_> = sent.iter().map(|entry| entry.version.counter).collect();
+        assert_eq!(counters, vec![1, 2]);
+
+        // another member applies them
+        let peer = Server::new(
+            TestConnectionManager::new(),
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        );
+        peer.set_id(2);
+        assert!(matches!(
+            peer.sync_catalog(sent.clone()).await,
+            Err(Error::ClusterDisabled)
+        ));
+        peer.set_membership(Membership::new(
+            member("serverB"),
+            vec![],
+            Duration::from_secs(1),
+        ));
+        assert_eq!(peer.sync_catalog(sent.clone()).await?.len(), 2);
+        assert_eq!(peer.orgs().await, vec![org]);
+        let db_name = DatabaseName::new("myorg_mybucket").unwrap();
+        assert!(peer.db(&db_name).await.is_some());
+
+        // and its own changes supersede them
+        peer.delete_database(&db_name).await?;
+        let entries = peer.sync_catalog(vec![]).await?;
+        server.sync_catalog(entries).await?;
+        assert!(server.db(&db_name).await.is_none());
+        server.sync_catalog(sent).await?;
+        assert!(server.db(&db_name).await.is_none());
+
+        // the log survives restarts, so later changes stay more recent
+        let restarted = Server::new(TestConnectionManager::new(), store);
+        restarted.set_id(1);
+        restarted.load_database_configs().await?;
+        restarted.set_membership(Membership::new(
+            member("serverA"),
+            vec![],
+            Duration::from_secs(1),
+        ));
+        restarted.create_org("otherorg").await?;
+        let entries = restarted.sync_catalog(vec![]).await?;
+        let other = entries
+            .iter()
+            .find(|entry| {
+                matches!(&entry.change, CatalogChange::Org { org } if org.name == "otherorg")
+            })
             .unwrap();
+        assert_eq!(other.version.counter, 4);
 
-        let batches = collect(physical_plan).await.unwrap();
-        let expected = vec![
-            "+-----+------+",
-            "| bar | time |",
-            "+-----+------+",
-            "| 1   | 10   |",
-            "+-----+------+",
-        ];
-        assert_table_eq!(expected, &batches);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn replicate_orgs_and_tokens() -> Result {
+        use crate::cluster::Role;
+        use std::num::NonZeroU32;
+
+        let member = |address: &str| MemberState {
+            address: address.to_string(),
+            id: 0,
+            role: Role::Storage,
+            databases: vec![],
+            incarnation: 1,
+            heartbeat: 1,
+        };
+        let request = || TokenRequest {
+            scopes: vec![tokens::Scope::Read],
+            ..Default::default()
+        };
+
+        let mut servers = vec![];
+        for (id, address) in &[(1, "serverA"), (2, "serverB")] {
+            let server = Server::new(
+                TestConnectionManager::new(),
+                Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+            );
+            server.set_id(*id);
+            server.set_membership(Membership::new(
+                member(address),
+                vec![],
+                Duration::from_secs(1),
+            ));
+            servers.push(server);
+        }
+        let (server, peer) = (&servers[0], &servers[1]);
+        let exchange = || async move {
+            let entries = peer
+                .sync_catalog(server.sync_catalog(vec![]).await?)
+                .await?;
+            server.sync_catalog(entries).await
+        };
+
+        // tokens created concurrently on different members are both kept
+        let (token, secret) = server.create_token(request()).await?;
+        let (other, other_secret) = peer.create_token(request()).await?;
+        assert_ne!(token.id, other.id);
+        exchange().await?;
+        for s in &servers {
+            assert_eq!(s.authenticate_token(&secret), Some(token.clone()));
+            assert_eq!(s.authenticate_token(&other_secret), Some(other.clone()));
+        }
+
+        // a revoked token isn't brought back by a concurrent change
+        server.delete_token(&token.id).await?;
+        let (created, created_secret) = peer.create_token(request()).await?;
+        exchange().await?;
+        for s in &servers {
+            assert_eq!(s.authenticate_token(&secret), None);
+            assert_eq!(s.tokens(), vec![other.clone(), created.clone()]);
+            assert_eq!(s.authenticate_token(&created_secret), Some(created.clone()));
+        }
+
+        // nor is a deleted org
+        let org = server.create_org("company").await?;
+        exchange().await?;
+        assert_eq!(peer.orgs().await, vec![org.clone()]);
+        server.delete_org(&org.id).await?;
+        peer.set_org_rate_limits(
+            &org.id,
+            RateLimits {
+                concurrent_queries: NonZeroU32::new(1),
+                ..Default::default()
+            },
+        )
+        .await?;
+        exchange().await?;
+        for s in &servers {
+            assert!(s.orgs().await.is_empty());
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn quorum_lost() -> Result {
+        use crate::cluster::Role;
+
+        let member = |address: &str| MemberState {
+            address: address.to_string(),
+            id: 0,
+            role: Role::Storage,
+            databases: vec![],
+            incarnation: 1,
+            heartbeat: 1,
+        };
+        let lines: Vec<_> = parse_lines("cpu bar=1 10").map(|l| l.unwrap()).collect();
+
+        let server = Server::new(
+            TestConnectionManager::new(),
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        );
+        server.set_id(1);
+        server.create_database("foo", DatabaseRules::new()).await?;
+        server.set_quorum(Quorum::new(3, OnQuorumLoss::RejectWrites));
+        assert_eq!(server.quorum_status(), None);
+
+        server.set_membership(Membership::new(
+            member("serverA"),
+            vec![],
+            Duration::from_secs(1),
+        ));
+        let status = server.quorum_status().unwrap();
+        assert_eq!((status.alive, status.required), (1, 2));
+        assert!(matches!(
+            server.write_lines("foo", &lines).await,
+            Err(Error::QuorumLost {
+                alive: 1,
+                required: 2
+            })
+        ));
+        assert!(matches!(
+            server.create_database("bar", DatabaseRules::new()).await,
+            Err(Error::QuorumLost { .. })
+        ));
+        assert!(matches!(
+            server.create_org("myorg").await,
+            Err(Error::QuorumLost { .. })
+        ));
+
+        // writes are accepted again once the quorum is regained
+        server.receive_gossip(vec![member("serverB")])?;
+        assert!(!server.quorum_status().unwrap().lost());
+        server.write_lines("foo", &lines).await?;
+
+        // or throughout, only warning
+        server.set_quorum(Quorum::new(5, OnQuorumLoss::Warn));
+        assert!(server.quorum_status().unwrap().lost());
+        server.write_lines("foo", &lines).await?;
 
         Ok(())
     }
@@ -950,6 +5947,151 @@ partition_key:
         assert_eq!(segment.writes[0].to_string(), write);
     }
 
+    #[tokio::test]
+    async fn open_wal_segments_persisted_on_request() {
+        let manager = TestConnectionManager::new();
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+
+        let server = Server::new(manager, Arc::clone(&store));
+        server.set_id(1);
+        let db_name = "my_db";
+        let rules = DatabaseRules {
+            wal_buffer_config: Some(WalBufferConfig {
+                buffer_size: 4000,
+                segment_size: 2000,
+                buffer_rollover: WalBufferRollover::ReturnError,
+                store_segments: true,
+                close_segment_after: None,
+            }),
+            ..Default::default()
+        };
+        server.create_database(db_name, rules).await.unwrap();
+
+        let lines = parsed_lines("disk,host=a used=10.1 12");
+        server.write_lines(db_name, &lines).await.unwrap();
+
+        // the segment is not full, so nothing has been persisted yet
+        let mut path = store.new_path();
+        path.push_all_dirs(&["1", "my_db", "wal", "000", "000"]);
+        path.set_file_name("001.segment");
+        assert!(store.get(&path).await.is_err());
+
+        server.persist_open_wal_segments().await.unwrap();
+
+        let data = store
+            .get(&path)
+            .await
+            .unwrap()
+            .map_ok(|b| bytes::BytesMut::from(&b[..]))
+            .try_concat()
+            .await
+            .unwrap();
+
+        let segment = Segment::from_file_bytes(&data).unwrap();
+        assert_eq!(segment.writes.len(), 1);
+
+        // calling again with no new writes is a no-op
+        server.persist_open_wal_segments().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn open_wal_segments_persisted_despite_failures() {
+        use object_store::secrets::EnvSecrets;
+
+        let manager = TestConnectionManager::new();
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let server = Server::new(manager, Arc::clone(&store));
+        server.set_id(1);
+        // the encryption key isn't set, so every segment fails to persist
+        server.set_encryption(Some(Arc::new(Encryption::new(
+            Arc::new(EnvSecrets),
+            "server_test_missing_encryption_keys",
+        ))));
+        for db_name in &["db_a", "db_b"] {
+            let rules = DatabaseRules {
+                wal_buffer_config: Some(WalBufferConfig {
+                    buffer_size: 4000,
+                    segment_size: 2000,
+                    buffer_rollover: WalBufferRollover::ReturnError,
+                    store_segments: true,
+                    close_segment_after: None,
+                }),
+                ..Default::default()
+            };
+            server.create_database(*db_name, rules).await.unwrap();
+            let lines = parsed_lines("disk,host=a used=10.1 12");
+            server.write_lines(*db_name, &lines).await.unwrap();
+        }
+
+        // the failure of the first database doesn't stop the second one
+        // from being persisted
+        let err = server.persist_open_wal_segments().await.unwrap_err();
+        match err {
+            Error::PersistingOpenSegments { failed, errors } => {
+                assert_eq!(failed, 2);
+                assert!(errors.starts_with("db_a: "), "{}", errors);
+                assert!(errors.contains(", db_b: "), "{}", errors);
+            }
+            e => panic!("unexpected error: {}", e),
+        }
+    }
+
+    #[tokio::test]
+    async fn wal_segments_encrypted() {
+        use object_store::secrets::EnvSecrets;
+
+        std::env::set_var(
+            "SERVER_TEST_ENCRYPTION_KEYS",
+            "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f",
+        );
+        let encryption = Arc::new(Encryption::new(
+            Arc::new(EnvSecrets),
+            "server_test_encryption_keys",
+        ));
+
+        let manager = TestConnectionManager::new();
+        let store = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        let server = Server::new(manager, Arc::clone(&store));
+        server.set_id(1);
+        server.set_encryption(Some(Arc::clone(&encryption)));
+        let db_name = "my_db";
+        let rules = DatabaseRules {
+            wal_buffer_config: Some(WalBufferConfig {
+                buffer_size: 4000,
+                segment_size: 2000,
+                buffer_rollover: WalBufferRollover::ReturnError,
+                store_segments: true,
+                close_segment_after: None,
+            }),
+            ..Default::default()
+        };
+        server.create_database(db_name, rules).await.unwrap();
+
+        let lines = parsed_lines("disk,host=a used=10.1 12");
+        server.write_lines(db_name, &lines).await.unwrap();
+        server.persist_open_wal_segments().await.unwrap();
+
+        let mut path = store.new_path();
+        path.push_all_dirs(&["1", "my_db", "wal", "000", "000"]);
+        path.set_file_name("001.segment");
+        let data = store
+            .get(&path)
+            .await
+            .unwrap()
+            .map_ok(|b| bytes::BytesMut::from(&b[..]))
+            .try_concat()
+            .await
+            .unwrap();
+
+        assert!(encryption::is_encrypted(&data));
+        assert!(Segment::from_file_bytes(&data).is_err());
+        let data = encryption::decrypt_if_encrypted(Some(&encryption), data.freeze())
+            .await
+            .unwrap();
+        let segment = Segment::from_file_bytes(&data).unwrap();
+        assert_eq!(segment.writes.len(), 1);
+    }
+
     #[derive(Snafu, Debug, Clone)]
     enum TestClusterError {
         #[snafu(display("Test cluster error:  {}", message))]
@@ -975,13 +6117,28 @@ partition_key:
         type RemoteServer = TestRemoteServer;
 
         async fn remote_server(&self, id: &str) -> Result<Arc<TestRemoteServer>, Self::Error> {
-            Ok(Arc::clone(&self.remotes.get(id).unwrap()))
+            self.remotes.get(id).map(Arc::clone).context(General {
+                message: format!("unknown server {}", id),
+            })
         }
     }
 
     #[derive(Debug, Default)]
     struct TestRemoteServer {
         writes: Mutex<BTreeMap<String, Vec<ReplicatedWrite>>>,
+        lines: Mutex<BTreeMap<String, Vec<String>>>,
+        /// The member states gossiped to the server, and those it knows
+        gossiped: Mutex<Vec<MemberState>>,
+        members: Vec<MemberState>,
+        digests: Mutex<Vec<WriteDigest>>,
+        /// Whether the server fails to store replicated writes
+        down: AtomicBool,
+        /// Whether the server never answers replicated writes
+        stalled: AtomicBool,
+        /// The catalog entries synced with the server
+        catalog: Mutex<Vec<CatalogEntry>>,
+        /// The shards handed off by the server, and their targets
+        handed_off: Mutex<Vec<(ShardRange, String)>>,
     }
 
     #[async_trait]
@@ -993,12 +6150,56 @@ partition_key:
             db: &str,
             replicated_write: &ReplicatedWrite,
         ) -> Result<(), Self::Error> {
+            ensure!(
+                !self.down.load(Ordering::SeqCst),
+                General {
+                    message: "server down"
+                }
+            );
+            if self.stalled.load(Ordering::SeqCst) {
+                futures::future::pending::<()>().await;
+            }
             let mut writes = self.writes.lock();
             let entries = writes.entry(db.to_string()).or_insert_with(Vec::new);
             entries.push(replicated_write.clone());
 
             Ok(())
         }
+
+        async fn write_lines(&self, db: &str, lp: &str) -> Result<(), Self::Error> {
+            let mut lines = self.lines.lock();
+            let entries = lines.entry(db.to_string()).or_insert_with(Vec::new);
+            entries.push(lp.to_string());
+
+            Ok(())
+        }
+
+        async fn gossip(&self, states: Vec<MemberState>) -> Result<Vec<MemberState>, Self::Error> {
+            self.gossiped.lock().extend(states);
+            Ok(self.members.clone())
+        }
+
+        async fn write_digests(&self, _db: &str) -> Result<Vec<WriteDigest>, Self::Error> {
+            Ok(self.digests.lock().clone())
+        }
+
+        async fn sync_catalog(
+            &self,
+            entries: Vec<CatalogEntry>,
+        ) -> Result<Vec<CatalogEntry>, Self::Error> {
+            let mut catalog = self.catalog.lock();
+            catalog.extend(entries);
+            Ok(catalog.clone())
+        }
+
+        async fn hand_off_shard(
+            &self,
+            range: ShardRange,
+            target: &str,
+        ) -> Result<u64, Self::Error> {
+            self.handed_off.lock().push((range, target.to_string()));
+            Ok(1)
+        }
     }
 
     fn parsed_lines(lp: &str) -> Vec<ParsedLine<'_>> {
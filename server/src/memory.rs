@@ -0,0 +1,248 @@
+//! Accounting of the memory held by the server against a limit for the
+//! whole process.
+//!
+//! The memory tracker attributes the bytes the server holds to the subsystem
+//! holding them. The mutable buffer, the read buffer and the WAL buffers of
+//! the databases, and the writes kept for hinted handoff, are measured from
+//! their sizes periodically in the background, as is the memory of the
+//! queries running, counted by their own trackers (see
+//! [`query::exec::memory`]). Writes and queries are checked against the
+//! last measure, as measuring goes through all the data. The caches account for
+//! what they hold through a reservation, which they resize as they change and
+//! which gives the memory back when dropped.
+//!
+//! Once the usage reaches `HIGH_WATERMARK_PERCENT` of the limit, the server
+//! is under memory pressure: it compacts the mutable buffer of its databases
+//! into the read buffer, which holds the same data in far less memory, and
+//! the caches drop what they hold and stop caching. Writes and queries are
+//! rejected while the usage is at or over the limit. The sizes are
+//! estimates, so the limit should leave room for the memory not accounted
+//! for, such as that of the requests being handled.
+use snafu::Snafu;
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display(
+        "server is at its memory limit of {} bytes, using {} bytes",
+        limit,
+        used
+    ))]
+    LimitReached { limit: u64, used: u64 },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The percentage of the limit from which the server is under memory
+/// pressure
+pub const HIGH_WATERMARK_PERCENT: u64 = 90;
+
+/// What holds the memory accounted for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Subsystem {
+    MutableBuffer,
+    ReadBuffer,
+    WalBuffers,
+    HintedWrites,
+    Caches,
+    Queries,
+}
+
+impl Subsystem {
+    pub const ALL: [Self; 6] = [
+        Self::MutableBuffer,
+        Self::ReadBuffer,
+        Self::WalBuffers,
+        Self::HintedWrites,
+        Self::Caches,
+        Self::Queries,
+    ];
+}
+
+impl fmt::Display for Subsystem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MutableBuffer => write!(f, "mutable_buffer"),
+            Self::ReadBuffer => write!(f, "read_buffer"),
+            Self::WalBuffers => write!(f, "wal_buffers"),
+            Self::HintedWrites => write!(f, "hinted_writes"),
+            Self::Caches => write!(f, "caches"),
+            Self::Queries => write!(f, "queries"),
+        }
+    }
+}
+
+/// Tracks the memory of each subsystem, as described in the module docs
+#[derive(Debug, Default)]
+pub struct MemoryTracker {
+    /// The number of bytes the process may use, unlimited if 0
+    limit: AtomicU64,
+    /// The bytes held by each subsystem, in the order of `Subsystem::ALL`
+    used: [AtomicU64; 6],
+    /// The number of writes and queries rejected at the limit
+    rejected: AtomicU64,
+}
+
+impl MemoryTracker {
+    pub fn new(limit: Option<u64>) -> Self {
+        let tracker = Self::default();
+        tracker.set_limit(limit);
+        tracker
+    }
+
+    /// Limits the memory of the process to `limit` bytes, or removes the
+    /// limit if None
+    pub fn set_limit(&self, limit: Option<u64>) {
+        self.limit.store(limit.unwrap_or(0), Ordering::Relaxed);
+    }
+
+    /// The number of bytes the process may use, if limited
+    pub fn limit(&self) -> Option<u64> {
+        match self.limit.load(Ordering::Relaxed) {
+            0 => None,
+            limit => Some(limit),
+        }
+    }
+
+    /// Records that `subsystem` was measured to hold `bytes`. Only for the
+    /// subsystems measured by the server rather than holding reservations.
+    pub fn set(&self, subsystem: Subsystem, bytes: u64) {
+        self.counter(subsystem).store(bytes, Ordering::Relaxed);
+    }
+
+    /// The number of bytes held by `subsystem`
+    pub fn used(&self, subsystem: Subsystem) -> u64 {
+        self.counter(subsystem).load(Ordering::Relaxed)
+    }
+
+    /// The number of bytes held by all subsystems
+    pub fn total(&self) -> u64 {
+        Subsystem::ALL.iter().map(|&s| self.used(s)).sum()
+    }
+
+    /// The number of bytes held by each subsystem
+    pub fn breakdown(&self) -> Vec<(Subsystem, u64)> {
+        Subsystem::ALL.iter().map(|&s| (s, self.used(s))).collect()
+    }
+
+    /// Whether the usage is at or over the high watermark of the limit
+    pub fn under_pressure(&self) -> bool {
+        self.limit().map_or(false, |limit| {
+            self.total() >= limit / 100 * HIGH_WATERMARK_PERCENT
+        })
+    }
+
+    /// Returns an error if the usage is at or over the limit, counting the
+    /// work it rejects
+    pub fn check(&self) -> Result<()> {
+        let used = self.total();
+        match self.limit() {
+            Some(limit) if used >= limit => {
+                self.rejected.fetch_add(1, Ordering::Relaxed);
+                LimitReached { limit, used }.fail()
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The number of writes and queries rejected at the limit since the
+    /// server started
+    pub fn rejected(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+
+    /// Returns an empty reservation of memory held by `subsystem`
+    pub fn reservation(self: &Arc<Self>, subsystem: Subsystem) -> MemoryReservation {
+        MemoryReservation {
+            tracker: Arc::clone(self),
+            subsystem,
+            bytes: 0,
+        }
+    }
+
+    fn counter(&self, subsystem: Subsystem) -> &AtomicU64 {
+        let index = Subsystem::ALL
+            .iter()
+            .position(|&s| s == subsystem)
+            .expect("subsystem in Subsystem::ALL");
+        &self.used[index]
+    }
+}
+
+/// Memory held by a subsystem, counted by a `MemoryTracker` until the
+/// reservation is dropped
+#[derive(Debug)]
+pub struct MemoryReservation {
+    tracker: Arc<MemoryTracker>,
+    subsystem: Subsystem,
+    bytes: u64,
+}
+
+impl MemoryReservation {
+    /// Records that the subsystem now holds `bytes` through this
+    /// reservation
+    pub fn resize(&mut self, bytes: u64) {
+        let counter = self.tracker.counter(self.subsystem);
+        if bytes > self.bytes {
+            counter.fetch_add(bytes - self.bytes, Ordering::Relaxed);
+        } else {
+            counter.fetch_sub(self.bytes - bytes, Ordering::Relaxed);
+        }
+        self.bytes = bytes;
+    }
+
+    /// The tracker counting the reservation, e.g. to check whether the
+    /// server is under memory pressure
+    pub fn tracker(&self) -> &MemoryTracker {
+        &self.tracker
+    }
+}
+
+impl Drop for MemoryReservation {
+    fn drop(&mut self) {
+        self.resize(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limit() {
+        let tracker = Arc::new(MemoryTracker::new(Some(1000)));
+        tracker.set(Subsystem::MutableBuffer, 800);
+        tracker.check().unwrap();
+        assert!(!tracker.under_pressure());
+
+        let mut reservation = tracker.reservation(Subsystem::Caches);
+        reservation.resize(150);
+        assert!(tracker.under_pressure());
+        tracker.check().unwrap();
+
+        reservation.resize(200);
+        let e = tracker.check().unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "server is at its memory limit of 1000 bytes, using 1000 bytes"
+        );
+        assert_eq!(tracker.rejected(), 1);
+
+        // dropping the reservation gives the memory back
+        drop(reservation);
+        assert_eq!(tracker.used(Subsystem::Caches), 0);
+        assert_eq!(tracker.total(), 800);
+        assert_eq!(tracker.breakdown()[0], (Subsystem::MutableBuffer, 800));
+
+        tracker.set_limit(None);
+        tracker.set(Subsystem::MutableBuffer, u64::MAX / 2);
+        tracker.check().unwrap();
+        assert!(!tracker.under_pressure());
+    }
+}
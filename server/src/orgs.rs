@@ -0,0 +1,173 @@
+//! This module contains the catalog of organizations known to the server.
+//!
+//! Buckets are mapped to databases by org name, with
+//! [`data_types::names::org_and_bucket_to_database`], so the catalog only
+//! records the id, name and rate limits of each org. Renaming an org renames
+//! the databases of its buckets with it. The catalog is persisted as a single
+//! file in the root of the server's object storage.
+//!
+//! Orgs are replicated to the other members of a cluster one by one, so ids
+//! start with the writer id of the server that created the org, to be unique
+//! across the cluster. Orgs are kept sorted by id, so that if orgs with the
+//! same name were created concurrently on different members, every member
+//! looks up the same one by name.
+use crate::{rate_limits::RateLimits, Error, OrgNotFound, Result};
+use serde::{Deserialize, Serialize};
+use snafu::OptionExt;
+
+pub(crate) const ORGS_FILE_NAME: &str = "orgs.json";
+
+/// An organization, as in the InfluxDB 2.x API
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Org {
+    /// 16 character hex id: the writer id of the server that created the
+    /// org, followed by a counter of that server
+    pub id: String,
+    pub name: String,
+    /// How often requests may write to and read from the org's buckets
+    #[serde(default, skip_serializing_if = "RateLimits::is_unlimited")]
+    pub limits: RateLimits,
+}
+
+/// The persisted form of the org catalog
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) struct OrgCatalog {
+    orgs: Vec<Org>,
+    /// The numeric value of the id assigned to the next org created
+    next_id: u64,
+}
+
+impl OrgCatalog {
+    pub(crate) fn orgs(&self) -> &[Org] {
+        &self.orgs
+    }
+
+    pub(crate) fn get(&self, id: &str) -> Option<&Org> {
+        self.orgs.iter().find(|org| org.id == id)
+    }
+
+    /// Returns the org named `name`, if any
+    pub(crate) fn find(&self, name: &str) -> Option<&Org> {
+        self.orgs.iter().find(|org| org.name == name)
+    }
+
+    /// Adds an org named `name`, created on the server with writer id
+    /// `writer_id`, returning it
+    pub(crate) fn create(&mut self, writer_id: u32, name: String) -> Result<Org> {
+        if self.orgs.iter().any(|org| org.name == name) {
+            return Err(Error::OrgAlreadyExists { name });
+        }
+
+        // ids start at 1 so that no org has the all zero id
+        self.next_id = self.next_id.max(1);
+        let org = Org {
+            id: format!("{:08x}{:08x}", writer_id, self.next_id),
+            name,
+            limits: RateLimits::default(),
+        };
+        self.next_id += 1;
+
+        self.upsert(org.clone());
+        Ok(org)
+    }
+
+    /// Adds `org`, or replaces the org with its id
+    pub(crate) fn upsert(&mut self, org: Org) {
+        match self.orgs.binary_search_by(|o| o.id.cmp(&org.id)) {
+            Ok(index) => self.orgs[index] = org,
+            Err(index) => self.orgs.insert(index, org),
+        }
+    }
+
+    /// Renames the org with id `id` to `name`, returning the renamed org
+    pub(crate) fn rename(&mut self, id: &str, name: String) -> Result<Org> {
+        if self.orgs.iter().any(|org| org.name == name && org.id != id) {
+            return Err(Error::OrgAlreadyExists { name });
+        }
+
+        let org = self
+            .orgs
+            .iter_mut()
+            .find(|org| org.id == id)
+            .context(OrgNotFound { id })?;
+        org.name = name;
+        Ok(org.clone())
+    }
+
+    /// Replaces the rate limits of the org with id `id`, returning the
+    /// updated org
+    pub(crate) fn set_limits(&mut self, id: &str, limits: RateLimits) -> Result<Org> {
+        let org = self
+            .orgs
+            .iter_mut()
+            .find(|org| org.id == id)
+            .context(OrgNotFound { id })?;
+        org.limits = limits;
+        Ok(org.clone())
+    }
+
+    /// Removes the org with id `id`, returning it
+    pub(crate) fn remove(&mut self, id: &str) -> Result<Org> {
+        let index = self
+            .orgs
+            .iter()
+            .position(|org| org.id == id)
+            .context(OrgNotFound { id })?;
+        Ok(self.orgs.remove(index))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn create_rename_remove() {
+        let mut catalog = OrgCatalog::default();
+
+        let org = catalog.create(1, "company".to_string()).unwrap();
+        assert_eq!(org.id, "0000000100000001");
+        let err = catalog.create(1, "company".to_string()).unwrap_err();
+        assert!(matches!(err, Error::OrgAlreadyExists { .. }));
+        let other = catalog.create(1, "other".to_string()).unwrap();
+        assert_eq!(other.id, "0000000100000002");
+        assert_eq!(catalog.find("other"), Some(&other));
+
+        let renamed = catalog.rename(&org.id, "renamed".to_string()).unwrap();
+        assert_eq!(renamed.name, "renamed");
+        assert_eq!(catalog.get(&org.id), Some(&renamed));
+        let limits = RateLimits {
+            concurrent_queries: std::num::NonZeroU32::new(4),
+            ..Default::default()
+        };
+        let renamed = catalog.set_limits(&org.id, limits).unwrap();
+        assert_eq!(renamed.limits, limits);
+        let err = catalog.set_limits("ffffffffffffffff", limits).unwrap_err();
+        assert!(matches!(err, Error::OrgNotFound { .. }));
+        let err = catalog.rename(&org.id, "other".to_string()).unwrap_err();
+        assert!(matches!(err, Error::OrgAlreadyExists { .. }));
+        let err = catalog
+            .rename("ffffffffffffffff", "new".to_string())
+            .unwrap_err();
+        assert!(matches!(err, Error::OrgNotFound { .. }));
+
+        assert_eq!(catalog.remove(&org.id).unwrap(), renamed);
+        let err = catalog.remove(&org.id).unwrap_err();
+        assert!(matches!(err, Error::OrgNotFound { .. }));
+        assert_eq!(catalog.orgs(), &[other]);
+
+        // ids are not reused
+        let org = catalog.create(1, "company".to_string()).unwrap();
+        assert_eq!(org.id, "0000000100000003");
+
+        // orgs created on other servers are kept sorted by id
+        let replicated = Org {
+            id: "0000000200000001".to_string(),
+            name: "company".to_string(),
+            limits: RateLimits::default(),
+        };
+        catalog.upsert(replicated.clone());
+        assert_eq!(catalog.orgs(), &[other.clone(), org.clone(), replicated]);
+        assert_eq!(catalog.find("company"), Some(&org));
+    }
+}
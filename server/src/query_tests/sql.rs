@@ -5,7 +5,9 @@
 
 use super::scenarios::*;
 use arrow_deps::{
-    arrow::record_batch::RecordBatch, assert_table_eq, datafusion::physical_plan::collect,
+    arrow::{record_batch::RecordBatch, util::pretty::pretty_format_batches},
+    assert_table_eq,
+    datafusion::physical_plan::collect,
 };
 use query::{exec::Executor, frontend::sql::SQLQueryPlanner};
 
@@ -161,3 +163,54 @@ async fn sql_select_with_schema_merge_subset() {
         &expected
     );
 }
+
+#[tokio::test]
+async fn sql_select_with_pruned_chunk() {
+    // the chunk with the first rows ends before 350, so it is skipped
+    let expected = vec![
+        "+----------+------+-------+------+------+",
+        "| borough  | city | state | temp | time |",
+        "+----------+------+-------+------+------+",
+        "|          |      | NY    | 60.8 | 400  |",
+        "|          | NYC  | NY    | 61   | 500  |",
+        "| Brooklyn | NYC  | NY    | 61   | 600  |",
+        "+----------+------+-------+------+------+",
+    ];
+    run_sql_test_case!(
+        TwoMeasurementsManyNulls {},
+        "SELECT * from o2 where time > 350 order by time",
+        &expected
+    );
+}
+
+#[tokio::test]
+async fn sql_explain_pruned_chunks() {
+    let sql = "EXPLAIN VERBOSE SELECT * from o2 where time > 350";
+    for scenario in (TwoMeasurementsManyNulls {}).make().await {
+        let DBScenario {
+            scenario_name, db, ..
+        } = scenario;
+        println!("Running scenario '{}'", scenario_name);
+        let planner = SQLQueryPlanner::new();
+        let executor = Executor::new();
+
+        let physical_plan = planner
+            .query(&db, sql, &executor)
+            .await
+            .expect("built plan successfully");
+        let results: Vec<RecordBatch> = collect(physical_plan).await.expect("Running plan");
+        let explain = pretty_format_batches(&results).unwrap();
+
+        // all the data is in one chunk in the first scenario
+        let chunks_pruned = if scenario_name.contains("single") {
+            0
+        } else {
+            1
+        };
+        assert!(
+            explain.contains(&format!("chunks_pruned: {}", chunks_pruned)),
+            "{}",
+            explain
+        );
+    }
+}
@@ -0,0 +1,228 @@
+//! This module contains the quotas limiting how many series and how many
+//! bytes each org may store.
+//!
+//! Usage is measured over the buckets of an org in memory: the series in
+//! each partition of the mutable buffer, summed over partitions, and the
+//! size of the mutable buffer and read buffer. Each write is checked
+//! against the usage before it, so a write may take an org past its hard
+//! limit, but the next one is rejected. Crossing a soft limit is logged once
+//! until the usage drops below it again.
+use serde::Serialize;
+use std::{collections::HashSet, fmt};
+
+/// A soft and a hard limit, either of which may be unset
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Limit {
+    pub soft: Option<u64>,
+    pub hard: Option<u64>,
+}
+
+/// The quotas applied to each org
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Quotas {
+    pub series: Limit,
+    pub bytes: Limit,
+}
+
+impl Quotas {
+    /// Whether any limit is set, i.e. usage needs to be measured on writes
+    pub fn enabled(&self) -> bool {
+        *self != Self::default()
+    }
+
+    fn limit(&self, resource: Resource) -> Limit {
+        match resource {
+            Resource::Series => self.series,
+            Resource::Bytes => self.bytes,
+        }
+    }
+}
+
+/// What the quotas limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Resource {
+    Series,
+    Bytes,
+}
+
+impl Resource {
+    const ALL: [Self; 2] = [Self::Series, Self::Bytes];
+}
+
+impl fmt::Display for Resource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Series => write!(f, "series"),
+            Self::Bytes => write!(f, "bytes"),
+        }
+    }
+}
+
+/// The series and bytes stored by a bucket or an org
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Usage {
+    pub series: u64,
+    pub bytes: u64,
+}
+
+impl Usage {
+    fn get(&self, resource: Resource) -> u64 {
+        match resource {
+            Resource::Series => self.series,
+            Resource::Bytes => self.bytes,
+        }
+    }
+}
+
+impl std::ops::AddAssign for Usage {
+    fn add_assign(&mut self, other: Self) {
+        self.series += other.series;
+        self.bytes += other.bytes;
+    }
+}
+
+/// The usage of an org and of each of its buckets
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct OrgUsage {
+    #[serde(flatten)]
+    pub total: Usage,
+    pub buckets: Vec<BucketUsage>,
+}
+
+/// The usage of a bucket
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BucketUsage {
+    pub bucket: String,
+    #[serde(flatten)]
+    pub usage: Usage,
+}
+
+/// The outcome of checking the usage of an org against the quotas
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Check {
+    /// Writes may go ahead
+    Allowed,
+    /// Writes must be rejected as `resource` is at or over its hard limit
+    Exceeded {
+        resource: Resource,
+        usage: u64,
+        limit: u64,
+    },
+}
+
+/// Tracks which orgs are over a soft limit, so that crossing it is only
+/// logged once
+#[derive(Debug, Default)]
+pub(crate) struct SoftLimits {
+    over: HashSet<(String, Resource)>,
+}
+
+impl SoftLimits {
+    /// Checks `usage` of `org` against `quotas`, returning the soft limits
+    /// that it has just crossed along with the outcome
+    pub(crate) fn check(
+        &mut self,
+        quotas: &Quotas,
+        org: &str,
+        usage: Usage,
+    ) -> (Check, Vec<(Resource, u64, u64)>) {
+        let mut crossed = vec![];
+        for &resource in &Resource::ALL {
+            let key = (org.to_string(), resource);
+            match quotas.limit(resource).soft {
+                Some(soft) if usage.get(resource) >= soft => {
+                    if self.over.insert(key) {
+                        crossed.push((resource, usage.get(resource), soft));
+                    }
+                }
+                _ => {
+                    self.over.remove(&key);
+                }
+            }
+        }
+
+        for &resource in &Resource::ALL {
+            if let Some(hard) = quotas.limit(resource).hard {
+                if usage.get(resource) >= hard {
+                    let check = Check::Exceeded {
+                        resource,
+                        usage: usage.get(resource),
+                        limit: hard,
+                    };
+                    return (check, crossed);
+                }
+            }
+        }
+        (Check::Allowed, crossed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check() {
+        let quotas = Quotas {
+            series: Limit {
+                soft: Some(8),
+                hard: Some(10),
+            },
+            bytes: Limit {
+                soft: None,
+                hard: Some(1000),
+            },
+        };
+        assert!(quotas.enabled());
+        assert!(!Quotas::default().enabled());
+
+        let mut soft_limits = SoftLimits::default();
+        let usage = |series, bytes| Usage { series, bytes };
+
+        assert_eq!(
+            soft_limits.check(&quotas, "org", usage(7, 0)),
+            (Check::Allowed, vec![])
+        );
+
+        // the soft limit is reported once when crossed, and for each org
+        assert_eq!(
+            soft_limits.check(&quotas, "org", usage(8, 0)),
+            (Check::Allowed, vec![(Resource::Series, 8, 8)])
+        );
+        assert_eq!(
+            soft_limits.check(&quotas, "org", usage(9, 0)),
+            (Check::Allowed, vec![])
+        );
+        assert_eq!(
+            soft_limits.check(&quotas, "other", usage(9, 0)),
+            (Check::Allowed, vec![(Resource::Series, 9, 8)])
+        );
+
+        assert_eq!(
+            soft_limits.check(&quotas, "org", usage(10, 0)),
+            (
+                Check::Exceeded {
+                    resource: Resource::Series,
+                    usage: 10,
+                    limit: 10
+                },
+                vec![]
+            )
+        );
+        assert_eq!(
+            soft_limits.check(&quotas, "org", usage(0, 1000)).0,
+            Check::Exceeded {
+                resource: Resource::Bytes,
+                usage: 1000,
+                limit: 1000
+            }
+        );
+
+        // and again after the usage dropped below it
+        soft_limits.check(&quotas, "org", usage(1, 0));
+        assert_eq!(
+            soft_limits.check(&quotas, "org", usage(8, 0)),
+            (Check::Allowed, vec![(Resource::Series, 8, 8)])
+        );
+    }
+}
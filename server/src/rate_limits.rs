@@ -0,0 +1,338 @@
+//! This module contains the rate limits of API tokens and orgs.
+//!
+//! Writes are limited in requests and points per second, and reads in the
+//! number of queries running at once. The limits of a token are set when it
+//! is created, and those of an org in the org catalog. A request is subject
+//! to both the limits of its token and those of the org it reads or writes,
+//! so that an org can't get around its limits by creating more tokens.
+//!
+//! Requests and points per second are limited with token buckets holding a
+//! second's worth of each, so that short bursts over the rate are allowed.
+//! A write of more points than a second's worth is allowed once the bucket
+//! is full, and holds back later writes for as long as it went over. The
+//! state of the limits is kept in memory, per server.
+use crate::{RateLimited, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fmt,
+    num::{NonZeroU32, NonZeroU64},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// How long clients over their limit of concurrent queries are asked to
+/// wait before retrying, as there is no telling when a query will finish
+const QUERY_RETRY_AFTER: Duration = Duration::from_secs(1);
+
+/// The rate limits of a token or an org, any of which may be unset
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RateLimits {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requests_per_second: Option<NonZeroU32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub points_per_second: Option<NonZeroU64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub concurrent_queries: Option<NonZeroU32>,
+}
+
+impl RateLimits {
+    /// Whether no limit is set
+    pub fn is_unlimited(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Whose rate limits a request is subject to
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Subject {
+    /// The token with this id
+    Token(String),
+    /// The org with this name
+    Org(String),
+}
+
+impl fmt::Display for Subject {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Token(id) => write!(f, "token {}", id),
+            Self::Org(name) => write!(f, "org {}", name),
+        }
+    }
+}
+
+/// A token bucket refilled at a rate per second, holding up to a second's
+/// worth
+#[derive(Debug)]
+struct Bucket {
+    available: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn full(now: Instant) -> Self {
+        // capped at the rate when first refilled
+        Self {
+            available: f64::MAX,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, rate: f64, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.available = (self.available + elapsed * rate).min(rate);
+        self.updated = now;
+    }
+
+    /// Returns how long until `n` can be taken, `None` if it can be now
+    fn wait(&self, n: f64, rate: f64) -> Option<Duration> {
+        let needed = n.min(rate);
+        if self.available >= needed {
+            None
+        } else {
+            Some(Duration::from_secs_f64((needed - self.available) / rate))
+        }
+    }
+}
+
+/// The state of the limits of a token or org
+#[derive(Debug)]
+struct State {
+    requests: Bucket,
+    points: Bucket,
+    queries: u32,
+}
+
+impl State {
+    fn new(now: Instant) -> Self {
+        Self {
+            requests: Bucket::full(now),
+            points: Bucket::full(now),
+            queries: 0,
+        }
+    }
+}
+
+type States = Arc<parking_lot::Mutex<HashMap<Subject, State>>>;
+
+/// Keeps track of requests against the rate limits of tokens and orgs
+#[derive(Debug, Default)]
+pub(crate) struct RateLimiter {
+    states: States,
+}
+
+impl RateLimiter {
+    /// Counts a write of `points` points against the limits of each of
+    /// `subjects`, unless one of them is over its limits, in which case
+    /// nothing is counted and the write must be rejected
+    pub(crate) fn check_write(
+        &self,
+        subjects: &[(Subject, RateLimits)],
+        points: u64,
+        now: Instant,
+    ) -> Result<()> {
+        let mut states = self.states.lock();
+
+        for (subject, limits) in subjects {
+            let state = states
+                .entry(subject.clone())
+                .or_insert_with(|| State::new(now));
+
+            if let Some(limit) = limits.requests_per_second {
+                let rate = f64::from(limit.get());
+                state.requests.refill(rate, now);
+                if let Some(retry_after) = state.requests.wait(1.0, rate) {
+                    return RateLimited {
+                        subject: subject.clone(),
+                        limit: format!("{} requests per second", limit),
+                        retry_after,
+                    }
+                    .fail();
+                }
+            }
+            if let Some(limit) = limits.points_per_second {
+                let rate = limit.get() as f64;
+                state.points.refill(rate, now);
+                if let Some(retry_after) = state.points.wait(points as f64, rate) {
+                    return RateLimited {
+                        subject: subject.clone(),
+                        limit: format!("{} points per second", limit),
+                        retry_after,
+                    }
+                    .fail();
+                }
+            }
+        }
+
+        for (subject, limits) in subjects {
+            let state = states.get_mut(subject).expect("state created above");
+            if limits.requests_per_second.is_some() {
+                state.requests.available -= 1.0;
+            }
+            if limits.points_per_second.is_some() {
+                state.points.available -= points as f64;
+            }
+        }
+        Ok(())
+    }
+
+    /// Counts a query against the limits of each of `subjects` until the
+    /// returned permit is dropped, unless one of them already runs as many
+    /// queries as it may
+    pub(crate) fn start_query(
+        &self,
+        subjects: &[(Subject, RateLimits)],
+        now: Instant,
+    ) -> Result<QueryPermit> {
+        let mut states = self.states.lock();
+
+        let limited: Vec<_> = subjects
+            .iter()
+            .filter_map(|(subject, limits)| Some((subject, limits.concurrent_queries?)))
+            .collect();
+        for &(subject, limit) in &limited {
+            let state = states
+                .entry(subject.clone())
+                .or_insert_with(|| State::new(now));
+            if state.queries >= limit.get() {
+                return RateLimited {
+                    subject: subject.clone(),
+                    limit: format!("{} concurrent queries", limit),
+                    retry_after: QUERY_RETRY_AFTER,
+                }
+                .fail();
+            }
+        }
+
+        let mut permit = QueryPermit {
+            states: Arc::clone(&self.states),
+            subjects: vec![],
+        };
+        for (subject, _) in limited {
+            states
+                .get_mut(subject)
+                .expect("state created above")
+                .queries += 1;
+            permit.subjects.push(subject.clone());
+        }
+        Ok(permit)
+    }
+}
+
+/// Counts a running query against the rate limits of its token and org,
+/// until dropped
+#[derive(Debug)]
+pub struct QueryPermit {
+    states: States,
+    subjects: Vec<Subject>,
+}
+
+impl Drop for QueryPermit {
+    fn drop(&mut self) {
+        let mut states = self.states.lock();
+        for subject in &self.subjects {
+            if let Some(state) = states.get_mut(subject) {
+                state.queries = state.queries.saturating_sub(1);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Error;
+
+    fn limits(
+        requests_per_second: u32,
+        points_per_second: u64,
+        concurrent_queries: u32,
+    ) -> RateLimits {
+        RateLimits {
+            requests_per_second: NonZeroU32::new(requests_per_second),
+            points_per_second: NonZeroU64::new(points_per_second),
+            concurrent_queries: NonZeroU32::new(concurrent_queries),
+        }
+    }
+
+    fn retry_after(result: Result<()>) -> Duration {
+        match result.unwrap_err() {
+            Error::RateLimited { retry_after, .. } => retry_after,
+            e => panic!("unexpected error: {}", e),
+        }
+    }
+
+    #[test]
+    fn writes() {
+        let limiter = RateLimiter::default();
+        let token = (Subject::Token("1".to_string()), limits(2, 0, 0));
+        let org = (Subject::Org("company".to_string()), limits(0, 100, 0));
+        let subjects = [token.clone(), org.clone()];
+        let now = Instant::now();
+
+        limiter.check_write(&subjects, 10, now).unwrap();
+        limiter.check_write(&subjects, 10, now).unwrap();
+        // the token has used its two requests of this second
+        let wait = retry_after(limiter.check_write(&subjects, 10, now));
+        assert_eq!(wait, Duration::from_millis(500));
+        // without counting the rejected write against the org
+        limiter
+            .check_write(&[org.clone()], 80, now)
+            .expect("80 points left");
+
+        let now = now + Duration::from_millis(500);
+        // the token may write again, but the org has only 50 points left
+        let wait = retry_after(limiter.check_write(&subjects, 75, now));
+        assert_eq!(wait, Duration::from_millis(250));
+        limiter.check_write(&subjects, 50, now).unwrap();
+
+        // a write larger than a second's worth goes through once the bucket
+        // is full, and holds back the next ones
+        let now = now + Duration::from_secs(1);
+        limiter.check_write(&[org.clone()], 300, now).unwrap();
+        let wait = retry_after(limiter.check_write(&[org.clone()], 100, now));
+        assert_eq!(wait, Duration::from_secs(3));
+
+        // others are not limited
+        let other = (Subject::Org("other".to_string()), limits(0, 100, 0));
+        limiter.check_write(&[other], 100, now).unwrap();
+    }
+
+    #[test]
+    fn queries() {
+        let limiter = RateLimiter::default();
+        let token = (Subject::Token("1".to_string()), limits(0, 0, 2));
+        let org = (Subject::Org("company".to_string()), limits(0, 0, 1));
+        let now = Instant::now();
+
+        let first = limiter.start_query(&[token.clone()], now).unwrap();
+        let second = limiter
+            .start_query(&[token.clone(), org.clone()], now)
+            .unwrap();
+        let err = limiter.start_query(&[token.clone()], now).unwrap_err();
+        assert!(
+            matches!(err, Error::RateLimited { subject: Subject::Token(_), retry_after, .. } if retry_after == QUERY_RETRY_AFTER)
+        );
+
+        drop(first);
+        // the org runs as many queries as it may
+        let err = limiter
+            .start_query(&[token.clone(), org.clone()], now)
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            Error::RateLimited {
+                subject: Subject::Org(_),
+                ..
+            }
+        ));
+        drop(second);
+        let _permit = limiter.start_query(&[token, org], now).unwrap();
+
+        let unlimited = (Subject::Org("other".to_string()), RateLimits::default());
+        assert!(unlimited.1.is_unlimited());
+        let _permits: Vec<_> = (0..10)
+            .map(|_| limiter.start_query(&[unlimited.clone()], now).unwrap())
+            .collect();
+    }
+}
@@ -0,0 +1,280 @@
+//! Moving the ownership of shards between the backends writes are routed to.
+//!
+//! A router sends the series of each shard of its ring to the backend owning
+//! it; see the [`router`](crate::router) module. To even out the load of
+//! the backends, e.g. after adding one, the ownership of a shard can be
+//! moved to another backend without downtime. The move first reroutes the
+//! shard, so that the writes of its series go to the new owner from then
+//! on, and records the owners of the moved shards in object storage, where
+//! routers sharing the object store load them from. It then drains the
+//! source: the previous owner replays the writes to the shard it holds in
+//! the WAL buffers of its databases into the new owner, as line protocol.
+//!
+//! The source keeps its copy of the shard's data until it expires, and the
+//! data no longer in its WAL buffers isn't replayed, nor are writes the
+//! source received just before the shard was rerouted. Queries scattered
+//! to every backend still see all of it, merging the points of a series
+//! returned by several backends.
+use chrono::{DateTime, Utc};
+use data_types::{data::ReplicatedWrite, TIME_COLUMN_NAME};
+use generated_types::wal as wb;
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::{collections::BTreeMap, fmt::Write};
+
+use crate::router::{push_escaped, series_key_of, ShardRange};
+
+/// The name of the file of the object store holding the owners of the
+/// moved shards, by shard id
+pub(crate) const SHARD_OWNERS_FILE_NAME: &str = "shard_owners.json";
+
+/// The number of lines replayed into the new owner of a shard per write
+pub(crate) const REPLAY_BATCH_LINES: usize = 10_000;
+
+/// The phase a shard move is in
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MovePhase {
+    /// Routing the writes of the shard to its new owner
+    Rerouting,
+    /// Replaying the writes of the shard the source holds into the new
+    /// owner
+    Replaying,
+    /// Done: the new owner holds the shard
+    Moved,
+    /// Stopped by an error. The move can be started again.
+    Failed,
+}
+
+/// The status of the move of a shard, as reported by the admin API
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct ShardMove {
+    /// The id of the shard, in hexadecimal
+    pub shard: String,
+    pub from: String,
+    pub to: String,
+    pub phase: MovePhase,
+    pub started: DateTime<Utc>,
+    pub finished: Option<DateTime<Utc>>,
+    /// The number of lines the source replayed into the new owner
+    pub lines_replayed: u64,
+    pub error: Option<String>,
+}
+
+/// The moves of shards started on a router, the last one of each shard
+#[derive(Debug, Default)]
+pub(crate) struct ShardMoves {
+    moves: Mutex<BTreeMap<u64, ShardMove>>,
+}
+
+impl ShardMoves {
+    /// Starts moving `shard` from `from` to `to` unless a move of the shard
+    /// is running, returning its status
+    pub(crate) fn start(&self, shard: u64, from: &str, to: &str) -> Option<ShardMove> {
+        let mut moves = self.moves.lock();
+        if let Some(current) = moves.get(&shard) {
+            if current.finished.is_none() {
+                return None;
+            }
+        }
+        let started = ShardMove {
+            shard: format!("{:016x}", shard),
+            from: from.to_string(),
+            to: to.to_string(),
+            phase: MovePhase::Rerouting,
+            started: Utc::now(),
+            finished: None,
+            lines_replayed: 0,
+            error: None,
+        };
+        moves.insert(shard, started.clone());
+        Some(started)
+    }
+
+    /// Returns the last move of `shard`, if any
+    pub(crate) fn get(&self, shard: u64) -> Option<ShardMove> {
+        self.moves.lock().get(&shard).cloned()
+    }
+
+    /// Returns the last move of every shard moved, by shard id
+    pub(crate) fn all(&self) -> Vec<ShardMove> {
+        self.moves.lock().values().cloned().collect()
+    }
+
+    /// Updates the status of the move of `shard`
+    pub(crate) fn update(&self, shard: u64, f: impl FnOnce(&mut ShardMove)) {
+        if let Some(status) = self.moves.lock().get_mut(&shard) {
+            f(status)
+        }
+    }
+
+    /// Records the end of the move of `shard`, with the error stopping it if
+    /// any
+    pub(crate) fn finish(&self, shard: u64, error: Option<String>) {
+        self.update(shard, |status| {
+            status.finished = Some(Utc::now());
+            status.phase = match error {
+                Some(_) => MovePhase::Failed,
+                None => MovePhase::Moved,
+            };
+            status.error = error;
+        })
+    }
+}
+
+/// Returns the rows of `write` whose series are in `range`, as lines of
+/// line protocol
+pub fn lines_in_range(write: &ReplicatedWrite, range: &ShardRange) -> Vec<String> {
+    let mut lines = vec![];
+    let entries = match write.write_buffer_batch().and_then(|batch| batch.entries()) {
+        Some(entries) => entries,
+        None => return lines,
+    };
+    for entry in entries {
+        for table in entry.table_batches().into_iter().flatten() {
+            let measurement = table.name().unwrap_or("");
+            for row in table.rows().into_iter().flatten() {
+                let values: Vec<_> = row.values().into_iter().flatten().collect();
+                let mut tags: Vec<_> = values
+                    .iter()
+                    .filter_map(|value| {
+                        let tag = value.value_as_tag_value()?;
+                        Some((value.column().unwrap_or(""), tag.value().unwrap_or("")))
+                    })
+                    .collect();
+                tags.sort_unstable();
+                if !range.contains(&series_key_of(measurement, &tags)) {
+                    continue;
+                }
+                lines.push(row_line(measurement, &tags, &values));
+            }
+        }
+    }
+    lines
+}
+
+/// Formats a row of the table `measurement` as line protocol
+fn row_line(measurement: &str, tags: &[(&str, &str)], values: &[wb::Value<'_>]) -> String {
+    let mut line = String::new();
+    push_escaped(&mut line, measurement, &[',', ' ']);
+    for (key, value) in tags {
+        line.push(',');
+        push_escaped(&mut line, key, &[',', '=', ' ']);
+        line.push('=');
+        push_escaped(&mut line, value, &[',', '=', ' ']);
+    }
+
+    let mut time = None;
+    let mut first = true;
+    for value in values {
+        let column = value.column().unwrap_or("");
+        if column == TIME_COLUMN_NAME {
+            time = value.value_as_i64value().map(|v| v.value());
+            continue;
+        }
+        let formatted = match value.value_type() {
+            wb::ColumnValue::I64Value => {
+                value.value_as_i64value().map(|v| format!("{}i", v.value()))
+            }
+            wb::ColumnValue::U64Value => {
+                value.value_as_u64value().map(|v| format!("{}u", v.value()))
+            }
+            wb::ColumnValue::F64Value => value.value_as_f64value().map(|v| v.value().to_string()),
+            wb::ColumnValue::BoolValue => {
+                value.value_as_bool_value().map(|v| v.value().to_string())
+            }
+            wb::ColumnValue::StringValue => value.value_as_string_value().map(|v| {
+                let mut quoted = String::from('"');
+                push_escaped(&mut quoted, v.value().unwrap_or(""), &['"', '\\']);
+                quoted.push('"');
+                quoted
+            }),
+            _ => None,
+        };
+        if let Some(formatted) = formatted {
+            line.push(if first { ' ' } else { ',' });
+            first = false;
+            push_escaped(&mut line, column, &[',', '=', ' ']);
+            line.push('=');
+            line.push_str(&formatted);
+        }
+    }
+
+    if let Some(time) = time {
+        write!(line, " {}", time).expect("writing to a string");
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::router::{series_key, ShardRouter};
+    use data_types::{data::lines_to_replicated_write, database_rules::DatabaseRules};
+    use influxdb_line_protocol::parse_lines;
+
+    #[test]
+    fn moves() {
+        let moves = ShardMoves::default();
+        let status = moves.start(42, "a:8082", "b:8082").unwrap();
+        assert_eq!(status.shard, "000000000000002a");
+        assert!(moves.start(42, "a:8082", "b:8082").is_none());
+
+        moves.update(42, |status| status.lines_replayed = 3);
+        moves.finish(42, Some("boom".to_string()));
+        let status = moves.get(42).unwrap();
+        assert_eq!(status.phase, MovePhase::Failed);
+        assert_eq!(status.lines_replayed, 3);
+
+        // a finished move can be started again
+        assert!(moves.start(42, "a:8082", "b:8082").is_some());
+        moves.finish(42, None);
+        assert_eq!(moves.all()[0].phase, MovePhase::Moved);
+    }
+
+    #[test]
+    fn lines() {
+        let lp =
+            "cpu,host=a,region=west usage=1.5,count=2i,big=3u,up=true,note=\"a \\\"b\\\"\" 10\n\
+                  cpu,host=b value=1 20\n\
+                  mem,host=a used=3 30";
+        let lines: Vec<_> = parse_lines(lp).map(|l| l.unwrap()).collect();
+        let write = lines_to_replicated_write(1, 1, &lines, &DatabaseRules::new());
+
+        // a single backend owns the whole ring
+        let router = ShardRouter::new(vec!["a:8082".to_string()]).unwrap();
+        let shards = router.shards();
+        let mut replayed: Vec<_> = shards
+            .iter()
+            .flat_map(|shard| lines_in_range(&write, &shard.range))
+            .collect();
+        replayed.sort();
+        assert_eq!(replayed.len(), 3);
+
+        // the replayed lines parse back to the same points
+        let reparsed: Vec<_> = parse_lines(&replayed[0]).map(|l| l.unwrap()).collect();
+        assert_eq!(reparsed[0].series.measurement.as_str(), "cpu");
+        assert_eq!(reparsed[0].timestamp, Some(10));
+        assert_eq!(
+            reparsed[0].field_value("note"),
+            lines[0].field_value("note")
+        );
+        assert_eq!(
+            reparsed[0].field_value("count"),
+            lines[0].field_value("count")
+        );
+        assert_eq!(reparsed[0].field_value("big"), lines[0].field_value("big"));
+        assert_eq!(
+            reparsed[0].field_value("usage"),
+            lines[0].field_value("usage")
+        );
+
+        // each line is in the shard of its series
+        for shard in &shards {
+            for line in lines_in_range(&write, &shard.range) {
+                let parsed: Vec<_> = parse_lines(&line).map(|l| l.unwrap()).collect();
+                assert!(shard.range.contains(&series_key(&parsed[0])));
+            }
+        }
+    }
+}
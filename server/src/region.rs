@@ -0,0 +1,366 @@
+//! Asynchronous replication of the object store to a secondary region.
+//!
+//! Everything the servers of a region persist is in their object store: the
+//! chunks snapshotted from the mutable buffer, the segments of the WAL buffers,
+//! and the catalog files (the rules of each database, the org and token
+//! catalogs, the catalog log, the write buffer checkpoints and the owners of
+//! the moved shards). So that losing the region doesn't lose the data, a server
+//! configured with a secondary object store, such as a bucket in another
+//! region, periodically mirrors the object store to it. Chunk files and WAL
+//! segments are never rewritten, so each is copied once, while the catalog
+//! files are copied again whenever their content changes. Objects deleted from
+//! the object store, e.g. by retention or along with their database, are
+//! deleted from the secondary store too, which must therefore not be used for
+//! anything else. Partition leases are not copied: they only coordinate the
+//! servers of a region.
+//!
+//! The secondary store holds everything persisted before the last
+//! successful sync started. The time since then, reported as the recovery
+//! point objective (RPO), is how much of the persisted data would be lost
+//! with the region; data not yet persisted would be lost too. Before the
+//! first sync succeeds, the RPO is the time since replication started.
+use crate::{
+    catalog::CATALOG_LOG_FILE_NAME, config::DB_RULES_FILE_NAME, leases::LEASES_DIR,
+    orgs::ORGS_FILE_NAME, rebalance::SHARD_OWNERS_FILE_NAME, tokens::TOKENS_FILE_NAME,
+    write_buffer::CHECKPOINT_FILE_NAME,
+};
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use futures::TryStreamExt;
+use object_store::{
+    path::{ObjectStorePath, Path},
+    ObjectStore, ObjectStoreApi,
+};
+use parking_lot::Mutex;
+use snafu::{ResultExt, Snafu};
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
+    hash::{Hash, Hasher},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("error listing the object store: {}", source))]
+    ListingPrimary { source: object_store::Error },
+
+    #[snafu(display("error listing the secondary object store: {}", source))]
+    ListingSecondary { source: object_store::Error },
+
+    #[snafu(display("error reading {}: {}", location, source))]
+    Reading {
+        location: String,
+        source: object_store::Error,
+    },
+
+    #[snafu(display("error copying {} to the secondary object store: {}", location, source))]
+    Copying {
+        location: String,
+        source: object_store::Error,
+    },
+
+    #[snafu(display(
+        "error deleting {} from the secondary object store: {}",
+        location,
+        source
+    ))]
+    Deleting {
+        location: String,
+        source: object_store::Error,
+    },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The files rewritten in place, whose changes are copied
+const CATALOG_FILES: &[&str] = &[
+    DB_RULES_FILE_NAME,
+    ORGS_FILE_NAME,
+    TOKENS_FILE_NAME,
+    CATALOG_LOG_FILE_NAME,
+    CHECKPOINT_FILE_NAME,
+    SHARD_OWNERS_FILE_NAME,
+];
+
+/// An object in the secondary store
+#[derive(Debug)]
+struct Copied {
+    location: Path,
+    /// The hash of the content copied, for catalog files
+    hash: Option<u64>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// The objects in the secondary store, by displayed location
+    copied: HashMap<String, Copied>,
+    /// Whether the objects the secondary store held before have been listed
+    listed: bool,
+}
+
+/// What a sync did
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SyncStats {
+    pub copied: usize,
+    pub bytes: usize,
+    pub deleted: usize,
+}
+
+/// The replication to a secondary object store, as described in the module
+/// docs
+#[derive(Debug)]
+pub struct RegionReplication {
+    secondary: Arc<ObjectStore>,
+    state: tokio::sync::Mutex<State>,
+    /// When the last successful sync started
+    synced: Mutex<DateTime<Utc>>,
+    copied: AtomicU64,
+    copied_bytes: AtomicU64,
+    failures: AtomicU64,
+}
+
+impl RegionReplication {
+    /// Mirrors the object store to `secondary`
+    pub fn new(secondary: Arc<ObjectStore>) -> Self {
+        Self {
+            secondary,
+            state: Default::default(),
+            synced: Mutex::new(Utc::now()),
+            copied: AtomicU64::new(0),
+            copied_bytes: AtomicU64::new(0),
+            failures: AtomicU64::new(0),
+        }
+    }
+
+    /// Copies the objects of `primary` the secondary store doesn't hold
+    /// yet or holds an older version of, and deletes those `primary` no
+    /// longer holds
+    pub async fn sync(&self, primary: &ObjectStore) -> Result<SyncStats> {
+        let started = Utc::now();
+        let result = self.mirror(primary).await;
+        match &result {
+            Ok(_) => *self.synced.lock() = started,
+            Err(_) => {
+                self.failures.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        result
+    }
+
+    async fn mirror(&self, primary: &ObjectStore) -> Result<SyncStats> {
+        // a single sync at a time
+        let mut state = self.state.lock().await;
+        if !state.listed {
+            for location in list(&self.secondary).await.context(ListingSecondary)? {
+                state.copied.insert(
+                    location.display(),
+                    Copied {
+                        location,
+                        hash: None,
+                    },
+                );
+            }
+            state.listed = true;
+        }
+
+        let leases = format!("{}/", LEASES_DIR);
+        let mut stats = SyncStats::default();
+        let mut present = HashSet::new();
+        for location in list(primary).await.context(ListingPrimary)? {
+            let key = location.display();
+            if key.starts_with(&leases) {
+                continue;
+            }
+            present.insert(key.clone());
+
+            let catalog_file = CATALOG_FILES
+                .iter()
+                .any(|name| key == *name || key.ends_with(&format!("/{}", name)));
+            if !catalog_file && state.copied.contains_key(&key) {
+                continue;
+            }
+
+            let data = read(primary, &location)
+                .await
+                .context(Reading { location: &key })?;
+            let hash = if catalog_file {
+                let mut hasher = DefaultHasher::new();
+                data.hash(&mut hasher);
+                let hash = Some(hasher.finish());
+                if state.copied.get(&key).map(|copied| copied.hash) == Some(hash) {
+                    continue;
+                }
+                hash
+            } else {
+                None
+            };
+
+            let secondary_location = self.secondary.convert_path(&location);
+            let len = data.len();
+            let stream_data = std::io::Result::Ok(data);
+            self.secondary
+                .put(
+                    &secondary_location,
+                    futures::stream::once(async move { stream_data }),
+                    Some(len),
+                )
+                .await
+                .context(Copying { location: &key })?;
+            stats.copied += 1;
+            stats.bytes += len;
+            self.copied.fetch_add(1, Ordering::Relaxed);
+            self.copied_bytes.fetch_add(len as u64, Ordering::Relaxed);
+            state.copied.insert(
+                key,
+                Copied {
+                    location: secondary_location,
+                    hash,
+                },
+            );
+        }
+
+        let deleted: Vec<_> = state
+            .copied
+            .keys()
+            .filter(|key| !present.contains(*key))
+            .cloned()
+            .collect();
+        for key in deleted {
+            if let Some(copied) = state.copied.get(&key) {
+                self.secondary
+                    .delete(&copied.location)
+                    .await
+                    .context(Deleting { location: &key })?;
+                state.copied.remove(&key);
+                stats.deleted += 1;
+            }
+        }
+
+        Ok(stats)
+    }
+
+    /// The recovery point objective: the age of the most recent data the
+    /// secondary store may not hold
+    pub fn rpo(&self) -> chrono::Duration {
+        Utc::now() - *self.synced.lock()
+    }
+
+    /// The number of objects copied since the server started
+    pub fn copied(&self) -> u64 {
+        self.copied.load(Ordering::Relaxed)
+    }
+
+    /// The number of bytes copied since the server started
+    pub fn copied_bytes(&self) -> u64 {
+        self.copied_bytes.load(Ordering::Relaxed)
+    }
+
+    /// The number of syncs that failed since the server started
+    pub fn failures(&self) -> u64 {
+        self.failures.load(Ordering::Relaxed)
+    }
+}
+
+async fn list(store: &ObjectStore) -> Result<Vec<Path>, object_store::Error> {
+    store.list(None).await?.try_concat().await
+}
+
+async fn read(store: &ObjectStore, location: &Path) -> Result<Bytes, object_store::Error> {
+    let data = store
+        .get(location)
+        .await?
+        .map_ok(|b| bytes::BytesMut::from(&b[..]))
+        .try_concat()
+        .await?;
+    Ok(data.freeze())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object_store::memory::InMemory;
+
+    fn path(store: &ObjectStore, location: &str) -> Path {
+        let mut path = store.new_path();
+        let mut parts: Vec<_> = location.split('/').collect();
+        let file_name = parts.pop().unwrap();
+        path.push_all_dirs(&parts);
+        path.set_file_name(file_name);
+        path
+    }
+
+    async fn put(store: &ObjectStore, location: &str, data: &'static [u8]) {
+        let stream_data = std::io::Result::Ok(Bytes::from(data));
+        store
+            .put(
+                &path(store, location),
+                futures::stream::once(async move { stream_data }),
+                Some(data.len()),
+            )
+            .await
+            .unwrap();
+    }
+
+    async fn contents(store: &ObjectStore) -> Vec<(String, Bytes)> {
+        let mut contents = vec![];
+        for location in list(store).await.unwrap() {
+            let data = read(store, &location).await.unwrap();
+            contents.push((location.display(), data));
+        }
+        contents.sort();
+        contents
+    }
+
+    #[tokio::test]
+    async fn sync() {
+        let primary = ObjectStore::new_in_memory(InMemory::new());
+        let secondary = Arc::new(ObjectStore::new_in_memory(InMemory::new()));
+        put(&primary, "1/foo/rules.json", b"rules").await;
+        put(&primary, "foo/data/part/cpu.parquet", b"chunk").await;
+        put(&primary, "leases/foo/part.json", b"lease").await;
+
+        let replication = RegionReplication::new(Arc::clone(&secondary));
+        let stats = replication.sync(&primary).await.unwrap();
+        assert_eq!(stats.copied, 2);
+        assert_eq!(replication.copied_bytes(), 10);
+        assert_eq!(
+            contents(&secondary).await,
+            vec![
+                ("1/foo/rules.json".to_string(), Bytes::from("rules")),
+                (
+                    "foo/data/part/cpu.parquet".to_string(),
+                    Bytes::from("chunk")
+                ),
+            ]
+        );
+        assert!(replication.rpo() < chrono::Duration::seconds(60));
+
+        // only changed catalog files and new objects are copied again
+        put(&primary, "1/foo/rules.json", b"new rules").await;
+        put(&primary, "foo/data/part/mem.parquet", b"chunk").await;
+        let stats = replication.sync(&primary).await.unwrap();
+        assert_eq!(stats.copied, 2);
+        assert_eq!(replication.sync(&primary).await.unwrap().copied, 0);
+
+        // deletions are mirrored, including those made before a restart
+        primary
+            .delete(&path(&primary, "foo/data/part/cpu.parquet"))
+            .await
+            .unwrap();
+        let replication = RegionReplication::new(Arc::clone(&secondary));
+        let stats = replication.sync(&primary).await.unwrap();
+        assert_eq!(stats.deleted, 1);
+        assert_eq!(
+            contents(&secondary).await,
+            contents(&primary)
+                .await
+                .into_iter()
+                .filter(|(location, _)| !location.starts_with("leases/"))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(replication.failures(), 0);
+    }
+}
@@ -0,0 +1,104 @@
+//! Read replicas, which serve queries for the databases of a primary server.
+//!
+//! A read replica doesn't accept writes. Instead it follows the primary
+//! through object storage: it periodically loads the rules of the primary's
+//! databases and stores the writes of the WAL segments the primary has
+//! persisted since, in the order of their segment ids. Queries on a replica
+//! therefore see a write once the primary has persisted the segment holding
+//! it, which happens when the segment fills up or is closed after the
+//! database's `close_segment_after`. Only databases storing their WAL
+//! segments in object storage can be followed, and replicas must share the
+//! primary's object store and encryption keys.
+//!
+//! Segments persist in the background and may land out of order, so a
+//! replica only stores a segment once all the segments before it have been
+//! stored. The first time a database is followed, this starts from the
+//! oldest segment in object storage.
+use parking_lot::Mutex;
+use std::collections::HashMap;
+
+/// The primary a read replica follows, and how far it has followed it
+#[derive(Debug)]
+pub struct ReadReplica {
+    primary_id: u32,
+    /// The id of the last segment stored, by database
+    applied: Mutex<HashMap<String, u64>>,
+}
+
+impl ReadReplica {
+    /// Follows the server with id `primary_id`
+    pub fn new(primary_id: u32) -> Self {
+        Self {
+            primary_id,
+            applied: Default::default(),
+        }
+    }
+
+    /// The id of the primary
+    pub fn primary_id(&self) -> u32 {
+        self.primary_id
+    }
+
+    /// Returns the id of the last segment of `db_name` stored, if any
+    pub fn applied_segment(&self, db_name: &str) -> Option<u64> {
+        self.applied.lock().get(db_name).copied()
+    }
+
+    /// Returns the segments of `db_name` to store next, in order, out of the
+    /// segment ids `ids` found in object storage
+    pub(crate) fn segments_to_apply(&self, db_name: &str, mut ids: Vec<u64>) -> Vec<u64> {
+        ids.sort_unstable();
+        ids.dedup();
+
+        let mut next = match self.applied_segment(db_name) {
+            Some(applied) => applied + 1,
+            None => match ids.first() {
+                Some(first) => *first,
+                None => return vec![],
+            },
+        };
+
+        ids.into_iter()
+            .skip_while(|id| *id < next)
+            .take_while(|id| {
+                let contiguous = *id == next;
+                next += 1;
+                contiguous
+            })
+            .collect()
+    }
+
+    /// Records the segment `id` of `db_name` as stored
+    pub(crate) fn set_applied(&self, db_name: &str, id: u64) {
+        self.applied.lock().insert(db_name.to_string(), id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn segments_to_apply() {
+        let replica = ReadReplica::new(1);
+        assert_eq!(replica.primary_id(), 1);
+        assert!(replica.segments_to_apply("foo", vec![]).is_empty());
+
+        // starts from the oldest segment, stopping at gaps
+        assert_eq!(
+            replica.segments_to_apply("foo", vec![5, 3, 4, 7]),
+            vec![3, 4, 5]
+        );
+        replica.set_applied("foo", 5);
+        assert_eq!(replica.applied_segment("foo"), Some(5));
+
+        assert!(replica
+            .segments_to_apply("foo", vec![3, 4, 5, 7])
+            .is_empty());
+        assert_eq!(
+            replica.segments_to_apply("foo", vec![3, 4, 5, 6, 7, 9]),
+            vec![6, 7]
+        );
+        assert_eq!(replica.segments_to_apply("bar", vec![2]), vec![2]);
+    }
+}
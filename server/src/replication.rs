@@ -0,0 +1,105 @@
+//! Synchronous replication of writes to peer servers.
+//!
+//! When peers are configured, every write accepted by the server is sent to
+//! each of them over the gRPC write service, and the write is only
+//! acknowledged once a quorum of the servers, this one included, has stored
+//! it. The peers store the writes they receive without replicating them
+//! further. Peers that have not confirmed a write by the time the quorum is
+//! reached still receive it in the background, but a peer that was down
+//! misses the writes sent meanwhile. A write fails if the quorum is not
+//! reached within the replication timeout.
+use std::time::Duration;
+
+use snafu::{ensure, Snafu};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display(
+        "write quorum {} must be between 1 and the {} servers replicating writes",
+        quorum,
+        servers
+    ))]
+    InvalidQuorum { quorum: usize, servers: usize },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// How long writes wait for the quorum by default
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The peers writes are replicated to and how many servers must store them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerReplication {
+    peers: Vec<String>,
+    quorum: usize,
+    timeout: Duration,
+}
+
+impl PeerReplication {
+    /// Replicates writes to `peers`, the gRPC addresses of the other
+    /// servers, acknowledging them once `quorum` servers including this one
+    /// have stored them, or a majority of the servers if not set
+    pub fn new(peers: Vec<String>, quorum: Option<usize>) -> Result<Self> {
+        let servers = peers.len() + 1;
+        let quorum = quorum.unwrap_or(servers / 2 + 1);
+        ensure!(
+            (1..=servers).contains(&quorum),
+            InvalidQuorum { quorum, servers }
+        );
+
+        Ok(Self {
+            peers,
+            quorum,
+            timeout: DEFAULT_TIMEOUT,
+        })
+    }
+
+    /// Fails the writes whose quorum isn't reached within `timeout`
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        Self { timeout, ..self }
+    }
+
+    /// The addresses of the peers
+    pub fn peers(&self) -> &[String] {
+        &self.peers
+    }
+
+    /// The number of servers, this one included, that must store a write
+    /// before it is acknowledged
+    pub fn quorum(&self) -> usize {
+        self.quorum
+    }
+
+    /// How long a write waits for the quorum
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// The number of peers that must confirm a write, as this server
+    /// stores it too
+    pub(crate) fn peer_acks(&self) -> usize {
+        self.quorum - 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quorum() {
+        let peers = |n| (0..n).map(|i| format!("http://peer{}:8082", i)).collect();
+
+        assert_eq!(PeerReplication::new(peers(0), None).unwrap().quorum(), 1);
+        assert_eq!(PeerReplication::new(peers(1), None).unwrap().quorum(), 2);
+        assert_eq!(PeerReplication::new(peers(2), None).unwrap().quorum(), 2);
+        assert_eq!(PeerReplication::new(peers(4), None).unwrap().quorum(), 3);
+
+        let replication = PeerReplication::new(peers(2), Some(3)).unwrap();
+        assert_eq!(replication.peer_acks(), 2);
+        assert_eq!(replication.peers().len(), 2);
+
+        assert!(PeerReplication::new(peers(2), Some(4)).is_err());
+        assert!(PeerReplication::new(peers(2), Some(0)).is_err());
+    }
+}
@@ -0,0 +1,415 @@
+//! Sharding of writes across backend servers.
+//!
+//! A server routing writes doesn't store them. Each line it receives is sent
+//! on to one of its backends, chosen by hashing the line's series key (its
+//! measurement and sorted tag set) onto a consistent hash ring, so that all
+//! the points of a series end up on the same backend. Every backend is
+//! placed on the ring at many points, which spreads the series evenly, and
+//! adding or removing a backend only moves the series of the ring segments
+//! it gains or loses.
+//!
+//! The hash is computed the same way by every build of the server, so
+//! several routers configured with the same backends route the same way.
+//!
+//! Each segment of the ring is a shard, identified by the point it ends at
+//! and owned at first by the backend that placed the point. The ownership
+//! of a shard can be moved to another backend, e.g. to even out the load of
+//! the backends; see the [`rebalance`](crate::rebalance) module.
+use influxdb_line_protocol::{FieldValue, ParsedLine};
+use snafu::{ensure, OptionExt, Snafu};
+use std::{collections::BTreeMap, fmt::Write};
+
+/// The number of points each backend has on the ring
+const POINTS_PER_BACKEND: usize = 128;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("writes can't be routed without backends"))]
+    NoBackends,
+
+    #[snafu(display("backend {} is listed more than once", backend))]
+    DuplicateBackend { backend: String },
+
+    #[snafu(display("shard {:016x} not found", shard))]
+    ShardNotFound { shard: u64 },
+
+    #[snafu(display("{} is not a backend", backend))]
+    UnknownBackend { backend: String },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The hashes of the series keys a shard holds: those after `start` up to
+/// `end` included, wrapping around the ring
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShardRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ShardRange {
+    /// Whether the series `series_key` is in the range
+    pub fn contains(&self, series_key: &str) -> bool {
+        let hash = hash(series_key.as_bytes());
+        if self.start < self.end {
+            self.start < hash && hash <= self.end
+        } else {
+            // the first shard, which also holds the hashes after the last
+            // point; or the whole ring when it has a single point
+            hash > self.start || hash <= self.end
+        }
+    }
+}
+
+/// A segment of the ring and the backend owning it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Shard {
+    pub range: ShardRange,
+    pub owner: String,
+    /// Whether the shard was moved from the backend that placed it
+    pub moved: bool,
+}
+
+impl Shard {
+    /// The id of the shard: the point of the ring it ends at
+    pub fn id(&self) -> u64 {
+        self.range.end
+    }
+}
+
+/// Routes the lines of writes to backends, as described in the module docs
+#[derive(Debug, Clone)]
+pub struct ShardRouter {
+    backends: Vec<String>,
+    /// The backend that placed each point of the ring, as an index into
+    /// `backends`
+    ring: BTreeMap<u64, usize>,
+    /// The backends owning the ring segments moved from the backend that
+    /// placed their end point
+    moved: BTreeMap<u64, usize>,
+}
+
+impl ShardRouter {
+    /// Routes writes to `backends`, the gRPC addresses of the servers
+    /// storing them
+    pub fn new(backends: Vec<String>) -> Result<Self> {
+        ensure!(!backends.is_empty(), NoBackends);
+
+        let mut ring = BTreeMap::new();
+        for (index, backend) in backends.iter().enumerate() {
+            ensure!(
+                !backends[..index].contains(backend),
+                DuplicateBackend { backend }
+            );
+            for point in 0..POINTS_PER_BACKEND {
+                ring.insert(hash(format!("{}#{}", backend, point).as_bytes()), index);
+            }
+        }
+
+        Ok(Self {
+            backends,
+            ring,
+            moved: BTreeMap::new(),
+        })
+    }
+
+    /// The addresses of the backends
+    pub fn backends(&self) -> &[String] {
+        &self.backends
+    }
+
+    /// Returns the backend the series `series_key` is routed to
+    pub fn backend(&self, series_key: &str) -> &str {
+        let hash = hash(series_key.as_bytes());
+        let (point, _) = self
+            .ring
+            .range(hash..)
+            .next()
+            .or_else(|| self.ring.iter().next())
+            .expect("the ring has points");
+        self.owner(*point)
+    }
+
+    /// The backend owning the shard ending at `point`, a point of the ring
+    fn owner(&self, point: u64) -> &str {
+        let index = self.moved.get(&point).unwrap_or(&self.ring[&point]);
+        &self.backends[*index]
+    }
+
+    /// The shards of the ring, in order
+    pub fn shards(&self) -> Vec<Shard> {
+        let mut start = *self.ring.keys().last().expect("the ring has points");
+        self.ring
+            .keys()
+            .map(|point| {
+                let shard = Shard {
+                    range: ShardRange { start, end: *point },
+                    owner: self.owner(*point).to_string(),
+                    moved: self.moved.contains_key(point),
+                };
+                start = *point;
+                shard
+            })
+            .collect()
+    }
+
+    /// Returns the shard with id `id`
+    pub fn shard(&self, id: u64) -> Result<Shard> {
+        ensure!(self.ring.contains_key(&id), ShardNotFound { shard: id });
+        let start = self
+            .ring
+            .range(..id)
+            .next_back()
+            .or_else(|| self.ring.iter().next_back())
+            .map(|(point, _)| *point)
+            .expect("the ring has points");
+        Ok(Shard {
+            range: ShardRange { start, end: id },
+            owner: self.owner(id).to_string(),
+            moved: self.moved.contains_key(&id),
+        })
+    }
+
+    /// Returns a router routing the series of the shard `shard` to
+    /// `backend`, one of the backends, instead of its current owner
+    pub fn with_owner(&self, shard: u64, backend: &str) -> Result<Self> {
+        let placed_by = *self.ring.get(&shard).context(ShardNotFound { shard })?;
+        let index = self
+            .backends
+            .iter()
+            .position(|b| b == backend)
+            .context(UnknownBackend { backend })?;
+
+        let mut router = self.clone();
+        if index == placed_by {
+            router.moved.remove(&shard);
+        } else {
+            router.moved.insert(shard, index);
+        }
+        Ok(router)
+    }
+
+    /// The owners of the shards moved from the backend that placed them, by
+    /// shard id
+    pub fn moved_shards(&self) -> BTreeMap<u64, String> {
+        self.moved
+            .iter()
+            .map(|(shard, index)| (*shard, self.backends[*index].clone()))
+            .collect()
+    }
+
+    /// Groups `lines` by the backend they are routed to, as line protocol.
+    /// Lines without a timestamp are given `default_time`, so that the
+    /// lines of a write have the same time on every backend.
+    pub fn shard_lines(
+        &self,
+        lines: &[ParsedLine<'_>],
+        default_time: i64,
+    ) -> BTreeMap<String, String> {
+        let mut shards: BTreeMap<String, String> = BTreeMap::new();
+        for line in lines {
+            let lp = shards
+                .entry(self.backend(&series_key(line)).to_string())
+                .or_default();
+            if !lp.is_empty() {
+                lp.push('\n');
+            }
+            push_line(lp, line, default_time);
+        }
+        shards
+    }
+}
+
+/// Appends `line` to `lp` as line protocol, with the timestamp
+/// `default_time` if it has none. The fields are written here as the
+/// `Display` of `ParsedLine` doesn't quote string values.
+fn push_line(lp: &mut String, line: &ParsedLine<'_>, default_time: i64) {
+    write!(lp, "{}", line.series).expect("writing to a string");
+    for (index, (key, value)) in line.field_set.iter().enumerate() {
+        lp.push(if index == 0 { ' ' } else { ',' });
+        push_escaped(lp, key.as_str(), &[',', '=', ' ']);
+        lp.push('=');
+        match value {
+            FieldValue::String(value) => {
+                lp.push('"');
+                push_escaped(lp, value.as_str(), &['"', '\\']);
+                lp.push('"');
+            }
+            value => write!(lp, "{}", value).expect("writing to a string"),
+        }
+    }
+    write!(lp, " {}", line.timestamp.unwrap_or(default_time)).expect("writing to a string");
+}
+
+/// Appends `value` to `lp`, escaping the characters `special` with a
+/// backslash
+pub(crate) fn push_escaped(lp: &mut String, value: &str, special: &[char]) {
+    for c in value.chars() {
+        if special.contains(&c) {
+            lp.push('\\');
+        }
+        lp.push(c);
+    }
+}
+
+/// Returns the series key of `line`: its measurement followed by its tags,
+/// sorted by key
+pub(crate) fn series_key(line: &ParsedLine<'_>) -> String {
+    let mut tags: Vec<_> = line
+        .series
+        .tag_set
+        .iter()
+        .flatten()
+        .map(|(key, value)| (key.as_str(), value.as_str()))
+        .collect();
+    tags.sort_unstable();
+    series_key_of(line.series.measurement.as_str(), &tags)
+}
+
+/// Returns the series key of the measurement `measurement` with the tags
+/// `tags`, sorted by key
+pub(crate) fn series_key_of(measurement: &str, tags: &[(&str, &str)]) -> String {
+    let mut key = measurement.to_string();
+    for (tag_key, tag_value) in tags {
+        key.push(',');
+        key.push_str(tag_key);
+        key.push('=');
+        key.push_str(tag_value);
+    }
+    key
+}
+
+/// The 64 bit FNV-1a hash of `bytes`, which unlike the hashers of the
+/// standard library is guaranteed to stay the same
+pub(crate) fn hash(bytes: &[u8]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0100_0000_01b3;
+
+    bytes.iter().fold(OFFSET_BASIS, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(PRIME)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use influxdb_line_protocol::parse_lines;
+
+    fn backends(n: usize) -> Vec<String> {
+        (0..n)
+            .map(|i| format!("http://backend{}:8082", i))
+            .collect()
+    }
+
+    #[test]
+    fn routing() {
+        assert!(ShardRouter::new(vec![]).is_err());
+        assert!(ShardRouter::new(vec!["a:8082".to_string(), "a:8082".to_string()]).is_err());
+
+        let router = ShardRouter::new(backends(3)).unwrap();
+        let keys: Vec<_> = (0..3000).map(|i| format!("cpu,host=host{}", i)).collect();
+
+        // the series are spread over every backend
+        let mut counts = BTreeMap::new();
+        for key in &keys {
+            *counts.entry(router.backend(key)).or_insert(0) += 1;
+        }
+        assert_eq!(counts.len(), 3);
+        assert!(counts.values().all(|count| *count > 500), "{:?}", counts);
+
+        // adding a backend only moves series to it
+        let grown = ShardRouter::new(backends(4)).unwrap();
+        let mut moved = 0;
+        for key in &keys {
+            if grown.backend(key) != router.backend(key) {
+                assert_eq!(grown.backend(key), "http://backend3:8082");
+                moved += 1;
+            }
+        }
+        assert!(moved > 0 && moved < 1500, "{}", moved);
+    }
+
+    #[test]
+    fn shards() {
+        let router = ShardRouter::new(backends(3)).unwrap();
+        let shards = router.shards();
+        assert_eq!(shards.len(), 3 * POINTS_PER_BACKEND);
+        assert!(shards.iter().all(|shard| !shard.moved));
+
+        // every series is in exactly one shard, owned by its backend
+        for i in 0..100 {
+            let key = format!("cpu,host=host{}", i);
+            let containing: Vec<_> = shards
+                .iter()
+                .filter(|shard| shard.range.contains(&key))
+                .collect();
+            assert_eq!(containing.len(), 1);
+            assert_eq!(containing[0].owner, router.backend(&key));
+        }
+
+        // moving a shard only moves its series
+        let shard = shards[0].clone();
+        assert_eq!(router.shard(shard.id()).unwrap(), shard);
+        let target = router
+            .backends()
+            .iter()
+            .find(|backend| **backend != shard.owner)
+            .unwrap()
+            .clone();
+        let moved = router.with_owner(shard.id(), &target).unwrap();
+        assert_eq!(moved.shard(shard.id()).unwrap().owner, target);
+        assert_eq!(moved.moved_shards().len(), 1);
+        for i in 0..3000 {
+            let key = format!("cpu,host=host{}", i);
+            if shard.range.contains(&key) {
+                assert_eq!(moved.backend(&key), target);
+            } else {
+                assert_eq!(moved.backend(&key), router.backend(&key));
+            }
+        }
+
+        // and moving it back to the backend that placed it undoes the move
+        let back = moved.with_owner(shard.id(), &shard.owner).unwrap();
+        assert!(back.moved_shards().is_empty());
+
+        assert!(router.with_owner(shard.id() + 1, &target).is_err());
+        assert!(router.with_owner(shard.id(), "elsewhere:8082").is_err());
+    }
+
+    #[test]
+    fn shard_lines() {
+        let router = ShardRouter::new(backends(2)).unwrap();
+        let lp = "cpu,host=a,region=west usage=1 10\n\
+                  cpu,region=west,host=a usage=2 20\n\
+                  mem,host=b used=3,note=\"a \\\"b\\\"\"";
+        let lines: Vec<_> = parse_lines(lp).map(|l| l.unwrap()).collect();
+        assert_eq!(series_key(&lines[0]), series_key(&lines[1]));
+
+        let shards = router.shard_lines(&lines, 30);
+        let mut routed: Vec<_> = shards
+            .iter()
+            .flat_map(|(backend, lp)| lp.lines().map(move |line| (line, backend.as_str())))
+            .collect();
+        routed.sort_unstable();
+        assert_eq!(routed.len(), 3);
+
+        assert_eq!(routed[0].0, "cpu,host=a,region=west usage=1 10");
+        assert_eq!(routed[1].0, "cpu,region=west,host=a usage=2 20");
+        assert_eq!(routed[0].1, routed[1].1);
+        assert_eq!(routed[0].1, router.backend("cpu,host=a,region=west"));
+        assert_eq!(
+            routed[2],
+            (
+                r#"mem,host=b used=3,note="a \"b\"" 30"#,
+                router.backend("mem,host=b")
+            )
+        );
+
+        // the routed lines parse back to the same values
+        let reparsed: Vec<_> = parse_lines(routed[2].0).map(|l| l.unwrap()).collect();
+        assert_eq!(
+            reparsed[0].field_value("note"),
+            lines[2].field_value("note")
+        );
+    }
+}
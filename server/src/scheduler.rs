@@ -0,0 +1,256 @@
+//! Fair scheduling of background jobs across tenants.
+//!
+//! Compactions and the snapshots persisting chunks to object storage run as
+//! background jobs, at most a configured number of them at a time. Jobs
+//! started beyond that wait in a queue per tenant, the org of the database
+//! they work on or the database itself if it doesn't back a bucket, and the
+//! tenants with waiting jobs take turns starting their next one. A tenant
+//! with a long backlog of compactions thus delays the jobs of others by at
+//! most one job per turn. The jobs of a tenant start in the order they were
+//! queued.
+//!
+//! The IO of the running jobs is limited by the scheduler's
+//! [`throttle`](crate::throttle), which they get from their permit.
+use crate::throttle::{IoLimits, IoThrottle};
+use data_types::names::database_to_org_and_bucket;
+use parking_lot::Mutex;
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    sync::Arc,
+};
+use tokio::sync::oneshot;
+
+/// The number of background jobs run at a time unless configured
+pub const DEFAULT_MAX_RUNNING: usize = 4;
+
+/// Returns the tenant the jobs of the database `db_name` are queued for
+pub(crate) fn tenant(db_name: &str) -> String {
+    database_to_org_and_bucket(db_name).map_or_else(|| db_name.to_string(), |(org, _)| org)
+}
+
+#[derive(Debug, Default)]
+struct State {
+    max_running: usize,
+    running: usize,
+    /// The jobs waiting to start, by tenant
+    queues: HashMap<String, VecDeque<oneshot::Sender<()>>>,
+    /// The tenants with waiting jobs, in the order they take turns
+    turns: VecDeque<String>,
+}
+
+impl State {
+    /// Starts waiting jobs while fewer than `max_running` are running
+    fn start_next(&mut self) {
+        while self.running < self.max_running {
+            let tenant = match self.turns.pop_front() {
+                Some(tenant) => tenant,
+                None => return,
+            };
+            let queue = self.queues.get_mut(&tenant).expect("tenant has a queue");
+            let job = queue.pop_front().expect("queues are not empty");
+            if queue.is_empty() {
+                self.queues.remove(&tenant);
+            } else {
+                self.turns.push_back(tenant);
+            }
+
+            // jobs dropped while waiting are skipped
+            if job.send(()).is_ok() {
+                self.running += 1;
+            }
+        }
+    }
+}
+
+/// Decides when background jobs start, as described in the module docs
+#[derive(Debug)]
+pub struct JobScheduler {
+    state: Mutex<State>,
+    throttle: IoThrottle,
+}
+
+impl Default for JobScheduler {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_RUNNING)
+    }
+}
+
+impl JobScheduler {
+    /// Creates a scheduler running up to `max_running` jobs at a time, at
+    /// least one
+    pub fn new(max_running: usize) -> Self {
+        Self {
+            state: Mutex::new(State {
+                max_running: max_running.max(1),
+                ..Default::default()
+            }),
+            throttle: IoThrottle::default(),
+        }
+    }
+
+    /// Changes the number of jobs run at a time. Running jobs are not
+    /// interrupted if it is lowered.
+    pub fn set_max_running(&self, max_running: usize) {
+        let mut state = self.state.lock();
+        state.max_running = max_running.max(1);
+        state.start_next();
+    }
+
+    /// Limits the IO of the running jobs to `limits`. See the
+    /// [`throttle`](crate::throttle) module for details.
+    pub fn set_io_limits(&self, limits: IoLimits) {
+        self.throttle.set_limits(limits);
+    }
+
+    /// Returns the throttle limiting the IO of the running jobs
+    pub fn throttle(&self) -> &IoThrottle {
+        &self.throttle
+    }
+
+    /// Queues a job for `tenant`, to be started with `QueuedJob::start`
+    pub(crate) fn enqueue(self: &Arc<Self>, tenant: String) -> QueuedJob {
+        let (sender, receiver) = oneshot::channel();
+
+        let mut state = self.state.lock();
+        let queue = state.queues.entry(tenant.clone()).or_default();
+        queue.push_back(sender);
+        if queue.len() == 1 {
+            state.turns.push_back(tenant);
+        }
+        state.start_next();
+
+        QueuedJob {
+            scheduler: Arc::clone(self),
+            started: Some(receiver),
+        }
+    }
+
+    /// Returns the number of jobs running
+    pub fn running(&self) -> usize {
+        self.state.lock().running
+    }
+
+    /// Returns the number of jobs waiting to start for each tenant that has
+    /// any
+    pub fn queue_depths(&self) -> BTreeMap<String, usize> {
+        self.state
+            .lock()
+            .queues
+            .iter()
+            .map(|(tenant, queue)| (tenant.clone(), queue.len()))
+            .collect()
+    }
+
+    /// Counts a job as finished, starting the next one
+    fn finish(&self) {
+        let mut state = self.state.lock();
+        state.running -= 1;
+        state.start_next();
+    }
+}
+
+/// A job waiting for its turn to start
+#[derive(Debug)]
+pub struct QueuedJob {
+    scheduler: Arc<JobScheduler>,
+    started: Option<oneshot::Receiver<()>>,
+}
+
+impl QueuedJob {
+    /// Waits for the job's turn, returning a permit to hold while it runs
+    pub async fn start(mut self) -> JobPermit {
+        if let Some(started) = self.started.as_mut() {
+            // senders are only dropped after sending, as the scheduler
+            // outlives its jobs
+            let _ = started.await;
+        }
+        self.started = None;
+
+        JobPermit {
+            scheduler: Arc::clone(&self.scheduler),
+        }
+    }
+}
+
+impl Drop for QueuedJob {
+    fn drop(&mut self) {
+        // a job dropped after its turn came gives it up
+        if let Some(mut started) = self.started.take() {
+            started.close();
+            if started.try_recv().is_ok() {
+                self.scheduler.finish();
+            }
+        }
+    }
+}
+
+/// Counts a job as running until dropped
+#[derive(Debug)]
+pub struct JobPermit {
+    scheduler: Arc<JobScheduler>,
+}
+
+impl JobPermit {
+    /// Returns the throttle the job must limit its IO with
+    pub fn throttle(&self) -> &IoThrottle {
+        &self.scheduler.throttle
+    }
+}
+
+impl Drop for JobPermit {
+    fn drop(&mut self) {
+        self.scheduler.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::FutureExt;
+
+    #[test]
+    fn tenants() {
+        assert_eq!(tenant("company_sensors"), "company");
+        assert_eq!(tenant("standalone"), "standalone");
+    }
+
+    #[tokio::test]
+    async fn fairness() {
+        let scheduler = Arc::new(JobScheduler::new(1));
+
+        let running = scheduler.enqueue("big".to_string()).start().await;
+        let big: Vec<_> = (0..3)
+            .map(|_| scheduler.enqueue("big".to_string()))
+            .collect();
+        let small = scheduler.enqueue("small".to_string());
+        assert_eq!(scheduler.running(), 1);
+        let depths = scheduler.queue_depths();
+        assert_eq!(depths["big"], 3);
+        assert_eq!(depths["small"], 1);
+
+        // the small tenant's job starts after one of the big tenant's,
+        // rather than after all of them
+        let mut big = big.into_iter();
+        let next = big.next().unwrap();
+        drop(running);
+        let running = next.start().await;
+        let mut small = small.start().boxed();
+        assert!((&mut small).now_or_never().is_none());
+        drop(running);
+        let running = small.await;
+        assert_eq!(scheduler.queue_depths()["big"], 2);
+
+        // jobs dropped while waiting give up their turn
+        drop(big.next());
+        drop(running);
+        let running = big.next().unwrap().start().await;
+        assert_eq!(scheduler.running(), 1);
+        assert!(scheduler.queue_depths().is_empty());
+
+        scheduler.set_max_running(2);
+        let other = scheduler.enqueue("other".to_string()).start().await;
+        assert_eq!(scheduler.running(), 2);
+        drop((running, other));
+        assert_eq!(scheduler.running(), 0);
+    }
+}
@@ -1,5 +1,12 @@
 //! This module contains code for snapshotting a database chunk to Parquet
 //! files in object storage.
+//!
+//! If encryption is enabled, the Parquet files and the partition metadata
+//! are encrypted as described in the [`encryption`](crate::encryption)
+//! module.
+//!
+//! Snapshots run as background jobs, whose writes to object storage are
+//! limited by the [`throttle`](crate::throttle) of the job scheduler.
 use arrow_deps::{
     arrow::datatypes::SchemaRef,
     datafusion::physical_plan::SendableRecordBatchStream,
@@ -17,6 +24,12 @@ use std::{
     sync::Arc,
 };
 
+use crate::{
+    encryption::{self, Encryption},
+    scheduler::{JobPermit, QueuedJob},
+    throttle::IoThrottle,
+};
+
 use bytes::Bytes;
 use futures::StreamExt;
 use parking_lot::Mutex;
@@ -61,6 +74,9 @@ pub enum Error {
     #[snafu(display("Error writing to object store: {}", source))]
     WritingToObjectStore { source: object_store::Error },
 
+    #[snafu(display("Error encrypting snapshot: {}", source))]
+    Encrypting { source: encryption::Error },
+
     #[snafu(display("Error reading batches while writing to '{}': {}", file_name, source))]
     ReadingBatches {
         file_name: String,
@@ -83,6 +99,7 @@ where
     pub metadata_path: object_store::path::Path,
     pub data_path: object_store::path::Path,
     store: Arc<ObjectStore>,
+    encryption: Option<Arc<Encryption>>,
     chunk: Arc<T>,
     status: Mutex<Status>,
 }
@@ -96,6 +113,7 @@ where
         metadata_path: object_store::path::Path,
         data_path: object_store::path::Path,
         store: Arc<ObjectStore>,
+        encryption: Option<Arc<Encryption>>,
         partition: Arc<T>,
         tables: Vec<TableSummary>,
     ) -> Self {
@@ -115,6 +133,7 @@ where
             metadata_path,
             data_path,
             store,
+            encryption,
             chunk: partition,
             status: Mutex::new(status),
         }
@@ -161,7 +180,11 @@ where
         status.stop_on_next_update
     }
 
-    async fn run(&self, notify: Option<oneshot::Sender<()>>) -> Result<()> {
+    async fn run(
+        &self,
+        notify: Option<oneshot::Sender<()>>,
+        throttle: Option<&IoThrottle>,
+    ) -> Result<()> {
         while let Some((pos, table_name)) = self.next_table() {
             // get all the data in this chunk:
             let predicate = Predicate::default();
@@ -178,7 +201,8 @@ where
             let file_name = format!("{}.parquet", table_name);
             location.set_file_name(&file_name);
             let data = Self::parquet_stream_to_bytes(stream, schema).await?;
-            self.write_to_object_store(data, &location).await?;
+            self.write_to_object_store(data, &location, throttle)
+                .await?;
             self.mark_table_finished(pos);
 
             if self.should_stop() {
@@ -190,17 +214,8 @@ where
         let key = format!("{}.json", &self.partition_summary.key);
         partition_meta_path.set_file_name(&key);
         let json_data = serde_json::to_vec(&self.partition_summary).context(JsonGenerationError)?;
-        let data = Bytes::from(json_data);
-        let len = data.len();
-        let stream_data = std::io::Result::Ok(data);
-        self.store
-            .put(
-                &partition_meta_path,
-                futures::stream::once(async move { stream_data }),
-                Some(len),
-            )
-            .await
-            .context(WritingToObjectStore)?;
+        self.write_to_object_store(json_data, &partition_meta_path, throttle)
+            .await?;
 
         self.mark_meta_written();
 
@@ -241,9 +256,16 @@ where
         &self,
         data: Vec<u8>,
         file_name: &object_store::path::Path,
+        throttle: Option<&IoThrottle>,
     ) -> Result<()> {
+        let data = encryption::encrypt_if_enabled(self.encryption.as_deref(), Bytes::from(data))
+            .await
+            .context(Encrypting)?;
         let len = data.len();
-        let data = Bytes::from(data);
+        let _permit = match throttle {
+            Some(throttle) => Some(throttle.file_op(len as u64).await),
+            None => None,
+        };
         let stream_data = Result::Ok(data);
 
         self.store
@@ -281,8 +303,10 @@ pub fn snapshot_chunk<T>(
     metadata_path: object_store::path::Path,
     data_path: object_store::path::Path,
     store: Arc<ObjectStore>,
+    encryption: Option<Arc<Encryption>>,
     partition_key: &str,
     chunk: Arc<T>,
+    job: Option<QueuedJob>,
     notify: Option<oneshot::Sender<()>>,
 ) -> Result<Arc<Snapshot<T>>>
 where
@@ -298,6 +322,7 @@ where
         metadata_path,
         data_path,
         store,
+        encryption,
         chunk,
         table_stats,
     );
@@ -306,12 +331,17 @@ where
     let return_snapshot = Arc::clone(&snapshot);
 
     tokio::spawn(async move {
+        let permit = match job {
+            Some(job) => Some(job.start().await),
+            None => None,
+        };
         info!(
             "starting snapshot of {} to {}",
             &snapshot.partition_summary.key,
             &snapshot.data_path.display()
         );
-        if let Err(e) = snapshot.run(notify).await {
+        let throttle = permit.as_ref().map(JobPermit::throttle);
+        if let Err(e) = snapshot.run(notify, throttle).await {
             error!("error running snapshot: {:?}", e);
             snapshot.set_error(e);
         }
@@ -401,8 +431,10 @@ mem,host=A,region=west used=45 1
             metadata_path.clone(),
             data_path,
             Arc::clone(&store),
+            None,
             "testaroo",
             chunk,
+            None,
             Some(tx),
         )
         .unwrap();
@@ -450,7 +482,15 @@ mem,host=A,region=west used=45 1
         let mut data_path = store.new_path();
         data_path.push_dir("data");
 
-        let snapshot = Snapshot::new("testaroo", metadata_path, data_path, store, chunk, tables);
+        let snapshot = Snapshot::new(
+            "testaroo",
+            metadata_path,
+            data_path,
+            store,
+            None,
+            chunk,
+            tables,
+        );
 
         let (pos, name) = snapshot.next_table().unwrap();
         assert_eq!(0, pos);
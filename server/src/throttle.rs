@@ -0,0 +1,205 @@
+//! Throttling of the IO done by background jobs.
+//!
+//! Compactions and snapshots run in the background, but move as much data
+//! as they can, as fast as they can: a snapshot of a large partition could
+//! otherwise take all the bandwidth of the object store, and a compaction
+//! the memory bandwidth, that foreground queries and writes need. The
+//! throttle limits them in bytes per second, with a token bucket holding a
+//! second's worth so that short bursts over the rate are allowed, and in the
+//! number of files they read or write at once.
+//!
+//! Snapshots count the bytes of each file they write to the object store,
+//! and compactions the size of each chunk they move into the read buffer.
+//! An operation of more bytes than a second's worth is allowed once the
+//! bucket is full, and holds back later operations for as long as it went
+//! over. The limits are shared by all the background jobs of a server.
+use parking_lot::Mutex;
+use std::{
+    num::{NonZeroU32, NonZeroU64},
+    time::{Duration, Instant},
+};
+use tokio::sync::Notify;
+
+/// The limits of the IO of background jobs, either of which may be unset
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IoLimits {
+    pub bytes_per_second: Option<NonZeroU64>,
+    pub concurrent_file_ops: Option<NonZeroU32>,
+}
+
+#[derive(Debug)]
+struct State {
+    limits: IoLimits,
+    /// The bytes that can be read or written now, negative after an
+    /// operation larger than a second's worth
+    available: f64,
+    updated: Instant,
+    /// The number of files being read or written
+    file_ops: u32,
+}
+
+impl State {
+    /// Takes `bytes` from the bucket, returning how long to wait before
+    /// trying again if there aren't enough yet
+    fn take(&mut self, bytes: u64, now: Instant) -> Option<Duration> {
+        let rate = self.limits.bytes_per_second?.get() as f64;
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.available = (self.available + elapsed * rate).min(rate);
+        self.updated = now;
+
+        let needed = (bytes as f64).min(rate);
+        if self.available >= needed {
+            self.available -= bytes as f64;
+            None
+        } else {
+            Some(Duration::from_secs_f64((needed - self.available) / rate))
+        }
+    }
+}
+
+/// Limits the IO of background jobs, as described in the module docs
+#[derive(Debug)]
+pub struct IoThrottle {
+    state: Mutex<State>,
+    /// Notified when a file operation finishes
+    released: Notify,
+}
+
+impl Default for IoThrottle {
+    fn default() -> Self {
+        Self::new(IoLimits::default())
+    }
+}
+
+impl IoThrottle {
+    pub fn new(limits: IoLimits) -> Self {
+        Self {
+            state: Mutex::new(State {
+                limits,
+                // capped at the rate when first refilled
+                available: f64::MAX,
+                updated: Instant::now(),
+                file_ops: 0,
+            }),
+            released: Notify::new(),
+        }
+    }
+
+    /// Changes the limits. Running operations are not interrupted if they
+    /// are lowered.
+    pub fn set_limits(&self, limits: IoLimits) {
+        self.state.lock().limits = limits;
+        self.released.notify_waiters();
+    }
+
+    pub fn limits(&self) -> IoLimits {
+        self.state.lock().limits
+    }
+
+    /// Returns the number of files being read or written
+    pub fn file_ops(&self) -> u32 {
+        self.state.lock().file_ops
+    }
+
+    /// Waits until `bytes` may be read or written
+    pub async fn consume(&self, bytes: u64) {
+        loop {
+            let wait = self.state.lock().take(bytes, Instant::now());
+            match wait {
+                Some(wait) => tokio::time::sleep(wait).await,
+                None => return,
+            }
+        }
+    }
+
+    /// Waits until a file of `bytes` may be read or written, returning a
+    /// permit to hold while it is
+    pub async fn file_op(&self, bytes: u64) -> FileOpPermit<'_> {
+        loop {
+            let released = self.released.notified();
+            {
+                let mut state = self.state.lock();
+                let under_limit = state
+                    .limits
+                    .concurrent_file_ops
+                    .map_or(true, |limit| state.file_ops < limit.get());
+                if under_limit {
+                    state.file_ops += 1;
+                    break;
+                }
+            }
+            released.await;
+        }
+
+        let permit = FileOpPermit { throttle: self };
+        self.consume(bytes).await;
+        permit
+    }
+}
+
+/// Counts a file as being read or written until dropped
+#[derive(Debug)]
+pub struct FileOpPermit<'a> {
+    throttle: &'a IoThrottle,
+}
+
+impl Drop for FileOpPermit<'_> {
+    fn drop(&mut self) {
+        self.throttle.state.lock().file_ops -= 1;
+        self.throttle.released.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::FutureExt;
+
+    #[test]
+    fn bytes_per_second() {
+        let now = Instant::now();
+        let mut state = State {
+            limits: IoLimits {
+                bytes_per_second: NonZeroU64::new(1000),
+                concurrent_file_ops: None,
+            },
+            available: f64::MAX,
+            updated: now,
+            file_ops: 0,
+        };
+
+        // a second's worth is available at once
+        assert_eq!(state.take(600, now), None);
+        assert_eq!(state.take(600, now), Some(Duration::from_millis(200)));
+        assert_eq!(state.take(600, now + Duration::from_millis(200)), None);
+
+        // more than a second's worth goes over once the bucket is full
+        let later = now + Duration::from_secs(10);
+        assert_eq!(state.take(3000, later), None);
+        assert_eq!(state.take(100, later), Some(Duration::from_millis(2100)));
+
+        state.limits.bytes_per_second = None;
+        assert_eq!(state.take(u64::MAX, later), None);
+    }
+
+    #[tokio::test]
+    async fn concurrent_file_ops() {
+        let throttle = IoThrottle::new(IoLimits {
+            bytes_per_second: None,
+            concurrent_file_ops: NonZeroU32::new(1),
+        });
+
+        let first = throttle.file_op(100).await;
+        assert_eq!(throttle.file_ops(), 1);
+
+        // the second waits for the first to finish
+        let mut second = Box::pin(throttle.file_op(100));
+        assert!((&mut second).now_or_never().is_none());
+        drop(first);
+        let second = second.await;
+        assert_eq!(throttle.file_ops(), 1);
+
+        drop(second);
+        assert_eq!(throttle.file_ops(), 0);
+    }
+}
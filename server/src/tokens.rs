@@ -0,0 +1,263 @@
+//! This module contains the catalog of the API tokens created through the
+//! server's API.
+//!
+//! Each token has scopes saying what it may be used for, and may be
+//! restricted to an org, or to a bucket of an org. Only a SHA-256 hash of
+//! each token is stored, so a token is only ever known to whoever created
+//! it. The catalog is persisted as a single file in the root of the server's
+//! object storage, next to the org catalog.
+//!
+//! Tokens are replicated to the other members of a cluster one by one, with
+//! the hash of their secret, so ids start with the writer id of the server
+//! that created the token, to be unique across the cluster.
+use crate::{rate_limits::RateLimits, InvalidToken, Result, TokenNotFound};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use snafu::{ensure, OptionExt};
+
+pub(crate) const TOKENS_FILE_NAME: &str = "tokens.json";
+
+/// What a token may be used for
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// Reading and querying data
+    Read,
+    /// Writing and deleting data
+    Write,
+    /// Managing buckets, orgs, databases and tokens
+    Admin,
+}
+
+/// An API token, without the token itself
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Token {
+    /// 16 character hex id: the writer id of the server that created the
+    /// token, followed by a counter of that server
+    pub id: String,
+    #[serde(default)]
+    pub description: String,
+    pub scopes: Vec<Scope>,
+    /// If set, the token can only be used in the org with this name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub org: Option<String>,
+    /// If set, the token can only be used in the bucket with this name of
+    /// `org`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bucket: Option<String>,
+    /// How often requests made with the token may write and read
+    #[serde(default, skip_serializing_if = "RateLimits::is_unlimited")]
+    pub limits: RateLimits,
+}
+
+/// What a new token may be used for
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TokenRequest {
+    pub description: String,
+    pub scopes: Vec<Scope>,
+    pub org: Option<String>,
+    pub bucket: Option<String>,
+    pub limits: RateLimits,
+}
+
+/// A token with the hash of its secret, as persisted and replicated
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) struct StoredToken {
+    #[serde(flatten)]
+    pub(crate) token: Token,
+    hash: String,
+}
+
+/// The persisted form of the token catalog
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) struct TokenCatalog {
+    tokens: Vec<StoredToken>,
+    /// The numeric value of the id assigned to the next token created
+    next_id: u64,
+}
+
+impl TokenCatalog {
+    pub(crate) fn tokens(&self) -> Vec<Token> {
+        self.tokens
+            .iter()
+            .map(|stored| stored.token.clone())
+            .collect()
+    }
+
+    pub(crate) fn get(&self, id: &str) -> Option<&Token> {
+        self.tokens
+            .iter()
+            .map(|stored| &stored.token)
+            .find(|token| token.id == id)
+    }
+
+    /// Returns the token with id `id` along with the hash of its secret
+    pub(crate) fn stored(&self, id: &str) -> Option<&StoredToken> {
+        self.tokens.iter().find(|stored| stored.token.id == id)
+    }
+
+    /// Adds a token as described by `request`, created on the server with
+    /// writer id `writer_id`, returning it along with the secret to
+    /// authenticate with, which is not kept
+    pub(crate) fn create(
+        &mut self,
+        writer_id: u32,
+        request: TokenRequest,
+    ) -> Result<(Token, String)> {
+        ensure!(
+            !request.scopes.is_empty(),
+            InvalidToken {
+                reason: "a token needs at least one scope"
+            }
+        );
+        ensure!(
+            request.bucket.is_none() || request.org.is_some(),
+            InvalidToken {
+                reason: "a token restricted to a bucket must be restricted to its org"
+            }
+        );
+
+        let mut scopes = request.scopes;
+        scopes.sort();
+        scopes.dedup();
+
+        // ids start at 1 so that no token has the all zero id
+        self.next_id = self.next_id.max(1);
+        let token = Token {
+            id: format!("{:08x}{:08x}", writer_id, self.next_id),
+            description: request.description,
+            scopes,
+            org: request.org,
+            bucket: request.bucket,
+            limits: request.limits,
+        };
+        self.next_id += 1;
+
+        // 244 random bits
+        let secret = format!(
+            "{}{}",
+            uuid::Uuid::new_v4().to_simple(),
+            uuid::Uuid::new_v4().to_simple()
+        );
+        self.upsert(StoredToken {
+            token: token.clone(),
+            hash: hash(&secret),
+        });
+        Ok((token, secret))
+    }
+
+    /// Adds `stored`, or replaces the token with its id
+    pub(crate) fn upsert(&mut self, stored: StoredToken) {
+        match self
+            .tokens
+            .iter_mut()
+            .find(|existing| existing.token.id == stored.token.id)
+        {
+            Some(existing) => *existing = stored,
+            None => self.tokens.push(stored),
+        }
+    }
+
+    /// Removes the token with id `id`, returning it
+    pub(crate) fn remove(&mut self, id: &str) -> Result<Token> {
+        let index = self
+            .tokens
+            .iter()
+            .position(|stored| stored.token.id == id)
+            .context(TokenNotFound { id })?;
+        Ok(self.tokens.remove(index).token)
+    }
+
+    /// Returns the token whose secret is `secret`, if any
+    pub(crate) fn authenticate(&self, secret: &str) -> Option<&Token> {
+        // the hashes, unlike the secrets, can be compared in variable time
+        let hash = hash(secret);
+        self.tokens
+            .iter()
+            .find(|stored| stored.hash == hash)
+            .map(|stored| &stored.token)
+    }
+}
+
+fn hash(secret: &str) -> String {
+    Sha256::digest(secret.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Error;
+
+    fn request(scopes: Vec<Scope>, org: Option<&str>, bucket: Option<&str>) -> TokenRequest {
+        TokenRequest {
+            description: "test".to_string(),
+            scopes,
+            org: org.map(ToString::to_string),
+            bucket: bucket.map(ToString::to_string),
+            limits: RateLimits::default(),
+        }
+    }
+
+    #[test]
+    fn create_authenticate_remove() {
+        let mut catalog = TokenCatalog::default();
+
+        let (token, secret) = catalog
+            .create(
+                1,
+                request(
+                    vec![Scope::Write, Scope::Read, Scope::Write],
+                    Some("company"),
+                    None,
+                ),
+            )
+            .unwrap();
+        assert_eq!(token.id, "0000000100000001");
+        assert_eq!(token.scopes, vec![Scope::Read, Scope::Write]);
+        assert_eq!(secret.len(), 64);
+        assert_eq!(catalog.authenticate(&secret), Some(&token));
+        assert_eq!(catalog.authenticate("wrong"), None);
+
+        // only the hash of the secret is persisted
+        let json = serde_json::to_string(&catalog).unwrap();
+        assert!(!json.contains(&secret));
+        assert!(json.contains(&hash(&secret)));
+        let loaded: TokenCatalog = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.authenticate(&secret), Some(&token));
+
+        let (other, other_secret) = catalog
+            .create(
+                1,
+                request(vec![Scope::Read], Some("company"), Some("sensors")),
+            )
+            .unwrap();
+        assert_eq!(other.id, "0000000100000002");
+        assert_ne!(secret, other_secret);
+        assert_eq!(catalog.get(&other.id), Some(&other));
+
+        let err = catalog.create(1, request(vec![], None, None)).unwrap_err();
+        assert!(matches!(err, Error::InvalidToken { .. }));
+        let err = catalog
+            .create(1, request(vec![Scope::Read], None, Some("sensors")))
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidToken { .. }));
+
+        // tokens created on other servers authenticate with the same secret
+        let mut replica = TokenCatalog::default();
+        replica.upsert(catalog.stored(&token.id).unwrap().clone());
+        assert_eq!(replica.authenticate(&secret), Some(&token));
+        let (replicated, _) = replica
+            .create(2, request(vec![Scope::Read], None, None))
+            .unwrap();
+        assert_eq!(replicated.id, "0000000200000001");
+
+        assert_eq!(catalog.remove(&token.id).unwrap(), token);
+        assert_eq!(catalog.authenticate(&secret), None);
+        let err = catalog.remove(&token.id).unwrap_err();
+        assert!(matches!(err, Error::TokenNotFound { .. }));
+        assert_eq!(catalog.tokens(), vec![other]);
+    }
+}
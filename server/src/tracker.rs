@@ -8,6 +8,7 @@ use std::task::{Context, Poll};
 use futures::prelude::*;
 use parking_lot::Mutex;
 use pin_project::{pin_project, pinned_drop};
+use tokio::sync::watch;
 
 /// Every future registered with a `TrackerRegistry` is assigned a unique
 /// `TrackerId`
@@ -24,6 +25,9 @@ struct Tracker<T> {
 struct TrackerContextInner<T> {
     id: AtomicUsize,
     trackers: Mutex<HashMap<TrackerId, Tracker<T>>>,
+    /// The number of tracked futures, sent whenever it changes. The
+    /// receiver is kept so that sending never fails.
+    tracked_count: (watch::Sender<usize>, watch::Receiver<usize>),
 }
 
 /// Allows tracking the lifecycle of futures registered by
@@ -50,6 +54,7 @@ impl<T> Default for TrackerRegistry<T> {
             inner: Arc::new(TrackerContextInner {
                 id: AtomicUsize::new(0),
                 trackers: Mutex::new(Default::default()),
+                tracked_count: watch::channel(0),
             }),
         }
     }
@@ -76,21 +81,44 @@ impl<T> TrackerRegistry<T> {
         }
     }
 
+    /// Waits until no futures are registered
+    pub async fn idle(&self) {
+        let mut tracked_count = self.inner.tracked_count.1.clone();
+        while *tracked_count.borrow() != 0 {
+            tracked_count
+                .changed()
+                .await
+                .expect("registry holds the sender");
+        }
+    }
+
     fn untrack(&self, id: &TrackerId) {
-        self.inner.trackers.lock().remove(id);
+        let mut trackers = self.inner.trackers.lock();
+        trackers.remove(id);
+        self.send_tracked_count(trackers.len());
+    }
+
+    fn send_tracked_count(&self, count: usize) {
+        self.inner
+            .tracked_count
+            .0
+            .send(count)
+            .expect("registry holds a receiver");
     }
 
     fn track(&self, metadata: T) -> (TrackerId, future::AbortRegistration) {
         let id = TrackerId(self.inner.id.fetch_add(1, Ordering::Relaxed));
         let (abort_handle, abort_registration) = future::AbortHandle::new_pair();
 
-        self.inner.trackers.lock().insert(
+        let mut trackers = self.inner.trackers.lock();
+        trackers.insert(
             id,
             Tracker {
                 abort: abort_handle,
                 data: metadata,
             },
         );
+        self.send_tracked_count(trackers.len());
 
         (id, abort_registration)
     }
@@ -223,6 +251,22 @@ mod tests {
         assert_eq!(reg.tracked().len(), 0);
     }
 
+    #[tokio::test]
+    async fn test_idle() {
+        let (sender, receive) = oneshot::channel();
+        let reg = TrackerRegistry::new();
+        reg.idle().await;
+
+        let task = tokio::spawn(receive.track(&reg, ()));
+        let idle = reg.idle();
+        tokio::pin!(idle);
+        assert!(idle.as_mut().now_or_never().is_none());
+
+        sender.send(()).unwrap();
+        task.await.unwrap().unwrap().unwrap();
+        idle.await;
+    }
+
     #[tokio::test]
     async fn test_terminate() {
         let reg = TrackerRegistry::new();
@@ -0,0 +1,275 @@
+//! Durable write buffer on Kafka.
+//!
+//! With a write buffer, every write a server accepts is produced to a Kafka
+//! topic before it is stored and acknowledged, so that the writes survive
+//! the loss of the server and its disks, and any number of consumers can
+//! follow them independently. The lines of a write are sharded by series
+//! across the partitions of the topic, hashing their series key as
+//! [`router`](crate::router) does, so that the points of a series are
+//! always produced to the same partition and read back in order.
+//!
+//! Each message holds the lines of one shard of a write as a
+//! `ReplicatedWrite` flatbuffer, the format of WAL entries, with the name
+//! of the database as its key.
+//!
+//! As the mutable buffer only lives in memory, a server replays the writes
+//! it produced into it on startup. Replay starts from the server's
+//! checkpoint, the offsets of each partition before which every write is
+//! older than the retention period of all its databases and so no longer
+//! needed; without a checkpoint, or while a database keeps its data
+//! forever, it starts from the oldest offset the topic retains.
+//!
+//! The Kafka client is only built with the `kafka` feature, as it links
+//! librdkafka.
+
+// the errors of the Kafka client are only used with the `kafka` feature
+#![cfg_attr(not(feature = "kafka"), allow(dead_code))]
+
+use std::{collections::BTreeMap, fmt::Debug, sync::Arc};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use data_types::data::ReplicatedWrite;
+use influxdb_line_protocol::ParsedLine;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use snafu::{ensure, Snafu};
+
+use crate::router;
+
+#[cfg(feature = "kafka")]
+mod kafka;
+#[cfg(feature = "kafka")]
+pub use kafka::KafkaWriteBuffer;
+
+/// The error of the client of a write buffer
+pub type ClientError = Box<dyn std::error::Error + Send + Sync>;
+
+/// The name of the file holding the checkpoint of a server
+pub(crate) const CHECKPOINT_FILE_NAME: &str = "write_buffer_checkpoint.json";
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("error creating Kafka client: {}", source))]
+    CreatingClient { source: ClientError },
+
+    #[snafu(display(
+        "this server was built without Kafka support, build it with the 'kafka' feature"
+    ))]
+    KafkaNotBuilt,
+
+    #[snafu(display("a write buffer topic needs at least one partition"))]
+    NoPartitions,
+
+    #[snafu(display(
+        "error producing write to partition {} of topic {}: {}",
+        partition,
+        topic,
+        source
+    ))]
+    Producing {
+        topic: String,
+        partition: u32,
+        source: ClientError,
+    },
+
+    #[snafu(display(
+        "error consuming partition {} of topic {}: {}",
+        partition,
+        topic,
+        source
+    ))]
+    Consuming {
+        topic: String,
+        partition: u32,
+        source: ClientError,
+    },
+
+    #[snafu(display(
+        "message {} of partition {} has no database name or write",
+        offset,
+        partition
+    ))]
+    InvalidMessage { partition: u32, offset: i64 },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// A durable buffer writes are produced to before they are acknowledged
+#[async_trait]
+pub trait WriteBuffer: Debug + Send + Sync {
+    /// The number of partitions writes are sharded across
+    fn partitions(&self) -> u32;
+
+    /// Produces `write` to the database `db_name` to `partition`, returning
+    /// once it is durably stored
+    async fn produce(&self, db_name: &str, partition: u32, write: &ReplicatedWrite) -> Result<()>;
+
+    /// Returns the oldest offset of `partition` still retained and the
+    /// offset of its next message
+    async fn watermarks(&self, partition: u32) -> Result<(i64, i64)>;
+
+    /// Returns the offset of the first message of `partition` produced at
+    /// or after `time`, if any
+    async fn offset_for_time(&self, partition: u32, time: DateTime<Utc>) -> Result<Option<i64>>;
+
+    /// Reads up to `max` messages of `partition` from `offset`, fewer if
+    /// the end of the partition is reached
+    async fn fetch(&self, partition: u32, offset: i64, max: usize) -> Result<Vec<BufferedWrite>>;
+}
+
+/// A write read back from a write buffer
+#[derive(Debug)]
+pub struct BufferedWrite {
+    pub offset: i64,
+    pub db_name: String,
+    pub write: ReplicatedWrite,
+}
+
+/// The checkpoint of a server: the offset of each partition replay starts
+/// from
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Checkpoint {
+    pub offsets: BTreeMap<u32, i64>,
+}
+
+impl Checkpoint {
+    /// Moves the offset of `partition` forward to `offset`
+    pub fn advance(&mut self, partition: u32, offset: i64) {
+        let current = self.offsets.entry(partition).or_insert(offset);
+        *current = (*current).max(offset);
+    }
+}
+
+/// The progress of replaying a partition on startup
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+pub struct PartitionReplay {
+    pub partition: u32,
+    /// The offset of the next message to replay
+    pub next: i64,
+    /// The offset replay stops at, the end of the partition when it started
+    pub end: i64,
+    /// The number of writes of this server replayed so far
+    pub replayed: u64,
+}
+
+impl PartitionReplay {
+    /// The number of messages left to read
+    pub fn remaining(&self) -> u64 {
+        (self.end - self.next).max(0) as u64
+    }
+}
+
+/// The progress of replaying every partition of a write buffer
+#[derive(Debug, Default)]
+pub(crate) struct ReplayProgress {
+    partitions: Mutex<BTreeMap<u32, PartitionReplay>>,
+}
+
+impl ReplayProgress {
+    /// Returns the progress of `partition`, if its replay has started
+    pub(crate) fn get(&self, partition: u32) -> Option<PartitionReplay> {
+        self.partitions.lock().get(&partition).copied()
+    }
+
+    /// Records that `partition` is replayed from `next` to `end`
+    pub(crate) fn start(&self, partition: u32, next: i64, end: i64) {
+        self.partitions.lock().insert(
+            partition,
+            PartitionReplay {
+                partition,
+                next,
+                end,
+                replayed: 0,
+            },
+        );
+    }
+
+    /// Records that the messages of `partition` up to `next` have been
+    /// read, `replayed` of them being writes of this server
+    pub(crate) fn advance(&self, partition: u32, next: i64, replayed: u64) {
+        if let Some(progress) = self.partitions.lock().get_mut(&partition) {
+            progress.next = next;
+            progress.replayed += replayed;
+        }
+    }
+
+    /// Returns the progress of every partition
+    pub(crate) fn partitions(&self) -> Vec<PartitionReplay> {
+        self.partitions.lock().values().copied().collect()
+    }
+}
+
+/// Creates a write buffer on the Kafka topic `topic`, which has `partitions`
+/// partitions, on the Kafka cluster of `brokers`. Fails if the server was
+/// built without the `kafka` feature.
+pub fn kafka(brokers: &[String], topic: &str, partitions: u32) -> Result<Arc<dyn WriteBuffer>> {
+    ensure!(cfg!(feature = "kafka"), KafkaNotBuilt);
+    new_kafka(brokers, topic, partitions)
+}
+
+#[cfg(feature = "kafka")]
+fn new_kafka(brokers: &[String], topic: &str, partitions: u32) -> Result<Arc<dyn WriteBuffer>> {
+    Ok(Arc::new(KafkaWriteBuffer::new(brokers, topic, partitions)?))
+}
+
+#[cfg(not(feature = "kafka"))]
+fn new_kafka(_: &[String], _: &str, _: u32) -> Result<Arc<dyn WriteBuffer>> {
+    unreachable!("the kafka feature is checked first")
+}
+
+/// Returns the partition out of `partitions` the series of `line` is
+/// produced to
+pub(crate) fn partition(line: &ParsedLine<'_>, partitions: u32) -> u32 {
+    (router::hash(router::series_key(line).as_bytes()) % u64::from(partitions)) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use influxdb_line_protocol::parse_lines;
+
+    #[test]
+    fn checkpoint() {
+        let mut checkpoint = Checkpoint::default();
+        checkpoint.advance(0, 10);
+        checkpoint.advance(0, 5);
+        checkpoint.advance(1, 3);
+        assert_eq!(checkpoint.offsets.get(&0), Some(&10));
+
+        let json = serde_json::to_string(&checkpoint).unwrap();
+        assert_eq!(json, r#"{"offsets":{"0":10,"1":3}}"#);
+        assert_eq!(
+            serde_json::from_str::<Checkpoint>(&json).unwrap(),
+            checkpoint
+        );
+
+        let progress = ReplayProgress::default();
+        progress.start(0, 10, 15);
+        progress.advance(0, 12, 1);
+        let partition = progress.get(0).unwrap();
+        assert_eq!(partition.remaining(), 3);
+        assert_eq!(partition.replayed, 1);
+        assert!(progress.get(1).is_none());
+    }
+
+    #[test]
+    fn partitions() {
+        let lp = "cpu,host=a,region=west usage=1 10\n\
+                  cpu,region=west,host=a usage=2 20";
+        let lines: Vec<_> = parse_lines(lp).map(|l| l.unwrap()).collect();
+        assert_eq!(partition(&lines[0], 1), 0);
+        assert_eq!(partition(&lines[0], 8), partition(&lines[1], 8));
+
+        let lp: Vec<_> = (0..100)
+            .map(|i| format!("cpu,host=host{} usage=1 10", i))
+            .collect();
+        let lp = lp.join("\n");
+        let mut used: Vec<_> = parse_lines(&lp)
+            .map(|l| partition(&l.unwrap(), 4))
+            .collect();
+        used.sort_unstable();
+        used.dedup();
+        assert_eq!(used, vec![0, 1, 2, 3]);
+    }
+}
@@ -0,0 +1,215 @@
+//! The write buffer on Kafka, see the [parent module](super) for details.
+use std::{fmt::Debug, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use data_types::data::ReplicatedWrite;
+use rdkafka::{
+    config::ClientConfig,
+    consumer::{BaseConsumer, Consumer},
+    error::KafkaError,
+    producer::{FutureProducer, FutureRecord},
+    Message, Offset, TopicPartitionList,
+};
+use snafu::{OptionExt, ResultExt};
+
+use super::{
+    BufferedWrite, ClientError, Consuming, CreatingClient, InvalidMessage, NoPartitions, Producing,
+    Result, WriteBuffer,
+};
+
+/// How long producing a message may take, including retries
+const MESSAGE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long requests for the metadata of a partition may take
+const METADATA_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long to wait for the next message of a partition while fetching
+const FETCH_TIMEOUT: Duration = Duration::from_secs(1);
+
+fn client_error(e: KafkaError) -> ClientError {
+    Box::new(e)
+}
+
+/// A write buffer on a Kafka topic, as described in the [module docs](super)
+pub struct KafkaWriteBuffer {
+    producer: FutureProducer,
+    /// Reads partitions back, blocking
+    consumer: Arc<BaseConsumer>,
+    topic: String,
+    partitions: u32,
+}
+
+impl Debug for KafkaWriteBuffer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KafkaWriteBuffer")
+            .field("topic", &self.topic)
+            .field("partitions", &self.partitions)
+            .finish()
+    }
+}
+
+impl KafkaWriteBuffer {
+    /// Produces writes to `topic`, which has `partitions` partitions, on the
+    /// Kafka cluster of `brokers`. Messages are only acknowledged once all
+    /// in-sync replicas have them.
+    pub fn new(brokers: &[String], topic: impl Into<String>, partitions: u32) -> Result<Self> {
+        if partitions == 0 {
+            return NoPartitions.fail();
+        }
+
+        let producer = ClientConfig::new()
+            .set("bootstrap.servers", &brokers.join(","))
+            .set("acks", "all")
+            .set("enable.idempotence", "true")
+            .set(
+                "message.timeout.ms",
+                &MESSAGE_TIMEOUT.as_millis().to_string(),
+            )
+            .create()
+            .map_err(client_error)
+            .context(CreatingClient)?;
+        let consumer = ClientConfig::new()
+            .set("bootstrap.servers", &brokers.join(","))
+            .set("group.id", "influxdb_iox_replay")
+            .set("enable.auto.commit", "false")
+            .set("enable.partition.eof", "true")
+            .create()
+            .map_err(client_error)
+            .context(CreatingClient)?;
+
+        Ok(Self {
+            producer,
+            consumer: Arc::new(consumer),
+            topic: topic.into(),
+            partitions,
+        })
+    }
+
+    /// The topic writes are produced to
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+}
+
+#[async_trait]
+impl WriteBuffer for KafkaWriteBuffer {
+    fn partitions(&self) -> u32 {
+        self.partitions
+    }
+
+    async fn produce(&self, db_name: &str, partition: u32, write: &ReplicatedWrite) -> Result<()> {
+        let record = FutureRecord::to(&self.topic)
+            .partition(partition as i32)
+            .key(db_name)
+            .payload(write.bytes().as_slice());
+
+        self.producer
+            .send(record, MESSAGE_TIMEOUT)
+            .await
+            .map(|_| ())
+            .map_err(|(source, _)| client_error(source))
+            .context(Producing {
+                topic: &self.topic,
+                partition,
+            })
+    }
+
+    async fn watermarks(&self, partition: u32) -> Result<(i64, i64)> {
+        let consumer = Arc::clone(&self.consumer);
+        let topic = self.topic.clone();
+        tokio::task::spawn_blocking(move || {
+            consumer
+                .fetch_watermarks(&topic, partition as i32, METADATA_TIMEOUT)
+                .map_err(client_error)
+                .context(Consuming { topic, partition })
+        })
+        .await
+        .expect("watermarks task panicked")
+    }
+
+    async fn offset_for_time(&self, partition: u32, time: DateTime<Utc>) -> Result<Option<i64>> {
+        let consumer = Arc::clone(&self.consumer);
+        let topic = self.topic.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut partitions = TopicPartitionList::new();
+            partitions
+                .add_partition_offset(
+                    &topic,
+                    partition as i32,
+                    Offset::Offset(time.timestamp_millis()),
+                )
+                .map_err(client_error)
+                .context(Consuming {
+                    topic: &topic,
+                    partition,
+                })?;
+            let offsets = consumer
+                .offsets_for_times(partitions, METADATA_TIMEOUT)
+                .map_err(client_error)
+                .context(Consuming {
+                    topic: &topic,
+                    partition,
+                })?;
+
+            Ok(offsets
+                .elements()
+                .into_iter()
+                .find_map(|element| match element.offset() {
+                    Offset::Offset(offset) => Some(offset),
+                    _ => None,
+                }))
+        })
+        .await
+        .expect("offset task panicked")
+    }
+
+    async fn fetch(&self, partition: u32, offset: i64, max: usize) -> Result<Vec<BufferedWrite>> {
+        let consumer = Arc::clone(&self.consumer);
+        let topic = self.topic.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut partitions = TopicPartitionList::new();
+            partitions
+                .add_partition_offset(&topic, partition as i32, Offset::Offset(offset))
+                .map_err(client_error)
+                .context(Consuming {
+                    topic: &topic,
+                    partition,
+                })?;
+            consumer
+                .assign(&partitions)
+                .map_err(client_error)
+                .context(Consuming {
+                    topic: &topic,
+                    partition,
+                })?;
+
+            let mut writes = vec![];
+            while writes.len() < max {
+                let message = match consumer.poll(FETCH_TIMEOUT) {
+                    None | Some(Err(KafkaError::PartitionEOF(_))) => break,
+                    Some(message) => message.map_err(client_error).context(Consuming {
+                        topic: &topic,
+                        partition,
+                    })?,
+                };
+                let offset = message.offset();
+                let db_name = message
+                    .key()
+                    .and_then(|key| std::str::from_utf8(key).ok())
+                    .context(InvalidMessage { partition, offset })?;
+                let payload = message
+                    .payload()
+                    .context(InvalidMessage { partition, offset })?;
+                writes.push(BufferedWrite {
+                    offset,
+                    db_name: db_name.to_string(),
+                    write: ReplicatedWrite::from(payload),
+                });
+            }
+            Ok(writes)
+        })
+        .await
+        .expect("fetch task panicked")
+    }
+}
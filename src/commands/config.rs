@@ -2,15 +2,28 @@
 //! config
 
 use clap::arg_enum;
+use data_types::redaction::Redaction;
+use server::cluster::OnQuorumLoss;
 use std::{net::SocketAddr, net::ToSocketAddrs, path::PathBuf};
 use structopt::StructOpt;
 
+pub mod file;
+
 /// The default bind address for the HTTP API.
 pub const DEFAULT_API_BIND_ADDR: &str = "127.0.0.1:8080";
 
 /// The default bind address for the gRPC.
 pub const DEFAULT_GRPC_BIND_ADDR: &str = "127.0.0.1:8082";
 
+/// The default write timeout of the HTTP API, in seconds.
+pub const DEFAULT_WRITE_TIMEOUT: &str = "30";
+
+/// The default read timeout of the HTTP API, in seconds.
+pub const DEFAULT_READ_TIMEOUT: &str = "300";
+
+/// The default interval between retention checks, in seconds.
+pub const DEFAULT_RETENTION_CHECK_INTERVAL: &str = "60";
+
 #[derive(Debug, StructOpt)]
 #[structopt(
     name = "server",
@@ -18,15 +31,23 @@ pub const DEFAULT_GRPC_BIND_ADDR: &str = "127.0.0.1:8082";
     long_about = "Run the IOx server.\n\nThe configuration options below can be \
     set either with the command line flags or with the specified environment \
     variable. If there is a file named '.env' in the current working directory, \
-    it is sourced before loading the configuration.
+    it is sourced before loading the configuration. Settings can also be \
+    loaded from a TOML file given with `--config`; see \
+    docs/config.example.toml for the settings it supports.
 
 Configuration is loaded from the following sources (highest precedence first):
         - command line arguments
         - user set environment variables
         - .env file contents
+        - config file contents
         - pre-configured default values"
 )]
 pub struct Config {
+    /// Path to a TOML file to load settings from. Settings in the file take
+    /// precedence over the default values only.
+    #[structopt(long = "--config", env = file::CONFIG_FILE_ENV)]
+    pub config_file: Option<PathBuf>,
+
     /// This controls the IOx server logging level, as described in
     /// https://crates.io/crates/env_logger.
     ///
@@ -41,7 +62,14 @@ pub struct Config {
     ///
     /// "rust" (default)
     /// "logfmt" (logfmt/Heroku style - https://brandur.org/logfmt)
-    #[structopt(long = "--log_format", env = "INFLUXDB_IOX_LOG_FORMAT")]
+    /// "json" (one JSON object per line, including the fields of the
+    /// current span such as the HTTP request method, path, org and bucket)
+    /// "pretty" (multi-line, human readable)
+    #[structopt(
+        long = "--log_format",
+        alias = "log-format",
+        env = "INFLUXDB_IOX_LOG_FORMAT"
+    )]
     pub log_format: Option<LogFormat>,
 
     /// This sets logging up with a pre-configured set of convenient log levels.
@@ -70,6 +98,9 @@ pub struct Config {
     pub writer_id: Option<u32>,
 
     /// The address on which IOx will serve HTTP API requests.
+    ///
+    /// Use port 0 to have the OS pick a free port; the chosen address is
+    /// logged on startup.
     #[structopt(
         long = "--api-bind",
         env = "INFLUXDB_IOX_BIND_ADDR",
@@ -78,7 +109,31 @@ pub struct Config {
     )]
     pub http_bind_address: SocketAddr,
 
+    /// If set, the HTTP API is served on a unix socket created at this path
+    /// instead of on `--api-bind`, for clients on the same host. TLS is not
+    /// used on the socket; access is controlled by the permissions of the
+    /// socket file, which follow the umask of the process.
+    #[structopt(long = "--http-unix-socket", env = "INFLUXDB_IOX_HTTP_UNIX_SOCKET")]
+    pub http_unix_socket: Option<PathBuf>,
+
+    /// If set, the operational HTTP endpoints (health and readiness checks,
+    /// `/debug` and the admin endpoints) are served on this address instead
+    /// of with the rest of the API, so that they can be firewalled
+    /// separately. TLS is not used on this address.
+    ///
+    /// Use port 0 to have the OS pick a free port; the chosen address is
+    /// logged on startup.
+    #[structopt(
+        long = "--admin-bind",
+        env = "INFLUXDB_IOX_ADMIN_BIND_ADDR",
+        parse(try_from_str = parse_socket_addr),
+    )]
+    pub admin_bind_address: Option<SocketAddr>,
+
     /// The address on which IOx will serve Storage gRPC API requests.
+    ///
+    /// Use port 0 to have the OS pick a free port; the chosen address is
+    /// logged on startup.
     #[structopt(
         long = "--grpc-bind",
         env = "INFLUXDB_IOX_GRPC_BIND_ADDR",
@@ -87,6 +142,625 @@ pub struct Config {
     )]
     pub grpc_bind_address: SocketAddr,
 
+    /// Comma separated IP addresses and CIDR ranges (e.g. `10.0.0.0/8`) the
+    /// HTTP API accepts requests from. Requests from other addresses fail
+    /// with `403 Forbidden`. If not set, requests from any address not in
+    /// `--api-denied-ips` are accepted. Not applied to `--http-unix-socket`.
+    #[structopt(
+        long = "--api-allowed-ips",
+        env = "INFLUXDB_IOX_API_ALLOWED_IPS",
+        use_delimiter = true
+    )]
+    pub api_allowed_ips: Vec<String>,
+
+    /// Comma separated IP addresses and CIDR ranges the HTTP API rejects
+    /// requests from, even if they are in `--api-allowed-ips`.
+    #[structopt(
+        long = "--api-denied-ips",
+        env = "INFLUXDB_IOX_API_DENIED_IPS",
+        use_delimiter = true
+    )]
+    pub api_denied_ips: Vec<String>,
+
+    /// Like `--api-allowed-ips`, for the operational endpoints served on
+    /// `--admin-bind`. Without `--admin-bind` they are served with the
+    /// rest of the API and `--api-allowed-ips` applies.
+    #[structopt(
+        long = "--admin-allowed-ips",
+        env = "INFLUXDB_IOX_ADMIN_ALLOWED_IPS",
+        use_delimiter = true
+    )]
+    pub admin_allowed_ips: Vec<String>,
+
+    /// Like `--api-denied-ips`, for the operational endpoints served on
+    /// `--admin-bind`.
+    #[structopt(
+        long = "--admin-denied-ips",
+        env = "INFLUXDB_IOX_ADMIN_DENIED_IPS",
+        use_delimiter = true
+    )]
+    pub admin_denied_ips: Vec<String>,
+
+    /// Comma separated IP addresses and CIDR ranges the gRPC API accepts
+    /// connections from. Connections from other addresses are closed. If
+    /// not set, connections from any address not in `--grpc-denied-ips` are
+    /// accepted.
+    #[structopt(
+        long = "--grpc-allowed-ips",
+        env = "INFLUXDB_IOX_GRPC_ALLOWED_IPS",
+        use_delimiter = true
+    )]
+    pub grpc_allowed_ips: Vec<String>,
+
+    /// Comma separated IP addresses and CIDR ranges the gRPC API closes
+    /// connections from, even if they are in `--grpc-allowed-ips`.
+    #[structopt(
+        long = "--grpc-denied-ips",
+        env = "INFLUXDB_IOX_GRPC_DENIED_IPS",
+        use_delimiter = true
+    )]
+    pub grpc_denied_ips: Vec<String>,
+
+    /// How long, in seconds, to wait after receiving SIGTERM or SIGINT for
+    /// in-flight HTTP and gRPC requests to complete and for open WAL
+    /// segments to be persisted before exiting anyway.
+    #[structopt(
+        long = "--shutdown-timeout",
+        env = "INFLUXDB_IOX_SHUTDOWN_TIMEOUT",
+        default_value = "30"
+    )]
+    pub shutdown_timeout_seconds: u64,
+
+    /// HTTP responses with bodies of at least this many bytes are gzip or
+    /// deflate compressed when the client sends a matching
+    /// `Accept-Encoding` header.
+    #[structopt(
+        long = "--http-compression-min-size",
+        env = "INFLUXDB_IOX_HTTP_COMPRESSION_MIN_SIZE",
+        default_value = "1024"
+    )]
+    pub http_compression_min_size: usize,
+
+    /// Requests to the HTTP API with bodies larger than this many bytes,
+    /// after decompression, are rejected. This bounds the memory used to
+    /// buffer each request.
+    #[structopt(
+        long = "--max-http-request-size",
+        env = "INFLUXDB_IOX_MAX_HTTP_REQUEST_SIZE",
+        default_value = "10485760"
+    )]
+    pub max_http_request_size: usize,
+
+    /// The maximum number of concurrent requests (streams) a client may
+    /// make over one HTTP/2 connection to the HTTP API. There is no limit if
+    /// not set.
+    #[structopt(
+        long = "--http2-max-concurrent-streams",
+        env = "INFLUXDB_IOX_HTTP2_MAX_CONCURRENT_STREAMS"
+    )]
+    pub http2_max_concurrent_streams: Option<u32>,
+
+    /// How often, in seconds, to send HTTP/2 PING frames to keep idle
+    /// connections to the HTTP API alive and detect dead ones. 0 disables
+    /// the pings.
+    #[structopt(
+        long = "--http2-keepalive-interval",
+        env = "INFLUXDB_IOX_HTTP2_KEEPALIVE_INTERVAL",
+        default_value = "0"
+    )]
+    pub http2_keepalive_interval_seconds: u64,
+
+    /// How long, in seconds, to wait for a PING to be acknowledged before
+    /// closing the connection.
+    #[structopt(
+        long = "--http2-keepalive-timeout",
+        env = "INFLUXDB_IOX_HTTP2_KEEPALIVE_TIMEOUT",
+        default_value = "20"
+    )]
+    pub http2_keepalive_timeout_seconds: u64,
+
+    /// Token required by the admin endpoints of the HTTP API, such as
+    /// `POST /api/v2/partitions/{partition}/persist`. Requests must send it
+    /// in an `Authorization: Token <token>` header. The admin endpoints are
+    /// disabled if not set.
+    #[structopt(long = "--admin-token", env = "INFLUXDB_IOX_ADMIN_TOKEN")]
+    pub admin_token: Option<String>,
+
+    /// Comma separated tokens accepted by the data endpoints of the HTTP API
+    /// and by the gRPC services. Requests must send one in an
+    /// `Authorization: Token <token>` header. Requests are not authenticated
+    /// if not set.
+    #[structopt(
+        long = "--api-tokens",
+        env = "INFLUXDB_IOX_API_TOKENS",
+        use_delimiter = true
+    )]
+    pub api_tokens: Vec<String>,
+
+    /// Comma separated tokens like `--api-tokens` that may only read and
+    /// query data, e.g. for dashboards. Setting any enables authentication.
+    #[structopt(
+        long = "--api-read-tokens",
+        env = "INFLUXDB_IOX_API_READ_TOKENS",
+        use_delimiter = true
+    )]
+    pub api_read_tokens: Vec<String>,
+
+    /// Comma separated tokens like `--api-tokens` that may only write and
+    /// delete data, e.g. for ingestion agents. Setting any enables
+    /// authentication.
+    #[structopt(
+        long = "--api-write-tokens",
+        env = "INFLUXDB_IOX_API_WRITE_TOKENS",
+        use_delimiter = true
+    )]
+    pub api_write_tokens: Vec<String>,
+
+    /// The URL of an OpenID Connect issuer whose JWTs are accepted as
+    /// `Authorization: Bearer <jwt>` tokens, e.g. `https://sso.example.com`.
+    /// Setting it enables authentication. Its signing keys are read from
+    /// its discovery document on startup.
+    #[structopt(long = "--oidc-issuer", env = "INFLUXDB_IOX_OIDC_ISSUER")]
+    pub oidc_issuer: Option<String>,
+
+    /// The audience the JWTs of `--oidc-issuer` must be issued for, if
+    /// any.
+    #[structopt(long = "--oidc-audience", env = "INFLUXDB_IOX_OIDC_AUDIENCE")]
+    pub oidc_audience: Option<String>,
+
+    /// The claim of the JWTs of `--oidc-issuer` naming the org they may be
+    /// used in. JWTs without it are rejected.
+    #[structopt(
+        long = "--oidc-org-claim",
+        env = "INFLUXDB_IOX_OIDC_ORG_CLAIM",
+        default_value = "org"
+    )]
+    pub oidc_org_claim: String,
+
+    /// The claim of the JWTs of `--oidc-issuer` holding their scopes, a
+    /// space separated string or an array of strings. Its `read`, `write`
+    /// and `admin` values are used like the scopes of API tokens.
+    #[structopt(
+        long = "--oidc-scopes-claim",
+        env = "INFLUXDB_IOX_OIDC_SCOPES_CLAIM",
+        default_value = "scope"
+    )]
+    pub oidc_scopes_claim: String,
+
+    /// How often, in seconds, the signing keys of `--oidc-issuer` are read
+    /// again, so that rotated keys are accepted without a restart.
+    #[structopt(
+        long = "--oidc-refresh-interval",
+        env = "INFLUXDB_IOX_OIDC_REFRESH_INTERVAL",
+        default_value = "300"
+    )]
+    pub oidc_refresh_interval_seconds: u64,
+
+    /// Writes and deletes through the HTTP API taking longer than this many
+    /// seconds, including the time to receive the request body, are aborted
+    /// with `504 Gateway Timeout` (or `408 Request Timeout` if the body
+    /// was not received in time). 0 disables the timeout.
+    #[structopt(
+        long = "--write-timeout",
+        env = "INFLUXDB_IOX_WRITE_TIMEOUT",
+        default_value = DEFAULT_WRITE_TIMEOUT
+    )]
+    pub write_timeout_seconds: u64,
+
+    /// Queries through the HTTP API taking longer than this many seconds are
+    /// aborted with `504 Gateway Timeout`. 0 disables the timeout.
+    #[structopt(
+        long = "--read-timeout",
+        env = "INFLUXDB_IOX_READ_TIMEOUT",
+        default_value = DEFAULT_READ_TIMEOUT
+    )]
+    pub read_timeout_seconds: u64,
+
+    /// The approximate number of bytes each query may hold in memory, for
+    /// sorting, aggregating and collecting its results. Queries needing
+    /// more fail with a "memory limit exceeded" error. 0 disables the
+    /// limit.
+    #[structopt(
+        long = "--query-memory-limit",
+        env = "INFLUXDB_IOX_QUERY_MEMORY_LIMIT",
+        default_value = "0"
+    )]
+    pub query_memory_limit: usize,
+
+    /// The number of plans of a query, such as the scans of the partitions
+    /// a `read_filter` covers, run at a time on separate tasks. 0 runs as
+    /// many as there are cores.
+    #[structopt(
+        long = "--query-scan-concurrency",
+        env = "INFLUXDB_IOX_QUERY_SCAN_CONCURRENCY",
+        default_value = "0"
+    )]
+    pub query_scan_concurrency: usize,
+
+    /// The number of threads running the plans of queries, separate from
+    /// those handling requests and writes (see `--num-threads`) so that
+    /// large scans don't delay them. 0 uses as many as there are cores.
+    #[structopt(
+        long = "--query-threads",
+        env = "INFLUXDB_IOX_QUERY_THREADS",
+        default_value = "0"
+    )]
+    pub query_threads: usize,
+
+    /// The approximate number of bytes the server may hold in memory, in
+    /// its buffers, caches and queries. From 90% of the limit the mutable
+    /// buffer is compacted and the caches emptied, and at the limit writes
+    /// and queries are rejected with `503 Service Unavailable`.
+    #[structopt(long = "--memory-limit", env = "INFLUXDB_IOX_MEMORY_LIMIT")]
+    pub memory_limit: Option<u64>,
+
+    /// The number of series each org may have in memory, across its
+    /// buckets. Writes to an org at the limit are rejected with `429 Too
+    /// Many Requests` until some of its data is dropped.
+    #[structopt(long = "--org-series-limit", env = "INFLUXDB_IOX_ORG_SERIES_LIMIT")]
+    pub org_series_limit: Option<u64>,
+
+    /// The number of series of an org above which a warning is logged,
+    /// ahead of `--org-series-limit`.
+    #[structopt(
+        long = "--org-series-soft-limit",
+        env = "INFLUXDB_IOX_ORG_SERIES_SOFT_LIMIT"
+    )]
+    pub org_series_soft_limit: Option<u64>,
+
+    /// The approximate number of bytes each org may have in memory, across
+    /// its buckets, enforced like `--org-series-limit`.
+    #[structopt(long = "--org-bytes-limit", env = "INFLUXDB_IOX_ORG_BYTES_LIMIT")]
+    pub org_bytes_limit: Option<u64>,
+
+    /// The number of bytes of an org above which a warning is logged, ahead
+    /// of `--org-bytes-limit`.
+    #[structopt(
+        long = "--org-bytes-soft-limit",
+        env = "INFLUXDB_IOX_ORG_BYTES_SOFT_LIMIT"
+    )]
+    pub org_bytes_soft_limit: Option<u64>,
+
+    /// The number of compactions and snapshots run at a time. Jobs beyond
+    /// that wait, with each org taking turns starting its next one.
+    #[structopt(
+        long = "--max-background-jobs",
+        env = "INFLUXDB_IOX_MAX_BACKGROUND_JOBS",
+        default_value = "4"
+    )]
+    pub max_background_jobs: usize,
+
+    /// The bytes per second compactions and snapshots may move, in total,
+    /// so that they leave the disk and object store bandwidth queries and
+    /// writes need. Unlimited if unset or 0.
+    #[structopt(
+        long = "--background-io-bytes-per-second",
+        env = "INFLUXDB_IOX_BACKGROUND_IO_BYTES_PER_SECOND"
+    )]
+    pub background_io_bytes_per_second: Option<u64>,
+
+    /// The number of files snapshots may write at once, in total. Unlimited
+    /// if unset or 0.
+    #[structopt(
+        long = "--background-io-concurrent-files",
+        env = "INFLUXDB_IOX_BACKGROUND_IO_CONCURRENT_FILES"
+    )]
+    pub background_io_concurrent_files: Option<u32>,
+
+    /// Comma separated gRPC addresses of the peer servers every accepted
+    /// write is replicated to, as `host:port` or `http://host:port`. Writes
+    /// are acknowledged once `--write-quorum` servers have stored them.
+    /// Peers given as `https://host:port` are reached over TLS, presenting
+    /// `--tls-cert` to those requiring client certificates and verifying
+    /// them against `--grpc-tls-client-ca`.
+    #[structopt(
+        long = "--replication-peers",
+        env = "INFLUXDB_IOX_REPLICATION_PEERS",
+        use_delimiter = true
+    )]
+    pub replication_peers: Vec<String>,
+
+    /// The number of servers, this one included, that must have stored a
+    /// write before it is acknowledged, when replicating writes to
+    /// `--replication-peers`. Defaults to a majority of the servers.
+    #[structopt(long = "--write-quorum", env = "INFLUXDB_IOX_WRITE_QUORUM")]
+    pub write_quorum: Option<usize>,
+
+    /// The API token sent to `--replication-peers`, if they require
+    /// authentication. As it is also sent to their cluster service, it must
+    /// be one that may administer them, such as one of their `--api-tokens`.
+    #[structopt(long = "--replication-token", env = "INFLUXDB_IOX_REPLICATION_TOKEN")]
+    pub replication_token: Option<String>,
+
+    /// How long, in seconds, connecting to a peer server may take.
+    #[structopt(
+        long = "--replication-connect-timeout",
+        env = "INFLUXDB_IOX_REPLICATION_CONNECT_TIMEOUT",
+        default_value = "5"
+    )]
+    pub replication_connect_timeout_seconds: u64,
+
+    /// How long, in seconds, each request to a peer server may take, and
+    /// writes wait for the `--write-quorum` before failing.
+    #[structopt(
+        long = "--replication-timeout",
+        env = "INFLUXDB_IOX_REPLICATION_TIMEOUT",
+        default_value = "10"
+    )]
+    pub replication_timeout_seconds: u64,
+
+    /// How often, in seconds, to compare the writes of each database with
+    /// those of the `--replication-peers` and send them the writes they
+    /// miss. 0 disables anti-entropy repair.
+    #[structopt(
+        long = "--anti-entropy-interval",
+        env = "INFLUXDB_IOX_ANTI_ENTROPY_INTERVAL",
+        default_value = "60"
+    )]
+    pub anti_entropy_interval_seconds: u64,
+
+    /// The maximum size, in bytes, of the writes kept for each of the
+    /// `--replication-peers` failing to store them, to send it again once
+    /// it is back. The oldest writes are dropped beyond it. 0 disables
+    /// hinted handoff.
+    #[structopt(
+        long = "--hinted-handoff-max-size",
+        env = "INFLUXDB_IOX_HINTED_HANDOFF_MAX_SIZE",
+        default_value = "104857600"
+    )]
+    pub hinted_handoff_max_size: usize,
+
+    /// How long, in seconds, the writes kept for a peer failing to store
+    /// them are kept at most.
+    #[structopt(
+        long = "--hinted-handoff-max-age",
+        env = "INFLUXDB_IOX_HINTED_HANDOFF_MAX_AGE",
+        default_value = "10800"
+    )]
+    pub hinted_handoff_max_age_seconds: u64,
+
+    /// Run as a read replica of the server with this writer id: writes are
+    /// rejected, and the databases of that server are followed through the
+    /// WAL segments it persists to the shared object store.
+    #[structopt(long = "--replica-of", env = "INFLUXDB_IOX_REPLICA_OF")]
+    pub replica_of: Option<u32>,
+
+    /// How often, in seconds, a read replica checks object storage for the
+    /// WAL segments persisted by its primary.
+    #[structopt(
+        long = "--replica-poll-interval",
+        env = "INFLUXDB_IOX_REPLICA_POLL_INTERVAL",
+        default_value = "5"
+    )]
+    pub replica_poll_interval_seconds: u64,
+
+    /// Comma separated gRPC addresses of the servers to shard writes
+    /// across. When set, the server stores no writes itself: each line is
+    /// sent to the server its series hashes to.
+    #[structopt(
+        long = "--route-writes-to",
+        env = "INFLUXDB_IOX_ROUTE_WRITES_TO",
+        use_delimiter = true
+    )]
+    pub route_writes_to: Vec<String>,
+
+    /// Comma separated addresses of the Kafka brokers to produce every
+    /// accepted write to before acknowledging it, as `host:port`. When set,
+    /// writes are durable once in Kafka rather than on the server's disks.
+    #[structopt(
+        long = "--write-buffer-brokers",
+        env = "INFLUXDB_IOX_WRITE_BUFFER_BROKERS",
+        use_delimiter = true
+    )]
+    pub write_buffer_brokers: Vec<String>,
+
+    /// The Kafka topic writes are produced to, when
+    /// `--write-buffer-brokers` is set.
+    #[structopt(
+        long = "--write-buffer-topic",
+        env = "INFLUXDB_IOX_WRITE_BUFFER_TOPIC",
+        default_value = "influxdb_iox_writes"
+    )]
+    pub write_buffer_topic: String,
+
+    /// The number of partitions of `--write-buffer-topic`, which writes are
+    /// sharded across by series.
+    #[structopt(
+        long = "--write-buffer-partitions",
+        env = "INFLUXDB_IOX_WRITE_BUFFER_PARTITIONS",
+        default_value = "1"
+    )]
+    pub write_buffer_partitions: u32,
+
+    /// The gRPC address other servers reach this one at, as `host:port` or
+    /// `http://host:port`. When set, the server joins the cluster: it
+    /// gossips its state with the other members and lists them at
+    /// `/cluster/members`.
+    #[structopt(long = "--gossip-address", env = "INFLUXDB_IOX_GOSSIP_ADDRESS")]
+    pub gossip_address: Option<String>,
+
+    /// Comma separated gRPC addresses of the servers first gossiped with
+    /// to join the cluster. Any member of the cluster will do.
+    #[structopt(
+        long = "--gossip-seeds",
+        env = "INFLUXDB_IOX_GOSSIP_SEEDS",
+        use_delimiter = true
+    )]
+    pub gossip_seeds: Vec<String>,
+
+    /// How often, in seconds, to gossip with other members of the cluster.
+    /// Members not heard of for several intervals are reported as down.
+    #[structopt(
+        long = "--gossip-interval",
+        env = "INFLUXDB_IOX_GOSSIP_INTERVAL",
+        default_value = "1"
+    )]
+    pub gossip_interval_seconds: u64,
+
+    /// The number of members the cluster is expected to have. When set,
+    /// a server that sees fewer than a majority of them alive, itself
+    /// included, has lost the quorum and does as `--on-quorum-loss` says,
+    /// rather than diverging from the rest of the cluster. 0 disables the
+    /// check.
+    #[structopt(
+        long = "--cluster-size",
+        env = "INFLUXDB_IOX_CLUSTER_SIZE",
+        default_value = "0"
+    )]
+    pub cluster_size: usize,
+
+    /// What a server does while it has lost the quorum: "reject-writes"
+    /// (rejects writes and catalog changes) or "warn" (keeps accepting
+    /// them). Either way, responses carry a `Warning` header saying they
+    /// may be stale.
+    #[structopt(
+        long = "--on-quorum-loss",
+        env = "INFLUXDB_IOX_ON_QUORUM_LOSS",
+        default_value = "reject-writes"
+    )]
+    pub on_quorum_loss: OnQuorumLoss,
+
+    /// How often, in seconds, to drop the data older than the retention
+    /// period of each database. 0 disables dropping expired data.
+    #[structopt(
+        long = "--retention-check-interval",
+        env = "INFLUXDB_IOX_RETENTION_CHECK_INTERVAL",
+        default_value = DEFAULT_RETENTION_CHECK_INTERVAL
+    )]
+    pub retention_check_interval_seconds: u64,
+
+    /// Elect a single server to compact each partition and drop its
+    /// expired data among the servers sharing the object store, through
+    /// leases lasting this many seconds. Should be a few times
+    /// `--retention-check-interval`, which is how often leases are renewed.
+    #[structopt(
+        long = "--partition-lease-duration",
+        env = "INFLUXDB_IOX_PARTITION_LEASE_DURATION"
+    )]
+    pub partition_lease_duration_seconds: Option<u64>,
+
+    /// The fraction of successful queries, between 0 and 1, written to the
+    /// query log along with their normalized shape and duration. Failed
+    /// queries are always logged. The log uses the `query_log` target.
+    #[structopt(
+        long = "--query-log-sample-rate",
+        env = "INFLUXDB_IOX_QUERY_LOG_SAMPLE_RATE",
+        default_value = "0.01",
+        parse(try_from_str = parse_sample_rate)
+    )]
+    pub query_log_sample_rate: f64,
+
+    /// How tag values and field contents are shown in logs, traces and
+    /// error messages: "off" (as they are), "hash" (replaced by a hash, so
+    /// that messages about the same value can still be correlated) or
+    /// "redact" (replaced by `<redacted>`). Measurement, tag and field names
+    /// stay visible.
+    #[structopt(
+        long = "--redact-values",
+        env = "INFLUXDB_IOX_REDACT_VALUES",
+        default_value = "off"
+    )]
+    pub redact_values: Redaction,
+
+    /// If set, administrative actions (creating, changing and deleting
+    /// buckets, orgs, databases and tokens, deleting data and changing
+    /// settings) are appended to this file as one JSON object per line, with
+    /// who took them, when and from where. They are also logged with the
+    /// `audit` target.
+    #[structopt(long = "--audit-log", env = "INFLUXDB_IOX_AUDIT_LOG")]
+    pub audit_log_path: Option<PathBuf>,
+
+    /// The number of responses to `/api/v2/read` cached, so that dashboards
+    /// refreshing the same reads don't rerun them until data is written in
+    /// their time range. 0 disables the cache.
+    #[structopt(
+        long = "--read-cache-size",
+        env = "INFLUXDB_IOX_READ_CACHE_SIZE",
+        default_value = "0"
+    )]
+    pub read_cache_size: usize,
+
+    /// How long, in seconds, cached read responses are reused at most.
+    /// Reads of a range relative to now may lag by up to this long.
+    #[structopt(
+        long = "--read-cache-ttl",
+        env = "INFLUXDB_IOX_READ_CACHE_TTL",
+        default_value = "60"
+    )]
+    pub read_cache_ttl_seconds: u64,
+
+    /// Comma separated list of origins (e.g. `https://ui.example.com`)
+    /// browsers may make cross-origin (CORS) requests to the HTTP API from.
+    /// `*` allows any origin. CORS is disabled if not set.
+    #[structopt(
+        long = "--cors-allowed-origins",
+        env = "INFLUXDB_IOX_CORS_ALLOWED_ORIGINS",
+        use_delimiter = true
+    )]
+    pub cors_allowed_origins: Vec<String>,
+
+    /// Comma separated list of methods allowed in CORS requests.
+    #[structopt(
+        long = "--cors-allowed-methods",
+        env = "INFLUXDB_IOX_CORS_ALLOWED_METHODS",
+        default_value = "GET,HEAD,POST,PUT,PATCH,DELETE",
+        use_delimiter = true
+    )]
+    pub cors_allowed_methods: Vec<String>,
+
+    /// Comma separated list of request headers allowed in CORS requests.
+    #[structopt(
+        long = "--cors-allowed-headers",
+        env = "INFLUXDB_IOX_CORS_ALLOWED_HEADERS",
+        default_value = "Authorization,Content-Type,Content-Encoding",
+        use_delimiter = true
+    )]
+    pub cors_allowed_headers: Vec<String>,
+
+    /// How long, in seconds, browsers may cache the result of a CORS
+    /// preflight request.
+    #[structopt(
+        long = "--cors-max-age",
+        env = "INFLUXDB_IOX_CORS_MAX_AGE",
+        default_value = "600"
+    )]
+    pub cors_max_age_seconds: u64,
+
+    /// Path to a PEM encoded certificate chain. When set together with
+    /// `--tls-key`, both the HTTP and gRPC APIs are served over TLS.
+    #[structopt(long = "--tls-cert", env = "INFLUXDB_IOX_TLS_CERT")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// Path to the PEM encoded (PKCS8 or RSA) private key for `--tls-cert`.
+    #[structopt(long = "--tls-key", env = "INFLUXDB_IOX_TLS_KEY")]
+    pub tls_key: Option<PathBuf>,
+
+    /// If set, the TLS certificate and key are reloaded from disk every
+    /// this many seconds so that rotated certificates are used for new
+    /// connections without a restart.
+    #[structopt(
+        long = "--tls-reload-interval",
+        env = "INFLUXDB_IOX_TLS_RELOAD_INTERVAL"
+    )]
+    pub tls_reload_interval_seconds: Option<u64>,
+
+    /// Path to a PEM encoded bundle of CA certificates. When set, clients of
+    /// the gRPC API must present a certificate signed by one of them.
+    /// Requires `--tls-cert` and `--tls-key`.
+    #[structopt(long = "--grpc-tls-client-ca", env = "INFLUXDB_IOX_GRPC_TLS_CLIENT_CA")]
+    pub grpc_tls_client_ca: Option<PathBuf>,
+
+    /// Comma separated DNS names of the gRPC clients allowed to connect.
+    /// When set, a client certificate must also be valid for one of these
+    /// names (per its subject alternative names). Requires
+    /// `--grpc-tls-client-ca`.
+    #[structopt(
+        long = "--grpc-tls-allowed-clients",
+        env = "INFLUXDB_IOX_GRPC_TLS_ALLOWED_CLIENTS",
+        use_delimiter = true
+    )]
+    pub grpc_tls_allowed_clients: Vec<String>,
+
     /// The location InfluxDB IOx will use to store files locally.
     #[structopt(long = "--data-dir", env = "INFLUXDB_IOX_DB_DIR")]
     pub database_directory: Option<PathBuf>,
@@ -123,6 +797,113 @@ Possible values (case insensitive):
     #[structopt(long = "--bucket", env = "INFLUXDB_IOX_BUCKET")]
     pub bucket: Option<String>,
 
+    /// Which object storage to mirror the object store to, such as a bucket
+    /// in another region, so that the data persisted in the object store
+    /// survives the loss of its region. Takes the same values as
+    /// `--object-store`; the secondary object store must not be used for
+    /// anything else. If not specified, the object store isn't mirrored.
+    #[structopt(
+        long = "--secondary-object-store",
+        env = "INFLUXDB_IOX_SECONDARY_OBJECT_STORE",
+        possible_values = &ObjectStore::variants(),
+        case_insensitive = true
+    )]
+    pub secondary_object_store: Option<ObjectStore>,
+
+    /// Name of the bucket of the secondary object store, for cloud object
+    /// storage.
+    #[structopt(long = "--secondary-bucket", env = "INFLUXDB_IOX_SECONDARY_BUCKET")]
+    pub secondary_bucket: Option<String>,
+
+    /// The directory of the secondary object store, for `file`.
+    #[structopt(long = "--secondary-data-dir", env = "INFLUXDB_IOX_SECONDARY_DB_DIR")]
+    pub secondary_database_directory: Option<PathBuf>,
+
+    /// The AWS region of the bucket of the secondary object store, for
+    /// `s3`. If not specified, AWS_DEFAULT_REGION is used.
+    #[structopt(
+        long = "--secondary-s3-region",
+        env = "INFLUXDB_IOX_SECONDARY_S3_REGION"
+    )]
+    pub secondary_s3_region: Option<String>,
+
+    /// How often, in seconds, the object store is mirrored to the secondary
+    /// object store. The data persisted since the last sync would be lost
+    /// with the region of the object store.
+    #[structopt(
+        long = "--secondary-sync-interval",
+        env = "INFLUXDB_IOX_SECONDARY_SYNC_INTERVAL",
+        default_value = "300"
+    )]
+    pub secondary_sync_interval_seconds: u64,
+
+    /// Where to read secrets from, instead of the command line and the
+    /// environment variables listed for `--object-store`. If not specified,
+    /// no secrets provider is used.
+    ///
+    /// Possible values (case insensitive):
+    ///
+    /// * env: environment variables named after the secrets in uppercase.
+    ///
+    /// * file: files named after the secrets in `--secrets-dir`, as mounted by
+    ///   Kubernetes and Docker secrets. Rewriting a file rotates the secret.
+    ///
+    /// * aws: the S3 credentials of the IAM role of the EC2 instance, from its
+    ///   instance metadata service.
+    ///
+    /// S3 credentials are read from the `aws_access_key_id`,
+    /// `aws_secret_access_key` and `aws_session_token` secrets for each
+    /// request, and the `api_tokens`, `api_read_tokens` and
+    /// `api_write_tokens` secrets are comma separated tokens added to those
+    /// of `--api-tokens`, `--api-read-tokens` and `--api-write-tokens`.
+    #[structopt(
+        long = "--secrets-provider",
+        env = "INFLUXDB_IOX_SECRETS_PROVIDER",
+        possible_values = &SecretsProvider::variants(),
+        case_insensitive = true
+    )]
+    pub secrets_provider: Option<SecretsProvider>,
+
+    /// The directory the `file` secrets provider reads the secrets from.
+    #[structopt(long = "--secrets-dir", env = "INFLUXDB_IOX_SECRETS_DIR")]
+    pub secrets_dir: Option<PathBuf>,
+
+    /// How often, in seconds, the API tokens and encryption keys are read
+    /// again from the secrets provider, so that rotated tokens and keys are
+    /// used without a restart.
+    #[structopt(
+        long = "--secrets-refresh-interval",
+        env = "INFLUXDB_IOX_SECRETS_REFRESH_INTERVAL",
+        default_value = "60"
+    )]
+    pub secrets_refresh_interval_seconds: u64,
+
+    /// The secret of `--secrets-provider` holding the keys to encrypt the
+    /// chunk files and WAL segments written to object storage with. If not
+    /// specified, they are not encrypted.
+    ///
+    /// The secret holds comma separated, hex encoded 256 bit keys (e.g.
+    /// from `openssl rand -hex 32`). The first one encrypts new files; the
+    /// others are only used to read files, so keys can be rotated by adding
+    /// a new key in front.
+    #[structopt(
+        long = "--encryption-key-secret",
+        env = "INFLUXDB_IOX_ENCRYPTION_KEY_SECRET"
+    )]
+    pub encryption_key_secret: Option<String>,
+
+    /// Whether the chunk files and WAL segments that are not encrypted can
+    /// still be read when `--encryption-key-secret` is set. Only meant for
+    /// migrating data written before encryption was enabled: otherwise
+    /// unencrypted files are rejected.
+    #[structopt(
+        long = "--encryption-allow-plaintext",
+        env = "INFLUXDB_IOX_ENCRYPTION_ALLOW_PLAINTEXT",
+        parse(try_from_str),
+        default_value = "false"
+    )]
+    pub encryption_allow_plaintext: bool,
+
     /// If set, Jaeger traces are emitted to this host
     /// using the OpenTelemetry tracer.
     ///
@@ -160,6 +941,20 @@ pub fn load_config() -> Config {
     Config::from_iter(strip_server(std::env::args()).iter())
 }
 
+/// Loads the config again from the command line and the environment, as
+/// `load_config` does, for reloading settings while the server runs.
+/// Unlike `load_config`, invalid settings are returned as an error rather
+/// than exiting.
+pub fn reload_config() -> Result<Config, clap::Error> {
+    Config::from_iter_safe(reload_args().iter())
+}
+
+/// The command line arguments the config is loaded from, without those
+/// before "server"
+pub fn reload_args() -> Vec<String> {
+    strip_server(std::env::args())
+}
+
 fn parse_socket_addr(s: &str) -> std::io::Result<SocketAddr> {
     let mut addrs = s.to_socket_addrs()?;
     // when name resolution fails, to_socket_address returns a validation error
@@ -170,6 +965,13 @@ fn parse_socket_addr(s: &str) -> std::io::Result<SocketAddr> {
         .expect("name resolution should return at least one address"))
 }
 
+fn parse_sample_rate(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(rate) if (0.0..=1.0).contains(&rate) => Ok(rate),
+        _ => Err(format!("expected a number between 0 and 1, got {:?}", s)),
+    }
+}
+
 /// Strip everything prior to the "server" portion of the args so the generated
 /// Clap instance plays nicely with the subcommand bits in main.
 fn strip_server(args: impl Iterator<Item = String>) -> Vec<String> {
@@ -199,6 +1001,15 @@ arg_enum! {
     }
 }
 
+arg_enum! {
+    #[derive(Debug, Copy, Clone, PartialEq)]
+    pub enum SecretsProvider {
+        Env,
+        File,
+        Aws,
+    }
+}
+
 /// How to format output logging messages
 #[derive(Debug, Clone, Copy)]
 pub enum LogFormat {
@@ -218,6 +1029,17 @@ pub enum LogFormat {
     /// Jan 31 13:19:39.059  WARN influxdb_iox::influxdb_ioxd: NO PERSISTENCE: using memory for object storage
     /// ```
     LogFmt,
+
+    /// Newline delimited JSON, suitable for log aggregation systems
+    ///
+    /// Example:
+    /// ```
+    /// {"timestamp":"Jan 31 13:19:39.059","level":"WARN","message":"NO PERSISTENCE: using memory for object storage","target":"influxdb_iox::influxdb_ioxd"}
+    /// ```
+    Json,
+
+    /// Multi-line human readable output, useful when developing
+    Pretty,
 }
 
 impl Default for LogFormat {
@@ -233,8 +1055,10 @@ impl std::str::FromStr for LogFormat {
         match s.to_ascii_lowercase().as_str() {
             "rust" => Ok(Self::Rust),
             "logfmt" => Ok(Self::LogFmt),
+            "json" => Ok(Self::Json),
+            "pretty" => Ok(Self::Pretty),
             _ => Err(format!(
-                "Invalid log format '{}'. Valid options: rust, logfmt",
+                "Invalid log format '{}'. Valid options: rust, logfmt, json, pretty",
                 s
             )),
         }
@@ -335,4 +1159,109 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_bind_any_port() -> Result<(), clap::Error> {
+        let c = Config::from_iter_safe(strip_server(
+            to_vec(&[
+                "cmd",
+                "server",
+                "--api-bind",
+                "127.0.0.1:0",
+                "--grpc-bind",
+                "0.0.0.0:0",
+            ])
+            .into_iter(),
+        ))?;
+        assert_eq!(c.http_bind_address, SocketAddr::from(([127, 0, 0, 1], 0)));
+        assert_eq!(c.grpc_bind_address, SocketAddr::from(([0, 0, 0, 0], 0)));
+        assert_eq!(c.admin_bind_address, None);
+
+        let c = Config::from_iter_safe(strip_server(
+            to_vec(&["cmd", "server", "--admin-bind", "127.0.0.1:0"]).into_iter(),
+        ))?;
+        assert_eq!(
+            c.admin_bind_address,
+            Some(SocketAddr::from(([127, 0, 0, 1], 0)))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_query_log_sample_rate() -> Result<(), clap::Error> {
+        let c = Config::from_iter_safe(strip_server(to_vec(&["cmd", "server"]).into_iter()))?;
+        assert!((c.query_log_sample_rate - 0.01).abs() < f64::EPSILON);
+
+        let c = Config::from_iter_safe(strip_server(
+            to_vec(&["cmd", "server", "--query-log-sample-rate", "1"]).into_iter(),
+        ))?;
+        assert!((c.query_log_sample_rate - 1.0).abs() < f64::EPSILON);
+
+        assert!(Config::from_iter_safe(strip_server(
+            to_vec(&["cmd", "server", "--query-log-sample-rate", "2"]).into_iter(),
+        ))
+        .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_redact_values() -> Result<(), clap::Error> {
+        let c = Config::from_iter_safe(strip_server(to_vec(&["cmd", "server"]).into_iter()))?;
+        assert_eq!(c.redact_values, Redaction::Off);
+
+        let c = Config::from_iter_safe(strip_server(
+            to_vec(&["cmd", "server", "--redact-values", "hash"]).into_iter(),
+        ))?;
+        assert_eq!(c.redact_values, Redaction::Hash);
+
+        assert!(Config::from_iter_safe(strip_server(
+            to_vec(&["cmd", "server", "--redact-values", "mask"]).into_iter(),
+        ))
+        .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_http2() -> Result<(), clap::Error> {
+        let c = Config::from_iter_safe(strip_server(to_vec(&["cmd", "server"]).into_iter()))?;
+        assert_eq!(c.http2_max_concurrent_streams, None);
+        assert_eq!(c.http2_keepalive_interval_seconds, 0);
+        assert_eq!(c.http2_keepalive_timeout_seconds, 20);
+
+        let c = Config::from_iter_safe(strip_server(
+            to_vec(&[
+                "cmd",
+                "server",
+                "--http2-max-concurrent-streams",
+                "100",
+                "--http2-keepalive-interval",
+                "30",
+            ])
+            .into_iter(),
+        ))?;
+        assert_eq!(c.http2_max_concurrent_streams, Some(100));
+        assert_eq!(c.http2_keepalive_interval_seconds, 30);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_log_format() -> Result<(), clap::Error> {
+        let c = Config::from_iter_safe(strip_server(
+            to_vec(&["cmd", "server", "--log-format", "json"]).into_iter(),
+        ))?;
+        assert!(matches!(c.log_format, Some(LogFormat::Json)));
+
+        let c = Config::from_iter_safe(strip_server(
+            to_vec(&["cmd", "server", "--log_format", "Pretty"]).into_iter(),
+        ))?;
+        assert!(matches!(c.log_format, Some(LogFormat::Pretty)));
+
+        assert!("yaml".parse::<LogFormat>().is_err());
+
+        Ok(())
+    }
 }
@@ -0,0 +1,749 @@
+//! Loading of server settings from a TOML config file.
+//!
+//! Each setting in the file corresponds to one of the environment variables
+//! the server [`Config`](super::Config) is read from. Loading the file sets
+//! those environment variables that are not already set, so that settings
+//! in the file take precedence over the defaults but not over the
+//! environment or the command line flags.
+//!
+//! The file can be reloaded while the server runs. The environment is left
+//! as it is then, as it can't be changed safely once other threads run: the
+//! reloaded settings are kept here instead, and [`source`] tells where the
+//! current value of each setting comes from.
+
+use parking_lot::{const_mutex, Mutex};
+use snafu::{ResultExt, Snafu};
+use std::path::{Path, PathBuf};
+use toml::Value;
+
+/// The environment variable that can be used instead of `--config`
+pub const CONFIG_FILE_ENV: &str = "INFLUXDB_IOX_CONFIG_FILE";
+
+/// The settings of the config file, as last loaded
+static FILE_VARS: Mutex<FileVars> = const_mutex(FileVars {
+    set: Vec::new(),
+    values: Vec::new(),
+});
+
+#[derive(Debug)]
+struct FileVars {
+    /// The environment variables set from the file on startup
+    set: Vec<&'static str>,
+    /// The settings in the file, by environment variable
+    values: Vec<(&'static str, String)>,
+}
+
+impl FileVars {
+    /// Where the value of the setting for the environment variable `name`
+    /// comes from, given the value of the variable in the environment
+    fn source(&self, name: &str, env: Option<String>) -> Source {
+        match env {
+            Some(_) if !self.set.contains(&name) => Source::Environment,
+            _ => match self.values.iter().find(|(var, _)| *var == name) {
+                Some((_, value)) => Source::File(value.clone()),
+                None => Source::Default,
+            },
+        }
+    }
+}
+
+/// Where the value of a setting comes from
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    /// The environment (or the command line, which takes precedence)
+    Environment,
+    /// The config file, as last loaded
+    File(String),
+    /// Neither, so the setting has its default value
+    Default,
+}
+
+/// The type of value a setting expects
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    String,
+    Integer,
+    Float,
+    Bool,
+    /// An array of strings, or a single comma separated string
+    List,
+}
+
+/// The settings that can be used in the config file: the (dotted) key in
+/// the file, the environment variable it sets and the type of its value
+const SETTINGS: &[(&str, &str, Kind)] = &[
+    ("writer_id", "INFLUXDB_IOX_ID", Kind::Integer),
+    (
+        "shutdown_timeout",
+        "INFLUXDB_IOX_SHUTDOWN_TIMEOUT",
+        Kind::Integer,
+    ),
+    (
+        "retention_check_interval",
+        "INFLUXDB_IOX_RETENTION_CHECK_INTERVAL",
+        Kind::Integer,
+    ),
+    (
+        "max_background_jobs",
+        "INFLUXDB_IOX_MAX_BACKGROUND_JOBS",
+        Kind::Integer,
+    ),
+    (
+        "background_io_bytes_per_second",
+        "INFLUXDB_IOX_BACKGROUND_IO_BYTES_PER_SECOND",
+        Kind::Integer,
+    ),
+    (
+        "background_io_concurrent_files",
+        "INFLUXDB_IOX_BACKGROUND_IO_CONCURRENT_FILES",
+        Kind::Integer,
+    ),
+    ("num_threads", "INFLUXDB_IOX_NUM_THREADS", Kind::Integer),
+    ("query_threads", "INFLUXDB_IOX_QUERY_THREADS", Kind::Integer),
+    ("memory_limit", "INFLUXDB_IOX_MEMORY_LIMIT", Kind::Integer),
+    (
+        "replication.peers",
+        "INFLUXDB_IOX_REPLICATION_PEERS",
+        Kind::List,
+    ),
+    (
+        "replication.write_quorum",
+        "INFLUXDB_IOX_WRITE_QUORUM",
+        Kind::Integer,
+    ),
+    (
+        "replication.token",
+        "INFLUXDB_IOX_REPLICATION_TOKEN",
+        Kind::String,
+    ),
+    (
+        "replication.connect_timeout",
+        "INFLUXDB_IOX_REPLICATION_CONNECT_TIMEOUT",
+        Kind::Integer,
+    ),
+    (
+        "replication.timeout",
+        "INFLUXDB_IOX_REPLICATION_TIMEOUT",
+        Kind::Integer,
+    ),
+    (
+        "replication.anti_entropy_interval",
+        "INFLUXDB_IOX_ANTI_ENTROPY_INTERVAL",
+        Kind::Integer,
+    ),
+    (
+        "replication.hinted_handoff_max_size",
+        "INFLUXDB_IOX_HINTED_HANDOFF_MAX_SIZE",
+        Kind::Integer,
+    ),
+    (
+        "replication.hinted_handoff_max_age",
+        "INFLUXDB_IOX_HINTED_HANDOFF_MAX_AGE",
+        Kind::Integer,
+    ),
+    (
+        "replication.replica_of",
+        "INFLUXDB_IOX_REPLICA_OF",
+        Kind::Integer,
+    ),
+    (
+        "replication.replica_poll_interval",
+        "INFLUXDB_IOX_REPLICA_POLL_INTERVAL",
+        Kind::Integer,
+    ),
+    (
+        "routing.backends",
+        "INFLUXDB_IOX_ROUTE_WRITES_TO",
+        Kind::List,
+    ),
+    (
+        "write_buffer.brokers",
+        "INFLUXDB_IOX_WRITE_BUFFER_BROKERS",
+        Kind::List,
+    ),
+    (
+        "write_buffer.topic",
+        "INFLUXDB_IOX_WRITE_BUFFER_TOPIC",
+        Kind::String,
+    ),
+    (
+        "write_buffer.partitions",
+        "INFLUXDB_IOX_WRITE_BUFFER_PARTITIONS",
+        Kind::Integer,
+    ),
+    (
+        "cluster.address",
+        "INFLUXDB_IOX_GOSSIP_ADDRESS",
+        Kind::String,
+    ),
+    ("cluster.seeds", "INFLUXDB_IOX_GOSSIP_SEEDS", Kind::List),
+    (
+        "cluster.gossip_interval",
+        "INFLUXDB_IOX_GOSSIP_INTERVAL",
+        Kind::Integer,
+    ),
+    ("cluster.size", "INFLUXDB_IOX_CLUSTER_SIZE", Kind::Integer),
+    (
+        "cluster.on_quorum_loss",
+        "INFLUXDB_IOX_ON_QUORUM_LOSS",
+        Kind::String,
+    ),
+    (
+        "cluster.partition_lease_duration",
+        "INFLUXDB_IOX_PARTITION_LEASE_DURATION",
+        Kind::Integer,
+    ),
+    (
+        "query_log.sample_rate",
+        "INFLUXDB_IOX_QUERY_LOG_SAMPLE_RATE",
+        Kind::Float,
+    ),
+    ("log.filter", "RUST_LOG", Kind::String),
+    ("log.format", "INFLUXDB_IOX_LOG_FORMAT", Kind::String),
+    ("log.audit_file", "INFLUXDB_IOX_AUDIT_LOG", Kind::String),
+    (
+        "log.redact_values",
+        "INFLUXDB_IOX_REDACT_VALUES",
+        Kind::String,
+    ),
+    ("http.bind", "INFLUXDB_IOX_BIND_ADDR", Kind::String),
+    (
+        "http.unix_socket",
+        "INFLUXDB_IOX_HTTP_UNIX_SOCKET",
+        Kind::String,
+    ),
+    (
+        "http.admin_bind",
+        "INFLUXDB_IOX_ADMIN_BIND_ADDR",
+        Kind::String,
+    ),
+    (
+        "http.compression_min_size",
+        "INFLUXDB_IOX_HTTP_COMPRESSION_MIN_SIZE",
+        Kind::Integer,
+    ),
+    (
+        "http.max_request_size",
+        "INFLUXDB_IOX_MAX_HTTP_REQUEST_SIZE",
+        Kind::Integer,
+    ),
+    (
+        "http.http2.max_concurrent_streams",
+        "INFLUXDB_IOX_HTTP2_MAX_CONCURRENT_STREAMS",
+        Kind::Integer,
+    ),
+    (
+        "http.http2.keepalive_interval",
+        "INFLUXDB_IOX_HTTP2_KEEPALIVE_INTERVAL",
+        Kind::Integer,
+    ),
+    (
+        "http.http2.keepalive_timeout",
+        "INFLUXDB_IOX_HTTP2_KEEPALIVE_TIMEOUT",
+        Kind::Integer,
+    ),
+    ("http.admin_token", "INFLUXDB_IOX_ADMIN_TOKEN", Kind::String),
+    (
+        "http.allowed_ips",
+        "INFLUXDB_IOX_API_ALLOWED_IPS",
+        Kind::List,
+    ),
+    ("http.denied_ips", "INFLUXDB_IOX_API_DENIED_IPS", Kind::List),
+    (
+        "http.admin_allowed_ips",
+        "INFLUXDB_IOX_ADMIN_ALLOWED_IPS",
+        Kind::List,
+    ),
+    (
+        "http.admin_denied_ips",
+        "INFLUXDB_IOX_ADMIN_DENIED_IPS",
+        Kind::List,
+    ),
+    ("auth.api_tokens", "INFLUXDB_IOX_API_TOKENS", Kind::List),
+    (
+        "auth.api_read_tokens",
+        "INFLUXDB_IOX_API_READ_TOKENS",
+        Kind::List,
+    ),
+    (
+        "auth.api_write_tokens",
+        "INFLUXDB_IOX_API_WRITE_TOKENS",
+        Kind::List,
+    ),
+    ("auth.oidc.issuer", "INFLUXDB_IOX_OIDC_ISSUER", Kind::String),
+    (
+        "auth.oidc.audience",
+        "INFLUXDB_IOX_OIDC_AUDIENCE",
+        Kind::String,
+    ),
+    (
+        "auth.oidc.org_claim",
+        "INFLUXDB_IOX_OIDC_ORG_CLAIM",
+        Kind::String,
+    ),
+    (
+        "auth.oidc.scopes_claim",
+        "INFLUXDB_IOX_OIDC_SCOPES_CLAIM",
+        Kind::String,
+    ),
+    (
+        "auth.oidc.refresh_interval",
+        "INFLUXDB_IOX_OIDC_REFRESH_INTERVAL",
+        Kind::Integer,
+    ),
+    (
+        "http.write_timeout",
+        "INFLUXDB_IOX_WRITE_TIMEOUT",
+        Kind::Integer,
+    ),
+    (
+        "http.read_timeout",
+        "INFLUXDB_IOX_READ_TIMEOUT",
+        Kind::Integer,
+    ),
+    (
+        "http.cors.allowed_origins",
+        "INFLUXDB_IOX_CORS_ALLOWED_ORIGINS",
+        Kind::List,
+    ),
+    (
+        "http.cors.allowed_methods",
+        "INFLUXDB_IOX_CORS_ALLOWED_METHODS",
+        Kind::List,
+    ),
+    (
+        "http.cors.allowed_headers",
+        "INFLUXDB_IOX_CORS_ALLOWED_HEADERS",
+        Kind::List,
+    ),
+    (
+        "http.cors.max_age",
+        "INFLUXDB_IOX_CORS_MAX_AGE",
+        Kind::Integer,
+    ),
+    ("grpc.bind", "INFLUXDB_IOX_GRPC_BIND_ADDR", Kind::String),
+    (
+        "grpc.allowed_ips",
+        "INFLUXDB_IOX_GRPC_ALLOWED_IPS",
+        Kind::List,
+    ),
+    (
+        "grpc.denied_ips",
+        "INFLUXDB_IOX_GRPC_DENIED_IPS",
+        Kind::List,
+    ),
+    ("tls.cert", "INFLUXDB_IOX_TLS_CERT", Kind::String),
+    ("tls.key", "INFLUXDB_IOX_TLS_KEY", Kind::String),
+    (
+        "tls.reload_interval",
+        "INFLUXDB_IOX_TLS_RELOAD_INTERVAL",
+        Kind::Integer,
+    ),
+    (
+        "tls.grpc_client_ca",
+        "INFLUXDB_IOX_GRPC_TLS_CLIENT_CA",
+        Kind::String,
+    ),
+    (
+        "tls.grpc_allowed_clients",
+        "INFLUXDB_IOX_GRPC_TLS_ALLOWED_CLIENTS",
+        Kind::List,
+    ),
+    ("storage.data_dir", "INFLUXDB_IOX_DB_DIR", Kind::String),
+    (
+        "storage.object_store",
+        "INFLUXDB_IOX_OBJECT_STORE",
+        Kind::String,
+    ),
+    ("storage.bucket", "INFLUXDB_IOX_BUCKET", Kind::String),
+    (
+        "storage.secondary.object_store",
+        "INFLUXDB_IOX_SECONDARY_OBJECT_STORE",
+        Kind::String,
+    ),
+    (
+        "storage.secondary.bucket",
+        "INFLUXDB_IOX_SECONDARY_BUCKET",
+        Kind::String,
+    ),
+    (
+        "storage.secondary.data_dir",
+        "INFLUXDB_IOX_SECONDARY_DB_DIR",
+        Kind::String,
+    ),
+    (
+        "storage.secondary.s3_region",
+        "INFLUXDB_IOX_SECONDARY_S3_REGION",
+        Kind::String,
+    ),
+    (
+        "storage.secondary.sync_interval",
+        "INFLUXDB_IOX_SECONDARY_SYNC_INTERVAL",
+        Kind::Integer,
+    ),
+    (
+        "storage.s3.access_key_id",
+        "AWS_ACCESS_KEY_ID",
+        Kind::String,
+    ),
+    (
+        "storage.s3.secret_access_key",
+        "AWS_SECRET_ACCESS_KEY",
+        Kind::String,
+    ),
+    ("storage.s3.region", "AWS_DEFAULT_REGION", Kind::String),
+    (
+        "storage.google.service_account",
+        "SERVICE_ACCOUNT",
+        Kind::String,
+    ),
+    (
+        "storage.azure.storage_account",
+        "AZURE_STORAGE_ACCOUNT",
+        Kind::String,
+    ),
+    (
+        "storage.azure.master_key",
+        "AZURE_STORAGE_MASTER_KEY",
+        Kind::String,
+    ),
+    (
+        "secrets.provider",
+        "INFLUXDB_IOX_SECRETS_PROVIDER",
+        Kind::String,
+    ),
+    ("secrets.dir", "INFLUXDB_IOX_SECRETS_DIR", Kind::String),
+    (
+        "secrets.refresh_interval",
+        "INFLUXDB_IOX_SECRETS_REFRESH_INTERVAL",
+        Kind::Integer,
+    ),
+    (
+        "secrets.encryption_key_secret",
+        "INFLUXDB_IOX_ENCRYPTION_KEY_SECRET",
+        Kind::String,
+    ),
+    (
+        "secrets.encryption_allow_plaintext",
+        "INFLUXDB_IOX_ENCRYPTION_ALLOW_PLAINTEXT",
+        Kind::Bool,
+    ),
+    (
+        "quotas.series_limit",
+        "INFLUXDB_IOX_ORG_SERIES_LIMIT",
+        Kind::Integer,
+    ),
+    (
+        "quotas.series_soft_limit",
+        "INFLUXDB_IOX_ORG_SERIES_SOFT_LIMIT",
+        Kind::Integer,
+    ),
+    (
+        "quotas.bytes_limit",
+        "INFLUXDB_IOX_ORG_BYTES_LIMIT",
+        Kind::Integer,
+    ),
+    (
+        "quotas.bytes_soft_limit",
+        "INFLUXDB_IOX_ORG_BYTES_SOFT_LIMIT",
+        Kind::Integer,
+    ),
+    (
+        "tracing.jaeger_agent_host",
+        "OTEL_EXPORTER_JAEGER_AGENT_HOST",
+        Kind::String,
+    ),
+];
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Unable to read config file {:?}: {}", path, source))]
+    ReadingFile {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Invalid TOML in config file {:?}: {}", path, source))]
+    ParsingFile {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+
+    #[snafu(display(
+        "Unknown setting '{}' in config file {:?}. Valid settings are: {}",
+        key,
+        path,
+        valid
+    ))]
+    UnknownSetting {
+        path: PathBuf,
+        key: String,
+        valid: String,
+    },
+
+    #[snafu(display(
+        "Invalid value for setting '{}' in config file {:?}: expected {}",
+        key,
+        path,
+        expected
+    ))]
+    InvalidValue {
+        path: PathBuf,
+        key: String,
+        expected: &'static str,
+    },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Returns the path of the config file given with `--config` in `args`, or
+/// else in the `INFLUXDB_IOX_CONFIG_FILE` environment variable
+pub fn config_file_path(args: impl Iterator<Item = String>) -> Option<PathBuf> {
+    let mut args = args.skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            return args.next().map(PathBuf::from);
+        }
+        if let Some(path) = arg.strip_prefix("--config=") {
+            return Some(PathBuf::from(path));
+        }
+    }
+    std::env::var_os(CONFIG_FILE_ENV).map(PathBuf::from)
+}
+
+/// Loads the config file at `path`, setting the environment variables for
+/// the settings in it that are not already set.
+///
+/// This changes the environment, so it must be called on startup, before
+/// any other thread is started.
+pub fn load(path: &Path) -> Result<()> {
+    let vars = read(path)?;
+
+    let mut file_vars = FILE_VARS.lock();
+    for (name, value) in &vars {
+        if std::env::var_os(name).is_none() {
+            std::env::set_var(name, value);
+            file_vars.set.push(name);
+        }
+    }
+    file_vars.values = vars;
+    Ok(())
+}
+
+/// Loads the config file at `path` again, keeping its settings for
+/// [`source`] rather than changing the environment. Nothing is changed if
+/// the file can not be read.
+pub fn reload(path: &Path) -> Result<()> {
+    let vars = read(path)?;
+    FILE_VARS.lock().values = vars;
+    Ok(())
+}
+
+/// Returns where the current value of the setting for the environment
+/// variable `name` comes from: variables set by other means than the config
+/// file take precedence over the file as last loaded, and those set from the
+/// file on startup no longer count once it is reloaded.
+pub fn source(name: &str) -> Source {
+    FILE_VARS.lock().source(name, std::env::var(name).ok())
+}
+
+/// Reads and validates the config file at `path`, returning the environment
+/// variables its settings correspond to along with their values
+pub fn read(path: &Path) -> Result<Vec<(&'static str, String)>> {
+    let contents = std::fs::read_to_string(path).context(ReadingFile { path })?;
+    let table: Value = contents.parse().context(ParsingFile { path })?;
+
+    let mut vars = vec![];
+    flatten(path, "", &table, &mut vars)?;
+    Ok(vars)
+}
+
+fn flatten(
+    path: &Path,
+    prefix: &str,
+    value: &Value,
+    vars: &mut Vec<(&'static str, String)>,
+) -> Result<()> {
+    match value {
+        Value::Table(table) => {
+            for (key, value) in table {
+                let key = if prefix.is_empty() {
+                    key.to_string()
+                } else {
+                    format!("{}.{}", prefix, key)
+                };
+                flatten(path, &key, value, vars)?;
+            }
+            Ok(())
+        }
+        value => {
+            let (name, kind) = SETTINGS
+                .iter()
+                .find(|(key, _, _)| *key == prefix)
+                .map(|(_, name, kind)| (*name, *kind))
+                .ok_or_else(|| Error::UnknownSetting {
+                    path: path.to_path_buf(),
+                    key: prefix.to_string(),
+                    valid: SETTINGS
+                        .iter()
+                        .map(|(key, _, _)| *key)
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                })?;
+
+            let invalid = |expected| Error::InvalidValue {
+                path: path.to_path_buf(),
+                key: prefix.to_string(),
+                expected,
+            };
+            let value = match (kind, value) {
+                (Kind::String, Value::String(s)) => s.clone(),
+                (Kind::String, _) => return Err(invalid("a string")),
+                (Kind::Integer, Value::Integer(i)) if *i >= 0 => i.to_string(),
+                (Kind::Integer, _) => return Err(invalid("a non-negative integer")),
+                (Kind::Float, Value::Float(f)) if *f >= 0.0 => f.to_string(),
+                (Kind::Float, Value::Integer(i)) if *i >= 0 => i.to_string(),
+                (Kind::Float, _) => return Err(invalid("a non-negative number")),
+                (Kind::Bool, Value::Boolean(b)) => b.to_string(),
+                (Kind::Bool, _) => return Err(invalid("a boolean")),
+                (Kind::List, Value::String(s)) => s.clone(),
+                (Kind::List, Value::Array(values)) => values
+                    .iter()
+                    .map(|v| v.as_str().map(|s| s.to_string()))
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or_else(|| invalid("an array of strings"))?
+                    .join(","),
+                (Kind::List, _) => return Err(invalid("an array of strings")),
+            };
+            vars.push((name, value));
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn read_str(contents: &str) -> Result<Vec<(&'static str, String)>> {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(contents.as_bytes()).unwrap();
+        read(file.path())
+    }
+
+    fn to_vec(v: &[&str]) -> Vec<String> {
+        v.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_config_file_path() {
+        assert_eq!(
+            config_file_path(to_vec(&["cmd", "server", "--config", "iox.toml"]).into_iter()),
+            Some(PathBuf::from("iox.toml"))
+        );
+        assert_eq!(
+            config_file_path(to_vec(&["cmd", "--config=/etc/iox.toml", "-v"]).into_iter()),
+            Some(PathBuf::from("/etc/iox.toml"))
+        );
+    }
+
+    #[test]
+    fn test_read() {
+        let mut vars = read_str(
+            r#"
+            writer_id = 42
+
+            [http]
+            bind = "0.0.0.0:8080"
+            cors.allowed_origins = ["https://ui.example.com", "https://other.example.com"]
+
+            [storage]
+            object_store = "s3"
+            bucket = "iox"
+            s3 = { region = "us-east-2" }
+            "#,
+        )
+        .unwrap();
+        vars.sort();
+        assert_eq!(
+            vars,
+            vec![
+                ("AWS_DEFAULT_REGION", "us-east-2".to_string()),
+                ("INFLUXDB_IOX_BIND_ADDR", "0.0.0.0:8080".to_string()),
+                ("INFLUXDB_IOX_BUCKET", "iox".to_string()),
+                (
+                    "INFLUXDB_IOX_CORS_ALLOWED_ORIGINS",
+                    "https://ui.example.com,https://other.example.com".to_string()
+                ),
+                ("INFLUXDB_IOX_ID", "42".to_string()),
+                ("INFLUXDB_IOX_OBJECT_STORE", "s3".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_source() {
+        let env = |value: &str| Some(value.to_string());
+        let mut file_vars = FileVars {
+            set: vec!["AZURE_STORAGE_MASTER_KEY"],
+            values: read_str(
+                "[storage.azure]\nstorage_account = \"from_file\"\nmaster_key = \"secret\"",
+            )
+            .unwrap(),
+        };
+
+        // the environment takes precedence over the file, unless the
+        // variable was set from the file
+        assert_eq!(
+            file_vars.source("AZURE_STORAGE_ACCOUNT", env("from_env")),
+            Source::Environment
+        );
+        assert_eq!(
+            file_vars.source("AZURE_STORAGE_MASTER_KEY", env("secret")),
+            Source::File("secret".to_string())
+        );
+
+        // once reloaded, the file replaces the values set from it on startup
+        file_vars.values = read_str("[storage.azure]\nmaster_key = \"new_secret\"").unwrap();
+        assert_eq!(
+            file_vars.source("AZURE_STORAGE_MASTER_KEY", env("secret")),
+            Source::File("new_secret".to_string())
+        );
+        assert_eq!(
+            file_vars.source("AZURE_STORAGE_ACCOUNT", None),
+            Source::Default
+        );
+
+        file_vars.values = vec![];
+        assert_eq!(
+            file_vars.source("AZURE_STORAGE_MASTER_KEY", env("secret")),
+            Source::Default
+        );
+    }
+
+    #[test]
+    fn test_read_errors() {
+        let err = read_str("[http]\nbnid = \"0.0.0.0:8080\"").unwrap_err();
+        assert!(matches!(&err, Error::UnknownSetting { key, .. } if key == "http.bnid"));
+        assert!(err.to_string().contains("Valid settings are: writer_id"));
+
+        let err = read_str("writer_id = \"one\"").unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("Invalid value for setting 'writer_id'"),
+            "{}",
+            err
+        );
+        assert!(err.to_string().ends_with("expected a non-negative integer"));
+
+        let err = read_str("[http.cors]\nallowed_origins = [1]").unwrap_err();
+        assert!(err.to_string().ends_with("expected an array of strings"));
+
+        let err = read_str("writer_id = ").unwrap_err();
+        assert!(matches!(err, Error::ParsingFile { .. }));
+
+        let err = read(Path::new("/does/not/exist.toml")).unwrap_err();
+        assert!(matches!(err, Error::ReadingFile { .. }));
+    }
+}
@@ -1,9 +1,53 @@
 //! Logging initization and setup
 
-use tracing_subscriber::{prelude::*, EnvFilter};
+use snafu::{ResultExt, Snafu};
+use tracing_subscriber::{filter::ParseError, prelude::*, reload, EnvFilter, Registry};
 
 use super::config::{Config, LogFormat};
 
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Invalid log filter '{}': {}", filter, source))]
+    InvalidLogFilter { filter: String, source: ParseError },
+
+    #[snafu(display("Unable to change the log filter: {}", source))]
+    ChangingLogFilter { source: reload::Error },
+
+    #[snafu(display("Unable to read the log filter: {}", source))]
+    ReadingLogFilter { source: reload::Error },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Handle to change the filter (in `RUST_LOG` syntax) selecting which logs
+/// are emitted by a running server
+#[derive(Debug, Clone)]
+pub struct LogFilterHandle(reload::Handle<EnvFilter, Registry>);
+
+impl LogFilterHandle {
+    /// Replaces the current filter with `filter`
+    pub fn set(&self, filter: &str) -> Result<()> {
+        let new_filter = EnvFilter::try_new(filter).context(InvalidLogFilter { filter })?;
+        self.0.reload(new_filter).context(ChangingLogFilter)
+    }
+
+    /// Returns the current filter
+    pub fn current(&self) -> Result<String> {
+        self.0
+            .with_current(|filter| filter.to_string())
+            .context(ReadingLogFilter)
+    }
+
+    /// Returns a handle to `filter`, which is not installed in any
+    /// subscriber, and the layer that must be kept alive for the handle to
+    /// work
+    #[cfg(test)]
+    pub fn new_for_test(filter: &str) -> (reload::Layer<EnvFilter, Registry>, Self) {
+        let (layer, handle) = reload::Layer::new(EnvFilter::new(filter));
+        (layer, Self(handle))
+    }
+}
+
 /// Handles setting up logging levels
 #[derive(Debug)]
 pub enum LoggingLevel {
@@ -38,9 +82,9 @@ impl LoggingLevel {
         Self::new(std::cmp::max(self as u64, other as u64))
     }
 
-    /// set RUST_LOG to the level represented by self, unless RUST_LOG
-    /// is already set
-    fn set_rust_log_if_needed(&self, level: Option<String>) {
+    /// Returns the log filter represented by self, used if RUST_LOG is
+    /// not set
+    pub fn default_log_filter(&self) -> &'static str {
         /// Default debug level is debug for everything except
         /// some especially noisy low level libraries
         const DEFAULT_DEBUG_LOG_LEVEL: &str = "debug,hyper::proto::h1=info,h2=info";
@@ -51,6 +95,16 @@ impl LoggingLevel {
         // Default log level is warn level for all components
         const DEFAULT_LOG_LEVEL: &str = "info";
 
+        match self {
+            Self::Default => DEFAULT_LOG_LEVEL,
+            Self::Verbose => DEFAULT_VERBOSE_LOG_LEVEL,
+            Self::Debug => DEFAULT_DEBUG_LOG_LEVEL,
+        }
+    }
+
+    /// set RUST_LOG to the level represented by self, unless RUST_LOG
+    /// is already set
+    fn set_rust_log_if_needed(&self, level: Option<String>) {
         match level {
             Some(lvl) => {
                 if !matches!(self, Self::Default) {
@@ -62,13 +116,7 @@ impl LoggingLevel {
                     std::env::set_var("RUST_LOG", lvl);
                 }
             }
-            None => {
-                match self {
-                    Self::Default => std::env::set_var("RUST_LOG", DEFAULT_LOG_LEVEL),
-                    Self::Verbose => std::env::set_var("RUST_LOG", DEFAULT_VERBOSE_LOG_LEVEL),
-                    Self::Debug => std::env::set_var("RUST_LOG", DEFAULT_DEBUG_LOG_LEVEL),
-                };
-            }
+            None => std::env::set_var("RUST_LOG", self.default_log_filter()),
         }
     }
 
@@ -80,8 +128,12 @@ impl LoggingLevel {
     }
 
     /// Configures logging and tracing, based on the configuration
-    /// values, for the IOx server (the whole enchalada)
-    pub fn setup_logging(&self, config: &Config) -> Option<opentelemetry_jaeger::Uninstall> {
+    /// values, for the IOx server (the whole enchalada). The returned
+    /// handle can be used to change the log filter later.
+    pub fn setup_logging(
+        &self,
+        config: &Config,
+    ) -> (Option<opentelemetry_jaeger::Uninstall>, LogFilterHandle) {
         // Copy anything from the config to the rust log environment
         self.set_rust_log_if_needed(config.rust_log.clone());
 
@@ -106,16 +158,19 @@ impl LoggingLevel {
                 (None, None)
             };
 
+        // filter messages to only those specified by RUST_LOG environment,
+        // allowing the filter to be replaced while running
+        let (filter, filter_handle) = reload::Layer::new(EnvFilter::from_default_env());
+
         // Register the chain of event subscribers:
         //
-        //      - Jaeger tracing emitter
         //      - Env filter (using RUST_LOG as the filter env)
+        //      - Jaeger tracing emitter
         //      - A stderr logger
         //
         let subscriber = tracing_subscriber::registry()
-            .with(opentelemetry)
-            // filter messages to only those specified by RUST_LOG environment
-            .with(EnvFilter::from_default_env());
+            .with(filter)
+            .with(opentelemetry);
 
         // Configure the logger to write to stderr and install it
         let output_stream = std::io::stderr;
@@ -131,8 +186,23 @@ impl LoggingLevel {
                 let logger = logfmt::LogFmtLayer::new(output_stream);
                 subscriber.with(logger).init();
             }
+            LogFormat::Json => {
+                let logger = tracing_subscriber::fmt::layer()
+                    .json()
+                    .flatten_event(true)
+                    .with_current_span(true)
+                    .with_span_list(false)
+                    .with_writer(output_stream);
+                subscriber.with(logger).init();
+            }
+            LogFormat::Pretty => {
+                let logger = tracing_subscriber::fmt::layer()
+                    .pretty()
+                    .with_writer(output_stream);
+                subscriber.with(logger).init();
+            }
         };
 
-        drop_handle
+        (drop_handle, LogFilterHandle(filter_handle))
     }
 }
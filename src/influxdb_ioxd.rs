@@ -1,23 +1,70 @@
 use std::fs;
 use std::net::SocketAddr;
+use std::num::{NonZeroU32, NonZeroU64};
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use hyper::Server;
+use futures::FutureExt;
+use hyper::{
+    server::{accept, conn::AddrStream},
+    service::make_service_fn,
+    Server,
+};
 use snafu::{ResultExt, Snafu};
 use tracing::{error, info, warn};
 
-use object_store::{self, aws::AmazonS3, gcp::GoogleCloudStorage, ObjectStore};
+use tokio_stream::wrappers::TcpListenerStream;
+#[cfg(unix)]
+use tokio_stream::wrappers::UnixListenerStream;
+
+use object_store::{
+    self, aws::AmazonS3, gcp::GoogleCloudStorage, secrets::SecretsProvider, ObjectStore,
+};
 use panic_logging::SendPanicsToTracing;
-use server::{ConnectionManagerImpl as ConnectionManager, Server as AppServer};
+use query::DatabaseStore;
+use server::{
+    cluster::{MemberState, Membership, Quorum, Role},
+    encryption::{self, Encryption},
+    hints::HintedHandoff,
+    leases::PartitionLeases,
+    quotas::{Limit, Quotas},
+    region::RegionReplication,
+    replica::ReadReplica,
+    replication::{self, PeerReplication},
+    router::{self, ShardRouter},
+    throttle::IoLimits,
+    write_buffer, ConnectionManagerImpl as ConnectionManager, Server as AppServer,
+};
 
 use crate::commands::{
     config::{load_config, Config, ObjectStore as ObjStoreOpt},
     logging::LoggingLevel,
 };
 
+mod anti_entropy;
+mod audit;
+mod auth;
+mod build_info;
+mod gossip;
+mod hinted_handoff;
 mod http;
+mod ip_filter;
+mod memory;
+mod oidc;
+mod query_log;
+mod region_replication;
+mod reload;
+mod replica;
+mod request_id;
+mod retention;
 mod rpc;
+mod secrets;
+mod serving_readiness;
+mod shard_owners;
+mod tls;
+
+use serving_readiness::{ServingReadiness, StartupPhase};
 
 #[derive(Debug, Snafu)]
 pub enum Error {
@@ -49,6 +96,16 @@ pub enum Error {
         source: hyper::Error,
     },
 
+    #[snafu(display(
+        "Unable to bind to listen for admin HTTP requests on {}: {}",
+        bind_addr,
+        source
+    ))]
+    StartListeningAdmin {
+        bind_addr: SocketAddr,
+        source: hyper::Error,
+    },
+
     #[snafu(display(
         "Unable to bind to listen for gRPC requests on {}: {}",
         grpc_bind_addr,
@@ -59,6 +116,43 @@ pub enum Error {
         source: std::io::Error,
     },
 
+    #[snafu(display(
+        "Unable to bind to listen for HTTPS requests on {}: {}",
+        bind_addr,
+        source
+    ))]
+    StartListeningHttps {
+        bind_addr: SocketAddr,
+        source: std::io::Error,
+    },
+
+    #[snafu(display(
+        "Unable to bind to listen for HTTP requests on unix socket {:?}: {}",
+        path,
+        source
+    ))]
+    StartListeningUnix {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Unable to load TLS certificate: {}", source))]
+    LoadingTlsCertificate { source: tls::Error },
+
+    #[snafu(display("Both a TLS certificate and key must be specified to serve TLS"))]
+    InvalidTlsConfiguration,
+
+    #[snafu(display(
+        "Requiring gRPC client certificates needs TLS, and allowed clients need a client CA"
+    ))]
+    InvalidClientAuthConfiguration,
+
+    #[snafu(display("Error configuring gRPC client certificates: {}", source))]
+    LoadingClientAuth { source: tls::Error },
+
+    #[snafu(display("Error configuring TLS for peer servers: {}", source))]
+    LoadingPeerTls { source: tls::Error },
+
     #[snafu(display("Error serving HTTP: {}", source))]
     ServingHttp { source: hyper::Error },
 
@@ -70,6 +164,45 @@ pub enum Error {
 
     #[snafu(display("Specified file for the object store, but not a database directory"))]
     InvalidFileObjectStoreConfiguration,
+
+    #[snafu(display("{}", source))]
+    InvalidS3Region { source: object_store::aws::Error },
+
+    #[snafu(display("Error configuring the secondary object store: {}", source))]
+    InvalidSecondaryObjectStore { source: Box<Error> },
+
+    #[snafu(display("Error reading secrets: {}", source))]
+    ReadingSecrets { source: secrets::Error },
+
+    #[snafu(display("--encryption-key-secret requires --secrets-provider"))]
+    EncryptionWithoutSecretsProvider,
+
+    #[snafu(display("Error reading the encryption keys: {}", source))]
+    ReadingEncryptionKeys { source: server::encryption::Error },
+
+    #[snafu(display("Error reading the OIDC signing keys: {}", source))]
+    LoadingOidcKeys { source: oidc::Error },
+
+    #[snafu(display("Error setting up the audit log: {}", source))]
+    OpeningAuditLog { source: audit::Error },
+
+    #[snafu(display("Invalid replication configuration: {}", source))]
+    InvalidReplication { source: replication::Error },
+
+    #[snafu(display("Invalid --route-writes-to: {}", source))]
+    InvalidRouting { source: router::Error },
+
+    #[snafu(display("Invalid write buffer: {}", source))]
+    InvalidWriteBuffer { source: write_buffer::Error },
+
+    #[snafu(display("Invalid --replication-token: {}", source))]
+    InvalidReplicationToken { source: server::Error },
+
+    #[snafu(display("Invalid {} IP filter: {}", listener, source))]
+    InvalidIpFilter {
+        listener: &'static str,
+        source: ip_filter::Error,
+    },
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -87,7 +220,9 @@ pub async fn main(logging_level: LoggingLevel, config: Option<Config>) -> Result
     // command
     let logging_level = logging_level.combine(LoggingLevel::new(config.verbose_count));
 
-    let _drop_handle = logging_level.setup_logging(&config);
+    // Values are redacted from the first message logged
+    config.redact_values.install();
+    let (_drop_handle, log_filter) = logging_level.setup_logging(&config);
 
     // Install custom panic handler and forget about it.
     //
@@ -98,11 +233,677 @@ pub async fn main(logging_level: LoggingLevel, config: Option<Config>) -> Result
     let f = SendPanicsToTracing::new();
     std::mem::forget(f);
 
-    let object_store = match (
+    let http2 = Http2Settings::new(&config);
+
+    let audit_log =
+        Arc::new(audit::AuditLog::open(config.audit_log_path.as_deref()).context(OpeningAuditLog)?);
+
+    // Some settings can be changed without a restart by sending SIGHUP
+    let write_timeout = Arc::new(http::Timeout::new(timeout(config.write_timeout_seconds)));
+    let read_timeout = Arc::new(http::Timeout::new(timeout(config.read_timeout_seconds)));
+    let (retention_check_interval, retention_check_interval_rx) =
+        tokio::sync::watch::channel(timeout(config.retention_check_interval_seconds));
+    let reloader = reload::Reloader::new(
+        &config,
+        logging_level.default_log_filter(),
+        log_filter.clone(),
+        Arc::clone(&write_timeout),
+        Arc::clone(&read_timeout),
+        retention_check_interval,
+        Arc::clone(&audit_log),
+    );
+    tokio::spawn(reload::reload_on_sighup(reloader));
+
+    let secrets_provider = secrets::provider(&config).context(ReadingSecrets)?;
+
+    let encryption = match (&config.encryption_key_secret, &secrets_provider) {
+        (Some(secret_name), Some(secrets)) => {
+            let mut encryption = Encryption::new(Arc::clone(secrets), secret_name);
+            if config.encryption_allow_plaintext {
+                encryption = encryption.with_plaintext_allowed();
+            }
+            encryption
+                .refresh_keys()
+                .await
+                .context(ReadingEncryptionKeys)?;
+            info!(
+                %secret_name,
+                allow_plaintext = config.encryption_allow_plaintext,
+                "Encrypting chunk files and WAL segments"
+            );
+            let encryption = Arc::new(encryption);
+            let interval = Duration::from_secs(config.secrets_refresh_interval_seconds.max(1));
+            tokio::spawn(encryption::refresh_keys_periodically(
+                Arc::clone(&encryption),
+                interval,
+            ));
+            Some(encryption)
+        }
+        (Some(_), None) => return EncryptionWithoutSecretsProvider.fail(),
+        (None, _) => None,
+    };
+
+    let object_store = create_object_store(
         config.object_store,
         config.bucket,
         config.database_directory,
-    ) {
+        None,
+        secrets_provider.as_ref(),
+    )?;
+    let object_storage = Arc::new(object_store);
+
+    // The server does not report itself as ready until the database
+    // configurations have been loaded and all listeners are bound
+    let serving_readiness = ServingReadiness::new();
+
+    serving_readiness.start_phase(StartupPhase::LoadingCatalog);
+    let peer_tls_files = match (&config.tls_cert, &config.tls_key) {
+        (Some(cert_path), Some(key_path)) => Some(tls::TlsFiles {
+            cert_path: cert_path.clone(),
+            key_path: key_path.clone(),
+        }),
+        _ => None,
+    };
+    let peer_tls = tls::client_config(
+        peer_tls_files.as_ref(),
+        config.grpc_tls_client_ca.as_deref(),
+    )
+    .context(LoadingPeerTls)?;
+    let connection_manager = ConnectionManager::new(config.replication_token.as_deref())
+        .context(InvalidReplicationToken)?
+        .with_timeouts(
+            Duration::from_secs(config.replication_connect_timeout_seconds),
+            Duration::from_secs(config.replication_timeout_seconds),
+        )
+        .with_tls(peer_tls);
+    let app_server = Arc::new(AppServer::new(connection_manager, object_storage));
+    if config.query_memory_limit > 0 {
+        app_server
+            .executor()
+            .set_memory_limit(Some(config.query_memory_limit));
+    }
+    app_server.set_memory_limit(config.memory_limit);
+    if config.query_scan_concurrency > 0 {
+        app_server
+            .executor()
+            .set_scan_concurrency(Some(config.query_scan_concurrency));
+    }
+    app_server
+        .executor()
+        .set_query_threads(config.query_threads);
+    info!(
+        query_threads = app_server.executor().query_threads().unwrap_or_default(),
+        "Running queries on a dedicated thread pool"
+    );
+    app_server.set_max_background_jobs(config.max_background_jobs);
+    app_server.set_background_io_limits(IoLimits {
+        bytes_per_second: config
+            .background_io_bytes_per_second
+            .and_then(NonZeroU64::new),
+        concurrent_file_ops: config
+            .background_io_concurrent_files
+            .and_then(NonZeroU32::new),
+    });
+    if config.secondary_object_store.is_some() {
+        let secondary = create_object_store(
+            config.secondary_object_store,
+            config.secondary_bucket.clone(),
+            config.secondary_database_directory.clone(),
+            config.secondary_s3_region.as_deref(),
+            secrets_provider.as_ref(),
+        )
+        .map_err(Box::new)
+        .context(InvalidSecondaryObjectStore)?;
+        info!(
+            interval_seconds = config.secondary_sync_interval_seconds,
+            "Mirroring the object store to the secondary object store"
+        );
+        app_server.set_region_replication(RegionReplication::new(Arc::new(secondary)));
+    }
+    if !config.replication_peers.is_empty() {
+        let replication =
+            PeerReplication::new(config.replication_peers.clone(), config.write_quorum)
+                .context(InvalidReplication)?
+                .with_timeout(Duration::from_secs(config.replication_timeout_seconds));
+        info!(
+            peers = ?replication.peers(),
+            quorum = replication.quorum(),
+            "Replicating writes to peers"
+        );
+        app_server.set_peer_replication(replication);
+
+        if config.hinted_handoff_max_size > 0 {
+            app_server.set_hinted_handoff(HintedHandoff::new(
+                config.hinted_handoff_max_size,
+                Duration::from_secs(config.hinted_handoff_max_age_seconds),
+            ));
+        }
+    }
+    if !config.route_writes_to.is_empty() {
+        let router = ShardRouter::new(config.route_writes_to.clone()).context(InvalidRouting)?;
+        info!(backends = ?router.backends(), "Routing writes to backends");
+        app_server.set_shard_router(router);
+    }
+    if !config.write_buffer_brokers.is_empty() {
+        let write_buffer = write_buffer::kafka(
+            &config.write_buffer_brokers,
+            &config.write_buffer_topic,
+            config.write_buffer_partitions,
+        )
+        .context(InvalidWriteBuffer)?;
+        info!(
+            brokers = ?config.write_buffer_brokers,
+            topic = %config.write_buffer_topic,
+            partitions = config.write_buffer_partitions,
+            "Producing writes to Kafka"
+        );
+        app_server.set_write_buffer(write_buffer);
+    }
+    app_server.set_quotas(Quotas {
+        series: Limit {
+            soft: config.org_series_soft_limit,
+            hard: config.org_series_limit,
+        },
+        bytes: Limit {
+            soft: config.org_bytes_soft_limit,
+            hard: config.org_bytes_limit,
+        },
+    });
+    app_server.set_encryption(encryption);
+    if let Some(seconds) = config.partition_lease_duration_seconds {
+        info!(seconds, "Electing partition owners through leases");
+        app_server.set_partition_leases(PartitionLeases::new(Duration::from_secs(seconds.max(1))));
+    }
+
+    // if this ID isn't set the server won't be usable until this is set via an API
+    // call
+    if let Some(id) = config.writer_id {
+        app_server.set_id(id);
+        if let Err(e) = app_server.load_database_configs().await {
+            error!(
+                "unable to load database configurations from object storage: {}",
+                e
+            )
+        }
+    } else {
+        warn!("server ID not set. ID must be set via the INFLUXDB_IOX_ID config or API before writing or querying data.");
+    }
+
+    tokio::spawn(retention::drop_expired_data_periodically(
+        Arc::clone(&app_server),
+        retention_check_interval_rx,
+    ));
+
+    if let Some(primary_id) = config.replica_of {
+        info!(primary_id, "Running as a read replica");
+        app_server.set_read_replica(ReadReplica::new(primary_id));
+        let interval = Duration::from_secs(config.replica_poll_interval_seconds.max(1));
+        tokio::spawn(replica::follow_primary_periodically(
+            Arc::clone(&app_server),
+            interval,
+        ));
+    }
+
+    if let Some(address) = &config.gossip_address {
+        let role = if !config.route_writes_to.is_empty() {
+            Role::Router
+        } else if config.replica_of.is_some() {
+            Role::Replica
+        } else {
+            Role::Storage
+        };
+        let local = MemberState {
+            address: address.clone(),
+            id: config.writer_id.unwrap_or(0),
+            role,
+            databases: vec![],
+            incarnation: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|since| since.as_millis() as u64)
+                .unwrap_or(0),
+            heartbeat: 0,
+        };
+        let interval = Duration::from_secs(config.gossip_interval_seconds.max(1));
+        info!(%address, %role, seeds = ?config.gossip_seeds, "Joining the cluster");
+        app_server.set_membership(Membership::new(
+            local,
+            config.gossip_seeds.clone(),
+            interval,
+        ));
+        if config.cluster_size > 0 {
+            let quorum = Quorum::new(config.cluster_size, config.on_quorum_loss);
+            info!(
+                cluster_size = config.cluster_size,
+                required = quorum.required(),
+                on_loss = ?config.on_quorum_loss,
+                "Protecting against split brain"
+            );
+            app_server.set_quorum(quorum);
+        }
+        tokio::spawn(gossip::gossip_periodically(
+            Arc::clone(&app_server),
+            interval,
+        ));
+    }
+
+    if !config.replication_peers.is_empty() && config.anti_entropy_interval_seconds > 0 {
+        tokio::spawn(anti_entropy::repair_periodically(
+            Arc::clone(&app_server),
+            Duration::from_secs(config.anti_entropy_interval_seconds),
+        ));
+    }
+
+    if app_server.shard_router().is_some() {
+        tokio::spawn(shard_owners::load_periodically(Arc::clone(&app_server)));
+    }
+
+    if app_server.hinted_handoff().is_some() {
+        tokio::spawn(hinted_handoff::deliver_periodically(Arc::clone(
+            &app_server,
+        )));
+    }
+
+    if config.memory_limit.is_some() {
+        tokio::spawn(memory::measure_periodically(Arc::clone(&app_server)));
+    }
+
+    if app_server.region_replication().is_some() {
+        tokio::spawn(region_replication::sync_periodically(
+            Arc::clone(&app_server),
+            Duration::from_secs(config.secondary_sync_interval_seconds.max(1)),
+        ));
+    }
+
+    // Resolves once a shutdown has been requested. Both servers stop
+    // accepting new connections at that point and the server reports itself
+    // as not ready while in-flight requests drain.
+    let shutdown = {
+        let serving_readiness = serving_readiness.clone();
+        async move {
+            wait_for_shutdown_signal().await;
+            info!("Shutdown requested, draining in-flight requests");
+            serving_readiness.set_ready(false);
+        }
+    }
+    .shared();
+
+    // Both servers share the certificate resolver so that a reload applies
+    // to both
+    let tls_resolver = match (config.tls_cert, config.tls_key) {
+        (Some(cert_path), Some(key_path)) => {
+            let files = tls::TlsFiles {
+                cert_path,
+                key_path,
+            };
+            info!(cert_path=?files.cert_path, "Serving HTTP and gRPC over TLS");
+            let resolver =
+                Arc::new(tls::ReloadingCertResolver::new(files).context(LoadingTlsCertificate)?);
+            if let Some(seconds) = config.tls_reload_interval_seconds {
+                let interval = Duration::from_secs(seconds);
+                tokio::spawn(tls::reload_periodically(Arc::clone(&resolver), interval));
+            }
+            Some(resolver)
+        }
+        (None, None) => None,
+        _ => return InvalidTlsConfiguration.fail(),
+    };
+    let tls_acceptor = tls_resolver.clone().map(tls::make_acceptor);
+
+    // The gRPC server may additionally require client certificates
+    let grpc_client_allowlist = Arc::new(
+        tls::ClientAllowlist::new(config.grpc_tls_allowed_clients).context(LoadingClientAuth)?,
+    );
+    let grpc_tls_acceptor = match (tls_resolver, config.grpc_tls_client_ca) {
+        (Some(resolver), Some(ca_path)) => {
+            info!(?ca_path, "Requiring gRPC client certificates");
+            Some(tls::make_client_auth_acceptor(resolver, &ca_path).context(LoadingClientAuth)?)
+        }
+        (_, None) if *grpc_client_allowlist == tls::ClientAllowlist::default() => {
+            tls_acceptor.clone()
+        }
+        _ => return InvalidClientAuthConfiguration.fail(),
+    };
+
+    let query_log = Arc::new(query_log::QueryLog::new(config.query_log_sample_rate));
+
+    // Each listener may only accept clients from some addresses
+    let ip_filter = |listener: &'static str, allowed: &[String], denied: &[String]| {
+        let filter =
+            ip_filter::IpFilter::new(allowed, denied).context(InvalidIpFilter { listener })?;
+        if filter.enabled() {
+            info!(%listener, ?allowed, ?denied, "Filtering clients by IP address");
+        }
+        Ok::<_, Error>(Arc::new(filter))
+    };
+    let http_ip_filter = ip_filter("HTTP", &config.api_allowed_ips, &config.api_denied_ips)?;
+    let admin_ip_filter = ip_filter("admin", &config.admin_allowed_ips, &config.admin_denied_ips)?;
+    let grpc_ip_filter = ip_filter("gRPC", &config.grpc_allowed_ips, &config.grpc_denied_ips)?;
+
+    // Construct and start up gRPC server
+
+    serving_readiness.start_phase(StartupPhase::BindingListeners);
+    let grpc_bind_addr = config.grpc_bind_address;
+    let socket = tokio::net::TcpListener::bind(grpc_bind_addr)
+        .await
+        .context(StartListeningGrpc { grpc_bind_addr })?;
+
+    // The bound address may differ from the configured one, e.g. if port 0
+    // was requested the OS picks a free port
+    let grpc_listen_addr = socket
+        .local_addr()
+        .context(StartListeningGrpc { grpc_bind_addr })?;
+
+    let configured_tokens = secrets::ConfiguredTokens {
+        all: config.api_tokens,
+        read: config.api_read_tokens,
+        write: config.api_write_tokens,
+    };
+    let api_tokens = configured_tokens
+        .load(secrets_provider.as_deref())
+        .await
+        .context(ReadingSecrets)?;
+    let token_store: Arc<dyn auth::TokenStore> = Arc::<AppServer<_>>::clone(&app_server);
+    let mut authorizer = auth::Authorizer::new(api_tokens, token_store);
+    if let Some(issuer) = config.oidc_issuer {
+        let validator = Arc::new(oidc::OidcValidator::new(oidc::OidcConfig {
+            issuer,
+            audience: config.oidc_audience,
+            org_claim: config.oidc_org_claim,
+            scopes_claim: config.oidc_scopes_claim,
+        }));
+        validator.refresh_keys().await.context(LoadingOidcKeys)?;
+        let interval = Duration::from_secs(config.oidc_refresh_interval_seconds.max(1));
+        tokio::spawn(oidc::refresh_keys_periodically(
+            Arc::clone(&validator),
+            interval,
+        ));
+        authorizer = authorizer.with_oidc(validator);
+    }
+    let authorizer = Arc::new(authorizer);
+    if let Some(secrets_provider) = secrets_provider {
+        let interval = Duration::from_secs(config.secrets_refresh_interval_seconds.max(1));
+        tokio::spawn(secrets::refresh_tokens_periodically(
+            Arc::clone(&authorizer),
+            configured_tokens,
+            secrets_provider,
+            interval,
+        ));
+    }
+    if authorizer.enabled() {
+        info!("API token authentication enabled");
+    }
+
+    let grpc_server = self::rpc::make_server(
+        socket,
+        Arc::clone(&app_server),
+        serving_readiness.clone(),
+        Arc::clone(&query_log),
+        Arc::clone(&authorizer),
+        grpc_tls_acceptor,
+        grpc_client_allowlist,
+        grpc_ip_filter,
+        shutdown.clone(),
+    );
+
+    info!(bind_address=?grpc_listen_addr, "gRPC server listening");
+
+    // Construct and start up HTTP server
+
+    let cors = if config.cors_allowed_origins.is_empty() {
+        None
+    } else {
+        info!(allowed_origins=?config.cors_allowed_origins, "CORS enabled");
+        Some(http::CorsConfig {
+            allowed_origins: config.cors_allowed_origins,
+            allowed_methods: config.cors_allowed_methods,
+            allowed_headers: config.cors_allowed_headers,
+            max_age: Some(config.cors_max_age_seconds),
+        })
+    };
+    let read_cache = match config.read_cache_size {
+        0 => None,
+        size => Some(Arc::new(
+            http::ReadCache::new(size, Duration::from_secs(config.read_cache_ttl_seconds))
+                .with_memory(
+                    app_server
+                        .memory_tracker()
+                        .reservation(server::memory::Subsystem::Caches),
+                ),
+        )),
+    };
+    let http_options = http::HttpOptions {
+        compression_min_size: config.http_compression_min_size,
+        cors,
+        max_request_size: config.max_http_request_size,
+        admin_token: config.admin_token,
+        authorizer,
+        write_timeout,
+        read_timeout,
+        log_filter: Some(log_filter),
+        query_log,
+        audit_log,
+        ip_filter: http_ip_filter,
+        read_cache,
+        endpoints: match config.admin_bind_address {
+            Some(_) => http::Endpoints::Data,
+            None => http::Endpoints::All,
+        },
+    };
+
+    // The operational endpoints are served on their own address, if set
+    let admin_server = match config.admin_bind_address {
+        Some(admin_bind_addr) => {
+            let mut builder = http::request_service_builder(
+                Arc::clone(&app_server),
+                serving_readiness.clone(),
+                http::HttpOptions {
+                    endpoints: http::Endpoints::Admin,
+                    ip_filter: admin_ip_filter,
+                    ..http_options.clone()
+                },
+            );
+            let make_service = make_service_fn(move |conn: &AddrStream| {
+                let remote_addr = conn.remote_addr();
+                let service = http::LoggedService::new(builder.build(remote_addr), remote_addr);
+                async move { Ok::<_, std::convert::Infallible>(service) }
+            });
+
+            let admin_server = Server::try_bind(&admin_bind_addr)
+                .context(StartListeningAdmin {
+                    bind_addr: admin_bind_addr,
+                })?
+                .serve(make_service);
+            info!(bind_address=%admin_server.local_addr(), "Admin HTTP server listening");
+            admin_server
+                .with_graceful_shutdown(shutdown.clone())
+                .boxed()
+        }
+        None => futures::future::ok(()).boxed(),
+    };
+
+    let bind_addr = config.http_bind_address;
+    let http_unix_socket = config.http_unix_socket;
+    let (http_server, http_listen_addr) = match (&http_unix_socket, tls_acceptor) {
+        // Local clients connecting over the socket don't need TLS
+        #[cfg(unix)]
+        (Some(path), _) => {
+            let listener = bind_unix_socket(path)?;
+
+            // access is controlled by the permissions of the socket file
+            let mut builder = http::request_service_builder(
+                Arc::clone(&app_server),
+                serving_readiness.clone(),
+                http::HttpOptions {
+                    ip_filter: Default::default(),
+                    ..http_options
+                },
+            );
+            let make_service = make_service_fn(move |_conn: &tokio::net::UnixStream| {
+                // unix socket peers have no IP address
+                let remote_addr = SocketAddr::from(([0, 0, 0, 0], 0));
+                let service = http::LoggedService::new(builder.build(remote_addr), remote_addr);
+                async move { Ok::<_, std::convert::Infallible>(service) }
+            });
+
+            let incoming = accept::from_stream(UnixListenerStream::new(listener));
+            let http_server = http2
+                .apply(Server::builder(incoming))
+                .serve(make_service)
+                .with_graceful_shutdown(shutdown.clone());
+            (http_server.boxed(), format!("unix:{}", path.display()))
+        }
+        #[cfg(not(unix))]
+        (Some(path), _) => {
+            let unsupported = std::io::Error::new(
+                std::io::ErrorKind::Other,
+                "unix sockets are only supported on unix",
+            );
+            return Err(unsupported).context(StartListeningUnix { path });
+        }
+        (None, Some(acceptor)) => {
+            let listener = tokio::net::TcpListener::bind(bind_addr)
+                .await
+                .context(StartListeningHttps { bind_addr })?;
+            let http_listen_addr = listener
+                .local_addr()
+                .context(StartListeningHttps { bind_addr })?;
+
+            let mut builder = http::request_service_builder(
+                Arc::clone(&app_server),
+                serving_readiness.clone(),
+                http_options,
+            );
+            let make_service = make_service_fn(move |conn: &tls::TlsStream| {
+                let remote_addr = conn
+                    .get_ref()
+                    .0
+                    .peer_addr()
+                    .unwrap_or_else(|_| SocketAddr::from(([0, 0, 0, 0], 0)));
+                let service = http::LoggedService::new(builder.build(remote_addr), remote_addr);
+                async move { Ok::<_, std::convert::Infallible>(service) }
+            });
+
+            let incoming = accept::from_stream(tls::incoming(
+                TcpListenerStream::new(listener),
+                acceptor,
+                Default::default(),
+            ));
+            let http_server = http2
+                .apply(Server::builder(incoming))
+                .serve(make_service)
+                .with_graceful_shutdown(shutdown.clone());
+            (http_server.boxed(), http_listen_addr.to_string())
+        }
+        (None, None) => {
+            let mut builder = http::request_service_builder(
+                Arc::clone(&app_server),
+                serving_readiness.clone(),
+                http_options,
+            );
+            let make_service = make_service_fn(move |conn: &AddrStream| {
+                let remote_addr = conn.remote_addr();
+                let service = http::LoggedService::new(builder.build(remote_addr), remote_addr);
+                async move { Ok::<_, std::convert::Infallible>(service) }
+            });
+
+            let http_server = http2
+                .apply(Server::try_bind(&bind_addr).context(StartListeningHttp { bind_addr })?)
+                .serve(make_service);
+            let http_listen_addr = http_server.local_addr();
+            let http_server = http_server.with_graceful_shutdown(shutdown.clone());
+            (http_server.boxed(), http_listen_addr.to_string())
+        }
+    };
+    info!(bind_address=%http_listen_addr, "HTTP server listening");
+
+    // Writes acknowledged before a restart may only be in the write buffer,
+    // so they're replayed before reporting ready. The listeners are already
+    // bound so that /ready and the metrics report the progress of replay.
+    let log_ready = move || {
+        info!(
+            version = build_info::VERSION,
+            git_hash = build_info::GIT_HASH,
+            http_bind_address=%http_listen_addr,
+            grpc_bind_address=?grpc_listen_addr,
+            "InfluxDB IOx server ready"
+        )
+    };
+    if app_server.write_buffer().is_some() {
+        serving_readiness.start_phase(StartupPhase::ReplayingWriteBuffer);
+        let app_server = Arc::clone(&app_server);
+        let serving_readiness = serving_readiness.clone();
+        tokio::spawn(async move {
+            // replay resumes where it stopped on error
+            while let Err(e) = app_server.replay_write_buffer().await {
+                error!("Error replaying the write buffer, retrying: {}", e);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            serving_readiness.finish_startup();
+            log_ready();
+        });
+    } else {
+        serving_readiness.finish_startup();
+        log_ready();
+    }
+
+    // Wait for all the servers to complete, giving up on any requests still
+    // in flight once the shutdown timeout has elapsed. The same deadline
+    // bounds persisting the open WAL segments below.
+    let shutdown_timeout = Duration::from_secs(config.shutdown_timeout_seconds);
+    let servers = futures::future::join3(grpc_server, http_server, admin_server);
+    let deadline = async {
+        shutdown.clone().await;
+        tokio::time::sleep(shutdown_timeout).await;
+    }
+    .shared();
+
+    tokio::select! {
+        (grpc_server, server, admin_server) = servers => {
+            grpc_server.context(ServingRPC)?;
+            server.context(ServingHttp)?;
+            admin_server.context(ServingHttp)?;
+        }
+        _ = deadline.clone() => {
+            warn!(?shutdown_timeout, "In-flight requests did not complete before the shutdown timeout");
+        }
+    }
+
+    if let Some(path) = &http_unix_socket {
+        if let Err(e) = fs::remove_file(path) {
+            warn!(?path, "Unable to remove unix socket: {}", e);
+        }
+    }
+
+    // Persist any writes only held in memory before exiting. Without a
+    // writer id no databases can have been created.
+    if app_server.require_id().is_ok() {
+        tokio::select! {
+            result = app_server.persist_open_wal_segments() => match result {
+                Ok(()) => info!("Persisted open WAL segments"),
+                Err(e) => error!("Error persisting open WAL segments on shutdown: {}", e),
+            },
+            _ = deadline => {
+                warn!(?shutdown_timeout, "Timed out persisting open WAL segments");
+            }
+        }
+    }
+
+    info!("InfluxDB IOx server shutdown");
+
+    Ok(())
+}
+
+/// Creates the object store `kind`, in `bucket` for cloud object stores or
+/// `directory` for files. S3 buckets are reached in `s3_region`, or the
+/// region of `AWS_DEFAULT_REGION` if not set.
+fn create_object_store(
+    kind: Option<ObjStoreOpt>,
+    bucket: Option<String>,
+    directory: Option<PathBuf>,
+    s3_region: Option<&str>,
+    secrets_provider: Option<&Arc<dyn SecretsProvider>>,
+) -> Result<ObjectStore> {
+    Ok(match (kind, bucket, directory) {
         (Some(ObjStoreOpt::Google), Some(bucket), _) => {
             info!("Using GCP bucket {} for storage", bucket);
             ObjectStore::new_google_cloud_storage(GoogleCloudStorage::new(bucket))
@@ -116,7 +917,15 @@ pub async fn main(logging_level: LoggingLevel, config: Option<Config>) -> Result
         (Some(ObjStoreOpt::S3), Some(bucket), _) => {
             info!("Using S3 bucket {} for storage", bucket);
             // rusoto::Region's default takes the value from the AWS_DEFAULT_REGION env var.
-            ObjectStore::new_amazon_s3(AmazonS3::new(Default::default(), bucket))
+            let region = match s3_region {
+                Some(region) => object_store::aws::region(region).context(InvalidS3Region)?,
+                None => Default::default(),
+            };
+            let s3 = match secrets_provider {
+                Some(secrets) => AmazonS3::new_with_secrets(region, bucket, Arc::clone(secrets)),
+                None => AmazonS3::new(region, bucket),
+            };
+            ObjectStore::new_amazon_s3(s3)
         }
         (Some(ObjStoreOpt::S3), None, _) => {
             return InvalidCloudObjectStoreConfiguration {
@@ -145,55 +954,74 @@ pub async fn main(logging_level: LoggingLevel, config: Option<Config>) -> Result
             warn!("NO PERSISTENCE: using memory for object storage");
             ObjectStore::new_in_memory(object_store::memory::InMemory::new())
         }
-    };
-    let object_storage = Arc::new(object_store);
+    })
+}
 
-    let connection_manager = ConnectionManager {};
-    let app_server = Arc::new(AppServer::new(connection_manager, object_storage));
+/// Resolves when the process receives a request to shut down: SIGINT
+/// (ctrl-c) or, on unix, SIGTERM.
+async fn wait_for_shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
 
-    // if this ID isn't set the server won't be usable until this is set via an API
-    // call
-    if let Some(id) = config.writer_id {
-        app_server.set_id(id);
-        if let Err(e) = app_server.load_database_configs().await {
-            error!(
-                "unable to load database configurations from object storage: {}",
-                e
-            )
+        let mut sigterm = signal(SignalKind::terminate()).expect("installing SIGTERM handler");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {},
+            _ = sigterm.recv() => {},
         }
-    } else {
-        warn!("server ID not set. ID must be set via the INFLUXDB_IOX_ID config or API before writing or querying data.");
     }
 
-    // Construct and start up gRPC server
-
-    let grpc_bind_addr = config.grpc_bind_address;
-    let socket = tokio::net::TcpListener::bind(grpc_bind_addr)
-        .await
-        .context(StartListeningGrpc { grpc_bind_addr })?;
-
-    let grpc_server = self::rpc::make_server(socket, Arc::clone(&app_server));
-
-    info!(bind_address=?grpc_bind_addr, "gRPC server listening");
-
-    // Construct and start up HTTP server
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
 
-    let router_service = http::router_service(Arc::clone(&app_server));
+/// Binds a unix socket at `path`, first removing any socket left behind by
+/// a previous run. Other files at `path` are not removed.
+#[cfg(unix)]
+fn bind_unix_socket(path: &std::path::Path) -> Result<tokio::net::UnixListener> {
+    use std::os::unix::fs::FileTypeExt;
 
-    let bind_addr = config.http_bind_address;
-    let http_server = Server::try_bind(&bind_addr)
-        .context(StartListeningHttp { bind_addr })?
-        .serve(router_service);
-    info!(bind_address=?bind_addr, "HTTP server listening");
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        if metadata.file_type().is_socket() {
+            fs::remove_file(path).context(StartListeningUnix { path })?;
+        }
+    }
+    tokio::net::UnixListener::bind(path).context(StartListeningUnix { path })
+}
 
-    let git_hash = option_env!("GIT_HASH").unwrap_or("UNKNOWN");
-    info!(git_hash, "InfluxDB IOx server ready");
+/// The HTTP/2 connection settings of the HTTP API listener. HTTP/2 is
+/// negotiated with ALPN over TLS, and used without TLS by clients that
+/// start the connection with the HTTP/2 preface.
+#[derive(Debug, Clone, Copy)]
+struct Http2Settings {
+    max_concurrent_streams: Option<u32>,
+    keepalive_interval: Option<Duration>,
+    keepalive_timeout: Duration,
+}
 
-    // Wait for both the servers to complete
-    let (grpc_server, server) = futures::future::join(grpc_server, http_server).await;
+impl Http2Settings {
+    fn new(config: &Config) -> Self {
+        Self {
+            max_concurrent_streams: config.http2_max_concurrent_streams,
+            keepalive_interval: timeout(config.http2_keepalive_interval_seconds),
+            keepalive_timeout: Duration::from_secs(config.http2_keepalive_timeout_seconds),
+        }
+    }
 
-    grpc_server.context(ServingRPC)?;
-    server.context(ServingHttp)?;
+    fn apply<I, E>(self, builder: hyper::server::Builder<I, E>) -> hyper::server::Builder<I, E> {
+        builder
+            .http2_max_concurrent_streams(self.max_concurrent_streams)
+            .http2_keep_alive_interval(self.keepalive_interval)
+            .http2_keep_alive_timeout(self.keepalive_timeout)
+    }
+}
 
-    Ok(())
+/// Converts a timeout or interval setting in seconds, where 0 disables it
+fn timeout(seconds: u64) -> Option<Duration> {
+    match seconds {
+        0 => None,
+        seconds => Some(Duration::from_secs(seconds)),
+    }
 }
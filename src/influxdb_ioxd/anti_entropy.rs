@@ -0,0 +1,19 @@
+//! Periodic anti-entropy repair between the replicas of each database.
+
+use std::{sync::Arc, time::Duration};
+
+use server::{ConnectionManagerImpl as ConnectionManager, Server as AppServer};
+use tracing::warn;
+
+/// Runs a round of anti-entropy repair of `server` every `interval`,
+/// starting after the first interval. Runs forever.
+pub async fn repair_periodically(server: Arc<AppServer<ConnectionManager>>, interval: Duration) {
+    let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+
+    loop {
+        interval.tick().await;
+        if let Err(e) = server.repair_round().await {
+            warn!("Error repairing diverging writes: {}", e);
+        }
+    }
+}
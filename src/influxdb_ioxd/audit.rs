@@ -0,0 +1,162 @@
+//! The audit log of administrative actions: creating, changing and deleting
+//! buckets, orgs, databases and tokens, deleting data, and changing the
+//! server's settings.
+//!
+//! Each action is recorded with who took it (the id of the API token, `all`
+//! for the tokens configured with `--api-tokens` or if authentication is
+//! disabled, `admin` for the admin token and `SIGHUP` for reloaded
+//! settings), when, from which address, what it was, and whether it failed.
+//! Records are logged with the `audit` target and, if `--audit-log` is set,
+//! appended to that file as one JSON object per line. The file is only ever
+//! appended to, so it can be shipped and rotated by external tools.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Utc};
+use parking_lot::Mutex;
+use serde::Serialize;
+use snafu::{ResultExt, Snafu};
+use tracing::{error, info, warn};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Unable to open audit log {:?}: {}", path, source))]
+    OpeningAuditLog {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// A single administrative action
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AuditEvent {
+    pub time: DateTime<Utc>,
+    pub actor: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remote_addr: Option<SocketAddr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    /// What was done, e.g. `create_bucket`
+    pub action: &'static str,
+    /// What it was done to, e.g. the request path or the setting changed
+    pub target: String,
+    /// The error the action failed with, if it did
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Records administrative actions as described in the module docs
+#[derive(Debug, Default)]
+pub struct AuditLog {
+    file: Option<Mutex<File>>,
+}
+
+impl AuditLog {
+    /// Creates an audit log appending to the file at `path`, if any, which
+    /// is created if it doesn't exist
+    pub fn open(path: Option<&Path>) -> Result<Self> {
+        let file = path
+            .map(|path| {
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .context(OpeningAuditLog { path })
+            })
+            .transpose()?;
+        if let Some(path) = path {
+            info!(?path, "Appending administrative actions to audit log");
+        }
+        Ok(Self {
+            file: file.map(Mutex::new),
+        })
+    }
+
+    /// Records `event`
+    pub fn record(&self, event: &AuditEvent) {
+        match &event.error {
+            Some(error) => warn!(
+                target: "audit",
+                actor = %event.actor,
+                remote_addr = ?event.remote_addr,
+                request_id = ?event.request_id,
+                action = event.action,
+                target = %event.target,
+                %error,
+                "Administrative action failed"
+            ),
+            None => info!(
+                target: "audit",
+                actor = %event.actor,
+                remote_addr = ?event.remote_addr,
+                request_id = ?event.request_id,
+                action = event.action,
+                target = %event.target,
+                "Administrative action"
+            ),
+        }
+
+        if let Some(file) = &self.file {
+            let mut line = serde_json::to_vec(event).expect("serializing audit event");
+            line.push(b'\n');
+            // a single write, so that concurrent records don't interleave
+            if let Err(e) = file.lock().write_all(&line) {
+                error!("Error appending to audit log: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn append() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.log");
+        std::fs::write(&path, "existing\n").unwrap();
+
+        let event = AuditEvent {
+            time: Utc::now(),
+            actor: "0000000000000001".to_string(),
+            remote_addr: Some("127.0.0.1:1234".parse().unwrap()),
+            request_id: None,
+            action: "delete_bucket",
+            target: "/api/v2/buckets/sensors?org=company".to_string(),
+            error: None,
+        };
+        let log = AuditLog::open(Some(&path)).unwrap();
+        log.record(&event);
+        log.record(&AuditEvent {
+            error: Some("bucket not found".to_string()),
+            ..event.clone()
+        });
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<_> = contents.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], "existing");
+
+        let record: serde_json::Value = serde_json::from_str(lines[1]).unwrap();
+        assert_eq!(record["actor"], "0000000000000001");
+        assert_eq!(record["remote_addr"], "127.0.0.1:1234");
+        assert_eq!(record["action"], "delete_bucket");
+        assert_eq!(record["target"], "/api/v2/buckets/sensors?org=company");
+        assert!(record.get("error").is_none());
+        assert!(record.get("request_id").is_none());
+
+        let record: serde_json::Value = serde_json::from_str(lines[2]).unwrap();
+        assert_eq!(record["error"], "bucket not found");
+
+        // records are only logged without a file
+        AuditLog::default().record(&event);
+    }
+}
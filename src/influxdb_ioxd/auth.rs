@@ -0,0 +1,582 @@
+//! Authentication and authorization of the requests to the HTTP and gRPC
+//! APIs with tokens, so that the server can run on untrusted networks.
+//!
+//! Authentication is enabled by configuring tokens with `--api-tokens`.
+//! Requests to the data endpoints of the HTTP API and to the gRPC services
+//! must then send a token in an `Authorization: Token <token>` header, as
+//! InfluxDB 2.x clients do. `/ping`, the OpenAPI document, the gRPC health
+//! service and the operational HTTP endpoints, guarded by the admin token,
+//! are left open so that load balancers can check the server.
+//!
+//! The tokens configured with `--api-tokens` may do anything, while those
+//! configured with `--api-read-tokens` or `--api-write-tokens` may only
+//! read or only write and delete data, in any org, so that dashboards and
+//! ingestion agents can be given credentials that limit the damage if they
+//! leak. Tokens created through the API are stored in the server's token
+//! catalog, and are limited to their scopes and to their org or bucket, if any.
+//! The `Authorizer` decides what a request may do, so that the rules are the
+//! same for both APIs: a token restricted to an org can't create buckets in,
+//! write to or read from another org, whichever API it is used with. Requests
+//! it doesn't allow fail with `403 Forbidden` over HTTP and `PERMISSION_DENIED`
+//! over gRPC, with the same message.
+//!
+//! If an OpenID Connect issuer is configured, requests may also send a JWT
+//! it issued in an `Authorization: Bearer <jwt>` header, which is validated
+//! by the `oidc` module.
+
+use std::{fmt::Debug, sync::Arc};
+
+use data_types::names::database_to_org_and_bucket;
+use http::{header::AUTHORIZATION, HeaderMap};
+use parking_lot::RwLock;
+use server::{
+    tokens::{Scope, Token},
+    ConnectionManager, Server,
+};
+use snafu::{ResultExt, Snafu};
+use tonic::metadata::MetadataMap;
+
+use super::oidc::{self, OidcValidator};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Missing API token, expected an 'Authorization: Token <token>' header"))]
+    MissingToken,
+
+    #[snafu(display("Invalid API token"))]
+    InvalidToken,
+
+    #[snafu(display("Invalid bearer token: {}", source))]
+    InvalidBearerToken { source: oidc::Error },
+
+    #[snafu(display("Permission denied: token {} can not {} {}", id, action(*scope), resource))]
+    PermissionDenied {
+        id: String,
+        scope: Scope,
+        resource: String,
+    },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+    /// Converts the error into the status of a gRPC response
+    pub fn to_status(&self) -> tonic::Status {
+        match self {
+            Self::MissingToken | Self::InvalidToken | Self::InvalidBearerToken { .. } => {
+                tonic::Status::unauthenticated(self.to_string())
+            }
+            Self::PermissionDenied { .. } => tonic::Status::permission_denied(self.to_string()),
+        }
+    }
+}
+
+fn action(scope: Scope) -> &'static str {
+    match scope {
+        Scope::Read => "read",
+        Scope::Write => "write",
+        Scope::Admin => "administer",
+    }
+}
+
+/// The tokens configured at startup with what they may do, none if
+/// authentication is disabled
+#[derive(Debug, Default)]
+pub struct ApiTokens {
+    tokens: Vec<(String, Access)>,
+}
+
+impl ApiTokens {
+    /// Creates the tokens that may do anything
+    pub fn new(tokens: impl IntoIterator<Item = String>) -> Self {
+        Self::default().with_access(tokens, Access::All)
+    }
+
+    /// Adds tokens that may only do what `scope` allows, in any org
+    pub fn with_scope(self, tokens: impl IntoIterator<Item = String>, scope: Scope) -> Self {
+        let token = Token {
+            id: format!("configured-{}", action(scope)),
+            description: format!("configured {} token", action(scope)),
+            scopes: vec![scope],
+            org: None,
+            bucket: None,
+            limits: Default::default(),
+        };
+        self.with_access(tokens, Access::Token(token))
+    }
+
+    fn with_access(mut self, tokens: impl IntoIterator<Item = String>, access: Access) -> Self {
+        self.tokens.extend(
+            tokens
+                .into_iter()
+                .filter(|token| !token.is_empty())
+                .map(|token| (token, access.clone())),
+        );
+        self
+    }
+
+    /// Whether requests must send a token
+    pub fn enabled(&self) -> bool {
+        !self.tokens.is_empty()
+    }
+
+    /// Returns what `token` may do, if it is one of the tokens
+    fn get(&self, token: &str) -> Option<&Access> {
+        // every token is compared, so that the time taken doesn't tell
+        // which one nearly matched
+        self.tokens.iter().fold(None, |found, (expected, access)| {
+            let valid = constant_time_eq(token.as_bytes(), expected.as_bytes());
+            found.or_else(|| Some(access).filter(|_| valid))
+        })
+    }
+}
+
+/// Looks up the tokens created through the API
+pub trait TokenStore: Debug + Send + Sync + 'static {
+    /// Returns the token whose secret is `secret`, if any
+    fn token(&self, secret: &str) -> Option<Token>;
+}
+
+impl<M> TokenStore for Server<M>
+where
+    M: ConnectionManager + Send + Sync + Debug + 'static,
+{
+    fn token(&self, secret: &str) -> Option<Token> {
+        self.authenticate_token(secret)
+    }
+}
+
+/// Authenticates the requests to both APIs, as described in the module docs
+#[derive(Debug, Default)]
+pub struct Authorizer {
+    tokens: RwLock<ApiTokens>,
+    store: Option<Arc<dyn TokenStore>>,
+    oidc: Option<Arc<OidcValidator>>,
+}
+
+impl Authorizer {
+    pub fn new(tokens: ApiTokens, store: Arc<dyn TokenStore>) -> Self {
+        Self {
+            tokens: RwLock::new(tokens),
+            store: Some(store),
+            oidc: None,
+        }
+    }
+
+    /// Also accepts the bearer tokens `oidc` validates, which enables
+    /// authentication even without configured tokens
+    pub fn with_oidc(self, oidc: Arc<OidcValidator>) -> Self {
+        Self {
+            oidc: Some(oidc),
+            ..self
+        }
+    }
+
+    /// Replaces the configured tokens, e.g. after they were rotated
+    pub fn set_tokens(&self, tokens: ApiTokens) {
+        *self.tokens.write() = tokens;
+    }
+
+    /// Whether requests must send a token
+    pub fn enabled(&self) -> bool {
+        self.tokens.read().enabled() || self.oidc.is_some()
+    }
+
+    /// Returns what a request may do given `authorization`, the value of
+    /// its `Authorization` header
+    pub fn authenticate(&self, authorization: Option<&str>) -> Result<Access> {
+        let token = {
+            let tokens = self.tokens.read();
+            if !tokens.enabled() && self.oidc.is_none() {
+                return Ok(Access::All);
+            }
+            let authorization = authorization.ok_or(Error::MissingToken)?;
+            if let (Some(oidc), Some(jwt)) = (&self.oidc, parse_bearer_token(authorization)) {
+                return oidc
+                    .validate(jwt)
+                    .map(Access::Token)
+                    .context(InvalidBearerToken);
+            }
+            let token = parse_token(authorization).ok_or(Error::InvalidToken)?;
+            if let Some(access) = tokens.get(token) {
+                return Ok(access.clone());
+            }
+            token
+        };
+        self.store
+            .as_ref()
+            .and_then(|store| store.token(token))
+            .map(Access::Token)
+            .ok_or(Error::InvalidToken)
+    }
+
+    /// Authenticates an HTTP request by its `Authorization` header
+    pub fn authenticate_headers(&self, headers: &HeaderMap) -> Result<Access> {
+        self.authenticate(headers.get(AUTHORIZATION).and_then(|v| v.to_str().ok()))
+    }
+
+    /// Authenticates a gRPC request by its `authorization` metadata
+    pub fn authenticate_metadata(&self, metadata: &MetadataMap) -> Result<Access> {
+        self.authenticate(
+            metadata
+                .get(AUTHORIZATION.as_str())
+                .and_then(|v| v.to_str().ok()),
+        )
+    }
+}
+
+/// What an authenticated request may do
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Access {
+    /// Anything, as authentication is disabled or the request sent one of
+    /// the configured tokens that may do anything
+    All,
+    /// What the token, created through the API or configured with a single
+    /// scope, allows
+    Token(Token),
+}
+
+impl Access {
+    /// Checks that the request may do what `scope` allows in `bucket` of
+    /// `org`. Operations on a whole org are checked without a bucket, and
+    /// operations not within an org without either, so that they are only
+    /// allowed to tokens that aren't restricted to one.
+    pub fn authorize(&self, scope: Scope, org: Option<&str>, bucket: Option<&str>) -> Result<()> {
+        let token = match self {
+            Self::All => return Ok(()),
+            Self::Token(token) => token,
+        };
+
+        let org_allowed = match (&token.org, org) {
+            (None, _) => true,
+            (Some(token_org), Some(org)) => token_org == org,
+            (Some(_), None) => false,
+        };
+        let bucket_allowed = match (&token.bucket, bucket) {
+            (None, _) => true,
+            (Some(token_bucket), Some(bucket)) => token_bucket == bucket,
+            (Some(_), None) => false,
+        };
+        if token.scopes.contains(&scope) && org_allowed && bucket_allowed {
+            return Ok(());
+        }
+
+        let resource = match (org, bucket) {
+            (Some(org), Some(bucket)) => format!("bucket {} of org {}", bucket, org),
+            (Some(org), None) => format!("org {}", org),
+            _ => "the server".to_string(),
+        };
+        PermissionDenied {
+            id: &token.id,
+            scope,
+            resource,
+        }
+        .fail()
+    }
+
+    /// Like `authorize`, for the database `db_name`, which is checked as the
+    /// bucket it backs, if any
+    pub fn authorize_database(&self, scope: Scope, db_name: &str) -> Result<()> {
+        match database_to_org_and_bucket(db_name) {
+            Some((org, bucket)) => self.authorize(scope, Some(&org), Some(&bucket)),
+            None => self.authorize(scope, None, None),
+        }
+    }
+
+    /// Checks that the request may act as another server of the cluster,
+    /// which can change the catalog and move data between servers, so is
+    /// only allowed to tokens that may administer the whole server
+    pub fn authorize_peer(&self) -> Result<()> {
+        self.authorize(Scope::Admin, None, None)
+    }
+
+    /// Identifies who made the request in the audit log: the id of its
+    /// token, or `all`
+    pub fn actor(&self) -> &str {
+        match self {
+            Self::All => "all",
+            Self::Token(token) => &token.id,
+        }
+    }
+
+    /// The token the request was made with, whose rate limits it is subject
+    /// to, `None` for the tokens that may do anything
+    pub fn token(&self) -> Option<&Token> {
+        match self {
+            Self::All => None,
+            Self::Token(token) => Some(token),
+        }
+    }
+}
+
+/// Returns the token of the value of an `Authorization: Token <token>`
+/// header, `None` if it uses another scheme
+pub fn parse_token(authorization: &str) -> Option<&str> {
+    parse_authorization(authorization, "token")
+}
+
+/// Returns the token of the value of an `Authorization: Bearer <token>`
+/// header, `None` if it uses another scheme
+fn parse_bearer_token(authorization: &str) -> Option<&str> {
+    parse_authorization(authorization, "bearer")
+}
+
+fn parse_authorization<'a>(authorization: &'a str, expected_scheme: &str) -> Option<&'a str> {
+    let mut parts = authorization.splitn(2, ' ');
+    match (parts.next(), parts.next()) {
+        (Some(scheme), Some(token)) if scheme.eq_ignore_ascii_case(expected_scheme) => {
+            Some(token.trim())
+        }
+        _ => None,
+    }
+}
+
+/// Compares `a` and `b` in time depending only on their lengths, so that
+/// tokens can not be guessed by timing the responses
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/// Returns an interceptor rejecting the gRPC requests that `authorizer`
+/// doesn't authenticate, with the `UNAUTHENTICATED` status
+pub fn interceptor(authorizer: Arc<Authorizer>) -> tonic::Interceptor {
+    tonic::Interceptor::new(move |req: tonic::Request<()>| {
+        match authorizer.authenticate_metadata(req.metadata()) {
+            Ok(_) => Ok(req),
+            Err(e) => Err(e.to_status()),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug)]
+    struct TestStore(Token);
+
+    impl TokenStore for TestStore {
+        fn token(&self, secret: &str) -> Option<Token> {
+            if secret == "created" {
+                Some(self.0.clone())
+            } else {
+                None
+            }
+        }
+    }
+
+    fn token(scopes: Vec<Scope>, org: Option<&str>, bucket: Option<&str>) -> Token {
+        Token {
+            id: "0000000000000001".to_string(),
+            description: String::new(),
+            scopes,
+            org: org.map(ToString::to_string),
+            bucket: bucket.map(ToString::to_string),
+            limits: Default::default(),
+        }
+    }
+
+    #[test]
+    fn authenticate() {
+        let created = token(vec![Scope::Read], None, None);
+        let authorizer = Authorizer::new(
+            ApiTokens::new(vec!["secret".to_string(), "other".to_string()]),
+            Arc::new(TestStore(created.clone())),
+        );
+        assert!(authorizer.enabled());
+        assert_eq!(
+            authorizer.authenticate(Some("Token secret")).unwrap(),
+            Access::All
+        );
+        assert_eq!(
+            authorizer.authenticate(Some("token  other")).unwrap(),
+            Access::All
+        );
+        assert_eq!(
+            authorizer.authenticate(Some("Token created")).unwrap(),
+            Access::Token(created)
+        );
+
+        for authorization in &["Token wrong", "Token secret2", "Bearer secret", "secret"] {
+            let err = authorizer.authenticate(Some(authorization)).unwrap_err();
+            assert!(matches!(err, Error::InvalidToken), "{}", authorization);
+        }
+        let err = authorizer.authenticate(None).unwrap_err();
+        assert!(matches!(err, Error::MissingToken));
+
+        let disabled = Authorizer::new(
+            ApiTokens::new(vec![String::new()]),
+            Arc::new(TestStore(token(vec![], None, None))),
+        );
+        assert!(!disabled.enabled());
+        assert_eq!(disabled.authenticate(None).unwrap(), Access::All);
+    }
+
+    #[test]
+    fn configured_scopes() {
+        let authorizer = Authorizer::new(
+            ApiTokens::new(vec!["secret".to_string()])
+                .with_scope(vec!["dashboard".to_string()], Scope::Read)
+                .with_scope(vec!["telegraf".to_string(), String::new()], Scope::Write),
+            Arc::new(TestStore(token(vec![], None, None))),
+        );
+        let access = |token: &str| {
+            authorizer
+                .authenticate(Some(&format!("Token {}", token)))
+                .unwrap()
+        };
+
+        assert_eq!(access("secret"), Access::All);
+
+        let dashboard = access("dashboard");
+        dashboard
+            .authorize_database(Scope::Read, "company_sensors")
+            .unwrap();
+        dashboard
+            .authorize_database(Scope::Read, "other_sensors")
+            .unwrap();
+        let err = dashboard
+            .authorize_database(Scope::Write, "company_sensors")
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Permission denied: token configured-read can not write bucket sensors of org company"
+        );
+
+        let telegraf = access("telegraf");
+        telegraf
+            .authorize_database(Scope::Write, "company_sensors")
+            .unwrap();
+        for scope in &[Scope::Read, Scope::Admin] {
+            let err = telegraf
+                .authorize_database(*scope, "company_sensors")
+                .unwrap_err();
+            assert!(matches!(err, Error::PermissionDenied { .. }));
+        }
+
+        // empty tokens are ignored
+        let err = authorizer.authenticate(Some("Token ")).unwrap_err();
+        assert!(matches!(err, Error::InvalidToken));
+    }
+
+    #[test]
+    fn bearer_tokens() {
+        let oidc = Arc::new(OidcValidator::new(oidc::OidcConfig {
+            issuer: "https://sso.example.com".to_string(),
+            audience: None,
+            org_claim: "org".to_string(),
+            scopes_claim: "scope".to_string(),
+        }));
+        let authorizer = Authorizer::new(
+            ApiTokens::new(vec![String::new()]),
+            Arc::new(TestStore(token(vec![Scope::Read], None, None))),
+        )
+        .with_oidc(oidc);
+
+        // bearer tokens alone enable authentication
+        assert!(authorizer.enabled());
+        let err = authorizer.authenticate(None).unwrap_err();
+        assert!(matches!(err, Error::MissingToken));
+
+        let err = authorizer.authenticate(Some("Bearer abc")).unwrap_err();
+        assert!(matches!(err, Error::InvalidBearerToken { .. }));
+        assert_eq!(
+            err.to_status().code(),
+            tonic::Code::Unauthenticated,
+            "{}",
+            err
+        );
+
+        // tokens created through the API are still accepted
+        authorizer.authenticate(Some("Token created")).unwrap();
+    }
+
+    #[test]
+    fn authorize() {
+        Access::All.authorize(Scope::Admin, None, None).unwrap();
+
+        let access = Access::Token(token(vec![Scope::Read], Some("company"), None));
+        access
+            .authorize(Scope::Read, Some("company"), Some("sensors"))
+            .unwrap();
+        access
+            .authorize(Scope::Read, Some("company"), None)
+            .unwrap();
+        access
+            .authorize_database(Scope::Read, "company_sensors")
+            .unwrap();
+        for (scope, org, bucket) in &[
+            (Scope::Write, Some("company"), Some("sensors")),
+            (Scope::Read, Some("other"), Some("sensors")),
+            (Scope::Read, None, None),
+        ] {
+            let err = access.authorize(*scope, *org, *bucket).unwrap_err();
+            assert!(matches!(err, Error::PermissionDenied { .. }));
+        }
+        let err = access
+            .authorize_database(Scope::Read, "other_sensors")
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Permission denied: token 0000000000000001 can not read bucket sensors of org other"
+        );
+
+        let access = Access::Token(token(vec![Scope::Write], Some("company"), Some("sensors")));
+        access
+            .authorize(Scope::Write, Some("company"), Some("sensors"))
+            .unwrap();
+        let err = access
+            .authorize(Scope::Write, Some("company"), Some("archive"))
+            .unwrap_err();
+        assert!(matches!(err, Error::PermissionDenied { .. }));
+        let err = access
+            .authorize(Scope::Write, Some("company"), None)
+            .unwrap_err();
+        assert!(matches!(err, Error::PermissionDenied { .. }));
+
+        let access = Access::Token(token(vec![Scope::Admin], None, None));
+        access.authorize(Scope::Admin, None, None).unwrap();
+        let err = access.authorize(Scope::Read, None, None).unwrap_err();
+        assert!(matches!(err, Error::PermissionDenied { .. }));
+    }
+
+    #[test]
+    fn authorize_metadata() {
+        let authorizer = Authorizer::new(
+            ApiTokens::new(vec!["secret".to_string()]),
+            Arc::new(TestStore(token(vec![Scope::Read], Some("company"), None))),
+        );
+        let access = |authorization: Option<&str>| {
+            let mut metadata = MetadataMap::new();
+            if let Some(authorization) = authorization {
+                metadata.insert("authorization", authorization.parse().unwrap());
+            }
+            authorizer.authenticate_metadata(&metadata)
+        };
+
+        access(Some("Token secret"))
+            .unwrap()
+            .authorize_database(Scope::Admin, "other_sensors")
+            .unwrap();
+        access(Some("Token created"))
+            .unwrap()
+            .authorize_database(Scope::Read, "company_sensors")
+            .unwrap();
+
+        let status = access(Some("Token created"))
+            .unwrap()
+            .authorize_database(Scope::Read, "other_sensors")
+            .unwrap_err()
+            .to_status();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+        assert_eq!(
+            status.message(),
+            "Permission denied: token 0000000000000001 can not read bucket sensors of org other"
+        );
+
+        let status = access(None).unwrap_err().to_status();
+        assert_eq!(status.code(), tonic::Code::Unauthenticated);
+        let status = access(Some("Token wrong")).unwrap_err().to_status();
+        assert_eq!(status.code(), tonic::Code::Unauthenticated);
+    }
+}
@@ -0,0 +1,39 @@
+//! Information about the build of the running server, captured at compile
+//! time by `build.rs`
+
+use serde::Serialize;
+
+/// The version of the server
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// The git commit the server was built from
+pub const GIT_HASH: &str = match option_env!("GIT_HASH") {
+    Some(git_hash) => git_hash,
+    None => "UNKNOWN",
+};
+
+/// Everything known about the build, as served at `/debug/build`
+#[derive(Debug, Serialize)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    pub git_hash: &'static str,
+    pub rustc_version: &'static str,
+    pub profile: &'static str,
+    pub features: Vec<&'static str>,
+}
+
+impl BuildInfo {
+    pub fn get() -> Self {
+        Self {
+            version: VERSION,
+            git_hash: GIT_HASH,
+            rustc_version: option_env!("RUSTC_VERSION").unwrap_or("UNKNOWN"),
+            profile: option_env!("IOX_PROFILE").unwrap_or("UNKNOWN"),
+            features: option_env!("IOX_FEATURES")
+                .unwrap_or_default()
+                .split(',')
+                .filter(|feature| !feature.is_empty())
+                .collect(),
+        }
+    }
+}
@@ -0,0 +1,19 @@
+//! Periodic gossip of the cluster membership.
+
+use std::{sync::Arc, time::Duration};
+
+use server::{ConnectionManagerImpl as ConnectionManager, Server as AppServer};
+use tracing::warn;
+
+/// Runs a gossip round of `server` every `interval`, starting immediately.
+/// Runs forever.
+pub async fn gossip_periodically(server: Arc<AppServer<ConnectionManager>>, interval: Duration) {
+    let mut interval = tokio::time::interval(interval);
+
+    loop {
+        interval.tick().await;
+        if let Err(e) = server.gossip_round().await {
+            warn!("Error gossiping cluster membership: {}", e);
+        }
+    }
+}
@@ -0,0 +1,22 @@
+//! Periodic delivery of the writes kept for unavailable peers.
+
+use std::{sync::Arc, time::Duration};
+
+use server::{ConnectionManagerImpl as ConnectionManager, Server as AppServer};
+
+/// How often the hinted writes are sent to their peers
+const DELIVERY_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Sends the peers of `server` the writes hinted for them every
+/// `DELIVERY_INTERVAL`, starting after the first interval. Runs forever.
+pub async fn deliver_periodically(server: Arc<AppServer<ConnectionManager>>) {
+    let mut interval = tokio::time::interval_at(
+        tokio::time::Instant::now() + DELIVERY_INTERVAL,
+        DELIVERY_INTERVAL,
+    );
+
+    loop {
+        interval.tick().await;
+        server.deliver_hints().await;
+    }
+}
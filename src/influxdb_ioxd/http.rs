@@ -11,33 +11,93 @@
 //! database names and may remove this quasi /v2 API.
 
 // Influx crates
-use arrow_deps::datafusion::physical_plan::collect;
 use data_types::{
     database_rules::DatabaseRules,
-    http::{ListDatabasesResponse, WalMetadataQuery},
-    names::{org_and_bucket_to_database, OrgBucketMappingError},
+    http::{
+        Bucket, ListBucketsResponse, ListDatabasesResponse, PageLinks, PatchBucketRequest,
+        RetentionRule, WalMetadataQuery,
+    },
+    names::{database_to_org_and_bucket, org_and_bucket_to_database, OrgBucketMappingError},
+    pool::Pool,
+    redaction::{Redacted, Redaction},
     DatabaseName,
 };
-use influxdb_line_protocol::parse_lines;
+use influxdb_line_protocol::parse_lines_numbered;
 use object_store::ObjectStoreApi;
-use query::{frontend::sql::SQLQueryPlanner, Database, DatabaseStore};
-use server::{ConnectionManager, Server as AppServer};
+use query::{
+    exec::Executor, frontend::sql::SQLQueryPlanner, group_by::GroupByAndAggregate,
+    plan::seriesset::SeriesSetPlans, predicate::Predicate, Database, DatabaseStore,
+};
+use server::{
+    cluster::Member,
+    compaction::CompactionStatus,
+    db::{tombstone::DeletePredicate, Db, PartitionStatus},
+    drain::DrainStatus,
+    orgs::Org,
+    rate_limits::{QueryPermit, RateLimits},
+    rebalance::ShardMove,
+    router::Shard,
+    tokens::{Scope, Token, TokenRequest},
+    ConnectionManager, Server as AppServer,
+};
 
 // External crates
-use bytes::{Bytes, BytesMut};
-use futures::{self, StreamExt};
-use http::header::{CONTENT_ENCODING, CONTENT_TYPE};
+use bytes::Bytes;
+use futures::{self, future::BoxFuture, Future, FutureExt, StreamExt};
+use http::{
+    header::{CONTENT_ENCODING, CONTENT_TYPE, RETRY_AFTER, WARNING, WWW_AUTHENTICATE},
+    HeaderValue, Uri,
+};
 use hyper::{Body, Method, Request, Response, StatusCode};
-use routerify::{prelude::*, Middleware, RequestInfo, Router, RouterError, RouterService};
+use once_cell::sync::Lazy;
+use routerify::{
+    prelude::*, Middleware, RequestInfo, RequestServiceBuilder, Router, RouterBuilder, RouterError,
+};
 use serde::{Deserialize, Serialize};
-use snafu::{OptionExt, ResultExt, Snafu};
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
 use tracing::{debug, error, info};
 
 use data_types::http::WalMetadataResponse;
-use std::{fmt::Debug, str, sync::Arc};
+use std::{
+    fmt::Debug,
+    str,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::{sync::mpsc, time::Instant};
+
+use super::{
+    audit::{AuditEvent, AuditLog},
+    auth::{self as api_auth, Access, Authorizer},
+    build_info::{self, BuildInfo},
+    ip_filter::IpFilter,
+    query_log::{self, QueryLog},
+    request_id::REQUEST_ID_HEADER,
+    serving_readiness::{ServingReadiness, StartupPhase},
+};
+use crate::commands::logging::{self, LogFilterHandle};
 
+mod auth;
+mod compression;
+mod cors;
 mod format;
+mod openapi;
+mod read;
+mod read_cache;
+mod request_logging;
+use auth::AdminToken;
+pub use cors::CorsConfig;
 use format::QueryOutputFormat;
+use read::{
+    compute_fields, timestamp_nanos, ComputedField, ReadFormat, ReadOptions, ReadParams,
+    ReadResults, CSV_CHUNK_SIZE,
+};
+pub use read_cache::ReadCache;
+use read_cache::{CacheKey, CachedRead};
+pub use request_logging::LoggedService;
 
 #[derive(Debug, Snafu)]
 pub enum ApplicationError {
@@ -68,7 +128,34 @@ pub enum ApplicationError {
         source: Box<dyn std::error::Error + Send + Sync>,
     },
 
-    #[snafu(display("Error planning query {}: {}", query, source))]
+    #[snafu(display("Unable to write points: {}", source))]
+    QuotaExceeded { source: server::Error },
+
+    #[snafu(display("{}", source))]
+    RateLimited { source: server::Error },
+
+    #[snafu(display("Unable to replicate points: {}", source))]
+    WriteQuorumNotReached { source: server::Error },
+
+    #[snafu(display("Unable to write points: {}", source))]
+    ReadReplicaWrite { source: server::Error },
+
+    #[snafu(display("Unable to route points: {}", source))]
+    ShardWriteFailed { source: server::Error },
+
+    #[snafu(display("Unable to write points: {}", source))]
+    WriteBufferFailed { source: server::Error },
+
+    #[snafu(display("Unable to write points: {}", source))]
+    Draining { source: server::Error },
+
+    #[snafu(display("Unable to write points: {}", source))]
+    QuorumLost { source: server::Error },
+
+    #[snafu(display("Unable to write points: {}", source))]
+    MemoryLimitReached { source: server::Error },
+
+    #[snafu(display("Error planning query {}: {}", query_log::loggable_sql(query), source))]
     PlanningSQLQuery {
         query: String,
         source: query::frontend::sql::Error,
@@ -90,9 +177,19 @@ pub enum ApplicationError {
     #[snafu(display("Expected query string in request, but none was provided"))]
     ExpectedQueryString {},
 
+    #[snafu(display("Invalid limit {}: must be between 1 and {}", limit, max))]
+    InvalidPageLimit { limit: usize, max: usize },
+
+    #[snafu(display("Listing buckets after a bucket name requires an org"))]
+    BucketCursorWithoutOrg {},
+
     /// Error for when we could not parse the http query uri (e.g.
     /// `?foo=bar&bar=baz)`
-    #[snafu(display("Invalid query string in HTTP URI '{}': {}", query_string, source))]
+    #[snafu(display(
+        "Invalid query string in HTTP URI '{}': {}",
+        loggable_query(query_string),
+        source
+    ))]
     InvalidQueryString {
         query_string: String,
         source: serde_urlencoded::de::Error,
@@ -124,8 +221,13 @@ pub enum ApplicationError {
     #[snafu(display("Error reading request body as utf8: {}", source))]
     ReadingBodyAsUtf8 { source: std::str::Utf8Error },
 
-    #[snafu(display("Error parsing line protocol: {}", source))]
+    #[snafu(display(
+        "Error parsing line {} of line protocol: {}",
+        line,
+        Redaction::current().line_protocol_error(source)
+    ))]
     ParsingLineProtocol {
+        line: usize,
         source: influxdb_line_protocol::Error,
     },
 
@@ -163,7 +265,7 @@ pub enum ApplicationError {
 
     #[snafu(display(
         "Error formatting results of SQL query '{}' using '{:?}': {}",
-        q,
+        query_log::loggable_sql(q),
         format,
         source
     ))]
@@ -172,6 +274,166 @@ pub enum ApplicationError {
         format: QueryOutputFormat,
         source: format::Error,
     },
+
+    #[snafu(display("Server is not ready to serve requests"))]
+    ServiceNotReady {},
+
+    #[snafu(display("Server is starting up: {}", status))]
+    StartingUp { status: String },
+
+    #[snafu(display("Server is draining"))]
+    ServerDraining {},
+
+    #[snafu(display("Internal error compressing HTTP response: {}", source))]
+    CompressingResponse { source: compression::Error },
+
+    #[snafu(display("Error updating bucket {} in org {}: {}", bucket, org, source))]
+    UpdatingBucket {
+        org: String,
+        bucket: String,
+        source: server::Error,
+    },
+
+    #[snafu(display("Error deleting bucket {} in org {}: {}", bucket, org, source))]
+    DeletingBucket {
+        org: String,
+        bucket: String,
+        source: server::Error,
+    },
+
+    #[snafu(display("Error creating org: {}", source))]
+    CreatingOrg { source: server::Error },
+
+    #[snafu(display("Error updating org {}: {}", id, source))]
+    UpdatingOrg { id: String, source: server::Error },
+
+    #[snafu(display("Error deleting org {}: {}", id, source))]
+    DeletingOrg { id: String, source: server::Error },
+
+    #[snafu(display("Org {} not found", id))]
+    OrgNotFound { id: String },
+
+    #[snafu(display("Error getting the usage of org {}: {}", id, source))]
+    GettingOrgUsage { id: String, source: server::Error },
+
+    #[snafu(display(
+        "Invalid {} time '{}', expected an RFC3339 time between 1677-09-21 and 2262-04-11",
+        name,
+        value
+    ))]
+    InvalidDeleteTime { name: &'static str, value: String },
+
+    #[snafu(display("{}", source))]
+    ReadError { source: read::Error },
+
+    #[snafu(display("{}", source))]
+    InvalidDeletePredicate {
+        source: server::db::tombstone::Error,
+    },
+
+    #[snafu(display(
+        "Error deleting data from bucket {} in org {}: {}",
+        bucket,
+        org,
+        source
+    ))]
+    DeletingData {
+        org: String,
+        bucket: String,
+        source: server::Error,
+    },
+
+    #[snafu(display("{}", source))]
+    AdminAuthorization { source: auth::Error },
+
+    #[snafu(display("{}", source))]
+    ApiAuthorization { source: api_auth::Error },
+
+    #[snafu(display("Error creating token: {}", source))]
+    CreatingToken { source: server::Error },
+
+    #[snafu(display("Error deleting token {}: {}", id, source))]
+    DeletingToken { id: String, source: server::Error },
+
+    #[snafu(display("Token {} not found", id))]
+    TokenNotFound { id: String },
+
+    #[snafu(display(
+        "Error persisting partition {} of bucket {} in org {}: {}",
+        partition,
+        bucket,
+        org,
+        source
+    ))]
+    PersistingPartition {
+        org: String,
+        bucket: String,
+        partition: String,
+        source: server::Error,
+    },
+
+    #[snafu(display(
+        "Error starting compaction of bucket {} in org {}: {}",
+        bucket,
+        org,
+        source
+    ))]
+    StartingCompaction {
+        org: String,
+        bucket: String,
+        source: server::Error,
+    },
+
+    #[snafu(display("Compaction {} not found", id))]
+    CompactionNotFound { id: String },
+
+    #[snafu(display(
+        "Error getting the partition status of bucket {} in org {}: {}",
+        bucket,
+        org,
+        source
+    ))]
+    GettingPartitionStatus {
+        org: String,
+        bucket: String,
+        source: server::Error,
+    },
+
+    #[snafu(display("The log filter can not be changed on this server"))]
+    LogFilterUnavailable {},
+
+    #[snafu(display("Cluster membership is not enabled on this server"))]
+    ClusterDisabled {},
+
+    #[snafu(display("The server is not draining"))]
+    NotDraining {},
+
+    #[snafu(display("Writes are not routed to shards on this server"))]
+    RoutingDisabled {},
+
+    #[snafu(display("Shard {} not found", id))]
+    ShardNotFound { id: String },
+
+    #[snafu(display("Error moving shard {}: {}", id, source))]
+    MovingShard { id: String, source: server::Error },
+
+    #[snafu(display("{}", source))]
+    LogFilterError { source: logging::Error },
+
+    #[snafu(display("Request timed out after {:?}", timeout))]
+    RequestTimedOut { timeout: Duration },
+
+    #[snafu(display(
+        "Invalid timeout '{}': expected a positive duration such as 500ms, 30s or 5m",
+        value
+    ))]
+    InvalidTimeout { value: String },
+
+    #[snafu(display("Timed out reading request body"))]
+    RequestBodyTimedOut {},
+
+    #[snafu(display("Requests from {} are not allowed", ip))]
+    IpNotAllowed { ip: std::net::IpAddr },
 }
 
 impl ApplicationError {
@@ -180,12 +442,27 @@ impl ApplicationError {
             Self::BucketByName { .. } => self.internal_error(),
             Self::BucketMappingError { .. } => self.internal_error(),
             Self::WritingPoints { .. } => self.internal_error(),
+            Self::QuotaExceeded { .. } => self.too_many_requests(),
+            Self::WriteQuorumNotReached { .. } => self.service_unavailable(),
+            Self::ReadReplicaWrite { .. } => self.forbidden(),
+            Self::ShardWriteFailed { .. } => self.service_unavailable(),
+            Self::WriteBufferFailed { .. } => self.service_unavailable(),
+            Self::Draining { .. } => self.service_unavailable(),
+            Self::QuorumLost { .. } => self.service_unavailable(),
+            Self::MemoryLimitReached { .. } => self.service_unavailable(),
+            Self::RateLimited { source } => match source {
+                server::Error::RateLimited { retry_after, .. } => self.rate_limited(*retry_after),
+                server::Error::MemoryLimitReached { .. } => self.service_unavailable(),
+                _ => self.too_many_requests(),
+            },
             Self::PlanningSQLQuery { .. } => self.bad_request(),
             Self::Query { .. } => self.internal_error(),
             Self::QueryError { .. } => self.bad_request(),
             Self::BucketNotFound { .. } => self.not_found(),
             Self::RequestSizeExceeded { .. } => self.bad_request(),
             Self::ExpectedQueryString { .. } => self.bad_request(),
+            Self::InvalidPageLimit { .. } => self.bad_request(),
+            Self::BucketCursorWithoutOrg { .. } => self.bad_request(),
             Self::InvalidQueryString { .. } => self.bad_request(),
             Self::InvalidRequestBody { .. } => self.bad_request(),
             Self::InternalSerializationError { .. } => self.internal_error(),
@@ -198,41 +475,188 @@ impl ApplicationError {
             Self::RouteNotFound { .. } => self.not_found(),
             Self::DatabaseError { .. } => self.internal_error(),
             Self::JsonGenerationError { .. } => self.internal_error(),
-            Self::ErrorCreatingDatabase { .. } => self.bad_request(),
+            Self::ErrorCreatingDatabase { source } => match source {
+                server::Error::OrgNotFound { .. } => self.not_found(),
+                server::Error::QuorumLost { .. } => self.service_unavailable(),
+                _ => self.bad_request(),
+            },
             Self::DatabaseNameError { .. } => self.bad_request(),
             Self::DatabaseNotFound { .. } => self.not_found(),
             Self::WALNotFound { .. } => self.not_found(),
             Self::CreatingResponse { .. } => self.internal_error(),
             Self::FormattingResult { .. } => self.internal_error(),
+            Self::ServiceNotReady { .. } => self.service_unavailable(),
+            Self::StartingUp { .. } => self.service_unavailable(),
+            Self::ServerDraining { .. } => self.service_unavailable(),
+            Self::CompressingResponse { .. } => self.internal_error(),
+            Self::UpdatingBucket { source, .. } | Self::DeletingBucket { source, .. } => {
+                match source {
+                    server::Error::QuorumLost { .. } => self.service_unavailable(),
+                    _ => self.internal_error(),
+                }
+            }
+            Self::CreatingOrg { source }
+            | Self::UpdatingOrg { source, .. }
+            | Self::DeletingOrg { source, .. } => match source {
+                server::Error::OrgNotFound { .. } => self.not_found(),
+                server::Error::OrgAlreadyExists { .. } | server::Error::OrgHasBuckets { .. } => {
+                    self.conflict()
+                }
+                server::Error::InvalidOrgName { .. } => self.bad_request(),
+                server::Error::QuorumLost { .. } => self.service_unavailable(),
+                _ => self.internal_error(),
+            },
+            Self::OrgNotFound { .. } => self.not_found(),
+            Self::GettingOrgUsage { .. } => self.internal_error(),
+            Self::ReadError { source } => match source {
+                read::Error::InvalidTime { .. }
+                | read::Error::InvalidTimeRange { .. }
+                | read::Error::InvalidPredicate { .. }
+                | read::Error::InvalidRegex { .. }
+                | read::Error::InvalidField { .. }
+                | read::Error::ComputedSelector { .. }
+                | read::Error::InvalidShift { .. }
+                | read::Error::InvalidResample { .. }
+                | read::Error::WindowedResample { .. }
+                | read::Error::TooManyResampledPoints { .. }
+                | read::Error::InvalidEvery { .. }
+                | read::Error::IncompleteWindow { .. }
+                | read::Error::GroupedWindow { .. }
+                | read::Error::UnwindowedAggregate { .. }
+                | read::Error::InvalidPercentile { .. }
+                | read::Error::UnwindowedTop { .. }
+                | read::Error::InvalidTop { .. }
+                | read::Error::UnionTop { .. }
+                | read::Error::FilledSelector { .. }
+                | read::Error::InvalidUnit { .. }
+                | read::Error::GroupedPivot { .. }
+                | read::Error::UnwindowedFill { .. }
+                | read::Error::TooManyWindows { .. } => self.bad_request(),
+                _ => self.internal_error(),
+            },
+            Self::InvalidDeleteTime { .. } => self.bad_request(),
+            Self::InvalidDeletePredicate { .. } => self.bad_request(),
+            Self::DeletingData { .. } => self.internal_error(),
+            Self::AdminAuthorization { source } => match source {
+                auth::Error::AdminDisabled => self.forbidden(),
+                auth::Error::InvalidToken => self.unauthorized(),
+            },
+            Self::ApiAuthorization { source } => match source {
+                api_auth::Error::PermissionDenied { .. } => self.forbidden(),
+                _ => self.unauthorized(),
+            },
+            Self::CreatingToken { source } => match source {
+                server::Error::InvalidToken { .. } => self.bad_request(),
+                server::Error::QuorumLost { .. } => self.service_unavailable(),
+                _ => self.internal_error(),
+            },
+            Self::DeletingToken { source, .. } => match source {
+                server::Error::TokenNotFound { .. } => self.not_found(),
+                server::Error::QuorumLost { .. } => self.service_unavailable(),
+                _ => self.internal_error(),
+            },
+            Self::TokenNotFound { .. } => self.not_found(),
+            Self::PersistingPartition { source, .. }
+            | Self::StartingCompaction { source, .. }
+            | Self::GettingPartitionStatus { source, .. } => match source {
+                server::Error::DatabaseNotFound { .. }
+                | server::Error::PartitionNotFound { .. } => self.not_found(),
+                server::Error::PartitionLeaseHeld { .. } => self.conflict(),
+                _ => self.internal_error(),
+            },
+            Self::CompactionNotFound { .. } => self.not_found(),
+            Self::LogFilterUnavailable { .. } => self.not_found(),
+            Self::ClusterDisabled { .. } => self.not_found(),
+            Self::NotDraining { .. } => self.not_found(),
+            Self::RoutingDisabled { .. } => self.not_found(),
+            Self::ShardNotFound { .. } => self.not_found(),
+            Self::MovingShard { source, .. } => match source {
+                server::Error::InvalidShardMove {
+                    source: server::router::Error::ShardNotFound { .. },
+                } => self.not_found(),
+                server::Error::InvalidShardMove { .. } => self.bad_request(),
+                server::Error::ShardAlreadyOwned { .. }
+                | server::Error::ShardMoveRunning { .. } => self.conflict(),
+                _ => self.internal_error(),
+            },
+            Self::LogFilterError { source } => match source {
+                logging::Error::InvalidLogFilter { .. } => self.bad_request(),
+                _ => self.internal_error(),
+            },
+            Self::RequestTimedOut { .. } => self.gateway_timeout(),
+            Self::InvalidTimeout { .. } => self.bad_request(),
+            Self::RequestBodyTimedOut { .. } => self.request_timeout(),
+            Self::IpNotAllowed { .. } => self.forbidden(),
         }
     }
 
     fn bad_request(&self) -> Response<Body> {
-        Response::builder()
-            .status(StatusCode::BAD_REQUEST)
-            .body(self.body())
-            .unwrap()
+        self.error_response(StatusCode::BAD_REQUEST, "invalid")
     }
 
     fn internal_error(&self) -> Response<Body> {
-        Response::builder()
-            .status(StatusCode::INTERNAL_SERVER_ERROR)
-            .body(self.body())
-            .unwrap()
+        self.error_response(StatusCode::INTERNAL_SERVER_ERROR, "internal error")
+    }
+
+    fn unauthorized(&self) -> Response<Body> {
+        let mut response = self.error_response(StatusCode::UNAUTHORIZED, "unauthorized");
+        response
+            .headers_mut()
+            .insert(WWW_AUTHENTICATE, HeaderValue::from_static("Token"));
+        response
+    }
+
+    fn forbidden(&self) -> Response<Body> {
+        self.error_response(StatusCode::FORBIDDEN, "forbidden")
+    }
+
+    fn conflict(&self) -> Response<Body> {
+        self.error_response(StatusCode::CONFLICT, "conflict")
+    }
+
+    fn too_many_requests(&self) -> Response<Body> {
+        self.error_response(StatusCode::TOO_MANY_REQUESTS, "too many requests")
+    }
+
+    /// Like `too_many_requests`, telling the client how long to wait before
+    /// retrying, in whole seconds
+    fn rate_limited(&self, retry_after: Duration) -> Response<Body> {
+        let mut response = self.too_many_requests();
+        let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+        response
+            .headers_mut()
+            .insert(RETRY_AFTER, HeaderValue::from(seconds.max(1)));
+        response
+    }
+
+    fn service_unavailable(&self) -> Response<Body> {
+        self.error_response(StatusCode::SERVICE_UNAVAILABLE, "unavailable")
+    }
+
+    fn request_timeout(&self) -> Response<Body> {
+        self.error_response(StatusCode::REQUEST_TIMEOUT, "invalid")
+    }
+
+    fn gateway_timeout(&self) -> Response<Body> {
+        self.error_response(StatusCode::GATEWAY_TIMEOUT, "unavailable")
     }
 
     fn not_found(&self) -> Response<Body> {
-        Response::builder()
-            .status(StatusCode::NOT_FOUND)
-            .body(Body::empty())
-            .unwrap()
+        self.error_response(StatusCode::NOT_FOUND, "not found")
     }
 
-    fn body(&self) -> Body {
-        let json =
-            serde_json::json!({"error": self.to_string(), "error_code": self.api_error_code()})
-                .to_string();
-        Body::from(json)
+    fn error_response(&self, status: StatusCode, code: &'static str) -> Response<Body> {
+        let line = match self {
+            Self::ParsingLineProtocol { line, .. } => Some(*line),
+            _ => None,
+        };
+        ErrorResponse {
+            code,
+            message: self.to_string(),
+            line,
+            error_code: self.api_error_code(),
+        }
+        .response(status)
     }
 
     /// Map the error type into an API error code.
@@ -263,589 +687,5329 @@ impl ApplicationError {
     }
 }
 
-const MAX_SIZE: usize = 10_485_760; // max write request size of 10MB
-
-fn router<M>(server: Arc<AppServer<M>>) -> Router<Body, ApplicationError>
-where
-    M: ConnectionManager + Send + Sync + Debug + 'static,
-{
-    // Create a router and specify the the handlers.
-    Router::builder()
-        .data(server)
-        .middleware(Middleware::pre(|req| async move {
-            info!(request = ?req, "Processing request");
-            Ok(req)
-        }))
-        .middleware(Middleware::post(|res| async move {
-            info!(response = ?res, "Successfully processed request");
-            Ok(res)
-        })) // this endpoint is for API backward compatibility with InfluxDB 2.x
-        .post("/api/v2/write", write::<M>)
-        .get("/ping", ping)
-        .get("/health", health)
-        .get("/iox/api/v1/databases", list_databases::<M>)
-        .put("/iox/api/v1/databases/:name", create_database::<M>)
-        .get("/iox/api/v1/databases/:name", get_database::<M>)
-        .get("/iox/api/v1/databases/:name/query", query::<M>)
-        .get("/iox/api/v1/databases/:name/wal/meta", get_wal_meta::<M>)
-        .put("/iox/api/v1/id", set_writer::<M>)
-        .get("/iox/api/v1/id", get_writer::<M>)
-        .get("/api/v1/partitions", list_partitions::<M>)
-        .post("/api/v1/snapshot", snapshot_partition::<M>)
-        // Specify the error handler to handle any errors caused by
-        // a route or any middleware.
-        .err_handler_with_info(error_handler)
-        .build()
-        .unwrap()
+/// The body of error responses, following the error schema of the InfluxDB
+/// 2.0 API
+#[derive(Debug, Serialize)]
+struct ErrorResponse {
+    /// The InfluxDB 2.0 error code, such as `invalid` or `not found`
+    code: &'static str,
+    message: String,
+    /// For writes, the number of the first line of line protocol that could
+    /// not be parsed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    line: Option<usize>,
+    /// The IOx specific error code, see `ApiErrorCode`
+    error_code: u32,
 }
 
-// The API-global error handler, handles ApplicationErrors originating from
-// individual routes and middlewares, along with errors from the router itself
-async fn error_handler(err: RouterError<ApplicationError>, req: RequestInfo) -> Response<Body> {
-    match err {
-        RouterError::HandleRequest(e, _)
-        | RouterError::HandlePreMiddlewareRequest(e)
-        | RouterError::HandlePostMiddlewareWithInfoRequest(e)
-        | RouterError::HandlePostMiddlewareWithoutInfoRequest(e) => {
-            error!(error = ?e, error_message = ?e.to_string(), "Error while handling request");
-            e.response()
-        }
-        _ => {
-            let method = req.method().clone();
-            let uri = req.uri().clone();
-            error!(error = ?err, error_message = ?err.to_string(), method = ?method, uri = ?uri, "Error while handling request");
-
-            let json = serde_json::json!({"error": err.to_string()}).to_string();
-            Response::builder()
-                .status(StatusCode::INTERNAL_SERVER_ERROR)
-                .body(Body::from(json))
-                .unwrap()
-        }
+impl ErrorResponse {
+    fn response(&self, status: StatusCode) -> Response<Body> {
+        let json = serde_json::to_string(self).expect("error responses serialize");
+        Response::builder()
+            .status(status)
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(json))
+            .unwrap()
     }
 }
 
-#[derive(Debug, Deserialize)]
-/// Body of the request to the /write endpoint
-struct WriteInfo {
-    org: String,
-    bucket: String,
-}
+/// The default maximum size of request bodies, 10MB
+pub const DEFAULT_MAX_REQUEST_SIZE: usize = 10_485_760;
 
-/// Parse the request's body into raw bytes, applying size limits and
-/// content encoding as needed.
-async fn parse_body(req: hyper::Request<Body>) -> Result<Bytes, ApplicationError> {
-    // clippy says the const needs to be assigned to a local variable:
-    // error: a `const` item with interior mutability should not be borrowed
-    let header_name = CONTENT_ENCODING;
-    let ungzip = match req.headers().get(&header_name) {
-        None => false,
-        Some(content_encoding) => {
-            let content_encoding = content_encoding.to_str().context(ReadingHeaderAsUtf8 {
-                header_name: header_name.as_str(),
-            })?;
-            match content_encoding {
-                "gzip" => true,
-                _ => InvalidContentEncoding { content_encoding }.fail()?,
-            }
-        }
-    };
+/// The maximum size of request bodies, after decompression. Stored in the
+/// router data so that `parse_body` can find it.
+#[derive(Debug, Clone, Copy)]
+struct MaxRequestSize(usize);
 
-    let mut payload = req.into_body();
+/// The number of body buffers kept in `BODY_BUFFERS` between writes
+const MAX_IDLE_BODY_BUFFERS: usize = 64;
 
-    let mut body = BytesMut::new();
-    while let Some(chunk) = payload.next().await {
-        let chunk = chunk.expect("Should have been able to read the next chunk");
-        // limit max size of in-memory payload
-        if (body.len() + chunk.len()) > MAX_SIZE {
-            return Err(ApplicationError::RequestSizeExceeded {
-                max_body_size: MAX_SIZE,
-            });
-        }
-        body.extend_from_slice(&chunk);
-    }
-    let body = body.freeze();
+/// Body buffers that grew larger than this are dropped rather than kept, so
+/// that a large write doesn't hold on to its buffer
+const MAX_POOLED_BODY_BYTES: usize = 1024 * 1024;
 
-    // apply any content encoding needed
-    if ungzip {
-        use std::io::Read;
-        let decoder = flate2::read::GzDecoder::new(&body[..]);
+/// The buffers the bodies of writes are read into, reused across requests
+/// rather than allocated for each one (see [`data_types::pool`])
+static BODY_BUFFERS: Lazy<Pool<Vec<u8>>> = Lazy::new(|| {
+    Pool::new(MAX_IDLE_BODY_BUFFERS, Vec::new, |body| {
+        body.clear();
+        body.capacity() <= MAX_POOLED_BODY_BYTES
+    })
+});
 
-        // Read at most MAX_SIZE bytes to prevent a decompression bomb based
-        // DoS.
-        let mut decoder = decoder.take(MAX_SIZE as u64);
-        let mut decoded_data = Vec::new();
-        decoder
-            .read_to_end(&mut decoded_data)
-            .context(ReadingBodyAsGzip)?;
-        Ok(decoded_data.into())
-    } else {
-        Ok(body)
-    }
-}
+/// Options controlling how the HTTP API serves requests
+#[derive(Debug, Clone)]
+pub struct HttpOptions {
+    /// Responses with bodies of at least this many bytes are compressed if
+    /// the client sends a supported `Accept-Encoding`
+    pub compression_min_size: usize,
 
-#[tracing::instrument(level = "debug")]
-async fn write<M>(req: Request<Body>) -> Result<Response<Body>, ApplicationError>
-where
-    M: ConnectionManager + Send + Sync + Debug + 'static,
-{
-    let server = Arc::clone(&req.data::<Arc<AppServer<M>>>().expect("server state"));
+    /// If set, cross-origin requests from browsers are allowed as
+    /// configured
+    pub cors: Option<CorsConfig>,
 
-    let query = req.uri().query().context(ExpectedQueryString)?;
+    /// Requests with bodies larger than this many bytes, after
+    /// decompression, are rejected
+    pub max_request_size: usize,
 
-    let write_info: WriteInfo = serde_urlencoded::from_str(query).context(InvalidQueryString {
-        query_string: String::from(query),
-    })?;
+    /// The token required by the admin endpoints. They are disabled if not
+    /// set.
+    pub admin_token: Option<String>,
 
-    let db_name = org_and_bucket_to_database(&write_info.org, &write_info.bucket)
-        .context(BucketMappingError)?;
+    /// Authenticates and authorizes the requests to the data endpoints,
+    /// other than `/ping`
+    pub authorizer: Arc<Authorizer>,
 
-    let body = parse_body(req).await?;
+    /// If set, writes and deletes taking longer than this are aborted
+    pub write_timeout: Arc<Timeout>,
 
-    let body = str::from_utf8(&body).context(ReadingBodyAsUtf8)?;
+    /// If set, queries taking longer than this are aborted
+    pub read_timeout: Arc<Timeout>,
 
-    let lines = parse_lines(body)
-        .collect::<Result<Vec<_>, influxdb_line_protocol::Error>>()
-        .context(ParsingLineProtocol)?;
+    /// The handle used by `/debug/log_filter` to change the log filter. The
+    /// endpoint is disabled if not set.
+    pub log_filter: Option<LogFilterHandle>,
 
-    debug!(
-        "Inserting {} lines into database {} (org {} bucket {})",
-        lines.len(),
-        db_name,
-        write_info.org,
-        write_info.bucket
-    );
+    /// The log SQL queries are sampled into
+    pub query_log: Arc<QueryLog>,
 
-    server
-        .write_lines(&db_name, &lines)
-        .await
-        .map_err(|e| Box::new(e) as _)
-        .context(WritingPoints {
-            org: write_info.org.clone(),
-            bucket_name: write_info.bucket.clone(),
-        })?;
+    /// The log administrative actions are recorded in
+    pub audit_log: Arc<AuditLog>,
 
-    Ok(Response::builder()
-        .status(StatusCode::NO_CONTENT)
-        .body(Body::empty())
-        .unwrap())
-}
+    /// The clients requests are accepted from
+    pub ip_filter: Arc<IpFilter>,
 
-#[derive(Deserialize, Debug, PartialEq)]
-/// Parsed URI Parameters of the request to the .../query endpoint
-struct QueryParams {
-    q: String,
-    #[serde(default)]
-    format: QueryOutputFormat,
+    /// If set, the responses to `/api/v2/read` are cached until data is
+    /// written or deleted in their time range
+    pub read_cache: Option<Arc<ReadCache>>,
+
+    /// The endpoints served
+    pub endpoints: Endpoints,
 }
 
-#[tracing::instrument(level = "debug")]
-async fn query<M: ConnectionManager + Send + Sync + Debug + 'static>(
-    req: Request<Body>,
-) -> Result<Response<Body>, ApplicationError> {
-    let server = Arc::clone(&req.data::<Arc<AppServer<M>>>().expect("server state"));
+/// Which of the HTTP endpoints a listener serves
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endpoints {
+    All,
+    /// Writes, queries and the management of buckets, orgs and databases
+    Data,
+    /// Health and readiness checks, `/debug` and the admin endpoints
+    Admin,
+}
 
-    let uri_query = req.uri().query().context(ExpectedQueryString {})?;
+impl Endpoints {
+    fn data(self) -> bool {
+        matches!(self, Self::All | Self::Data)
+    }
 
-    let QueryParams { q, format } =
-        serde_urlencoded::from_str(uri_query).context(InvalidQueryString {
-            query_string: uri_query,
-        })?;
+    fn admin(self) -> bool {
+        matches!(self, Self::All | Self::Admin)
+    }
+}
 
-    let db_name_str = req
-        .param("name")
-        .expect("db name must have been set by routerify")
-        .clone();
+impl Default for HttpOptions {
+    fn default() -> Self {
+        Self {
+            compression_min_size: compression::DEFAULT_MIN_SIZE,
+            cors: None,
+            max_request_size: DEFAULT_MAX_REQUEST_SIZE,
+            admin_token: None,
+            authorizer: Default::default(),
+            write_timeout: Default::default(),
+            read_timeout: Default::default(),
+            log_filter: None,
+            query_log: Default::default(),
+            audit_log: Default::default(),
+            ip_filter: Default::default(),
+            read_cache: None,
+            endpoints: Endpoints::All,
+        }
+    }
+}
 
-    let db_name = DatabaseName::new(&db_name_str).context(DatabaseNameError)?;
-    debug!(uri = ?req.uri(), %q, ?format, %db_name, "running SQL query");
+/// A request timeout that can be changed while the server is running
+#[derive(Debug, Default)]
+pub struct Timeout {
+    /// The timeout in milliseconds, 0 if disabled
+    millis: AtomicU64,
+}
 
-    let db = server
-        .db(&db_name)
-        .await
-        .context(DatabaseNotFound { name: &db_name_str })?;
+impl Timeout {
+    pub fn new(timeout: Option<Duration>) -> Self {
+        let new = Self::default();
+        new.set(timeout);
+        new
+    }
 
-    let planner = SQLQueryPlanner::default();
-    let executor = server.executor();
+    /// Returns the timeout, `None` if disabled
+    pub fn get(&self) -> Option<Duration> {
+        match self.millis.load(Ordering::Relaxed) {
+            0 => None,
+            millis => Some(Duration::from_millis(millis)),
+        }
+    }
 
-    let physical_plan = planner
-        .query(db.as_ref(), &q, executor.as_ref())
-        .await
-        .context(PlanningSQLQuery { query: &q })?;
+    /// Replaces the timeout, applying to requests received from now on
+    pub fn set(&self, timeout: Option<Duration>) {
+        let millis = timeout.map_or(0, |timeout| (timeout.as_millis() as u64).max(1));
+        self.millis.store(millis, Ordering::Relaxed);
+    }
+}
 
-    // TODO: stream read results out rather than rendering the
-    // whole thing in mem
-    let batches = collect(physical_plan)
-        .await
-        .map_err(|e| Box::new(e) as _)
-        .context(Query { db_name })?;
+/// The time by which a request must be handled, stored in the request
+/// extensions by `with_timeout` so that `parse_body` can tell when the
+/// client is too slow sending the body
+#[derive(Debug, Clone, Copy)]
+struct Deadline(Instant);
+
+/// Wraps `handler` so that requests it takes longer than `timeout` to
+/// respond to are aborted, dropping the handler's future and the resources
+/// it holds, and answered with `504 Gateway Timeout`. The timeout is read
+/// when each request is received, so changes to it apply to new requests.
+fn with_timeout<H, R>(
+    timeout: Arc<Timeout>,
+    handler: H,
+) -> impl Fn(Request<Body>) -> BoxFuture<'static, Result<Response<Body>, ApplicationError>>
+       + Send
+       + Sync
+       + 'static
+where
+    H: Fn(Request<Body>) -> R + Send + Sync + 'static,
+    R: Future<Output = Result<Response<Body>, ApplicationError>> + Send + 'static,
+{
+    move |req| apply_timeout(timeout.get(), req, &handler)
+}
 
-    let results = format
-        .format(&batches)
-        .context(FormattingResult { q, format })?;
+/// Like `with_timeout`, except that queries can ask for a shorter timeout
+/// than the server's with the `timeout` query parameter, e.g. `timeout=10s`,
+/// to bound the time and resources spent on an exploratory query
+fn with_query_timeout<H, R>(
+    timeout: Arc<Timeout>,
+    handler: H,
+) -> impl Fn(Request<Body>) -> BoxFuture<'static, Result<Response<Body>, ApplicationError>>
+       + Send
+       + Sync
+       + 'static
+where
+    H: Fn(Request<Body>) -> R + Send + Sync + 'static,
+    R: Future<Output = Result<Response<Body>, ApplicationError>> + Send + 'static,
+{
+    move |req| {
+        let requested = match requested_timeout(&req) {
+            Ok(requested) => requested,
+            Err(e) => return futures::future::err(e).boxed(),
+        };
+        let timeout = match (timeout.get(), requested) {
+            (Some(timeout), Some(requested)) => Some(timeout.min(requested)),
+            (timeout, requested) => timeout.or(requested),
+        };
+        apply_timeout(timeout, req, &handler)
+    }
+}
 
-    let body = Body::from(results.into_bytes());
+/// Wraps `handler` so that requests without a valid API token are rejected
+/// with `401 Unauthorized` before being handled. What the token allows is
+/// stored in the request extensions, to be checked by the handler with
+/// `access`.
+fn authenticated<H, R>(
+    handler: H,
+) -> impl Fn(Request<Body>) -> BoxFuture<'static, Result<Response<Body>, ApplicationError>>
+       + Send
+       + Sync
+       + 'static
+where
+    H: Fn(Request<Body>) -> R + Send + Sync + 'static,
+    R: Future<Output = Result<Response<Body>, ApplicationError>> + Send + 'static,
+{
+    move |mut req| {
+        let authorizer = req.data::<Arc<Authorizer>>().expect("authorizer");
+        match authorizer.authenticate_headers(req.headers()) {
+            Ok(access) => {
+                req.extensions_mut().insert(access);
+                handler(req).boxed()
+            }
+            Err(e) => {
+                futures::future::err(ApplicationError::ApiAuthorization { source: e }).boxed()
+            }
+        }
+    }
+}
 
-    let response = Response::builder()
-        .header(CONTENT_TYPE, format.content_type())
-        .body(body)
-        .context(CreatingResponse)?;
+/// Returns what `req`, authenticated by `authenticated`, may do
+fn access(req: &Request<Body>) -> Access {
+    req.extensions()
+        .get::<Access>()
+        .cloned()
+        .expect("request must have been authenticated")
+}
 
-    Ok(response)
+/// What an audited request created, recorded in the audit log instead of
+/// the request path, set on the response by the handler
+#[derive(Debug, Clone)]
+struct AuditTarget(String);
+
+fn with_audit_target(mut res: Response<Body>, target: String) -> Response<Body> {
+    res.extensions_mut().insert(AuditTarget(target));
+    res
 }
 
-#[tracing::instrument(level = "debug")]
-async fn list_databases<M>(req: Request<Body>) -> Result<Response<Body>, ApplicationError>
+/// Wraps `handler` to record the requests it handles in the audit log as
+/// `action`, after it completes
+fn audited<H, R>(
+    action: &'static str,
+    handler: H,
+) -> impl Fn(Request<Body>) -> BoxFuture<'static, Result<Response<Body>, ApplicationError>>
+       + Send
+       + Sync
+       + 'static
 where
-    M: ConnectionManager + Send + Sync + Debug + 'static,
+    H: Fn(Request<Body>) -> R + Send + Sync + 'static,
+    R: Future<Output = Result<Response<Body>, ApplicationError>> + Send + 'static,
 {
-    let server = Arc::clone(&req.data::<Arc<AppServer<M>>>().expect("server state"));
+    move |req| {
+        let audit_log = Arc::clone(req.data::<Arc<AuditLog>>().expect("audit log"));
+        // the admin endpoints are authorized by the handlers, with the admin
+        // token rather than an API token
+        let access = req.extensions().get::<Access>().cloned();
+        let remote_addr = req.remote_addr();
+        let request_id = req
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .map(ToString::to_string);
+        let path = req
+            .uri()
+            .path_and_query()
+            .map_or_else(|| req.uri().path().to_string(), ToString::to_string);
+
+        let response = handler(req);
+        async move {
+            let result = response.await;
+            let actor = match (&access, &result) {
+                (Some(access), _) => access.actor(),
+                (None, Err(ApplicationError::AdminAuthorization { .. })) => "unauthenticated",
+                (None, _) => "admin",
+            };
+            let (target, error) = match &result {
+                Ok(res) => match res.extensions().get::<AuditTarget>() {
+                    Some(AuditTarget(target)) => (target.clone(), None),
+                    None => (path, None),
+                },
+                Err(e) => (path, Some(e.to_string())),
+            };
+            audit_log.record(&AuditEvent {
+                time: chrono::Utc::now(),
+                actor: actor.to_string(),
+                remote_addr: Some(remote_addr),
+                request_id,
+                action,
+                target,
+                error,
+            });
+            result
+        }
+        .boxed()
+    }
+}
 
-    let names = server.db_names_sorted().await;
-    let json = serde_json::to_string(&ListDatabasesResponse { names })
-        .context(InternalSerializationError)?;
-    Ok(Response::new(Body::from(json)))
+/// Handles `req` with `handler`, failing with `RequestTimedOut` if it takes
+/// longer than `timeout`
+fn apply_timeout<H, R>(
+    timeout: Option<Duration>,
+    mut req: Request<Body>,
+    handler: &H,
+) -> BoxFuture<'static, Result<Response<Body>, ApplicationError>>
+where
+    H: Fn(Request<Body>) -> R,
+    R: Future<Output = Result<Response<Body>, ApplicationError>> + Send + 'static,
+{
+    match timeout {
+        None => handler(req).boxed(),
+        Some(timeout) => {
+            let deadline = Instant::now() + timeout;
+            req.extensions_mut().insert(Deadline(deadline));
+            let response = handler(req);
+            async move {
+                tokio::time::timeout_at(deadline, response)
+                    .await
+                    .unwrap_or_else(|_| RequestTimedOut { timeout }.fail())
+            }
+            .boxed()
+        }
+    }
 }
 
-#[tracing::instrument(level = "debug")]
-async fn create_database<M: ConnectionManager + Send + Sync + Debug + 'static>(
-    req: Request<Body>,
-) -> Result<Response<Body>, ApplicationError> {
-    let server = Arc::clone(&req.data::<Arc<AppServer<M>>>().expect("server state"));
+#[derive(Deserialize, Debug, Default)]
+/// The `timeout` query parameter of queries
+struct TimeoutParams {
+    timeout: Option<String>,
+}
 
-    // with routerify, we shouldn't have gotten here without this being set
-    let db_name = req
-        .param("name")
-        .expect("db name must have been set")
-        .clone();
-    let body = parse_body(req).await?;
+/// Returns the timeout requested by the `timeout` query parameter of `req`,
+/// if any
+fn requested_timeout(req: &Request<Body>) -> Result<Option<Duration>, ApplicationError> {
+    // other errors in the query string are reported by the handler
+    let params: TimeoutParams =
+        serde_urlencoded::from_str(req.uri().query().unwrap_or_default()).unwrap_or_default();
+    params
+        .timeout
+        .map(|value| {
+            read::parse_duration(&value)
+                .filter(|&nanoseconds| nanoseconds > 0)
+                .map(|nanoseconds| Duration::from_nanos(nanoseconds as u64))
+                .context(InvalidTimeout { value })
+        })
+        .transpose()
+}
 
-    let rules: DatabaseRules = serde_json::from_slice(body.as_ref()).context(InvalidRequestBody)?;
+fn router<M>(
+    server: Arc<AppServer<M>>,
+    serving_readiness: ServingReadiness,
+    options: HttpOptions,
+) -> Router<Body, ApplicationError>
+where
+    M: ConnectionManager + Send + Sync + Debug + 'static,
+{
+    let compression_min_size = options.compression_min_size;
+    let write_timeout = options.write_timeout;
+    let read_timeout = options.read_timeout;
+    let ip_filter = options.ip_filter;
+    let quorum_server = Arc::clone(&server);
 
-    server
-        .create_database(db_name, rules)
-        .await
-        .context(ErrorCreatingDatabase)?;
+    // Create a router and specify the the handlers.
+    let mut builder = Router::builder()
+        .data(server)
+        .data(serving_readiness)
+        .data(MaxRequestSize(options.max_request_size))
+        .data(AdminToken(options.admin_token))
+        .data(options.authorizer)
+        .data(options.log_filter)
+        .data(options.query_log)
+        .data(options.audit_log)
+        .data(options.read_cache)
+        .data(options.endpoints)
+        // rejected before anything else is done with the request
+        .middleware(Middleware::pre(move |req| {
+            let ip = req.remote_addr().ip();
+            let allowed = ip_filter.allows(ip);
+            async move {
+                if allowed {
+                    Ok(req)
+                } else {
+                    IpNotAllowed { ip }.fail()
+                }
+            }
+        }))
+        .middleware(Middleware::pre(|req| async move {
+            debug!(
+                method = %req.method(),
+                uri = %loggable_uri(req.uri()),
+                headers = ?req.headers(),
+                "Processing request"
+            );
+            Ok(req)
+        }))
+        .middleware(Middleware::post(|mut res| async move {
+            add_build_headers(&mut res);
+            Ok(res)
+        }))
+        // responses of a server that lost the quorum may miss the most
+        // recent data of the cluster
+        .middleware(Middleware::post(move |mut res| {
+            let lost = quorum_server
+                .quorum_status()
+                .map_or(false, |status| status.lost());
+            if lost {
+                res.headers_mut()
+                    .insert(WARNING, HeaderValue::from_static(STALE_RESPONSE_WARNING));
+            }
+            async move { Ok(res) }
+        }))
+        .middleware(Middleware::post_with_info(
+            move |res, req_info| async move {
+                compression::compress_response(res, &req_info, compression_min_size)
+                    .await
+                    .context(CompressingResponse)
+            },
+        ));
 
-    Ok(Response::new(Body::empty()))
-}
+    if let Some(cors) = options.cors.map(Arc::new) {
+        let preflight_cors = Arc::clone(&cors);
+        builder = builder
+            .middleware(Middleware::post_with_info(move |mut res, req_info| {
+                cors.add_response_headers(req_info.headers(), res.headers_mut());
+                async move { Ok(res) }
+            }))
+            .options("/*", move |req| {
+                let res = preflight_cors.preflight_response(&req);
+                async move { Ok(res) }
+            });
+    }
 
-#[tracing::instrument(level = "debug")]
-async fn get_database<M: ConnectionManager + Send + Sync + Debug + 'static>(
-    req: Request<Body>,
-) -> Result<Response<Body>, ApplicationError> {
-    let server = Arc::clone(&req.data::<Arc<AppServer<M>>>().expect("server state"));
+    builder = builder.get("/api/openapi.json", get_openapi);
+    if options.endpoints.data() {
+        builder = data_routes::<M>(builder, write_timeout, read_timeout);
+    }
+    if options.endpoints.admin() {
+        builder = admin_routes::<M>(builder);
+    }
 
-    // with routerify, we shouldn't have gotten here without this being set
-    let db_name_str = req
-        .param("name")
-        .expect("db name must have been set")
-        .clone();
-    let db_name = DatabaseName::new(&db_name_str).context(DatabaseNameError)?;
-    let db = server
-        .db_rules(&db_name)
-        .await
-        .context(DatabaseNotFound { name: &db_name_str })?;
+    builder
+        // Unknown routes get the same error body as any other error
+        .any(route_not_found)
+        // Specify the error handler to handle any errors caused by
+        // a route or any middleware.
+        .err_handler_with_info(error_handler)
+        .build()
+        .unwrap()
+}
 
-    let data = serde_json::to_string(&db).context(JsonGenerationError)?;
-    let response = Response::builder()
-        .header("Content-Type", "application/json")
-        .status(StatusCode::OK)
-        .body(Body::from(data))
-        .expect("builder should be successful");
+/// Adds the routes of the data API to `builder`
+fn data_routes<M>(
+    builder: RouterBuilder<Body, ApplicationError>,
+    write_timeout: Arc<Timeout>,
+    read_timeout: Arc<Timeout>,
+) -> RouterBuilder<Body, ApplicationError>
+where
+    M: ConnectionManager + Send + Sync + Debug + 'static,
+{
+    // this endpoint is for API backward compatibility with InfluxDB 2.x
+    builder
+        .post(
+            "/api/v2/write",
+            authenticated(with_timeout(Arc::clone(&write_timeout), write::<M>)),
+        )
+        .post(
+            "/api/v2/delete",
+            authenticated(audited(
+                "delete_data",
+                with_timeout(write_timeout, delete::<M>),
+            )),
+        )
+        .get(
+            "/api/v2/read",
+            authenticated(with_query_timeout(Arc::clone(&read_timeout), read::<M>)),
+        )
+        .get("/api/v2/buckets", authenticated(list_buckets::<M>))
+        .post(
+            "/api/v2/buckets",
+            authenticated(audited("create_bucket", create_bucket::<M>)),
+        )
+        .patch(
+            "/api/v2/buckets/:bucket",
+            authenticated(audited("update_bucket", update_bucket::<M>)),
+        )
+        .delete(
+            "/api/v2/buckets/:bucket",
+            authenticated(audited("delete_bucket", delete_bucket::<M>)),
+        )
+        .get("/api/v2/orgs", authenticated(list_orgs::<M>))
+        .post(
+            "/api/v2/orgs",
+            authenticated(audited("create_org", create_org::<M>)),
+        )
+        .get("/api/v2/orgs/:id", authenticated(get_org::<M>))
+        .get("/api/v2/orgs/:id/usage", authenticated(get_org_usage::<M>))
+        .patch(
+            "/api/v2/orgs/:id",
+            authenticated(audited("rename_org", update_org::<M>)),
+        )
+        .put(
+            "/api/v2/orgs/:id/limits",
+            authenticated(audited("set_org_rate_limits", set_org_limits::<M>)),
+        )
+        .delete(
+            "/api/v2/orgs/:id",
+            authenticated(audited("delete_org", delete_org::<M>)),
+        )
+        // left open so that load balancers can check the server
+        .get_or_head("/ping", ping)
+        .get("/iox/api/v1/databases", authenticated(list_databases::<M>))
+        .put(
+            "/iox/api/v1/databases/:name",
+            authenticated(audited("create_database", create_database::<M>)),
+        )
+        .get(
+            "/iox/api/v1/databases/:name",
+            authenticated(get_database::<M>),
+        )
+        .get(
+            "/iox/api/v1/databases/:name/query",
+            authenticated(with_query_timeout(read_timeout, query::<M>)),
+        )
+        .get(
+            "/iox/api/v1/databases/:name/wal/meta",
+            authenticated(get_wal_meta::<M>),
+        )
+        .put(
+            "/iox/api/v1/id",
+            authenticated(audited("set_writer_id", set_writer::<M>)),
+        )
+        .get("/iox/api/v1/id", authenticated(get_writer::<M>))
+        .get("/api/v1/partitions", authenticated(list_partitions::<M>))
+        .get("/api/v2/tokens", authenticated(list_tokens::<M>))
+        .post(
+            "/api/v2/tokens",
+            authenticated(audited("create_token", create_token::<M>)),
+        )
+        .delete(
+            "/api/v2/tokens/:id",
+            authenticated(audited("delete_token", delete_token::<M>)),
+        )
+}
 
-    Ok(response)
+/// Adds the operational routes to `builder`
+fn admin_routes<M>(
+    builder: RouterBuilder<Body, ApplicationError>,
+) -> RouterBuilder<Body, ApplicationError>
+where
+    M: ConnectionManager + Send + Sync + Debug + 'static,
+{
+    builder
+        .get("/health", health)
+        .get("/ready", ready::<M>)
+        .get("/debug/build", build)
+        .get("/metrics", metrics::<M>)
+        .get("/debug/log_filter", get_log_filter)
+        .put(
+            "/debug/log_filter",
+            audited("set_log_filter", set_log_filter),
+        )
+        .get("/api/v2/partitions", list_partition_statuses::<M>)
+        .post("/api/v1/snapshot", snapshot_partition::<M>)
+        .post(
+            "/api/v2/partitions/:partition/persist",
+            persist_partition::<M>,
+        )
+        .get("/api/v2/compactions", list_compactions::<M>)
+        .post("/api/v2/compactions", start_compaction::<M>)
+        .get("/api/v2/compactions/:id", get_compaction::<M>)
+        .get("/cluster/members", list_cluster_members::<M>)
+        .get("/api/v2/drain", get_drain::<M>)
+        .post("/api/v2/drain", start_drain::<M>)
+        .get("/cluster/shards", list_shards::<M>)
+        .get("/cluster/shards/:id", get_shard::<M>)
+        .post("/cluster/shards/:id/move", start_shard_move::<M>)
 }
 
 #[tracing::instrument(level = "debug")]
-async fn get_wal_meta<M: ConnectionManager + Send + Sync + Debug + 'static>(
-    req: Request<Body>,
-) -> Result<Response<Body>, ApplicationError> {
-    let server = Arc::clone(&req.data::<Arc<AppServer<M>>>().expect("server state"));
-
-    let db_name_str = req
-        .param("name")
-        .expect("db name must have been set")
-        .clone();
-
-    let query: WalMetadataQuery = req
-        .uri()
-        .query()
-        .map(|query| {
-            serde_urlencoded::from_str(query).context(InvalidQueryString {
-                query_string: query,
-            })
-        })
-        .transpose()?
-        .unwrap_or_default();
+async fn get_openapi(req: Request<Body>) -> Result<Response<Body>, ApplicationError> {
+    let endpoints = *req.data::<Endpoints>().expect("endpoints");
+    let json = serde_json::to_string(&openapi::document(endpoints)).context(JsonGenerationError)?;
+
+    Response::builder()
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .context(CreatingResponse)
+}
 
-    let db_name = DatabaseName::new(&db_name_str).context(DatabaseNameError)?;
+async fn route_not_found(req: Request<Body>) -> Result<Response<Body>, ApplicationError> {
+    RouteNotFound {
+        method: req.method().clone(),
+        path: req.uri().path(),
+    }
+    .fail()
+}
 
-    let db = server
-        .db(&db_name)
-        .await
-        .context(DatabaseNotFound { name: &db_name_str })?;
+/// The query parameters holding data values rather than names, which are
+/// redacted from logged URIs if configured
+const VALUE_PARAMS: &[&str] = &["q", "predicate"];
 
-    let wal = db
-        .wal_buffer
-        .as_ref()
-        .context(WALNotFound { name: &db_name_str })?;
-    let wal_buffer = wal.lock();
+/// Returns the URL query string `query` as shown in logs and error messages,
+/// with the values of its `VALUE_PARAMS` redacted if configured
+fn loggable_query(query: &str) -> String {
+    let redaction = Redaction::current();
+    if !redaction.is_enabled() {
+        return query.to_string();
+    }
 
-    let segments = wal_buffer
-        .segments(query.offset)
-        .take(query.limit.unwrap_or(10))
-        .take_while(|x| {
-            query
-                .newer_than
-                .map(|newer_than| x.created_at > newer_than)
-                .unwrap_or(true)
+    query
+        .split('&')
+        .map(|param| match param.find('=') {
+            Some(i) if VALUE_PARAMS.contains(&&param[..i]) => {
+                format!("{}={}", &param[..i], redaction.value(&param[i + 1..]))
+            }
+            _ => param.to_string(),
         })
-        .collect();
-
-    let response = Response::builder()
-        .status(StatusCode::OK)
-        .body(Body::from(
-            serde_json::to_string(&WalMetadataResponse { segments })
-                .expect("json encoding should not fail"),
-        ))
-        .expect("builder should be successful");
-
-    Ok(response)
+        .collect::<Vec<_>>()
+        .join("&")
 }
 
-#[tracing::instrument(level = "debug")]
-async fn set_writer<M: ConnectionManager + Send + Sync + Debug + 'static>(
-    req: Request<Body>,
-) -> Result<Response<Body>, ApplicationError> {
-    let server = Arc::clone(&req.data::<Arc<AppServer<M>>>().expect("server state"));
+/// Returns `uri` as shown in logs, see `loggable_query`
+fn loggable_uri(uri: &Uri) -> String {
+    match uri.query() {
+        Some(query) => format!("{}?{}", uri.path(), loggable_query(query)),
+        None => uri.to_string(),
+    }
+}
 
-    // Read the request body
-    let body = parse_body(req).await?;
+// The API-global error handler, handles ApplicationErrors originating from
+// individual routes and middlewares, along with errors from the router itself
+async fn error_handler(err: RouterError<ApplicationError>, req: RequestInfo) -> Response<Body> {
+    match err {
+        RouterError::HandleRequest(e, _)
+        | RouterError::HandlePreMiddlewareRequest(e)
+        | RouterError::HandlePostMiddlewareWithInfoRequest(e)
+        | RouterError::HandlePostMiddlewareWithoutInfoRequest(e) => {
+            error!(error = ?e, error_message = ?e.to_string(), "Error while handling request");
+            e.response()
+        }
+        _ => {
+            let method = req.method().clone();
+            let uri = req.uri().clone();
+            error!(error = ?err, error_message = ?err.to_string(), method = ?method, uri = %loggable_uri(&uri), "Error while handling request");
 
-    // Parse the JSON body into a structure
-    #[derive(Serialize, Deserialize)]
-    struct WriterIdBody {
-        id: u32,
+            ErrorResponse {
+                code: "internal error",
+                message: err.to_string(),
+                line: None,
+                error_code: influxdb_iox_client::errors::ApiErrorCode::UNKNOWN.into(),
+            }
+            .response(StatusCode::INTERNAL_SERVER_ERROR)
+        }
     }
-    let req: WriterIdBody = serde_json::from_slice(body.as_ref()).context(InvalidRequestBody)?;
-
-    // Set the writer ID
-    server.set_id(req.id);
+}
 
-    // Build a HTTP 200 response
-    let response = Response::builder()
-        .status(StatusCode::OK)
-        .body(Body::from(
-            serde_json::to_string(&req).expect("json encoding should not fail"),
-        ))
-        .expect("builder should be successful");
+#[derive(Debug, Deserialize)]
+/// Query string of the requests to the /write and /delete endpoints
+struct WriteInfo {
+    org: String,
+    bucket: String,
+}
 
-    Ok(response)
+/// Parse the request's body into raw bytes, applying size limits and
+/// content encoding as needed.
+async fn parse_body(req: hyper::Request<Body>) -> Result<Bytes, ApplicationError> {
+    let mut body = Vec::new();
+    read_body(req, &mut body).await?;
+    Ok(body.into())
 }
 
-#[tracing::instrument(level = "debug")]
-async fn get_writer<M: ConnectionManager + Send + Sync + Debug + 'static>(
-    req: Request<Body>,
-) -> Result<Response<Body>, ApplicationError> {
-    let id = {
-        let server = Arc::clone(&req.data::<Arc<AppServer<M>>>().expect("server state"));
-        server.require_id()
-    };
+/// Reads the request's body into `body` as `parse_body` does, so that the
+/// buffer can be taken from `BODY_BUFFERS`
+async fn read_body(req: hyper::Request<Body>, body: &mut Vec<u8>) -> Result<(), ApplicationError> {
+    let max_size = req
+        .data::<MaxRequestSize>()
+        .map(|max_size| max_size.0)
+        .unwrap_or(DEFAULT_MAX_REQUEST_SIZE);
 
-    // Parse the JSON body into a structure
-    #[derive(Serialize)]
-    struct WriterIdBody {
-        id: u32,
-    }
-
-    let body = WriterIdBody {
-        id: id.unwrap_or(0),
+    // clippy says the const needs to be assigned to a local variable:
+    // error: a `const` item with interior mutability should not be borrowed
+    let header_name = CONTENT_ENCODING;
+    let ungzip = match req.headers().get(&header_name) {
+        None => false,
+        Some(content_encoding) => {
+            let content_encoding = content_encoding.to_str().context(ReadingHeaderAsUtf8 {
+                header_name: header_name.as_str(),
+            })?;
+            match content_encoding {
+                "gzip" => true,
+                _ => InvalidContentEncoding { content_encoding }.fail()?,
+            }
+        }
     };
 
-    // Build a HTTP 200 response
-    let response = Response::builder()
-        .status(StatusCode::OK)
-        .body(Body::from(
-            serde_json::to_string(&body).expect("json encoding should not fail"),
-        ))
-        .expect("builder should be successful");
+    let deadline = req.extensions().get::<Deadline>().copied();
+    let payload = req.into_body();
 
-    Ok(response)
+    // apply any content encoding needed
+    if ungzip {
+        use std::io::Read;
+        let mut compressed = BODY_BUFFERS.get();
+        read_payload(payload, &mut compressed, max_size, deadline).await?;
+        let decoder = flate2::read::GzDecoder::new(&compressed[..]);
+
+        // Read at most max_size bytes to prevent a decompression bomb based
+        // DoS.
+        let mut decoder = decoder.take(max_size as u64);
+        decoder.read_to_end(body).context(ReadingBodyAsGzip)?;
+        Ok(())
+    } else {
+        read_payload(payload, body, max_size, deadline).await
+    }
 }
 
-// Route to test that the server is alive
-#[tracing::instrument(level = "debug")]
-async fn ping(_: Request<Body>) -> Result<Response<Body>, ApplicationError> {
-    let response_body = "PONG";
-    Ok(Response::new(Body::from(response_body.to_string())))
+/// Reads the chunks of `payload` into `body`, failing if it grows larger
+/// than `max_size` bytes or isn't read by the `deadline`
+async fn read_payload(
+    mut payload: Body,
+    body: &mut Vec<u8>,
+    max_size: usize,
+    deadline: Option<Deadline>,
+) -> Result<(), ApplicationError> {
+    let read_body = async {
+        while let Some(chunk) = payload.next().await {
+            let chunk = chunk.expect("Should have been able to read the next chunk");
+            // limit max size of in-memory payload
+            if (body.len() + chunk.len()) > max_size {
+                return Err(ApplicationError::RequestSizeExceeded {
+                    max_body_size: max_size,
+                });
+            }
+            body.extend_from_slice(&chunk);
+        }
+        Ok(())
+    };
+    match deadline {
+        Some(Deadline(deadline)) => tokio::time::timeout_at(deadline, read_body)
+            .await
+            .map_err(|_| ApplicationError::RequestBodyTimedOut {})?,
+        None => read_body.await,
+    }
 }
 
 #[tracing::instrument(level = "debug")]
-async fn health(_: Request<Body>) -> Result<Response<Body>, ApplicationError> {
-    let response_body = "OK";
-    Ok(Response::new(Body::from(response_body.to_string())))
+async fn write<M>(req: Request<Body>) -> Result<Response<Body>, ApplicationError>
+where
+    M: ConnectionManager + Send + Sync + Debug + 'static,
+{
+    let server = Arc::clone(&req.data::<Arc<AppServer<M>>>().expect("server state"));
+
+    let query = req.uri().query().context(ExpectedQueryString)?;
+
+    let write_info: WriteInfo = serde_urlencoded::from_str(query).context(InvalidQueryString {
+        query_string: String::from(query),
+    })?;
+
+    request_logging::span(&req).record_org_and_bucket(&write_info.org, &write_info.bucket);
+    let access = access(&req);
+    access
+        .authorize(
+            Scope::Write,
+            Some(&write_info.org),
+            Some(&write_info.bucket),
+        )
+        .context(ApiAuthorization)?;
+
+    let db_name = org_and_bucket_to_database(&write_info.org, &write_info.bucket)
+        .context(BucketMappingError)?;
+
+    let read_cache = req
+        .data::<Option<Arc<ReadCache>>>()
+        .expect("read cache")
+        .clone();
+    let mut body = BODY_BUFFERS.get();
+    read_body(req, &mut body).await?;
+
+    let body = str::from_utf8(&body).context(ReadingBodyAsUtf8)?;
+
+    let lines = parse_lines_numbered(body)
+        .map(|(line, res)| res.context(ParsingLineProtocol { line }))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    server
+        .check_write_rate(access.token(), &write_info.org, lines.len())
+        .await
+        .context(RateLimited)?;
+
+    debug!(
+        "Inserting {} lines into database {} (org {} bucket {})",
+        lines.len(),
+        db_name,
+        write_info.org,
+        write_info.bucket
+    );
+
+    let write_start = chrono::Utc::now().timestamp_nanos();
+    let result = server.write_lines(&db_name, &lines).await;
+
+    // even a failed write may have written some of the lines. Lines without
+    // a timestamp are written at the time of the write.
+    if let Some(read_cache) = read_cache {
+        let write_end = chrono::Utc::now().timestamp_nanos();
+        let min = lines
+            .iter()
+            .map(|line| line.timestamp.unwrap_or(write_start));
+        let max = lines.iter().map(|line| line.timestamp.unwrap_or(write_end));
+        if let (Some(min), Some(max)) = (min.min(), max.max()) {
+            read_cache.invalidate(&db_name, (min, max));
+        }
+    }
+
+    match result {
+        Ok(()) => {}
+        Err(source @ server::Error::QuotaExceeded { .. }) => {
+            return Err(ApplicationError::QuotaExceeded { source })
+        }
+        Err(source @ server::Error::WriteQuorumNotReached { .. }) => {
+            return Err(ApplicationError::WriteQuorumNotReached { source })
+        }
+        Err(source @ server::Error::ReadReplicaWrite { .. }) => {
+            return Err(ApplicationError::ReadReplicaWrite { source })
+        }
+        Err(source @ server::Error::ShardWriteFailed { .. }) => {
+            return Err(ApplicationError::ShardWriteFailed { source })
+        }
+        Err(source @ server::Error::WriteBufferFailed { .. }) => {
+            return Err(ApplicationError::WriteBufferFailed { source })
+        }
+        Err(source @ server::Error::Draining) => return Err(ApplicationError::Draining { source }),
+        Err(source @ server::Error::QuorumLost { .. }) => {
+            return Err(ApplicationError::QuorumLost { source })
+        }
+        Err(source @ server::Error::MemoryLimitReached { .. }) => {
+            return Err(ApplicationError::MemoryLimitReached { source })
+        }
+        Err(e) => {
+            return Err(Box::new(e) as _).context(WritingPoints {
+                org: write_info.org.clone(),
+                bucket_name: write_info.bucket.clone(),
+            })
+        }
+    }
+
+    Ok(Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .body(Body::empty())
+        .unwrap())
 }
 
-#[derive(Deserialize, Debug)]
-/// Arguments in the query string of the request to /partitions
-struct DatabaseInfo {
-    org: String,
-    bucket: String,
+/// The number of buckets listed per page unless a limit is given
+const DEFAULT_BUCKETS_LIMIT: usize = 20;
+
+/// The largest number of buckets listed per page
+const MAX_BUCKETS_LIMIT: usize = 100;
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+/// Query string of the GET /buckets endpoint. Buckets are listed ordered by
+/// org and name, a page at a time.
+struct ListBucketsQuery {
+    org: Option<String>,
+    /// Only list the buckets whose names start with this
+    name_prefix: Option<String>,
+    /// The number of buckets per page
+    limit: Option<usize>,
+    /// The number of buckets to skip
+    offset: Option<usize>,
+    /// Only list the buckets whose names sort after this. Bucket names are
+    /// only unique within an org, so this requires `org`.
+    after: Option<String>,
 }
 
-#[tracing::instrument(level = "debug")]
-async fn list_partitions<M: ConnectionManager + Send + Sync + Debug + 'static>(
-    req: Request<Body>,
-) -> Result<Response<Body>, ApplicationError> {
-    let server = Arc::clone(&req.data::<Arc<AppServer<M>>>().expect("server state"));
-    let query = req.uri().query().context(ExpectedQueryString {})?;
+#[derive(Debug, Deserialize)]
+/// Query string of the PATCH and DELETE /buckets/:bucket endpoints
+struct BucketQuery {
+    org: String,
+}
 
-    let info: DatabaseInfo = serde_urlencoded::from_str(query).context(InvalidQueryString {
+/// Parses the `BucketQuery` and bucket name of a request to the
+/// /buckets/:bucket endpoints, returning them along with the name of the
+/// database backing the bucket
+fn bucket_params(
+    req: &Request<Body>,
+) -> Result<(String, String, DatabaseName<'static>), ApplicationError> {
+    let query = req.uri().query().context(ExpectedQueryString)?;
+    let BucketQuery { org } = serde_urlencoded::from_str(query).context(InvalidQueryString {
         query_string: query,
     })?;
 
-    let db_name =
-        org_and_bucket_to_database(&info.org, &info.bucket).context(BucketMappingError)?;
-
-    let db = server.db(&db_name).await.context(BucketNotFound {
-        org: &info.org,
-        bucket: &info.bucket,
-    })?;
+    // with routerify, we shouldn't have gotten here without this being set
+    let bucket = req
+        .param("bucket")
+        .expect("bucket name must have been set")
+        .clone();
 
-    let partition_keys =
-        db.partition_keys()
-            .map_err(|e| Box::new(e) as _)
-            .context(BucketByName {
-                org: &info.org,
-                bucket_name: &info.bucket,
-            })?;
+    request_logging::span(req).record_org_and_bucket(&org, &bucket);
 
-    let result = serde_json::to_string(&partition_keys).context(JsonGenerationError)?;
+    let db_name = org_and_bucket_to_database(&org, &bucket).context(BucketMappingError)?;
 
-    Ok(Response::new(Body::from(result)))
+    Ok((org, bucket, db_name))
 }
 
-#[derive(Deserialize, Debug)]
-/// Arguments in the query string of the request to /snapshot
-struct SnapshotInfo {
-    org: String,
-    bucket: String,
-    partition: String,
+fn bucket_json(bucket: &Bucket, status: StatusCode) -> Result<Response<Body>, ApplicationError> {
+    let json = serde_json::to_string(bucket).context(JsonGenerationError)?;
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .context(CreatingResponse)
 }
 
 #[tracing::instrument(level = "debug")]
-async fn snapshot_partition<M: ConnectionManager + Send + Sync + Debug + 'static>(
-    req: Request<Body>,
-) -> Result<Response<Body>, ApplicationError> {
-    use object_store::path::ObjectStorePath;
+async fn list_buckets<M>(req: Request<Body>) -> Result<Response<Body>, ApplicationError>
+where
+    M: ConnectionManager + Send + Sync + Debug + 'static,
+{
+    let server = Arc::clone(&req.data::<Arc<AppServer<M>>>().expect("server state"));
+
+    let query: ListBucketsQuery = req
+        .uri()
+        .query()
+        .map(|query| {
+            serde_urlencoded::from_str(query).context(InvalidQueryString {
+                query_string: query,
+            })
+        })
+        .transpose()?
+        .unwrap_or_default();
+
+    access(&req)
+        .authorize(Scope::Read, query.org.as_deref(), None)
+        .context(ApiAuthorization)?;
 
+    let limit = query.limit.unwrap_or(DEFAULT_BUCKETS_LIMIT);
+    ensure!(
+        (1..=MAX_BUCKETS_LIMIT).contains(&limit),
+        InvalidPageLimit {
+            limit,
+            max: MAX_BUCKETS_LIMIT
+        }
+    );
+    ensure!(
+        query.after.is_none() || query.org.is_some(),
+        BucketCursorWithoutOrg
+    );
+    let offset = query.offset.unwrap_or_default();
+
+    let mut matching = vec![];
+    for db_name in server.db_names_sorted().await {
+        let (bucket_org, name) = match database_to_org_and_bucket(&db_name) {
+            Some(org_and_bucket) => org_and_bucket,
+            None => continue,
+        };
+        if matches!(&query.org, Some(org) if *org != bucket_org)
+            || matches!(&query.name_prefix, Some(prefix) if !name.starts_with(prefix.as_str()))
+            || matches!(&query.after, Some(after) if name <= *after)
+        {
+            continue;
+        }
+        matching.push((bucket_org, name, db_name));
+    }
+    // database names are escaped, so may not sort like the org and bucket names
+    matching.sort();
+
+    let more = matching.len() > offset.saturating_add(limit);
+    let page: Vec<_> = matching.into_iter().skip(offset).take(limit).collect();
+
+    let next = match page.last() {
+        Some((_, last_name, _)) if more => {
+            let next = if query.after.is_some() {
+                ListBucketsQuery {
+                    after: Some(last_name.clone()),
+                    offset: None,
+                    limit: Some(limit),
+                    ..query
+                }
+            } else {
+                ListBucketsQuery {
+                    offset: Some(offset + limit),
+                    limit: Some(limit),
+                    ..query
+                }
+            };
+            let next = serde_urlencoded::to_string(&next)
+                .expect("serializing a bucket listing query string");
+            Some(format!("/api/v2/buckets?{}", next))
+        }
+        _ => None,
+    };
+
+    let mut buckets = vec![];
+    for (org, name, db_name) in page {
+        let db_name = DatabaseName::new(&db_name).context(DatabaseNameError)?;
+        // the database may have been deleted since listing the names
+        if let Some(rules) = server.db_rules(&db_name).await {
+            buckets.push(Bucket {
+                org,
+                name,
+                retention_rules: RetentionRule::from_retention_period(
+                    rules.retention_period_seconds,
+                ),
+            });
+        }
+    }
+
+    let json = serde_json::to_string(&ListBucketsResponse {
+        buckets,
+        links: PageLinks { next },
+    })
+    .context(JsonGenerationError)?;
+    Response::builder()
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .context(CreatingResponse)
+}
+
+#[tracing::instrument(level = "debug")]
+async fn create_bucket<M>(req: Request<Body>) -> Result<Response<Body>, ApplicationError>
+where
+    M: ConnectionManager + Send + Sync + Debug + 'static,
+{
     let server = Arc::clone(&req.data::<Arc<AppServer<M>>>().expect("server state"));
-    let query = req.uri().query().context(ExpectedQueryString {})?;
+    let access = access(&req);
+    let span = request_logging::span(&req);
 
-    let snapshot: SnapshotInfo = serde_urlencoded::from_str(query).context(InvalidQueryString {
-        query_string: query,
-    })?;
+    let body = parse_body(req).await?;
+    let bucket: Bucket = serde_json::from_slice(body.as_ref()).context(InvalidRequestBody)?;
+
+    span.record_org_and_bucket(&bucket.org, &bucket.name);
+    access
+        .authorize(Scope::Admin, Some(&bucket.org), Some(&bucket.name))
+        .context(ApiAuthorization)?;
 
     let db_name =
-        org_and_bucket_to_database(&snapshot.org, &snapshot.bucket).context(BucketMappingError)?;
+        org_and_bucket_to_database(&bucket.org, &bucket.name).context(BucketMappingError)?;
+    let retention_period_seconds = RetentionRule::retention_period(&bucket.retention_rules);
+    let rules = DatabaseRules {
+        retention_period_seconds,
+        ..DatabaseRules::new()
+    };
 
-    // TODO: refactor the rest of this out of the http route and into the server
-    // crate.
-    let db = server.db(&db_name).await.context(BucketNotFound {
-        org: &snapshot.org,
-        bucket: &snapshot.bucket,
-    })?;
+    server
+        .create_bucket(&bucket.org, db_name.to_string(), rules)
+        .await
+        .context(ErrorCreatingDatabase)?;
 
-    let store = Arc::clone(&server.store);
+    let bucket = Bucket {
+        retention_rules: RetentionRule::from_retention_period(retention_period_seconds),
+        ..bucket
+    };
+    let target = format!("/api/v2/buckets/{}?org={}", bucket.name, bucket.org);
+    bucket_json(&bucket, StatusCode::CREATED).map(|res| with_audit_target(res, target))
+}
 
-    let mut metadata_path = store.new_path();
-    metadata_path.push_dir(&db_name.to_string());
-    let mut data_path = metadata_path.clone();
-    metadata_path.push_dir("meta");
-    data_path.push_all_dirs(&["data", &snapshot.partition]);
+#[tracing::instrument(level = "debug")]
+async fn update_bucket<M>(req: Request<Body>) -> Result<Response<Body>, ApplicationError>
+where
+    M: ConnectionManager + Send + Sync + Debug + 'static,
+{
+    let server = Arc::clone(&req.data::<Arc<AppServer<M>>>().expect("server state"));
 
-    let partition_key = &snapshot.partition;
-    let chunk = db.rollover_partition(partition_key).await.unwrap();
-    let snapshot = server::snapshot::snapshot_chunk(
-        metadata_path,
-        data_path,
-        store,
-        partition_key,
-        chunk,
-        None,
-    )
-    .unwrap();
+    let (org, bucket, db_name) = bucket_params(&req)?;
+    access(&req)
+        .authorize(Scope::Admin, Some(&org), Some(&bucket))
+        .context(ApiAuthorization)?;
 
-    let ret = format!("{}", snapshot.id);
-    Ok(Response::new(Body::from(ret)))
-}
+    let body = parse_body(req).await?;
+    let update: PatchBucketRequest =
+        serde_json::from_slice(body.as_ref()).context(InvalidRequestBody)?;
 
-pub fn router_service<M: ConnectionManager + Send + Sync + Debug + 'static>(
-    server: Arc<AppServer<M>>,
-) -> RouterService<Body, ApplicationError> {
-    let router = router(server);
-    RouterService::new(router).unwrap()
+    if server.db(&db_name).await.is_none() {
+        return BucketNotFound { org, bucket }.fail();
+    }
+
+    let rules = server
+        .update_db_rules(&db_name, |rules| {
+            if let Some(retention_rules) = &update.retention_rules {
+                rules.retention_period_seconds = RetentionRule::retention_period(retention_rules);
+            }
+        })
+        .await
+        .context(UpdatingBucket {
+            org: &org,
+            bucket: &bucket,
+        })?;
+
+    let bucket = Bucket {
+        org,
+        name: bucket,
+        retention_rules: RetentionRule::from_retention_period(rules.retention_period_seconds),
+    };
+    bucket_json(&bucket, StatusCode::OK)
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+#[tracing::instrument(level = "debug")]
+async fn delete_bucket<M>(req: Request<Body>) -> Result<Response<Body>, ApplicationError>
+where
+    M: ConnectionManager + Send + Sync + Debug + 'static,
+{
+    let server = Arc::clone(&req.data::<Arc<AppServer<M>>>().expect("server state"));
 
-    use arrow_deps::{arrow::record_batch::RecordBatch, assert_table_eq};
-    use query::exec::Executor;
-    use reqwest::{Client, Response};
+    let (org, bucket, db_name) = bucket_params(&req)?;
+    access(&req)
+        .authorize(Scope::Admin, Some(&org), Some(&bucket))
+        .context(ApiAuthorization)?;
 
-    use hyper::Server;
+    if server.db(&db_name).await.is_none() {
+        return BucketNotFound { org, bucket }.fail();
+    }
 
-    use data_types::{
-        database_rules::{DatabaseRules, WalBufferConfig, WalBufferRollover},
-        wal::WriterSummary,
-        DatabaseName,
-    };
-    use object_store::{memory::InMemory, ObjectStore};
-    use serde::de::DeserializeOwned;
-    use server::{db::Db, ConnectionManagerImpl};
+    server
+        .delete_database(&db_name)
+        .await
+        .context(DeletingBucket { org, bucket })?;
 
-    type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
-    type Result<T, E = Error> = std::result::Result<T, E>;
+    // a bucket created with the same name starts empty
+    if let Some(read_cache) = req.data::<Option<Arc<ReadCache>>>().expect("read cache") {
+        read_cache.invalidate(&db_name, (i64::MIN, i64::MAX));
+    }
 
-    #[tokio::test]
-    async fn test_ping() -> Result<()> {
-        let test_storage = Arc::new(AppServer::new(
-            ConnectionManagerImpl {},
-            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
-        ));
-        let server_url = test_server(Arc::clone(&test_storage));
+    Ok(Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .body(Body::empty())
+        .unwrap())
+}
 
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+/// Body of the response to the GET /orgs endpoint
+struct ListOrgsResponse {
+    orgs: Vec<Org>,
+}
+
+#[derive(Debug, Deserialize)]
+/// Body of the requests to the POST /orgs and PATCH /orgs/:id endpoints
+struct OrgRequest {
+    name: String,
+}
+
+/// Checks that `access` allows administering the org with id `id`
+async fn authorize_org<M>(
+    server: &AppServer<M>,
+    access: &Access,
+    id: &str,
+) -> Result<(), ApplicationError>
+where
+    M: ConnectionManager + Send + Sync + Debug + 'static,
+{
+    let org = server.org(id).await.context(OrgNotFound { id })?;
+    access
+        .authorize(Scope::Admin, Some(&org.name), None)
+        .context(ApiAuthorization)
+}
+
+fn org_json(org: &Org, status: StatusCode) -> Result<Response<Body>, ApplicationError> {
+    let json = serde_json::to_string(org).context(JsonGenerationError)?;
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .context(CreatingResponse)
+}
+
+#[tracing::instrument(level = "debug")]
+async fn list_orgs<M>(req: Request<Body>) -> Result<Response<Body>, ApplicationError>
+where
+    M: ConnectionManager + Send + Sync + Debug + 'static,
+{
+    let server = Arc::clone(&req.data::<Arc<AppServer<M>>>().expect("server state"));
+
+    // tokens restricted to an org only see that org
+    let access = access(&req);
+    let mut orgs = server.orgs().await;
+    orgs.retain(|org| access.authorize(Scope::Read, Some(&org.name), None).is_ok());
+    let json = serde_json::to_string(&ListOrgsResponse { orgs }).context(JsonGenerationError)?;
+    Response::builder()
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .context(CreatingResponse)
+}
+
+#[tracing::instrument(level = "debug")]
+async fn create_org<M>(req: Request<Body>) -> Result<Response<Body>, ApplicationError>
+where
+    M: ConnectionManager + Send + Sync + Debug + 'static,
+{
+    let server = Arc::clone(&req.data::<Arc<AppServer<M>>>().expect("server state"));
+    access(&req)
+        .authorize(Scope::Admin, None, None)
+        .context(ApiAuthorization)?;
+
+    let body = parse_body(req).await?;
+    let OrgRequest { name } = serde_json::from_slice(body.as_ref()).context(InvalidRequestBody)?;
+
+    let org = server.create_org(name).await.context(CreatingOrg)?;
+    let target = format!("/api/v2/orgs/{}", org.id);
+    org_json(&org, StatusCode::CREATED).map(|res| with_audit_target(res, target))
+}
+
+#[tracing::instrument(level = "debug")]
+async fn get_org<M>(req: Request<Body>) -> Result<Response<Body>, ApplicationError>
+where
+    M: ConnectionManager + Send + Sync + Debug + 'static,
+{
+    let server = Arc::clone(&req.data::<Arc<AppServer<M>>>().expect("server state"));
+
+    // with routerify, we shouldn't have gotten here without this being set
+    let id = req.param("id").expect("org id must have been set").clone();
+
+    let org = server.org(&id).await.context(OrgNotFound { id })?;
+    access(&req)
+        .authorize(Scope::Read, Some(&org.name), None)
+        .context(ApiAuthorization)?;
+    org_json(&org, StatusCode::OK)
+}
+
+#[tracing::instrument(level = "debug")]
+async fn get_org_usage<M>(req: Request<Body>) -> Result<Response<Body>, ApplicationError>
+where
+    M: ConnectionManager + Send + Sync + Debug + 'static,
+{
+    let server = Arc::clone(&req.data::<Arc<AppServer<M>>>().expect("server state"));
+
+    // with routerify, we shouldn't have gotten here without this being set
+    let id = req.param("id").expect("org id must have been set").clone();
+
+    let org = server.org(&id).await.context(OrgNotFound { id: &id })?;
+    access(&req)
+        .authorize(Scope::Read, Some(&org.name), None)
+        .context(ApiAuthorization)?;
+
+    let usage = server
+        .org_usage(&org.name)
+        .context(GettingOrgUsage { id })?;
+    let json = serde_json::to_string(&usage).context(JsonGenerationError)?;
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .context(CreatingResponse)
+}
+
+#[tracing::instrument(level = "debug")]
+async fn update_org<M>(req: Request<Body>) -> Result<Response<Body>, ApplicationError>
+where
+    M: ConnectionManager + Send + Sync + Debug + 'static,
+{
+    let server = Arc::clone(&req.data::<Arc<AppServer<M>>>().expect("server state"));
+
+    // with routerify, we shouldn't have gotten here without this being set
+    let id = req.param("id").expect("org id must have been set").clone();
+    authorize_org(&server, &access(&req), &id).await?;
+
+    let body = parse_body(req).await?;
+    let OrgRequest { name } = serde_json::from_slice(body.as_ref()).context(InvalidRequestBody)?;
+
+    let org = server
+        .rename_org(&id, name)
+        .await
+        .context(UpdatingOrg { id })?;
+    org_json(&org, StatusCode::OK)
+}
+
+#[tracing::instrument(level = "debug")]
+async fn set_org_limits<M>(req: Request<Body>) -> Result<Response<Body>, ApplicationError>
+where
+    M: ConnectionManager + Send + Sync + Debug + 'static,
+{
+    let server = Arc::clone(&req.data::<Arc<AppServer<M>>>().expect("server state"));
+
+    // with routerify, we shouldn't have gotten here without this being set
+    let id = req.param("id").expect("org id must have been set").clone();
+    server.org(&id).await.context(OrgNotFound { id: &id })?;
+    // the limits of an org are set by tokens not restricted to it, so that
+    // they can't be lifted from within the org
+    access(&req)
+        .authorize(Scope::Admin, None, None)
+        .context(ApiAuthorization)?;
+
+    let body = parse_body(req).await?;
+    let limits: RateLimits = serde_json::from_slice(body.as_ref()).context(InvalidRequestBody)?;
+
+    let org = server
+        .set_org_rate_limits(&id, limits)
+        .await
+        .context(UpdatingOrg { id })?;
+    org_json(&org, StatusCode::OK)
+}
+
+#[tracing::instrument(level = "debug")]
+async fn delete_org<M>(req: Request<Body>) -> Result<Response<Body>, ApplicationError>
+where
+    M: ConnectionManager + Send + Sync + Debug + 'static,
+{
+    let server = Arc::clone(&req.data::<Arc<AppServer<M>>>().expect("server state"));
+
+    // with routerify, we shouldn't have gotten here without this being set
+    let id = req.param("id").expect("org id must have been set").clone();
+    authorize_org(&server, &access(&req), &id).await?;
+
+    server.delete_org(&id).await.context(DeletingOrg { id })?;
+
+    Ok(Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .body(Body::empty())
+        .unwrap())
+}
+
+#[derive(Debug, Default, Deserialize)]
+/// Query string of the GET /tokens endpoint
+struct ListTokensQuery {
+    /// If set, only the tokens restricted to this org are listed
+    org: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+/// Body of the response to the GET /tokens endpoint
+struct ListTokensResponse {
+    tokens: Vec<Token>,
+}
+
+#[derive(Debug, Deserialize)]
+/// Body of the request to the POST /tokens endpoint
+struct CreateTokenRequest {
+    #[serde(default)]
+    description: String,
+    scopes: Vec<Scope>,
+    org: Option<String>,
+    bucket: Option<String>,
+    #[serde(default)]
+    limits: RateLimits,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+/// Body of the response to the POST /tokens endpoint, the only one that
+/// includes the token itself
+struct CreateTokenResponse {
+    #[serde(flatten)]
+    details: Token,
+    token: String,
+}
+
+#[tracing::instrument(level = "debug")]
+async fn list_tokens<M>(req: Request<Body>) -> Result<Response<Body>, ApplicationError>
+where
+    M: ConnectionManager + Send + Sync + Debug + 'static,
+{
+    let server = Arc::clone(&req.data::<Arc<AppServer<M>>>().expect("server state"));
+
+    let query: ListTokensQuery = req
+        .uri()
+        .query()
+        .map(|query| {
+            serde_urlencoded::from_str(query).context(InvalidQueryString {
+                query_string: query,
+            })
+        })
+        .transpose()?
+        .unwrap_or_default();
+    access(&req)
+        .authorize(Scope::Admin, query.org.as_deref(), None)
+        .context(ApiAuthorization)?;
+
+    let mut tokens = server.tokens();
+    if let Some(org) = &query.org {
+        tokens.retain(|token| token.org.as_ref() == Some(org));
+    }
+
+    let json =
+        serde_json::to_string(&ListTokensResponse { tokens }).context(JsonGenerationError)?;
+    Response::builder()
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .context(CreatingResponse)
+}
+
+#[tracing::instrument(level = "debug")]
+async fn create_token<M>(req: Request<Body>) -> Result<Response<Body>, ApplicationError>
+where
+    M: ConnectionManager + Send + Sync + Debug + 'static,
+{
+    let server = Arc::clone(&req.data::<Arc<AppServer<M>>>().expect("server state"));
+    let access = access(&req);
+
+    let body = parse_body(req).await?;
+    let request: CreateTokenRequest =
+        serde_json::from_slice(body.as_ref()).context(InvalidRequestBody)?;
+
+    // tokens can only be created within the creator's own restrictions
+    access
+        .authorize(
+            Scope::Admin,
+            request.org.as_deref(),
+            request.bucket.as_deref(),
+        )
+        .context(ApiAuthorization)?;
+
+    let (details, token) = server
+        .create_token(TokenRequest {
+            description: request.description,
+            scopes: request.scopes,
+            org: request.org,
+            bucket: request.bucket,
+            limits: request.limits,
+        })
+        .await
+        .context(CreatingToken)?;
+
+    let target = format!("/api/v2/tokens/{}", details.id);
+    let json = serde_json::to_string(&CreateTokenResponse { details, token })
+        .context(JsonGenerationError)?;
+    Response::builder()
+        .status(StatusCode::CREATED)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .context(CreatingResponse)
+        .map(|res| with_audit_target(res, target))
+}
+
+#[tracing::instrument(level = "debug")]
+async fn delete_token<M>(req: Request<Body>) -> Result<Response<Body>, ApplicationError>
+where
+    M: ConnectionManager + Send + Sync + Debug + 'static,
+{
+    let server = Arc::clone(&req.data::<Arc<AppServer<M>>>().expect("server state"));
+
+    // with routerify, we shouldn't have gotten here without this being set
+    let id = req
+        .param("id")
+        .expect("token id must have been set")
+        .clone();
+
+    let token = server.token(&id).context(TokenNotFound { id: &id })?;
+    access(&req)
+        .authorize(Scope::Admin, token.org.as_deref(), token.bucket.as_deref())
+        .context(ApiAuthorization)?;
+
+    server
+        .delete_token(&id)
+        .await
+        .context(DeletingToken { id })?;
+
+    Ok(Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .body(Body::empty())
+        .unwrap())
+}
+
+#[derive(Debug, Deserialize)]
+/// Body of the request to the /delete endpoint
+struct DeleteRequest {
+    /// RFC3339 timestamp of the earliest data to delete
+    start: String,
+    /// RFC3339 timestamp of the latest data to delete
+    stop: String,
+    /// Restricts the delete to matching rows, e.g. `_measurement="cpu" AND
+    /// host="a"`
+    #[serde(default)]
+    predicate: String,
+}
+
+fn parse_delete_time(name: &'static str, value: &str) -> Result<i64, ApplicationError> {
+    chrono::DateTime::parse_from_rfc3339(value)
+        .ok()
+        .and_then(|time| timestamp_nanos(&time))
+        .context(InvalidDeleteTime { name, value })
+}
+
+#[tracing::instrument(level = "debug")]
+async fn delete<M>(req: Request<Body>) -> Result<Response<Body>, ApplicationError>
+where
+    M: ConnectionManager + Send + Sync + Debug + 'static,
+{
+    let server = Arc::clone(&req.data::<Arc<AppServer<M>>>().expect("server state"));
+
+    let query = req.uri().query().context(ExpectedQueryString)?;
+
+    let write_info: WriteInfo = serde_urlencoded::from_str(query).context(InvalidQueryString {
+        query_string: String::from(query),
+    })?;
+
+    request_logging::span(&req).record_org_and_bucket(&write_info.org, &write_info.bucket);
+    access(&req)
+        .authorize(
+            Scope::Write,
+            Some(&write_info.org),
+            Some(&write_info.bucket),
+        )
+        .context(ApiAuthorization)?;
+
+    let db_name = org_and_bucket_to_database(&write_info.org, &write_info.bucket)
+        .context(BucketMappingError)?;
+
+    let read_cache = req
+        .data::<Option<Arc<ReadCache>>>()
+        .expect("read cache")
+        .clone();
+    let body = parse_body(req).await?;
+    let request: DeleteRequest =
+        serde_json::from_slice(body.as_ref()).context(InvalidRequestBody)?;
+
+    let start = parse_delete_time("start", &request.start)?;
+    let stop = parse_delete_time("stop", &request.stop)?;
+    let predicate = DeletePredicate::try_new(start, stop, &request.predicate)
+        .context(InvalidDeletePredicate)?;
+
+    ensure!(
+        server.db(&db_name).await.is_some(),
+        BucketNotFound {
+            org: &write_info.org,
+            bucket: &write_info.bucket,
+        }
+    );
+
+    debug!(%db_name, predicate = %Redacted(format!("{:?}", predicate)), "Deleting data");
+
+    let result = server.delete(&db_name, predicate).await;
+    if let Some(read_cache) = read_cache {
+        read_cache.invalidate(&db_name, (start, stop));
+    }
+    result.context(DeletingData {
+        org: write_info.org,
+        bucket: write_info.bucket,
+    })?;
+
+    Ok(Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .body(Body::empty())
+        .unwrap())
+}
+
+#[tracing::instrument(level = "debug", skip(req), fields(uri = %loggable_uri(req.uri())))]
+async fn read<M>(req: Request<Body>) -> Result<Response<Body>, ApplicationError>
+where
+    M: ConnectionManager + Send + Sync + Debug + 'static,
+{
+    let server = Arc::clone(&req.data::<Arc<AppServer<M>>>().expect("server state"));
+
+    let query = req.uri().query().context(ExpectedQueryString)?;
+
+    let params: ReadParams = serde_urlencoded::from_str(query).context(InvalidQueryString {
+        query_string: String::from(query),
+    })?;
+
+    request_logging::span(&req).record_org_and_bucket(&params.org, &params.bucket);
+
+    let now = chrono::Utc::now().timestamp_nanos();
+    let pass = ReadPass::try_new(&params, now)?;
+    // a shifted read reads the earlier time range in a second pass
+    let shifted = match params.shifted(now).context(ReadError)? {
+        Some(shifted) => Some((ReadPass::try_new(&shifted.params, now)?, shifted)),
+        None => None,
+    };
+
+    let access = access(&req);
+    let mut dbs = vec![];
+    for bucket in params.buckets() {
+        access
+            .authorize(Scope::Read, Some(&params.org), Some(bucket))
+            .context(ApiAuthorization)?;
+        let db_name =
+            org_and_bucket_to_database(&params.org, bucket).context(BucketMappingError)?;
+        let db = server.db(&db_name).await.context(BucketNotFound {
+            org: &params.org,
+            bucket,
+        })?;
+        dbs.push((db_name.to_string(), db));
+    }
+    let permit = server
+        .start_query(access.token(), Some(&params.org))
+        .await
+        .context(RateLimited)?;
+    let executor = server.executor();
+
+    // the generation is taken before reading, so that the response isn't
+    // cached if data is written meanwhile
+    let cache = match req.data::<Option<Arc<ReadCache>>>().expect("read cache") {
+        Some(read_cache) => {
+            let db_names = dbs.iter().map(|(db_name, _)| db_name.clone()).collect();
+            let key = CacheKey::new(db_names, query);
+            if let Some(cached) = read_cache.get(&key) {
+                debug!(db_name = %dbs[0].0, "Read response cached");
+                return read_response(cached);
+            }
+            let range = params.covered_range(now).context(ReadError)?;
+            Some((Arc::clone(read_cache), key, range, read_cache.generation()))
+        }
+        None => None,
+    };
+
+    // reads that merge the results of several buckets or passes are
+    // collected first
+    if let (None, ReadFormat::Csv, None, [(db_name, db)]) =
+        (&cache, params.format, &shifted, dbs.as_slice())
+    {
+        let chunks = pass.execute_csv(db_name, db, executor).await?;
+        return csv_stream_response(chunks, permit).await;
+    }
+
+    let mut results = pass.run(&dbs, &executor).await?;
+    if let Some((pass, shifted)) = shifted {
+        let shifted_results = pass.run(&dbs, &executor).await?;
+        results.append_shifted(shifted_results, &shifted);
+    }
+    if cache.is_none() && params.format == ReadFormat::Csv {
+        return csv_response(results);
+    }
+    let read = CachedRead {
+        content_type: params.format.content_type(),
+        body: results.format(params.format).context(ReadError)?,
+    };
+
+    if let Some((read_cache, key, range, generation)) = cache {
+        read_cache.insert(key, range, generation, read.clone());
+    }
+    read_response(read)
+}
+
+fn read_response(read: CachedRead) -> Result<Response<Body>, ApplicationError> {
+    Response::builder()
+        .header(CONTENT_TYPE, read.content_type)
+        .body(Body::from(read.body))
+        .context(CreatingResponse)
+}
+
+/// Streams the CSV of `results`, serializing it as the body is sent. Bodies
+/// of a single chunk are sent whole, so that their length is known and they
+/// can be compressed.
+fn csv_response(results: ReadResults) -> Result<Response<Body>, ApplicationError> {
+    let mut chunks = results.into_csv_chunks(CSV_CHUNK_SIZE).peekable();
+    let first = chunks.next().unwrap_or_default();
+    let body = if chunks.peek().is_none() {
+        Body::from(first)
+    } else {
+        let chunks = std::iter::once(first)
+            .chain(chunks)
+            .map(Ok::<_, std::convert::Infallible>);
+        Body::wrap_stream(futures::stream::iter(chunks))
+    };
+
+    Response::builder()
+        .header(CONTENT_TYPE, ReadFormat::Csv.content_type())
+        .body(body)
+        .context(CreatingResponse)
+}
+
+/// Streams the CSV chunks received from `chunks`, holding `permit` until the
+/// last is sent. Bodies of a single chunk are sent whole, so that their
+/// length is known.
+async fn csv_stream_response(
+    mut chunks: mpsc::Receiver<read::Result<Bytes>>,
+    permit: QueryPermit,
+) -> Result<Response<Body>, ApplicationError> {
+    let first = chunks
+        .recv()
+        .await
+        .transpose()
+        .context(ReadError)?
+        .unwrap_or_default();
+    // only the last chunk is smaller than a full one
+    let body = if first.len() < CSV_CHUNK_SIZE {
+        Body::from(first)
+    } else {
+        let rest = futures::stream::unfold((chunks, permit), |(mut chunks, permit)| async move {
+            let chunk = chunks.recv().await?;
+            Some((chunk, (chunks, permit)))
+        });
+        Body::wrap_stream(futures::stream::once(async move { Ok(first) }).chain(rest))
+    };
+
+    Response::builder()
+        .header(CONTENT_TYPE, ReadFormat::Csv.content_type())
+        .body(body)
+        .context(CreatingResponse)
+}
+
+/// One pass of a read over a time range: the plans of the query and how
+/// their results are collected. The buckets of a union are read one after
+/// the other and their results merged.
+#[derive(Debug)]
+struct ReadPass {
+    predicate: Predicate,
+    gby_agg: Option<GroupByAndAggregate>,
+    options: ReadOptions,
+    fields: Vec<ComputedField>,
+    grouped: bool,
+}
+
+impl ReadPass {
+    fn try_new(params: &ReadParams, now: i64) -> Result<Self, ApplicationError> {
+        let gby_agg = params.group_by_and_aggregate().context(ReadError)?;
+        let grouped = matches!(
+            &gby_agg,
+            Some(GroupByAndAggregate::Columns { group_columns, .. }) if !group_columns.is_empty()
+        );
+        Ok(Self {
+            predicate: params.predicate(now).context(ReadError)?,
+            gby_agg,
+            options: params.options(now).context(ReadError)?,
+            fields: params.computed_fields().context(ReadError)?,
+            grouped,
+        })
+    }
+
+    async fn run(
+        self,
+        dbs: &[(String, Arc<Db>)],
+        executor: &Executor,
+    ) -> Result<ReadResults, ApplicationError> {
+        if let [(db_name, db)] = dbs {
+            let plans = self.plans(db, db_name).await?;
+            return ReadResults::execute(executor, plans, self.grouped, self.options)
+                .await
+                .context(ReadError);
+        }
+
+        let mut results = vec![];
+        for (db_name, db) in dbs {
+            let plans = self.plans(db, db_name).await?;
+            let options = self.options.unprocessed();
+            results.push(
+                ReadResults::execute(executor, plans, self.grouped, options)
+                    .await
+                    .context(ReadError)?,
+            );
+        }
+        Ok(ReadResults::merge(results, self.options))
+    }
+
+    /// Reads `db` and streams the results as CSV, see
+    /// `ReadResults::execute_csv`
+    async fn execute_csv(
+        self,
+        db_name: &str,
+        db: &Db,
+        executor: Arc<Executor>,
+    ) -> Result<mpsc::Receiver<read::Result<Bytes>>, ApplicationError> {
+        let plans = self.plans(db, db_name).await?;
+        Ok(ReadResults::execute_csv(
+            executor,
+            plans,
+            self.grouped,
+            self.options,
+            CSV_CHUNK_SIZE,
+        ))
+    }
+
+    async fn plans(&self, db: &Db, db_name: &str) -> Result<SeriesSetPlans, ApplicationError> {
+        let predicate = self.predicate.clone();
+        let gby_agg = self.gby_agg.clone();
+        debug!(
+            %db_name,
+            predicate = %Redacted(format!("{:?}", predicate)),
+            ?gby_agg,
+            "Reading series"
+        );
+
+        let plans = match gby_agg {
+            Some(gby_agg) => db.query_groups(predicate, gby_agg).await,
+            None => db.query_series(predicate).await,
+        }
+        .map_err(|e| Box::new(e) as _)
+        .context(Query { db_name })?;
+        compute_fields(plans, &self.fields).context(ReadError)
+    }
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+/// Parsed URI Parameters of the request to the .../query endpoint
+struct QueryParams {
+    q: String,
+    #[serde(default)]
+    format: QueryOutputFormat,
+    /// Comma separated databases queried along with the database of the
+    /// endpoint, each table holding the rows of all of them
+    #[serde(default)]
+    union: String,
+}
+
+#[tracing::instrument(level = "debug", skip(req), fields(uri = %loggable_uri(req.uri())))]
+async fn query<M: ConnectionManager + Send + Sync + Debug + 'static>(
+    req: Request<Body>,
+) -> Result<Response<Body>, ApplicationError> {
+    let server = Arc::clone(&req.data::<Arc<AppServer<M>>>().expect("server state"));
+
+    let uri_query = req.uri().query().context(ExpectedQueryString {})?;
+
+    let QueryParams { q, format, union } =
+        serde_urlencoded::from_str(uri_query).context(InvalidQueryString {
+            query_string: uri_query,
+        })?;
+
+    let db_name_str = req
+        .param("name")
+        .expect("db name must have been set by routerify")
+        .clone();
+
+    let db_name = DatabaseName::new(&db_name_str).context(DatabaseNameError)?;
+    debug!(
+        uri = %loggable_uri(req.uri()),
+        q = %query_log::loggable_sql(&q),
+        ?format,
+        %db_name,
+        "running SQL query"
+    );
+
+    let access = access(&req);
+    access
+        .authorize_database(Scope::Read, &db_name_str)
+        .context(ApiAuthorization)?;
+    let org = database_to_org_and_bucket(&db_name_str).map(|(org, _)| org);
+    let _permit = server
+        .start_query(access.token(), org.as_deref())
+        .await
+        .context(RateLimited)?;
+
+    let query_log = Arc::clone(&req.data::<Arc<QueryLog>>().expect("query log"));
+    let shape = query_log::normalize_sql(&q);
+    let start = Instant::now();
+
+    let result = async {
+        let db = server
+            .db(&db_name)
+            .await
+            .context(DatabaseNotFound { name: &db_name_str })?;
+
+        let mut dbs = vec![db];
+        let mut names = vec![db_name_str.as_str()];
+        for name in union.split(',').map(str::trim) {
+            if name.is_empty() || names.contains(&name) {
+                continue;
+            }
+            access
+                .authorize_database(Scope::Read, name)
+                .context(ApiAuthorization)?;
+            let union_name = DatabaseName::new(name).context(DatabaseNameError)?;
+            let db = server
+                .db(&union_name)
+                .await
+                .context(DatabaseNotFound { name })?;
+            dbs.push(db);
+            names.push(name);
+        }
+        let dbs: Vec<&Db> = dbs.iter().map(AsRef::as_ref).collect();
+
+        let planner = SQLQueryPlanner::default();
+        let executor = server.executor();
+
+        let physical_plan = planner
+            .query_union(&dbs, &q, executor.as_ref())
+            .await
+            .context(PlanningSQLQuery { query: &q })?;
+
+        // TODO: stream read results out rather than rendering the
+        // whole thing in mem
+        let batches = executor
+            .collect(physical_plan)
+            .await
+            .map_err(|e| Box::new(e) as _)
+            .context(Query { db_name })?;
+
+        format
+            .format(&batches)
+            .context(FormattingResult { q, format })
+    }
+    .await;
+
+    query_log.record(
+        "sql",
+        &db_name_str,
+        &shape,
+        start.elapsed(),
+        result.as_ref().err().map(|e| e as &dyn std::fmt::Display),
+    );
+    let results = result?;
+
+    let body = Body::from(results.into_bytes());
+
+    let response = Response::builder()
+        .header(CONTENT_TYPE, format.content_type())
+        .body(body)
+        .context(CreatingResponse)?;
+
+    Ok(response)
+}
+
+#[tracing::instrument(level = "debug")]
+async fn list_databases<M>(req: Request<Body>) -> Result<Response<Body>, ApplicationError>
+where
+    M: ConnectionManager + Send + Sync + Debug + 'static,
+{
+    let server = Arc::clone(&req.data::<Arc<AppServer<M>>>().expect("server state"));
+    access(&req)
+        .authorize(Scope::Read, None, None)
+        .context(ApiAuthorization)?;
+
+    let names = server.db_names_sorted().await;
+    let json = serde_json::to_string(&ListDatabasesResponse { names })
+        .context(InternalSerializationError)?;
+    Ok(Response::new(Body::from(json)))
+}
+
+#[tracing::instrument(level = "debug")]
+async fn create_database<M: ConnectionManager + Send + Sync + Debug + 'static>(
+    req: Request<Body>,
+) -> Result<Response<Body>, ApplicationError> {
+    let server = Arc::clone(&req.data::<Arc<AppServer<M>>>().expect("server state"));
+
+    // with routerify, we shouldn't have gotten here without this being set
+    let db_name = req
+        .param("name")
+        .expect("db name must have been set")
+        .clone();
+    access(&req)
+        .authorize_database(Scope::Admin, &db_name)
+        .context(ApiAuthorization)?;
+    let body = parse_body(req).await?;
+
+    let rules: DatabaseRules = serde_json::from_slice(body.as_ref()).context(InvalidRequestBody)?;
+
+    server
+        .create_database(db_name, rules)
+        .await
+        .context(ErrorCreatingDatabase)?;
+
+    Ok(Response::new(Body::empty()))
+}
+
+#[tracing::instrument(level = "debug")]
+async fn get_database<M: ConnectionManager + Send + Sync + Debug + 'static>(
+    req: Request<Body>,
+) -> Result<Response<Body>, ApplicationError> {
+    let server = Arc::clone(&req.data::<Arc<AppServer<M>>>().expect("server state"));
+
+    // with routerify, we shouldn't have gotten here without this being set
+    let db_name_str = req
+        .param("name")
+        .expect("db name must have been set")
+        .clone();
+    access(&req)
+        .authorize_database(Scope::Read, &db_name_str)
+        .context(ApiAuthorization)?;
+    let db_name = DatabaseName::new(&db_name_str).context(DatabaseNameError)?;
+    let db = server
+        .db_rules(&db_name)
+        .await
+        .context(DatabaseNotFound { name: &db_name_str })?;
+
+    let data = serde_json::to_string(&db).context(JsonGenerationError)?;
+    let response = Response::builder()
+        .header("Content-Type", "application/json")
+        .status(StatusCode::OK)
+        .body(Body::from(data))
+        .expect("builder should be successful");
+
+    Ok(response)
+}
+
+#[tracing::instrument(level = "debug")]
+async fn get_wal_meta<M: ConnectionManager + Send + Sync + Debug + 'static>(
+    req: Request<Body>,
+) -> Result<Response<Body>, ApplicationError> {
+    let server = Arc::clone(&req.data::<Arc<AppServer<M>>>().expect("server state"));
+
+    let db_name_str = req
+        .param("name")
+        .expect("db name must have been set")
+        .clone();
+    access(&req)
+        .authorize_database(Scope::Read, &db_name_str)
+        .context(ApiAuthorization)?;
+
+    let query: WalMetadataQuery = req
+        .uri()
+        .query()
+        .map(|query| {
+            serde_urlencoded::from_str(query).context(InvalidQueryString {
+                query_string: query,
+            })
+        })
+        .transpose()?
+        .unwrap_or_default();
+
+    let db_name = DatabaseName::new(&db_name_str).context(DatabaseNameError)?;
+
+    let db = server
+        .db(&db_name)
+        .await
+        .context(DatabaseNotFound { name: &db_name_str })?;
+
+    let wal = db
+        .wal_buffer
+        .as_ref()
+        .context(WALNotFound { name: &db_name_str })?;
+    let wal_buffer = wal.lock();
+
+    let segments = wal_buffer
+        .segments(query.offset)
+        .take(query.limit.unwrap_or(10))
+        .take_while(|x| {
+            query
+                .newer_than
+                .map(|newer_than| x.created_at > newer_than)
+                .unwrap_or(true)
+        })
+        .collect();
+
+    let response = Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(
+            serde_json::to_string(&WalMetadataResponse { segments })
+                .expect("json encoding should not fail"),
+        ))
+        .expect("builder should be successful");
+
+    Ok(response)
+}
+
+#[tracing::instrument(level = "debug")]
+async fn set_writer<M: ConnectionManager + Send + Sync + Debug + 'static>(
+    req: Request<Body>,
+) -> Result<Response<Body>, ApplicationError> {
+    let server = Arc::clone(&req.data::<Arc<AppServer<M>>>().expect("server state"));
+    access(&req)
+        .authorize(Scope::Admin, None, None)
+        .context(ApiAuthorization)?;
+
+    // Read the request body
+    let body = parse_body(req).await?;
+
+    // Parse the JSON body into a structure
+    #[derive(Serialize, Deserialize)]
+    struct WriterIdBody {
+        id: u32,
+    }
+    let req: WriterIdBody = serde_json::from_slice(body.as_ref()).context(InvalidRequestBody)?;
+
+    // Set the writer ID
+    server.set_id(req.id);
+
+    // Build a HTTP 200 response
+    let response = Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(
+            serde_json::to_string(&req).expect("json encoding should not fail"),
+        ))
+        .expect("builder should be successful");
+
+    Ok(response)
+}
+
+#[tracing::instrument(level = "debug")]
+async fn get_writer<M: ConnectionManager + Send + Sync + Debug + 'static>(
+    req: Request<Body>,
+) -> Result<Response<Body>, ApplicationError> {
+    access(&req)
+        .authorize(Scope::Read, None, None)
+        .context(ApiAuthorization)?;
+    let id = {
+        let server = Arc::clone(&req.data::<Arc<AppServer<M>>>().expect("server state"));
+        server.require_id()
+    };
+
+    // Parse the JSON body into a structure
+    #[derive(Serialize)]
+    struct WriterIdBody {
+        id: u32,
+    }
+
+    let body = WriterIdBody {
+        id: id.unwrap_or(0),
+    };
+
+    // Build a HTTP 200 response
+    let response = Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(
+            serde_json::to_string(&body).expect("json encoding should not fail"),
+        ))
+        .expect("builder should be successful");
+
+    Ok(response)
+}
+
+// Route to test that the server is alive. Matches the InfluxDB 1.x `/ping`
+// response so that existing health checks and client libraries work. The
+// `X-Influxdb-Version` header is added to every response.
+#[tracing::instrument(level = "debug")]
+async fn ping(_: Request<Body>) -> Result<Response<Body>, ApplicationError> {
+    Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .body(Body::empty())
+        .context(CreatingResponse)
+}
+
+/// The warning added to the responses of a server that lost the quorum of the
+/// cluster
+const STALE_RESPONSE_WARNING: &str = "110 - \"Response is Stale\"";
+
+/// Adds headers identifying the version and commit of the server to a
+/// response
+fn add_build_headers(res: &mut Response<Body>) {
+    let headers = res.headers_mut();
+    headers.insert(
+        "X-Influxdb-Version",
+        HeaderValue::from_static(build_info::VERSION),
+    );
+    headers.insert("X-Influxdb-Build", HeaderValue::from_static("IOx"));
+    headers.insert(
+        "X-Iox-Git-Hash",
+        HeaderValue::from_static(build_info::GIT_HASH),
+    );
+}
+
+#[tracing::instrument(level = "debug")]
+async fn build(_: Request<Body>) -> Result<Response<Body>, ApplicationError> {
+    let json = serde_json::to_string(&BuildInfo::get()).context(JsonGenerationError)?;
+    Response::builder()
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .context(CreatingResponse)
+}
+
+/// Returns the server's metrics in the Prometheus text format
+#[tracing::instrument(level = "debug")]
+async fn metrics<M: ConnectionManager + Send + Sync + Debug + 'static>(
+    req: Request<Body>,
+) -> Result<Response<Body>, ApplicationError> {
+    let server = Arc::clone(&req.data::<Arc<AppServer<M>>>().expect("server state"));
+    let pruning = &query::pruning::METRICS;
+    let mut body = format!(
+        "# HELP iox_query_chunks_considered_total Chunks checked against query predicates\n\
+         # TYPE iox_query_chunks_considered_total counter\n\
+         iox_query_chunks_considered_total {}\n\
+         # HELP iox_query_chunks_pruned_total Chunks skipped by queries using their statistics\n\
+         # TYPE iox_query_chunks_pruned_total counter\n\
+         iox_query_chunks_pruned_total {}\n",
+        pruning.chunks_considered(),
+        pruning.chunks_pruned(),
+    );
+
+    let scheduler = server.job_scheduler();
+    body.push_str(&format!(
+        "# HELP iox_background_jobs_running Compactions and snapshots running\n\
+         # TYPE iox_background_jobs_running gauge\n\
+         iox_background_jobs_running {}\n\
+         # HELP iox_background_jobs_queued Compactions and snapshots waiting to run, by tenant\n\
+         # TYPE iox_background_jobs_queued gauge\n",
+        scheduler.running(),
+    ));
+    for (tenant, depth) in scheduler.queue_depths() {
+        body.push_str(&format!(
+            "iox_background_jobs_queued{{tenant=\"{}\"}} {}\n",
+            label_value(&tenant),
+            depth
+        ));
+    }
+    body.push_str(&format!(
+        "# HELP iox_background_io_file_ops Files being written by background jobs\n\
+         # TYPE iox_background_io_file_ops gauge\n\
+         iox_background_io_file_ops {}\n",
+        scheduler.throttle().file_ops(),
+    ));
+
+    let memory = server.measure_memory();
+    body.push_str(
+        "# HELP iox_memory_used_bytes Approximate bytes held in memory, by subsystem\n\
+         # TYPE iox_memory_used_bytes gauge\n",
+    );
+    for (subsystem, bytes) in memory.breakdown() {
+        body.push_str(&format!(
+            "iox_memory_used_bytes{{subsystem=\"{}\"}} {}\n",
+            subsystem, bytes
+        ));
+    }
+    if let Some(limit) = memory.limit() {
+        body.push_str(&format!(
+            "# HELP iox_memory_limit_bytes Bytes the server may hold in memory\n\
+             # TYPE iox_memory_limit_bytes gauge\n\
+             iox_memory_limit_bytes {}\n",
+            limit
+        ));
+    }
+    body.push_str(&format!(
+        "# HELP iox_memory_rejected_total Writes and queries rejected at the memory limit\n\
+         # TYPE iox_memory_rejected_total counter\n\
+         iox_memory_rejected_total {}\n",
+        memory.rejected()
+    ));
+
+    let replay = server.write_buffer_replay();
+    if !replay.is_empty() {
+        body.push_str(
+            "# HELP iox_write_buffer_replay_remaining Write buffer messages left to replay, by partition\n\
+             # TYPE iox_write_buffer_replay_remaining gauge\n",
+        );
+        for partition in &replay {
+            body.push_str(&format!(
+                "iox_write_buffer_replay_remaining{{partition=\"{}\"}} {}\n",
+                partition.partition,
+                partition.remaining()
+            ));
+        }
+        body.push_str(
+            "# HELP iox_write_buffer_replayed_total Writes replayed from the write buffer, by partition\n\
+             # TYPE iox_write_buffer_replayed_total counter\n",
+        );
+        for partition in &replay {
+            body.push_str(&format!(
+                "iox_write_buffer_replayed_total{{partition=\"{}\"}} {}\n",
+                partition.partition, partition.replayed
+            ));
+        }
+    }
+
+    if let Some(hints) = server.hinted_handoff() {
+        body.push_str(
+            "# HELP iox_hinted_writes Writes kept for peers that failed to store them, by peer\n\
+             # TYPE iox_hinted_writes gauge\n",
+        );
+        for (peer, writes) in hints.pending() {
+            body.push_str(&format!(
+                "iox_hinted_writes{{peer=\"{}\"}} {}\n",
+                label_value(&peer),
+                writes
+            ));
+        }
+        body.push_str(&format!(
+            "# HELP iox_hinted_writes_dropped_total Hinted writes dropped as too old or too many\n\
+             # TYPE iox_hinted_writes_dropped_total counter\n\
+             iox_hinted_writes_dropped_total {}\n",
+            hints.dropped()
+        ));
+    }
+
+    if let Some(replication) = server.region_replication() {
+        body.push_str(&format!(
+            "# HELP iox_secondary_region_rpo_seconds Seconds of persisted data the secondary object store may be missing\n\
+             # TYPE iox_secondary_region_rpo_seconds gauge\n\
+             iox_secondary_region_rpo_seconds {}\n\
+             # HELP iox_secondary_region_copied_objects_total Objects copied to the secondary object store\n\
+             # TYPE iox_secondary_region_copied_objects_total counter\n\
+             iox_secondary_region_copied_objects_total {}\n\
+             # HELP iox_secondary_region_copied_bytes_total Bytes copied to the secondary object store\n\
+             # TYPE iox_secondary_region_copied_bytes_total counter\n\
+             iox_secondary_region_copied_bytes_total {}\n\
+             # HELP iox_secondary_region_sync_failures_total Failed syncs of the secondary object store\n\
+             # TYPE iox_secondary_region_sync_failures_total counter\n\
+             iox_secondary_region_sync_failures_total {}\n",
+            replication.rpo().num_seconds(),
+            replication.copied(),
+            replication.copied_bytes(),
+            replication.failures()
+        ));
+    }
+
+    if let Some(status) = server.quorum_status() {
+        body.push_str(&format!(
+            "# HELP iox_cluster_members_alive Members of the cluster seen alive, this server included\n\
+             # TYPE iox_cluster_members_alive gauge\n\
+             iox_cluster_members_alive {}\n\
+             # HELP iox_cluster_quorum_lost Whether this server sees fewer members alive than the quorum requires\n\
+             # TYPE iox_cluster_quorum_lost gauge\n\
+             iox_cluster_quorum_lost {}\n",
+            status.alive,
+            status.lost() as u8
+        ));
+    }
+    Response::builder()
+        .header(CONTENT_TYPE, "text/plain; version=0.0.4")
+        .body(Body::from(body))
+        .context(CreatingResponse)
+}
+
+/// Escapes `value` for use as the value of a label in the Prometheus text
+/// format
+fn label_value(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', r#"\""#)
+        .replace('\n', r"\n")
+}
+
+/// Returns the handle used to change the log filter, if the server has one
+fn log_filter_handle(req: &Request<Body>) -> Result<LogFilterHandle, ApplicationError> {
+    req.data::<Option<LogFilterHandle>>()
+        .expect("log filter handle")
+        .clone()
+        .context(LogFilterUnavailable)
+}
+
+#[tracing::instrument(level = "debug")]
+async fn get_log_filter(req: Request<Body>) -> Result<Response<Body>, ApplicationError> {
+    authorize_admin(&req)?;
+
+    let filter = log_filter_handle(&req)?.current().context(LogFilterError)?;
+    Ok(Response::new(Body::from(filter)))
+}
+
+/// Replaces the log filter with the one in the request body, in `RUST_LOG`
+/// syntax, until the server restarts or the configured filter is changed
+/// and reloaded
+#[tracing::instrument(level = "debug")]
+async fn set_log_filter(req: Request<Body>) -> Result<Response<Body>, ApplicationError> {
+    authorize_admin(&req)?;
+
+    let log_filter = log_filter_handle(&req)?;
+    let body = parse_body(req).await?;
+    let filter = str::from_utf8(&body).context(ReadingBodyAsUtf8)?.trim();
+
+    log_filter.set(filter).context(LogFilterError)?;
+    info!(%filter, "Log filter changed");
+
+    let filter = log_filter.current().context(LogFilterError)?;
+    let target = format!("log_filter={}", filter);
+    Ok(with_audit_target(Response::new(Body::from(filter)), target))
+}
+
+#[tracing::instrument(level = "debug")]
+async fn health(_: Request<Body>) -> Result<Response<Body>, ApplicationError> {
+    let response_body = "OK";
+    Ok(Response::new(Body::from(response_body.to_string())))
+}
+
+// Route to test that the server has finished starting up and is not
+// shutting down, i.e. that it should receive traffic. Unlike /health,
+// which only reports that the process is alive.
+#[tracing::instrument(level = "debug")]
+async fn ready<M: ConnectionManager + Send + Sync + Debug + 'static>(
+    req: Request<Body>,
+) -> Result<Response<Body>, ApplicationError> {
+    let serving_readiness = req
+        .data::<ServingReadiness>()
+        .expect("serving readiness state");
+
+    if !serving_readiness.is_ready() {
+        if serving_readiness.phase() == Some(StartupPhase::ReplayingWriteBuffer) {
+            let server = req.data::<Arc<AppServer<M>>>().expect("server state");
+            let remaining: u64 = server
+                .write_buffer_replay()
+                .iter()
+                .map(|partition| partition.remaining())
+                .sum();
+            return StartingUp {
+                status: format!(
+                    "{}, {} messages left",
+                    StartupPhase::ReplayingWriteBuffer,
+                    remaining
+                ),
+            }
+            .fail();
+        }
+        return ServiceNotReady.fail();
+    }
+
+    // a draining server is taken out of load balancing
+    let server = req.data::<Arc<AppServer<M>>>().expect("server state");
+    if server.drain_status().is_some() {
+        return ServerDraining.fail();
+    }
+
+    let response_body = "OK";
+    Ok(Response::new(Body::from(response_body.to_string())))
+}
+
+#[derive(Deserialize, Debug)]
+/// Arguments in the query string of the request to /partitions
+struct DatabaseInfo {
+    org: String,
+    bucket: String,
+}
+
+#[tracing::instrument(level = "debug")]
+async fn list_partitions<M: ConnectionManager + Send + Sync + Debug + 'static>(
+    req: Request<Body>,
+) -> Result<Response<Body>, ApplicationError> {
+    let server = Arc::clone(&req.data::<Arc<AppServer<M>>>().expect("server state"));
+    let query = req.uri().query().context(ExpectedQueryString {})?;
+
+    let info: DatabaseInfo = serde_urlencoded::from_str(query).context(InvalidQueryString {
+        query_string: query,
+    })?;
+
+    access(&req)
+        .authorize(Scope::Read, Some(&info.org), Some(&info.bucket))
+        .context(ApiAuthorization)?;
+
+    let db_name =
+        org_and_bucket_to_database(&info.org, &info.bucket).context(BucketMappingError)?;
+
+    let db = server.db(&db_name).await.context(BucketNotFound {
+        org: &info.org,
+        bucket: &info.bucket,
+    })?;
+
+    let partition_keys =
+        db.partition_keys()
+            .map_err(|e| Box::new(e) as _)
+            .context(BucketByName {
+                org: &info.org,
+                bucket_name: &info.bucket,
+            })?;
+
+    let result = serde_json::to_string(&partition_keys).context(JsonGenerationError)?;
+
+    Ok(Response::new(Body::from(result)))
+}
+
+#[derive(Deserialize, Debug)]
+/// Arguments in the query string of the request to /snapshot
+struct SnapshotInfo {
+    org: String,
+    bucket: String,
+    partition: String,
+}
+
+#[tracing::instrument(level = "debug")]
+async fn snapshot_partition<M: ConnectionManager + Send + Sync + Debug + 'static>(
+    req: Request<Body>,
+) -> Result<Response<Body>, ApplicationError> {
+    authorize_admin(&req)?;
+
+    let server = Arc::clone(&req.data::<Arc<AppServer<M>>>().expect("server state"));
+    let query = req.uri().query().context(ExpectedQueryString {})?;
+
+    let snapshot: SnapshotInfo = serde_urlencoded::from_str(query).context(InvalidQueryString {
+        query_string: query,
+    })?;
+
+    let db_name =
+        org_and_bucket_to_database(&snapshot.org, &snapshot.bucket).context(BucketMappingError)?;
+
+    let partition_key = &snapshot.partition;
+    let snapshot = server
+        .snapshot_partition(&db_name, partition_key)
+        .await
+        .context(PersistingPartition {
+            org: &snapshot.org,
+            bucket: &snapshot.bucket,
+            partition: partition_key,
+        })?;
+
+    let ret = format!("{}", snapshot.id);
+    Ok(Response::new(Body::from(ret)))
+}
+
+/// Checks that the request is authorized to use the admin endpoints
+fn authorize_admin(req: &Request<Body>) -> Result<(), ApplicationError> {
+    req.data::<AdminToken>()
+        .expect("admin token")
+        .authorize(req.headers())
+        .context(AdminAuthorization)
+}
+
+#[derive(Serialize, Debug)]
+/// Body of the response to GET /api/v2/partitions
+struct ListPartitionStatusesResponse {
+    partitions: Vec<PartitionStatus>,
+}
+
+#[tracing::instrument(level = "debug")]
+async fn list_partition_statuses<M: ConnectionManager + Send + Sync + Debug + 'static>(
+    req: Request<Body>,
+) -> Result<Response<Body>, ApplicationError> {
+    authorize_admin(&req)?;
+
+    let server = Arc::clone(&req.data::<Arc<AppServer<M>>>().expect("server state"));
+    let query = req.uri().query().context(ExpectedQueryString {})?;
+    let info: DatabaseInfo = serde_urlencoded::from_str(query).context(InvalidQueryString {
+        query_string: query,
+    })?;
+
+    request_logging::span(&req).record_org_and_bucket(&info.org, &info.bucket);
+
+    let db_name =
+        org_and_bucket_to_database(&info.org, &info.bucket).context(BucketMappingError)?;
+
+    let partitions = server
+        .partition_statuses(&db_name)
+        .await
+        .context(GettingPartitionStatus {
+            org: &info.org,
+            bucket: &info.bucket,
+        })?;
+
+    let json = serde_json::to_string(&ListPartitionStatusesResponse { partitions })
+        .context(JsonGenerationError)?;
+    Response::builder()
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .context(CreatingResponse)
+}
+
+#[derive(Serialize, Debug)]
+/// Body of the response to /api/v2/partitions/:partition/persist
+struct PersistResponse {
+    /// The id of the snapshot writing the partition's chunk
+    id: String,
+    partition: String,
+}
+
+#[tracing::instrument(level = "debug")]
+async fn persist_partition<M: ConnectionManager + Send + Sync + Debug + 'static>(
+    req: Request<Body>,
+) -> Result<Response<Body>, ApplicationError> {
+    authorize_admin(&req)?;
+
+    let server = Arc::clone(&req.data::<Arc<AppServer<M>>>().expect("server state"));
+    let query = req.uri().query().context(ExpectedQueryString {})?;
+    let info: DatabaseInfo = serde_urlencoded::from_str(query).context(InvalidQueryString {
+        query_string: query,
+    })?;
+
+    // with routerify, we shouldn't have gotten here without this being set
+    let partition = req
+        .param("partition")
+        .expect("partition key must have been set")
+        .clone();
+
+    request_logging::span(&req).record_org_and_bucket(&info.org, &info.bucket);
+
+    let db_name =
+        org_and_bucket_to_database(&info.org, &info.bucket).context(BucketMappingError)?;
+
+    let snapshot = server
+        .snapshot_partition(&db_name, &partition)
+        .await
+        .context(PersistingPartition {
+            org: &info.org,
+            bucket: &info.bucket,
+            partition: &partition,
+        })?;
+
+    let json = serde_json::to_string(&PersistResponse {
+        id: snapshot.id.to_string(),
+        partition,
+    })
+    .context(JsonGenerationError)?;
+    Response::builder()
+        .status(StatusCode::ACCEPTED)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .context(CreatingResponse)
+}
+
+#[derive(Deserialize, Debug)]
+/// Arguments in the query string of the request to start a compaction
+struct CompactionInfo {
+    org: String,
+    bucket: String,
+    /// The partition to compact. All partitions are compacted if not set.
+    partition: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+/// Body of the response to GET /api/v2/compactions
+struct ListCompactionsResponse {
+    compactions: Vec<CompactionStatus>,
+}
+
+fn compaction_json(
+    status: &CompactionStatus,
+    code: StatusCode,
+) -> Result<Response<Body>, ApplicationError> {
+    let json = serde_json::to_string(status).context(JsonGenerationError)?;
+    Response::builder()
+        .status(code)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .context(CreatingResponse)
+}
+
+#[tracing::instrument(level = "debug")]
+async fn start_compaction<M: ConnectionManager + Send + Sync + Debug + 'static>(
+    req: Request<Body>,
+) -> Result<Response<Body>, ApplicationError> {
+    authorize_admin(&req)?;
+
+    let server = Arc::clone(&req.data::<Arc<AppServer<M>>>().expect("server state"));
+    let query = req.uri().query().context(ExpectedQueryString {})?;
+    let info: CompactionInfo = serde_urlencoded::from_str(query).context(InvalidQueryString {
+        query_string: query,
+    })?;
+
+    request_logging::span(&req).record_org_and_bucket(&info.org, &info.bucket);
+
+    let db_name =
+        org_and_bucket_to_database(&info.org, &info.bucket).context(BucketMappingError)?;
+
+    let status = server
+        .compact(&db_name, info.partition.as_deref())
+        .await
+        .context(StartingCompaction {
+            org: &info.org,
+            bucket: &info.bucket,
+        })?;
+
+    compaction_json(&status, StatusCode::ACCEPTED)
+}
+
+#[tracing::instrument(level = "debug")]
+async fn list_compactions<M: ConnectionManager + Send + Sync + Debug + 'static>(
+    req: Request<Body>,
+) -> Result<Response<Body>, ApplicationError> {
+    authorize_admin(&req)?;
+
+    let server = Arc::clone(&req.data::<Arc<AppServer<M>>>().expect("server state"));
+    let response = ListCompactionsResponse {
+        compactions: server.compactions(),
+    };
+    let json = serde_json::to_string(&response).context(JsonGenerationError)?;
+
+    Response::builder()
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .context(CreatingResponse)
+}
+
+#[tracing::instrument(level = "debug")]
+async fn get_compaction<M: ConnectionManager + Send + Sync + Debug + 'static>(
+    req: Request<Body>,
+) -> Result<Response<Body>, ApplicationError> {
+    authorize_admin(&req)?;
+
+    let server = Arc::clone(&req.data::<Arc<AppServer<M>>>().expect("server state"));
+
+    // with routerify, we shouldn't have gotten here without this being set
+    let id = req.param("id").expect("compaction id must have been set");
+    let status = id
+        .parse()
+        .ok()
+        .and_then(|id| server.compaction(id))
+        .context(CompactionNotFound { id })?;
+
+    compaction_json(&status, StatusCode::OK)
+}
+
+#[derive(Serialize, Debug)]
+/// Body of the response to GET /cluster/members
+struct ListClusterMembersResponse {
+    members: Vec<Member>,
+}
+
+#[tracing::instrument(level = "debug")]
+async fn list_cluster_members<M: ConnectionManager + Send + Sync + Debug + 'static>(
+    req: Request<Body>,
+) -> Result<Response<Body>, ApplicationError> {
+    authorize_admin(&req)?;
+
+    let server = Arc::clone(&req.data::<Arc<AppServer<M>>>().expect("server state"));
+    let membership = server.membership().context(ClusterDisabled)?;
+    let response = ListClusterMembersResponse {
+        members: membership.members(),
+    };
+    let json = serde_json::to_string(&response).context(JsonGenerationError)?;
+
+    Response::builder()
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .context(CreatingResponse)
+}
+
+fn drain_json(status: &DrainStatus, code: StatusCode) -> Result<Response<Body>, ApplicationError> {
+    let json = serde_json::to_string(status).context(JsonGenerationError)?;
+    Response::builder()
+        .status(code)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .context(CreatingResponse)
+}
+
+/// Starts draining the server in the background, unless it already is.
+/// The status of a drain started is returned with `202 Accepted`, that of
+/// a drain already running or done with `200 OK`.
+#[tracing::instrument(level = "debug")]
+async fn start_drain<M: ConnectionManager + Send + Sync + Debug + 'static>(
+    req: Request<Body>,
+) -> Result<Response<Body>, ApplicationError> {
+    authorize_admin(&req)?;
+
+    let server = Arc::clone(&req.data::<Arc<AppServer<M>>>().expect("server state"));
+    match server.start_drain() {
+        Some(status) => {
+            tokio::spawn(async move {
+                // errors are logged and reported in the status of the drain
+                let _ = server.drain().await;
+            });
+            drain_json(&status, StatusCode::ACCEPTED)
+        }
+        None => {
+            let status = server.drain_status().context(NotDraining)?;
+            drain_json(&status, StatusCode::OK)
+        }
+    }
+}
+
+#[tracing::instrument(level = "debug")]
+async fn get_drain<M: ConnectionManager + Send + Sync + Debug + 'static>(
+    req: Request<Body>,
+) -> Result<Response<Body>, ApplicationError> {
+    authorize_admin(&req)?;
+
+    let server = Arc::clone(&req.data::<Arc<AppServer<M>>>().expect("server state"));
+    let status = server.drain_status().context(NotDraining)?;
+    drain_json(&status, StatusCode::OK)
+}
+
+#[derive(Serialize, Debug)]
+/// A shard, as returned by GET /cluster/shards and /cluster/shards/:id
+struct ShardResponse {
+    /// The id of the shard, in hexadecimal
+    id: String,
+    /// The hash the range of the shard starts after, in hexadecimal
+    start: String,
+    owner: String,
+    moved: bool,
+    /// The last move of the shard, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_move: Option<ShardMove>,
+}
+
+impl ShardResponse {
+    fn new(shard: Shard, last_move: Option<ShardMove>) -> Self {
+        Self {
+            id: format!("{:016x}", shard.id()),
+            start: format!("{:016x}", shard.range.start),
+            owner: shard.owner,
+            moved: shard.moved,
+            last_move,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+/// Body of the response to GET /cluster/shards
+struct ListShardsResponse {
+    shards: Vec<ShardResponse>,
+    /// The last move of every shard moved since the server started
+    moves: Vec<ShardMove>,
+}
+
+#[tracing::instrument(level = "debug")]
+async fn list_shards<M: ConnectionManager + Send + Sync + Debug + 'static>(
+    req: Request<Body>,
+) -> Result<Response<Body>, ApplicationError> {
+    authorize_admin(&req)?;
+
+    let server = Arc::clone(&req.data::<Arc<AppServer<M>>>().expect("server state"));
+    let router = server.shard_router().context(RoutingDisabled)?;
+    let response = ListShardsResponse {
+        shards: router
+            .shards()
+            .into_iter()
+            .map(|shard| ShardResponse::new(shard, None))
+            .collect(),
+        moves: server.shard_moves(),
+    };
+    let json = serde_json::to_string(&response).context(JsonGenerationError)?;
+
+    Response::builder()
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .context(CreatingResponse)
+}
+
+/// Returns the id of the shard in the path of `req`, along with its
+/// hexadecimal form
+fn shard_id(req: &Request<Body>) -> Result<(u64, String), ApplicationError> {
+    // with routerify, we shouldn't have gotten here without this being set
+    let id = req.param("id").expect("shard id must have been set");
+    match u64::from_str_radix(id, 16) {
+        Ok(shard) => Ok((shard, id.clone())),
+        Err(_) => ShardNotFound { id }.fail(),
+    }
+}
+
+#[tracing::instrument(level = "debug")]
+async fn get_shard<M: ConnectionManager + Send + Sync + Debug + 'static>(
+    req: Request<Body>,
+) -> Result<Response<Body>, ApplicationError> {
+    authorize_admin(&req)?;
+
+    let server = Arc::clone(&req.data::<Arc<AppServer<M>>>().expect("server state"));
+    let router = server.shard_router().context(RoutingDisabled)?;
+    let (shard, id) = shard_id(&req)?;
+    let found = router.shard(shard).ok().context(ShardNotFound { id })?;
+    let response = ShardResponse::new(found, server.shard_move(shard));
+    let json = serde_json::to_string(&response).context(JsonGenerationError)?;
+
+    Response::builder()
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .context(CreatingResponse)
+}
+
+#[derive(Deserialize, Debug)]
+/// Body of a request to POST /cluster/shards/:id/move
+struct MoveShardRequest {
+    /// The backend the shard is moved to
+    to: String,
+}
+
+/// Starts moving a shard to another backend in the background, returning
+/// the status of the move with `202 Accepted`
+#[tracing::instrument(level = "debug")]
+async fn start_shard_move<M: ConnectionManager + Send + Sync + Debug + 'static>(
+    req: Request<Body>,
+) -> Result<Response<Body>, ApplicationError> {
+    authorize_admin(&req)?;
+
+    let server = Arc::clone(&req.data::<Arc<AppServer<M>>>().expect("server state"));
+    server.shard_router().context(RoutingDisabled)?;
+    let (shard, id) = shard_id(&req)?;
+    let body = parse_body(req).await?;
+    let MoveShardRequest { to } =
+        serde_json::from_slice(body.as_ref()).context(InvalidRequestBody)?;
+
+    let status = server
+        .start_shard_move(shard, &to)
+        .context(MovingShard { id })?;
+    tokio::spawn(async move {
+        // errors are logged and reported in the status of the move
+        let _ = server.move_shard(shard).await;
+    });
+
+    let json = serde_json::to_string(&status).context(JsonGenerationError)?;
+    Response::builder()
+        .status(StatusCode::ACCEPTED)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .context(CreatingResponse)
+}
+
+/// Returns a builder that creates the service handling the HTTP requests
+/// of a connection, given the connection's remote address. Wrap the
+/// services in a `LoggedService` to log each request.
+pub fn request_service_builder<M: ConnectionManager + Send + Sync + Debug + 'static>(
+    server: Arc<AppServer<M>>,
+    serving_readiness: ServingReadiness,
+    options: HttpOptions,
+) -> RequestServiceBuilder<Body, ApplicationError> {
+    let router = router(server, serving_readiness, options);
+    RequestServiceBuilder::new(router).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+    use arrow_deps::{arrow::record_batch::RecordBatch, assert_table_eq};
+    use query::exec::Executor;
+    use reqwest::{Client, Response};
+
+    use hyper::{server::conn::AddrStream, service::make_service_fn, Server};
+
+    use data_types::{
+        database_rules::{DatabaseRules, WalBufferConfig, WalBufferRollover},
+        wal::WriterSummary,
+        DatabaseName,
+    };
+    use object_store::{memory::InMemory, ObjectStore};
+    use serde::de::DeserializeOwned;
+    use server::{
+        cluster::{MemberState, Membership, OnQuorumLoss, Quorum, Role},
+        db::Db,
+        router::ShardRouter,
+        ConnectionManagerImpl,
+    };
+
+    use crate::influxdb_ioxd::auth::{ApiTokens, TokenStore};
+
+    type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
+    type Result<T, E = Error> = std::result::Result<T, E>;
+
+    #[tokio::test]
+    async fn test_ping() -> Result<()> {
+        let test_storage = Arc::new(AppServer::new(
+            ConnectionManagerImpl::default(),
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        ));
+        let server_url = test_server(Arc::clone(&test_storage));
+
+        let client = Client::new();
+        let response = client.get(&format!("{}/ping", server_url)).send().await?;
+        assert_eq!(
+            response.headers().get("X-Influxdb-Version").unwrap(),
+            build_info::VERSION
+        );
+
+        // Print the response so if the test fails, we have a log of what went wrong
+        check_response("ping", Ok(response), StatusCode::NO_CONTENT, "").await;
+
+        // InfluxDB 1.x clients may also probe with HEAD
+        let response = client.head(&format!("{}/ping", server_url)).send().await?;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(
+            response.headers().get("X-Influxdb-Version").unwrap(),
+            build_info::VERSION
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_http2() -> Result<()> {
+        let test_storage = Arc::new(AppServer::new(
+            ConnectionManagerImpl::default(),
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        ));
+        let server_url = test_server(Arc::clone(&test_storage));
+
+        let client = Client::builder().http2_prior_knowledge().build()?;
+        let response = client.get(&format!("{}/ping", server_url)).send().await?;
+        assert_eq!(response.version(), http::Version::HTTP_2);
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_build_info() -> Result<()> {
+        let test_storage = Arc::new(AppServer::new(
+            ConnectionManagerImpl::default(),
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        ));
+        let server_url = test_server(Arc::clone(&test_storage));
+
+        let client = Client::new();
+        let response = client
+            .get(&format!("{}/debug/build", server_url))
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = response.json().await?;
+        assert_eq!(body["version"], build_info::VERSION);
+        assert_eq!(body["git_hash"], build_info::GIT_HASH);
+        assert!(body["rustc_version"].is_string());
+        assert!(body["features"].is_array());
+
+        // responses identify the build
+        let response = client.get(&format!("{}/health", server_url)).send().await?;
+        let headers = response.headers();
+        assert_eq!(headers["X-Influxdb-Version"], build_info::VERSION);
+        assert_eq!(headers["X-Influxdb-Build"], "IOx");
+        assert_eq!(headers["X-Iox-Git-Hash"], build_info::GIT_HASH);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_metrics() -> Result<()> {
+        let test_storage = Arc::new(AppServer::new(
+            ConnectionManagerImpl::default(),
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        ));
+        let server_url = test_server(Arc::clone(&test_storage));
+
+        query::pruning::METRICS.record(2, 1);
+        let response = Client::new()
+            .get(&format!("{}/metrics", server_url))
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.text().await?;
+        assert!(
+            body.contains("# TYPE iox_query_chunks_pruned_total counter\n"),
+            "{}",
+            body
+        );
+        assert!(
+            body.contains("\niox_query_chunks_pruned_total "),
+            "{}",
+            body
+        );
+        assert!(
+            body.contains("\niox_background_jobs_running 0\n"),
+            "{}",
+            body
+        );
+        assert!(
+            body.contains("\niox_background_io_file_ops 0\n"),
+            "{}",
+            body
+        );
+        assert!(
+            body.contains("\niox_memory_used_bytes{subsystem=\"mutable_buffer\"} 0\n"),
+            "{}",
+            body
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_health() -> Result<()> {
+        let test_storage = Arc::new(AppServer::new(
+            ConnectionManagerImpl::default(),
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        ));
+        let server_url = test_server(Arc::clone(&test_storage));
+
+        let client = Client::new();
+        let response = client.get(&format!("{}/health", server_url)).send().await;
+
+        // Print the response so if the test fails, we have a log of what went wrong
+        check_response("health", response, StatusCode::OK, "OK").await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_ready() -> Result<()> {
+        let test_storage = Arc::new(AppServer::new(
+            ConnectionManagerImpl::default(),
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        ));
+        let serving_readiness = ServingReadiness::new();
+        let server_url =
+            test_server_with_readiness(Arc::clone(&test_storage), serving_readiness.clone());
+
+        let client = Client::new();
+        let response = client.get(&format!("{}/ready", server_url)).send().await;
+        check_response(
+            "ready",
+            response,
+            StatusCode::SERVICE_UNAVAILABLE,
+            r#"{"code":"unavailable","message":"Server is not ready to serve requests","error_code":100}"#,
+        )
+        .await;
+
+        // liveness is reported regardless of readiness
+        let response = client.get(&format!("{}/health", server_url)).send().await;
+        check_response("health", response, StatusCode::OK, "OK").await;
+
+        // replaying the write buffer reports its progress
+        serving_readiness.start_phase(StartupPhase::ReplayingWriteBuffer);
+        let response = client.get(&format!("{}/ready", server_url)).send().await;
+        check_response(
+            "ready",
+            response,
+            StatusCode::SERVICE_UNAVAILABLE,
+            r#"{"code":"unavailable","message":"Server is starting up: replaying write buffer, 0 messages left","error_code":100}"#,
+        )
+        .await;
+
+        serving_readiness.set_ready(true);
+        let response = client.get(&format!("{}/ready", server_url)).send().await;
+        check_response("ready", response, StatusCode::OK, "OK").await;
+
+        // e.g. when shutting down
+        serving_readiness.set_ready(false);
+        let response = client.get(&format!("{}/ready", server_url)).send().await;
+        assert_eq!(response?.status(), StatusCode::SERVICE_UNAVAILABLE);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_endpoints() -> Result<()> {
+        let client = Client::new();
+
+        for (endpoints, data_status, admin_status) in &[
+            (
+                Endpoints::Data,
+                StatusCode::NO_CONTENT,
+                StatusCode::NOT_FOUND,
+            ),
+            (Endpoints::Admin, StatusCode::NOT_FOUND, StatusCode::OK),
+        ] {
+            let serving_readiness = ServingReadiness::new();
+            serving_readiness.set_ready(true);
+            let options = HttpOptions {
+                endpoints: *endpoints,
+                ..Default::default()
+            };
+            let server_url = test_server_with_options(
+                Arc::new(AppServer::new(
+                    ConnectionManagerImpl::default(),
+                    Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+                )),
+                serving_readiness,
+                options,
+            );
+
+            let response = client.get(&format!("{}/ping", server_url)).send().await?;
+            assert_eq!(response.status(), *data_status, "{:?}", endpoints);
+
+            let response = client.get(&format!("{}/health", server_url)).send().await?;
+            assert_eq!(response.status(), *admin_status, "{:?}", endpoints);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_request_id() -> Result<()> {
+        let test_storage = Arc::new(AppServer::new(
+            ConnectionManagerImpl::default(),
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        ));
+        let server_url = test_server(Arc::clone(&test_storage));
+        let client = Client::new();
+
+        let response = client
+            .get(&format!("{}/ping", server_url))
+            .header("X-Request-Id", "my-request")
+            .send()
+            .await?;
+        assert_eq!(response.headers()["X-Request-Id"], "my-request");
+
+        // error responses carry the id too, generated if the client sent none
+        let response = client
+            .get(&format!(
+                "{}/iox/api/v1/databases/MyOrg_MyBucket/query",
+                server_url
+            ))
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(response.headers()["X-Request-Id"].len(), 36);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_openapi() -> Result<()> {
+        let client = Client::new();
+
+        for endpoints in &[Endpoints::Data, Endpoints::Admin] {
+            let serving_readiness = ServingReadiness::new();
+            serving_readiness.set_ready(true);
+            let options = HttpOptions {
+                endpoints: *endpoints,
+                ..Default::default()
+            };
+            let server_url = test_server_with_options(
+                Arc::new(AppServer::new(
+                    ConnectionManagerImpl::default(),
+                    Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+                )),
+                serving_readiness,
+                options,
+            );
+
+            let document: serde_json::Value = client
+                .get(&format!("{}/api/openapi.json", server_url))
+                .send()
+                .await?
+                .json()
+                .await?;
+
+            // every route is described on the listeners serving it, and only
+            // those
+            for route in openapi::ROUTES {
+                let served = route.served_by(*endpoints);
+                let path = route.path.replace(':', "");
+                let method = Method::from_bytes(route.method.to_uppercase().as_bytes())?;
+                let response = client
+                    .request(method, &format!("{}{}", server_url, path))
+                    .send()
+                    .await?;
+                let body = response.text().await?;
+                assert_eq!(
+                    !body.contains("No handler for"),
+                    served,
+                    "{} {} on {:?}: {}",
+                    route.method,
+                    route.path,
+                    endpoints,
+                    body
+                );
+
+                let described = document["paths"].as_object().unwrap().iter().any(
+                    |(described_path, operations)| {
+                        operations[route.method].is_object()
+                            && described_path.replace(|c| c == '{' || c == '}', "") == path
+                    },
+                );
+                assert_eq!(described, served, "{} {}", route.method, route.path);
+            }
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write() -> Result<()> {
+        let test_storage = Arc::new(AppServer::new(
+            ConnectionManagerImpl::default(),
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        ));
+        test_storage.set_id(1);
+        test_storage
+            .create_database("MyOrg_MyBucket", DatabaseRules::new())
+            .await
+            .unwrap();
+        let server_url = test_server(Arc::clone(&test_storage));
+
+        let client = Client::new();
+
+        let lp_data = "h2o_temperature,location=santa_monica,state=CA surface_degrees=65.2,bottom_degrees=50.4 1568756160";
+
+        // send write data
+        let bucket_name = "MyBucket";
+        let org_name = "MyOrg";
+        let response = client
+            .post(&format!(
+                "{}/api/v2/write?bucket={}&org={}",
+                server_url, bucket_name, org_name
+            ))
+            .body(lp_data)
+            .send()
+            .await;
+
+        check_response("write", response, StatusCode::NO_CONTENT, "").await;
+
+        // Check that the data got into the right bucket
+        let test_db = test_storage
+            .db(&DatabaseName::new("MyOrg_MyBucket").unwrap())
+            .await
+            .expect("Database exists");
+
+        let batches = run_query(test_db.as_ref(), "select * from h2o_temperature").await;
+        let expected = vec![
+            "+----------------+--------------+-------+-----------------+------------+",
+            "| bottom_degrees | location     | state | surface_degrees | time       |",
+            "+----------------+--------------+-------+-----------------+------------+",
+            "| 50.4           | santa_monica | CA    | 65.2            | 1568756160 |",
+            "+----------------+--------------+-------+-----------------+------------+",
+        ];
+        assert_table_eq!(expected, &batches);
+
+        Ok(())
+    }
+
+    /// Sets up a test database with some data for testing the query endpoint
+    /// returns a client for communicting with the server, and the server
+    /// endpoint
+    async fn setup_test_data() -> (Client, String) {
+        let test_storage: Arc<AppServer<ConnectionManagerImpl>> = Arc::new(AppServer::new(
+            ConnectionManagerImpl::default(),
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        ));
+        test_storage.set_id(1);
+        test_storage
+            .create_database("MyOrg_MyBucket", DatabaseRules::new())
+            .await
+            .unwrap();
+        let server_url = test_server(Arc::clone(&test_storage));
+
+        let client = Client::new();
+
+        let lp_data = "h2o_temperature,location=santa_monica,state=CA surface_degrees=65.2,bottom_degrees=50.4 1568756160";
+
+        // send write data
+        let bucket_name = "MyBucket";
+        let org_name = "MyOrg";
+        let response = client
+            .post(&format!(
+                "{}/api/v2/write?bucket={}&org={}",
+                server_url, bucket_name, org_name
+            ))
+            .body(lp_data)
+            .send()
+            .await;
+
+        check_response("write", response, StatusCode::NO_CONTENT, "").await;
+        (client, server_url)
+    }
+
+    #[tokio::test]
+    async fn test_write_error() -> Result<()> {
+        let test_storage = Arc::new(AppServer::new(
+            ConnectionManagerImpl::default(),
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        ));
+        test_storage.set_id(1);
+        test_storage
+            .create_database("MyOrg_MyBucket", DatabaseRules::new())
+            .await
+            .unwrap();
+        let server_url = test_server(Arc::clone(&test_storage));
+
+        let client = Client::new();
+        let response = client
+            .post(&format!(
+                "{}/api/v2/write?bucket=MyBucket&org=MyOrg",
+                server_url
+            ))
+            .body("h2o,state=CA temp=65.2 1568756160\n\nh2o,state=CA temp=")
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(response.headers()[CONTENT_TYPE], "application/json");
+
+        let body: serde_json::Value = response.json().await?;
+        assert_eq!(body["code"], "invalid");
+        assert_eq!(body["line"], 3);
+        let message = body["message"].as_str().unwrap();
+        assert!(
+            message.starts_with("Error parsing line 3 of line protocol"),
+            "{}",
+            message
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_route_not_found() -> Result<()> {
+        let test_storage = Arc::new(AppServer::new(
+            ConnectionManagerImpl::default(),
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        ));
+        let server_url = test_server(Arc::clone(&test_storage));
+
+        let client = Client::new();
+        let response = client
+            .get(&format!("{}/api/v2/nothing", server_url))
+            .send()
+            .await?;
+        assert_eq!(response.headers()[CONTENT_TYPE], "application/json");
+        check_response(
+            "not found",
+            Ok(response),
+            StatusCode::NOT_FOUND,
+            r#"{"code":"not found","message":"No handler for GET /api/v2/nothing","error_code":100}"#,
+        )
+        .await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_pretty() -> Result<()> {
+        let (client, server_url) = setup_test_data().await;
+
+        // send query data
+        let response = client
+            .get(&format!(
+                "{}/iox/api/v1/databases/MyOrg_MyBucket/query?q={}",
+                server_url, "select%20*%20from%20h2o_temperature"
+            ))
+            .send()
+            .await;
+
+        assert_eq!(get_content_type(&response), "text/plain");
+
+        let res = "+----------------+--------------+-------+-----------------+------------+\n\
+                   | bottom_degrees | location     | state | surface_degrees | time       |\n\
+                   +----------------+--------------+-------+-----------------+------------+\n\
+                   | 50.4           | santa_monica | CA    | 65.2            | 1568756160 |\n\
+                   +----------------+--------------+-------+-----------------+------------+\n";
+        check_response("query", response, StatusCode::OK, res).await;
+
+        // same response is expected if we explicitly request 'format=pretty'
+        let response = client
+            .get(&format!(
+                "{}/iox/api/v1/databases/MyOrg_MyBucket/query?q={}&format=pretty",
+                server_url, "select%20*%20from%20h2o_temperature"
+            ))
+            .send()
+            .await;
+        assert_eq!(get_content_type(&response), "text/plain");
+
+        check_response("query", response, StatusCode::OK, res).await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_csv() -> Result<()> {
+        let (client, server_url) = setup_test_data().await;
+
+        // send query data
+        let response = client
+            .get(&format!(
+                "{}/iox/api/v1/databases/MyOrg_MyBucket/query?q={}&format=csv",
+                server_url, "select%20*%20from%20h2o_temperature"
+            ))
+            .send()
+            .await;
+
+        assert_eq!(get_content_type(&response), "text/csv");
+
+        let res = "bottom_degrees,location,state,surface_degrees,time\n\
+                   50.4,santa_monica,CA,65.2,1568756160\n";
+        check_response("query", response, StatusCode::OK, res).await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read() -> Result<()> {
+        let test_storage = Arc::new(AppServer::new(
+            ConnectionManagerImpl::default(),
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        ));
+        test_storage.set_id(1);
+        test_storage
+            .create_database("MyOrg_MyBucket", DatabaseRules::new())
+            .await
+            .unwrap();
+        let server_url = test_server(Arc::clone(&test_storage));
+        let client = Client::new();
+
+        let recent = chrono::Utc::now().timestamp_nanos() - 1_000_000_000;
+        let old = recent - 2 * 3600 * 1_000_000_000;
+        let lp_data = format!(
+            "cpu,host=a,region=west usage=0.5 {recent}\n\
+             cpu,host=b,region=west usage=0.7 {recent}\n\
+             cpu,host=a,region=west usage=0.9 {old}\n\
+             mem,host=a,region=west free=10i {recent}",
+            recent = recent,
+            old = old
+        );
+        let response = client
+            .post(&format!(
+                "{}/api/v2/write?bucket=MyBucket&org=MyOrg",
+                server_url
+            ))
+            .body(lp_data)
+            .send()
+            .await;
+        check_response("write", response, StatusCode::NO_CONTENT, "").await;
+
+        let read = |params: &str| {
+            client
+                .get(&format!(
+                    "{}/api/v2/read?org=MyOrg&bucket=MyBucket&{}",
+                    server_url, params
+                ))
+                .send()
+        };
+
+        let response = read("start=-1h&predicate=_measurement%3D%22cpu%22").await;
+        assert_eq!(get_content_type(&response), "text/csv");
+        let expected = format!(
+            "_measurement,host,region,_field,_time,_value\n\
+             cpu,a,west,usage,{recent},0.5\n\
+             \n\
+             _measurement,host,region,_field,_time,_value\n\
+             cpu,b,west,usage,{recent},0.7\n",
+            recent = recent
+        );
+        check_response("read", response, StatusCode::OK, &expected).await;
+
+        // host!~/a/
+        let response =
+            read("start=-1h&predicate=_measurement%3D%22cpu%22%20AND%20host!~%2Fa%2F").await;
+        let expected = format!(
+            "_measurement,host,region,_field,_time,_value\n\
+             cpu,b,west,usage,{recent},0.7\n",
+            recent = recent
+        );
+        check_response("read", response, StatusCode::OK, &expected).await;
+
+        // _measurement="cpu" AND NOT (host="a" OR region="east")
+        let response = read(
+            "start=-1h&predicate=_measurement%3D%22cpu%22%20AND%20NOT%20%28host%3D%22a%22%20OR%20\
+             region%3D%22east%22%29",
+        )
+        .await;
+        check_response("read", response, StatusCode::OK, &expected).await;
+
+        // usage * 2 > 1
+        let response = read("start=-1h&predicate=usage%20%2A%202%20%3E%201").await;
+        check_response("read", response, StatusCode::OK, &expected).await;
+
+        // the old points, moved to line up with the recent ones
+        let response = read("start=-1h&predicate=_measurement%3D%22cpu%22&shift=2h").await;
+        let expected = format!(
+            "_measurement,host,region,_field,_time,_value\n\
+             cpu,a,west,usage,{recent},0.5\n\
+             \n\
+             _measurement,host,region,_field,_time,_value\n\
+             cpu,b,west,usage,{recent},0.7\n\
+             \n\
+             _measurement,_shift,host,region,_field,_time,_value\n\
+             cpu,2h,a,west,usage,{recent},0.9\n",
+            recent = recent
+        );
+        check_response("read", response, StatusCode::OK, &expected).await;
+
+        // fields computed as the rows are read
+        let response =
+            read("start=-1h&predicate=_measurement%3D%22cpu%22&fields=pct%3Dusage*100").await;
+        let expected = format!(
+            "_measurement,host,region,_field,_time,_value\n\
+             cpu,a,west,pct,{recent},50\n\
+             \n\
+             _measurement,host,region,_field,_time,_value\n\
+             cpu,b,west,pct,{recent},70\n",
+            recent = recent
+        );
+        check_response("read", response, StatusCode::OK, &expected).await;
+
+        let response = read("start=-1h&predicate=host%3D%22a%22&group_by=region&format=json")
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = response.json().await.unwrap();
+        let groups = body["groups"].as_array().unwrap();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0]["tags"], serde_json::json!({"region": "west"}));
+        let mut measurements: Vec<_> = groups[0]["series"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|series| series["measurement"].as_str().unwrap().to_string())
+            .collect();
+        measurements.sort();
+        assert_eq!(measurements, vec!["cpu", "mem"]);
+
+        // a window long enough for all the recent points to fall in it
+        let response = read("start=-1h&predicate=host%3D%22a%22&every=1000d&agg=sum&format=json")
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = response.json().await.unwrap();
+        let series = body["series"].as_array().unwrap();
+        assert_eq!(series.len(), 2);
+        assert_eq!(series[0]["measurement"], "cpu");
+        assert_eq!(series[0]["points"].as_array().unwrap().len(), 1);
+        assert_eq!(series[0]["points"][0][1], 0.5);
+
+        // the windows of the two hours since the old point, of which only
+        // the first and the last have points
+        let response = read(&format!(
+            "start={}&stop={}&predicate=host%3D%22a%22%20AND%20_measurement%3D%22cpu%22\
+             &every=1h&agg=max&fill=previous&format=json",
+            old,
+            old + 2 * 3600 * 1_000_000_000
+        ))
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = response.json().await.unwrap();
+        let points = body["series"][0]["points"].as_array().unwrap();
+        assert_eq!(points.len(), 3);
+        assert_eq!(points[0][1], 0.9);
+        assert_eq!(points[1][1], 0.9);
+
+        // the max of each window keeps its time
+        let response = read(&format!(
+            "start={}&stop={}&predicate=host%3D%22a%22%20AND%20_measurement%3D%22cpu%22\
+             &every=1000d&agg=max&format=json",
+            old, recent
+        ))
+        .await
+        .unwrap();
+        let body: serde_json::Value = response.json().await.unwrap();
+        let points = body["series"][0]["points"].as_array().unwrap();
+        assert_eq!(points.len(), 1);
+        assert_eq!(points[0], serde_json::json!([old, 0.9]));
+
+        // the last point of each series
+        let response = read("start=-3h&predicate=_measurement%3D%22cpu%22&agg=last&format=json")
+            .await
+            .unwrap();
+        let body: serde_json::Value = response.json().await.unwrap();
+        let series = body["series"].as_array().unwrap();
+        assert_eq!(series.len(), 2);
+        assert_eq!(series[0]["tags"]["host"], "a");
+        assert_eq!(series[0]["points"], serde_json::json!([[recent, 0.5]]));
+
+        // usage of host a went from 0.9 to 0.5 in two hours
+        let transformed = |transform: &str| {
+            read(&format!(
+                "start=-3h&predicate=host%3D%22a%22%20AND%20_measurement%3D%22cpu%22\
+                 &transform={}&unit=1h&format=json",
+                transform
+            ))
+        };
+        let body: serde_json::Value = transformed("derivative")
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(
+            body["series"][0]["points"],
+            serde_json::json!([[recent, -0.2]])
+        );
+        let body: serde_json::Value = transformed("non_negative_derivative")
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(body["series"], serde_json::json!([]));
+        let body: serde_json::Value = transformed("rate").await.unwrap().json().await.unwrap();
+        assert_eq!(
+            body["series"][0]["points"],
+            serde_json::json!([[recent, 0.25]])
+        );
+
+        // fields written at different times are aligned on time
+        let lp_data = format!(
+            "disk,host=a used=1i,free=9i {old}\n\
+             disk,host=a used=2i {recent}",
+            recent = recent,
+            old = old
+        );
+        let response = client
+            .post(&format!(
+                "{}/api/v2/write?bucket=MyBucket&org=MyOrg",
+                server_url
+            ))
+            .body(lp_data)
+            .send()
+            .await;
+        check_response("write", response, StatusCode::NO_CONTENT, "").await;
+
+        let response = read("start=-3h&predicate=_measurement%3D%22disk%22&pivot=true").await;
+        let expected = format!(
+            "_measurement,host,_time,free,used\n\
+             disk,a,{old},9,1\n\
+             disk,a,{recent},,2\n",
+            recent = recent,
+            old = old
+        );
+        check_response("read", response, StatusCode::OK, &expected).await;
+
+        let response = read("start=-1h&pivot=true&group_by=host").await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = read("start=-1h&agg=sum").await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = read("start=-1h&fill=null").await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        // host a has two points since -3h, the first of which is skipped
+        let response = read("start=-3h&predicate=_measurement%3D%22cpu%22&offset=1&format=json")
+            .await
+            .unwrap();
+        let body: serde_json::Value = response.json().await.unwrap();
+        let series = body["series"].as_array().unwrap();
+        assert_eq!(series.len(), 1);
+        assert_eq!(series[0]["tags"]["host"], "a");
+        assert_eq!(series[0]["points"][0][0], recent);
+
+        let response = read("start=-3h&predicate=_measurement%3D%22cpu%22&limit=1&format=json")
+            .await
+            .unwrap();
+        let body: serde_json::Value = response.json().await.unwrap();
+        let series = body["series"].as_array().unwrap();
+        assert_eq!(series.len(), 1);
+        assert_eq!(series[0]["points"].as_array().unwrap().len(), 1);
+
+        // the latest point of each cpu series
+        let response = read(
+            "start=-3h&predicate=_measurement%3D%22cpu%22&order=desc&series_limit=1&format=json",
+        )
+        .await
+        .unwrap();
+        let body: serde_json::Value = response.json().await.unwrap();
+        let series = body["series"].as_array().unwrap();
+        assert_eq!(series.len(), 2);
+        for series in series {
+            assert_eq!(series["points"].as_array().unwrap().len(), 1);
+            assert_eq!(series["points"][0][0], recent);
+        }
+
+        // an absolute range only covering the old point
+        let stop = chrono::TimeZone::timestamp_nanos(&chrono::Utc, old + 1)
+            .to_rfc3339_opts(chrono::SecondsFormat::Nanos, true);
+        let response = read(&format!(
+            "start={}&stop={}&predicate=_measurement%3D%22cpu%22",
+            old - 1,
+            stop
+        ))
+        .await;
+        let expected = format!(
+            "_measurement,host,region,_field,_time,_value\n\
+             cpu,a,west,usage,{old},0.9\n",
+            old = old
+        );
+        check_response("read", response, StatusCode::OK, &expected).await;
+
+        let response = read("start=1h").await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = read("start=-1h&stop=-2h").await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = read("start=-1h&every=1m").await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read_streams_csv() -> Result<()> {
+        let test_storage = Arc::new(AppServer::new(
+            ConnectionManagerImpl::default(),
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        ));
+        test_storage.set_id(1);
+        test_storage
+            .create_database("MyOrg_MyBucket", DatabaseRules::new())
+            .await
+            .unwrap();
+        let server_url = test_server(Arc::clone(&test_storage));
+        let client = Client::new();
+
+        let recent = chrono::Utc::now().timestamp_nanos() - 1_000_000_000;
+        let lp_data: Vec<_> = (0..2000)
+            .map(|i| format!("cpu,host=a usage={} {}", i, recent - i))
+            .collect();
+        let response = client
+            .post(&format!(
+                "{}/api/v2/write?bucket=MyBucket&org=MyOrg",
+                server_url
+            ))
+            .body(lp_data.join("\n"))
+            .send()
+            .await;
+        check_response("write", response, StatusCode::NO_CONTENT, "").await;
+
+        // the CSV is larger than a chunk, so it is streamed without a length
+        let response = client
+            .get(&format!(
+                "{}/api/v2/read?org=MyOrg&bucket=MyBucket&start=-1h",
+                server_url
+            ))
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(get_content_type(&response), "text/csv");
+        assert_eq!(response.content_length(), None);
+
+        let body = response.text().await?;
+        assert!(body.len() > CSV_CHUNK_SIZE);
+        let mut rows = body.lines();
+        assert_eq!(rows.next(), Some("_measurement,host,_field,_time,_value"));
+        assert_eq!(
+            rows.next(),
+            Some(format!("cpu,a,usage,{},1999", recent - 1999).as_str())
+        );
+        assert_eq!(rows.count(), 1999);
+
+        // and compressed as it is sent
+        let response = client
+            .get(&format!(
+                "{}/api/v2/read?org=MyOrg&bucket=MyBucket&start=-1h",
+                server_url
+            ))
+            .header(http::header::ACCEPT_ENCODING, "gzip")
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_ENCODING], "gzip");
+        let compressed = response.bytes().await?;
+        let mut decoded = String::new();
+        std::io::Read::read_to_string(
+            &mut flate2::read::GzDecoder::new(&compressed[..]),
+            &mut decoded,
+        )
+        .unwrap();
+        assert_eq!(decoded, body);
+        assert!(compressed.len() < body.len());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read_union() -> Result<()> {
+        let test_storage = Arc::new(AppServer::new(
+            ConnectionManagerImpl::default(),
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        ));
+        test_storage.set_id(1);
+        for db_name in &["MyOrg_MyBucket", "MyOrg_Archive"] {
+            test_storage
+                .create_database(*db_name, DatabaseRules::new())
+                .await
+                .unwrap();
+        }
+        let server_url = test_server(Arc::clone(&test_storage));
+        let client = Client::new();
+
+        let recent = chrono::Utc::now().timestamp_nanos() - 1_000_000_000;
+        let old = recent - 2 * 3600 * 1_000_000_000;
+        let write = |bucket: &str, lp_data: String| {
+            client
+                .post(&format!(
+                    "{}/api/v2/write?bucket={}&org=MyOrg",
+                    server_url, bucket
+                ))
+                .body(lp_data)
+                .send()
+        };
+        let response = write("MyBucket", format!("cpu,host=a usage=0.5 {}", recent)).await;
+        check_response("write", response, StatusCode::NO_CONTENT, "").await;
+        let lp_data = format!(
+            "cpu,host=a usage=0.9 {old}\n\
+             cpu,host=a usage=0.1 {recent}",
+            old = old,
+            recent = recent
+        );
+        let response = write("Archive", lp_data).await;
+        check_response("write", response, StatusCode::NO_CONTENT, "").await;
+
+        // the point of the first bucket wins at the same time
+        let response = client
+            .get(&format!(
+                "{}/api/v2/read?org=MyOrg&bucket=MyBucket&union=Archive&start=-3h",
+                server_url
+            ))
+            .send()
+            .await;
+        let expected = format!(
+            "_measurement,host,_field,_time,_value\n\
+             cpu,a,usage,{old},0.9\n\
+             cpu,a,usage,{recent},0.5\n",
+            old = old,
+            recent = recent
+        );
+        check_response("read", response, StatusCode::OK, &expected).await;
+
+        let response = client
+            .get(&format!(
+                "{}/api/v2/read?org=MyOrg&bucket=MyBucket&union=Missing&start=-3h",
+                server_url
+            ))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = client
+            .get(&format!(
+                "{}/iox/api/v1/databases/MyOrg_MyBucket/query?q={}&union=MyOrg_Archive&format=csv",
+                server_url, "select%20count(*)%20as%20n%20from%20cpu"
+            ))
+            .send()
+            .await;
+        check_response("query", response, StatusCode::OK, "n\n3\n").await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read_cache() -> Result<()> {
+        let test_storage = Arc::new(AppServer::new(
+            ConnectionManagerImpl::default(),
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        ));
+        test_storage.set_id(1);
+        test_storage
+            .create_database("MyOrg_MyBucket", DatabaseRules::new())
+            .await
+            .unwrap();
+        let serving_readiness = ServingReadiness::new();
+        serving_readiness.set_ready(true);
+        let options = HttpOptions {
+            read_cache: Some(Arc::new(ReadCache::new(10, Duration::from_secs(3600)))),
+            ..Default::default()
+        };
+        let server_url =
+            test_server_with_options(Arc::clone(&test_storage), serving_readiness, options);
+        let client = Client::new();
+
+        let write = |lp_data: String| {
+            client
+                .post(&format!(
+                    "{}/api/v2/write?bucket=MyBucket&org=MyOrg",
+                    server_url
+                ))
+                .body(lp_data)
+                .send()
+        };
+        let read = || {
+            client
+                .get(&format!(
+                    "{}/api/v2/read?org=MyOrg&bucket=MyBucket&start=-1h",
+                    server_url
+                ))
+                .send()
+        };
+
+        let recent = chrono::Utc::now().timestamp_nanos() - 1_000_000_000;
+        let response = write(format!("cpu,host=a usage=0.5 {}", recent)).await;
+        check_response("write", response, StatusCode::NO_CONTENT, "").await;
+        let expected = format!(
+            "_measurement,host,_field,_time,_value\n\
+             cpu,a,usage,{recent},0.5\n",
+            recent = recent
+        );
+        check_response("read", read().await, StatusCode::OK, &expected).await;
+
+        // data written behind the cache's back isn't read
+        let lines: std::result::Result<Vec<_>, _> =
+            influxdb_line_protocol::parse_lines(&format!("cpu,host=b usage=0.7 {}", recent))
+                .collect();
+        test_storage
+            .write_lines("MyOrg_MyBucket", &lines.unwrap())
+            .await
+            .unwrap();
+        check_response("cached read", read().await, StatusCode::OK, &expected).await;
+
+        // writing an older point doesn't change the read either
+        let old = recent - 2 * 3600 * 1_000_000_000;
+        let response = write(format!("cpu,host=c usage=0.1 {}", old)).await;
+        check_response("write", response, StatusCode::NO_CONTENT, "").await;
+        check_response("cached read", read().await, StatusCode::OK, &expected).await;
+
+        // but a recent one does
+        let response = write("cpu,host=c usage=0.9".to_string()).await;
+        check_response("write", response, StatusCode::NO_CONTENT, "").await;
+        let response = read().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.text().await.unwrap();
+        assert!(body.contains("cpu,b,usage"), "{}", body);
+        assert!(body.contains("cpu,c,usage"), "{}", body);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_compression() -> Result<()> {
+        use flate2::read::GzDecoder;
+        use http::header::ACCEPT_ENCODING;
+        use std::io::Read;
+
+        let test_storage: Arc<AppServer<ConnectionManagerImpl>> = Arc::new(AppServer::new(
+            ConnectionManagerImpl::default(),
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        ));
+        test_storage.set_id(1);
+        test_storage
+            .create_database("MyOrg_MyBucket", DatabaseRules::new())
+            .await
+            .unwrap();
+        let serving_readiness = ServingReadiness::new();
+        serving_readiness.set_ready(true);
+        let options = HttpOptions {
+            compression_min_size: 100,
+            ..Default::default()
+        };
+        let server_url = test_server_with_options(test_storage, serving_readiness, options);
+
+        let client = Client::new();
+        let lp_data = "h2o_temperature,location=santa_monica,state=CA surface_degrees=65.2,bottom_degrees=50.4 1568756160";
+        let response = client
+            .post(&format!(
+                "{}/api/v2/write?bucket=MyBucket&org=MyOrg",
+                server_url
+            ))
+            .body(lp_data)
+            .send()
+            .await;
+        check_response("write", response, StatusCode::NO_CONTENT, "").await;
+
+        // the pretty printed output is larger than the threshold
+        let response = client
+            .get(&format!(
+                "{}/iox/api/v1/databases/MyOrg_MyBucket/query?q={}",
+                server_url, "select%20*%20from%20h2o_temperature"
+            ))
+            .header(ACCEPT_ENCODING, "gzip, deflate")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_ENCODING], "gzip");
+
+        let body = response.bytes().await.unwrap();
+        let mut decoded = String::new();
+        GzDecoder::new(&body[..])
+            .read_to_string(&mut decoded)
+            .unwrap();
+        let expected = "+----------------+--------------+-------+-----------------+------------+\n\
+                        | bottom_degrees | location     | state | surface_degrees | time       |\n\
+                        +----------------+--------------+-------+-----------------+------------+\n\
+                        | 50.4           | santa_monica | CA    | 65.2            | 1568756160 |\n\
+                        +----------------+--------------+-------+-----------------+------------+\n";
+        assert_eq!(decoded, expected);
+
+        // the CSV output is smaller than the threshold
+        let response = client
+            .get(&format!(
+                "{}/iox/api/v1/databases/MyOrg_MyBucket/query?q={}&format=csv",
+                server_url, "select%20*%20from%20h2o_temperature"
+            ))
+            .header(ACCEPT_ENCODING, "gzip")
+            .send()
+            .await;
+        assert!(!response
+            .as_ref()
+            .unwrap()
+            .headers()
+            .contains_key(CONTENT_ENCODING));
+        let res = "bottom_degrees,location,state,surface_degrees,time\n\
+                   50.4,santa_monica,CA,65.2,1568756160\n";
+        check_response("query", response, StatusCode::OK, res).await;
+
+        // responses are not compressed unless the client asks for it
+        let response = client
+            .get(&format!(
+                "{}/iox/api/v1/databases/MyOrg_MyBucket/query?q={}",
+                server_url, "select%20*%20from%20h2o_temperature"
+            ))
+            .send()
+            .await;
+        assert!(!response
+            .as_ref()
+            .unwrap()
+            .headers()
+            .contains_key(CONTENT_ENCODING));
+        check_response("query", response, StatusCode::OK, expected).await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_timeout() {
+        let timeout = Arc::new(Timeout::new(Some(Duration::from_millis(10))));
+        let handler = with_timeout(Arc::clone(&timeout), |_: Request<Body>| async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            Ok(Response::new(Body::empty()))
+        });
+        let err = handler(Request::new(Body::empty())).await.unwrap_err();
+        assert!(matches!(err, ApplicationError::RequestTimedOut { .. }));
+        assert_eq!(err.response().status(), StatusCode::GATEWAY_TIMEOUT);
+
+        // changes to the timeout apply to new requests
+        timeout.set(None);
+        handler(Request::new(Body::empty())).await.unwrap();
+
+        // handlers responding in time are unaffected
+        let handler = with_timeout(
+            Arc::new(Timeout::new(Some(Duration::from_secs(10)))),
+            |_: Request<Body>| async { Ok(Response::new(Body::empty())) },
+        );
+        handler(Request::new(Body::empty())).await.unwrap();
+
+        // the client is too slow sending the body
+        let handler = with_timeout(
+            Arc::new(Timeout::new(Some(Duration::from_millis(10)))),
+            |req: Request<Body>| async move {
+                parse_body(req).await?;
+                Ok(Response::new(Body::empty()))
+            },
+        );
+        let (_sender, body) = Body::channel();
+        let err = handler(Request::new(body)).await.unwrap_err();
+        assert!(matches!(err, ApplicationError::RequestBodyTimedOut { .. }));
+        assert_eq!(err.response().status(), StatusCode::REQUEST_TIMEOUT);
+    }
+
+    #[tokio::test]
+    async fn test_query_timeout() {
+        let timeout = Arc::new(Timeout::new(Some(Duration::from_secs(10))));
+        let handler = with_query_timeout(Arc::clone(&timeout), |_: Request<Body>| async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            Ok(Response::new(Body::empty()))
+        });
+        let request = |uri: &str| Request::get(uri).body(Body::empty()).unwrap();
+
+        // the query asks for a shorter timeout than the server's
+        let err = handler(request("/query?q=x&timeout=10ms"))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, ApplicationError::RequestTimedOut { .. }));
+
+        // but can't extend it
+        timeout.set(Some(Duration::from_millis(10)));
+        let err = handler(request("/query?timeout=1h")).await.unwrap_err();
+        assert!(matches!(err, ApplicationError::RequestTimedOut { .. }));
+
+        // and sets the timeout when the server has none
+        timeout.set(None);
+        handler(request("/query?q=x")).await.unwrap();
+        let err = handler(request("/query?timeout=10ms")).await.unwrap_err();
+        assert!(matches!(err, ApplicationError::RequestTimedOut { .. }));
+
+        for invalid in &["0s", "10", "soon"] {
+            let err = handler(request(&format!("/query?timeout={}", invalid)))
+                .await
+                .unwrap_err();
+            assert!(matches!(err, ApplicationError::InvalidTimeout { .. }));
+            assert_eq!(err.response().status(), StatusCode::BAD_REQUEST);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_max_request_size() -> Result<()> {
+        let test_storage: Arc<AppServer<ConnectionManagerImpl>> = Arc::new(AppServer::new(
+            ConnectionManagerImpl::default(),
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        ));
+        test_storage.set_id(1);
+        test_storage
+            .create_database("MyOrg_MyBucket", DatabaseRules::new())
+            .await
+            .unwrap();
+        let serving_readiness = ServingReadiness::new();
+        serving_readiness.set_ready(true);
+        let options = HttpOptions {
+            max_request_size: 100,
+            ..Default::default()
+        };
+        let server_url = test_server_with_options(test_storage, serving_readiness, options);
+
+        let client = Client::new();
+        let lp_data = "h2o_temperature,location=santa_monica,state=CA surface_degrees=65.2,bottom_degrees=50.4 1568756160";
+        let response = client
+            .post(&format!(
+                "{}/api/v2/write?bucket=MyBucket&org=MyOrg",
+                server_url
+            ))
+            .body(lp_data)
+            .send()
+            .await;
+        check_response("write", response, StatusCode::NO_CONTENT, "").await;
+
+        let lp_data = format!("{}\n{}", lp_data, lp_data);
+        let response = client
+            .post(&format!(
+                "{}/api/v2/write?bucket=MyBucket&org=MyOrg",
+                server_url
+            ))
+            .body(lp_data)
+            .send()
+            .await;
+        check_response(
+            "write",
+            response,
+            StatusCode::BAD_REQUEST,
+            r#"{"code":"invalid","message":"Body exceeds limit of 100 bytes","error_code":100}"#,
+        )
+        .await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cors() -> Result<()> {
+        use http::header::{
+            ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN,
+            ACCESS_CONTROL_REQUEST_METHOD, ORIGIN,
+        };
+
+        let test_storage: Arc<AppServer<ConnectionManagerImpl>> = Arc::new(AppServer::new(
+            ConnectionManagerImpl::default(),
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        ));
+        let serving_readiness = ServingReadiness::new();
+        serving_readiness.set_ready(true);
+        let options = HttpOptions {
+            cors: Some(CorsConfig {
+                allowed_origins: vec!["https://ui.example.com".to_string()],
+                allowed_methods: vec!["GET".to_string(), "POST".to_string()],
+                allowed_headers: vec!["Content-Type".to_string()],
+                max_age: None,
+            }),
+            ..Default::default()
+        };
+        let server_url = test_server_with_options(test_storage, serving_readiness, options);
+        let client = Client::new();
+
+        // preflight from an allowed origin
+        let response = client
+            .request(Method::OPTIONS, &format!("{}/api/v2/write", server_url))
+            .header(ORIGIN, "https://ui.example.com")
+            .header(ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(
+            response.headers()[ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://ui.example.com"
+        );
+        assert_eq!(
+            response.headers()[ACCESS_CONTROL_ALLOW_METHODS],
+            "GET, POST"
+        );
+
+        // preflight from another origin
+        let response = client
+            .request(Method::OPTIONS, &format!("{}/api/v2/write", server_url))
+            .header(ORIGIN, "https://evil.example.com")
+            .header(ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        // actual requests from an allowed origin get the CORS headers
+        let response = client
+            .get(&format!("{}/health", server_url))
+            .header(ORIGIN, "https://ui.example.com")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://ui.example.com"
+        );
+
+        let response = client
+            .get(&format!("{}/health", server_url))
+            .header(ORIGIN, "https://evil.example.com")
+            .send()
+            .await
+            .unwrap();
+        assert!(response
+            .headers()
+            .get(ACCESS_CONTROL_ALLOW_ORIGIN)
+            .is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_json() -> Result<()> {
+        let (client, server_url) = setup_test_data().await;
+
+        // send a second line of data to demontrate how that works
+        let lp_data = "h2o_temperature,location=Boston,state=MA surface_degrees=50.2 1568756160";
+
+        // send write data
+        let bucket_name = "MyBucket";
+        let org_name = "MyOrg";
+        let response = client
+            .post(&format!(
+                "{}/api/v2/write?bucket={}&org={}",
+                server_url, bucket_name, org_name
+            ))
+            .body(lp_data)
+            .send()
+            .await;
+
+        check_response("write", response, StatusCode::NO_CONTENT, "").await;
+
+        // send query data
+        let response = client
+            .get(&format!(
+                "{}/iox/api/v1/databases/MyOrg_MyBucket/query?q={}&format=json",
+                server_url, "select%20*%20from%20h2o_temperature"
+            ))
+            .send()
+            .await;
+
+        assert_eq!(get_content_type(&response), "application/json");
+
+        // Note two json records: one record on each line
+        let res = r#"[{"bottom_degrees":50.4,"location":"santa_monica","state":"CA","surface_degrees":65.2,"time":1568756160},{"location":"Boston","state":"MA","surface_degrees":50.2,"time":1568756160}]"#;
+        check_response("query", response, StatusCode::OK, res).await;
+
+        Ok(())
+    }
+
+    fn gzip_str(s: &str) -> Vec<u8> {
+        use flate2::{write::GzEncoder, Compression};
+        use std::io::Write;
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        write!(encoder, "{}", s).expect("writing into encoder");
+        encoder.finish().expect("successfully encoding gzip data")
+    }
+
+    #[tokio::test]
+    async fn test_gzip_write() -> Result<()> {
+        let test_storage = Arc::new(AppServer::new(
+            ConnectionManagerImpl::default(),
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        ));
+        test_storage.set_id(1);
+        test_storage
+            .create_database("MyOrg_MyBucket", DatabaseRules::new())
+            .await
+            .unwrap();
+        let server_url = test_server(Arc::clone(&test_storage));
+
+        let client = Client::new();
+        let lp_data = "h2o_temperature,location=santa_monica,state=CA surface_degrees=65.2,bottom_degrees=50.4 1568756160";
+
+        // send write data encoded with gzip
+        let bucket_name = "MyBucket";
+        let org_name = "MyOrg";
+        let response = client
+            .post(&format!(
+                "{}/api/v2/write?bucket={}&org={}",
+                server_url, bucket_name, org_name
+            ))
+            .header(CONTENT_ENCODING, "gzip")
+            .body(gzip_str(lp_data))
+            .send()
+            .await;
+
+        check_response("gzip_write", response, StatusCode::NO_CONTENT, "").await;
+
+        // Check that the data got into the right bucket
+        let test_db = test_storage
+            .db(&DatabaseName::new("MyOrg_MyBucket").unwrap())
+            .await
+            .expect("Database exists");
+
+        let batches = run_query(test_db.as_ref(), "select * from h2o_temperature").await;
+
+        let expected = vec![
+            "+----------------+--------------+-------+-----------------+------------+",
+            "| bottom_degrees | location     | state | surface_degrees | time       |",
+            "+----------------+--------------+-------+-----------------+------------+",
+            "| 50.4           | santa_monica | CA    | 65.2            | 1568756160 |",
+            "+----------------+--------------+-------+-----------------+------------+",
+        ];
+        assert_table_eq!(expected, &batches);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_buckets() -> Result<()> {
+        let server = Arc::new(AppServer::new(
+            ConnectionManagerImpl::default(),
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        ));
+        server.set_id(1);
+        server
+            .create_database("not-a-bucket", DatabaseRules::new())
+            .await
+            .unwrap();
+        server.create_org("MyOrg").await.unwrap();
+        server.create_org("OtherOrg").await.unwrap();
+        let server_url = test_server(Arc::clone(&server));
+        let client = Client::new();
+
+        // buckets can only be created in an existing org
+        let response = client
+            .post(&format!("{}/api/v2/buckets", server_url))
+            .body(r#"{"org":"MissingOrg","name":"my_bucket"}"#)
+            .send()
+            .await;
+        assert_eq!(response?.status(), StatusCode::NOT_FOUND);
+
+        // create buckets the way InfluxDB 2.x clients do
+        let response = client
+            .post(&format!("{}/api/v2/buckets", server_url))
+            .body(r#"{"orgID":"MyOrg","name":"my_bucket","retentionRules":[{"type":"expire","everySeconds":3600}]}"#)
+            .send()
+            .await;
+        check_response(
+            "create_bucket",
+            response,
+            StatusCode::CREATED,
+            r#"{"org":"MyOrg","name":"my_bucket","retentionRules":[{"type":"expire","everySeconds":3600}]}"#,
+        )
+        .await;
+
+        let response = client
+            .post(&format!("{}/api/v2/buckets", server_url))
+            .body(r#"{"org":"OtherOrg","name":"forever"}"#)
+            .send()
+            .await;
+        assert_eq!(response?.status(), StatusCode::CREATED);
+
+        let db_name = DatabaseName::new("MyOrg_my%5Fbucket").unwrap();
+        let rules = server.db_rules(&db_name).await.unwrap();
+        assert_eq!(rules.retention_period_seconds, Some(3600));
+
+        // creating a bucket twice fails
+        let response = client
+            .post(&format!("{}/api/v2/buckets", server_url))
+            .body(r#"{"orgID":"MyOrg","name":"my_bucket"}"#)
+            .send()
+            .await;
+        assert_eq!(response?.status(), StatusCode::BAD_REQUEST);
+
+        let my_bucket = Bucket {
+            org: "MyOrg".to_string(),
+            name: "my_bucket".to_string(),
+            retention_rules: RetentionRule::from_retention_period(Some(3600)),
+        };
+        let forever = Bucket {
+            org: "OtherOrg".to_string(),
+            name: "forever".to_string(),
+            retention_rules: RetentionRule::from_retention_period(None),
+        };
+
+        let res: ListBucketsResponse = check_json_response(
+            &client,
+            &format!("{}/api/v2/buckets", server_url),
+            StatusCode::OK,
+        )
+        .await;
+        assert_eq!(res.buckets, vec![my_bucket.clone(), forever.clone()]);
+
+        let res: ListBucketsResponse = check_json_response(
+            &client,
+            &format!("{}/api/v2/buckets?org=OtherOrg", server_url),
+            StatusCode::OK,
+        )
+        .await;
+        assert_eq!(res.buckets, vec![forever.clone()]);
+
+        // update the retention of a bucket
+        let response = client
+            .patch(&format!(
+                "{}/api/v2/buckets/my_bucket?org=MyOrg",
+                server_url
+            ))
+            .body(r#"{"retentionRules":[{"type":"expire","everySeconds":0}]}"#)
+            .send()
+            .await;
+        check_response(
+            "update_bucket",
+            response,
+            StatusCode::OK,
+            r#"{"org":"MyOrg","name":"my_bucket","retentionRules":[{"type":"expire","everySeconds":0}]}"#,
+        )
+        .await;
+        let rules = server.db_rules(&db_name).await.unwrap();
+        assert_eq!(rules.retention_period_seconds, None);
+
+        let response = client
+            .patch(&format!("{}/api/v2/buckets/missing?org=MyOrg", server_url))
+            .body("{}")
+            .send()
+            .await;
+        assert_eq!(response?.status(), StatusCode::NOT_FOUND);
+
+        // delete a bucket
+        let response = client
+            .delete(&format!(
+                "{}/api/v2/buckets/my_bucket?org=MyOrg",
+                server_url
+            ))
+            .send()
+            .await;
+        check_response("delete_bucket", response, StatusCode::NO_CONTENT, "").await;
+        assert!(server.db(&db_name).await.is_none());
+
+        let response = client
+            .delete(&format!(
+                "{}/api/v2/buckets/my_bucket?org=MyOrg",
+                server_url
+            ))
+            .send()
+            .await;
+        assert_eq!(response?.status(), StatusCode::NOT_FOUND);
+
+        let res: ListBucketsResponse = check_json_response(
+            &client,
+            &format!("{}/api/v2/buckets", server_url),
+            StatusCode::OK,
+        )
+        .await;
+        assert_eq!(res.buckets, vec![forever]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_list_buckets_pages() {
+        let server = Arc::new(AppServer::new(
+            ConnectionManagerImpl::default(),
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        ));
+        server.set_id(1);
+        for (org, bucket) in &[
+            ("MyOrg", "b3"),
+            ("MyOrg", "b1"),
+            ("MyOrg", "b2"),
+            ("MyOrg", "other"),
+            ("OtherOrg", "b0"),
+        ] {
+            let db_name = org_and_bucket_to_database(org, bucket).unwrap();
+            server
+                .create_database(db_name, DatabaseRules::new())
+                .await
+                .unwrap();
+        }
+        let server_url = test_server(Arc::clone(&server));
+        let client = Client::new();
+
+        let list = |query: &str| {
+            let client = &client;
+            let url = format!("{}/api/v2/buckets?{}", server_url, query);
+            async move {
+                let res: ListBucketsResponse =
+                    check_json_response(client, &url, StatusCode::OK).await;
+                let names: Vec<_> = res
+                    .buckets
+                    .into_iter()
+                    .map(|bucket| format!("{}/{}", bucket.org, bucket.name))
+                    .collect();
+                (names, res.links.next)
+            }
+        };
+
+        let (names, next) = list("namePrefix=b&limit=2").await;
+        assert_eq!(names, vec!["MyOrg/b1", "MyOrg/b2"]);
+        assert_eq!(
+            next.as_deref(),
+            Some("/api/v2/buckets?namePrefix=b&limit=2&offset=2")
+        );
+
+        let (names, next) = list("namePrefix=b&limit=2&offset=2").await;
+        assert_eq!(names, vec!["MyOrg/b3", "OtherOrg/b0"]);
+        assert_eq!(next, None);
+
+        let (names, next) = list("org=MyOrg&after=b1&limit=1").await;
+        assert_eq!(names, vec!["MyOrg/b2"]);
+        assert_eq!(
+            next.as_deref(),
+            Some("/api/v2/buckets?org=MyOrg&limit=1&after=b2")
+        );
+
+        let (names, next) = list("org=MyOrg&after=b3").await;
+        assert_eq!(names, vec!["MyOrg/other"]);
+        assert_eq!(next, None);
+
+        for query in &["limit=0", "limit=101", "after=b1"] {
+            let response = client
+                .get(&format!("{}/api/v2/buckets?{}", server_url, query))
+                .send()
+                .await;
+            assert_eq!(response.unwrap().status(), StatusCode::BAD_REQUEST);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_orgs() -> Result<()> {
+        let server = Arc::new(AppServer::new(
+            ConnectionManagerImpl::default(),
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        ));
+        server.set_id(1);
+        let server_url = test_server(Arc::clone(&server));
+        let client = Client::new();
+
+        let response = client
+            .post(&format!("{}/api/v2/orgs", server_url))
+            .body(r#"{"name":"company"}"#)
+            .send()
+            .await;
+        check_response(
+            "create_org",
+            response,
+            StatusCode::CREATED,
+            r#"{"id":"0000000100000001","name":"company"}"#,
+        )
+        .await;
+
+        let response = client
+            .post(&format!("{}/api/v2/orgs", server_url))
+            .body(r#"{"name":"company"}"#)
+            .send()
+            .await;
+        assert_eq!(response?.status(), StatusCode::CONFLICT);
+
+        let company = Org {
+            id: "0000000100000001".to_string(),
+            name: "company".to_string(),
+            limits: Default::default(),
+        };
+        let res: ListOrgsResponse = check_json_response(
+            &client,
+            &format!("{}/api/v2/orgs", server_url),
+            StatusCode::OK,
+        )
+        .await;
+        assert_eq!(res.orgs, vec![company.clone()]);
+
+        let org: Org = check_json_response(
+            &client,
+            &format!("{}/api/v2/orgs/{}", server_url, company.id),
+            StatusCode::OK,
+        )
+        .await;
+        assert_eq!(org, company);
+
+        let response = client
+            .get(&format!("{}/api/v2/orgs/ffffffffffffffff", server_url))
+            .send()
+            .await;
+        assert_eq!(response?.status(), StatusCode::NOT_FOUND);
+
+        // rename the org
+        let response = client
+            .patch(&format!("{}/api/v2/orgs/{}", server_url, company.id))
+            .body(r#"{"name":"renamed"}"#)
+            .send()
+            .await;
+        check_response(
+            "update_org",
+            response,
+            StatusCode::OK,
+            r#"{"id":"0000000100000001","name":"renamed"}"#,
+        )
+        .await;
+
+        // orgs with buckets can not be deleted
+        server
+            .create_database("renamed_bucket", DatabaseRules::new())
+            .await
+            .unwrap();
+        let response = client
+            .delete(&format!("{}/api/v2/orgs/{}", server_url, company.id))
+            .send()
+            .await;
+        assert_eq!(response?.status(), StatusCode::CONFLICT);
+
+        server
+            .delete_database(&DatabaseName::new("renamed_bucket").unwrap())
+            .await
+            .unwrap();
+        let response = client
+            .delete(&format!("{}/api/v2/orgs/{}", server_url, company.id))
+            .send()
+            .await;
+        check_response("delete_org", response, StatusCode::NO_CONTENT, "").await;
+        assert!(server.orgs().await.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_org_quotas() -> Result<()> {
+        use server::quotas::{Limit, Quotas};
+
+        let server = Arc::new(AppServer::new(
+            ConnectionManagerImpl::default(),
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        ));
+        server.set_id(1);
+        server.set_quotas(Quotas {
+            series: Limit {
+                soft: None,
+                hard: Some(1),
+            },
+            ..Default::default()
+        });
+        let org = server.create_org("MyOrg").await?;
+        server
+            .create_database("MyOrg_MyBucket", DatabaseRules::new())
+            .await?;
+        let server_url = test_server(Arc::clone(&server));
+        let client = Client::new();
+
+        let write = |lp_data: &'static str| {
+            client
+                .post(&format!(
+                    "{}/api/v2/write?bucket=MyBucket&org=MyOrg",
+                    server_url
+                ))
+                .body(lp_data)
+                .send()
+        };
+
+        let response = write("cpu,host=a usage=1 10").await;
+        check_response("write", response, StatusCode::NO_CONTENT, "").await;
+
+        // the org is at its limit of one series
+        let response = write("cpu,host=b usage=1 10").await?;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        let body = response.text().await?;
+        assert!(body.contains(r#""code":"too many requests""#), "{}", body);
+
+        let usage: serde_json::Value = client
+            .get(&format!("{}/api/v2/orgs/{}/usage", server_url, org.id))
+            .send()
+            .await?
+            .json()
+            .await?;
+        assert_eq!(usage["series"], 1);
+        assert_eq!(usage["buckets"][0]["bucket"], "MyBucket");
+        assert_eq!(usage["buckets"][0]["series"], 1);
+
+        // the usage is what the server reports
+        let expected = serde_json::to_value(server.org_usage("MyOrg")?)?;
+        assert_eq!(usage, expected);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_org_rate_limits() -> Result<()> {
+        let server = Arc::new(AppServer::new(
+            ConnectionManagerImpl::default(),
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        ));
+        server.set_id(1);
+        let org = server.create_org("MyOrg").await?;
+        server
+            .create_database("MyOrg_MyBucket", DatabaseRules::new())
+            .await?;
+        let server_url = test_server(Arc::clone(&server));
+        let client = Client::new();
+
+        let org: Org = client
+            .put(&format!("{}/api/v2/orgs/{}/limits", server_url, org.id))
+            .body(r#"{"requests_per_second":1}"#)
+            .send()
+            .await?
+            .json()
+            .await?;
+        assert_eq!(org.limits.requests_per_second.map(|l| l.get()), Some(1));
+
+        let response = client
+            .put(&format!("{}/api/v2/orgs/{}/limits", server_url, org.id))
+            .body(r#"{"requests_per_second":0}"#)
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let write = || {
+            client
+                .post(&format!(
+                    "{}/api/v2/write?bucket=MyBucket&org=MyOrg",
+                    server_url
+                ))
+                .body("cpu,host=a usage=1 10")
+                .send()
+        };
+
+        let response = write().await;
+        check_response("write", response, StatusCode::NO_CONTENT, "").await;
+
+        // the org has made its one write of this second
+        let response = write().await?;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()["Retry-After"], "1");
+        let body = response.text().await?;
+        assert!(
+            body.contains("org MyOrg is over its rate limit"),
+            "{}",
+            body
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_audit_log() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("audit.log");
+        let serving_readiness = ServingReadiness::new();
+        serving_readiness.set_ready(true);
+        let options = HttpOptions {
+            audit_log: Arc::new(AuditLog::open(Some(&path))?),
+            ..Default::default()
+        };
+        let server = Arc::new(AppServer::new(
+            ConnectionManagerImpl::default(),
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        ));
+        server.set_id(1);
+        let server_url = test_server_with_options(server, serving_readiness, options);
+        let client = Client::new();
+
+        let response = client
+            .post(&format!("{}/api/v2/orgs", server_url))
+            .body(r#"{"name":"company"}"#)
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let response = client
+            .delete(&format!(
+                "{}/api/v2/buckets/sensors?org=company",
+                server_url
+            ))
+            .header(REQUEST_ID_HEADER, "audited")
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        // reads aren't recorded
+        let response = client
+            .get(&format!("{}/api/v2/orgs", server_url))
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let contents = std::fs::read_to_string(&path)?;
+        let records: Vec<serde_json::Value> = contents
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<Result<_, _>>()?;
+        assert_eq!(records.len(), 2, "{}", contents);
+
+        assert_eq!(records[0]["action"], "create_org");
+        assert_eq!(records[0]["actor"], "all");
+        assert_eq!(records[0]["target"], "/api/v2/orgs/0000000100000001");
+        assert!(records[0]["remote_addr"]
+            .as_str()
+            .unwrap()
+            .starts_with("127.0.0.1:"));
+        assert!(records[0].get("error").is_none());
+
+        assert_eq!(records[1]["action"], "delete_bucket");
+        assert_eq!(records[1]["target"], "/api/v2/buckets/sensors?org=company");
+        assert_eq!(records[1]["request_id"], "audited");
+        assert!(records[1]["error"].is_string());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_delete() -> Result<()> {
+        let test_storage = Arc::new(AppServer::new(
+            ConnectionManagerImpl::default(),
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        ));
+        test_storage.set_id(1);
+        test_storage
+            .create_database("MyOrg_MyBucket", DatabaseRules::new())
+            .await
+            .unwrap();
+        let server_url = test_server(Arc::clone(&test_storage));
+        let client = Client::new();
+
+        let lp_data = "h2o,location=santa_monica level=1 1000000000\n\
+                       h2o,location=coyote_creek level=2 1000000000\n\
+                       h2o,location=santa_monica level=3 3000000000";
+        let response = client
+            .post(&format!(
+                "{}/api/v2/write?bucket=MyBucket&org=MyOrg",
+                server_url
+            ))
+            .body(lp_data)
+            .send()
+            .await;
+        check_response("write", response, StatusCode::NO_CONTENT, "").await;
+
+        let response = client
+            .post(&format!(
+                "{}/api/v2/delete?bucket=MyBucket&org=MyOrg",
+                server_url
+            ))
+            .body(r#"{"start":"1970-01-01T00:00:00Z","stop":"1970-01-01T00:00:02Z","predicate":"_measurement=\"h2o\" AND location=\"santa_monica\""}"#)
+            .send()
+            .await;
+        check_response("delete", response, StatusCode::NO_CONTENT, "").await;
+
+        let test_db = test_storage
+            .db(&DatabaseName::new("MyOrg_MyBucket").unwrap())
+            .await
+            .expect("Database exists");
+        let batches = run_query(
+            test_db.as_ref(),
+            "select location, level from h2o order by level",
+        )
+        .await;
+        let expected = vec![
+            "+--------------+-------+",
+            "| location     | level |",
+            "+--------------+-------+",
+            "| coyote_creek | 2     |",
+            "| santa_monica | 3     |",
+            "+--------------+-------+",
+        ];
+        assert_table_eq!(expected, &batches);
+
+        // invalid requests
+        let cases = vec![
+            (
+                "bucket=MyBucket&org=MyOrg",
+                r#"{"start":"yesterday","stop":"1970-01-01T00:00:02Z"}"#,
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                "bucket=MyBucket&org=MyOrg",
+                r#"{"start":"1000-01-01T00:00:00Z","stop":"1970-01-01T00:00:02Z"}"#,
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                "bucket=MyBucket&org=MyOrg",
+                r#"{"start":"1970-01-01T00:00:00Z","stop":"1970-01-01T00:00:02Z","predicate":"location"}"#,
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                "bucket=NotMyBucket&org=MyOrg",
+                r#"{"start":"1970-01-01T00:00:00Z","stop":"1970-01-01T00:00:02Z"}"#,
+                StatusCode::NOT_FOUND,
+            ),
+        ];
+        for (query, body, expected_status) in cases {
+            let response = client
+                .post(&format!("{}/api/v2/delete?{}", server_url, query))
+                .body(body)
+                .send()
+                .await?;
+            assert_eq!(response.status(), expected_status, "{}", body);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_persist_partition() -> Result<()> {
+        use data_types::database_rules::{PartitionTemplate, TemplatePart};
+
+        let test_storage = Arc::new(AppServer::new(
+            ConnectionManagerImpl::default(),
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        ));
+        test_storage.set_id(1);
+        let rules = DatabaseRules {
+            partition_template: PartitionTemplate {
+                parts: vec![TemplatePart::Table],
+            },
+            ..DatabaseRules::new()
+        };
+        test_storage
+            .create_database("MyOrg_MyBucket", rules)
+            .await
+            .unwrap();
+        let serving_readiness = ServingReadiness::new();
+        serving_readiness.set_ready(true);
+        let options = HttpOptions {
+            admin_token: Some("secret".to_string()),
+            ..Default::default()
+        };
+        let server_url =
+            test_server_with_options(Arc::clone(&test_storage), serving_readiness, options);
+        let client = Client::new();
+
+        let lp_data = "h2o,location=santa_monica level=1 1000000000";
+        let response = client
+            .post(&format!(
+                "{}/api/v2/write?bucket=MyBucket&org=MyOrg",
+                server_url
+            ))
+            .body(lp_data)
+            .send()
+            .await;
+        check_response("write", response, StatusCode::NO_CONTENT, "").await;
+
+        let url = format!(
+            "{}/api/v2/partitions/h2o/persist?bucket=MyBucket&org=MyOrg",
+            server_url
+        );
+        let response = client
+            .post(&url)
+            .header("Authorization", "Token secret")
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        let body: serde_json::Value = response.json().await?;
+        assert_eq!(body["partition"], "h2o");
+        assert!(body["id"].is_string());
+
+        // the open chunk was rolled over
+        let test_db = test_storage
+            .db(&DatabaseName::new("MyOrg_MyBucket").unwrap())
+            .await
+            .expect("Database exists");
+        assert_eq!(test_db.mutable_buffer_chunks("h2o").len(), 2);
+
+        let response = client.post(&url).send().await?;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = client
+            .post(&url)
+            .header("Authorization", "Token wrong")
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        // snapshots need the admin token too
+        let response = client
+            .post(&format!(
+                "{}/api/v1/snapshot?bucket=MyBucket&org=MyOrg&partition=h2o",
+                server_url
+            ))
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = client
+            .post(&format!(
+                "{}/api/v2/partitions/cpu/persist?bucket=MyBucket&org=MyOrg",
+                server_url
+            ))
+            .header("Authorization", "Token secret")
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        // admin endpoints are disabled without a token
+        let server_url = test_server(test_storage);
+        let response = client
+            .post(&format!(
+                "{}/api/v2/partitions/h2o/persist?bucket=MyBucket&org=MyOrg",
+                server_url
+            ))
+            .header("Authorization", "Token secret")
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        Ok(())
+    }
+
+    /// Returns the url of a server requiring the API token "secret"
+    fn test_server_with_api_tokens(server: Arc<AppServer<ConnectionManagerImpl>>) -> String {
+        let serving_readiness = ServingReadiness::new();
+        serving_readiness.set_ready(true);
+        let token_store: Arc<dyn TokenStore> = Arc::<AppServer<_>>::clone(&server);
+        let options = HttpOptions {
+            authorizer: Arc::new(Authorizer::new(
+                ApiTokens::new(vec!["secret".to_string()]),
+                token_store,
+            )),
+            ..Default::default()
+        };
+        test_server_with_options(server, serving_readiness, options)
+    }
+
+    #[tokio::test]
+    async fn test_ip_filter() -> Result<()> {
+        let client = Client::new();
+        let ranges =
+            |ranges: &[&str]| -> Vec<String> { ranges.iter().map(ToString::to_string).collect() };
+
+        for (allowed, denied, status) in vec![
+            (ranges(&["127.0.0.0/8"]), vec![], StatusCode::NO_CONTENT),
+            (ranges(&["10.0.0.0/8"]), vec![], StatusCode::FORBIDDEN),
+            (vec![], ranges(&["127.0.0.1"]), StatusCode::FORBIDDEN),
+            (vec![], ranges(&["10.0.0.0/8"]), StatusCode::NO_CONTENT),
+        ] {
+            let serving_readiness = ServingReadiness::new();
+            serving_readiness.set_ready(true);
+            let options = HttpOptions {
+                ip_filter: Arc::new(IpFilter::new(&allowed, &denied)?),
+                ..Default::default()
+            };
+            let server_url = test_server_with_options(
+                Arc::new(AppServer::new(
+                    ConnectionManagerImpl::default(),
+                    Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+                )),
+                serving_readiness,
+                options,
+            );
+
+            let response = client.get(&format!("{}/ping", server_url)).send().await?;
+            assert_eq!(response.status(), status, "{:?} {:?}", allowed, denied);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_api_tokens() -> Result<()> {
+        let test_storage = Arc::new(AppServer::new(
+            ConnectionManagerImpl::default(),
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        ));
+        test_storage.set_id(1);
+        test_storage
+            .create_database("MyOrg_MyBucket", DatabaseRules::new())
+            .await
+            .unwrap();
+        let server_url = test_server_with_api_tokens(test_storage);
+        let client = Client::new();
+
+        let url = format!("{}/api/v2/write?bucket=MyBucket&org=MyOrg", server_url);
+        let lp_data = "h2o,location=santa_monica level=1 1000000000";
+        let response = client.post(&url).body(lp_data).send().await?;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(response.headers().get(WWW_AUTHENTICATE).unwrap(), "Token");
+
+        let response = client
+            .post(&url)
+            .header("Authorization", "Token wrong")
+            .body(lp_data)
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = client
+            .post(&url)
+            .header("Authorization", "Token secret")
+            .body(lp_data)
+            .send()
+            .await;
+        check_response("write", response, StatusCode::NO_CONTENT, "").await;
+
+        let response = client
+            .get(&format!("{}/api/v2/buckets?org=MyOrg", server_url))
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        // the health of the server can still be checked
+        let response = client.get(&format!("{}/ping", server_url)).send().await?;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let response = client.get(&format!("{}/health", server_url)).send().await?;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_scoped_api_tokens() -> Result<()> {
+        let test_storage = Arc::new(AppServer::new(
+            ConnectionManagerImpl::default(),
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        ));
+        test_storage.set_id(1);
+        for db_name in &["MyOrg_MyBucket", "MyOrg_Archive", "OtherOrg_MyBucket"] {
+            test_storage
+                .create_database(*db_name, DatabaseRules::new())
+                .await
+                .unwrap();
+        }
+        let server_url = test_server_with_api_tokens(test_storage);
+        let client = Client::new();
+
+        // creates a token with the configured token, returning its secret
+        let create = |body: serde_json::Value| {
+            client
+                .post(&format!("{}/api/v2/tokens", server_url))
+                .header("Authorization", "Token secret")
+                .json(&body)
+                .send()
+        };
+        let response = create(serde_json::json!({
+            "description": "telegraf",
+            "scopes": ["write"],
+            "org": "MyOrg",
+            "bucket": "MyBucket",
+        }))
+        .await?;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let created: CreateTokenResponse = response.json().await?;
+        assert_eq!(created.details.scopes, vec![Scope::Write]);
+        let writer = format!("Token {}", created.token);
+
+        let response = create(serde_json::json!({
+            "scopes": ["read", "admin"],
+            "org": "MyOrg",
+        }))
+        .await?;
+        let created: CreateTokenResponse = response.json().await?;
+        let org_admin = format!("Token {}", created.token);
+
+        let response = create(serde_json::json!({"scopes": []})).await?;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let write = |org: &str, bucket: &str, authorization: &str| {
+            client
+                .post(&format!(
+                    "{}/api/v2/write?bucket={}&org={}",
+                    server_url, bucket, org
+                ))
+                .header("Authorization", authorization)
+                .body("h2o,location=santa_monica level=1 1000000000")
+                .send()
+        };
+        let response = write("MyOrg", "MyBucket", writer.as_str()).await;
+        check_response("write", response, StatusCode::NO_CONTENT, "").await;
+        for (org, bucket, authorization) in vec![
+            ("MyOrg", "Archive", writer.as_str()),
+            ("OtherOrg", "MyBucket", writer.as_str()),
+            ("MyOrg", "MyBucket", org_admin.as_str()),
+        ] {
+            let response = write(org, bucket, authorization).await?;
+            assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        }
+
+        let read = |org: &str, authorization: &str| {
+            client
+                .get(&format!(
+                    "{}/api/v2/read?org={}&bucket=MyBucket&start=-1h",
+                    server_url, org
+                ))
+                .header("Authorization", authorization)
+                .send()
+        };
+        let response = read("MyOrg", org_admin.as_str()).await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = read("MyOrg", writer.as_str()).await?;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = read("OtherOrg", org_admin.as_str()).await?;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        // tokens only see and manage the tokens within their restrictions
+        let response = client
+            .get(&format!("{}/api/v2/tokens?org=MyOrg", server_url))
+            .header("Authorization", &org_admin)
+            .send()
+            .await?;
+        let listed: ListTokensResponse = response.json().await?;
+        assert_eq!(listed.tokens.len(), 2);
+        let writer_id = listed.tokens[0].id.clone();
+        let response = client
+            .get(&format!("{}/api/v2/tokens", server_url))
+            .header("Authorization", &org_admin)
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response = client
+            .get(&format!("{}/api/v2/orgs", server_url))
+            .header("Authorization", &org_admin)
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = create(serde_json::json!({"scopes": ["read"]})).await?;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let response = client
+            .post(&format!("{}/api/v2/tokens", server_url))
+            .header("Authorization", &org_admin)
+            .json(&serde_json::json!({"scopes": ["read"], "org": "OtherOrg"}))
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response = client
+            .delete(&format!("{}/api/v2/tokens/{}", server_url, writer_id))
+            .header("Authorization", &org_admin)
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let response = write("MyOrg", "MyBucket", writer.as_str()).await?;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = client
+            .delete(&format!("{}/api/v2/tokens/{}", server_url, writer_id))
+            .header("Authorization", "Token secret")
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_log_filter() -> Result<()> {
+        let (_layer, log_filter) = LogFilterHandle::new_for_test("info");
+        let serving_readiness = ServingReadiness::new();
+        serving_readiness.set_ready(true);
+        let options = HttpOptions {
+            admin_token: Some("secret".to_string()),
+            log_filter: Some(log_filter),
+            ..Default::default()
+        };
+        let server_url = test_server_with_options(
+            Arc::new(AppServer::new(
+                ConnectionManagerImpl::default(),
+                Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+            )),
+            serving_readiness,
+            options,
+        );
         let client = Client::new();
-        let response = client.get(&format!("{}/ping", server_url)).send().await;
+        let url = format!("{}/debug/log_filter", server_url);
 
-        // Print the response so if the test fails, we have a log of what went wrong
-        check_response("ping", response, StatusCode::OK, "PONG").await;
-        Ok(())
-    }
+        let response = client
+            .get(&url)
+            .header("Authorization", "Token secret")
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.text().await?.to_lowercase(), "info");
 
-    #[tokio::test]
-    async fn test_health() -> Result<()> {
-        let test_storage = Arc::new(AppServer::new(
-            ConnectionManagerImpl {},
-            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
-        ));
-        let server_url = test_server(Arc::clone(&test_storage));
+        let response = client
+            .put(&url)
+            .header("Authorization", "Token secret")
+            .body("server=debug\n")
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.text().await?.to_lowercase(), "server=debug");
 
-        let client = Client::new();
-        let response = client.get(&format!("{}/health", server_url)).send().await;
+        let response = client
+            .put(&url)
+            .header("Authorization", "Token secret")
+            .body("server=loud")
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = client.put(&url).body("trace").send().await?;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = client
+            .get(&url)
+            .header("Authorization", "Token secret")
+            .send()
+            .await?;
+        assert_eq!(response.text().await?.to_lowercase(), "server=debug");
 
-        // Print the response so if the test fails, we have a log of what went wrong
-        check_response("health", response, StatusCode::OK, "OK").await;
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_write() -> Result<()> {
+    async fn test_partition_statuses() -> Result<()> {
         let test_storage = Arc::new(AppServer::new(
-            ConnectionManagerImpl {},
+            ConnectionManagerImpl::default(),
             Arc::new(ObjectStore::new_in_memory(InMemory::new())),
         ));
         test_storage.set_id(1);
@@ -853,51 +6017,63 @@ mod tests {
             .create_database("MyOrg_MyBucket", DatabaseRules::new())
             .await
             .unwrap();
-        let server_url = test_server(Arc::clone(&test_storage));
-
+        let serving_readiness = ServingReadiness::new();
+        serving_readiness.set_ready(true);
+        let options = HttpOptions {
+            admin_token: Some("secret".to_string()),
+            ..Default::default()
+        };
+        let server_url =
+            test_server_with_options(Arc::clone(&test_storage), serving_readiness, options);
         let client = Client::new();
 
-        let lp_data = "h2o_temperature,location=santa_monica,state=CA surface_degrees=65.2,bottom_degrees=50.4 1568756160";
-
-        // send write data
-        let bucket_name = "MyBucket";
-        let org_name = "MyOrg";
+        let lp_data = "h2o,location=santa_monica level=1 1000000000\n\
+                       h2o,location=coyote_creek level=2 1000000000";
         let response = client
             .post(&format!(
-                "{}/api/v2/write?bucket={}&org={}",
-                server_url, bucket_name, org_name
+                "{}/api/v2/write?bucket=MyBucket&org=MyOrg",
+                server_url
             ))
             .body(lp_data)
             .send()
             .await;
-
         check_response("write", response, StatusCode::NO_CONTENT, "").await;
 
-        // Check that the data got into the right bucket
-        let test_db = test_storage
-            .db(&DatabaseName::new("MyOrg_MyBucket").unwrap())
-            .await
-            .expect("Database exists");
+        let url = format!("{}/api/v2/partitions?bucket=MyBucket&org=MyOrg", server_url);
+        let response = client
+            .get(&url)
+            .header("Authorization", "Token secret")
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = response.json().await?;
+        let partitions = body["partitions"].as_array().unwrap();
+        assert_eq!(partitions.len(), 1);
+        assert_eq!(partitions[0]["series_count"], 2);
+        assert_eq!(partitions[0]["point_count"], 2);
+        assert_eq!(partitions[0]["persisted_chunk_count"], 0);
+        assert!(partitions[0]["last_write_time"].is_string());
+
+        let response = client.get(&url).send().await?;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
 
-        let batches = run_query(test_db.as_ref(), "select * from h2o_temperature").await;
-        let expected = vec![
-            "+----------------+--------------+-------+-----------------+------------+",
-            "| bottom_degrees | location     | state | surface_degrees | time       |",
-            "+----------------+--------------+-------+-----------------+------------+",
-            "| 50.4           | santa_monica | CA    | 65.2            | 1568756160 |",
-            "+----------------+--------------+-------+-----------------+------------+",
-        ];
-        assert_table_eq!(expected, &batches);
+        let response = client
+            .get(&format!(
+                "{}/api/v2/partitions?bucket=Missing&org=MyOrg",
+                server_url
+            ))
+            .header("Authorization", "Token secret")
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
 
         Ok(())
     }
 
-    /// Sets up a test database with some data for testing the query endpoint
-    /// returns a client for communicting with the server, and the server
-    /// endpoint
-    async fn setup_test_data() -> (Client, String) {
-        let test_storage: Arc<AppServer<ConnectionManagerImpl>> = Arc::new(AppServer::new(
-            ConnectionManagerImpl {},
+    #[tokio::test]
+    async fn test_compaction() -> Result<()> {
+        let test_storage = Arc::new(AppServer::new(
+            ConnectionManagerImpl::default(),
             Arc::new(ObjectStore::new_in_memory(InMemory::new())),
         ));
         test_storage.set_id(1);
@@ -905,138 +6081,334 @@ mod tests {
             .create_database("MyOrg_MyBucket", DatabaseRules::new())
             .await
             .unwrap();
-        let server_url = test_server(Arc::clone(&test_storage));
-
+        let serving_readiness = ServingReadiness::new();
+        serving_readiness.set_ready(true);
+        let options = HttpOptions {
+            admin_token: Some("secret".to_string()),
+            ..Default::default()
+        };
+        let server_url =
+            test_server_with_options(Arc::clone(&test_storage), serving_readiness, options);
         let client = Client::new();
 
-        let lp_data = "h2o_temperature,location=santa_monica,state=CA surface_degrees=65.2,bottom_degrees=50.4 1568756160";
-
-        // send write data
-        let bucket_name = "MyBucket";
-        let org_name = "MyOrg";
+        let lp_data = "h2o,location=santa_monica level=1 1000000000";
         let response = client
             .post(&format!(
-                "{}/api/v2/write?bucket={}&org={}",
-                server_url, bucket_name, org_name
+                "{}/api/v2/write?bucket=MyBucket&org=MyOrg",
+                server_url
             ))
             .body(lp_data)
             .send()
             .await;
-
         check_response("write", response, StatusCode::NO_CONTENT, "").await;
-        (client, server_url)
-    }
-
-    #[tokio::test]
-    async fn test_query_pretty() -> Result<()> {
-        let (client, server_url) = setup_test_data().await;
 
-        // send query data
         let response = client
-            .get(&format!(
-                "{}/iox/api/v1/databases/MyOrg_MyBucket/query?q={}",
-                server_url, "select%20*%20from%20h2o_temperature"
+            .post(&format!(
+                "{}/api/v2/compactions?bucket=MyBucket&org=MyOrg",
+                server_url
             ))
+            .header("Authorization", "Token secret")
             .send()
-            .await;
-
-        assert_eq!(get_content_type(&response), "text/plain");
+            .await?;
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        let body: serde_json::Value = response.json().await?;
+        assert_eq!(body["db_name"], "MyOrg_MyBucket");
+        let id = body["id"].as_u64().unwrap();
+
+        let url = format!("{}/api/v2/compactions/{}", server_url, id);
+        let mut body = serde_json::Value::Null;
+        for _ in 0..50 {
+            body = client
+                .get(&url)
+                .header("Authorization", "Token secret")
+                .send()
+                .await?
+                .json()
+                .await?;
+            if body["state"] != "running" {
+                break;
+            }
+            tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(body["state"], "completed");
+        assert_eq!(body["chunks_compacted"], 1);
 
-        let res = "+----------------+--------------+-------+-----------------+------------+\n\
-                   | bottom_degrees | location     | state | surface_degrees | time       |\n\
-                   +----------------+--------------+-------+-----------------+------------+\n\
-                   | 50.4           | santa_monica | CA    | 65.2            | 1568756160 |\n\
-                   +----------------+--------------+-------+-----------------+------------+\n";
-        check_response("query", response, StatusCode::OK, res).await;
+        let body: serde_json::Value = client
+            .get(&format!("{}/api/v2/compactions", server_url))
+            .header("Authorization", "Token secret")
+            .send()
+            .await?
+            .json()
+            .await?;
+        assert_eq!(body["compactions"].as_array().unwrap().len(), 1);
+
+        let cases = vec![
+            (
+                "/api/v2/compactions/42",
+                "Token secret",
+                StatusCode::NOT_FOUND,
+            ),
+            (
+                "/api/v2/compactions/abc",
+                "Token secret",
+                StatusCode::NOT_FOUND,
+            ),
+            (
+                "/api/v2/compactions",
+                "Token wrong",
+                StatusCode::UNAUTHORIZED,
+            ),
+        ];
+        for (path, authorization, expected_status) in cases {
+            let response = client
+                .get(&format!("{}{}", server_url, path))
+                .header("Authorization", authorization)
+                .send()
+                .await?;
+            assert_eq!(response.status(), expected_status, "{}", path);
+        }
 
-        // same response is expected if we explicitly request 'format=pretty'
         let response = client
-            .get(&format!(
-                "{}/iox/api/v1/databases/MyOrg_MyBucket/query?q={}&format=pretty",
-                server_url, "select%20*%20from%20h2o_temperature"
+            .post(&format!(
+                "{}/api/v2/compactions?bucket=MyBucket&org=MyOrg&partition=nope",
+                server_url
             ))
+            .header("Authorization", "Token secret")
             .send()
-            .await;
-        assert_eq!(get_content_type(&response), "text/plain");
-
-        check_response("query", response, StatusCode::OK, res).await;
+            .await?;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_query_csv() -> Result<()> {
-        let (client, server_url) = setup_test_data().await;
+    async fn test_drain() -> Result<()> {
+        let test_storage = Arc::new(AppServer::new(
+            ConnectionManagerImpl::default(),
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        ));
+        test_storage.set_id(1);
+        test_storage
+            .create_database("MyOrg_MyBucket", DatabaseRules::new())
+            .await
+            .unwrap();
+        let serving_readiness = ServingReadiness::new();
+        serving_readiness.set_ready(true);
+        let options = HttpOptions {
+            admin_token: Some("secret".to_string()),
+            ..Default::default()
+        };
+        let server_url =
+            test_server_with_options(Arc::clone(&test_storage), serving_readiness, options);
+        let client = Client::new();
+        let write_url = format!("{}/api/v2/write?bucket=MyBucket&org=MyOrg", server_url);
+        let drain_url = format!("{}/api/v2/drain", server_url);
+
+        let lp_data = "h2o,location=santa_monica level=1 1000000000";
+        let response = client.post(&write_url).body(lp_data).send().await;
+        check_response("write", response, StatusCode::NO_CONTENT, "").await;
 
-        // send query data
         let response = client
-            .get(&format!(
-                "{}/iox/api/v1/databases/MyOrg_MyBucket/query?q={}&format=csv",
-                server_url, "select%20*%20from%20h2o_temperature"
-            ))
+            .get(&drain_url)
+            .header("Authorization", "Token secret")
             .send()
-            .await;
+            .await?;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
 
-        assert_eq!(get_content_type(&response), "text/csv");
+        let response = client
+            .post(&drain_url)
+            .header("Authorization", "Token secret")
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+
+        let mut body = serde_json::Value::Null;
+        for _ in 0..50 {
+            body = client
+                .get(&drain_url)
+                .header("Authorization", "Token secret")
+                .send()
+                .await?
+                .json()
+                .await?;
+            if body["finished"] != serde_json::Value::Null {
+                break;
+            }
+            tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(body["phase"], "drained");
+        assert_eq!(body["safe_to_terminate"], true);
+        assert_eq!(body["partitions_persisted"], 1);
 
-        let res = "bottom_degrees,location,state,surface_degrees,time\n\
-                   50.4,santa_monica,CA,65.2,1568756160\n";
-        check_response("query", response, StatusCode::OK, res).await;
+        // a drain already done isn't started again
+        let response = client
+            .post(&drain_url)
+            .header("Authorization", "Token secret")
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = client.post(&write_url).body(lp_data).send().await?;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let response = client.get(&format!("{}/ready", server_url)).send().await?;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_query_json() -> Result<()> {
-        let (client, server_url) = setup_test_data().await;
-
-        // send a second line of data to demontrate how that works
-        let lp_data = "h2o_temperature,location=Boston,state=MA surface_degrees=50.2 1568756160";
+    async fn test_cluster_members() -> Result<()> {
+        let test_storage = Arc::new(AppServer::new(
+            ConnectionManagerImpl::default(),
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        ));
+        let serving_readiness = ServingReadiness::new();
+        serving_readiness.set_ready(true);
+        let options = HttpOptions {
+            admin_token: Some("secret".to_string()),
+            ..Default::default()
+        };
+        let server_url =
+            test_server_with_options(Arc::clone(&test_storage), serving_readiness, options);
+        let client = Client::new();
+        let url = format!("{}/cluster/members", server_url);
 
-        // send write data
-        let bucket_name = "MyBucket";
-        let org_name = "MyOrg";
         let response = client
-            .post(&format!(
-                "{}/api/v2/write?bucket={}&org={}",
-                server_url, bucket_name, org_name
-            ))
-            .body(lp_data)
+            .get(&url)
+            .header("Authorization", "Token secret")
             .send()
-            .await;
+            .await?;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        test_storage.set_membership(Membership::new(
+            MemberState {
+                address: "http://a:8082".to_string(),
+                id: 1,
+                role: Role::Storage,
+                databases: vec![],
+                incarnation: 1,
+                heartbeat: 0,
+            },
+            vec![],
+            Duration::from_secs(1),
+        ));
+        let body: serde_json::Value = client
+            .get(&url)
+            .header("Authorization", "Token secret")
+            .send()
+            .await?
+            .json()
+            .await?;
+        let members = body["members"].as_array().unwrap();
+        assert_eq!(members.len(), 1);
+        assert_eq!(members[0]["address"], "http://a:8082");
+        assert_eq!(members[0]["role"], "storage");
+        assert_eq!(members[0]["status"], "alive");
+        assert_eq!(members[0]["local"], true);
+
+        let response = client.get(&url).send().await?;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
 
-        check_response("write", response, StatusCode::NO_CONTENT, "").await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_shards() -> Result<()> {
+        let test_storage = Arc::new(AppServer::new(
+            ConnectionManagerImpl::default(),
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        ));
+        let serving_readiness = ServingReadiness::new();
+        serving_readiness.set_ready(true);
+        let options = HttpOptions {
+            admin_token: Some("secret".to_string()),
+            ..Default::default()
+        };
+        let server_url =
+            test_server_with_options(Arc::clone(&test_storage), serving_readiness, options);
+        let client = Client::new();
+        let url = format!("{}/cluster/shards", server_url);
 
-        // send query data
         let response = client
-            .get(&format!(
-                "{}/iox/api/v1/databases/MyOrg_MyBucket/query?q={}&format=json",
-                server_url, "select%20*%20from%20h2o_temperature"
+            .get(&url)
+            .header("Authorization", "Token secret")
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        // backends that can't be reached
+        let backends = vec!["127.0.0.1:1".to_string(), "127.0.0.1:2".to_string()];
+        test_storage.set_shard_router(ShardRouter::new(backends).unwrap());
+        let body: serde_json::Value = client
+            .get(&url)
+            .header("Authorization", "Token secret")
+            .send()
+            .await?
+            .json()
+            .await?;
+        let shards = body["shards"].as_array().unwrap();
+        assert_eq!(shards.len(), 256);
+        let shard = &shards[0];
+        let id = shard["id"].as_str().unwrap();
+        let target = if shard["owner"] == "127.0.0.1:1" {
+            "127.0.0.1:2"
+        } else {
+            "127.0.0.1:1"
+        };
+
+        let shard_url = format!("{}/{}", url, id);
+        let response = client
+            .post(&format!("{}/move", shard_url))
+            .header("Authorization", "Token secret")
+            .body(format!(
+                r#"{{"to": "{}"}}"#,
+                shard["owner"].as_str().unwrap()
             ))
             .send()
-            .await;
+            .await?;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
 
-        assert_eq!(get_content_type(&response), "application/json");
+        let response = client
+            .post(&format!("{}/move", shard_url))
+            .header("Authorization", "Token secret")
+            .body(format!(r#"{{"to": "{}"}}"#, target))
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+
+        let mut body = serde_json::Value::Null;
+        for _ in 0..100 {
+            body = client
+                .get(&shard_url)
+                .header("Authorization", "Token secret")
+                .send()
+                .await?
+                .json()
+                .await?;
+            if body["last_move"]["finished"] != serde_json::Value::Null {
+                break;
+            }
+            tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
+        }
+        // the shard is rerouted, but its previous owner can't hand it off
+        assert_eq!(body["owner"], target);
+        assert_eq!(body["moved"], true);
+        assert_eq!(body["last_move"]["phase"], "failed");
 
-        // Note two json records: one record on each line
-        let res = r#"[{"bottom_degrees":50.4,"location":"santa_monica","state":"CA","surface_degrees":65.2,"time":1568756160},{"location":"Boston","state":"MA","surface_degrees":50.2,"time":1568756160}]"#;
-        check_response("query", response, StatusCode::OK, res).await;
+        let response = client
+            .get(&format!("{}/ffffffffffffffff", url))
+            .header("Authorization", "Token secret")
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
 
         Ok(())
     }
 
-    fn gzip_str(s: &str) -> Vec<u8> {
-        use flate2::{write::GzEncoder, Compression};
-        use std::io::Write;
-        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
-        write!(encoder, "{}", s).expect("writing into encoder");
-        encoder.finish().expect("successfully encoding gzip data")
-    }
-
     #[tokio::test]
-    async fn test_gzip_write() -> Result<()> {
+    async fn test_quorum_lost() -> Result<()> {
         let test_storage = Arc::new(AppServer::new(
-            ConnectionManagerImpl {},
+            ConnectionManagerImpl::default(),
             Arc::new(ObjectStore::new_in_memory(InMemory::new())),
         ));
         test_storage.set_id(1);
@@ -1044,42 +6416,39 @@ mod tests {
             .create_database("MyOrg_MyBucket", DatabaseRules::new())
             .await
             .unwrap();
+        test_storage.set_quorum(Quorum::new(3, OnQuorumLoss::RejectWrites));
         let server_url = test_server(Arc::clone(&test_storage));
-
         let client = Client::new();
-        let lp_data = "h2o_temperature,location=santa_monica,state=CA surface_degrees=65.2,bottom_degrees=50.4 1568756160";
-
-        // send write data encoded with gzip
-        let bucket_name = "MyBucket";
-        let org_name = "MyOrg";
-        let response = client
-            .post(&format!(
-                "{}/api/v2/write?bucket={}&org={}",
-                server_url, bucket_name, org_name
-            ))
-            .header(CONTENT_ENCODING, "gzip")
-            .body(gzip_str(lp_data))
-            .send()
-            .await;
-
-        check_response("gzip_write", response, StatusCode::NO_CONTENT, "").await;
-
-        // Check that the data got into the right bucket
-        let test_db = test_storage
-            .db(&DatabaseName::new("MyOrg_MyBucket").unwrap())
-            .await
-            .expect("Database exists");
-
-        let batches = run_query(test_db.as_ref(), "select * from h2o_temperature").await;
+        let write_url = format!("{}/api/v2/write?bucket=MyBucket&org=MyOrg", server_url);
+        let lp_data = "h2o,location=santa_monica level=1 1000000000";
+
+        // without membership the quorum isn't checked
+        let response = client.post(&write_url).body(lp_data).send().await?;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert!(response.headers().get("Warning").is_none());
+
+        test_storage.set_membership(Membership::new(
+            MemberState {
+                address: "http://a:8082".to_string(),
+                id: 1,
+                role: Role::Storage,
+                databases: vec![],
+                incarnation: 1,
+                heartbeat: 0,
+            },
+            vec![],
+            Duration::from_secs(1),
+        ));
+        let response = client.post(&write_url).body(lp_data).send().await?;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
 
-        let expected = vec![
-            "+----------------+--------------+-------+-----------------+------------+",
-            "| bottom_degrees | location     | state | surface_degrees | time       |",
-            "+----------------+--------------+-------+-----------------+------------+",
-            "| 50.4           | santa_monica | CA    | 65.2            | 1568756160 |",
-            "+----------------+--------------+-------+-----------------+------------+",
-        ];
-        assert_table_eq!(expected, &batches);
+        // reads are still served, flagged as stale
+        let response = client.get(&format!("{}/ping", server_url)).send().await?;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(
+            response.headers().get("Warning").unwrap(),
+            "110 - \"Response is Stale\""
+        );
 
         Ok(())
     }
@@ -1087,7 +6456,7 @@ mod tests {
     #[tokio::test]
     async fn set_writer_id() {
         let server = Arc::new(AppServer::new(
-            ConnectionManagerImpl {},
+            ConnectionManagerImpl::default(),
             Arc::new(ObjectStore::new_in_memory(InMemory::new())),
         ));
         server.set_id(1);
@@ -1119,7 +6488,7 @@ mod tests {
     #[tokio::test]
     async fn list_databases() {
         let server = Arc::new(AppServer::new(
-            ConnectionManagerImpl {},
+            ConnectionManagerImpl::default(),
             Arc::new(ObjectStore::new_in_memory(InMemory::new())),
         ));
         server.set_id(1);
@@ -1154,7 +6523,7 @@ mod tests {
     #[tokio::test]
     async fn create_database() {
         let server = Arc::new(AppServer::new(
-            ConnectionManagerImpl {},
+            ConnectionManagerImpl::default(),
             Arc::new(ObjectStore::new_in_memory(InMemory::new())),
         ));
         server.set_id(1);
@@ -1184,7 +6553,7 @@ mod tests {
     #[tokio::test]
     async fn get_database() {
         let server = Arc::new(AppServer::new(
-            ConnectionManagerImpl {},
+            ConnectionManagerImpl::default(),
             Arc::new(ObjectStore::new_in_memory(InMemory::new())),
         ));
         server.set_id(1);
@@ -1214,7 +6583,7 @@ mod tests {
     #[tokio::test]
     async fn get_wal_meta() {
         let server = Arc::new(AppServer::new(
-            ConnectionManagerImpl {},
+            ConnectionManagerImpl::default(),
             Arc::new(ObjectStore::new_in_memory(InMemory::new())),
         ));
         server.set_id(1);
@@ -1294,11 +6663,12 @@ mod tests {
         assert!(r2.segments[1].persisted.is_none());
         assert_eq!(r2.segments[1].size, 368);
         assert_eq!(r2.segments[1].writers.len(), 1);
+        let writer = r2.segments[1].writers.values().next().unwrap();
         assert_eq!(
-            r2.segments[1].writers.values().next().unwrap(),
+            writer,
             &WriterSummary {
-                start_sequence: 1,
-                end_sequence: 1,
+                start_sequence: writer.start_sequence,
+                end_sequence: writer.start_sequence,
                 missing_sequence: false
             }
         );
@@ -1377,7 +6747,31 @@ mod tests {
     /// creates an instance of the http service backed by a in-memory
     /// testable database.  Returns the url of the server
     fn test_server(server: Arc<AppServer<ConnectionManagerImpl>>) -> String {
-        let make_svc = router_service(server);
+        let serving_readiness = ServingReadiness::new();
+        serving_readiness.set_ready(true);
+        test_server_with_readiness(server, serving_readiness)
+    }
+
+    /// creates an instance of the http service backed by the given
+    /// server and readiness state
+    fn test_server_with_readiness(
+        server: Arc<AppServer<ConnectionManagerImpl>>,
+        serving_readiness: ServingReadiness,
+    ) -> String {
+        test_server_with_options(server, serving_readiness, HttpOptions::default())
+    }
+
+    fn test_server_with_options(
+        server: Arc<AppServer<ConnectionManagerImpl>>,
+        serving_readiness: ServingReadiness,
+        options: HttpOptions,
+    ) -> String {
+        let mut builder = request_service_builder(server, serving_readiness, options);
+        let make_svc = make_service_fn(move |conn: &AddrStream| {
+            let remote_addr = conn.remote_addr();
+            let service = LoggedService::new(builder.build(remote_addr), remote_addr);
+            async move { Ok::<_, std::convert::Infallible>(service) }
+        });
 
         // NB: specify port 0 to let the OS pick the port.
         let bind_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 0);
@@ -1394,7 +6788,7 @@ mod tests {
         let executor = Executor::new();
         let physical_plan = planner.query(db, query, &executor).await.unwrap();
 
-        collect(physical_plan).await.unwrap()
+        executor.collect(physical_plan).await.unwrap()
     }
 
     #[test]
@@ -1404,7 +6798,8 @@ mod tests {
             serde_urlencoded::from_str("q=foo"),
             Ok(QueryParams {
                 q: "foo".to_string(),
-                format: QueryOutputFormat::Pretty
+                format: QueryOutputFormat::Pretty,
+                union: String::new(),
             })
         );
     }
@@ -1415,7 +6810,8 @@ mod tests {
             serde_urlencoded::from_str("q=foo&format=pretty"),
             Ok(QueryParams {
                 q: "foo".to_string(),
-                format: QueryOutputFormat::Pretty
+                format: QueryOutputFormat::Pretty,
+                union: String::new(),
             })
         );
     }
@@ -1426,7 +6822,8 @@ mod tests {
             serde_urlencoded::from_str("q=foo&format=csv"),
             Ok(QueryParams {
                 q: "foo".to_string(),
-                format: QueryOutputFormat::CSV
+                format: QueryOutputFormat::CSV,
+                union: String::new(),
             })
         );
     }
@@ -1437,7 +6834,20 @@ mod tests {
             serde_urlencoded::from_str("q=foo&format=json"),
             Ok(QueryParams {
                 q: "foo".to_string(),
-                format: QueryOutputFormat::JSON
+                format: QueryOutputFormat::JSON,
+                union: String::new(),
+            })
+        );
+    }
+
+    #[test]
+    fn query_params_union() {
+        assert_eq!(
+            serde_urlencoded::from_str("q=foo&union=MyOrg_archive"),
+            Ok(QueryParams {
+                q: "foo".to_string(),
+                format: QueryOutputFormat::Pretty,
+                union: "MyOrg_archive".to_string(),
             })
         );
     }
@@ -0,0 +1,72 @@
+//! Authorization of requests to the admin endpoints of the HTTP API.
+//!
+//! Admin endpoints are only enabled when an admin token is configured, and
+//! requests to them must send it in an `Authorization: Token <token>`
+//! header, as InfluxDB 2.x clients do.
+
+use http::{header::AUTHORIZATION, HeaderMap};
+use snafu::Snafu;
+
+use crate::influxdb_ioxd::auth::{constant_time_eq, parse_token};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Admin endpoints are disabled, set --admin-token to enable them"))]
+    AdminDisabled,
+
+    #[snafu(display("Missing or invalid admin token"))]
+    InvalidToken,
+}
+
+/// The token admin requests must send, stored in the router data
+#[derive(Debug, Clone)]
+pub struct AdminToken(pub Option<String>);
+
+impl AdminToken {
+    /// Checks that `headers` contain the admin token
+    pub fn authorize(&self, headers: &HeaderMap) -> Result<(), Error> {
+        let expected = self.0.as_deref().ok_or(Error::AdminDisabled)?;
+
+        let token = headers
+            .get(AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_token)
+            .ok_or(Error::InvalidToken)?;
+        if constant_time_eq(token.as_bytes(), expected.as_bytes()) {
+            Ok(())
+        } else {
+            Err(Error::InvalidToken)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::HeaderValue;
+
+    fn headers(authorization: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, HeaderValue::from_static(authorization));
+        headers
+    }
+
+    #[test]
+    fn authorize() {
+        let token = AdminToken(Some("secret".to_string()));
+        token.authorize(&headers("Token secret")).unwrap();
+        token.authorize(&headers("token secret")).unwrap();
+
+        for authorization in &["Token wrong", "Token secret2", "Bearer secret", "secret"] {
+            let err = token.authorize(&headers(authorization)).unwrap_err();
+            assert!(matches!(err, Error::InvalidToken), "{}", authorization);
+        }
+        let err = token.authorize(&HeaderMap::new()).unwrap_err();
+        assert!(matches!(err, Error::InvalidToken));
+
+        let err = AdminToken(None)
+            .authorize(&headers("Token secret"))
+            .unwrap_err();
+        assert!(matches!(err, Error::AdminDisabled));
+    }
+}
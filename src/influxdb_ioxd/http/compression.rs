@@ -0,0 +1,301 @@
+//! Response compression for the HTTP API, negotiated with the client via
+//! the `Accept-Encoding` request header
+
+use std::io::Write;
+
+use bytes::Bytes;
+use flate2::{
+    write::{GzEncoder, ZlibEncoder},
+    Compression,
+};
+use http::{
+    header::{ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_LENGTH, VARY},
+    HeaderValue,
+};
+use hyper::{body::HttpBody, Body, Method, Response, StatusCode};
+use routerify::RequestInfo;
+use snafu::{ResultExt, Snafu};
+
+/// By default, response bodies smaller than this are sent uncompressed as
+/// the savings do not justify the CPU cost
+pub const DEFAULT_MIN_SIZE: usize = 1024;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Error reading response body: {}", source))]
+    ReadingBody { source: hyper::Error },
+
+    #[snafu(display("Error compressing response body as {}: {}", encoding, source))]
+    Compressing {
+        encoding: &'static str,
+        source: std::io::Error,
+    },
+}
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// A content coding the server can compress responses with
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ContentEncoding {
+    Gzip,
+    /// The zlib format, which is what HTTP calls "deflate"
+    Deflate,
+}
+
+impl ContentEncoding {
+    /// The name of the coding as used in the `Content-Encoding` header
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Gzip => "gzip",
+            Self::Deflate => "deflate",
+        }
+    }
+
+    /// Picks the encoding to use given the value of an `Accept-Encoding`
+    /// header, preferring the coding with the highest quality value and
+    /// gzip over deflate on ties. Returns `None` if the client does not
+    /// accept any supported coding.
+    pub fn negotiate(accept_encoding: &str) -> Option<Self> {
+        let mut best: Option<(Self, f32)> = None;
+
+        for entry in accept_encoding.split(',') {
+            let mut parts = entry.split(';');
+            let coding = parts.next().unwrap_or_default().trim();
+            let quality = parts
+                .find_map(|param| {
+                    let param = param.trim();
+                    param
+                        .strip_prefix("q=")
+                        .or_else(|| param.strip_prefix("Q="))
+                })
+                .map(|q| q.trim().parse::<f32>().unwrap_or(0.0))
+                .unwrap_or(1.0);
+
+            let encoding = if coding.eq_ignore_ascii_case("gzip") || coding == "*" {
+                Self::Gzip
+            } else if coding.eq_ignore_ascii_case("deflate") {
+                Self::Deflate
+            } else {
+                continue;
+            };
+
+            if quality <= 0.0 {
+                continue;
+            }
+
+            let better = match best {
+                None => true,
+                Some((_, best_quality)) => {
+                    quality > best_quality || (quality >= best_quality && encoding == Self::Gzip)
+                }
+            };
+            if better {
+                best = Some((encoding, quality));
+            }
+        }
+
+        best.map(|(encoding, _)| encoding)
+    }
+
+    fn encode(&self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        let mut encoder = self.encoder();
+        let mut encoded = encoder.write(data)?.to_vec();
+        encoded.extend_from_slice(&encoder.finish()?);
+        Ok(encoded)
+    }
+
+    fn encoder(&self) -> Encoder {
+        match self {
+            Self::Gzip => Encoder::Gzip(GzEncoder::new(Vec::new(), Compression::default())),
+            Self::Deflate => Encoder::Deflate(ZlibEncoder::new(Vec::new(), Compression::default())),
+        }
+    }
+}
+
+/// Compresses data written in pieces, such as the chunks of a streamed body
+#[derive(Debug)]
+enum Encoder {
+    Gzip(GzEncoder<Vec<u8>>),
+    Deflate(ZlibEncoder<Vec<u8>>),
+}
+
+impl Encoder {
+    /// Compresses `data`, returning the compressed data produced so far.
+    /// The encoder may hold on to some of it until more is written.
+    fn write(&mut self, data: &[u8]) -> std::io::Result<Bytes> {
+        let output = match self {
+            Self::Gzip(encoder) => {
+                encoder.write_all(data)?;
+                encoder.get_mut()
+            }
+            Self::Deflate(encoder) => {
+                encoder.write_all(data)?;
+                encoder.get_mut()
+            }
+        };
+        Ok(Bytes::from(std::mem::take(output)))
+    }
+
+    /// Returns the rest of the compressed data
+    fn finish(self) -> std::io::Result<Bytes> {
+        match self {
+            Self::Gzip(encoder) => encoder.finish(),
+            Self::Deflate(encoder) => encoder.finish(),
+        }
+        .map(Bytes::from)
+    }
+}
+
+/// Compresses `body` with `encoding` as it is sent
+fn compress_stream(body: Body, encoding: ContentEncoding) -> Body {
+    let stream = futures::stream::unfold(Some((body, encoding.encoder())), |state| async move {
+        let (mut body, mut encoder) = state?;
+        loop {
+            let compressed = match body.data().await {
+                Some(Ok(data)) => encoder.write(&data),
+                Some(Err(e)) => Err(std::io::Error::new(std::io::ErrorKind::Other, e)),
+                None => return Some((encoder.finish(), None)),
+            };
+            match compressed {
+                // nothing to send until more is compressed
+                Ok(compressed) if compressed.is_empty() => continue,
+                Ok(compressed) => return Some((Ok(compressed), Some((body, encoder)))),
+                Err(e) => return Some((Err(e), None)),
+            }
+        }
+    });
+    Body::wrap_stream(stream)
+}
+
+/// Compresses the body of `res` if the request accepts a supported
+/// encoding and the body is at least `min_size` bytes long. Bodies whose
+/// length is not known up front are streamed, and only large responses are
+/// streamed, so they are compressed as they are sent whatever their size.
+/// Responses that are already encoded are returned unchanged.
+pub async fn compress_response(
+    res: Response<Body>,
+    req_info: &RequestInfo,
+    min_size: usize,
+) -> Result<Response<Body>> {
+    let encoding = match req_info
+        .headers()
+        .get(ACCEPT_ENCODING)
+        .and_then(|v| v.to_str().ok())
+        .and_then(ContentEncoding::negotiate)
+    {
+        Some(encoding) => encoding,
+        None => return Ok(res),
+    };
+
+    let compressible = req_info.method() != Method::HEAD
+        && res.status() != StatusCode::NO_CONTENT
+        && res.status() != StatusCode::NOT_MODIFIED
+        && !res.headers().contains_key(CONTENT_ENCODING)
+        && res
+            .body()
+            .size_hint()
+            .exact()
+            .map_or(true, |len| len as usize >= min_size);
+
+    if !compressible {
+        return Ok(res);
+    }
+
+    let (mut parts, body) = res.into_parts();
+    let body = if body.size_hint().exact().is_some() {
+        let body = hyper::body::to_bytes(body).await.context(ReadingBody)?;
+        let compressed = encoding.encode(&body).context(Compressing {
+            encoding: encoding.as_str(),
+        })?;
+        Body::from(compressed)
+    } else {
+        compress_stream(body, encoding)
+    };
+
+    parts.headers.remove(CONTENT_LENGTH);
+    parts.headers.insert(
+        CONTENT_ENCODING,
+        HeaderValue::from_static(encoding.as_str()),
+    );
+    parts
+        .headers
+        .append(VARY, HeaderValue::from_static("Accept-Encoding"));
+
+    Ok(Response::from_parts(parts, body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn negotiate() {
+        use ContentEncoding::*;
+
+        let cases = vec![
+            ("gzip", Some(Gzip)),
+            ("deflate", Some(Deflate)),
+            ("GZIP", Some(Gzip)),
+            ("gzip, deflate, br", Some(Gzip)),
+            ("deflate, gzip", Some(Gzip)),
+            ("gzip;q=0.5, deflate", Some(Deflate)),
+            ("gzip;q=0, deflate;q=0.1", Some(Deflate)),
+            ("*", Some(Gzip)),
+            ("gzip;q=0", None),
+            ("br, identity", None),
+            ("", None),
+        ];
+
+        for (accept_encoding, expected) in cases {
+            assert_eq!(
+                ContentEncoding::negotiate(accept_encoding),
+                expected,
+                "Accept-Encoding: {}",
+                accept_encoding
+            );
+        }
+    }
+
+    #[test]
+    fn encode_round_trip() {
+        let data = "cpu,host=A usage=0.5 100\n".repeat(100);
+
+        let gzip = ContentEncoding::Gzip.encode(data.as_bytes()).unwrap();
+        let mut decoded = String::new();
+        flate2::read::GzDecoder::new(&gzip[..])
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, data);
+
+        let deflate = ContentEncoding::Deflate.encode(data.as_bytes()).unwrap();
+        let mut decoded = String::new();
+        flate2::read::ZlibDecoder::new(&deflate[..])
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, data);
+
+        assert!(gzip.len() < data.len());
+        assert!(deflate.len() < data.len());
+    }
+
+    #[tokio::test]
+    async fn compress_stream_round_trip() {
+        let data = "cpu,host=A usage=0.5 100\n".repeat(1000);
+        let chunks: Vec<_> = data
+            .as_bytes()
+            .chunks(100)
+            .map(|chunk| Ok::<_, std::io::Error>(Bytes::copy_from_slice(chunk)))
+            .collect();
+        let body = Body::wrap_stream(futures::stream::iter(chunks));
+        assert_eq!(body.size_hint().exact(), None);
+
+        let compressed = compress_stream(body, ContentEncoding::Gzip);
+        let compressed = hyper::body::to_bytes(compressed).await.unwrap();
+        let mut decoded = String::new();
+        flate2::read::GzDecoder::new(&compressed[..])
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, data);
+        assert!(compressed.len() < data.len());
+    }
+}
@@ -0,0 +1,211 @@
+//! Cross-Origin Resource Sharing (CORS) support for the HTTP API, so that
+//! browser based UIs served from a different origin can call it directly
+
+use http::{
+    header::{
+        ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN,
+        ACCESS_CONTROL_MAX_AGE, ACCESS_CONTROL_REQUEST_METHOD, ORIGIN, VARY,
+    },
+    HeaderMap, HeaderValue,
+};
+use hyper::{Body, Request, Response, StatusCode};
+
+/// Which cross-origin requests browsers are allowed to make
+#[derive(Debug, Clone, PartialEq)]
+pub struct CorsConfig {
+    /// Origins (e.g. `https://ui.example.com`) allowed to call the API.
+    /// `*` allows any origin.
+    pub allowed_origins: Vec<String>,
+
+    /// Methods allowed in cross-origin requests
+    pub allowed_methods: Vec<String>,
+
+    /// Request headers allowed in cross-origin requests
+    pub allowed_headers: Vec<String>,
+
+    /// How long, in seconds, browsers may cache the result of a preflight
+    /// request
+    pub max_age: Option<u64>,
+}
+
+impl CorsConfig {
+    /// Returns the value of the `Access-Control-Allow-Origin` header to
+    /// send for a request from `origin`, or `None` if the origin is not
+    /// allowed
+    fn allow_origin(&self, origin: &str) -> Option<HeaderValue> {
+        if self.allowed_origins.iter().any(|o| o == "*") {
+            Some(HeaderValue::from_static("*"))
+        } else if self
+            .allowed_origins
+            .iter()
+            .any(|o| o.eq_ignore_ascii_case(origin))
+        {
+            HeaderValue::from_str(origin).ok()
+        } else {
+            None
+        }
+    }
+
+    fn allows_method(&self, method: &str) -> bool {
+        self.allowed_methods
+            .iter()
+            .any(|m| m.eq_ignore_ascii_case(method))
+    }
+
+    /// Adds the CORS headers to `headers` of a response to a request with
+    /// the request headers `request_headers`, if the request came from an
+    /// allowed origin
+    pub fn add_response_headers(&self, request_headers: &HeaderMap, headers: &mut HeaderMap) {
+        if let Some(allow_origin) = request_headers
+            .get(ORIGIN)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|origin| self.allow_origin(origin))
+        {
+            headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+            headers.append(VARY, HeaderValue::from_static("Origin"));
+        }
+    }
+
+    /// Responds to a preflight (`OPTIONS`) request. Requests from origins
+    /// or for methods that are not allowed are rejected with `403
+    /// Forbidden`, which the browser reports as a CORS failure.
+    pub fn preflight_response(&self, req: &Request<Body>) -> Response<Body> {
+        let headers = req.headers();
+        let allow_origin = headers
+            .get(ORIGIN)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|origin| self.allow_origin(origin));
+        let method_allowed = headers
+            .get(ACCESS_CONTROL_REQUEST_METHOD)
+            .and_then(|v| v.to_str().ok())
+            .map(|method| self.allows_method(method))
+            .unwrap_or(false);
+
+        let allow_origin = match allow_origin {
+            Some(allow_origin) if method_allowed => allow_origin,
+            _ => {
+                return Response::builder()
+                    .status(StatusCode::FORBIDDEN)
+                    .body(Body::empty())
+                    .unwrap()
+            }
+        };
+
+        let mut builder = Response::builder()
+            .status(StatusCode::NO_CONTENT)
+            .header(ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin)
+            .header(
+                ACCESS_CONTROL_ALLOW_METHODS,
+                self.allowed_methods.join(", "),
+            )
+            .header(VARY, "Origin");
+
+        if !self.allowed_headers.is_empty() {
+            builder = builder.header(
+                ACCESS_CONTROL_ALLOW_HEADERS,
+                self.allowed_headers.join(", "),
+            );
+        }
+        if let Some(max_age) = self.max_age {
+            builder = builder.header(ACCESS_CONTROL_MAX_AGE, max_age);
+        }
+
+        builder.body(Body::empty()).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(allowed_origins: &[&str]) -> CorsConfig {
+        CorsConfig {
+            allowed_origins: allowed_origins.iter().map(|s| s.to_string()).collect(),
+            allowed_methods: vec!["GET".to_string(), "POST".to_string()],
+            allowed_headers: vec!["Authorization".to_string(), "Content-Type".to_string()],
+            max_age: Some(600),
+        }
+    }
+
+    fn preflight(origin: &str, method: &str) -> Request<Body> {
+        Request::builder()
+            .method("OPTIONS")
+            .uri("/api/v2/write")
+            .header(ORIGIN, origin)
+            .header(ACCESS_CONTROL_REQUEST_METHOD, method)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[test]
+    fn allowed_origins() {
+        let config = config(&["https://ui.example.com"]);
+        assert_eq!(
+            config.allow_origin("https://ui.example.com").unwrap(),
+            "https://ui.example.com"
+        );
+        assert!(config.allow_origin("https://evil.example.com").is_none());
+
+        let config = self::config(&["*"]);
+        assert_eq!(
+            config.allow_origin("https://evil.example.com").unwrap(),
+            "*"
+        );
+    }
+
+    #[test]
+    fn response_headers() {
+        let config = config(&["https://ui.example.com"]);
+
+        let mut request_headers = HeaderMap::new();
+        request_headers.insert(ORIGIN, HeaderValue::from_static("https://ui.example.com"));
+        let mut headers = HeaderMap::new();
+        config.add_response_headers(&request_headers, &mut headers);
+        assert_eq!(
+            headers[ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://ui.example.com"
+        );
+        assert_eq!(headers[VARY], "Origin");
+
+        // no headers for other origins, or same origin requests
+        request_headers.insert(ORIGIN, HeaderValue::from_static("https://evil.example.com"));
+        let mut headers = HeaderMap::new();
+        config.add_response_headers(&request_headers, &mut headers);
+        assert!(headers.is_empty());
+
+        let mut headers = HeaderMap::new();
+        config.add_response_headers(&HeaderMap::new(), &mut headers);
+        assert!(headers.is_empty());
+    }
+
+    #[test]
+    fn preflight_allowed() {
+        let config = config(&["https://ui.example.com"]);
+        let res = config.preflight_response(&preflight("https://ui.example.com", "POST"));
+
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        let headers = res.headers();
+        assert_eq!(
+            headers[ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://ui.example.com"
+        );
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_METHODS], "GET, POST");
+        assert_eq!(
+            headers[ACCESS_CONTROL_ALLOW_HEADERS],
+            "Authorization, Content-Type"
+        );
+        assert_eq!(headers[ACCESS_CONTROL_MAX_AGE], "600");
+    }
+
+    #[test]
+    fn preflight_rejected() {
+        let config = config(&["https://ui.example.com"]);
+
+        let res = config.preflight_response(&preflight("https://evil.example.com", "POST"));
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        assert!(res.headers().get(ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+
+        let res = config.preflight_response(&preflight("https://ui.example.com", "DELETE"));
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    }
+}
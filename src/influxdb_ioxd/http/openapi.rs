@@ -0,0 +1,641 @@
+//! A description of the HTTP API in the [OpenAPI 3] format, served at
+//! `/api/openapi.json` so that client generators and API gateways can
+//! integrate with IOx.
+//!
+//! The description is generated from [`ROUTES`], which lists the routes
+//! registered by the router along with their parameters. Each listener only
+//! describes the [`Endpoints`] it serves.
+//!
+//! [OpenAPI 3]: https://spec.openapis.org/oas/v3.0.3
+
+use serde_json::{json, Map, Value};
+
+use super::{super::build_info::VERSION, Endpoints};
+
+/// A route served by the router
+#[derive(Debug)]
+pub struct Route {
+    pub method: &'static str,
+    /// The path, with path parameters in routerify's `:name` syntax
+    pub path: &'static str,
+    pub summary: &'static str,
+    /// The listeners serving the route
+    pub endpoints: Endpoints,
+    /// Whether the route requires the admin token
+    pub admin_token: bool,
+    pub query: &'static [Param],
+    /// The media type of the request body, if the route takes one
+    pub body: Option<&'static str>,
+    /// The status of successful responses
+    pub status: u16,
+}
+
+/// A query string parameter of a route
+#[derive(Debug)]
+pub struct Param {
+    pub name: &'static str,
+    pub required: bool,
+    pub description: &'static str,
+}
+
+const fn required(name: &'static str, description: &'static str) -> Param {
+    Param {
+        name,
+        required: true,
+        description,
+    }
+}
+
+const fn optional(name: &'static str, description: &'static str) -> Param {
+    Param {
+        name,
+        required: false,
+        description,
+    }
+}
+
+const ORG: Param = required("org", "The name of the org");
+const BUCKET: Param = required("bucket", "The name of the bucket");
+
+const fn route(method: &'static str, path: &'static str, summary: &'static str) -> Route {
+    Route {
+        method,
+        path,
+        summary,
+        endpoints: Endpoints::Data,
+        admin_token: false,
+        query: &[],
+        body: None,
+        status: 200,
+    }
+}
+
+/// Every route registered by `router`, in the order they are registered
+pub const ROUTES: &[Route] = &[
+    Route {
+        endpoints: Endpoints::All,
+        ..route("get", "/api/openapi.json", "This description of the API")
+    },
+    Route {
+        query: &[ORG, BUCKET],
+        body: Some("text/plain"),
+        status: 204,
+        ..route("post", "/api/v2/write", "Write line protocol")
+    },
+    Route {
+        query: &[ORG, BUCKET],
+        body: Some("application/json"),
+        status: 204,
+        ..route(
+            "post",
+            "/api/v2/delete",
+            "Delete data by time range and predicate",
+        )
+    },
+    Route {
+        query: &[
+            ORG,
+            BUCKET,
+            required(
+                "start",
+                "The start of the time range: relative to now, e.g. -1h, RFC3339 or nanoseconds",
+            ),
+            optional(
+                "stop",
+                "The inclusive end of the time range, in the format of start, now by default",
+            ),
+            optional(
+                "predicate",
+                "Only read matching rows, e.g. _measurement=\"cpu\" AND host=\"a\", or with \
+                 regular expressions host=~/^a/ AND region!~/west/, combined with AND, OR, NOT \
+                 and parentheses, or comparing fields usage_user + usage_system > 90",
+            ),
+            optional(
+                "fields",
+                "Comma separated expressions computed from the fields of each measurement and \
+                 returned instead, e.g. total=usage_user+usage_system,kb=value*8/1024",
+            ),
+            optional("group_by", "Comma separated tags to group the series by"),
+            optional(
+                "every",
+                "The duration of the windows to downsample over, e.g. 1m",
+            ),
+            optional(
+                "agg",
+                "The aggregate of each window: mean, sum, count, count_distinct, median, \
+                 percentile, or the selectors min, max, first or last, which also apply to whole \
+                 series without every",
+            ),
+            optional(
+                "percentile",
+                "The percentile computed by agg=percentile, between 0 and 100",
+            ),
+            optional(
+                "fill",
+                "How windows without points are filled: null, previous, 0 or linear",
+            ),
+            optional(
+                "transform",
+                "Replace the points by their rate of change: derivative, \
+                 non_negative_derivative or rate, for counters",
+            ),
+            optional(
+                "unit",
+                "The time unit of the rates of transform, 1s by default",
+            ),
+            optional(
+                "top",
+                "Keep the series with the N highest values in each window of every and agg",
+            ),
+            optional(
+                "bottom",
+                "Keep the series with the N lowest values in each window of every and agg",
+            ),
+            optional("limit", "The most points to return in total"),
+            optional("series_limit", "The most points to return per series"),
+            optional(
+                "offset",
+                "The number of points to skip at the start of each series",
+            ),
+            optional(
+                "resample",
+                "Align the points of each series onto the multiples of this duration, e.g. 1m",
+            ),
+            optional(
+                "interpolate",
+                "The values of resampled points: linear (default) or previous",
+            ),
+            optional(
+                "shift",
+                "Also read the time range this much earlier, e.g. 7d, returning its series \
+                 moved later by as much and tagged _shift",
+            ),
+            optional(
+                "union",
+                "Comma separated buckets of the org read along with bucket as one dataset, \
+                 whose series are merged by time, the first bucket's point winning",
+            ),
+            optional(
+                "order",
+                "The time order of the points: asc (default) or desc",
+            ),
+            optional(
+                "pivot",
+                "Whether to return the fields of a measurement as columns aligned on time",
+            ),
+            optional(
+                "timeout",
+                "Abort the query after this duration, e.g. 10s, if shorter than the server's",
+            ),
+            optional("format", "The output format: csv (default) or json"),
+        ],
+        ..route("get", "/api/v2/read", "Read series")
+    },
+    Route {
+        query: &[
+            optional("org", "Only list the buckets of this org"),
+            optional(
+                "namePrefix",
+                "Only list the buckets whose names start with this",
+            ),
+            optional("limit", "The number of buckets per page, at most 100"),
+            optional("offset", "The number of buckets to skip"),
+            optional("after", "Only list the buckets whose names sort after this"),
+        ],
+        ..route("get", "/api/v2/buckets", "List buckets")
+    },
+    Route {
+        body: Some("application/json"),
+        status: 201,
+        ..route("post", "/api/v2/buckets", "Create a bucket")
+    },
+    Route {
+        query: &[ORG],
+        body: Some("application/json"),
+        ..route(
+            "patch",
+            "/api/v2/buckets/:bucket",
+            "Change the retention of a bucket",
+        )
+    },
+    Route {
+        query: &[ORG],
+        status: 204,
+        ..route(
+            "delete",
+            "/api/v2/buckets/:bucket",
+            "Delete a bucket and its data",
+        )
+    },
+    route("get", "/api/v2/orgs", "List orgs"),
+    Route {
+        body: Some("application/json"),
+        status: 201,
+        ..route("post", "/api/v2/orgs", "Create an org")
+    },
+    route("get", "/api/v2/orgs/:id", "Get an org"),
+    route(
+        "get",
+        "/api/v2/orgs/:id/usage",
+        "Get the series and bytes stored by an org and its buckets",
+    ),
+    Route {
+        body: Some("application/json"),
+        ..route("patch", "/api/v2/orgs/:id", "Rename an org")
+    },
+    Route {
+        body: Some("application/json"),
+        ..route(
+            "put",
+            "/api/v2/orgs/:id/limits",
+            "Set the rate limits of an org",
+        )
+    },
+    Route {
+        status: 204,
+        ..route(
+            "delete",
+            "/api/v2/orgs/:id",
+            "Delete an org without buckets",
+        )
+    },
+    Route {
+        status: 204,
+        ..route("get", "/ping", "Check that the server is up")
+    },
+    route("get", "/iox/api/v1/databases", "List databases"),
+    Route {
+        body: Some("application/json"),
+        ..route("put", "/iox/api/v1/databases/:name", "Create a database")
+    },
+    route(
+        "get",
+        "/iox/api/v1/databases/:name",
+        "Get the rules of a database",
+    ),
+    Route {
+        query: &[
+            required("q", "The SQL query"),
+            optional(
+                "timeout",
+                "Abort the query after this duration, e.g. 10s, if shorter than the server's",
+            ),
+            optional("format", "The output format: pretty (default), csv or json"),
+            optional(
+                "union",
+                "Comma separated databases queried along with this one, each table holding \
+                 the rows of all of them",
+            ),
+        ],
+        ..route(
+            "get",
+            "/iox/api/v1/databases/:name/query",
+            "Query a database",
+        )
+    },
+    Route {
+        query: &[
+            optional("limit", "The number of segments to return"),
+            optional("offset", "The number of segments to skip"),
+            optional(
+                "newer_than",
+                "Only return segments newer than this RFC3339 time",
+            ),
+        ],
+        ..route(
+            "get",
+            "/iox/api/v1/databases/:name/wal/meta",
+            "List the WAL segments of a database",
+        )
+    },
+    Route {
+        body: Some("application/json"),
+        ..route("put", "/iox/api/v1/id", "Set the writer id")
+    },
+    route("get", "/iox/api/v1/id", "Get the writer id"),
+    Route {
+        query: &[ORG, BUCKET],
+        ..route(
+            "get",
+            "/api/v1/partitions",
+            "List the partition keys of a bucket",
+        )
+    },
+    Route {
+        query: &[optional(
+            "org",
+            "Only list the tokens restricted to this org",
+        )],
+        ..route("get", "/api/v2/tokens", "List API tokens")
+    },
+    Route {
+        body: Some("application/json"),
+        status: 201,
+        ..route(
+            "post",
+            "/api/v2/tokens",
+            "Create an API token with scopes, optionally restricted to an org or bucket",
+        )
+    },
+    Route {
+        status: 204,
+        ..route("delete", "/api/v2/tokens/:id", "Delete an API token")
+    },
+    Route {
+        endpoints: Endpoints::Admin,
+        ..route("get", "/health", "Check that the server is alive")
+    },
+    Route {
+        endpoints: Endpoints::Admin,
+        ..route(
+            "get",
+            "/ready",
+            "Check that the server is ready to serve requests",
+        )
+    },
+    Route {
+        endpoints: Endpoints::Admin,
+        ..route("get", "/debug/build", "Describe the build of the server")
+    },
+    Route {
+        endpoints: Endpoints::Admin,
+        ..route(
+            "get",
+            "/metrics",
+            "Get the server's metrics in the Prometheus text format",
+        )
+    },
+    Route {
+        endpoints: Endpoints::Admin,
+        admin_token: true,
+        ..route("get", "/debug/log_filter", "Get the log filter")
+    },
+    Route {
+        endpoints: Endpoints::Admin,
+        admin_token: true,
+        body: Some("text/plain"),
+        ..route("put", "/debug/log_filter", "Change the log filter")
+    },
+    Route {
+        endpoints: Endpoints::Admin,
+        admin_token: true,
+        query: &[ORG, BUCKET],
+        ..route(
+            "get",
+            "/api/v2/partitions",
+            "Get the status of the partitions of a bucket",
+        )
+    },
+    Route {
+        endpoints: Endpoints::Admin,
+        admin_token: true,
+        query: &[
+            ORG,
+            BUCKET,
+            required("partition", "The key of the partition"),
+        ],
+        ..route(
+            "post",
+            "/api/v1/snapshot",
+            "Snapshot a partition to object storage",
+        )
+    },
+    Route {
+        endpoints: Endpoints::Admin,
+        admin_token: true,
+        query: &[ORG, BUCKET],
+        ..route(
+            "post",
+            "/api/v2/partitions/:partition/persist",
+            "Persist a partition to object storage",
+        )
+    },
+    Route {
+        endpoints: Endpoints::Admin,
+        admin_token: true,
+        ..route("get", "/api/v2/compactions", "List compactions")
+    },
+    Route {
+        endpoints: Endpoints::Admin,
+        admin_token: true,
+        query: &[
+            ORG,
+            BUCKET,
+            optional("partition", "The partition to compact, all if not set"),
+        ],
+        status: 202,
+        ..route("post", "/api/v2/compactions", "Start compacting a bucket")
+    },
+    Route {
+        endpoints: Endpoints::Admin,
+        admin_token: true,
+        ..route(
+            "get",
+            "/api/v2/compactions/:id",
+            "Get the status of a compaction",
+        )
+    },
+    Route {
+        endpoints: Endpoints::Admin,
+        admin_token: true,
+        ..route("get", "/cluster/members", "List the members of the cluster")
+    },
+    Route {
+        endpoints: Endpoints::Admin,
+        admin_token: true,
+        ..route("get", "/api/v2/drain", "Get the status of the drain")
+    },
+    Route {
+        endpoints: Endpoints::Admin,
+        admin_token: true,
+        status: 202,
+        ..route("post", "/api/v2/drain", "Start draining the server")
+    },
+    Route {
+        endpoints: Endpoints::Admin,
+        admin_token: true,
+        ..route(
+            "get",
+            "/cluster/shards",
+            "List the shards writes are routed to and their owners",
+        )
+    },
+    Route {
+        endpoints: Endpoints::Admin,
+        admin_token: true,
+        ..route(
+            "get",
+            "/cluster/shards/:id",
+            "Get a shard and the status of its last move",
+        )
+    },
+    Route {
+        endpoints: Endpoints::Admin,
+        admin_token: true,
+        body: Some("application/json"),
+        status: 202,
+        ..route(
+            "post",
+            "/cluster/shards/:id/move",
+            "Start moving a shard to another backend",
+        )
+    },
+];
+
+impl Route {
+    /// Whether a listener serving `endpoints` serves this route
+    pub fn served_by(&self, endpoints: Endpoints) -> bool {
+        match self.endpoints {
+            Endpoints::All => true,
+            Endpoints::Data => endpoints.data(),
+            Endpoints::Admin => endpoints.admin(),
+        }
+    }
+
+    /// The parameters in the path of this route
+    fn path_params(&self) -> impl Iterator<Item = &'static str> {
+        self.path
+            .split('/')
+            .filter_map(|segment| segment.strip_prefix(':'))
+    }
+
+    /// The path of this route in OpenAPI's `{name}` syntax
+    fn openapi_path(&self) -> String {
+        self.path
+            .split('/')
+            .map(|segment| match segment.strip_prefix(':') {
+                Some(param) => format!("{{{}}}", param),
+                None => segment.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    fn operation(&self) -> Value {
+        let path_params = self.path_params().map(|name| {
+            json!({
+                "name": name,
+                "in": "path",
+                "required": true,
+                "schema": {"type": "string"},
+            })
+        });
+        let query_params = self.query.iter().map(|param| {
+            json!({
+                "name": param.name,
+                "in": "query",
+                "required": param.required,
+                "description": param.description,
+                "schema": {"type": "string"},
+            })
+        });
+        let parameters: Vec<_> = path_params.chain(query_params).collect();
+
+        let mut operation = json!({
+            "summary": self.summary,
+            "parameters": parameters,
+            "responses": {
+                self.status.to_string(): {"description": "Success"},
+                "default": {
+                    "description": "Error",
+                    "content": {
+                        "application/json": {
+                            "schema": {"$ref": "#/components/schemas/Error"},
+                        },
+                    },
+                },
+            },
+        });
+        if let Some(media_type) = self.body {
+            operation["requestBody"] = json!({
+                "required": true,
+                "content": {media_type: {}},
+            });
+        }
+        if self.admin_token {
+            operation["security"] = json!([{"AdminToken": []}]);
+        }
+        operation
+    }
+}
+
+/// Returns the OpenAPI document describing the routes served by a listener
+/// serving `endpoints`
+pub fn document(endpoints: Endpoints) -> Value {
+    let mut paths = Map::new();
+    for route in ROUTES.iter().filter(|route| route.served_by(endpoints)) {
+        let path = paths
+            .entry(route.openapi_path())
+            .or_insert_with(|| json!({}));
+        path[route.method] = route.operation();
+    }
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "InfluxDB IOx",
+            "version": VERSION,
+        },
+        "paths": paths,
+        "components": {
+            "schemas": {
+                "Error": {
+                    "type": "object",
+                    "required": ["code", "message"],
+                    "properties": {
+                        "code": {"type": "string"},
+                        "message": {"type": "string"},
+                        "line": {"type": "integer"},
+                        "error_code": {"type": "integer"},
+                    },
+                },
+            },
+            "securitySchemes": {
+                "AdminToken": {
+                    "type": "apiKey",
+                    "in": "header",
+                    "name": "Authorization",
+                    "description": "`Token <admin token>`",
+                },
+            },
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn openapi_path() {
+        let route = route("get", "/api/v2/buckets/:bucket/x/:id", "");
+        assert_eq!(route.openapi_path(), "/api/v2/buckets/{bucket}/x/{id}");
+        assert_eq!(
+            route.path_params().collect::<Vec<_>>(),
+            vec!["bucket", "id"]
+        );
+    }
+
+    #[test]
+    fn document_endpoints() {
+        let all = document(Endpoints::All);
+        assert!(all["paths"]["/api/v2/write"]["post"].is_object());
+        assert!(all["paths"]["/health"]["get"].is_object());
+        assert_eq!(
+            all["paths"]["/api/v2/buckets/{bucket}"]["delete"]["parameters"][0]["in"],
+            "path"
+        );
+
+        let data = document(Endpoints::Data);
+        assert!(data["paths"]["/api/v2/write"].is_object());
+        assert!(data["paths"]["/health"].is_null());
+        assert!(data["paths"]["/api/openapi.json"].is_object());
+
+        let admin = document(Endpoints::Admin);
+        assert!(admin["paths"]["/api/v2/write"].is_null());
+        assert_eq!(
+            admin["paths"]["/api/v2/compactions"]["post"]["security"][0]["AdminToken"],
+            json!([])
+        );
+    }
+}
@@ -0,0 +1,2587 @@
+//! The `/api/v2/read` endpoint, which returns the series of a bucket in a
+//! time range, optionally filtered by a predicate and grouped by tags.
+//!
+//! The time range runs from `start` up to and including `stop`, now if not
+//! set. Each is either a duration relative to now such as `-1h`, an RFC3339
+//! time or a number of nanoseconds since the epoch.
+//!
+//! The predicate uses the syntax of deletes, extended with regular
+//! expressions matching tag values: `host=~/^server-\d+$/` keeps the rows
+//! whose host matches and `host!~/^server-\d+$/` those whose host doesn't.
+//! Each expression is compiled once, when the read is planned.
+//!
+//! Comparisons can also be joined with `OR`, negated with `NOT` and grouped
+//! with parentheses: `NOT (host="a" OR region=~/west/)`. `NOT` binds
+//! tighter than `AND`, which binds tighter than `OR`. `_measurement` can
+//! only be joined to the rest of the predicate with `AND`, as it selects the
+//! tables read rather than rows.
+//!
+//! With `resample=1m`, the irregular points of each series are aligned onto
+//! the multiples of a minute in the time range, with the `linear`
+//! interpolation of the points around each time or, with
+//! `interpolate=previous`, the value of the point before it.
+//!
+//! With `union=archive`, the `archive` bucket of the org is read too, and
+//! the series of both buckets are merged by time as if they were one. Where
+//! both have a point of a series at the same time, the point of `bucket`
+//! wins.
+//!
+//! With `shift=7d`, the time range 7 days earlier is read too, and its
+//! series are returned after the others with their points moved 7 days
+//! later and a `_shift=7d` tag, so that this week can be compared to the
+//! last.
+//!
+//! Fields can be compared to arithmetic of fields and numbers:
+//! `usage_user + usage_system > 90`. With
+//! `fields=total=usage_user+usage_system` the expressions are computed as the
+//! rows are read and returned instead of the fields of each measurement, after
+//! any aggregation. Arithmetic is computed on floats.
+//!
+//! Each series is the values of one field of a measurement for one set of
+//! tag values. In CSV, each series is a block of rows with its own header,
+//! with blocks separated by an empty line:
+//!
+//! ```text
+//! _measurement,host,region,_field,_time,_value
+//! cpu,a,west,usage,1612345678000000000,0.5
+//! ```
+//!
+//! When grouping by tags with `group_by=host,region`, the series of each
+//! group follow a `#group` header naming the tag values of the group, and in
+//! JSON the series are nested in their group.
+//!
+//! With `every=1m&agg=mean`, each series is downsampled to one point per
+//! window of `every`, the aggregate of the points in the window. Windows
+//! without points are omitted, unless `fill` is set to fill them with null,
+//! the `previous` value, `0` or a `linear` interpolation of the values around
+//! them.
+//!
+//! The selectors `first`, `last`, `min` and `max` select one point of each
+//! window, keeping its own time rather than the time of the window. Without
+//! `every`, they select one point of each series.
+//!
+//! With `pivot=true`, the fields of each measurement and set of tag values
+//! are returned together, as one column per field with rows aligned on time.
+//! A field without a point at the time of a row is left empty:
+//!
+//! ```text
+//! _measurement,host,_time,usage_system,usage_user
+//! cpu,a,1612345678000000000,0.5,1.5
+//! cpu,a,1612345688000000000,,1.7
+//! ```
+//!
+//! `transform=derivative` replaces the points of each series by the rate of
+//! change between consecutive points per `unit` (default `1s`),
+//! `non_negative_derivative` drops the negative rates and `rate` treats
+//! decreases as counter resets. They apply after windowing and filling.
+//!
+//! `top=5` keeps only the 5 series with the highest values in each window
+//! of a windowed read, per measurement and field, and `bottom=5` the 5 with
+//! the lowest. The series are ranked by the query executor, so only the
+//! selected points are converted and returned.
+//!
+//! `offset` skips the first points of each series, `series_limit` caps the
+//! points returned per series and `limit` the points returned in total. The
+//! read stops pulling series from the storage layer once `limit` is reached,
+//! so that exploratory reads of large buckets stay cheap.
+//!
+//! Points are returned in ascending time order, or with `order=desc` latest
+//! first, so that `order=desc&series_limit=1` returns the last point of each
+//! series.
+//!
+//! CSV responses are serialized one row at a time as the response body is
+//! sent, in chunks of `CSV_CHUNK_SIZE` bytes, rather than assembled into
+//! one string first. Reads of one bucket without `shift` are serialized as
+//! the series are computed, so that they are not all held in memory.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt,
+    fmt::Write,
+    sync::Arc,
+};
+
+use bytes::{Bytes, BytesMut};
+
+use arrow_deps::{
+    arrow::{
+        array::{
+            Array, ArrayRef, BooleanArray, Float64Array, Int64Array, StringArray, UInt64Array,
+        },
+        datatypes::DataType,
+    },
+    datafusion::{
+        error::DataFusionError,
+        logical_plan::{binary_expr, col, lit, Expr, LogicalPlanBuilder, Operator},
+    },
+};
+use data_types::TIME_COLUMN_NAME;
+use query::{
+    exec::{
+        field::FieldColumns,
+        seriesset::{Error as SeriesSetError, SeriesSet, SeriesSetItem},
+        topn::{Ranking, TopN},
+        Executor,
+    },
+    func::{
+        regex::regex_match_expr,
+        window::{Duration, Window},
+    },
+    group_by::{Aggregate, GroupByAndAggregate, WindowDuration},
+    plan::seriesset::{SeriesSetPlan, SeriesSetPlans},
+    predicate::{Predicate, PredicateBuilder},
+};
+use regex::Regex;
+use serde::{ser::SerializeSeq, Deserialize, Serialize, Serializer};
+use server::db::predicate::{
+    self, ArithmeticOp, Comparison, ComparisonOp, FieldComparison, FieldComparisonOp, FieldExpr,
+    PredicateExpr, ReadPredicate,
+};
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+use tokio::sync::mpsc;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display(
+        "Invalid {} '{}': expected a negative duration such as -30s, -5m, -1h or -7d, \
+         an RFC3339 time or nanoseconds since the epoch",
+        name,
+        value
+    ))]
+    InvalidTime { name: &'static str, value: String },
+
+    #[snafu(display("Invalid time range: start {} is after stop {}", start, stop))]
+    InvalidTimeRange { start: i64, stop: i64 },
+
+    #[snafu(display("{}", source))]
+    InvalidPredicate { source: predicate::Error },
+
+    #[snafu(display("{}", source))]
+    InvalidField { source: predicate::Error },
+
+    #[snafu(display(
+        "Fields can not be computed from selected points, which keep their own times"
+    ))]
+    ComputedSelector {},
+
+    #[snafu(display("Error planning computed fields: {}", source))]
+    PlanningFields { source: DataFusionError },
+
+    #[snafu(display("Invalid regular expression /{}/: {}", pattern, source))]
+    InvalidRegex {
+        pattern: String,
+        source: regex::Error,
+    },
+
+    #[snafu(display(
+        "Invalid every '{}': expected a positive duration such as 30s, 5m, 1h or 1d",
+        value
+    ))]
+    InvalidEvery { value: String },
+
+    #[snafu(display("Windowed reads need both every and agg"))]
+    IncompleteWindow {},
+
+    #[snafu(display("Windowed reads can not be grouped by tags"))]
+    GroupedWindow {},
+
+    #[snafu(display("Only the selectors first, last, min and max can be computed without every"))]
+    UnwindowedAggregate {},
+
+    #[snafu(display("agg=percentile needs a percentile between 0 and 100"))]
+    InvalidPercentile {},
+
+    #[snafu(display("Selected points keep their time so can not be filled"))]
+    FilledSelector {},
+
+    #[snafu(display("Pivoted reads can not be grouped by tags"))]
+    GroupedPivot {},
+
+    #[snafu(display(
+        "Invalid unit '{}': expected a positive duration such as 1s, 1m or 1h",
+        value
+    ))]
+    InvalidUnit { value: String },
+
+    #[snafu(display(
+        "Invalid shift '{}': expected a positive duration such as 1h, 1d or 7d",
+        value
+    ))]
+    InvalidShift { value: String },
+
+    #[snafu(display(
+        "Invalid resample '{}': expected a positive duration such as 10s, 1m or 1h",
+        value
+    ))]
+    InvalidResample { value: String },
+
+    #[snafu(display("Resampled reads can not be windowed or aggregated"))]
+    WindowedResample {},
+
+    #[snafu(display(
+        "Resampling to {} points exceeds the maximum of {}, use a longer resample",
+        count,
+        max
+    ))]
+    TooManyResampledPoints { count: i64, max: i64 },
+
+    #[snafu(display("Only windowed reads can be filled"))]
+    UnwindowedFill {},
+
+    #[snafu(display("top and bottom rank the windows of reads with both every and agg"))]
+    UnwindowedTop {},
+
+    #[snafu(display("top and bottom need a number of series above 0, and can not be combined"))]
+    InvalidTop {},
+
+    #[snafu(display("top and bottom can not rank the series of several buckets"))]
+    UnionTop {},
+
+    #[snafu(display(
+        "Filling {} windows exceeds the maximum of {}, use a longer every",
+        count,
+        max
+    ))]
+    TooManyWindows { count: i64, max: i64 },
+
+    #[snafu(display("Error running read: {}", source))]
+    Executing { source: query::exec::Error },
+
+    #[snafu(display("Error computing series: {}", source))]
+    ComputingSeries { source: SeriesSetError },
+
+    #[snafu(display("Unsupported field type in read: {:?}", data_type))]
+    UnsupportedDataType { data_type: DataType },
+
+    #[snafu(display("Error serializing read results: {}", source))]
+    Serializing { source: serde_json::Error },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Parsed URI parameters of the request to `/api/v2/read`
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReadParams {
+    pub org: String,
+    pub bucket: String,
+    /// Comma separated buckets of the org read along with `bucket` as one
+    /// dataset, e.g. `metrics_archive`
+    #[serde(default)]
+    union: String,
+    /// The start of the time range, e.g. `-1h`
+    start: String,
+    /// The inclusive end of the time range, now if not set
+    stop: Option<String>,
+    /// Restricts the read to matching rows, in the syntax of delete
+    /// predicates, e.g. `_measurement="cpu" AND host="a"`
+    #[serde(default)]
+    predicate: String,
+    /// Comma separated expressions computed from the fields of each
+    /// measurement and returned instead of its fields, each optionally
+    /// named with `name=`, e.g. `total=usage_user+usage_system`
+    #[serde(default)]
+    fields: String,
+    /// Comma separated tags to group the series by
+    #[serde(default)]
+    group_by: String,
+    /// The duration of the windows to aggregate the points of each series
+    /// over, e.g. `1m`
+    every: Option<String>,
+    /// The aggregate computed for each window
+    agg: Option<ReadAggregate>,
+    /// The percentile, between 0 and 100, computed by `agg=percentile`
+    percentile: Option<f64>,
+    /// How windows without points are filled, omitted if not set
+    fill: Option<ReadFill>,
+    /// The interval of the regular times the points of each series are
+    /// resampled to, e.g. `1m`
+    resample: Option<String>,
+    /// How the values of resampled points are computed, `linear` by default
+    interpolate: Option<ReadInterpolation>,
+    /// The transformation of the points of each series
+    transform: Option<ReadTransform>,
+    /// The time unit of the rates computed by `transform`, e.g. `1m`
+    unit: Option<String>,
+    /// Also reads the time range this much earlier, e.g. `7d`, returning its
+    /// series moved later by as much and tagged with `_shift`
+    shift: Option<String>,
+    /// The number of series with the highest values kept in each window
+    top: Option<usize>,
+    /// The number of series with the lowest values kept in each window
+    bottom: Option<usize>,
+    /// The most points returned in total
+    limit: Option<usize>,
+    /// The most points returned per series
+    series_limit: Option<usize>,
+    /// The number of points skipped at the start of each series
+    #[serde(default)]
+    offset: usize,
+    /// The time order of the points of each series
+    #[serde(default)]
+    pub order: ReadOrder,
+    /// Whether the fields of a measurement are returned as columns aligned
+    /// on time
+    #[serde(default)]
+    pivot: bool,
+    #[serde(default)]
+    pub format: ReadFormat,
+}
+
+impl ReadParams {
+    /// Returns the buckets read, `bucket` first. Where more than one has a
+    /// point of a series at the same time, the point of the first is read.
+    pub fn buckets(&self) -> Vec<&str> {
+        let mut buckets = vec![self.bucket.as_str()];
+        for bucket in self.union.split(',').map(str::trim) {
+            if !bucket.is_empty() && !buckets.contains(&bucket) {
+                buckets.push(bucket);
+            }
+        }
+        buckets
+    }
+
+    /// Returns the predicate selecting the rows to read, for a read at
+    /// `now`, in nanoseconds since the epoch
+    pub fn predicate(&self, now: i64) -> Result<Predicate> {
+        let (start, stop) = self.time_range(now)?;
+        let parsed = ReadPredicate::try_new(&self.predicate).context(InvalidPredicate)?;
+
+        let mut builder = PredicateBuilder::default()
+            .timestamp_range(start, stop.saturating_add(1))
+            .table_option(parsed.table_name);
+        for conjunct in parsed
+            .expr
+            .map(PredicateExpr::into_conjuncts)
+            .unwrap_or_default()
+        {
+            builder = builder.add_expr(to_expr(conjunct)?);
+        }
+        Ok(builder.build())
+    }
+
+    /// Returns the fields computed by the read, none if the fields are read
+    /// as they are
+    pub fn computed_fields(&self) -> Result<Vec<ComputedField>> {
+        let fields = self
+            .fields
+            .split(',')
+            .map(str::trim)
+            .filter(|field| !field.is_empty())
+            .map(|field| {
+                // the expressions can't contain `=`, so the first one ends
+                // the name
+                let (name, expression) = match field.find('=') {
+                    Some(i) => (field[..i].trim(), &field[i + 1..]),
+                    None => (field, field),
+                };
+                Ok(ComputedField {
+                    name: name.to_string(),
+                    expr: predicate::parse_field_expr(expression).context(InvalidField)?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        ensure!(
+            fields.is_empty() || !self.agg.map_or(false, ReadAggregate::is_selector),
+            ComputedSelector
+        );
+        Ok(fields)
+    }
+
+    /// Returns the read of the time range `shift` earlier, for a read at
+    /// `now`, `None` if the read isn't shifted
+    pub fn shifted(&self, now: i64) -> Result<Option<ShiftedRead>> {
+        let label = match &self.shift {
+            Some(shift) => shift.trim(),
+            None => return Ok(None),
+        };
+        let shift = parse_duration(label)
+            .filter(|&nanoseconds| nanoseconds > 0)
+            .context(InvalidShift { value: label })?;
+
+        let (start, stop) = self.time_range(now)?;
+        let params = Self {
+            start: start.saturating_sub(shift).to_string(),
+            stop: Some(stop.saturating_sub(shift).to_string()),
+            shift: None,
+            ..self.clone()
+        };
+        Ok(Some(ShiftedRead {
+            params,
+            shift,
+            label: label.to_string(),
+        }))
+    }
+
+    /// Returns the inclusive time range of the data covered by the read at
+    /// `now`, including the earlier range of a shifted read. A read whose
+    /// range ends at now is open ended, as the same read later covers the
+    /// data written since.
+    pub fn covered_range(&self, now: i64) -> Result<(i64, i64)> {
+        let (start, stop) = self.time_range(now)?;
+        let start = match self.shifted(now)? {
+            Some(shifted) => start.saturating_sub(shifted.shift),
+            None => start,
+        };
+        let relative_stop = self
+            .stop
+            .as_ref()
+            .map_or(true, |stop| stop.starts_with('-'));
+        let stop = if relative_stop { i64::MAX } else { stop };
+        Ok((start, stop))
+    }
+
+    /// Returns the inclusive time range of the read at `now`
+    fn time_range(&self, now: i64) -> Result<(i64, i64)> {
+        let start = parse_time("start", &self.start, now)?;
+        let stop = match &self.stop {
+            Some(stop) => parse_time("stop", stop, now)?,
+            None => now,
+        };
+        ensure!(start <= stop, InvalidTimeRange { start, stop });
+        Ok((start, stop))
+    }
+
+    /// Returns how the series are grouped and aggregated, `None` if the
+    /// series are read as they are
+    pub fn group_by_and_aggregate(&self) -> Result<Option<GroupByAndAggregate>> {
+        let group_columns = self.group_columns();
+        match (self.every()?, self.agg) {
+            (None, None) if group_columns.is_empty() => Ok(None),
+            (None, None) => Ok(Some(GroupByAndAggregate::Columns {
+                agg: Aggregate::None,
+                group_columns,
+            })),
+            (None, Some(agg)) => {
+                ensure!(agg.is_selector(), UnwindowedAggregate);
+                Ok(Some(GroupByAndAggregate::Columns {
+                    agg: self.aggregate(agg)?,
+                    group_columns,
+                }))
+            }
+            (Some(every), Some(agg)) => {
+                ensure!(group_columns.is_empty(), GroupedWindow);
+                Ok(Some(GroupByAndAggregate::Window {
+                    agg: self.aggregate(agg)?,
+                    every: WindowDuration::from_nanoseconds(every),
+                    offset: WindowDuration::empty(),
+                }))
+            }
+            (Some(_), None) => IncompleteWindow.fail(),
+        }
+    }
+
+    /// Returns the aggregate computed by `agg`
+    fn aggregate(&self, agg: ReadAggregate) -> Result<Aggregate> {
+        Ok(match agg {
+            ReadAggregate::Mean => Aggregate::Mean,
+            ReadAggregate::Median => Aggregate::Percentile(50.0),
+            ReadAggregate::Percentile => {
+                let percentile = self
+                    .percentile
+                    .filter(|percentile| (0.0..=100.0).contains(percentile))
+                    .context(InvalidPercentile)?;
+                Aggregate::Percentile(percentile)
+            }
+            ReadAggregate::Sum => Aggregate::Sum,
+            ReadAggregate::Count => Aggregate::Count,
+            ReadAggregate::CountDistinct => Aggregate::CountDistinct,
+            ReadAggregate::Min => Aggregate::Min,
+            ReadAggregate::Max => Aggregate::Max,
+            ReadAggregate::First => Aggregate::First,
+            ReadAggregate::Last => Aggregate::Last,
+        })
+    }
+
+    /// Returns the duration of the windows in nanoseconds, `None` if the
+    /// read isn't windowed
+    fn every(&self) -> Result<Option<i64>> {
+        self.every
+            .as_ref()
+            .map(|every| {
+                parse_duration(every)
+                    .filter(|&nanoseconds| nanoseconds > 0)
+                    .context(InvalidEvery { value: every })
+            })
+            .transpose()
+    }
+
+    /// Returns the tags to group the series by, none if they are not
+    /// grouped
+    fn group_columns(&self) -> Vec<String> {
+        self.group_by
+            .split(',')
+            .map(str::trim)
+            .filter(|tag| !tag.is_empty())
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    /// Returns how the points of each series are returned, for a read at
+    /// `now`
+    pub fn options(&self, now: i64) -> Result<ReadOptions> {
+        ensure!(!self.pivot || self.group_columns().is_empty(), GroupedPivot);
+        let fill = match self.fill {
+            Some(policy) => {
+                let every = self.every()?.context(UnwindowedFill)?;
+                ensure!(
+                    !self.agg.map_or(false, ReadAggregate::is_selector),
+                    FilledSelector
+                );
+                let (start, stop) = self.time_range(now)?;
+                Some(WindowFill::try_new(policy, every, start, stop)?)
+            }
+            None => None,
+        };
+        let resample = match &self.resample {
+            Some(every) => {
+                let every = parse_duration(every)
+                    .filter(|&nanoseconds| nanoseconds > 0)
+                    .context(InvalidResample { value: every })?;
+                ensure!(self.every.is_none() && self.agg.is_none(), WindowedResample);
+                let (start, stop) = self.time_range(now)?;
+                let interpolation = self.interpolate.unwrap_or_default();
+                Some(Resample::try_new(interpolation, every, start, stop)?)
+            }
+            None => None,
+        };
+        let transform = match self.transform {
+            Some(transform) => {
+                let unit = match &self.unit {
+                    Some(unit) => parse_duration(unit)
+                        .filter(|&nanoseconds| nanoseconds > 0)
+                        .context(InvalidUnit { value: unit })?,
+                    None => 1_000_000_000,
+                };
+                Some(SeriesTransform { transform, unit })
+            }
+            None => None,
+        };
+        Ok(ReadOptions {
+            order: self.order,
+            pivot: self.pivot,
+            top: self.top_n()?,
+            fill,
+            resample,
+            transform,
+            limits: self.limits(),
+        })
+    }
+
+    /// Returns how the series of each window are ranked by `top` or
+    /// `bottom`, `None` if they aren't
+    fn top_n(&self) -> Result<Option<TopN>> {
+        let (ranking, n) = match (self.top, self.bottom) {
+            (None, None) => return Ok(None),
+            (Some(n), None) => (Ranking::Top, n),
+            (None, Some(n)) => (Ranking::Bottom, n),
+            (Some(_), Some(_)) => return InvalidTop.fail(),
+        };
+        ensure!(n > 0, InvalidTop);
+        ensure!(self.buckets().len() == 1, UnionTop);
+        let every = self.every()?.context(UnwindowedTop)?;
+        let agg = self.agg.context(UnwindowedTop)?;
+        Ok(Some(TopN {
+            ranking,
+            n,
+            selector_every: if agg.is_selector() { Some(every) } else { None },
+        }))
+    }
+
+    fn limits(&self) -> ReadLimits {
+        ReadLimits {
+            offset: self.offset,
+            series_limit: self.series_limit,
+            limit: self.limit,
+        }
+    }
+}
+
+/// The tag of the series of the earlier time range of a shifted read
+pub const SHIFT_TAG: &str = "_shift";
+
+/// The second pass of a shifted read, over the earlier time range
+#[derive(Debug, Clone)]
+pub struct ShiftedRead {
+    /// The parameters of the read of the earlier time range
+    pub params: ReadParams,
+    /// How much earlier the time range is, in nanoseconds
+    shift: i64,
+    /// The shift as requested, the value of the `_shift` tag
+    label: String,
+}
+
+/// A field computed from the fields of each measurement read
+#[derive(Debug, Clone, PartialEq)]
+pub struct ComputedField {
+    pub name: String,
+    pub expr: FieldExpr,
+}
+
+/// Returns `plans` computing `fields` from the fields of each measurement
+/// as the rows are read, instead of returning its fields. The fields that
+/// need fields a measurement doesn't have are not computed for it, and
+/// measurements without any computed fields are not read.
+pub fn compute_fields(plans: SeriesSetPlans, fields: &[ComputedField]) -> Result<SeriesSetPlans> {
+    if fields.is_empty() {
+        return Ok(plans);
+    }
+
+    let mut computed = vec![];
+    for plan in plans.plans {
+        let field_columns = match &plan.field_columns {
+            FieldColumns::SharedTimestamp(field_columns) => field_columns,
+            FieldColumns::DifferentTimestamp(_) => return ComputedSelector.fail(),
+        };
+        let fields: Vec<_> = fields
+            .iter()
+            .filter(|field| {
+                field
+                    .expr
+                    .columns()
+                    .iter()
+                    .all(|&column| field_columns.iter().any(|c| c.as_str() == column))
+            })
+            .collect();
+        if fields.is_empty() {
+            continue;
+        }
+
+        let mut select_exprs: Vec<_> = plan
+            .tag_columns
+            .iter()
+            .map(|tag| col(tag.as_str()))
+            .collect();
+        select_exprs.extend(
+            fields
+                .iter()
+                .map(|field| to_field_expr(field.expr.clone(), false).alias(&field.name)),
+        );
+        select_exprs.push(col(TIME_COLUMN_NAME));
+        let projected = LogicalPlanBuilder::from(&plan.plan)
+            .project(&select_exprs)
+            .and_then(|builder| builder.build())
+            .context(PlanningFields)?;
+
+        computed.push(SeriesSetPlan {
+            plan: projected,
+            field_columns: fields
+                .iter()
+                .map(|field| Arc::new(field.name.clone()))
+                .collect::<Vec<_>>()
+                .into(),
+            ..plan
+        });
+    }
+    Ok(computed.into())
+}
+
+/// How the points of each series of a read are returned
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ReadOptions {
+    pub order: ReadOrder,
+    /// Whether the series of each measurement and set of tag values are
+    /// pivoted into a table. The limits apply to the series, before
+    /// pivoting.
+    pub pivot: bool,
+    /// Keeps the top (or bottom) series of each window, ranked before the
+    /// points are filled, transformed and limited
+    pub top: Option<TopN>,
+    pub fill: Option<WindowFill>,
+    /// Resamples the points before they are transformed
+    pub resample: Option<Resample>,
+    pub transform: Option<SeriesTransform>,
+    pub limits: ReadLimits,
+}
+
+impl ReadOptions {
+    /// Returns the options reading the series of one of several buckets,
+    /// whose points are processed with these options once merged
+    pub fn unprocessed(&self) -> Self {
+        Self::default()
+    }
+}
+
+/// Limits on the points returned by a read. `limit` counts down as points
+/// are collected.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ReadLimits {
+    /// The number of points skipped at the start of each series
+    pub offset: usize,
+    /// The most points returned per series
+    pub series_limit: Option<usize>,
+    /// The most points left to return in total
+    pub limit: Option<usize>,
+}
+
+impl ReadLimits {
+    /// Whether no more points can be returned
+    fn exhausted(&self) -> bool {
+        self.limit == Some(0)
+    }
+
+    /// Returns the points of a series in `order` within the limits,
+    /// counting them against the total
+    fn take<I: DoubleEndedIterator>(&mut self, order: ReadOrder, points: I) -> Vec<I::Item> {
+        let max = self
+            .series_limit
+            .unwrap_or(usize::MAX)
+            .min(self.limit.unwrap_or(usize::MAX));
+        let points: Vec<_> = match order {
+            ReadOrder::Asc => points.skip(self.offset).take(max).collect(),
+            ReadOrder::Desc => points.rev().skip(self.offset).take(max).collect(),
+        };
+        if let Some(limit) = &mut self.limit {
+            *limit -= points.len();
+        }
+        points
+    }
+}
+
+/// The most windows a series can be filled to
+const MAX_FILLED_WINDOWS: i64 = 100_000;
+
+/// Fills the windows without points of the series of a windowed read
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WindowFill {
+    policy: ReadFill,
+    /// The duration of the windows in nanoseconds
+    every: i64,
+    /// The times of the first and last windows of the read, which are the
+    /// ends of the windows as computed by the storage layer
+    first: i64,
+    last: i64,
+}
+
+impl WindowFill {
+    /// Creates a fill for windows of `every` nanoseconds over the inclusive
+    /// time range from `start` to `stop`
+    fn try_new(policy: ReadFill, every: i64, start: i64, stop: i64) -> Result<Self> {
+        let window = Window::new(
+            Duration::from_nsecs(every),
+            Duration::from_nsecs(0),
+            Duration::from_nsecs(0),
+        );
+        let first = window.get_earliest_bounds(start).stop;
+        let last = window.get_earliest_bounds(stop).stop;
+
+        let count = (last - first) / every + 1;
+        ensure!(
+            count <= MAX_FILLED_WINDOWS,
+            TooManyWindows {
+                count,
+                max: MAX_FILLED_WINDOWS
+            }
+        );
+
+        Ok(Self {
+            policy,
+            every,
+            first,
+            last,
+        })
+    }
+
+    /// Returns `points`, ordered by time, with a point for every window
+    fn apply(&self, points: Vec<(i64, FieldValue)>) -> Vec<(i64, FieldValue)> {
+        let zero = points
+            .first()
+            .map_or(FieldValue::Null, |(_, value)| value.zero());
+        let mut points = points.into_iter().peekable();
+        let mut previous: Option<(i64, FieldValue)> = None;
+
+        let mut filled = vec![];
+        for time in (self.first..=self.last).step_by(self.every as usize) {
+            // points are at window times, skip any that aren't
+            while points.peek().map_or(false, |(t, _)| *t < time) {
+                points.next();
+            }
+            if points.peek().map_or(false, |(t, _)| *t == time) {
+                let point = points.next().expect("just peeked");
+                previous = Some(point.clone());
+                filled.push(point);
+                continue;
+            }
+
+            let value = match (self.policy, &previous, points.peek()) {
+                (ReadFill::Previous, Some((_, value)), _) => value.clone(),
+                (ReadFill::Zero, _, _) => zero.clone(),
+                (ReadFill::Linear, Some(previous), Some(next)) => {
+                    FieldValue::interpolate(previous, next, time)
+                }
+                _ => FieldValue::Null,
+            };
+            filled.push((time, value));
+        }
+        filled
+    }
+}
+
+/// Aligns the irregular points of the series of a read onto regular times,
+/// interpolating their values
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Resample {
+    interpolation: ReadInterpolation,
+    /// The interval between the times in nanoseconds
+    every: i64,
+    /// The first and last times, the first and last multiples of `every`
+    /// in the time range of the read
+    first: i64,
+    last: i64,
+}
+
+impl Resample {
+    /// Creates a resampling to every multiple of `every` nanoseconds in
+    /// the inclusive time range from `start` to `stop`
+    fn try_new(
+        interpolation: ReadInterpolation,
+        every: i64,
+        start: i64,
+        stop: i64,
+    ) -> Result<Self> {
+        let first = match start.rem_euclid(every) {
+            0 => start,
+            rem => start - rem + every,
+        };
+        let last = stop - stop.rem_euclid(every);
+
+        let count = (last - first) / every + 1;
+        ensure!(
+            count <= MAX_FILLED_WINDOWS,
+            TooManyResampledPoints {
+                count,
+                max: MAX_FILLED_WINDOWS
+            }
+        );
+
+        Ok(Self {
+            interpolation,
+            every,
+            first,
+            last,
+        })
+    }
+
+    /// Returns the values of `points`, ordered by time, at the resampled
+    /// times. Times before the first point, or with linear interpolation
+    /// after the last, have no value so are omitted.
+    fn apply(&self, points: Vec<(i64, FieldValue)>) -> Vec<(i64, FieldValue)> {
+        let mut points = points.into_iter().peekable();
+        let mut previous: Option<(i64, FieldValue)> = None;
+
+        let mut resampled = vec![];
+        for time in (self.first..=self.last).step_by(self.every as usize) {
+            while points.peek().map_or(false, |(t, _)| *t <= time) {
+                previous = points.next();
+            }
+
+            let value = match (self.interpolation, &previous, points.peek()) {
+                (_, Some((t, value)), _) if *t == time => value.clone(),
+                (ReadInterpolation::Previous, Some((_, value)), _) => value.clone(),
+                // strings and booleans can't be interpolated, so are null
+                (ReadInterpolation::Linear, Some(previous), Some(next)) => {
+                    FieldValue::interpolate(previous, next, time)
+                }
+                _ => FieldValue::Null,
+            };
+            if value != FieldValue::Null {
+                resampled.push((time, value));
+            }
+        }
+        resampled
+    }
+}
+
+/// Converts the logical expression of a read predicate into the expression
+/// evaluated against the rows of the bucket
+fn to_expr(expr: PredicateExpr) -> Result<Expr> {
+    Ok(match expr {
+        PredicateExpr::Comparison(Comparison { column, op, value }) => match op {
+            ComparisonOp::Equal => col(&column).eq(lit(value)),
+            ComparisonOp::RegexMatch | ComparisonOp::RegexNotMatch => {
+                let regex = Regex::new(&value).context(InvalidRegex { pattern: &value })?;
+                regex_match_expr(col(&column), regex, op == ComparisonOp::RegexMatch)
+            }
+        },
+        PredicateExpr::FieldComparison(FieldComparison { left, op, right }) => {
+            let (left, right) = (to_field_expr(left, false), to_field_expr(right, false));
+            match op {
+                FieldComparisonOp::Equal => left.eq(right),
+                // the mutable buffer doesn't evaluate `!=`
+                FieldComparisonOp::NotEqual => Expr::Not(Box::new(left.eq(right))),
+                FieldComparisonOp::Less => left.lt(right),
+                FieldComparisonOp::LessEqual => left.lt_eq(right),
+                FieldComparisonOp::Greater => left.gt(right),
+                FieldComparisonOp::GreaterEqual => left.gt_eq(right),
+            }
+        }
+        PredicateExpr::And(left, right) => to_expr(*left)?.and(to_expr(*right)?),
+        PredicateExpr::Or(left, right) => to_expr(*left)?.or(to_expr(*right)?),
+        PredicateExpr::Not(expr) => Expr::Not(Box::new(to_expr(*expr)?)),
+    })
+}
+
+/// Converts a field expression into the expression computing it. The
+/// columns of arithmetic are cast to floats, so that integer fields aren't
+/// divided with integer division.
+fn to_field_expr(expr: FieldExpr, in_arithmetic: bool) -> Expr {
+    match expr {
+        FieldExpr::Column(column) if in_arithmetic => Expr::Cast {
+            expr: Box::new(col(&column)),
+            data_type: DataType::Float64,
+        },
+        FieldExpr::Column(column) => col(&column),
+        FieldExpr::Number(number) => lit(number),
+        FieldExpr::Binary { left, op, right } => {
+            let op = match op {
+                ArithmeticOp::Add => Operator::Plus,
+                ArithmeticOp::Subtract => Operator::Minus,
+                ArithmeticOp::Multiply => Operator::Multiply,
+                ArithmeticOp::Divide => Operator::Divide,
+            };
+            binary_expr(to_field_expr(*left, true), op, to_field_expr(*right, true))
+        }
+    }
+}
+
+/// Parses the time `value` of the parameter `name` into nanoseconds since
+/// the epoch: a negative duration such as `-5m` before `now`, an RFC3339
+/// time or a number of nanoseconds
+fn parse_time(name: &'static str, value: &str, now: i64) -> Result<i64> {
+    let relative = value.strip_prefix('-').and_then(parse_duration);
+    relative
+        .map(|duration| now.saturating_sub(duration))
+        .or_else(|| value.parse().ok())
+        .or_else(|| {
+            chrono::DateTime::parse_from_rfc3339(value)
+                .ok()
+                .and_then(|time| timestamp_nanos(&time))
+        })
+        .context(InvalidTime { name, value })
+}
+
+/// Returns the nanoseconds since the epoch of `time`, `None` if they don't
+/// fit in an `i64`, for which `DateTime::timestamp_nanos` would panic
+pub fn timestamp_nanos<Tz: chrono::TimeZone>(time: &chrono::DateTime<Tz>) -> Option<i64> {
+    time.timestamp()
+        .checked_mul(1_000_000_000)
+        .and_then(|nanos| nanos.checked_add(time.timestamp_subsec_nanos().into()))
+}
+
+/// Parses a duration such as `5m` into nanoseconds
+pub fn parse_duration(value: &str) -> Option<i64> {
+    let unit_start = value.find(|c: char| !c.is_ascii_digit())?;
+    let count: i64 = value[..unit_start].parse().ok()?;
+    let unit_nanos: i64 = match &value[unit_start..] {
+        "ms" => 1_000_000,
+        "s" => 1_000_000_000,
+        "m" => 60 * 1_000_000_000,
+        "h" => 60 * 60 * 1_000_000_000,
+        "d" => 24 * 60 * 60 * 1_000_000_000,
+        _ => return None,
+    };
+    Some(count.saturating_mul(unit_nanos))
+}
+
+/// The aggregates reads can compute
+#[derive(Deserialize, Debug, Copy, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReadAggregate {
+    Mean,
+    /// Approximate median
+    Median,
+    /// Approximation of the percentile given by the `percentile` parameter
+    Percentile,
+    Sum,
+    Count,
+    /// Number of distinct values
+    #[serde(rename = "count_distinct")]
+    CountDistinct,
+    Min,
+    Max,
+    First,
+    Last,
+}
+
+impl ReadAggregate {
+    /// Whether this aggregate selects one of the points, keeping its time
+    fn is_selector(self) -> bool {
+        matches!(self, Self::Min | Self::Max | Self::First | Self::Last)
+    }
+}
+
+/// The time order of the points of a series
+#[derive(Deserialize, Debug, Copy, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReadOrder {
+    Asc,
+    Desc,
+}
+
+impl Default for ReadOrder {
+    fn default() -> Self {
+        Self::Asc
+    }
+}
+
+/// Transforms the points of the series of a read into their rate of change
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SeriesTransform {
+    transform: ReadTransform,
+    /// The time unit of the rates in nanoseconds
+    unit: i64,
+}
+
+impl SeriesTransform {
+    /// Returns the rates of change between consecutive numeric `points`,
+    /// ordered by time, each at the time of the later point
+    fn apply(&self, points: Vec<(i64, FieldValue)>) -> Vec<(i64, FieldValue)> {
+        let mut previous: Option<(i64, f64)> = None;
+        let mut rates = vec![];
+        for (time, value) in points {
+            // skips strings, booleans and filled nulls
+            let value = match value.as_f64() {
+                Some(value) => value,
+                None => continue,
+            };
+            if let Some((previous_time, previous_value)) = previous {
+                let elapsed = (time - previous_time) as f64 / self.unit as f64;
+                let rate = match self.transform {
+                    ReadTransform::Derivative => Some((value - previous_value) / elapsed),
+                    ReadTransform::NonNegativeDerivative => {
+                        Some((value - previous_value) / elapsed).filter(|&rate| rate >= 0.0)
+                    }
+                    // a counter that decreased was reset, and counted up
+                    // to `value` since
+                    ReadTransform::Rate if value < previous_value => Some(value / elapsed),
+                    ReadTransform::Rate => Some((value - previous_value) / elapsed),
+                };
+                if let Some(rate) = rate.filter(|_| elapsed > 0.0) {
+                    rates.push((time, FieldValue::Float(rate)));
+                }
+            }
+            previous = Some((time, value));
+        }
+        rates
+    }
+}
+
+/// The transformations of the points of a series
+#[derive(Deserialize, Debug, Copy, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReadTransform {
+    /// The rate of change between consecutive points
+    Derivative,
+    /// The rate of change, dropping the decreases
+    NonNegativeDerivative,
+    /// The rate of change of a counter, for which decreases are resets
+    Rate,
+}
+
+/// How the values of resampled points are computed from the points around
+/// them
+#[derive(Deserialize, Debug, Copy, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReadInterpolation {
+    /// The linear interpolation of the points before and after
+    Linear,
+    /// The value of the point before
+    Previous,
+}
+
+impl Default for ReadInterpolation {
+    fn default() -> Self {
+        Self::Linear
+    }
+}
+
+/// How windows without points are filled
+#[derive(Deserialize, Debug, Copy, Clone, PartialEq)]
+pub enum ReadFill {
+    /// With null
+    #[serde(rename = "null")]
+    Null,
+    /// With the value of the previous window, null if there is none
+    #[serde(rename = "previous")]
+    Previous,
+    /// With zero, null if the field isn't numeric
+    #[serde(rename = "0")]
+    Zero,
+    /// With the linear interpolation of the values of the windows around
+    /// them, null if there are none or the field isn't numeric
+    #[serde(rename = "linear")]
+    Linear,
+}
+
+/// The output format of reads
+#[derive(Deserialize, Debug, Copy, Clone, PartialEq)]
+pub enum ReadFormat {
+    #[serde(rename = "csv")]
+    Csv,
+    #[serde(rename = "json")]
+    Json,
+}
+
+impl Default for ReadFormat {
+    fn default() -> Self {
+        Self::Csv
+    }
+}
+
+impl ReadFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Csv => "text/csv",
+            Self::Json => "application/json",
+        }
+    }
+}
+
+/// The value of a field at some time
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum FieldValue {
+    Float(f64),
+    Integer(i64),
+    UInteger(u64),
+    String(String),
+    Boolean(bool),
+    /// A window without points filled with null
+    Null,
+}
+
+impl FieldValue {
+    /// Returns the zero value of the type of this value, null if it isn't
+    /// numeric
+    fn zero(&self) -> Self {
+        match self {
+            Self::Float(_) => Self::Float(0.0),
+            Self::Integer(_) => Self::Integer(0),
+            Self::UInteger(_) => Self::UInteger(0),
+            _ => Self::Null,
+        }
+    }
+
+    fn as_f64(&self) -> Option<f64> {
+        match self {
+            Self::Float(v) => Some(*v),
+            Self::Integer(v) => Some(*v as f64),
+            Self::UInteger(v) => Some(*v as f64),
+            _ => None,
+        }
+    }
+
+    /// Returns the value at `time` on the line between the points `previous`
+    /// and `next`, of the type of `previous`, null if either isn't numeric
+    fn interpolate(previous: &(i64, Self), next: &(i64, Self), time: i64) -> Self {
+        let ((t0, v0), (t1, v1)) = (previous, next);
+        let (v0, v1) = match (v0.as_f64(), v1.as_f64()) {
+            (Some(v0), Some(v1)) => (v0, v1),
+            _ => return Self::Null,
+        };
+        let value = v0 + (v1 - v0) * (time - t0) as f64 / (t1 - t0) as f64;
+        match previous.1 {
+            Self::Float(_) => Self::Float(value),
+            Self::Integer(_) => Self::Integer(value.round() as i64),
+            Self::UInteger(_) => Self::UInteger(value.round() as u64),
+            _ => Self::Null,
+        }
+    }
+}
+
+impl fmt::Display for FieldValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Float(v) => write!(f, "{}", v),
+            Self::Integer(v) => write!(f, "{}", v),
+            Self::UInteger(v) => write!(f, "{}", v),
+            Self::String(v) => write!(f, "{}", v),
+            Self::Boolean(v) => write!(f, "{}", v),
+            Self::Null => Ok(()),
+        }
+    }
+}
+
+/// The values of one field of a measurement for one set of tag values
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Series {
+    pub measurement: String,
+    pub tags: BTreeMap<String, String>,
+    pub field: String,
+    /// (timestamp, value) pairs, in the time order of the read
+    pub points: Vec<(i64, FieldValue)>,
+}
+
+/// The series sharing the same values for the tags grouped by
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Group {
+    pub tags: BTreeMap<String, String>,
+    pub series: Vec<Series>,
+}
+
+/// The fields of a measurement for one set of tag values, as columns with
+/// rows aligned on time
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Table {
+    pub measurement: String,
+    pub tags: BTreeMap<String, String>,
+    pub fields: Vec<String>,
+    /// The rows, in the time order of the read
+    pub rows: Vec<Row>,
+}
+
+impl Table {
+    /// Aligns `series`, the fields of one measurement for one set of tag
+    /// values, on time, with rows in `order`. Returns `None` if there are no
+    /// series.
+    fn pivot(series: Vec<Series>, order: ReadOrder) -> Option<Self> {
+        let first = series.first()?;
+        let measurement = first.measurement.clone();
+        let tags = first.tags.clone();
+        let fields: Vec<_> = series.iter().map(|series| series.field.clone()).collect();
+
+        let mut rows = BTreeMap::new();
+        for (i, series) in series.into_iter().enumerate() {
+            for (time, value) in series.points {
+                rows.entry(time)
+                    .or_insert_with(|| vec![FieldValue::Null; fields.len()])[i] = value;
+            }
+        }
+        let rows = rows.into_iter().map(|(time, values)| Row { time, values });
+        let rows = match order {
+            ReadOrder::Asc => rows.collect(),
+            ReadOrder::Desc => rows.rev().collect(),
+        };
+
+        Some(Self {
+            measurement,
+            tags,
+            fields,
+            rows,
+        })
+    }
+}
+
+/// The values of the fields of a table at some time, null for the fields
+/// without a point at that time. Serialized as `[time, value1, ...]`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Row {
+    pub time: i64,
+    pub values: Vec<FieldValue>,
+}
+
+impl Serialize for Row {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.values.len() + 1))?;
+        seq.serialize_element(&self.time)?;
+        for value in &self.values {
+            seq.serialize_element(value)?;
+        }
+        seq.end()
+    }
+}
+
+/// The results of a read
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReadResults {
+    Series(Vec<Series>),
+    Groups(Vec<Group>),
+    Tables(Vec<Table>),
+}
+
+impl ReadResults {
+    /// Runs `plans` with `executor`, collecting the points of the resulting
+    /// series as set by `options`. The plans come from `query_groups` if
+    /// `grouped` is set, or from `query_series` otherwise.
+    pub async fn execute(
+        executor: &Executor,
+        plans: SeriesSetPlans,
+        grouped: bool,
+        mut options: ReadOptions,
+    ) -> Result<Self> {
+        let (tx, mut rx) = mpsc::channel(4);
+        let top = options.top;
+
+        // moves `rx` so that it is dropped once the limit is reached, which
+        // stops the execution
+        let collect = async move {
+            let mut results = if grouped {
+                Self::Groups(vec![])
+            } else if options.pivot {
+                Self::Tables(vec![])
+            } else {
+                Self::Series(vec![])
+            };
+            while !options.limits.exhausted() {
+                match rx.recv().await {
+                    Some(item) => results.push(item.context(ComputingSeries)?, &mut options)?,
+                    None => return Ok((results, false)),
+                }
+            }
+            Ok::<_, Error>((results, true))
+        };
+        let execute = async {
+            match top {
+                Some(top) => executor.to_top_series_set(plans, top, tx).await,
+                None => executor.to_series_set(plans, tx).await,
+            }
+        };
+        let (executed, collected) = futures::join!(execute, collect);
+
+        // an error collecting the results makes the execution fail, so
+        // report it first
+        let (results, stopped) = collected?;
+        // stopping early fails the execution as it can't send the rest
+        if !stopped {
+            executed.context(Executing)?;
+        }
+        Ok(results)
+    }
+
+    fn push(&mut self, item: SeriesSetItem, options: &mut ReadOptions) -> Result<()> {
+        match (self, item) {
+            (Self::Groups(groups), SeriesSetItem::GroupStart(group)) => groups.push(Group {
+                tags: convert_tags(&group.tags),
+                series: vec![],
+            }),
+            (Self::Groups(groups), SeriesSetItem::Data(series_set)) => {
+                if groups.is_empty() {
+                    groups.push(Group {
+                        tags: BTreeMap::new(),
+                        series: vec![],
+                    });
+                }
+                let group = groups.last_mut().expect("just checked");
+                group
+                    .series
+                    .extend(series_set_to_series(&series_set, options)?);
+            }
+            (Self::Series(series), SeriesSetItem::Data(series_set)) => {
+                series.extend(series_set_to_series(&series_set, options)?)
+            }
+            // a series set is one measurement for one set of tag values
+            (Self::Tables(tables), SeriesSetItem::Data(series_set)) => {
+                let series = series_set_to_series(&series_set, options)?;
+                tables.extend(Table::pivot(series, options.order))
+            }
+            // groups only start if the read is grouped
+            (Self::Series(_), SeriesSetItem::GroupStart(_))
+            | (Self::Tables(_), SeriesSetItem::GroupStart(_)) => {}
+        }
+        Ok(())
+    }
+
+    /// Runs `plans` like `execute`, but serializes the results as CSV as the
+    /// series sets are computed rather than once all are, sending chunks of
+    /// about `chunk_size` bytes to the returned channel. Only the last chunk
+    /// is smaller than `chunk_size`. The execution stops if the channel is
+    /// dropped.
+    pub fn execute_csv(
+        executor: Arc<Executor>,
+        plans: SeriesSetPlans,
+        grouped: bool,
+        mut options: ReadOptions,
+        chunk_size: usize,
+    ) -> mpsc::Receiver<Result<Bytes>> {
+        let (chunks_tx, chunks_rx) = mpsc::channel(2);
+
+        tokio::spawn(async move {
+            let (tx, mut rx) = mpsc::channel(4);
+            let top = options.top;
+
+            let collect_tx = chunks_tx.clone();
+            let collect = async move {
+                let mut csv = CsvChunks::new(chunk_size);
+                let mut in_group = false;
+                let stopped = loop {
+                    if options.limits.exhausted() {
+                        break true;
+                    }
+                    let item = match rx.recv().await {
+                        Some(item) => item.context(ComputingSeries)?,
+                        None => break false,
+                    };
+                    match item {
+                        SeriesSetItem::GroupStart(group) if grouped => {
+                            csv.blocks
+                                .push_back(CsvBlock::Group(convert_tags(&group.tags)));
+                            in_group = true;
+                        }
+                        SeriesSetItem::GroupStart(_) => {}
+                        SeriesSetItem::Data(series_set) => {
+                            if grouped && !in_group {
+                                csv.blocks.push_back(CsvBlock::Group(BTreeMap::new()));
+                                in_group = true;
+                            }
+                            let series = series_set_to_series(&series_set, &mut options)?;
+                            if options.pivot {
+                                let table = Table::pivot(series, options.order);
+                                csv.blocks.extend(table.map(CsvBlock::Table));
+                            } else {
+                                csv.blocks.extend(series.into_iter().map(CsvBlock::Series));
+                            }
+                        }
+                    }
+                    while let Some(chunk) = csv.next_full() {
+                        if collect_tx.send(Ok(chunk)).await.is_err() {
+                            return Ok(true);
+                        }
+                    }
+                };
+
+                // stops the execution if it is still running
+                drop(rx);
+                for chunk in csv {
+                    if collect_tx.send(Ok(chunk)).await.is_err() {
+                        break;
+                    }
+                }
+                Ok::<_, Error>(stopped)
+            };
+            let execute = async {
+                match top {
+                    Some(top) => executor.to_top_series_set(plans, top, tx).await,
+                    None => executor.to_series_set(plans, tx).await,
+                }
+            };
+            let (executed, collected) = futures::join!(execute, collect);
+
+            // as in `execute`, an error collecting the results is reported
+            // first, and stopping early fails the execution
+            let result = match collected {
+                Ok(true) => Ok(()),
+                Ok(false) => executed.context(Executing),
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                chunks_tx.send(Err(e)).await.ok();
+            }
+        });
+
+        chunks_rx
+    }
+
+    /// Appends the results of `shifted`, moving their points later by the
+    /// shift so that they line up with the points of these results, and
+    /// tagging their series with `_shift`
+    pub fn append_shifted(&mut self, results: Self, shifted: &ShiftedRead) {
+        let tag = |tags: &mut BTreeMap<String, String>| {
+            tags.insert(SHIFT_TAG.to_string(), shifted.label.clone());
+        };
+        let shift_series = |mut series: Series| {
+            tag(&mut series.tags);
+            for (time, _) in &mut series.points {
+                *time = time.saturating_add(shifted.shift);
+            }
+            series
+        };
+
+        match (self, results) {
+            (Self::Series(series), Self::Series(results)) => {
+                series.extend(results.into_iter().map(shift_series))
+            }
+            (Self::Groups(groups), Self::Groups(results)) => {
+                groups.extend(results.into_iter().map(|mut group| {
+                    tag(&mut group.tags);
+                    group.series = group.series.into_iter().map(shift_series).collect();
+                    group
+                }))
+            }
+            (Self::Tables(tables), Self::Tables(results)) => {
+                tables.extend(results.into_iter().map(|mut table| {
+                    tag(&mut table.tags);
+                    for row in &mut table.rows {
+                        row.time = row.time.saturating_add(shifted.shift);
+                    }
+                    table
+                }))
+            }
+            _ => unreachable!("both passes of a shifted read return the same kind of results"),
+        }
+    }
+
+    /// Merges the results of reading several buckets with
+    /// `ReadOptions::unprocessed` into the results of one read with
+    /// `options`. The points of the series with the same measurement, tags
+    /// and field are merged by time, those of earlier results winning where
+    /// more than one has a point at the same time.
+    pub fn merge(results: Vec<Self>, mut options: ReadOptions) -> Self {
+        let mut grouped = false;
+        let mut groups: Vec<Group> = vec![];
+        let mut add_group = |group: Group| match groups.iter_mut().find(|g| g.tags == group.tags) {
+            Some(existing) => existing.series.extend(group.series),
+            None => groups.push(group),
+        };
+        for results in results {
+            match results {
+                Self::Series(series) => add_group(Group {
+                    tags: BTreeMap::new(),
+                    series,
+                }),
+                Self::Groups(results) => {
+                    grouped = true;
+                    results.into_iter().for_each(&mut add_group);
+                }
+                Self::Tables(_) => unreachable!("unprocessed reads aren't pivoted"),
+            }
+        }
+        groups.sort_by(|a, b| a.tags.cmp(&b.tags));
+
+        for group in &mut groups {
+            let mut merged: Vec<Series> = vec![];
+            for series in group.series.drain(..) {
+                let existing = merged.iter_mut().find(|s| {
+                    s.measurement == series.measurement
+                        && s.tags == series.tags
+                        && s.field == series.field
+                });
+                match existing {
+                    Some(existing) => existing.points.extend(series.points),
+                    None => merged.push(series),
+                }
+            }
+            // the sort is stable, so the fields keep their order
+            merged.sort_by(|a, b| (&a.measurement, &a.tags).cmp(&(&b.measurement, &b.tags)));
+
+            for mut series in merged {
+                // the sort is stable, so the earlier results come first
+                series.points.sort_by_key(|(time, _)| *time);
+                series.points.dedup_by_key(|(time, _)| *time);
+                series.points = process_points(series.points, &mut options);
+                if !series.points.is_empty() {
+                    group.series.push(series);
+                }
+            }
+        }
+
+        if grouped {
+            Self::Groups(groups)
+        } else {
+            let series = groups.pop().map(|group| group.series).unwrap_or_default();
+            if options.pivot {
+                let mut tables = vec![];
+                let mut series = series.into_iter().peekable();
+                while let Some(first) = series.next() {
+                    let mut fields = vec![first];
+                    while let Some(next) = series.peek() {
+                        if next.measurement != fields[0].measurement || next.tags != fields[0].tags
+                        {
+                            break;
+                        }
+                        fields.extend(series.next());
+                    }
+                    tables.extend(Table::pivot(fields, options.order));
+                }
+                Self::Tables(tables)
+            } else {
+                Self::Series(series)
+            }
+        }
+    }
+
+    /// Formats the results in `format`
+    pub fn format(self, format: ReadFormat) -> Result<String> {
+        match format {
+            ReadFormat::Csv => Ok(self.into_csv()),
+            ReadFormat::Json => serde_json::to_string(&self).context(Serializing),
+        }
+    }
+
+    /// Returns the results as CSV, serialized as they are iterated in
+    /// chunks of about `chunk_size` bytes
+    pub fn into_csv_chunks(self, chunk_size: usize) -> CsvChunks {
+        let mut chunks = CsvChunks::new(chunk_size);
+        let blocks = &mut chunks.blocks;
+        match self {
+            Self::Series(series) => blocks.extend(series.into_iter().map(CsvBlock::Series)),
+            Self::Groups(groups) => {
+                for group in groups {
+                    blocks.push_back(CsvBlock::Group(group.tags));
+                    blocks.extend(group.series.into_iter().map(CsvBlock::Series));
+                }
+            }
+            Self::Tables(tables) => blocks.extend(tables.into_iter().map(CsvBlock::Table)),
+        }
+        chunks
+    }
+
+    fn into_csv(self) -> String {
+        let mut csv = String::new();
+        for chunk in self.into_csv_chunks(CSV_CHUNK_SIZE) {
+            csv.push_str(std::str::from_utf8(&chunk).expect("CSV is UTF-8"));
+        }
+        csv
+    }
+}
+
+/// The size of the chunks CSV responses are sent in, in bytes
+pub const CSV_CHUNK_SIZE: usize = 32 * 1024;
+
+/// A block of CSV rows with a header, or the `#group` header of the blocks
+/// following it
+#[derive(Debug)]
+enum CsvBlock {
+    Group(BTreeMap<String, String>),
+    Series(Series),
+    Table(Table),
+}
+
+/// Iterates over chunks of the CSV of read results, serializing each as it
+/// is requested. The blocks of rows are separated by an empty line.
+///
+/// The chunks are split off a buffer whose memory is reused for the
+/// following chunks once they are dropped, i.e. sent.
+#[derive(Debug)]
+pub struct CsvChunks {
+    /// The blocks left to serialize after the current one
+    blocks: VecDeque<CsvBlock>,
+    /// The block being serialized, with the values prefixing its rows and
+    /// the index of its next row
+    current: Option<(CsvBlock, String, usize)>,
+    /// Whether any block was serialized yet
+    started: bool,
+    /// Whether the last block serialized was a `#group` header
+    after_group: bool,
+    buf: BytesMut,
+    chunk_size: usize,
+}
+
+impl Iterator for CsvChunks {
+    type Item = Bytes;
+
+    fn next(&mut self) -> Option<Bytes> {
+        while self.buf.len() < self.chunk_size && self.write_row() {}
+        if self.buf.is_empty() {
+            return None;
+        }
+        Some(self.buf.split().freeze())
+    }
+}
+
+impl CsvChunks {
+    fn new(chunk_size: usize) -> Self {
+        Self {
+            blocks: VecDeque::new(),
+            current: None,
+            started: false,
+            after_group: false,
+            buf: BytesMut::with_capacity(chunk_size),
+            chunk_size,
+        }
+    }
+
+    /// Returns the next chunk if the blocks left make up a full one, leaving
+    /// the rest of their rows in the buffer otherwise
+    fn next_full(&mut self) -> Option<Bytes> {
+        while self.buf.len() < self.chunk_size && self.write_row() {}
+        if self.buf.len() < self.chunk_size {
+            return None;
+        }
+        Some(self.buf.split().freeze())
+    }
+
+    /// Appends the next row, or the header of the next block, to the
+    /// buffer. Returns false once all rows have been.
+    fn write_row(&mut self) -> bool {
+        if let Some((block, prefix, row)) = &mut self.current {
+            let buf = &mut self.buf;
+            let written = match block {
+                CsvBlock::Series(series) => series.points.get(*row).map(|(time, value)| {
+                    write!(buf, "{},{},", prefix, time).expect("writing CSV");
+                    write_value(buf, value);
+                    buf.extend_from_slice(b"\n");
+                }),
+                CsvBlock::Table(table) => table.rows.get(*row).map(|row| {
+                    write!(buf, "{},{}", prefix, row.time).expect("writing CSV");
+                    for value in &row.values {
+                        buf.extend_from_slice(b",");
+                        write_value(buf, value);
+                    }
+                    buf.extend_from_slice(b"\n");
+                }),
+                CsvBlock::Group(_) => None,
+            };
+            if written.is_some() {
+                *row += 1;
+                return true;
+            }
+        }
+
+        match self.blocks.pop_front() {
+            Some(block) => {
+                self.write_header(block);
+                true
+            }
+            None => {
+                self.current = None;
+                false
+            }
+        }
+    }
+
+    /// Appends the header of `block` to the buffer, and makes it the block
+    /// being serialized
+    fn write_header(&mut self, block: CsvBlock) {
+        let is_group = matches!(block, CsvBlock::Group(_));
+        if self.started && (is_group || !self.after_group) {
+            self.buf.extend_from_slice(b"\n");
+        }
+        self.started = true;
+        self.after_group = is_group;
+
+        let mut header = String::new();
+        let mut prefix = String::new();
+        match &block {
+            CsvBlock::Group(tags) => {
+                header.push_str("#group");
+                for (key, value) in tags {
+                    header.push(',');
+                    header.push_str(&csv_escape(&format!("{}={}", key, value)));
+                }
+            }
+            CsvBlock::Series(series) => {
+                header.push_str("_measurement");
+                for key in series.tags.keys() {
+                    header.push(',');
+                    header.push_str(&csv_escape(key));
+                }
+                header.push_str(",_field,_time,_value");
+
+                prefix.push_str(&csv_escape(&series.measurement));
+                for value in series.tags.values() {
+                    prefix.push(',');
+                    prefix.push_str(&csv_escape(value));
+                }
+                prefix.push(',');
+                prefix.push_str(&csv_escape(&series.field));
+            }
+            CsvBlock::Table(table) => {
+                header.push_str("_measurement");
+                for key in table.tags.keys() {
+                    header.push(',');
+                    header.push_str(&csv_escape(key));
+                }
+                header.push_str(",_time");
+                for field in &table.fields {
+                    header.push(',');
+                    header.push_str(&csv_escape(field));
+                }
+
+                prefix.push_str(&csv_escape(&table.measurement));
+                for value in table.tags.values() {
+                    prefix.push(',');
+                    prefix.push_str(&csv_escape(value));
+                }
+            }
+        }
+        header.push('\n');
+        self.buf.extend_from_slice(header.as_bytes());
+        self.current = Some((block, prefix, 0));
+    }
+}
+
+/// Appends `value` to `buf`, quoted if it contains characters special to
+/// CSV
+fn write_value(buf: &mut BytesMut, value: &FieldValue) {
+    match value {
+        FieldValue::String(value) => buf.extend_from_slice(csv_escape(value).as_bytes()),
+        value => write!(buf, "{}", value).expect("writing CSV"),
+    }
+}
+
+/// Quotes `value` if it contains characters special to CSV
+fn csv_escape(value: &str) -> String {
+    if value.contains(|c| matches!(c, ',' | '"' | '\n' | '\r')) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn convert_tags(tags: &[(Arc<String>, Arc<String>)]) -> BTreeMap<String, String> {
+    tags.iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect()
+}
+
+/// Converts `series_set` into one series per field as set by `options`,
+/// skipping fields without values. The rows of a series set are sorted by
+/// time, so they are iterated in reverse for descending reads.
+/// Returns the points of a series, in time order, filled, resampled,
+/// transformed and then limited as set by `options`
+fn process_points(
+    mut points: Vec<(i64, FieldValue)>,
+    options: &mut ReadOptions,
+) -> Vec<(i64, FieldValue)> {
+    if points.is_empty() {
+        return points;
+    }
+    if let Some(fill) = &options.fill {
+        points = fill.apply(points);
+    }
+    if let Some(resample) = &options.resample {
+        points = resample.apply(points);
+    }
+    if let Some(transform) = &options.transform {
+        points = transform.apply(points);
+    }
+    options.limits.take(options.order, points.into_iter())
+}
+
+fn series_set_to_series(series_set: &SeriesSet, options: &mut ReadOptions) -> Result<Vec<Series>> {
+    let batch = &series_set.batch;
+    let schema = batch.schema();
+    let rows = series_set.start_row..series_set.start_row + series_set.num_rows;
+    let tags = convert_tags(&series_set.tags);
+
+    let mut series = vec![];
+    for indexes in series_set.field_indexes.as_slice() {
+        let timestamps = batch
+            .column(indexes.timestamp_index)
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .expect("timestamps are Int64");
+        let values = batch.column(indexes.value_index);
+
+        let rows = rows.clone().filter(|&row| !values.is_null(row));
+        let point = |row: usize| -> Result<(i64, FieldValue)> {
+            Ok((timestamps.value(row), field_value(values, row)?))
+        };
+        let points = if options.fill.is_none()
+            && options.resample.is_none()
+            && options.transform.is_none()
+        {
+            // only the rows within the limits need converting
+            options
+                .limits
+                .take(options.order, rows)
+                .into_iter()
+                .map(point)
+                .collect::<Result<Vec<_>>>()?
+        } else {
+            process_points(rows.map(point).collect::<Result<Vec<_>>>()?, options)
+        };
+
+        if !points.is_empty() {
+            series.push(Series {
+                measurement: series_set.table_name.to_string(),
+                tags: tags.clone(),
+                field: schema.field(indexes.value_index).name().clone(),
+                points,
+            });
+        }
+    }
+    Ok(series)
+}
+
+fn field_value(array: &ArrayRef, row: usize) -> Result<FieldValue> {
+    let any = array.as_any();
+    Ok(match array.data_type() {
+        DataType::Float64 => {
+            FieldValue::Float(any.downcast_ref::<Float64Array>().unwrap().value(row))
+        }
+        DataType::Int64 => {
+            FieldValue::Integer(any.downcast_ref::<Int64Array>().unwrap().value(row))
+        }
+        DataType::UInt64 => {
+            FieldValue::UInteger(any.downcast_ref::<UInt64Array>().unwrap().value(row))
+        }
+        DataType::Utf8 => FieldValue::String(
+            any.downcast_ref::<StringArray>()
+                .unwrap()
+                .value(row)
+                .to_string(),
+        ),
+        DataType::Boolean => {
+            FieldValue::Boolean(any.downcast_ref::<BooleanArray>().unwrap().value(row))
+        }
+        data_type => {
+            return UnsupportedDataType {
+                data_type: data_type.clone(),
+            }
+            .fail()
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn series(measurement: &str, host: &str, points: Vec<(i64, FieldValue)>) -> Series {
+        Series {
+            measurement: measurement.to_string(),
+            tags: vec![("host".to_string(), host.to_string())]
+                .into_iter()
+                .collect(),
+            field: "usage".to_string(),
+            points,
+        }
+    }
+
+    #[test]
+    fn time() {
+        let now = 10_000_000_000_000;
+        let time = |value| parse_time("start", value, now);
+        assert_eq!(time("-30s").unwrap(), now - 30_000_000_000);
+        assert_eq!(time("-2h").unwrap(), now - 7_200_000_000_000);
+        assert_eq!(time("1234").unwrap(), 1234);
+        assert_eq!(time("-30").unwrap(), -30);
+        assert_eq!(
+            time("2021-02-03T04:05:06.5Z").unwrap(),
+            1_612_325_106_500_000_000
+        );
+        assert_eq!(
+            time("2021-02-03T05:05:06+01:00").unwrap(),
+            1_612_325_106_000_000_000
+        );
+        assert_eq!(time("-5ms").unwrap(), now - 5_000_000);
+        for invalid in &["30s", "-s", "-30y", "-1.5h", "2021-02-03"] {
+            assert!(time(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn time_range() {
+        let predicate = |query: &str| {
+            serde_urlencoded::from_str::<ReadParams>(&format!("org=o&bucket=b&{}", query))
+                .unwrap()
+                .predicate(1000)
+        };
+        let range = predicate("start=100&stop=200").unwrap().range.unwrap();
+        assert_eq!((range.start, range.end), (100, 201));
+        let range = predicate("start=100").unwrap().range.unwrap();
+        assert_eq!((range.start, range.end), (100, 1001));
+        assert!(matches!(
+            predicate("start=200&stop=100"),
+            Err(Error::InvalidTimeRange {
+                start: 200,
+                stop: 100
+            })
+        ));
+        assert!(matches!(
+            predicate("start=100&stop=later"),
+            Err(Error::InvalidTime { name: "stop", .. })
+        ));
+        assert!(matches!(
+            predicate("start=1000-01-01T00:00:00Z"),
+            Err(Error::InvalidTime { name: "start", .. })
+        ));
+
+        // host=~/^a/ AND region!~/west/
+        let regexes =
+            predicate("start=100&predicate=host%3D~%2F%5Ea%2F%20AND%20region!~%2Fwest%2F");
+        assert_eq!(regexes.unwrap().exprs.len(), 2);
+        assert!(matches!(
+            predicate("start=100&predicate=host%3D~%2F(%2F"),
+            Err(Error::InvalidRegex { .. })
+        ));
+
+        // host="a" OR NOT (region=~/west/)
+        let or_not =
+            predicate("start=100&predicate=host%3D%22a%22%20OR%20NOT%20%28region%3D~%2Fwest%2F%29");
+        assert_eq!(or_not.unwrap().exprs.len(), 1);
+        // _measurement="cpu" AND (host="a" OR host="b") AND region="west"
+        let grouped = predicate(
+            "start=100&predicate=_measurement%3D%22cpu%22%20AND%20%28host%3D%22a%22%20OR%20\
+             host%3D%22b%22%29%20AND%20region%3D%22west%22",
+        )
+        .unwrap();
+        assert_eq!(grouped.table_names.unwrap().len(), 1);
+        assert_eq!(grouped.exprs.len(), 2);
+
+        // usage_user + usage_system > 90
+        let fields = predicate("start=100&predicate=usage_user%20%2B%20usage_system%20%3E%2090");
+        assert_eq!(fields.unwrap().exprs.len(), 1);
+    }
+
+    #[test]
+    fn resample() {
+        let resample = |interpolation, points| {
+            Resample::try_new(interpolation, 10, 5, 45)
+                .unwrap()
+                .apply(points)
+        };
+        let floats = vec![
+            (12, FieldValue::Float(1.0)),
+            (20, FieldValue::Float(2.0)),
+            (35, FieldValue::Float(5.0)),
+        ];
+        let float = FieldValue::Float;
+        assert_eq!(
+            resample(ReadInterpolation::Linear, floats.clone()),
+            vec![(20, float(2.0)), (30, float(4.0))]
+        );
+        assert_eq!(
+            resample(ReadInterpolation::Previous, floats),
+            vec![(20, float(2.0)), (30, float(2.0)), (40, float(5.0))]
+        );
+
+        let integers = vec![(5, FieldValue::Integer(1)), (25, FieldValue::Integer(2))];
+        let integer = FieldValue::Integer;
+        assert_eq!(
+            resample(ReadInterpolation::Linear, integers),
+            vec![(10, integer(1)), (20, integer(2))]
+        );
+
+        // strings keep their previous values, but aren't interpolated
+        let strings = vec![(15, FieldValue::String("a".into()))];
+        assert_eq!(
+            resample(ReadInterpolation::Previous, strings.clone()),
+            vec![
+                (20, FieldValue::String("a".into())),
+                (30, FieldValue::String("a".into())),
+                (40, FieldValue::String("a".into()))
+            ]
+        );
+        assert!(resample(ReadInterpolation::Linear, strings).is_empty());
+
+        let options = |query: &str| {
+            serde_urlencoded::from_str::<ReadParams>(&format!("org=o&bucket=b&start=0&{}", query))
+                .unwrap()
+                .options(1_000_000_000_000)
+        };
+        let resampled = options("resample=1s&interpolate=previous").unwrap();
+        assert_eq!(
+            resampled.resample.unwrap().interpolation,
+            ReadInterpolation::Previous
+        );
+        assert!(matches!(
+            options("resample=0s"),
+            Err(Error::InvalidResample { .. })
+        ));
+        assert!(matches!(
+            options("resample=1s&every=1m&agg=mean"),
+            Err(Error::WindowedResample {})
+        ));
+        assert!(matches!(
+            options("resample=1ms"),
+            Err(Error::TooManyResampledPoints { .. })
+        ));
+    }
+
+    #[test]
+    fn shifted() {
+        const NOW: i64 = 10_000_000;
+        let params = |query: &str| {
+            serde_urlencoded::from_str::<ReadParams>(&format!("org=o&bucket=b&{}", query)).unwrap()
+        };
+        assert!(params("start=100").shifted(NOW).unwrap().is_none());
+
+        let shifted = params("start=2000000&stop=3000000&shift=1ms")
+            .shifted(NOW)
+            .unwrap()
+            .unwrap();
+        assert_eq!(shifted.shift, 1_000_000);
+        assert_eq!(shifted.label, "1ms");
+        let range = shifted.params.predicate(NOW).unwrap().range.unwrap();
+        assert_eq!((range.start, range.end), (1_000_000, 2_000_001));
+        assert!(shifted.params.shifted(NOW).unwrap().is_none());
+
+        for invalid in &["shift=0s", "shift=-1h", "shift=week"] {
+            assert!(
+                matches!(
+                    params(&format!("start=100&{}", invalid)).shifted(NOW),
+                    Err(Error::InvalidShift { .. })
+                ),
+                "{}",
+                invalid
+            );
+        }
+
+        assert_eq!(
+            params("start=2000000&stop=3000000&shift=1ms")
+                .covered_range(NOW)
+                .unwrap(),
+            (1_000_000, 3_000_000)
+        );
+        assert_eq!(
+            params("start=2000000&stop=-1ms")
+                .covered_range(NOW)
+                .unwrap(),
+            (2_000_000, i64::MAX)
+        );
+
+        let mut results = ReadResults::Series(vec![series(
+            "cpu",
+            "a",
+            vec![(3_000_000, FieldValue::Float(1.0))],
+        )]);
+        let earlier = ReadResults::Series(vec![series(
+            "cpu",
+            "a",
+            vec![(2_000_000, FieldValue::Float(2.0))],
+        )]);
+        results.append_shifted(earlier, &shifted);
+        let mut expected = series("cpu", "a", vec![(3_000_000, FieldValue::Float(2.0))]);
+        expected
+            .tags
+            .insert(SHIFT_TAG.to_string(), "1ms".to_string());
+        assert_eq!(
+            results,
+            ReadResults::Series(vec![
+                series("cpu", "a", vec![(3_000_000, FieldValue::Float(1.0))]),
+                expected
+            ])
+        );
+    }
+
+    #[test]
+    fn computed_fields() {
+        let fields = |query: &str| {
+            serde_urlencoded::from_str::<ReadParams>(&format!("org=o&bucket=b&start=-1h&{}", query))
+                .unwrap()
+                .computed_fields()
+        };
+        assert!(fields("").unwrap().is_empty());
+        let computed = fields("fields=total%3Dusage_user%2Busage_system,%20value*8").unwrap();
+        assert_eq!(
+            computed
+                .iter()
+                .map(|field| field.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["total", "value*8"]
+        );
+        assert!(matches!(
+            fields("fields=total%3Dusage_user%2B"),
+            Err(Error::InvalidField { .. })
+        ));
+        assert!(matches!(
+            fields("fields=a%2Bb&agg=last"),
+            Err(Error::ComputedSelector {})
+        ));
+    }
+
+    #[test]
+    fn group_by_and_aggregate() {
+        let gby_agg = |query: &str| {
+            serde_urlencoded::from_str::<ReadParams>(&format!("org=o&bucket=b&start=-1h&{}", query))
+                .unwrap()
+                .group_by_and_aggregate()
+        };
+        assert_eq!(gby_agg("").unwrap(), None);
+        assert_eq!(
+            gby_agg("every=1m&agg=mean").unwrap(),
+            Some(GroupByAndAggregate::Window {
+                agg: Aggregate::Mean,
+                every: WindowDuration::from_nanoseconds(60_000_000_000),
+                offset: WindowDuration::empty(),
+            })
+        );
+        assert!(matches!(
+            gby_agg("every=1m"),
+            Err(Error::IncompleteWindow {})
+        ));
+        assert!(matches!(
+            gby_agg("every=0s&agg=sum"),
+            Err(Error::InvalidEvery { .. })
+        ));
+        assert!(matches!(
+            gby_agg("every=1m&agg=max&group_by=host"),
+            Err(Error::GroupedWindow {})
+        ));
+        assert_eq!(
+            gby_agg("agg=last&group_by=host").unwrap(),
+            Some(GroupByAndAggregate::Columns {
+                agg: Aggregate::Last,
+                group_columns: vec!["host".to_string()],
+            })
+        );
+        assert!(matches!(
+            gby_agg("agg=mean"),
+            Err(Error::UnwindowedAggregate {})
+        ));
+
+        let window_agg = |query: &str| match gby_agg(&format!("every=1m&{}", query)).unwrap() {
+            Some(GroupByAndAggregate::Window { agg, .. }) => agg,
+            other => panic!("unexpected {:?}", other),
+        };
+        assert_eq!(window_agg("agg=median"), Aggregate::Percentile(50.0));
+        assert_eq!(window_agg("agg=count_distinct"), Aggregate::CountDistinct);
+        assert_eq!(
+            window_agg("agg=percentile&percentile=99.9"),
+            Aggregate::Percentile(99.9)
+        );
+        for invalid in &["agg=percentile", "agg=percentile&percentile=101"] {
+            assert!(
+                matches!(
+                    gby_agg(&format!("every=1m&{}", invalid)),
+                    Err(Error::InvalidPercentile {})
+                ),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn group_columns() {
+        let params: ReadParams =
+            serde_urlencoded::from_str("org=o&bucket=b&start=-1h&group_by=host,%20region,")
+                .unwrap();
+        assert_eq!(params.group_columns(), vec!["host", "region"]);
+        assert_eq!(params.format, ReadFormat::Csv);
+        assert_eq!(params.order, ReadOrder::Asc);
+        assert_eq!(params.fill, None);
+
+        let params: ReadParams = serde_urlencoded::from_str("org=o&bucket=b&start=-1h").unwrap();
+        assert!(params.group_columns().is_empty());
+    }
+
+    #[test]
+    fn limits() {
+        let params: ReadParams =
+            serde_urlencoded::from_str("org=o&bucket=b&start=-1h&limit=5&series_limit=2&offset=1")
+                .unwrap();
+        let mut limits = params.limits();
+        assert_eq!(limits.take(ReadOrder::Asc, 0..10), vec![1, 2]);
+        assert_eq!(limits.take(ReadOrder::Asc, 0..2), vec![1]);
+        assert_eq!(limits.take(ReadOrder::Asc, 0..1), Vec::<i32>::new());
+        assert!(!limits.exhausted());
+        assert_eq!(limits.take(ReadOrder::Desc, 0..10), vec![8, 7]);
+        assert!(limits.exhausted());
+        assert_eq!(limits.take(ReadOrder::Asc, 0..10), Vec::<i32>::new());
+
+        let params: ReadParams = serde_urlencoded::from_str("org=o&bucket=b&start=-1h").unwrap();
+        let mut limits = params.limits();
+        assert_eq!(limits, ReadLimits::default());
+        assert_eq!(limits.take(ReadOrder::Asc, 0..3), vec![0, 1, 2]);
+        assert!(!limits.exhausted());
+    }
+
+    #[test]
+    fn fill() {
+        let fill = |policy, points| {
+            WindowFill::try_new(policy, 10, 5, 45)
+                .unwrap()
+                .apply(points)
+                .into_iter()
+                .map(|(_, value)| value)
+                .collect::<Vec<_>>()
+        };
+        let floats = vec![(20, FieldValue::Float(1.0)), (50, FieldValue::Float(4.0))];
+        let float = FieldValue::Float;
+        let null = FieldValue::Null;
+        assert_eq!(
+            fill(ReadFill::Null, floats.clone()),
+            vec![
+                null.clone(),
+                float(1.0),
+                null.clone(),
+                null.clone(),
+                float(4.0)
+            ]
+        );
+        assert_eq!(
+            fill(ReadFill::Previous, floats.clone()),
+            vec![null.clone(), float(1.0), float(1.0), float(1.0), float(4.0)]
+        );
+        assert_eq!(
+            fill(ReadFill::Zero, floats.clone()),
+            vec![float(0.0), float(1.0), float(0.0), float(0.0), float(4.0)]
+        );
+        assert_eq!(
+            fill(ReadFill::Linear, floats),
+            vec![null.clone(), float(1.0), float(2.0), float(3.0), float(4.0)]
+        );
+
+        let integers = vec![(20, FieldValue::Integer(1)), (50, FieldValue::Integer(2))];
+        let integer = FieldValue::Integer;
+        assert_eq!(
+            fill(ReadFill::Linear, integers),
+            vec![null.clone(), integer(1), integer(1), integer(2), integer(2)]
+        );
+
+        let strings = vec![(30, FieldValue::String("a".into()))];
+        assert_eq!(
+            fill(ReadFill::Zero, strings),
+            vec![
+                null.clone(),
+                null.clone(),
+                FieldValue::String("a".into()),
+                null.clone(),
+                null
+            ]
+        );
+
+        assert!(matches!(
+            WindowFill::try_new(ReadFill::Null, 1, 0, 1_000_000),
+            Err(Error::TooManyWindows { .. })
+        ));
+    }
+
+    #[test]
+    fn transform() {
+        let transform = |transform, points: &[(i64, FieldValue)]| {
+            SeriesTransform {
+                transform,
+                unit: 10,
+            }
+            .apply(points.to_vec())
+        };
+        let points = [
+            (0, FieldValue::Integer(10)),
+            (20, FieldValue::Integer(30)),
+            (30, FieldValue::Null),
+            (40, FieldValue::Integer(15)),
+            (40, FieldValue::Integer(20)),
+        ];
+        assert_eq!(
+            transform(ReadTransform::Derivative, &points),
+            vec![(20, FieldValue::Float(10.0)), (40, FieldValue::Float(-7.5))]
+        );
+        assert_eq!(
+            transform(ReadTransform::NonNegativeDerivative, &points),
+            vec![(20, FieldValue::Float(10.0))]
+        );
+        assert_eq!(
+            transform(ReadTransform::Rate, &points),
+            vec![(20, FieldValue::Float(10.0)), (40, FieldValue::Float(7.5))]
+        );
+        assert!(transform(ReadTransform::Rate, &points[..1]).is_empty());
+    }
+
+    #[test]
+    fn options() {
+        let options = |query: &str| {
+            serde_urlencoded::from_str::<ReadParams>(&format!("org=o&bucket=b&start=0&{}", query))
+                .unwrap()
+                .options(100)
+        };
+        let windowed = options("every=10s&agg=sum&fill=0&order=desc").unwrap();
+        assert_eq!(windowed.order, ReadOrder::Desc);
+        assert!(windowed.fill.is_some());
+        assert_eq!(options("").unwrap(), ReadOptions::default());
+        assert!(matches!(
+            options("fill=linear"),
+            Err(Error::UnwindowedFill {})
+        ));
+        assert!(matches!(
+            options("every=10s&agg=first&fill=null"),
+            Err(Error::FilledSelector {})
+        ));
+        assert_eq!(
+            options("transform=rate").unwrap().transform,
+            Some(SeriesTransform {
+                transform: ReadTransform::Rate,
+                unit: 1_000_000_000
+            })
+        );
+        assert!(matches!(
+            options("transform=derivative&unit=0s"),
+            Err(Error::InvalidUnit { .. })
+        ));
+        assert!(options("pivot=true").unwrap().pivot);
+        assert!(matches!(
+            options("pivot=true&group_by=host"),
+            Err(Error::GroupedPivot {})
+        ));
+        assert_eq!(
+            options("every=10s&agg=max&top=5").unwrap().top,
+            Some(TopN {
+                ranking: Ranking::Top,
+                n: 5,
+                selector_every: Some(10_000_000_000),
+            })
+        );
+        assert_eq!(
+            options("every=10s&agg=mean&bottom=1").unwrap().top,
+            Some(TopN {
+                ranking: Ranking::Bottom,
+                n: 1,
+                selector_every: None,
+            })
+        );
+        assert!(matches!(options("top=5"), Err(Error::UnwindowedTop {})));
+        for invalid in &[
+            "every=10s&agg=max&top=0",
+            "every=10s&agg=max&top=1&bottom=1",
+        ] {
+            assert!(
+                matches!(options(invalid), Err(Error::InvalidTop {})),
+                "{}",
+                invalid
+            );
+        }
+        assert!(serde_urlencoded::from_str::<ReadParams>("org=o&bucket=b&start=0&fill=1").is_err());
+    }
+
+    #[test]
+    fn csv() {
+        let results = ReadResults::Series(vec![
+            series("cpu", "a", vec![(1, FieldValue::Float(0.5))]),
+            series("cpu", "b,c", vec![(2, FieldValue::String("x\"y".into()))]),
+        ]);
+        assert_eq!(
+            results.into_csv(),
+            "_measurement,host,_field,_time,_value\n\
+             cpu,a,usage,1,0.5\n\
+             \n\
+             _measurement,host,_field,_time,_value\n\
+             cpu,\"b,c\",usage,2,\"x\"\"y\"\n"
+        );
+
+        let results = ReadResults::Groups(vec![
+            Group {
+                tags: series("", "a", vec![]).tags,
+                series: vec![series("cpu", "a", vec![(1, FieldValue::Integer(3))])],
+            },
+            Group {
+                tags: series("", "b", vec![]).tags,
+                series: vec![series("cpu", "b", vec![(2, FieldValue::Boolean(true))])],
+            },
+        ]);
+        assert_eq!(
+            results.clone().into_csv(),
+            "#group,host=a\n\
+             _measurement,host,_field,_time,_value\n\
+             cpu,a,usage,1,3\n\
+             \n\
+             #group,host=b\n\
+             _measurement,host,_field,_time,_value\n\
+             cpu,b,usage,2,true\n"
+        );
+
+        // streamed in small chunks, the rows make up the same CSV
+        let chunks: Vec<_> = results.clone().into_csv_chunks(16).collect();
+        assert!(chunks.len() > 1);
+        assert!(chunks[..chunks.len() - 1]
+            .iter()
+            .all(|chunk| chunk.len() >= 16));
+        assert_eq!(chunks.concat(), results.clone().into_csv().as_bytes());
+
+        // blocks added as the series are read only make up full chunks until
+        // the last
+        let mut csv = CsvChunks::new(16);
+        let mut streamed = vec![];
+        for block in results.clone().into_csv_chunks(16).blocks {
+            csv.blocks.push_back(block);
+            while let Some(chunk) = csv.next_full() {
+                assert!(chunk.len() >= 16);
+                streamed.push(chunk);
+            }
+        }
+        streamed.extend(csv);
+        assert_eq!(streamed.concat(), results.into_csv().as_bytes());
+    }
+
+    #[test]
+    fn pivot() {
+        let mut user = series("cpu", "a", vec![(1, FieldValue::Float(0.5))]);
+        user.field = "user".to_string();
+        let system = series(
+            "cpu",
+            "a",
+            vec![(1, FieldValue::Float(1.5)), (2, FieldValue::Float(1.7))],
+        );
+
+        let table = Table::pivot(vec![user.clone(), system.clone()], ReadOrder::Asc).unwrap();
+        assert_eq!(table.fields, vec!["user", "usage"]);
+        let results = ReadResults::Tables(vec![table]);
+        assert_eq!(
+            results.clone().into_csv(),
+            "_measurement,host,_time,user,usage\n\
+             cpu,a,1,0.5,1.5\n\
+             cpu,a,2,,1.7\n"
+        );
+        assert_eq!(
+            results.format(ReadFormat::Json).unwrap(),
+            r#"{"tables":[{"measurement":"cpu","tags":{"host":"a"},"fields":["user","usage"],"rows":[[1,0.5,1.5],[2,null,1.7]]}]}"#
+        );
+
+        let table = Table::pivot(vec![user, system], ReadOrder::Desc).unwrap();
+        assert_eq!(
+            table.rows.iter().map(|row| row.time).collect::<Vec<_>>(),
+            vec![2, 1]
+        );
+        assert_eq!(Table::pivot(vec![], ReadOrder::Asc), None);
+    }
+
+    #[test]
+    fn merge() {
+        let params = |query: &str| {
+            serde_urlencoded::from_str::<ReadParams>(&format!("org=o&bucket=hot&{}", query))
+                .unwrap()
+        };
+        assert_eq!(params("start=1").buckets(), vec!["hot"]);
+        assert_eq!(
+            params("start=1&union=archive,%20hot,,old").buckets(),
+            vec!["hot", "archive", "old"]
+        );
+        assert!(matches!(
+            params("start=-1h&every=1m&agg=max&top=1&union=archive").options(0),
+            Err(Error::UnionTop { .. })
+        ));
+
+        let point = |time, value| (time, FieldValue::Float(value));
+        let hot = ReadResults::Series(vec![
+            series("cpu", "b", vec![point(3, 3.0), point(4, 4.0)]),
+            series("mem", "a", vec![point(3, 3.0)]),
+        ]);
+        let archive = ReadResults::Series(vec![
+            series("cpu", "a", vec![point(1, 1.0)]),
+            series("cpu", "b", vec![point(1, 1.0), point(3, 0.0)]),
+        ]);
+
+        // the hot bucket's point wins at time 3
+        let merged = ReadResults::merge(vec![hot.clone(), archive.clone()], Default::default());
+        assert_eq!(
+            merged,
+            ReadResults::Series(vec![
+                series("cpu", "a", vec![point(1, 1.0)]),
+                series(
+                    "cpu",
+                    "b",
+                    vec![point(1, 1.0), point(3, 3.0), point(4, 4.0)]
+                ),
+                series("mem", "a", vec![point(3, 3.0)]),
+            ])
+        );
+
+        // the points are limited once merged
+        let options = ReadOptions {
+            order: ReadOrder::Desc,
+            limits: ReadLimits {
+                offset: 0,
+                series_limit: Some(2),
+                limit: Some(3),
+            },
+            ..Default::default()
+        };
+        let merged = ReadResults::merge(vec![hot.clone(), archive.clone()], options);
+        assert_eq!(
+            merged,
+            ReadResults::Series(vec![
+                series("cpu", "a", vec![point(1, 1.0)]),
+                series("cpu", "b", vec![point(4, 4.0), point(3, 3.0)]),
+            ])
+        );
+
+        let options = ReadOptions {
+            pivot: true,
+            ..Default::default()
+        };
+        match ReadResults::merge(vec![hot, archive], options) {
+            ReadResults::Tables(tables) => assert_eq!(
+                tables
+                    .iter()
+                    .map(|table| (table.measurement.as_str(), table.rows.len()))
+                    .collect::<Vec<_>>(),
+                vec![("cpu", 1), ("cpu", 3), ("mem", 1)]
+            ),
+            results => panic!("unexpected results {:?}", results),
+        }
+    }
+
+    #[test]
+    fn json() {
+        let results = ReadResults::Groups(vec![Group {
+            tags: series("", "a", vec![]).tags,
+            series: vec![series("cpu", "a", vec![(1, FieldValue::Float(0.5))])],
+        }]);
+        assert_eq!(
+            results.format(ReadFormat::Json).unwrap(),
+            r#"{"groups":[{"tags":{"host":"a"},"series":[{"measurement":"cpu","tags":{"host":"a"},"field":"usage","points":[[1,0.5]]}]}]}"#
+        );
+    }
+}
@@ -0,0 +1,321 @@
+//! A cache of the responses to `/api/v2/read`, so that dashboards
+//! refreshing the same panels every few seconds don't rerun the same reads
+//! against data that hasn't changed.
+//!
+//! Responses are cached by database and query parameters, and dropped when
+//! data is written or deleted within the time range they cover, or once
+//! they are older than the cache's time to live. Reads whose range ends at
+//! now are dropped by any write of recent data, but their start moves with
+//! now, so a cached response may lag by up to the time to live.
+//!
+//! The memory of the cached responses is accounted for by the server's
+//! memory tracker, and the cache is emptied, and caches nothing, while the
+//! server is under memory pressure.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+use server::memory::MemoryReservation;
+
+/// Identifies the responses that can be reused: those of the reads of the
+/// same databases with the same parameters, in any order. The `timeout` of
+/// a read doesn't change its response.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    db_names: Vec<String>,
+    params: Vec<(String, String)>,
+}
+
+impl CacheKey {
+    /// Creates the key of the read of `db_names` with the query string
+    /// `query`
+    pub fn new(db_names: Vec<String>, query: &str) -> Self {
+        let mut params: Vec<(String, String)> =
+            serde_urlencoded::from_str(query).unwrap_or_default();
+        params.retain(|(name, _)| name != "timeout");
+        params.sort();
+        Self { db_names, params }
+    }
+}
+
+/// A cached response
+#[derive(Debug, Clone, PartialEq)]
+pub struct CachedRead {
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+#[derive(Debug)]
+struct Entry {
+    /// The inclusive time range of the data the read covers
+    range: (i64, i64),
+    created: Instant,
+    /// The order the responses were cached in
+    sequence: u64,
+    read: CachedRead,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    entries: HashMap<CacheKey, Entry>,
+    /// Incremented by each invalidation, so that the responses of reads
+    /// that ran while data changed aren't cached
+    generation: u64,
+    /// The number of responses cached so far
+    inserted: u64,
+    /// Accounts for the bytes of the cached responses, if tracked
+    memory: Option<MemoryReservation>,
+}
+
+impl State {
+    /// Resizes the reservation to the bytes of the cached responses
+    fn account(&mut self) {
+        if let Some(memory) = &mut self.memory {
+            let bytes = self
+                .entries
+                .values()
+                .map(|entry| entry.read.body.len() as u64)
+                .sum();
+            memory.resize(bytes);
+        }
+    }
+}
+
+/// Caches read responses as described in the module docs
+#[derive(Debug)]
+pub struct ReadCache {
+    /// The most responses cached, the oldest being dropped first
+    max_entries: usize,
+    ttl: Duration,
+    state: Mutex<State>,
+}
+
+impl ReadCache {
+    pub fn new(max_entries: usize, ttl: Duration) -> Self {
+        Self {
+            max_entries,
+            ttl,
+            state: Default::default(),
+        }
+    }
+
+    /// Accounts for the memory of the cached responses through `memory`
+    pub fn with_memory(self, memory: MemoryReservation) -> Self {
+        self.state.lock().memory = Some(memory);
+        self
+    }
+
+    /// Returns the generation to pass to `insert` for a read starting now
+    pub fn generation(&self) -> u64 {
+        self.state.lock().generation
+    }
+
+    /// Returns the cached response of the read identified by `key`, if any
+    pub fn get(&self, key: &CacheKey) -> Option<CachedRead> {
+        let mut state = self.state.lock();
+        match state.entries.get(key) {
+            Some(entry) if entry.created.elapsed() < self.ttl => Some(entry.read.clone()),
+            Some(_) => {
+                state.entries.remove(key);
+                state.account();
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Caches the response of the read identified by `key`, covering the
+    /// inclusive time `range`, unless data was written or deleted since
+    /// `generation` was returned by `generation`
+    pub fn insert(&self, key: CacheKey, range: (i64, i64), generation: u64, read: CachedRead) {
+        let mut state = self.state.lock();
+        if state.generation != generation || self.max_entries == 0 {
+            return;
+        }
+        let under_pressure = state
+            .memory
+            .as_ref()
+            .map_or(false, |memory| memory.tracker().under_pressure());
+        if under_pressure {
+            state.entries.clear();
+            state.account();
+            return;
+        }
+
+        if !state.entries.contains_key(&key) && state.entries.len() >= self.max_entries {
+            let ttl = self.ttl;
+            state
+                .entries
+                .retain(|_, entry| entry.created.elapsed() < ttl);
+        }
+        if !state.entries.contains_key(&key) && state.entries.len() >= self.max_entries {
+            let oldest = state
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.sequence)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                state.entries.remove(&oldest);
+            }
+        }
+
+        state.inserted += 1;
+        let sequence = state.inserted;
+        state.entries.insert(
+            key,
+            Entry {
+                range,
+                created: Instant::now(),
+                sequence,
+                read,
+            },
+        );
+        state.account();
+    }
+
+    /// Drops the cached responses of reads of `db_name` covering any time
+    /// in the inclusive `range`, after data was written or deleted there
+    pub fn invalidate(&self, db_name: &str, range: (i64, i64)) {
+        let mut state = self.state.lock();
+        state.generation += 1;
+        state.entries.retain(|key, entry| {
+            !key.db_names.iter().any(|name| name == db_name)
+                || entry.range.1 < range.0
+                || range.1 < entry.range.0
+        });
+        state.account();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(body: &str) -> CachedRead {
+        CachedRead {
+            content_type: "application/json",
+            body: body.to_string(),
+        }
+    }
+
+    fn cache() -> ReadCache {
+        ReadCache::new(2, Duration::from_secs(60))
+    }
+
+    fn key(db_name: &str, query: &str) -> CacheKey {
+        CacheKey::new(vec![db_name.to_string()], query)
+    }
+
+    fn len(cache: &ReadCache) -> usize {
+        cache.state.lock().entries.len()
+    }
+
+    #[test]
+    fn keys() {
+        assert_eq!(
+            key("db", "start=-1h&bucket=b&org=o"),
+            key("db", "org=o&bucket=b&start=-1h&timeout=10s")
+        );
+        assert_ne!(
+            key("db", "org=o&bucket=b&start=-1h"),
+            key("db", "org=o&bucket=b&start=-2h")
+        );
+        assert_ne!(
+            key("db", "org=o&bucket=b&start=-1h"),
+            key("other", "org=o&bucket=b&start=-1h")
+        );
+    }
+
+    #[test]
+    fn hits_and_eviction() {
+        let cache = cache();
+        let a = key("db", "start=1");
+        let b = key("db", "start=2");
+        let c = key("db", "start=3");
+        assert_eq!(cache.get(&a), None);
+
+        cache.insert(a.clone(), (1, 10), cache.generation(), read("a"));
+        cache.insert(b.clone(), (2, 10), cache.generation(), read("b"));
+        assert_eq!(cache.get(&a), Some(read("a")));
+
+        // the oldest response is dropped to make room
+        cache.insert(c.clone(), (3, 10), cache.generation(), read("c"));
+        assert_eq!(len(&cache), 2);
+        assert_eq!(cache.get(&a), None);
+        assert_eq!(cache.get(&b), Some(read("b")));
+        assert_eq!(cache.get(&c), Some(read("c")));
+
+        let expired = ReadCache::new(2, Duration::from_secs(0));
+        expired.insert(a.clone(), (1, 10), expired.generation(), read("a"));
+        assert_eq!(expired.get(&a), None);
+
+        let disabled = ReadCache::new(0, Duration::from_secs(60));
+        disabled.insert(a.clone(), (1, 10), disabled.generation(), read("a"));
+        assert_eq!(disabled.get(&a), None);
+    }
+
+    #[test]
+    fn invalidation() {
+        let cache = cache();
+        let old = key("db", "start=1&stop=10");
+        let recent = key("db", "start=20");
+        cache.insert(old.clone(), (1, 10), cache.generation(), read("old"));
+        cache.insert(
+            recent.clone(),
+            (20, i64::MAX),
+            cache.generation(),
+            read("recent"),
+        );
+
+        // writes to other databases or times don't drop the responses
+        cache.invalidate("other", (1, 100));
+        cache.invalidate("db", (11, 19));
+        assert_eq!(len(&cache), 2);
+
+        cache.invalidate("db", (100, 100));
+        assert_eq!(cache.get(&old), Some(read("old")));
+        assert_eq!(cache.get(&recent), None);
+
+        cache.invalidate("db", (10, 10));
+        assert_eq!(cache.get(&old), None);
+
+        // reads of several databases are dropped by writes to any of them
+        let union = CacheKey::new(vec!["db".to_string(), "archive".to_string()], "start=1");
+        cache.insert(union.clone(), (1, 10), cache.generation(), read("union"));
+        cache.invalidate("archive", (1, 1));
+        assert_eq!(cache.get(&union), None);
+
+        // a read running while data changed isn't cached
+        let generation = cache.generation();
+        cache.invalidate("db", (5, 5));
+        cache.insert(old.clone(), (1, 10), generation, read("stale"));
+        assert_eq!(cache.get(&old), None);
+    }
+
+    #[test]
+    fn memory() {
+        use server::memory::{MemoryTracker, Subsystem};
+        use std::sync::Arc;
+
+        let tracker = Arc::new(MemoryTracker::new(Some(100)));
+        let cache = cache().with_memory(tracker.reservation(Subsystem::Caches));
+        let a = key("db", "start=1");
+        cache.insert(a.clone(), (1, 10), cache.generation(), read("abc"));
+        assert_eq!(tracker.used(Subsystem::Caches), 3);
+
+        cache.invalidate("db", (1, 1));
+        assert_eq!(tracker.used(Subsystem::Caches), 0);
+
+        // nothing is cached while the server is under memory pressure
+        cache.insert(a.clone(), (1, 10), cache.generation(), read("abc"));
+        tracker.set(Subsystem::MutableBuffer, 95);
+        let b = key("db", "start=2");
+        cache.insert(b.clone(), (1, 10), cache.generation(), read("def"));
+        assert_eq!(cache.get(&a), None);
+        assert_eq!(cache.get(&b), None);
+        assert_eq!(tracker.used(Subsystem::Caches), 0);
+    }
+}
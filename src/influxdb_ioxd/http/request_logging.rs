@@ -0,0 +1,164 @@
+//! Per request logging for the HTTP API.
+//!
+//! Each request is handled inside an `http_request` span carrying the
+//! method, path, remote address and id of the request, along with the org
+//! and bucket once a handler has determined them. Handlers run in spans of
+//! their own, so the `http_request` span is kept in the extensions of the
+//! request for them to record on, see `span`. A single event with the
+//! response status and duration is logged when the request completes, so
+//! that with JSON logging every request produces one structured record.
+//!
+//! The id of the request, see `influxdb_ioxd::request_id`, is
+//! returned in the `X-Request-Id` header of every response, including error
+//! responses.
+
+use std::{
+    net::SocketAddr,
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use futures::{future::BoxFuture, FutureExt};
+use hyper::{service::Service, Body, Request, Response};
+use tracing::{error, field, info, info_span, Span};
+use tracing_futures::Instrument;
+
+use crate::influxdb_ioxd::request_id::{self, ensure_request_id, REQUEST_ID_HEADER};
+
+/// Wraps the service handling the requests of a single connection, logging
+/// each request as described in the module docs
+#[derive(Debug)]
+pub struct LoggedService<S> {
+    inner: S,
+    remote_addr: SocketAddr,
+}
+
+impl<S> LoggedService<S> {
+    pub fn new(inner: S, remote_addr: SocketAddr) -> Self {
+        Self { inner, remote_addr }
+    }
+}
+
+impl<S> Service<Request<Body>> for LoggedService<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>>,
+    S::Future: Send + 'static,
+    S::Error: std::fmt::Display + Send + 'static,
+{
+    type Response = Response<Body>;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<Body>) -> Self::Future {
+        let request_id = ensure_request_id(req.headers_mut());
+        let span = info_span!(
+            "http_request",
+            method = %req.method(),
+            path = %req.uri().path(),
+            remote_addr = %self.remote_addr,
+            request_id = request_id::as_str(&request_id),
+            org = field::Empty,
+            bucket = field::Empty,
+        );
+        req.extensions_mut().insert(RequestSpan(span.clone()));
+        let start = Instant::now();
+        let response = span.in_scope(|| self.inner.call(req));
+
+        async move {
+            let mut response = response.await;
+            let duration_ms = start.elapsed().as_secs_f64() * 1000.0;
+            match &mut response {
+                Ok(res) => {
+                    res.headers_mut().insert(REQUEST_ID_HEADER, request_id);
+                    info!(
+                        status = res.status().as_u16(),
+                        duration_ms, "Processed request"
+                    )
+                }
+                Err(e) => error!(error = %e, duration_ms, "Error processing request"),
+            }
+            response
+        }
+        .instrument(span)
+        .boxed()
+    }
+}
+
+/// The `http_request` span of a request
+#[derive(Debug, Clone)]
+pub struct RequestSpan(Span);
+
+impl RequestSpan {
+    /// Records the org and bucket the request refers to
+    pub fn record_org_and_bucket(&self, org: &str, bucket: &str) {
+        self.0.record("org", &org);
+        self.0.record("bucket", &bucket);
+    }
+}
+
+/// Returns the `http_request` span of `req`, a disabled span if the request
+/// isn't logged
+pub fn span(req: &Request<Body>) -> RequestSpan {
+    req.extensions()
+        .get::<RequestSpan>()
+        .cloned()
+        .unwrap_or_else(|| RequestSpan(Span::none()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{convert::Infallible, io, sync::Arc};
+
+    use parking_lot::Mutex;
+
+    use super::*;
+
+    #[derive(Debug, Clone, Default)]
+    struct TestWriter(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for TestWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn org_and_bucket_logged() {
+        let output = TestWriter::default();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer({
+                let output = output.clone();
+                move || output.clone()
+            })
+            .with_ansi(false)
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let handler = hyper::service::service_fn(|req: Request<Body>| {
+            async move {
+                span(&req).record_org_and_bucket("company", "sensors");
+                Ok::<_, Infallible>(Response::new(Body::empty()))
+            }
+            .instrument(info_span!("handler"))
+        });
+        let mut service = LoggedService::new(handler, "127.0.0.1:8080".parse().unwrap());
+        service.call(Request::new(Body::empty())).await.unwrap();
+
+        let output = String::from_utf8(output.0.lock().clone()).unwrap();
+        let line = output
+            .lines()
+            .find(|line| line.contains("Processed request"))
+            .unwrap_or_else(|| panic!("request not logged: {}", output));
+        assert!(line.contains("org=\"company\""), "{}", line);
+        assert!(line.contains("bucket=\"sensors\""), "{}", line);
+    }
+}
@@ -0,0 +1,233 @@
+//! Network level access control: lists of CIDR ranges a listener accepts
+//! requests from, or rejects them from, for deployments that can't yet
+//! authenticate their clients with tokens or certificates.
+//!
+//! A client is rejected if its address is in a denied range, or if allowed
+//! ranges are configured and its address is in none of them. HTTP requests
+//! from rejected clients fail with `403 Forbidden` before they are handled;
+//! gRPC connections from them are closed as soon as they are accepted.
+//! IPv4 clients connecting to an IPv6 listener are matched by their IPv4
+//! address. Requests over the unix socket are not filtered.
+
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    str::FromStr,
+    sync::Arc,
+};
+
+use futures::{Stream, StreamExt};
+use snafu::{OptionExt, ResultExt, Snafu};
+use tokio::net::TcpStream;
+use tracing::debug;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Invalid IP address or CIDR range '{}': {}", range, source))]
+    InvalidAddress {
+        range: String,
+        source: std::net::AddrParseError,
+    },
+
+    #[snafu(display("Invalid prefix length in CIDR range '{}'", range))]
+    InvalidPrefixLength { range: String },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// A range of IP addresses, e.g. `10.0.0.0/8`. A single address is a range
+/// with the full prefix length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl Cidr {
+    /// Whether `ip` is in the range
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, canonical(ip)) {
+            (IpAddr::V4(addr), IpAddr::V4(ip)) => {
+                masked(u32::from(addr).into(), self.prefix_len, 32)
+                    == masked(u32::from(ip).into(), self.prefix_len, 32)
+            }
+            (IpAddr::V6(addr), IpAddr::V6(ip)) => {
+                masked(u128::from(addr), self.prefix_len, 128)
+                    == masked(u128::from(ip), self.prefix_len, 128)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = Error;
+
+    fn from_str(range: &str) -> Result<Self> {
+        let mut parts = range.trim().splitn(2, '/');
+        let addr = parts.next().unwrap_or_default();
+        let addr: IpAddr = addr.parse().context(InvalidAddress { range })?;
+        let addr = canonical(addr);
+        let max_len = if addr.is_ipv4() { 32 } else { 128 };
+
+        let prefix_len = match parts.next() {
+            Some(prefix_len) => prefix_len
+                .parse()
+                .ok()
+                .filter(|&prefix_len| prefix_len <= max_len)
+                .context(InvalidPrefixLength { range })?,
+            None => max_len,
+        };
+        Ok(Self { addr, prefix_len })
+    }
+}
+
+/// Returns the bits of `addr`, an address of `bits` bits, in its first
+/// `prefix_len` bits
+fn masked(addr: u128, prefix_len: u8, bits: u8) -> u128 {
+    match bits - prefix_len {
+        0 => addr,
+        host_bits if host_bits >= 128 => 0,
+        host_bits => addr >> host_bits,
+    }
+}
+
+/// Returns the IPv4 address of an IPv4-mapped IPv6 address, as seen by
+/// listeners bound to an IPv6 address, and any other address as is
+fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => match v6.segments() {
+            [0, 0, 0, 0, 0, 0xffff, high, low] => IpAddr::V4(Ipv4Addr::new(
+                (high >> 8) as u8,
+                high as u8,
+                (low >> 8) as u8,
+                low as u8,
+            )),
+            _ => IpAddr::V6(v6),
+        },
+        ip => ip,
+    }
+}
+
+/// The ranges a listener accepts clients from, as described in the module
+/// docs. The default filter accepts every client.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IpFilter {
+    allowed: Vec<Cidr>,
+    denied: Vec<Cidr>,
+}
+
+impl IpFilter {
+    /// Parses the `allowed` and `denied` ranges, e.g. `10.0.0.0/8` or
+    /// `::1`
+    pub fn new(allowed: &[String], denied: &[String]) -> Result<Self> {
+        let parse = |ranges: &[String]| -> Result<Vec<Cidr>> {
+            ranges
+                .iter()
+                .filter(|range| !range.trim().is_empty())
+                .map(|range| range.parse())
+                .collect()
+        };
+        Ok(Self {
+            allowed: parse(allowed)?,
+            denied: parse(denied)?,
+        })
+    }
+
+    /// Whether clients are filtered at all
+    pub fn enabled(&self) -> bool {
+        !self.allowed.is_empty() || !self.denied.is_empty()
+    }
+
+    /// Whether the client at `ip` is accepted
+    pub fn allows(&self, ip: IpAddr) -> bool {
+        !self.denied.iter().any(|range| range.contains(ip))
+            && (self.allowed.is_empty() || self.allowed.iter().any(|range| range.contains(ip)))
+    }
+}
+
+/// Closes the `connections` from clients `filter` doesn't accept
+pub fn filter_connections(
+    connections: impl Stream<Item = std::io::Result<TcpStream>>,
+    filter: Arc<IpFilter>,
+) -> impl Stream<Item = std::io::Result<TcpStream>> {
+    connections.filter(move |conn| {
+        let allowed = match conn.as_ref().map(TcpStream::peer_addr) {
+            Ok(Ok(peer_addr)) if !filter.allows(peer_addr.ip()) => {
+                debug!(%peer_addr, "Closing connection from client not allowed by IP filter");
+                false
+            }
+            _ => true,
+        };
+        futures::future::ready(allowed)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    fn ranges(ranges: &[&str]) -> Vec<String> {
+        ranges.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn cidr() {
+        let range: Cidr = "10.1.0.0/16".parse().unwrap();
+        assert!(range.contains(ip("10.1.2.3")));
+        assert!(!range.contains(ip("10.2.0.1")));
+        assert!(range.contains(ip("::ffff:10.1.2.3")));
+        assert!(!range.contains(ip("::1")));
+
+        let range: Cidr = "2001:db8::/32".parse().unwrap();
+        assert!(range.contains(ip("2001:db8:1::1")));
+        assert!(!range.contains(ip("2001:db9::1")));
+
+        let range: Cidr = "::1".parse().unwrap();
+        assert!(range.contains(ip("::1")));
+        assert!(!range.contains(ip("::2")));
+
+        let range: Cidr = "0.0.0.0/0".parse().unwrap();
+        assert!(range.contains(ip("192.168.1.1")));
+        let range: Cidr = "::/0".parse().unwrap();
+        assert!(range.contains(ip("2001:db8::1")));
+
+        for range in &[
+            "10.0.0.0/33",
+            "::/129",
+            "10.0.0.0/x",
+            "localhost",
+            "10.0.0/8",
+        ] {
+            assert!(range.parse::<Cidr>().is_err(), "{}", range);
+        }
+    }
+
+    #[test]
+    fn filter() {
+        assert!(!IpFilter::default().enabled());
+        assert!(IpFilter::default().allows(ip("192.168.1.1")));
+
+        let filter = IpFilter::new(
+            &ranges(&["10.0.0.0/8", "127.0.0.1"]),
+            &ranges(&["10.9.0.0/16"]),
+        )
+        .unwrap();
+        assert!(filter.enabled());
+        assert!(filter.allows(ip("10.1.2.3")));
+        assert!(filter.allows(ip("127.0.0.1")));
+        assert!(!filter.allows(ip("10.9.1.1")));
+        assert!(!filter.allows(ip("192.168.1.1")));
+
+        // without allowed ranges, every client not denied is allowed
+        let filter = IpFilter::new(&[], &ranges(&["192.168.0.0/16"])).unwrap();
+        assert!(filter.allows(ip("10.1.2.3")));
+        assert!(!filter.allows(ip("192.168.1.1")));
+
+        let err = IpFilter::new(&ranges(&["10.0.0.0/8", "nope"]), &[]).unwrap_err();
+        assert!(matches!(err, Error::InvalidAddress { .. }));
+    }
+}
@@ -0,0 +1,20 @@
+//! Periodic measure of the memory held by the server.
+
+use std::{sync::Arc, time::Duration};
+
+use server::{ConnectionManagerImpl as ConnectionManager, Server as AppServer};
+
+/// How often the memory held by the server is measured
+const MEASURE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Measures the memory held by `server` every `MEASURE_INTERVAL`, relieving
+/// its memory pressure if needed. Writes and queries are checked against
+/// the last measure. Runs forever.
+pub async fn measure_periodically(server: Arc<AppServer<ConnectionManager>>) {
+    let mut interval = tokio::time::interval(MEASURE_INTERVAL);
+
+    loop {
+        interval.tick().await;
+        server.update_memory_usage().await;
+    }
+}
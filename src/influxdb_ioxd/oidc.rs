@@ -0,0 +1,411 @@
+//! Validation of OpenID Connect bearer tokens, so that the server can plug
+//! into an existing single sign-on instead of managing its own tokens.
+//!
+//! If `--oidc-issuer` is set, requests may send a JWT issued by it in an
+//! `Authorization: Bearer <jwt>` header. Its signature is checked against
+//! the issuer's keys, read from the `jwks_uri` of its discovery document on
+//! startup and every `--oidc-refresh-interval` seconds so that rotated keys
+//! are picked up, and its `iss`, `exp`, `nbf` and, if `--oidc-audience` is
+//! set, `aud` claims are validated. Only RSA signatures are supported.
+//!
+//! A valid JWT may do what the `read`, `write` and `admin` values of its
+//! `--oidc-scopes-claim` claim (a space separated string or an array of
+//! strings) allow, in the org named by its `--oidc-org-claim` claim. JWTs
+//! without the org claim are rejected, so that a misconfigured issuer can't
+//! grant access to every org.
+
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use parking_lot::RwLock;
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::Value;
+use server::tokens::{Scope, Token};
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+use tracing::{error, info};
+
+/// How far, in seconds, the clocks of the issuer and the server may differ
+const LEEWAY_SECONDS: u64 = 60;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Error requesting {}: {}", url, source))]
+    Requesting { url: String, source: reqwest::Error },
+
+    #[snafu(display("Invalid response from {}: {}", url, source))]
+    InvalidResponse {
+        url: String,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display("No RSA signing keys at {}", url))]
+    NoKeys { url: String },
+
+    #[snafu(display("{}", source))]
+    InvalidJwt { source: jsonwebtoken::errors::Error },
+
+    #[snafu(display("unsupported signature algorithm {:?}", alg))]
+    UnsupportedAlgorithm { alg: Algorithm },
+
+    #[snafu(display("unknown signing key {:?}", kid))]
+    UnknownKey { kid: Option<String> },
+
+    #[snafu(display("missing {} claim", claim))]
+    MissingClaim { claim: String },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// How bearer tokens are validated and mapped to what they may do
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OidcConfig {
+    /// The issuer the tokens must be issued by, whose discovery document is
+    /// at `<issuer>/.well-known/openid-configuration`
+    pub issuer: String,
+    /// The audience the tokens must be issued for, if any
+    pub audience: Option<String>,
+    /// The claim naming the org a token may be used in
+    pub org_claim: String,
+    /// The claim holding the scopes of a token
+    pub scopes_claim: String,
+}
+
+/// The part of the discovery document of an issuer that is used
+#[derive(Debug, Deserialize)]
+struct Discovery {
+    jwks_uri: String,
+}
+
+/// A JSON Web Key Set
+#[derive(Debug, Deserialize)]
+struct Jwks {
+    keys: Vec<Jwk>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Jwk {
+    kty: String,
+    #[serde(default, rename = "use")]
+    usage: Option<String>,
+    #[serde(default)]
+    kid: Option<String>,
+    #[serde(default)]
+    n: Option<String>,
+    #[serde(default)]
+    e: Option<String>,
+}
+
+/// An RSA signing key of the issuer, with its base64url encoded modulus and
+/// exponent
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct RsaKey {
+    kid: Option<String>,
+    n: String,
+    e: String,
+}
+
+impl Jwks {
+    fn rsa_keys(self) -> Vec<RsaKey> {
+        self.keys
+            .into_iter()
+            .filter(|key| key.kty == "RSA" && key.usage.as_deref().unwrap_or("sig") == "sig")
+            .filter_map(|key| match (key.n, key.e) {
+                (Some(n), Some(e)) => Some(RsaKey { kid: key.kid, n, e }),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// Validates bearer tokens as described in the module docs
+#[derive(Debug)]
+pub struct OidcValidator {
+    config: OidcConfig,
+    client: reqwest::Client,
+    keys: RwLock<Vec<RsaKey>>,
+}
+
+impl OidcValidator {
+    /// Creates a validator without keys, which are loaded by
+    /// `refresh_keys`
+    pub fn new(config: OidcConfig) -> Self {
+        Self {
+            config,
+            client: reqwest::Client::new(),
+            keys: Default::default(),
+        }
+    }
+
+    /// Reads the signing keys of the issuer
+    pub async fn refresh_keys(&self) -> Result<()> {
+        let url = format!(
+            "{}/.well-known/openid-configuration",
+            self.config.issuer.trim_end_matches('/')
+        );
+        let discovery: Discovery = self.get_json(&url).await?;
+        let jwks: Jwks = self.get_json(&discovery.jwks_uri).await?;
+        let keys = jwks.rsa_keys();
+        ensure!(
+            !keys.is_empty(),
+            NoKeys {
+                url: discovery.jwks_uri
+            }
+        );
+
+        if *self.keys.read() != keys {
+            info!(issuer=%self.config.issuer, keys=keys.len(), "Loaded OIDC signing keys");
+        }
+        *self.keys.write() = keys;
+        Ok(())
+    }
+
+    async fn get_json<T: DeserializeOwned>(&self, url: &str) -> Result<T> {
+        let body = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .context(Requesting { url })?
+            .text()
+            .await
+            .context(Requesting { url })?;
+        serde_json::from_str(&body).context(InvalidResponse { url })
+    }
+
+    /// Returns what the bearer token `jwt` may do, if it is valid
+    pub fn validate(&self, jwt: &str) -> Result<Token> {
+        let header = jsonwebtoken::decode_header(jwt).context(InvalidJwt)?;
+        ensure!(
+            matches!(
+                header.alg,
+                Algorithm::RS256
+                    | Algorithm::RS384
+                    | Algorithm::RS512
+                    | Algorithm::PS256
+                    | Algorithm::PS384
+                    | Algorithm::PS512
+            ),
+            UnsupportedAlgorithm { alg: header.alg }
+        );
+
+        let mut validation = Validation::new(header.alg);
+        validation.leeway = LEEWAY_SECONDS;
+        validation.iss = Some(self.config.issuer.clone());
+        if let Some(audience) = &self.config.audience {
+            validation.set_audience(&[audience]);
+        }
+
+        let claims = {
+            let keys = self.keys.read();
+            // a token without a key id can only be checked against the only
+            // key
+            let key = match &header.kid {
+                Some(kid) => keys.iter().find(|key| key.kid.as_ref() == Some(kid)),
+                None if keys.len() == 1 => keys.first(),
+                None => None,
+            }
+            .context(UnknownKey { kid: header.kid })?;
+
+            let key = DecodingKey::from_rsa_components(&key.n, &key.e);
+            jsonwebtoken::decode::<HashMap<String, Value>>(jwt, &key, &validation)
+                .context(InvalidJwt)?
+                .claims
+        };
+
+        let claim = |name: &str| {
+            claims
+                .get(name)
+                .and_then(Value::as_str)
+                .context(MissingClaim { claim: name })
+        };
+        let subject = claim("sub")?;
+        let org = claim(&self.config.org_claim)?;
+
+        let mut scopes: Vec<Scope> = match claims.get(&self.config.scopes_claim) {
+            Some(Value::String(scopes)) => scopes.split_whitespace().filter_map(scope).collect(),
+            Some(Value::Array(scopes)) => scopes
+                .iter()
+                .filter_map(Value::as_str)
+                .filter_map(scope)
+                .collect(),
+            _ => vec![],
+        };
+        scopes.sort();
+        scopes.dedup();
+
+        Ok(Token {
+            id: format!("oidc:{}", subject),
+            description: format!("OIDC token of {}", subject),
+            scopes,
+            org: Some(org.to_string()),
+            bucket: None,
+            limits: Default::default(),
+        })
+    }
+}
+
+/// Returns the scope named `name`, ignoring the scopes of other services
+fn scope(name: &str) -> Option<Scope> {
+    match name {
+        "read" => Some(Scope::Read),
+        "write" => Some(Scope::Write),
+        "admin" => Some(Scope::Admin),
+        _ => None,
+    }
+}
+
+/// Reads the signing keys of the issuer of `validator` every `interval`.
+/// Runs forever; errors are logged and the previous keys kept.
+pub async fn refresh_keys_periodically(validator: Arc<OidcValidator>, interval: Duration) {
+    let mut interval = tokio::time::interval(interval);
+    // the first tick completes immediately, and the keys were just loaded
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+        if let Err(e) = validator.refresh_keys().await {
+            error!(
+                "Error refreshing OIDC signing keys, keeping the current ones: {}",
+                e
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{EncodingKey, Header};
+    use serde_json::json;
+    use std::path::PathBuf;
+
+    const ISSUER: &str = "https://sso.example.com";
+
+    fn fixture(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/oidc")
+            .join(name)
+    }
+
+    fn validator(audience: Option<&str>) -> OidcValidator {
+        let validator = OidcValidator::new(OidcConfig {
+            issuer: ISSUER.to_string(),
+            audience: audience.map(ToString::to_string),
+            org_claim: "org".to_string(),
+            scopes_claim: "scope".to_string(),
+        });
+        let jwks: Jwks =
+            serde_json::from_str(&std::fs::read_to_string(fixture("jwks.json")).unwrap()).unwrap();
+        *validator.keys.write() = jwks.rsa_keys();
+        validator
+    }
+
+    fn sign(kid: Option<&str>, claims: Value) -> String {
+        let key =
+            EncodingKey::from_rsa_pem(&std::fs::read(fixture("jwt-key.pem")).unwrap()).unwrap();
+        let header = Header {
+            kid: kid.map(ToString::to_string),
+            ..Header::new(Algorithm::RS256)
+        };
+        jsonwebtoken::encode(&header, &claims, &key).unwrap()
+    }
+
+    fn claims(extra: Value) -> Value {
+        let mut claims = json!({
+            "iss": ISSUER,
+            "sub": "alice",
+            "exp": chrono::Utc::now().timestamp() + 600,
+            "org": "company",
+            "scope": "openid read write",
+        });
+        for (name, value) in extra.as_object().unwrap() {
+            claims[name] = value.clone();
+        }
+        claims
+    }
+
+    #[test]
+    fn validate() {
+        let validator = validator(None);
+
+        let token = validator
+            .validate(&sign(Some("test-key"), claims(json!({}))))
+            .unwrap();
+        assert_eq!(
+            token,
+            Token {
+                id: "oidc:alice".to_string(),
+                description: "OIDC token of alice".to_string(),
+                scopes: vec![Scope::Read, Scope::Write],
+                org: Some("company".to_string()),
+                bucket: None,
+                limits: Default::default(),
+            }
+        );
+
+        // the only key is used for tokens without a key id
+        let jwt = sign(None, claims(json!({"scope": ["admin", "profile"]})));
+        assert_eq!(validator.validate(&jwt).unwrap().scopes, vec![Scope::Admin]);
+
+        let jwt = sign(Some("test-key"), claims(json!({ "scope": null })));
+        assert!(validator.validate(&jwt).unwrap().scopes.is_empty());
+
+        let expired = claims(json!({"exp": chrono::Utc::now().timestamp() - 600}));
+        let invalid = vec![
+            (sign(Some("test-key"), expired), "InvalidJwt"),
+            (
+                sign(
+                    Some("test-key"),
+                    claims(json!({"iss": "https://other.com"})),
+                ),
+                "InvalidJwt",
+            ),
+            (sign(Some("other-key"), claims(json!({}))), "UnknownKey"),
+            (
+                sign(Some("test-key"), claims(json!({ "org": null }))),
+                "MissingClaim",
+            ),
+            ("not a jwt".to_string(), "InvalidJwt"),
+        ];
+        for (jwt, expected) in invalid {
+            let err = validator.validate(&jwt).unwrap_err();
+            assert!(format!("{:?}", err).starts_with(expected), "{:?}", err);
+        }
+
+        // the signature of other claims is rejected
+        let jwt = sign(Some("test-key"), claims(json!({})));
+        let other = sign(Some("test-key"), claims(json!({"org": "other"})));
+        let signature = jwt.rsplit('.').next().unwrap();
+        let (other_claims, _) = other.split_at(other.rfind('.').unwrap());
+        let tampered = format!("{}.{}", other_claims, signature);
+        let err = validator.validate(&tampered).unwrap_err();
+        assert!(matches!(err, Error::InvalidJwt { .. }));
+    }
+
+    #[test]
+    fn audience() {
+        let validator = validator(Some("iox"));
+
+        let jwt = sign(Some("test-key"), claims(json!({"aud": "iox"})));
+        validator.validate(&jwt).unwrap();
+
+        let jwt = sign(Some("test-key"), claims(json!({"aud": "grafana"})));
+        let err = validator.validate(&jwt).unwrap_err();
+        assert!(matches!(err, Error::InvalidJwt { .. }));
+    }
+
+    #[test]
+    fn jwks() {
+        let jwks: Jwks = serde_json::from_value(json!({"keys": [
+            {"kty": "RSA", "kid": "a", "n": "AQAB", "e": "AQAB"},
+            {"kty": "RSA", "use": "enc", "kid": "b", "n": "AQAB", "e": "AQAB"},
+            {"kty": "EC", "kid": "c", "crv": "P-256", "x": "AQAB", "y": "AQAB"},
+            {"kty": "RSA", "kid": "d"},
+        ]}))
+        .unwrap();
+        let keys = jwks.rsa_keys();
+        assert_eq!(keys.len(), 1);
+        assert_eq!(keys[0].kid.as_deref(), Some("a"));
+    }
+}
@@ -0,0 +1,186 @@
+//! The query log, a sample of the queries run by the server, to help
+//! understand the workload without logging every request.
+//!
+//! A fraction of the successful queries, set by `--query-log-sample-rate`,
+//! and every failed query are logged with the `query_log` target. Instead of
+//! the query itself, the log records its shape: the query with its literals
+//! replaced by `?`, so that queries differing only in their constants can be
+//! grouped together and no data values end up in the logs.
+
+use std::{
+    borrow::Cow,
+    fmt::Display,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use data_types::redaction::Redaction;
+use tracing::{info, warn};
+
+/// Samples and logs queries as described in the module docs
+#[derive(Debug, Default)]
+pub struct QueryLog {
+    /// One in this many successful queries is logged, none if 0
+    every: u64,
+    /// The number of successful queries so far
+    count: AtomicU64,
+}
+
+impl QueryLog {
+    /// Creates a query log logging `sample_rate`, between 0 and 1, of the
+    /// successful queries
+    pub fn new(sample_rate: f64) -> Self {
+        let every = if sample_rate > 0.0 {
+            (1.0 / sample_rate).round() as u64
+        } else {
+            0
+        };
+        Self {
+            every,
+            count: AtomicU64::new(0),
+        }
+    }
+
+    /// Records a query of `kind` (e.g. `sql` or `read_filter`) against
+    /// `db_name`, with the given shape, that ran for `duration` and failed
+    /// with `error` if set
+    pub fn record(
+        &self,
+        kind: &str,
+        db_name: &str,
+        shape: &str,
+        duration: Duration,
+        error: Option<&dyn Display>,
+    ) {
+        let duration_ms = duration.as_secs_f64() * 1000.0;
+        match error {
+            Some(error) => warn!(
+                target: "query_log",
+                kind,
+                db_name,
+                shape,
+                duration_ms,
+                %error,
+                "Query failed"
+            ),
+            None if self.sampled() => info!(
+                target: "query_log",
+                kind,
+                db_name,
+                shape,
+                duration_ms,
+                "Query"
+            ),
+            None => {}
+        }
+    }
+
+    /// Whether the next successful query is logged. Every `every`th query is
+    /// sampled so that the sample rate holds without needing a random number
+    /// generator.
+    fn sampled(&self) -> bool {
+        self.every != 0 && self.count.fetch_add(1, Ordering::Relaxed) % self.every == 0
+    }
+}
+
+/// Returns the SQL query `sql` as shown in logs and error messages: as is,
+/// or its shape if data values are redacted
+pub fn loggable_sql(sql: &str) -> Cow<'_, str> {
+    if Redaction::current().is_enabled() {
+        Cow::Owned(normalize_sql(sql))
+    } else {
+        Cow::Borrowed(sql)
+    }
+}
+
+/// Returns the shape of the SQL query `sql`: the query with its string and
+/// numeric literals replaced by `?` and its whitespace collapsed
+pub fn normalize_sql(sql: &str) -> String {
+    let mut shape = String::with_capacity(sql.len());
+    let mut chars = sql.chars().peekable();
+    // whether the previous character can continue an identifier, in which
+    // case digits are part of the identifier rather than a literal
+    let mut in_word = false;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                // a string literal, in which '' is an escaped quote
+                while let Some(c) = chars.next() {
+                    if c == '\'' {
+                        if chars.peek() == Some(&'\'') {
+                            chars.next();
+                        } else {
+                            break;
+                        }
+                    }
+                }
+                shape.push('?');
+                in_word = false;
+            }
+            '"' => {
+                // a quoted identifier, kept as is
+                shape.push(c);
+                for c in &mut chars {
+                    shape.push(c);
+                    if c == '"' {
+                        break;
+                    }
+                }
+                in_word = false;
+            }
+            c if c.is_ascii_digit() && !in_word => {
+                while let Some(&c) = chars.peek() {
+                    if c.is_ascii_alphanumeric() || c == '.' {
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                shape.push('?');
+            }
+            c if c.is_whitespace() => {
+                while chars.peek().map_or(false, |c| c.is_whitespace()) {
+                    chars.next();
+                }
+                shape.push(' ');
+                in_word = false;
+            }
+            c => {
+                shape.push(c);
+                in_word = c.is_alphanumeric() || c == '_';
+            }
+        }
+    }
+
+    shape.trim().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sampling() {
+        let sampled = |log: &QueryLog| (0..100).filter(|_| log.sampled()).count();
+        assert_eq!(sampled(&QueryLog::new(0.0)), 0);
+        assert_eq!(sampled(&QueryLog::new(0.01)), 1);
+        assert_eq!(sampled(&QueryLog::new(0.1)), 10);
+        assert_eq!(sampled(&QueryLog::new(1.0)), 100);
+    }
+
+    #[test]
+    fn normalize() {
+        assert_eq!(
+            normalize_sql(
+                "SELECT  \"host-1\", cpu2 FROM t\n WHERE host = 'it''s' AND usage > 1.5e3 LIMIT 10"
+            ),
+            "SELECT \"host-1\", cpu2 FROM t WHERE host = ? AND usage > ? LIMIT ?"
+        );
+        assert_eq!(
+            normalize_sql("select * from t where time > -20"),
+            "select * from t where time > -?"
+        );
+        assert_eq!(normalize_sql("select 'unterminated"), "select ?");
+    }
+}
@@ -0,0 +1,29 @@
+//! Periodic replication of the object store to a secondary region.
+
+use std::{sync::Arc, time::Duration};
+
+use server::{ConnectionManagerImpl as ConnectionManager, Server as AppServer};
+use tracing::{debug, warn};
+
+/// Mirrors the object store of `server` to its secondary object store every
+/// `interval`, starting right away. Runs forever.
+pub async fn sync_periodically(server: Arc<AppServer<ConnectionManager>>, interval: Duration) {
+    let replication = match server.region_replication() {
+        Some(replication) => replication,
+        None => return,
+    };
+    let mut interval = tokio::time::interval(interval);
+
+    loop {
+        interval.tick().await;
+        match replication.sync(&server.store).await {
+            Ok(stats) => debug!(
+                copied = stats.copied,
+                bytes = stats.bytes,
+                deleted = stats.deleted,
+                "Synced the secondary object store"
+            ),
+            Err(e) => warn!("Error syncing the secondary object store: {}", e),
+        }
+    }
+}
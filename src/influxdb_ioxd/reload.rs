@@ -0,0 +1,314 @@
+//! Reloading of settings on SIGHUP, without restarting the server.
+//!
+//! On SIGHUP the config file, if any, is loaded again and the settings are
+//! read from the command line, the environment and the config file with the
+//! same precedence as on startup. The environment is not changed: the
+//! settings from the reloaded file are kept by [`file`]. Only the following
+//! settings are applied; changes to any others need a restart:
+//!
+//! * the log filter (`--log` / `RUST_LOG`)
+//! * the HTTP write and read timeouts (`--write-timeout`, `--read-timeout`)
+//! * the retention check interval (`--retention-check-interval`)
+//!
+//! Each change applied is recorded in the audit log.
+
+use std::{fmt::Display, path::PathBuf, str::FromStr, sync::Arc, time::Duration};
+
+use tokio::sync::watch;
+use tracing::{error, info};
+
+use super::{
+    audit::{AuditEvent, AuditLog},
+    http::Timeout,
+    timeout,
+};
+use crate::commands::{
+    config::{
+        file, reload_args, reload_config, Config, DEFAULT_READ_TIMEOUT,
+        DEFAULT_RETENTION_CHECK_INTERVAL, DEFAULT_WRITE_TIMEOUT,
+    },
+    logging::LogFilterHandle,
+};
+
+/// The settings that can be reloaded
+#[derive(Debug, Clone, PartialEq)]
+struct Settings {
+    log_filter: String,
+    write_timeout_seconds: u64,
+    read_timeout_seconds: u64,
+    retention_check_interval_seconds: u64,
+}
+
+impl Settings {
+    fn new(config: &Config, default_log_filter: &str) -> Self {
+        Self {
+            log_filter: config
+                .rust_log
+                .clone()
+                .unwrap_or_else(|| default_log_filter.to_string()),
+            write_timeout_seconds: config.write_timeout_seconds,
+            read_timeout_seconds: config.read_timeout_seconds,
+            retention_check_interval_seconds: config.retention_check_interval_seconds,
+        }
+    }
+
+    /// Returns the settings of `config`, reloaded from the command line
+    /// `args` and the environment, with those that come from the config file
+    /// replaced by their values in the file as last loaded
+    fn reload(config: &Config, args: &[String], default_log_filter: &str) -> Result<Self, String> {
+        let settings = Self::new(config, default_log_filter);
+        Ok(Self {
+            log_filter: reloaded(
+                args,
+                "--log",
+                "RUST_LOG",
+                settings.log_filter,
+                default_log_filter,
+            )?,
+            write_timeout_seconds: reloaded(
+                args,
+                "--write-timeout",
+                "INFLUXDB_IOX_WRITE_TIMEOUT",
+                settings.write_timeout_seconds,
+                DEFAULT_WRITE_TIMEOUT,
+            )?,
+            read_timeout_seconds: reloaded(
+                args,
+                "--read-timeout",
+                "INFLUXDB_IOX_READ_TIMEOUT",
+                settings.read_timeout_seconds,
+                DEFAULT_READ_TIMEOUT,
+            )?,
+            retention_check_interval_seconds: reloaded(
+                args,
+                "--retention-check-interval",
+                "INFLUXDB_IOX_RETENTION_CHECK_INTERVAL",
+                settings.retention_check_interval_seconds,
+                DEFAULT_RETENTION_CHECK_INTERVAL,
+            )?,
+        })
+    }
+}
+
+/// Returns the value of the setting with the command line `flag` and the
+/// environment variable `var`: `value`, as read from the command line and
+/// the environment, unless the setting comes from the config file, in which
+/// case it is read from there or else from `default`
+fn reloaded<T: FromStr>(
+    args: &[String],
+    flag: &str,
+    var: &str,
+    value: T,
+    default: &str,
+) -> Result<T, String> {
+    let on_command_line = args
+        .iter()
+        .any(|arg| arg == flag || arg.starts_with(&format!("{}=", flag)));
+    if on_command_line {
+        return Ok(value);
+    }
+
+    let (from, value) = match file::source(var) {
+        file::Source::Environment => return Ok(value),
+        file::Source::File(value) => ("config file", value),
+        file::Source::Default => ("default", default.to_string()),
+    };
+    value
+        .parse()
+        .map_err(|_| format!("Invalid {} value {:?} for {}", from, value, flag))
+}
+
+/// Applies reloaded settings to the running server
+#[derive(Debug)]
+pub struct Reloader {
+    config_file: Option<PathBuf>,
+    /// The log filter used if none is configured, which depends on the
+    /// verbosity flags
+    default_log_filter: &'static str,
+    settings: Settings,
+    log_filter: LogFilterHandle,
+    write_timeout: Arc<Timeout>,
+    read_timeout: Arc<Timeout>,
+    retention_check_interval: watch::Sender<Option<Duration>>,
+    audit_log: Arc<AuditLog>,
+}
+
+impl Reloader {
+    /// Creates a reloader for the settings of `config`, the config the
+    /// server was started with
+    pub fn new(
+        config: &Config,
+        default_log_filter: &'static str,
+        log_filter: LogFilterHandle,
+        write_timeout: Arc<Timeout>,
+        read_timeout: Arc<Timeout>,
+        retention_check_interval: watch::Sender<Option<Duration>>,
+        audit_log: Arc<AuditLog>,
+    ) -> Self {
+        Self {
+            config_file: config.config_file.clone(),
+            default_log_filter,
+            settings: Settings::new(config, default_log_filter),
+            log_filter,
+            write_timeout,
+            read_timeout,
+            retention_check_interval,
+            audit_log,
+        }
+    }
+
+    /// Reloads the settings, applying and logging those that changed. If
+    /// the settings can not be loaded, nothing is changed.
+    pub fn reload(&mut self) {
+        if let Some(path) = &self.config_file {
+            if let Err(e) = file::reload(path) {
+                error!("Error reloading settings, keeping the current ones: {}", e);
+                return;
+            }
+        }
+
+        let new = match reload_config()
+            .map_err(|e| e.to_string())
+            .and_then(|config| Settings::reload(&config, &reload_args(), self.default_log_filter))
+        {
+            Ok(new) => new,
+            Err(e) => {
+                error!("Error reloading settings, keeping the current ones: {}", e);
+                return;
+            }
+        };
+
+        if new.log_filter != self.settings.log_filter {
+            match self.log_filter.set(&new.log_filter) {
+                Ok(()) => {
+                    self.log_change("log_filter", &self.settings.log_filter, &new.log_filter);
+                    self.settings.log_filter = new.log_filter;
+                }
+                Err(e) => error!("Error reloading log filter, keeping the current one: {}", e),
+            }
+        }
+
+        if new.write_timeout_seconds != self.settings.write_timeout_seconds {
+            self.write_timeout.set(timeout(new.write_timeout_seconds));
+            self.log_change(
+                "write_timeout_seconds",
+                self.settings.write_timeout_seconds,
+                new.write_timeout_seconds,
+            );
+            self.settings.write_timeout_seconds = new.write_timeout_seconds;
+        }
+
+        if new.read_timeout_seconds != self.settings.read_timeout_seconds {
+            self.read_timeout.set(timeout(new.read_timeout_seconds));
+            self.log_change(
+                "read_timeout_seconds",
+                self.settings.read_timeout_seconds,
+                new.read_timeout_seconds,
+            );
+            self.settings.read_timeout_seconds = new.read_timeout_seconds;
+        }
+
+        if new.retention_check_interval_seconds != self.settings.retention_check_interval_seconds {
+            // fails only if the retention task has stopped
+            let _ = self
+                .retention_check_interval
+                .send(timeout(new.retention_check_interval_seconds));
+            self.log_change(
+                "retention_check_interval_seconds",
+                self.settings.retention_check_interval_seconds,
+                new.retention_check_interval_seconds,
+            );
+            self.settings.retention_check_interval_seconds = new.retention_check_interval_seconds;
+        }
+
+        info!("Reloaded settings");
+    }
+
+    fn log_change(&self, setting: &str, old: impl Display, new: impl Display) {
+        info!(%setting, %old, %new, "Setting changed");
+        self.audit_log.record(&AuditEvent {
+            time: chrono::Utc::now(),
+            actor: "SIGHUP".to_string(),
+            remote_addr: None,
+            request_id: None,
+            action: "change_setting",
+            target: format!("{}={}", setting, new),
+            error: None,
+        });
+    }
+}
+
+/// Reloads the settings of `reloader` each time the process receives
+/// SIGHUP. Runs forever.
+#[cfg(unix)]
+pub async fn reload_on_sighup(mut reloader: Reloader) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut sighup = signal(SignalKind::hangup()).expect("installing SIGHUP handler");
+    while sighup.recv().await.is_some() {
+        info!("SIGHUP received, reloading settings");
+        reloader.reload();
+    }
+}
+
+/// Settings can only be reloaded on unix, where there is SIGHUP. Holds on
+/// to `reloader` forever so that the settings stay as they are.
+#[cfg(not(unix))]
+pub async fn reload_on_sighup(reloader: Reloader) {
+    let _reloader = reloader;
+    futures::future::pending::<()>().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use structopt::StructOpt;
+
+    #[test]
+    fn settings() {
+        let config = Config::from_iter_safe(&["server", "--write-timeout", "10"]).unwrap();
+        let settings = Settings::new(&config, "warn");
+        assert_eq!(settings.write_timeout_seconds, 10);
+
+        let config = Config::from_iter_safe(&["server", "--log", "debug"]).unwrap();
+        assert_eq!(Settings::new(&config, "warn").log_filter, "debug");
+    }
+
+    #[test]
+    fn reloaded_settings() {
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+
+        // settings given on the command line are kept
+        let value: u64 = reloaded(
+            &args(&["server", "--write-timeout=10"]),
+            "--write-timeout",
+            "INFLUXDB_IOX_TEST_RELOADED_UNSET",
+            10,
+            "30",
+        )
+        .unwrap();
+        assert_eq!(value, 10);
+
+        // settings not in the environment nor in the config file go back to
+        // their default
+        let value: u64 = reloaded(
+            &args(&["server"]),
+            "--write-timeout",
+            "INFLUXDB_IOX_TEST_RELOADED_UNSET",
+            10,
+            "30",
+        )
+        .unwrap();
+        assert_eq!(value, 30);
+
+        let err = reloaded::<u64>(
+            &args(&["server"]),
+            "--write-timeout",
+            "INFLUXDB_IOX_TEST_RELOADED_UNSET",
+            10,
+            "soon",
+        )
+        .unwrap_err();
+        assert_eq!(err, r#"Invalid default value "soon" for --write-timeout"#);
+    }
+}
@@ -0,0 +1,23 @@
+//! Periodic following of the primary of a read replica.
+
+use std::{sync::Arc, time::Duration};
+
+use server::{ConnectionManagerImpl as ConnectionManager, Server as AppServer};
+use tracing::{debug, error};
+
+/// Stores the writes the primary of `server` persisted every `interval`,
+/// starting immediately. Runs forever.
+pub async fn follow_primary_periodically(
+    server: Arc<AppServer<ConnectionManager>>,
+    interval: Duration,
+) {
+    let mut interval = tokio::time::interval(interval);
+
+    loop {
+        interval.tick().await;
+        match server.sync_from_primary().await {
+            Ok(writes) => debug!(writes, "Followed the primary"),
+            Err(e) => error!("Error following the primary: {}", e),
+        }
+    }
+}
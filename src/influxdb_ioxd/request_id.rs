@@ -0,0 +1,126 @@
+//! Request ids, which correlate the logs of a request with the response the
+//! client received.
+//!
+//! Each HTTP and gRPC request is given an id: the one the client sent in the
+//! `X-Request-Id` header if it is valid, or a newly generated one otherwise.
+//! The id is recorded on the span the request is handled in, so that it is
+//! part of every event logged while handling the request, and is returned to
+//! the client in the `X-Request-Id` response header.
+
+use std::task::{Context, Poll};
+
+use futures::{future::BoxFuture, FutureExt};
+use http::{HeaderMap, HeaderValue};
+use hyper::{service::Service, Body, Request, Response};
+use tonic::transport::NamedService;
+use tracing::info_span;
+use tracing_futures::Instrument;
+use uuid::Uuid;
+
+/// The header carrying the id of a request, in both the request and the
+/// response
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// The longest request id accepted from a client
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Returns the id of the request with `headers`, replacing the
+/// `X-Request-Id` header with a new id if it is missing or invalid
+pub fn ensure_request_id(headers: &mut HeaderMap) -> HeaderValue {
+    match headers.get(REQUEST_ID_HEADER) {
+        Some(request_id) if is_valid(request_id) => request_id.clone(),
+        _ => {
+            let request_id = HeaderValue::from_str(&Uuid::new_v4().to_string())
+                .expect("uuids are valid header values");
+            headers.insert(REQUEST_ID_HEADER, request_id.clone());
+            request_id
+        }
+    }
+}
+
+/// Client supplied ids are logged, so only short ids of printable ASCII
+/// are accepted
+fn is_valid(request_id: &HeaderValue) -> bool {
+    !request_id.is_empty()
+        && request_id.len() <= MAX_REQUEST_ID_LEN
+        && request_id.as_bytes().iter().all(u8::is_ascii_graphic)
+}
+
+/// Returns `request_id` for recording on a span. Valid ids are ASCII.
+pub fn as_str(request_id: &HeaderValue) -> &str {
+    request_id.to_str().unwrap_or_default()
+}
+
+/// Wraps a gRPC service, handling each request inside a `grpc_request` span
+/// carrying its path and id as described in the module docs
+#[derive(Debug, Clone)]
+pub struct RequestIdService<S> {
+    inner: S,
+}
+
+impl<S> RequestIdService<S> {
+    pub fn new(inner: S) -> Self {
+        Self { inner }
+    }
+}
+
+impl<S, B> Service<Request<Body>> for RequestIdService<S>
+where
+    S: Service<Request<Body>, Response = Response<B>>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<B>;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<Body>) -> Self::Future {
+        let request_id = ensure_request_id(req.headers_mut());
+        let span = info_span!(
+            "grpc_request",
+            path = %req.uri().path(),
+            request_id = as_str(&request_id),
+        );
+        let response = span.in_scope(|| self.inner.call(req));
+
+        async move {
+            let mut response = response.await?;
+            response.headers_mut().insert(REQUEST_ID_HEADER, request_id);
+            Ok(response)
+        }
+        .instrument(span)
+        .boxed()
+    }
+}
+
+impl<S: NamedService> NamedService for RequestIdService<S> {
+    const NAME: &'static str = S::NAME;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_id() {
+        let mut headers = HeaderMap::new();
+        headers.insert(REQUEST_ID_HEADER, HeaderValue::from_static("abc-123"));
+        assert_eq!(ensure_request_id(&mut headers), "abc-123");
+
+        for invalid in &["", "has space", &"x".repeat(MAX_REQUEST_ID_LEN + 1)] {
+            let mut headers = HeaderMap::new();
+            headers.insert(REQUEST_ID_HEADER, HeaderValue::from_str(invalid).unwrap());
+            let request_id = ensure_request_id(&mut headers);
+            assert_ne!(request_id, *invalid);
+            assert_eq!(headers[REQUEST_ID_HEADER], request_id);
+        }
+
+        let mut headers = HeaderMap::new();
+        let request_id = ensure_request_id(&mut headers);
+        assert_eq!(as_str(&request_id).len(), 36);
+        assert_eq!(headers[REQUEST_ID_HEADER], request_id);
+    }
+}
@@ -0,0 +1,44 @@
+//! Periodic dropping of the data older than the retention period of each
+//! database, and checkpointing of the write buffer past it.
+
+use std::{sync::Arc, time::Duration};
+
+use server::{ConnectionManagerImpl as ConnectionManager, Server as AppServer};
+use tokio::sync::watch;
+use tracing::error;
+
+/// Drops the expired data of `server` every `interval`, or never while the
+/// interval is `None`. The interval can be changed while running; a new
+/// interval is waited for in full from the time it is set. Runs until the
+/// interval's sender is dropped.
+pub async fn drop_expired_data_periodically(
+    server: Arc<AppServer<ConnectionManager>>,
+    mut interval: watch::Receiver<Option<Duration>>,
+) {
+    loop {
+        let current = *interval.borrow();
+        let changed = match current {
+            Some(period) => {
+                tokio::select! {
+                    _ = tokio::time::sleep(period) => {
+                        if let Err(e) = server.drop_expired_data().await {
+                            error!("Error dropping data past the retention period: {}", e);
+                        }
+                        // the writes past the retention period no longer
+                        // need to be replayed
+                        if let Err(e) = server.checkpoint_write_buffer().await {
+                            error!("Error checkpointing the write buffer: {}", e);
+                        }
+                        Ok(())
+                    }
+                    changed = interval.changed() => changed,
+                }
+            }
+            None => interval.changed().await,
+        };
+
+        if changed.is_err() {
+            return;
+        }
+    }
+}
@@ -1,56 +1,138 @@
 use std::fmt::Debug;
+use std::future::Future;
 use std::sync::Arc;
 
 use snafu::{ResultExt, Snafu};
 use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
 use tokio_stream::wrappers::TcpListenerStream;
 
 use data_types::error::ErrorLogger;
 use server::{ConnectionManager, Server};
 
+use self::storage::scatter::ShardedStorage;
+use super::{
+    auth::Authorizer,
+    ip_filter::{filter_connections, IpFilter},
+    query_log::QueryLog,
+    request_id::RequestIdService,
+    serving_readiness::ServingReadiness,
+    tls::ClientAllowlist,
+};
+
+mod cluster;
 mod flight;
 mod storage;
 mod testing;
+mod write;
 
 #[derive(Debug, Snafu)]
 pub enum Error {
     #[snafu(display("gRPC server error:  {}", source))]
     ServerError { source: tonic::transport::Error },
+
+    #[snafu(display("Invalid shard backend address: {}", source))]
+    InvalidShardBackend { source: tonic::transport::Error },
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
 /// Instantiate a server listening on the specified address
-/// implementing the IOx, Storage, and Flight gRPC interfaces, the
-/// underlying hyper server instance. Resolves when the server has
-/// shutdown.
-pub async fn make_server<M>(socket: TcpListener, server: Arc<Server<M>>) -> Result<()>
+/// implementing the IOx, Storage, Write, Cluster, and Flight gRPC interfaces,
+/// the underlying hyper server instance. If `tls` is provided connections
+/// are served over TLS, and clients presenting a certificate not allowed by
+/// `client_allowlist` are disconnected, as are clients `ip_filter` doesn't
+/// accept. Once `shutdown` resolves the server stops
+/// accepting new connections; it resolves when in-flight requests have
+/// completed and the server has shutdown. The health service reports the
+/// services as serving only while `serving_readiness` is ready. Queries to
+/// the storage service are sampled into `query_log`. Requests to all
+/// services but the health service are authenticated and authorized by
+/// `authorizer`. If the server routes writes to shards, storage queries are
+/// scattered to the shards and their results merged.
+pub async fn make_server<M>(
+    socket: TcpListener,
+    server: Arc<Server<M>>,
+    serving_readiness: ServingReadiness,
+    query_log: Arc<QueryLog>,
+    authorizer: Arc<Authorizer>,
+    tls: Option<TlsAcceptor>,
+    client_allowlist: Arc<ClientAllowlist>,
+    ip_filter: Arc<IpFilter>,
+    shutdown: impl Future<Output = ()>,
+) -> Result<()>
 where
     M: ConnectionManager + Send + Sync + Debug + 'static,
 {
-    let stream = TcpListenerStream::new(socket);
-
     let (mut health_reporter, health_service) = tonic_health::server::health_reporter();
 
     let services = [
         generated_types::STORAGE_SERVICE,
         generated_types::IOX_TESTING_SERVICE,
         generated_types::ARROW_SERVICE,
+        generated_types::WRITE_SERVICE,
+        generated_types::CLUSTER_SERVICE,
     ];
 
-    for service in &services {
-        health_reporter
-            .set_service_status(service, tonic_health::ServingStatus::Serving)
-            .await;
-    }
+    let mut ready = serving_readiness.subscribe();
+    tokio::spawn(async move {
+        loop {
+            let status = if *ready.borrow() {
+                tonic_health::ServingStatus::Serving
+            } else {
+                tonic_health::ServingStatus::NotServing
+            };
+            for service in &services {
+                health_reporter.set_service_status(service, status).await;
+            }
 
-    tonic::transport::Server::builder()
-        .add_service(health_service)
-        .add_service(testing::make_server())
-        .add_service(storage::make_server(Arc::clone(&server)))
-        .add_service(flight::make_server(server))
-        .serve_with_incoming(stream)
-        .await
-        .context(ServerError {})
-        .log_if_error("Running Tonic Server")
+            if ready.changed().await.is_err() {
+                return;
+            }
+        }
+    });
+
+    let shards = server
+        .shard_router()
+        .map(|router| ShardedStorage::new(router.backends()).map(Arc::new))
+        .transpose()
+        .context(InvalidShardBackend)?;
+
+    let router = tonic::transport::Server::builder()
+        .add_service(RequestIdService::new(health_service))
+        .add_service(RequestIdService::new(testing::make_server(Arc::clone(
+            &authorizer,
+        ))))
+        .add_service(RequestIdService::new(storage::make_server(
+            Arc::clone(&server),
+            query_log,
+            Arc::clone(&authorizer),
+            shards,
+        )))
+        .add_service(RequestIdService::new(write::make_server(
+            Arc::clone(&server),
+            Arc::clone(&authorizer),
+        )))
+        .add_service(RequestIdService::new(cluster::make_server(
+            Arc::clone(&server),
+            Arc::clone(&authorizer),
+        )))
+        .add_service(RequestIdService::new(flight::make_server(
+            server, authorizer,
+        )));
+
+    let connections = filter_connections(TcpListenerStream::new(socket), ip_filter);
+    match tls {
+        Some(acceptor) => {
+            let stream = super::tls::incoming(connections, acceptor, client_allowlist);
+            router.serve_with_incoming_shutdown(stream, shutdown).await
+        }
+        None => {
+            router
+                .serve_with_incoming_shutdown(connections, shutdown)
+                .await
+        }
+    }
+    .context(ServerError {})
+    .log_if_error("Running Tonic Server")
 }
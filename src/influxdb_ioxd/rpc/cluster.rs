@@ -0,0 +1,219 @@
+use std::{fmt::Debug, sync::Arc};
+
+use generated_types::influxdata::iox::cluster::v1::{
+    cluster_service_server::{ClusterService, ClusterServiceServer},
+    GetWriteDigestsRequest, GetWriteDigestsResponse, GossipRequest, GossipResponse,
+    HandOffShardRequest, HandOffShardResponse, SyncCatalogRequest, SyncCatalogResponse,
+};
+use server::{
+    catalog::CatalogEntry, cluster::MemberState, router::ShardRange, ConnectionManager, Server,
+};
+use tonic::{Request, Response, Status};
+
+use crate::influxdb_ioxd::auth::{interceptor, Authorizer};
+
+/// Implementation of the gRPC cluster service, through which the members
+/// of the cluster gossip their views of its membership and sync their
+/// catalogs, replicas compare their writes, and routers have backends hand
+/// off the shards moved away from them. As they change the catalog and move
+/// data between servers, all require a token that may administer the
+/// server, such as the `--replication-token` of the members.
+#[derive(Debug)]
+struct ClusterServiceImpl<M: ConnectionManager> {
+    server: Arc<Server<M>>,
+    authorizer: Arc<Authorizer>,
+}
+
+pub fn make_server<M>(
+    server: Arc<Server<M>>,
+    authorizer: Arc<Authorizer>,
+) -> ClusterServiceServer<impl ClusterService>
+where
+    M: ConnectionManager + Send + Sync + Debug + 'static,
+{
+    ClusterServiceServer::with_interceptor(
+        ClusterServiceImpl {
+            server,
+            authorizer: Arc::clone(&authorizer),
+        },
+        interceptor(authorizer),
+    )
+}
+
+#[tonic::async_trait]
+impl<M> ClusterService for ClusterServiceImpl<M>
+where
+    M: ConnectionManager + Send + Sync + Debug + 'static,
+{
+    async fn gossip(
+        &self,
+        request: Request<GossipRequest>,
+    ) -> Result<Response<GossipResponse>, Status> {
+        self.authorizer
+            .authenticate_metadata(request.metadata())
+            .and_then(|access| access.authorize_peer())
+            .map_err(|e| e.to_status())?;
+
+        let states = request
+            .into_inner()
+            .members
+            .into_iter()
+            .filter_map(MemberState::from_proto)
+            .collect();
+        let members = self
+            .server
+            .receive_gossip(states)
+            .map_err(|e| match e {
+                server::Error::ClusterDisabled => Status::failed_precondition(e.to_string()),
+                _ => Status::internal(e.to_string()),
+            })?
+            .into_iter()
+            .map(Into::into)
+            .collect();
+
+        Ok(Response::new(GossipResponse { members }))
+    }
+
+    async fn get_write_digests(
+        &self,
+        request: Request<GetWriteDigestsRequest>,
+    ) -> Result<Response<GetWriteDigestsResponse>, Status> {
+        self.authorizer
+            .authenticate_metadata(request.metadata())
+            .and_then(|access| access.authorize_peer())
+            .map_err(|e| e.to_status())?;
+
+        let digests = self
+            .server
+            .write_digests(&request.into_inner().db_name)
+            .map_err(|e| match e {
+                server::Error::InvalidDatabaseName { .. } => {
+                    Status::invalid_argument(e.to_string())
+                }
+                server::Error::DatabaseNotFound { .. } => Status::not_found(e.to_string()),
+                _ => Status::internal(e.to_string()),
+            })?
+            .into_iter()
+            .map(Into::into)
+            .collect();
+
+        Ok(Response::new(GetWriteDigestsResponse { digests }))
+    }
+
+    async fn sync_catalog(
+        &self,
+        request: Request<SyncCatalogRequest>,
+    ) -> Result<Response<SyncCatalogResponse>, Status> {
+        self.authorizer
+            .authenticate_metadata(request.metadata())
+            .and_then(|access| access.authorize_peer())
+            .map_err(|e| e.to_status())?;
+
+        let entries = request
+            .into_inner()
+            .entries
+            .into_iter()
+            .filter_map(CatalogEntry::from_proto)
+            .collect();
+        let entries = self
+            .server
+            .sync_catalog(entries)
+            .await
+            .map_err(|e| match e {
+                server::Error::ClusterDisabled => Status::failed_precondition(e.to_string()),
+                _ => Status::internal(e.to_string()),
+            })?
+            .into_iter()
+            .map(Into::into)
+            .collect();
+
+        Ok(Response::new(SyncCatalogResponse { entries }))
+    }
+
+    async fn hand_off_shard(
+        &self,
+        request: Request<HandOffShardRequest>,
+    ) -> Result<Response<HandOffShardResponse>, Status> {
+        self.authorizer
+            .authenticate_metadata(request.metadata())
+            .and_then(|access| access.authorize_peer())
+            .map_err(|e| e.to_status())?;
+
+        let request = request.into_inner();
+        let range = ShardRange {
+            start: request.start,
+            end: request.end,
+        };
+        let lines = self
+            .server
+            .hand_off_shard(range, &request.target)
+            .await
+            .map_err(|e| match e {
+                server::Error::IdNotSet => Status::failed_precondition(e.to_string()),
+                server::Error::ShardHandOffFailed { .. } => Status::unavailable(e.to_string()),
+                _ => Status::internal(e.to_string()),
+            })?;
+
+        Ok(Response::new(HandOffShardResponse { lines }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object_store::{memory::InMemory, ObjectStore};
+    use server::{tokens::Scope, ConnectionManagerImpl};
+    use tonic::Code;
+
+    use crate::influxdb_ioxd::auth::ApiTokens;
+
+    fn request<T>(message: T, token: &str) -> Request<T> {
+        let mut request = Request::new(message);
+        request
+            .metadata_mut()
+            .insert("authorization", format!("Token {}", token).parse().unwrap());
+        request
+    }
+
+    #[tokio::test]
+    async fn write_tokens_rejected() {
+        let server = Arc::new(Server::new(
+            ConnectionManagerImpl::default(),
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        ));
+        let tokens = ApiTokens::new(vec!["peer".to_string()])
+            .with_scope(vec!["ingest".to_string()], Scope::Write);
+        let authorizer = Arc::new(Authorizer::new(tokens, Arc::<Server<_>>::clone(&server)));
+        let service = ClusterServiceImpl { server, authorizer };
+
+        let statuses = vec![
+            service
+                .gossip(request(Default::default(), "ingest"))
+                .await
+                .unwrap_err(),
+            service
+                .get_write_digests(request(Default::default(), "ingest"))
+                .await
+                .unwrap_err(),
+            service
+                .sync_catalog(request(Default::default(), "ingest"))
+                .await
+                .unwrap_err(),
+            service
+                .hand_off_shard(request(Default::default(), "ingest"))
+                .await
+                .unwrap_err(),
+        ];
+        for status in statuses {
+            assert_eq!(status.code(), Code::PermissionDenied, "{:?}", status);
+        }
+
+        // the token of the members is accepted, and the request then fails
+        // as the server isn't in a cluster
+        let status = service
+            .gossip(request(Default::default(), "peer"))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::FailedPrecondition, "{:?}", status);
+    }
+}
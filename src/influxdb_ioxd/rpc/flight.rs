@@ -14,9 +14,11 @@ use arrow_deps::{
         Action, ActionType, Criteria, Empty, FlightData, FlightDescriptor, FlightInfo,
         HandshakeRequest, HandshakeResponse, PutResult, SchemaResult, Ticket,
     },
-    datafusion::physical_plan::collect,
 };
 use query::{frontend::sql::SQLQueryPlanner, DatabaseStore};
+use server::tokens::Scope;
+
+use crate::influxdb_ioxd::auth::{interceptor, Authorizer};
 
 #[derive(Debug, Snafu)]
 pub enum Error {
@@ -89,10 +91,21 @@ struct ReadInfo {
 #[derive(Debug)]
 struct FlightService<T: DatabaseStore> {
     pub db_store: Arc<T>,
+    /// Checks that requests may read the databases they query
+    pub authorizer: Arc<Authorizer>,
 }
 
-pub fn make_server<T: DatabaseStore + 'static>(db_store: Arc<T>) -> FlightServer<impl Flight> {
-    FlightServer::new(FlightService { db_store })
+pub fn make_server<T: DatabaseStore + 'static>(
+    db_store: Arc<T>,
+    authorizer: Arc<Authorizer>,
+) -> FlightServer<impl Flight> {
+    FlightServer::with_interceptor(
+        FlightService {
+            db_store,
+            authorizer: Arc::clone(&authorizer),
+        },
+        interceptor(authorizer),
+    )
 }
 
 #[tonic::async_trait]
@@ -121,6 +134,10 @@ where
         &self,
         request: Request<Ticket>,
     ) -> Result<Response<Self::DoGetStream>, tonic::Status> {
+        let access = self
+            .authorizer
+            .authenticate_metadata(request.metadata())
+            .map_err(|e| e.to_status())?;
         let ticket = request.into_inner();
         let json_str = String::from_utf8(ticket.ticket.to_vec()).context(InvalidTicket {
             ticket: ticket.ticket,
@@ -128,6 +145,9 @@ where
 
         let read_info: ReadInfo =
             serde_json::from_str(&json_str).context(InvalidQuery { query: &json_str })?;
+        access
+            .authorize_database(Scope::Read, &read_info.database_name)
+            .map_err(|e| e.to_status())?;
 
         let db = self
             .db_store
@@ -148,7 +168,8 @@ where
             })?;
 
         // execute the query
-        let results = collect(Arc::clone(&physical_plan))
+        let results = executor
+            .collect(Arc::clone(&physical_plan))
             .await
             .map_err(|e| Box::new(e) as _)
             .context(Query {
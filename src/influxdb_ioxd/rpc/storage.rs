@@ -12,18 +12,46 @@ pub mod data;
 pub mod expr;
 pub mod id;
 pub mod input;
+pub mod scatter;
 pub mod service;
 
 use generated_types::storage_server::{Storage, StorageServer};
 use query::DatabaseStore;
 use std::sync::Arc;
 
+use self::scatter::ShardedStorage;
+
+use crate::influxdb_ioxd::{
+    auth::{interceptor, Authorizer},
+    query_log::QueryLog,
+};
+
 /// Concrete implementation of the gRPC InfluxDB Storage Service API
 #[derive(Debug)]
 struct StorageService<T: DatabaseStore> {
     pub db_store: Arc<T>,
+    /// The log queries are sampled into
+    pub query_log: Arc<QueryLog>,
+    /// Checks that requests may read the databases they ask for
+    pub authorizer: Arc<Authorizer>,
+    /// The shards `read_filter`, `tag_keys` and `tag_values` requests are
+    /// scattered to instead of `db_store`, if writes are routed to shards
+    pub shards: Option<Arc<ShardedStorage>>,
 }
 
-pub fn make_server<T: DatabaseStore + 'static>(db_store: Arc<T>) -> StorageServer<impl Storage> {
-    StorageServer::new(StorageService { db_store })
+pub fn make_server<T: DatabaseStore + 'static>(
+    db_store: Arc<T>,
+    query_log: Arc<QueryLog>,
+    authorizer: Arc<Authorizer>,
+    shards: Option<Arc<ShardedStorage>>,
+) -> StorageServer<impl Storage> {
+    StorageServer::with_interceptor(
+        StorageService {
+            db_store,
+            query_log,
+            authorizer: Arc::clone(&authorizer),
+            shards,
+        },
+        interceptor(authorizer),
+    )
 }
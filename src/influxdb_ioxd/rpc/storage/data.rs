@@ -47,16 +47,21 @@ pub fn tag_keys_to_byte_vecs(tag_keys: Arc<BTreeSet<String>>) -> Vec<Vec<u8>> {
     let mut byte_vecs = Vec::with_capacity(2 + tag_keys.len());
     byte_vecs.push(TAG_KEY_MEASUREMENT.to_vec()); // Shown as _m == _measurement
     tag_keys.iter().for_each(|name| {
-        byte_vecs.push(name.bytes().collect());
+        byte_vecs.push(name.as_bytes().to_vec());
     });
     byte_vecs.push(TAG_KEY_FIELD.to_vec()); // Shown as _f == _field
     byte_vecs
 }
 
 fn series_set_to_frames(series_set: SeriesSet) -> Result<Vec<Frame>> {
-    let mut data_records = Vec::new();
-    for field_index in series_set.field_indexes.as_slice().iter() {
-        field_to_data(&mut data_records, &series_set, field_index)?
+    // the measurement and tags are the same for every field of the series
+    // set, so they are only converted once
+    let tags = convert_tags(series_set.table_name.as_ref(), &series_set.tags);
+
+    let field_indexes = series_set.field_indexes.as_slice();
+    let mut data_records = Vec::with_capacity(2 * field_indexes.len());
+    for field_index in field_indexes.iter() {
+        field_to_data(&mut data_records, &series_set, &tags, field_index)?
     }
 
     let frames = data_records
@@ -106,7 +111,7 @@ fn group_description_to_frames(group_description: GroupDescription) -> Result<Ve
     let (tag_keys, partition_key_vals): (Vec<Vec<u8>>, Vec<Vec<u8>>) = group_description
         .tags
         .into_iter()
-        .map(|(k, v)| (k.bytes().collect(), v.bytes().collect()))
+        .map(|(k, v)| (k.as_bytes().to_vec(), v.as_bytes().to_vec()))
         .unzip();
 
     let group_frame = GroupFrame {
@@ -139,10 +144,12 @@ fn is_all_null(arr: &ArrayRef, start_row: usize, num_rows: usize) -> bool {
     (start_row..end_row).all(|i| arr.is_null(i))
 }
 
-// Convert and append a single field to a sequence of frames
+// Convert and append a single field to a sequence of frames. `tags` are
+// the converted tags of the series set, without the field name
 fn field_to_data(
     frames: &mut Vec<Data>,
     series_set: &SeriesSet,
+    tags: &[Tag],
     indexes: &FieldIndex,
 ) -> Result<()> {
     let batch = &series_set.batch;
@@ -160,12 +167,15 @@ fn field_to_data(
         return Ok(());
     }
 
+    let mut field_tags = Vec::with_capacity(1 + tags.len());
+    field_tags.push(Tag {
+        key: b"_field".to_vec(),
+        value: field.name().as_bytes().to_vec(),
+    });
+    field_tags.extend_from_slice(tags);
+
     let series_frame = SeriesFrame {
-        tags: convert_tags(
-            series_set.table_name.as_ref(),
-            schema.field(indexes.value_index).name(),
-            &series_set.tags,
-        ),
+        tags: field_tags,
         data_type: data_type(array)? as i32,
     };
     frames.push(Data::Series(series_frame));
@@ -221,29 +231,22 @@ fn field_to_data(
 }
 
 // Convert the tag=value pairs from the series set to the correct gRPC
-// format, and add the _f and _m tags for the field name and measurement
-fn convert_tags(
-    table_name: &str,
-    field_name: &str,
-    tags: &[(Arc<String>, Arc<String>)],
-) -> Vec<Tag> {
-    let mut converted_tags = Vec::new();
+// format, and add the _m tag for the measurement. The _f tag for the field
+// name, which sorts first, is added for each field by `field_to_data`
+fn convert_tags(table_name: &str, tags: &[(Arc<String>, Arc<String>)]) -> Vec<Tag> {
+    let mut converted_tags = Vec::with_capacity(1 + tags.len());
 
     // Special case "measurement" name which is modeled as a tag of
-    // "_measurement" and "field" which is modeled as a tag of "_field"
-    converted_tags.push(Tag {
-        key: b"_field".to_vec(),
-        value: field_name.bytes().collect(),
-    });
+    // "_measurement"
     converted_tags.push(Tag {
         key: b"_measurement".to_vec(),
-        value: table_name.bytes().collect(),
+        value: table_name.as_bytes().to_vec(),
     });
 
     // convert the rest of the tags
     converted_tags.extend(tags.iter().map(|(k, v)| {
-        let key = k.bytes().collect();
-        let value = v.bytes().collect();
+        let key = k.as_bytes().to_vec();
+        let value = v.as_bytes().to_vec();
 
         Tag { key, value }
     }));
@@ -252,7 +255,8 @@ fn convert_tags(
 }
 
 trait ExtractValues<T> {
-    /// Extracts num_rows of data starting from start_row as a vector
+    /// Extracts num_rows of data starting from start_row as a vector,
+    /// allocated once
     fn extract_values(&self, start_row: usize, num_rows: usize) -> Vec<T>;
 }
 
@@ -267,15 +271,15 @@ impl ExtractValues<String> for StringArray {
 
 impl ExtractValues<i64> for Int64Array {
     fn extract_values(&self, start_row: usize, num_rows: usize) -> Vec<i64> {
-        let end_row = start_row + num_rows;
-        (start_row..end_row).map(|row| self.value(row)).collect()
+        // copied straight from the buffer of the array
+        self.values()[start_row..start_row + num_rows].to_vec()
     }
 }
 
 impl ExtractValues<f64> for Float64Array {
     fn extract_values(&self, start_row: usize, num_rows: usize) -> Vec<f64> {
-        let end_row = start_row + num_rows;
-        (start_row..end_row).map(|row| self.value(row)).collect()
+        // copied straight from the buffer of the array
+        self.values()[start_row..start_row + num_rows].to_vec()
     }
 }
 
@@ -514,6 +518,19 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_extract_values_of_sliced_arrays() {
+        let array: ArrayRef = Arc::new(Int64Array::from(vec![1, 2, 3, 4, 5]));
+        let sliced = array.slice(1, 4);
+        let sliced = sliced.as_any().downcast_ref::<Int64Array>().unwrap();
+        assert_eq!(sliced.extract_values(1, 2), vec![3, 4]);
+
+        let array: ArrayRef = Arc::new(Float64Array::from(vec![1.0, 2.0, 3.0]));
+        let sliced = array.slice(2, 1);
+        let sliced = sliced.as_any().downcast_ref::<Float64Array>().unwrap();
+        assert_eq!(sliced.extract_values(0, 1), vec![3.0]);
+    }
+
     #[test]
     fn test_group_group_conversion() {
         let group_description = GroupDescription {
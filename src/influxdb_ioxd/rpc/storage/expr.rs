@@ -11,6 +11,7 @@ use arrow_deps::datafusion::{
     logical_plan::{binary_expr, Expr, Operator},
     prelude::*,
 };
+use data_types::redaction::Redacted;
 use generated_types::{
     aggregate::AggregateType as RPCAggregateType, node::Comparison as RPCComparison,
     node::Logical as RPCLogical, node::Value as RPCValue, read_group_request::Group as RPCGroup,
@@ -68,7 +69,7 @@ pub enum Error {
 
     #[snafu(display(
         "Error creating predicate: Regular expression predicates are not supported: {}",
-        regexp
+        Redacted(regexp)
     ))]
     RegExpLiteralNotSupported { regexp: String },
 
@@ -84,8 +85,8 @@ pub enum Error {
     StartsWithNotSupported {},
 
     #[snafu(display(
-        "Error creating predicate: Unexpected children for predicate: {:?}",
-        value
+        "Error creating predicate: Unexpected children for predicate: {}",
+        Redacted(format!("{:?}", value))
     ))]
     UnexpectedChildren { value: RPCValue },
 
@@ -642,7 +643,7 @@ impl<'a> Loggable<'a> for RPCPredicate {
 }
 
 /// Returns a struct that can format gRPC predicate (aka `RPCPredicates`) for
-/// Display
+/// Display, with its literal values redacted if configured
 ///
 /// For example:
 /// let pred = RPCPredicate (...);
@@ -654,21 +655,47 @@ pub fn displayable_predicate(pred: Option<&RPCPredicate>) -> impl fmt::Display +
         fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
             match self.0 {
                 None => write!(f, "<NONE>"),
-                Some(pred) => format_predicate(pred, f),
+                Some(pred) => format_predicate(pred, true, f),
             }
         }
     }
     Wrapper(pred)
 }
 
-fn format_predicate<'a>(pred: &'a RPCPredicate, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+/// Returns a struct that formats the shape of a gRPC predicate for Display:
+/// the predicate as formatted by `displayable_predicate`, but with its
+/// literal values replaced by `?`
+///
+/// For example, `(TagRef:host == "server01")` has the shape
+/// `(TagRef:host == ?)`
+pub fn predicate_shape(pred: Option<&RPCPredicate>) -> impl fmt::Display + '_ {
+    struct Wrapper<'a>(Option<&'a RPCPredicate>);
+
+    impl<'a> fmt::Display for Wrapper<'a> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self.0 {
+                None => write!(f, "<NONE>"),
+                Some(pred) => format_predicate(pred, false, f),
+            }
+        }
+    }
+    Wrapper(pred)
+}
+
+/// Formats `pred`, with its literal values if `literals` is set or `?`
+/// in their place otherwise
+fn format_predicate<'a>(
+    pred: &'a RPCPredicate,
+    literals: bool,
+    f: &mut fmt::Formatter<'_>,
+) -> fmt::Result {
     match &pred.root {
-        Some(r) => format_node(r, f),
+        Some(r) => format_node(r, literals, f),
         None => write!(f, "root: <NONE>"),
     }
 }
 
-fn format_node<'a>(node: &'a RPCNode, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+fn format_node<'a>(node: &'a RPCNode, literals: bool, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     let value = match &node.value {
         None => {
             write!(f, "node: NONE")?;
@@ -679,29 +706,29 @@ fn format_node<'a>(node: &'a RPCNode, f: &mut fmt::Formatter<'_>) -> fmt::Result
 
     match node.children.len() {
         0 => {
-            format_value(value, f)?;
+            format_value(value, literals, f)?;
         }
         // print using infix notation
         // (child0 <op> child1)
         2 => {
             write!(f, "(")?;
-            format_node(&node.children[0], f)?;
+            format_node(&node.children[0], literals, f)?;
             write!(f, " ")?;
-            format_value(value, f)?;
+            format_value(value, literals, f)?;
             write!(f, " ")?;
-            format_node(&node.children[1], f)?;
+            format_node(&node.children[1], literals, f)?;
             write!(f, ")")?;
         }
         // print func notation
         // <op>(child0, chold1, ...)
         _ => {
-            format_value(value, f)?;
+            format_value(value, literals, f)?;
             write!(f, "(")?;
             for (i, child) in node.children.iter().enumerate() {
                 if i > 0 {
                     write!(f, ", ")?;
                 }
-                format_node(child, f)?;
+                format_node(child, literals, f)?;
             }
             write!(f, ")")?;
         }
@@ -710,15 +737,23 @@ fn format_node<'a>(node: &'a RPCNode, f: &mut fmt::Formatter<'_>) -> fmt::Result
     Ok(())
 }
 
-fn format_value<'a>(value: &'a RPCValue, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+fn format_value<'a>(
+    value: &'a RPCValue,
+    literals: bool,
+    f: &mut fmt::Formatter<'_>,
+) -> fmt::Result {
     use RPCValue::*;
     match value {
-        StringValue(s) => write!(f, "\"{}\"", s),
-        BoolValue(b) => write!(f, "{}", b),
-        IntValue(i) => write!(f, "{}", i),
-        UintValue(u) => write!(f, "{}", u),
-        FloatValue(fval) => write!(f, "{}", fval),
-        RegexValue(r) => write!(f, "RegEx:{}", r),
+        StringValue(_) | BoolValue(_) | IntValue(_) | UintValue(_) | FloatValue(_) if !literals => {
+            write!(f, "?")
+        }
+        RegexValue(_) if !literals => write!(f, "RegEx:?"),
+        StringValue(s) => write!(f, "\"{}\"", Redacted(s)),
+        BoolValue(b) => write!(f, "{}", Redacted(b)),
+        IntValue(i) => write!(f, "{}", Redacted(i)),
+        UintValue(u) => write!(f, "{}", Redacted(u)),
+        FloatValue(fval) => write!(f, "{}", Redacted(fval)),
+        RegexValue(r) => write!(f, "RegEx:{}", Redacted(r)),
         TagRefValue(bytes) => {
             let temp = String::from_utf8_lossy(bytes);
             let sval = match bytes.as_slice() {
@@ -1471,4 +1506,19 @@ mod tests {
             format!("{}", displayable_predicate(rpc_pred.as_ref()))
         );
     }
+
+    #[test]
+    fn test_predicate_shape() {
+        let node = RPCNode {
+            node_type: RPCNodeType::LogicalExpression as i32,
+            children: vec![make_tag_ref_node(&[0], "val1"), make_host_comparison().0],
+            value: Some(RPCValue::Logical(RPCLogical::And as i32)),
+        };
+        let rpc_pred = Some(RPCPredicate { root: Some(node) });
+        assert_eq!(
+            "((TagRef:_m[0x00] == ?) AND (FieldRef:host > ?))",
+            format!("{}", predicate_shape(rpc_pred.as_ref()))
+        );
+        assert_eq!("<NONE>", format!("{}", predicate_shape(None)));
+    }
 }
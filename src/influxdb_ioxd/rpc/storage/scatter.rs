@@ -0,0 +1,342 @@
+//! Scatter-gather of storage queries across the servers writes are sharded
+//! to.
+//!
+//! A server routing writes stores none itself, so its storage service sends
+//! the `read_filter`, `tag_keys` and `tag_values` requests it receives to
+//! every backend in parallel, with the client's authorization, and merges
+//! their responses. The series returned by `read_filter` are sorted by
+//! series key, and the points of a series returned by several backends,
+//! e.g. after backends were added and some series moved, are merged by
+//! timestamp, keeping one point per timestamp. Tag keys and values are
+//! merged into a sorted list without duplicates. The request fails if any
+//! backend fails.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use futures::future::try_join_all;
+use generated_types::{
+    read_response::{frame::Data, Frame, SeriesFrame},
+    storage_client::StorageClient,
+    ReadFilterRequest, ReadResponse, StringValuesResponse, TagKeysRequest, TagValuesRequest,
+};
+use tonic::{
+    metadata::{Ascii, MetadataValue},
+    transport::{Channel, Endpoint},
+    Request, Status,
+};
+
+/// The `authorization` header of a request, sent on to the backends
+pub type Authorization = MetadataValue<Ascii>;
+
+/// The storage services of the backends writes are sharded to
+#[derive(Debug)]
+pub struct ShardedStorage {
+    backends: Vec<(String, StorageClient<Channel>)>,
+}
+
+impl ShardedStorage {
+    /// Queries `backends`, the gRPC addresses of the servers holding the
+    /// shards, as `host:port` or URLs. Connections are made on first use.
+    pub fn new(backends: &[String]) -> Result<Self, tonic::transport::Error> {
+        let backends = backends
+            .iter()
+            .map(|backend| {
+                let url = if backend.contains("://") {
+                    backend.clone()
+                } else {
+                    format!("http://{}", backend)
+                };
+                let channel = Endpoint::from_shared(url)?.connect_lazy()?;
+                Ok((backend.clone(), StorageClient::new(channel)))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self { backends })
+    }
+
+    /// Runs `read_filter` on every backend, returning the merged series
+    pub async fn read_filter(
+        &self,
+        request: ReadFilterRequest,
+        authorization: Option<&Authorization>,
+    ) -> Result<Vec<ReadResponse>, Status> {
+        let shards = try_join_all(self.backends.iter().map(|(backend, client)| {
+            let mut client = client.clone();
+            let request = with_authorization(request.clone(), authorization);
+            async move {
+                let mut responses = client
+                    .read_filter(request)
+                    .await
+                    .map_err(|e| backend_error(backend, e))?
+                    .into_inner();
+                let mut frames = vec![];
+                while let Some(response) = responses
+                    .message()
+                    .await
+                    .map_err(|e| backend_error(backend, e))?
+                {
+                    frames.extend(response.frames);
+                }
+                Ok::<_, Status>(frames)
+            }
+        }))
+        .await?;
+
+        Ok(merge_series(shards))
+    }
+
+    /// Runs `tag_keys` on every backend, returning the merged keys
+    pub async fn tag_keys(
+        &self,
+        request: TagKeysRequest,
+        authorization: Option<&Authorization>,
+    ) -> Result<StringValuesResponse, Status> {
+        let shards = try_join_all(self.backends.iter().map(|(backend, client)| {
+            let mut client = client.clone();
+            let request = with_authorization(request.clone(), authorization);
+            async move {
+                let responses = client
+                    .tag_keys(request)
+                    .await
+                    .map_err(|e| backend_error(backend, e))?
+                    .into_inner();
+                collect_values(backend, responses).await
+            }
+        }))
+        .await?;
+
+        Ok(merge_values(shards))
+    }
+
+    /// Runs `tag_values` on every backend, returning the merged values
+    pub async fn tag_values(
+        &self,
+        request: TagValuesRequest,
+        authorization: Option<&Authorization>,
+    ) -> Result<StringValuesResponse, Status> {
+        let shards = try_join_all(self.backends.iter().map(|(backend, client)| {
+            let mut client = client.clone();
+            let request = with_authorization(request.clone(), authorization);
+            async move {
+                let responses = client
+                    .tag_values(request)
+                    .await
+                    .map_err(|e| backend_error(backend, e))?
+                    .into_inner();
+                collect_values(backend, responses).await
+            }
+        }))
+        .await?;
+
+        Ok(merge_values(shards))
+    }
+}
+
+fn with_authorization<T>(message: T, authorization: Option<&Authorization>) -> Request<T> {
+    let mut request = Request::new(message);
+    if let Some(authorization) = authorization {
+        request
+            .metadata_mut()
+            .insert("authorization", authorization.clone());
+    }
+    request
+}
+
+/// Names the backend an error comes from, keeping its code
+fn backend_error(backend: &str, status: Status) -> Status {
+    Status::new(
+        status.code(),
+        format!("backend {}: {}", backend, status.message()),
+    )
+}
+
+async fn collect_values(
+    backend: &str,
+    mut responses: tonic::Streaming<StringValuesResponse>,
+) -> Result<Vec<Vec<u8>>, Status> {
+    let mut values = vec![];
+    while let Some(response) = responses
+        .message()
+        .await
+        .map_err(|e| backend_error(backend, e))?
+    {
+        values.extend(response.values);
+    }
+    Ok(values)
+}
+
+/// Merges the values returned by each backend into a sorted list without
+/// duplicates
+fn merge_values(shards: Vec<Vec<Vec<u8>>>) -> StringValuesResponse {
+    let values: BTreeSet<_> = shards.into_iter().flatten().collect();
+    StringValuesResponse {
+        values: values.into_iter().collect(),
+    }
+}
+
+/// A series being merged: its series frame and its points so far
+struct Series {
+    frame: SeriesFrame,
+    points: Option<Data>,
+}
+
+/// Merges the `read_filter` frames returned by each backend into one
+/// response per series, sorted by series key. When backends return points
+/// at the same timestamp of a series, the point of the first backend is
+/// kept.
+fn merge_series(shards: Vec<Vec<Frame>>) -> Vec<ReadResponse> {
+    let mut series: BTreeMap<Vec<(Vec<u8>, Vec<u8>)>, Series> = BTreeMap::new();
+
+    for frames in shards {
+        let mut current = None;
+        for frame in frames {
+            match frame.data {
+                Some(Data::Series(frame)) => {
+                    let key: Vec<_> = frame
+                        .tags
+                        .iter()
+                        .map(|tag| (tag.key.clone(), tag.value.clone()))
+                        .collect();
+                    series.entry(key.clone()).or_insert(Series {
+                        frame,
+                        points: None,
+                    });
+                    current = Some(key);
+                }
+                // groups aren't returned by read_filter
+                Some(Data::Group(_)) | None => {}
+                Some(points) => {
+                    if let Some(series) = current.as_ref().and_then(|key| series.get_mut(key)) {
+                        series.points = Some(match series.points.take() {
+                            Some(merged) => merge_points(merged, points),
+                            None => merge_points(points, None),
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    series
+        .into_iter()
+        .map(|(_, series)| {
+            let mut frames = vec![Frame {
+                data: Some(Data::Series(series.frame)),
+            }];
+            frames.extend(series.points.map(|points| Frame { data: Some(points) }));
+            ReadResponse { frames }
+        })
+        .collect()
+}
+
+/// Adds the points of `more` to `points`, sorted by timestamp with a single
+/// point per timestamp, the one already in `points` winning. Points of
+/// another type than those of `points`, which backends don't return for the
+/// same series, are ignored.
+fn merge_points(points: Data, more: impl Into<Option<Data>>) -> Data {
+    macro_rules! merge {
+        ($variant:ident, $points:expr, $more:expr) => {{
+            let mut points = $points;
+            let mut merged = BTreeMap::new();
+            if let Some(Data::$variant(more)) = $more {
+                merged.extend(more.timestamps.into_iter().zip(more.values));
+            }
+            merged.extend(points.timestamps.drain(..).zip(points.values.drain(..)));
+            let (timestamps, values) = merged.into_iter().unzip();
+            points.timestamps = timestamps;
+            points.values = values;
+            Data::$variant(points)
+        }};
+    }
+
+    let more = more.into();
+    match points {
+        Data::FloatPoints(points) => merge!(FloatPoints, points, more),
+        Data::IntegerPoints(points) => merge!(IntegerPoints, points, more),
+        Data::UnsignedPoints(points) => merge!(UnsignedPoints, points, more),
+        Data::BooleanPoints(points) => merge!(BooleanPoints, points, more),
+        Data::StringPoints(points) => merge!(StringPoints, points, more),
+        other => other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use generated_types::{
+        read_response::{DataType, FloatPointsFrame, IntegerPointsFrame},
+        Tag,
+    };
+
+    fn series(host: &str) -> Frame {
+        Frame {
+            data: Some(Data::Series(SeriesFrame {
+                tags: vec![
+                    Tag {
+                        key: b"_measurement".to_vec(),
+                        value: b"cpu".to_vec(),
+                    },
+                    Tag {
+                        key: b"host".to_vec(),
+                        value: host.as_bytes().to_vec(),
+                    },
+                ],
+                data_type: DataType::Float as i32,
+            })),
+        }
+    }
+
+    fn floats(timestamps: Vec<i64>, values: Vec<f64>) -> Frame {
+        Frame {
+            data: Some(Data::FloatPoints(FloatPointsFrame { timestamps, values })),
+        }
+    }
+
+    #[test]
+    fn merges_series() {
+        let shard_a = vec![
+            series("c"),
+            floats(vec![1, 3], vec![1.0, 3.0]),
+            series("a"),
+            floats(vec![1], vec![1.0]),
+            floats(vec![2], vec![2.0]),
+        ];
+        let shard_b = vec![
+            series("b"),
+            floats(vec![1], vec![1.0]),
+            series("c"),
+            floats(vec![2, 3], vec![20.0, 30.0]),
+        ];
+
+        let responses = merge_series(vec![shard_a, shard_b]);
+        assert_eq!(responses.len(), 3);
+        assert_eq!(responses[0].frames[0], series("a"));
+        assert_eq!(responses[0].frames[1], floats(vec![1, 2], vec![1.0, 2.0]));
+        assert_eq!(responses[1].frames[0], series("b"));
+        assert_eq!(responses[2].frames[0], series("c"));
+        assert_eq!(
+            responses[2].frames[1],
+            floats(vec![1, 2, 3], vec![1.0, 20.0, 3.0])
+        );
+
+        // points of another type are ignored
+        let integers = Data::IntegerPoints(IntegerPointsFrame {
+            timestamps: vec![5],
+            values: vec![5],
+        });
+        let merged = merge_points(floats(vec![1], vec![1.0]).data.unwrap(), integers);
+        assert_eq!(Some(merged), floats(vec![1], vec![1.0]).data);
+    }
+
+    #[test]
+    fn merges_values() {
+        let merged = merge_values(vec![
+            vec![vec![0], b"host".to_vec(), vec![255]],
+            vec![vec![0], b"region".to_vec(), b"host".to_vec(), vec![255]],
+        ]);
+        assert_eq!(
+            merged.values,
+            vec![vec![0], b"host".to_vec(), b"region".to_vec(), vec![255]]
+        );
+    }
+}
@@ -9,8 +9,13 @@ use super::{
     },
     expr::{self, AddRPCNode, Loggable, SpecialTagKeys},
     input::GrpcInputs,
+    scatter::{Authorization, ShardedStorage},
     StorageService,
 };
+use crate::influxdb_ioxd::{
+    auth::{self, Access},
+    query_log::QueryLog,
+};
 use data_types::{error::ErrorLogger, names::org_and_bucket_to_database, DatabaseName};
 use generated_types::{
     google::protobuf::Empty, storage_server::Storage, CapabilitiesResponse, Capability,
@@ -28,8 +33,9 @@ use query::{
     predicate::PredicateBuilder,
     Database, DatabaseStore,
 };
+use server::tokens::Scope;
 use snafu::{OptionExt, ResultExt, Snafu};
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, fmt::Display, sync::Arc, time::Instant};
 use tokio::sync::mpsc;
 use tokio_stream::wrappers::ReceiverStream;
 use tonic::Status;
@@ -40,6 +46,9 @@ pub enum Error {
     #[snafu(display("Database not found: {}", db_name))]
     DatabaseNotFound { db_name: String },
 
+    #[snafu(display("{}", source))]
+    PermissionDenied { source: auth::Error },
+
     #[snafu(display("Error listing tables in database '{}': {}", db_name, source))]
     ListingTables {
         db_name: String,
@@ -154,6 +163,9 @@ pub enum Error {
 
     #[snafu(display("Operation not yet implemented:  {}", operation))]
     NotYetImplemented { operation: String },
+
+    #[snafu(display("Error querying shards: {}", source.message()))]
+    QueryingShards { source: tonic::Status },
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -173,6 +185,7 @@ impl Error {
     fn to_status(&self) -> tonic::Status {
         match &self {
             Self::DatabaseNotFound { .. } => Status::not_found(self.to_string()),
+            Self::PermissionDenied { source } => source.to_status(),
             Self::ListingTables { .. } => Status::internal(self.to_string()),
             Self::ListingColumns { .. } => {
                 // TODO: distinguish between input errors and internal errors
@@ -199,6 +212,7 @@ impl Error {
             Self::SendingResults { .. } => Status::internal(self.to_string()),
             Self::InternalHintsFieldNotSupported { .. } => Status::internal(self.to_string()),
             Self::NotYetImplemented { .. } => Status::internal(self.to_string()),
+            Self::QueryingShards { source } => Status::new(source.code(), self.to_string()),
         }
     }
 }
@@ -217,9 +231,31 @@ where
     ) -> Result<tonic::Response<Self::ReadFilterStream>, Status> {
         let (tx, rx) = mpsc::channel(4);
 
+        let db_name = get_database_name(req.get_ref())?;
+        let access = self.access(&req)?;
+        let authorization = self.authorization(&req);
         let read_filter_request = req.into_inner();
 
-        let db_name = get_database_name(&read_filter_request)?;
+        if let Some(shards) = &self.shards {
+            let query = self.log_query(
+                "read_filter",
+                &db_name,
+                read_filter_request.predicate.as_ref(),
+            );
+            let result = scatter_read_filter(
+                tx,
+                shards,
+                db_name.as_str(),
+                &access,
+                authorization.as_ref(),
+                read_filter_request,
+            )
+            .await;
+            query.finish(&result);
+            result.map_err(|e| e.to_status())?;
+
+            return Ok(tonic::Response::new(ReceiverStream::new(rx)));
+        }
 
         let ReadFilterRequest {
             read_source: _read_source,
@@ -234,15 +270,18 @@ where
             predicate.loggable()
         );
 
-        read_filter_impl(
+        let query = self.log_query("read_filter", &db_name, predicate.as_ref());
+        let result = read_filter_impl(
             tx.clone(),
             Arc::clone(&self.db_store),
             db_name,
+            &access,
             range,
             predicate,
         )
-        .await
-        .map_err(|e| e.to_status())?;
+        .await;
+        query.finish(&result);
+        result.map_err(|e| e.to_status())?;
 
         Ok(tonic::Response::new(ReceiverStream::new(rx)))
     }
@@ -255,10 +294,10 @@ where
     ) -> Result<tonic::Response<Self::ReadGroupStream>, Status> {
         let (tx, rx) = mpsc::channel(4);
 
+        let db_name = get_database_name(req.get_ref())?;
+        let access = self.access(&req)?;
         let read_group_request = req.into_inner();
 
-        let db_name = get_database_name(&read_group_request)?;
-
         let ReadGroupRequest {
             read_source: _read_source,
             range,
@@ -284,23 +323,29 @@ where
             aggregate, group, group_keys
         );
 
-        let group = expr::convert_group_type(group).context(ConvertingReadGroupType {
-            aggregate_string: &aggregate_string,
-        })?;
+        let query = self.log_query("read_group", &db_name, predicate.as_ref());
+        let result = async {
+            let group = expr::convert_group_type(group).context(ConvertingReadGroupType {
+                aggregate_string: &aggregate_string,
+            })?;
 
-        let gby_agg = expr::make_read_group_aggregate(aggregate, group, group_keys)
-            .context(ConvertingReadGroupAggregate { aggregate_string })?;
+            let gby_agg = expr::make_read_group_aggregate(aggregate, group, group_keys)
+                .context(ConvertingReadGroupAggregate { aggregate_string })?;
 
-        query_group_impl(
-            tx.clone(),
-            Arc::clone(&self.db_store),
-            db_name,
-            range,
-            predicate,
-            gby_agg,
-        )
-        .await
-        .map_err(|e| e.to_status())?;
+            query_group_impl(
+                tx.clone(),
+                Arc::clone(&self.db_store),
+                db_name,
+                &access,
+                range,
+                predicate,
+                gby_agg,
+            )
+            .await
+        }
+        .await;
+        query.finish(&result);
+        result.map_err(|e| e.to_status())?;
 
         Ok(tonic::Response::new(ReceiverStream::new(rx)))
     }
@@ -313,10 +358,10 @@ where
     ) -> Result<tonic::Response<Self::ReadGroupStream>, Status> {
         let (tx, rx) = mpsc::channel(4);
 
+        let db_name = get_database_name(req.get_ref())?;
+        let access = self.access(&req)?;
         let read_window_aggregate_request = req.into_inner();
 
-        let db_name = get_database_name(&read_window_aggregate_request)?;
-
         let ReadWindowAggregateRequest {
             read_source: _read_source,
             range,
@@ -338,19 +383,25 @@ where
             aggregate, window_every, offset, window
         );
 
-        let gby_agg = expr::make_read_window_aggregate(aggregate, window_every, offset, window)
-            .context(ConvertingWindowAggregate { aggregate_string })?;
+        let query = self.log_query("read_window_aggregate", &db_name, predicate.as_ref());
+        let result = async {
+            let gby_agg = expr::make_read_window_aggregate(aggregate, window_every, offset, window)
+                .context(ConvertingWindowAggregate { aggregate_string })?;
 
-        query_group_impl(
-            tx.clone(),
-            Arc::clone(&self.db_store),
-            db_name,
-            range,
-            predicate,
-            gby_agg,
-        )
-        .await
-        .map_err(|e| e.to_status())?;
+            query_group_impl(
+                tx.clone(),
+                Arc::clone(&self.db_store),
+                db_name,
+                &access,
+                range,
+                predicate,
+                gby_agg,
+            )
+            .await
+        }
+        .await;
+        query.finish(&result);
+        result.map_err(|e| e.to_status())?;
 
         Ok(tonic::Response::new(ReceiverStream::new(rx)))
     }
@@ -363,9 +414,29 @@ where
     ) -> Result<tonic::Response<Self::TagKeysStream>, Status> {
         let (tx, rx) = mpsc::channel(4);
 
+        let db_name = get_database_name(req.get_ref())?;
+        let access = self.access(&req)?;
+        let authorization = self.authorization(&req);
         let tag_keys_request = req.into_inner();
 
-        let db_name = get_database_name(&tag_keys_request)?;
+        if let Some(shards) = &self.shards {
+            let query = self.log_query("tag_keys", &db_name, tag_keys_request.predicate.as_ref());
+            let response = async {
+                authorize_read(&access, db_name.as_str())?;
+                shards
+                    .tag_keys(tag_keys_request, authorization.as_ref())
+                    .await
+                    .context(QueryingShards)
+            }
+            .await;
+            query.finish(&response);
+
+            tx.send(response.map_err(|e| e.to_status()))
+                .await
+                .expect("sending tag_keys response to server");
+
+            return Ok(tonic::Response::new(ReceiverStream::new(rx)));
+        }
 
         let TagKeysRequest {
             tags_source: _tag_source,
@@ -382,15 +453,18 @@ where
 
         let measurement = None;
 
+        let query = self.log_query("tag_keys", &db_name, predicate.as_ref());
         let response = tag_keys_impl(
             Arc::clone(&self.db_store),
             db_name,
+            &access,
             measurement,
             range,
             predicate,
         )
-        .await
-        .map_err(|e| e.to_status());
+        .await;
+        query.finish(&response);
+        let response = response.map_err(|e| e.to_status());
 
         tx.send(response)
             .await
@@ -407,9 +481,33 @@ where
     ) -> Result<tonic::Response<Self::TagValuesStream>, Status> {
         let (tx, rx) = mpsc::channel(4);
 
+        let db_name = get_database_name(req.get_ref())?;
+        let access = self.access(&req)?;
+        let authorization = self.authorization(&req);
         let tag_values_request = req.into_inner();
 
-        let db_name = get_database_name(&tag_values_request)?;
+        if let Some(shards) = &self.shards {
+            let query = self.log_query(
+                "tag_values",
+                &db_name,
+                tag_values_request.predicate.as_ref(),
+            );
+            let response = async {
+                authorize_read(&access, db_name.as_str())?;
+                shards
+                    .tag_values(tag_values_request, authorization.as_ref())
+                    .await
+                    .context(QueryingShards)
+            }
+            .await;
+            query.finish(&response);
+
+            tx.send(response.map_err(|e| e.to_status()))
+                .await
+                .expect("sending tag_values response to server");
+
+            return Ok(tonic::Response::new(ReceiverStream::new(rx)));
+        }
 
         let TagValuesRequest {
             tags_source: _tag_source,
@@ -420,6 +518,8 @@ where
 
         let measurement = None;
 
+        let query = self.log_query("tag_values", &db_name, predicate.as_ref());
+
         // Special case a request for 'tag_key=_measurement" means to list all
         // measurements
         let response = if tag_key.is_measurement() {
@@ -433,7 +533,7 @@ where
                 unimplemented!("tag_value for a measurement, with general predicate");
             }
 
-            measurement_name_impl(Arc::clone(&self.db_store), db_name, range).await
+            measurement_name_impl(Arc::clone(&self.db_store), db_name, &access, range).await
         } else if tag_key.is_field() {
             info!(
                 "tag_values with tag_key=[xff] (field name) for database {}, range: {:?}, predicate: {} --> returning fields",
@@ -441,18 +541,25 @@ where
                 predicate.loggable()
             );
 
-            let fieldlist =
-                field_names_impl(Arc::clone(&self.db_store), db_name, None, range, predicate)
-                    .await?;
-
-            // Pick out the field names into a Vec<Vec<u8>>for return
-            let values = fieldlist
-                .fields
-                .into_iter()
-                .map(|f| f.name.bytes().collect())
-                .collect::<Vec<_>>();
-
-            Ok(StringValuesResponse { values })
+            field_names_impl(
+                Arc::clone(&self.db_store),
+                db_name,
+                &access,
+                None,
+                range,
+                predicate,
+            )
+            .await
+            .map(|fieldlist| {
+                // Pick out the field names into a Vec<Vec<u8>>for return
+                let values = fieldlist
+                    .fields
+                    .into_iter()
+                    .map(|f| f.name.bytes().collect())
+                    .collect::<Vec<_>>();
+
+                StringValuesResponse { values }
+            })
         } else {
             let tag_key = String::from_utf8(tag_key).context(ConvertingTagKeyInTagValues)?;
 
@@ -467,6 +574,7 @@ where
             tag_values_impl(
                 Arc::clone(&self.db_store),
                 db_name,
+                &access,
                 tag_key,
                 measurement,
                 range,
@@ -475,6 +583,7 @@ where
             .await
         };
 
+        query.finish(&response);
         let response = response.map_err(|e| e.to_status());
 
         tx.send(response)
@@ -537,10 +646,10 @@ where
     ) -> Result<tonic::Response<Self::MeasurementNamesStream>, Status> {
         let (tx, rx) = mpsc::channel(4);
 
+        let db_name = get_database_name(req.get_ref())?;
+        let access = self.access(&req)?;
         let measurement_names_request = req.into_inner();
 
-        let db_name = get_database_name(&measurement_names_request)?;
-
         let MeasurementNamesRequest {
             source: _source,
             range,
@@ -565,9 +674,11 @@ where
             predicate.loggable()
         );
 
-        let response = measurement_name_impl(Arc::clone(&self.db_store), db_name, range)
-            .await
-            .map_err(|e| e.to_status());
+        let query = self.log_query("measurement_names", &db_name, predicate.as_ref());
+        let response =
+            measurement_name_impl(Arc::clone(&self.db_store), db_name, &access, range).await;
+        query.finish(&response);
+        let response = response.map_err(|e| e.to_status());
 
         tx.send(response)
             .await
@@ -584,10 +695,10 @@ where
     ) -> Result<tonic::Response<Self::MeasurementTagKeysStream>, Status> {
         let (tx, rx) = mpsc::channel(4);
 
+        let db_name = get_database_name(req.get_ref())?;
+        let access = self.access(&req)?;
         let measurement_tag_keys_request = req.into_inner();
 
-        let db_name = get_database_name(&measurement_tag_keys_request)?;
-
         let MeasurementTagKeysRequest {
             source: _source,
             measurement,
@@ -605,15 +716,18 @@ where
 
         let measurement = Some(measurement);
 
+        let query = self.log_query("measurement_tag_keys", &db_name, predicate.as_ref());
         let response = tag_keys_impl(
             Arc::clone(&self.db_store),
             db_name,
+            &access,
             measurement,
             range,
             predicate,
         )
-        .await
-        .map_err(|e| e.to_status());
+        .await;
+        query.finish(&response);
+        let response = response.map_err(|e| e.to_status());
 
         tx.send(response)
             .await
@@ -630,10 +744,10 @@ where
     ) -> Result<tonic::Response<Self::MeasurementTagValuesStream>, Status> {
         let (tx, rx) = mpsc::channel(4);
 
+        let db_name = get_database_name(req.get_ref())?;
+        let access = self.access(&req)?;
         let measurement_tag_values_request = req.into_inner();
 
-        let db_name = get_database_name(&measurement_tag_values_request)?;
-
         let MeasurementTagValuesRequest {
             source: _source,
             measurement,
@@ -650,16 +764,19 @@ where
 
         let measurement = Some(measurement);
 
+        let query = self.log_query("measurement_tag_values", &db_name, predicate.as_ref());
         let response = tag_values_impl(
             Arc::clone(&self.db_store),
             db_name,
+            &access,
             tag_key,
             measurement,
             range,
             predicate,
         )
-        .await
-        .map_err(|e| e.to_status());
+        .await;
+        query.finish(&response);
+        let response = response.map_err(|e| e.to_status());
 
         tx.send(response)
             .await
@@ -676,10 +793,10 @@ where
     ) -> Result<tonic::Response<Self::MeasurementFieldsStream>, Status> {
         let (tx, rx) = mpsc::channel(4);
 
+        let db_name = get_database_name(req.get_ref())?;
+        let access = self.access(&req)?;
         let measurement_fields_request = req.into_inner();
 
-        let db_name = get_database_name(&measurement_fields_request)?;
-
         let MeasurementFieldsRequest {
             source: _source,
             measurement,
@@ -696,20 +813,25 @@ where
 
         let measurement = Some(measurement);
 
-        let response = field_names_impl(
+        let query = self.log_query("measurement_fields", &db_name, predicate.as_ref());
+        let result = field_names_impl(
             Arc::clone(&self.db_store),
             db_name,
+            &access,
             measurement,
             range,
             predicate,
         )
-        .await
-        .map(|fieldlist| {
-            fieldlist_to_measurement_fields_response(fieldlist)
-                .context(ConvertingFieldList)
-                .map_err(|e| e.to_status())
-        })
-        .map_err(|e| e.to_status())?;
+        .await;
+        query.finish(&result);
+
+        let response = result
+            .map(|fieldlist| {
+                fieldlist_to_measurement_fields_response(fieldlist)
+                    .context(ConvertingFieldList)
+                    .map_err(|e| e.to_status())
+            })
+            .map_err(|e| e.to_status())?;
 
         tx.send(response)
             .await
@@ -733,6 +855,62 @@ impl SetRange for PredicateBuilder {
     }
 }
 
+impl<T: DatabaseStore> StorageService<T> {
+    /// Returns what the request `req` may do, given the token in its
+    /// metadata
+    fn access<R>(&self, req: &tonic::Request<R>) -> Result<Access, Status> {
+        self.authorizer
+            .authenticate_metadata(req.metadata())
+            .map_err(|e| e.to_status())
+    }
+
+    /// Returns the `authorization` header of the request `req`, to send on
+    /// with the requests scattered to the shards
+    fn authorization<R>(&self, req: &tonic::Request<R>) -> Option<Authorization> {
+        req.metadata().get("authorization").cloned()
+    }
+
+    /// Starts timing a query of `kind` for the query log, with the shape of
+    /// `predicate`
+    fn log_query(
+        &self,
+        kind: &'static str,
+        db_name: &DatabaseName<'_>,
+        predicate: Option<&Predicate>,
+    ) -> LoggedQuery<'_> {
+        LoggedQuery {
+            query_log: &self.query_log,
+            kind,
+            db_name: db_name.to_string(),
+            shape: expr::predicate_shape(predicate).to_string(),
+            start: Instant::now(),
+        }
+    }
+}
+
+/// A query being timed for the query log
+struct LoggedQuery<'a> {
+    query_log: &'a QueryLog,
+    kind: &'static str,
+    db_name: String,
+    shape: String,
+    start: Instant,
+}
+
+impl<'a> LoggedQuery<'a> {
+    /// Records the query in the query log, as failed if `result` is an
+    /// error
+    fn finish<R>(self, result: &Result<R>) {
+        self.query_log.record(
+            self.kind,
+            &self.db_name,
+            &self.shape,
+            self.start.elapsed(),
+            result.as_ref().err().map(|e| e as &dyn Display),
+        );
+    }
+}
+
 fn get_database_name(input: &impl GrpcInputs) -> Result<DatabaseName<'static>, Status> {
     org_and_bucket_to_database(input.org_id()?.to_string(), &input.bucket_name()?)
         .map_err(|e| Status::internal(e.to_string()))
@@ -743,11 +921,64 @@ fn get_database_name(input: &impl GrpcInputs) -> Result<DatabaseName<'static>, S
 // can use ?, etc). The trait implemententations then handle mapping
 // to the appropriate tonic Status
 
+/// Returns the database `db_name`, if the request with `access` may read it.
+/// Every request looks up its database through here, so that no request
+/// reads a bucket its token doesn't allow, whatever the service method.
+async fn readable_db<T>(db_store: &T, access: &Access, db_name: &str) -> Result<Arc<T::Database>>
+where
+    T: DatabaseStore,
+{
+    authorize_read(access, db_name)?;
+    db_store
+        .db(db_name)
+        .await
+        .context(DatabaseNotFound { db_name })
+}
+
+/// Checks that the request with `access` may read the database `db_name`.
+/// Requests scattered to the shards are checked here as well as by the
+/// shards, so that a router doesn't forward requests it would refuse.
+fn authorize_read(access: &Access, db_name: &str) -> Result<()> {
+    access
+        .authorize_database(Scope::Read, db_name)
+        .context(PermissionDenied)
+}
+
+/// Scatters the `read_filter` request `request` across `shards`, sending
+/// the merged series to `tx`
+async fn scatter_read_filter(
+    tx: mpsc::Sender<Result<ReadResponse, Status>>,
+    shards: &ShardedStorage,
+    db_name: &str,
+    access: &Access,
+    authorization: Option<&Authorization>,
+    request: ReadFilterRequest,
+) -> Result<()> {
+    authorize_read(access, db_name)?;
+    let responses = shards
+        .read_filter(request, authorization)
+        .await
+        .context(QueryingShards)?;
+
+    // send from a task, so the client can start receiving the responses
+    tokio::spawn(async move {
+        for response in responses {
+            if tx.send(Ok(response)).await.is_err() {
+                // the client went away
+                break;
+            }
+        }
+    });
+
+    Ok(())
+}
+
 /// Gathers all measurement names that have data in the specified
 /// (optional) range
 async fn measurement_name_impl<T>(
     db_store: Arc<T>,
     db_name: DatabaseName<'static>,
+    access: &Access,
     range: Option<TimestampRange>,
 ) -> Result<StringValuesResponse>
 where
@@ -756,10 +987,7 @@ where
     let predicate = PredicateBuilder::default().set_range(range).build();
     let db_name = db_name.as_ref();
 
-    let db = db_store
-        .db(&db_name)
-        .await
-        .context(DatabaseNotFound { db_name })?;
+    let db = readable_db(db_store.as_ref(), access, db_name).await?;
 
     let planner = InfluxRPCPlanner::new();
 
@@ -790,6 +1018,7 @@ where
 async fn tag_keys_impl<T>(
     db_store: Arc<T>,
     db_name: DatabaseName<'static>,
+    access: &Access,
     measurement: Option<String>,
     range: Option<TimestampRange>,
     rpc_predicate: Option<Predicate>,
@@ -797,7 +1026,7 @@ async fn tag_keys_impl<T>(
 where
     T: DatabaseStore + 'static,
 {
-    let rpc_predicate_string = format!("{:?}", rpc_predicate);
+    let rpc_predicate_string = rpc_predicate.loggable().to_string();
 
     let predicate = PredicateBuilder::default()
         .set_range(range)
@@ -808,9 +1037,7 @@ where
         })?
         .build();
 
-    let db = db_store.db(&db_name).await.context(DatabaseNotFound {
-        db_name: db_name.as_str(),
-    })?;
+    let db = readable_db(db_store.as_ref(), access, db_name.as_str()).await?;
 
     let planner = InfluxRPCPlanner::new();
 
@@ -846,6 +1073,7 @@ where
 async fn tag_values_impl<T>(
     db_store: Arc<T>,
     db_name: DatabaseName<'static>,
+    access: &Access,
     tag_name: String,
     measurement: Option<String>,
     range: Option<TimestampRange>,
@@ -854,7 +1082,7 @@ async fn tag_values_impl<T>(
 where
     T: DatabaseStore + 'static,
 {
-    let rpc_predicate_string = format!("{:?}", rpc_predicate);
+    let rpc_predicate_string = rpc_predicate.loggable().to_string();
 
     let predicate = PredicateBuilder::default()
         .set_range(range)
@@ -868,10 +1096,7 @@ where
     let db_name = db_name.as_str();
     let tag_name = &tag_name;
 
-    let db = db_store
-        .db(db_name)
-        .await
-        .context(DatabaseNotFound { db_name })?;
+    let db = readable_db(db_store.as_ref(), access, db_name).await?;
 
     let planner = InfluxRPCPlanner::new();
 
@@ -907,13 +1132,14 @@ async fn read_filter_impl<'a, T>(
     tx: mpsc::Sender<Result<ReadResponse, Status>>,
     db_store: Arc<T>,
     db_name: DatabaseName<'static>,
+    access: &Access,
     range: Option<TimestampRange>,
     rpc_predicate: Option<Predicate>,
 ) -> Result<()>
 where
     T: DatabaseStore,
 {
-    let rpc_predicate_string = format!("{:?}", rpc_predicate);
+    let rpc_predicate_string = rpc_predicate.loggable().to_string();
 
     let predicate = PredicateBuilder::default()
         .set_range(range)
@@ -928,10 +1154,7 @@ where
     let owned_db_name = db_name;
 
     let db_name = owned_db_name.as_str();
-    let db = db_store
-        .db(db_name)
-        .await
-        .context(DatabaseNotFound { db_name })?;
+    let db = readable_db(db_store.as_ref(), access, db_name).await?;
 
     let executor = db_store.executor();
 
@@ -993,6 +1216,7 @@ async fn query_group_impl<T>(
     tx: mpsc::Sender<Result<ReadResponse, Status>>,
     db_store: Arc<T>,
     db_name: DatabaseName<'static>,
+    access: &Access,
     range: Option<TimestampRange>,
     rpc_predicate: Option<Predicate>,
     gby_agg: GroupByAndAggregate,
@@ -1000,7 +1224,7 @@ async fn query_group_impl<T>(
 where
     T: DatabaseStore,
 {
-    let rpc_predicate_string = format!("{:?}", rpc_predicate);
+    let rpc_predicate_string = rpc_predicate.loggable().to_string();
 
     let predicate = PredicateBuilder::default()
         .set_range(range)
@@ -1015,10 +1239,7 @@ where
     let owned_db_name = db_name;
     let db_name = owned_db_name.as_str();
 
-    let db = db_store
-        .db(&db_name)
-        .await
-        .context(DatabaseNotFound { db_name })?;
+    let db = readable_db(db_store.as_ref(), access, db_name).await?;
 
     let executor = db_store.executor();
 
@@ -1058,6 +1279,7 @@ where
 async fn field_names_impl<T>(
     db_store: Arc<T>,
     db_name: DatabaseName<'static>,
+    access: &Access,
     measurement: Option<String>,
     range: Option<TimestampRange>,
     rpc_predicate: Option<Predicate>,
@@ -1065,7 +1287,7 @@ async fn field_names_impl<T>(
 where
     T: DatabaseStore + 'static,
 {
-    let rpc_predicate_string = format!("{:?}", rpc_predicate);
+    let rpc_predicate_string = rpc_predicate.loggable().to_string();
 
     let predicate = PredicateBuilder::default()
         .set_range(range)
@@ -1077,10 +1299,7 @@ where
         .build();
 
     let db_name = db_name.as_str();
-    let db = db_store
-        .db(db_name)
-        .await
-        .context(DatabaseNotFound { db_name })?;
+    let db = readable_db(db_store.as_ref(), access, db_name).await?;
 
     let planner = InfluxRPCPlanner::new();
 
@@ -1350,6 +1569,60 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_storage_rpc_permission_denied() {
+        let test_storage = Arc::new(TestDatabaseStore::new());
+        let db_info = OrgAndBucket::new(123, 456);
+        let chunk = TestChunk::new(0).with_table("h2o");
+        test_storage
+            .db_or_create(&db_info.db_name)
+            .await
+            .unwrap()
+            .add_chunk("my_partition_key", Arc::new(chunk));
+
+        let (org, bucket) =
+            data_types::names::database_to_org_and_bucket(&db_info.db_name).unwrap();
+        let token = |scopes, org: &str| {
+            Access::Token(server::tokens::Token {
+                id: "0000000000000001".to_string(),
+                description: String::new(),
+                scopes,
+                org: Some(org.to_string()),
+                bucket: Some(bucket.clone()),
+                limits: Default::default(),
+            })
+        };
+
+        let allowed = token(vec![Scope::Read], &org);
+        let response = measurement_name_impl(
+            Arc::clone(&test_storage),
+            db_info.db_name.clone(),
+            &allowed,
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.values, vec![b"h2o".to_vec()]);
+
+        for access in &[
+            token(vec![Scope::Read], "other_org"),
+            token(vec![Scope::Write], &org),
+        ] {
+            let err = tag_keys_impl(
+                Arc::clone(&test_storage),
+                db_info.db_name.clone(),
+                access,
+                None,
+                None,
+                None,
+            )
+            .await
+            .unwrap_err();
+            assert!(matches!(err, Error::PermissionDenied { .. }));
+            assert_eq!(err.to_status().code(), Code::PermissionDenied);
+        }
+    }
+
     /// test the plumbing of the RPC layer for measurement_tag_keys--
     /// specifically that the right parameters are passed into the Database
     /// interface and that the returned values are sent back via gRPC.
@@ -2678,10 +2951,15 @@ mod tests {
             );
 
             let router = tonic::transport::Server::builder()
-                .add_service(crate::influxdb_ioxd::rpc::testing::make_server())
-                .add_service(crate::influxdb_ioxd::rpc::storage::make_server(Arc::clone(
-                    &test_storage,
-                )));
+                .add_service(crate::influxdb_ioxd::rpc::testing::make_server(
+                    Default::default(),
+                ))
+                .add_service(crate::influxdb_ioxd::rpc::storage::make_server(
+                    Arc::clone(&test_storage),
+                    Default::default(),
+                    Default::default(),
+                    None,
+                ));
 
             let server = async move {
                 let stream = TcpListenerStream::new(socket);
@@ -1,7 +1,10 @@
 use generated_types::i_ox_testing_server::{IOxTesting, IOxTestingServer};
 use generated_types::{TestErrorRequest, TestErrorResponse};
+use std::sync::Arc;
 use tracing::warn;
 
+use crate::influxdb_ioxd::auth::{interceptor, Authorizer};
+
 /// Concrete implementation of the gRPC IOx testing service API
 struct IOxTestingService {}
 
@@ -16,6 +19,6 @@ impl IOxTesting for IOxTestingService {
     }
 }
 
-pub fn make_server() -> IOxTestingServer<impl IOxTesting> {
-    IOxTestingServer::new(IOxTestingService {})
+pub fn make_server(authorizer: Arc<Authorizer>) -> IOxTestingServer<impl IOxTesting> {
+    IOxTestingServer::with_interceptor(IOxTestingService {}, interceptor(authorizer))
 }
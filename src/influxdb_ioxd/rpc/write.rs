@@ -0,0 +1,192 @@
+use std::{fmt::Debug, sync::Arc};
+
+use data_types::{data::ReplicatedWrite, names::database_to_org_and_bucket, redaction::Redaction};
+use generated_types::influxdata::iox::write::v1::{
+    write_service_server::{WriteService, WriteServiceServer},
+    ReplicateWriteRequest, ReplicateWriteResponse, WriteRequest, WriteResponse,
+};
+use influxdb_line_protocol::parse_lines;
+use server::{tokens::Scope, ConnectionManager, Server};
+use tonic::{Request, Response, Status};
+
+use crate::influxdb_ioxd::auth::{interceptor, Authorizer};
+
+/// Implementation of the gRPC write service, through which clients write
+/// line protocol and peers replicate the writes they accept. Replicated
+/// writes skip the quotas and rate limits of clients, so require a token
+/// that may administer the server, such as the `--replication-token` of the
+/// peers, and their structure is checked before they are stored.
+#[derive(Debug)]
+struct WriteServiceImpl<M: ConnectionManager> {
+    server: Arc<Server<M>>,
+    /// Checks that requests may write to their database, or come from peers
+    authorizer: Arc<Authorizer>,
+}
+
+pub fn make_server<M>(
+    server: Arc<Server<M>>,
+    authorizer: Arc<Authorizer>,
+) -> WriteServiceServer<impl WriteService>
+where
+    M: ConnectionManager + Send + Sync + Debug + 'static,
+{
+    WriteServiceServer::with_interceptor(
+        WriteServiceImpl {
+            server,
+            authorizer: Arc::clone(&authorizer),
+        },
+        interceptor(authorizer),
+    )
+}
+
+#[tonic::async_trait]
+impl<M> WriteService for WriteServiceImpl<M>
+where
+    M: ConnectionManager + Send + Sync + Debug + 'static,
+{
+    async fn write(
+        &self,
+        request: Request<WriteRequest>,
+    ) -> Result<Response<WriteResponse>, Status> {
+        let access = self
+            .authorizer
+            .authenticate_metadata(request.metadata())
+            .map_err(|e| e.to_status())?;
+        let WriteRequest { db_name, lp_data } = request.into_inner();
+        access
+            .authorize_database(Scope::Write, &db_name)
+            .map_err(|e| e.to_status())?;
+
+        let lines = parse_lines(&lp_data)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| Status::invalid_argument(Redaction::current().line_protocol_error(&e)))?;
+
+        if let Some((org, _)) = database_to_org_and_bucket(&db_name) {
+            self.server
+                .check_write_rate(access.token(), &org, lines.len())
+                .await
+                .map_err(to_status)?;
+        }
+
+        self.server
+            .write_lines(&db_name, &lines)
+            .await
+            .map_err(to_status)?;
+
+        Ok(Response::new(WriteResponse {
+            lines_written: lines.len() as u64,
+        }))
+    }
+
+    async fn replicate_write(
+        &self,
+        request: Request<ReplicateWriteRequest>,
+    ) -> Result<Response<ReplicateWriteResponse>, Status> {
+        let access = self
+            .authorizer
+            .authenticate_metadata(request.metadata())
+            .map_err(|e| e.to_status())?;
+        access.authorize_peer().map_err(|e| e.to_status())?;
+        let ReplicateWriteRequest {
+            db_name,
+            replicated_write,
+        } = request.into_inner();
+
+        let write = ReplicatedWrite::verified(&replicated_write)
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        self.server
+            .store_peer_write(&db_name, write)
+            .await
+            .map_err(to_status)?;
+
+        Ok(Response::new(ReplicateWriteResponse {}))
+    }
+}
+
+/// Converts an error writing to the server into the appropriate tonic
+/// status
+fn to_status(e: server::Error) -> Status {
+    use server::Error::*;
+    match e {
+        DatabaseNotFound { .. } => Status::not_found(e.to_string()),
+        InvalidDatabaseName { .. } => Status::invalid_argument(e.to_string()),
+        QuotaExceeded { .. } | RateLimited { .. } => Status::resource_exhausted(e.to_string()),
+        IdNotSet
+        | WriteQuorumNotReached { .. }
+        | ShardWriteFailed { .. }
+        | WriteBufferFailed { .. }
+        | Draining
+        | QuorumLost { .. }
+        | MemoryLimitReached { .. } => Status::unavailable(e.to_string()),
+        ReadReplicaWrite { .. } => Status::permission_denied(e.to_string()),
+        _ => Status::internal(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use data_types::{data::lines_to_replicated_write, database_rules::DatabaseRules};
+    use object_store::{memory::InMemory, ObjectStore};
+    use server::ConnectionManagerImpl;
+    use tonic::Code;
+
+    use crate::influxdb_ioxd::auth::ApiTokens;
+
+    fn request<T>(message: T, token: &str) -> Request<T> {
+        let mut request = Request::new(message);
+        request
+            .metadata_mut()
+            .insert("authorization", format!("Token {}", token).parse().unwrap());
+        request
+    }
+
+    #[tokio::test]
+    async fn replicate_write() {
+        let server = Arc::new(Server::new(
+            ConnectionManagerImpl::default(),
+            Arc::new(ObjectStore::new_in_memory(InMemory::new())),
+        ));
+        server.set_id(2);
+        server
+            .create_database("foo", DatabaseRules::new())
+            .await
+            .unwrap();
+        let tokens = ApiTokens::new(vec!["peer".to_string()])
+            .with_scope(vec!["ingest".to_string()], Scope::Write);
+        let authorizer = Arc::new(Authorizer::new(tokens, Arc::<Server<_>>::clone(&server)));
+        let service = WriteServiceImpl { server, authorizer };
+
+        let lines: Vec<_> = parse_lines("cpu bar=1 10").map(|l| l.unwrap()).collect();
+        let write = lines_to_replicated_write(1, 1, &lines, &DatabaseRules::new());
+        let replicate = |replicated_write: Vec<u8>, token| {
+            request(
+                ReplicateWriteRequest {
+                    db_name: "foo".to_string(),
+                    replicated_write,
+                },
+                token,
+            )
+        };
+
+        // a token that may only write can't replicate writes
+        let status = service
+            .replicate_write(replicate(write.bytes().clone(), "ingest"))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::PermissionDenied, "{:?}", status);
+
+        // nor can a malformed write be stored
+        let truncated = write.bytes()[..write.bytes().len() / 2].to_vec();
+        let status = service
+            .replicate_write(replicate(truncated, "peer"))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument, "{:?}", status);
+
+        service
+            .replicate_write(replicate(write.bytes().clone(), "peer"))
+            .await
+            .unwrap();
+    }
+}
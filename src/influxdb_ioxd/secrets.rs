@@ -0,0 +1,164 @@
+//! The secrets provider configured with `--secrets-provider`, and the API
+//! tokens read from it.
+//!
+//! The tokens in the `api_tokens`, `api_read_tokens` and `api_write_tokens`
+//! secrets are accepted in addition to the configured ones, and are read
+//! again every `--secrets-refresh-interval` so that rotated tokens are used
+//! without a restart.
+
+use std::{sync::Arc, time::Duration};
+
+use object_store::secrets::{self, AwsInstanceMetadata, EnvSecrets, FileSecrets, SecretsProvider};
+use server::tokens::Scope;
+use snafu::{ResultExt, Snafu};
+use tracing::{error, info};
+
+use super::auth::{ApiTokens, Authorizer};
+use crate::commands::config::{Config, SecretsProvider as SecretsProviderOpt};
+
+/// The secret holding comma separated tokens that may do anything
+const API_TOKENS: &str = "api_tokens";
+
+/// The secret holding comma separated tokens that may only read
+const API_READ_TOKENS: &str = "api_read_tokens";
+
+/// The secret holding comma separated tokens that may only write
+const API_WRITE_TOKENS: &str = "api_write_tokens";
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("The file secrets provider requires --secrets-dir"))]
+    MissingSecretsDir,
+
+    #[snafu(display("Unable to read the API tokens from the secrets provider: {}", source))]
+    ReadingTokens { source: secrets::Error },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Returns the secrets provider of `config`, if any
+pub fn provider(config: &Config) -> Result<Option<Arc<dyn SecretsProvider>>> {
+    let provider: Arc<dyn SecretsProvider> = match config.secrets_provider {
+        None => return Ok(None),
+        Some(SecretsProviderOpt::Env) => Arc::new(EnvSecrets),
+        Some(SecretsProviderOpt::File) => {
+            let dir = config
+                .secrets_dir
+                .as_ref()
+                .ok_or(Error::MissingSecretsDir)?;
+            Arc::new(FileSecrets::new(dir))
+        }
+        Some(SecretsProviderOpt::Aws) => Arc::new(AwsInstanceMetadata::default()),
+    };
+    info!(provider=?config.secrets_provider, "Reading secrets from provider");
+    Ok(Some(provider))
+}
+
+/// The API tokens configured on the command line, to which those read from
+/// the secrets provider are added
+#[derive(Debug, Clone, Default)]
+pub struct ConfiguredTokens {
+    pub all: Vec<String>,
+    pub read: Vec<String>,
+    pub write: Vec<String>,
+}
+
+impl ConfiguredTokens {
+    /// Returns the configured tokens, with those currently in `secrets` if
+    /// any
+    pub async fn load(&self, secrets: Option<&dyn SecretsProvider>) -> Result<ApiTokens> {
+        Ok(
+            ApiTokens::new(with_secret(&self.all, secrets, API_TOKENS).await?)
+                .with_scope(
+                    with_secret(&self.read, secrets, API_READ_TOKENS).await?,
+                    Scope::Read,
+                )
+                .with_scope(
+                    with_secret(&self.write, secrets, API_WRITE_TOKENS).await?,
+                    Scope::Write,
+                ),
+        )
+    }
+}
+
+/// Returns `configured` with the comma separated tokens in the secret `name`
+/// of `secrets`, if any
+async fn with_secret(
+    configured: &[String],
+    secrets: Option<&dyn SecretsProvider>,
+    name: &str,
+) -> Result<Vec<String>> {
+    let mut tokens = configured.to_vec();
+    if let Some(secrets) = secrets {
+        if let Some(secret) = secrets.secret(name).await.context(ReadingTokens)? {
+            tokens.extend(secret.expose().split(',').map(|t| t.trim().to_string()));
+        }
+    }
+    Ok(tokens)
+}
+
+/// Reads the API tokens from `secrets` every `interval` and applies them to
+/// `authorizer`. Runs forever; errors are logged and the previous tokens
+/// kept.
+pub async fn refresh_tokens_periodically(
+    authorizer: Arc<Authorizer>,
+    configured: ConfiguredTokens,
+    secrets: Arc<dyn SecretsProvider>,
+    interval: Duration,
+) {
+    let mut interval = tokio::time::interval(interval);
+    // the first tick completes immediately, and the tokens were just loaded
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+        match configured.load(Some(secrets.as_ref())).await {
+            Ok(tokens) => authorizer.set_tokens(tokens),
+            Err(e) => error!(
+                "Error refreshing API tokens, keeping the current ones: {}",
+                e
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object_store::secrets::Secret;
+
+    #[derive(Debug)]
+    struct TestSecrets;
+
+    #[tonic::async_trait]
+    impl SecretsProvider for TestSecrets {
+        async fn secret(&self, name: &str) -> secrets::Result<Option<Secret>> {
+            Ok(match name {
+                API_TOKENS => Some(Secret::new("rotated, other")),
+                API_READ_TOKENS => Some(Secret::new("dashboard")),
+                _ => None,
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn load_tokens() {
+        let configured = ConfiguredTokens {
+            all: vec!["secret".to_string()],
+            ..Default::default()
+        };
+
+        let authorizer = Authorizer::default();
+        authorizer.set_tokens(configured.load(None).await.unwrap());
+        assert!(authorizer.authenticate(Some("Token secret")).is_ok());
+        assert!(authorizer.authenticate(Some("Token rotated")).is_err());
+
+        authorizer.set_tokens(configured.load(Some(&TestSecrets)).await.unwrap());
+        for token in &["secret", "rotated", "other", "dashboard"] {
+            let authorization = format!("Token {}", token);
+            assert!(authorizer.authenticate(Some(&authorization)).is_ok());
+        }
+        let access = authorizer.authenticate(Some("Token dashboard")).unwrap();
+        assert!(access.authorize(Scope::Write, None, None).is_err());
+    }
+}
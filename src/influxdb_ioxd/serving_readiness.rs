@@ -0,0 +1,152 @@
+//! Tracks whether the IOx server is ready to accept traffic, as reported
+//! by the `/ready` endpoint and the gRPC health service.
+
+use std::{fmt, sync::Arc, time::Instant};
+
+use parking_lot::Mutex;
+use tokio::sync::watch;
+use tracing::info;
+
+/// The phases the server goes through on startup, in order. The server is
+/// ready once the last one has completed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StartupPhase {
+    /// Loading the org catalog and the database configurations from object
+    /// storage
+    LoadingCatalog,
+    /// Binding the gRPC and HTTP listeners
+    BindingListeners,
+    /// Replaying the writes of the write buffer since its checkpoint
+    ReplayingWriteBuffer,
+}
+
+impl fmt::Display for StartupPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::LoadingCatalog => write!(f, "loading catalog"),
+            Self::BindingListeners => write!(f, "binding listeners"),
+            Self::ReplayingWriteBuffer => write!(f, "replaying write buffer"),
+        }
+    }
+}
+
+/// Shared readiness flag for the server. It starts out not ready and is
+/// flipped to ready by `finish_startup` once the startup phases have
+/// completed. It is flipped back to not ready when the server starts to
+/// shut down so that load balancers stop routing new traffic to it.
+///
+/// Cloning a `ServingReadiness` returns a handle to the same flag.
+#[derive(Debug, Clone, Default)]
+pub struct ServingReadiness(Arc<Readiness>);
+
+#[derive(Debug)]
+struct Readiness {
+    ready: watch::Sender<bool>,
+    /// Kept so that sending never fails for lack of receivers
+    ready_rx: watch::Receiver<bool>,
+    /// When the server started
+    startup: Instant,
+    /// The startup phase in progress, and when it started
+    phase: Mutex<Option<(StartupPhase, Instant)>>,
+}
+
+impl Default for Readiness {
+    fn default() -> Self {
+        let (ready, ready_rx) = watch::channel(false);
+        Self {
+            ready,
+            ready_rx,
+            startup: Instant::now(),
+            phase: Default::default(),
+        }
+    }
+}
+
+impl ServingReadiness {
+    /// Creates a new readiness flag in the "not ready" state
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns true if the server is ready to accept traffic
+    pub fn is_ready(&self) -> bool {
+        *self.0.ready_rx.borrow()
+    }
+
+    /// Sets whether the server is ready to accept traffic
+    pub fn set_ready(&self, ready: bool) {
+        // can't fail, `self` holds a receiver
+        let _ = self.0.ready.send(ready);
+    }
+
+    /// Returns a receiver notified each time the readiness changes
+    pub fn subscribe(&self) -> watch::Receiver<bool> {
+        self.0.ready_rx.clone()
+    }
+
+    /// Returns the startup phase in progress, if startup hasn't completed
+    pub fn phase(&self) -> Option<StartupPhase> {
+        self.0.phase.lock().map(|(phase, _)| phase)
+    }
+
+    /// Records that startup has moved on to `phase`, logging the duration
+    /// of the previous phase
+    pub fn start_phase(&self, phase: StartupPhase) {
+        let mut current = self.0.phase.lock();
+        log_phase_complete(current.take());
+        info!(%phase, "Startup phase started");
+        *current = Some((phase, Instant::now()));
+    }
+
+    /// Records that startup has completed, logging the duration of the last
+    /// phase and of the whole startup, and reports the server as ready
+    pub fn finish_startup(&self) {
+        log_phase_complete(self.0.phase.lock().take());
+        let duration_ms = self.0.startup.elapsed().as_secs_f64() * 1000.0;
+        info!(duration_ms, "Startup complete");
+        self.set_ready(true);
+    }
+}
+
+fn log_phase_complete(phase: Option<(StartupPhase, Instant)>) {
+    if let Some((phase, start)) = phase {
+        let duration_ms = start.elapsed().as_secs_f64() * 1000.0;
+        info!(%phase, duration_ms, "Startup phase complete");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clones_share_state() {
+        let readiness = ServingReadiness::new();
+        assert!(!readiness.is_ready());
+
+        let handle = readiness.clone();
+        handle.set_ready(true);
+        assert!(readiness.is_ready());
+
+        readiness.set_ready(false);
+        assert!(!handle.is_ready());
+    }
+
+    #[tokio::test]
+    async fn startup() {
+        let readiness = ServingReadiness::new();
+        let mut ready = readiness.subscribe();
+
+        readiness.start_phase(StartupPhase::LoadingCatalog);
+        readiness.start_phase(StartupPhase::BindingListeners);
+        readiness.start_phase(StartupPhase::ReplayingWriteBuffer);
+        assert_eq!(readiness.phase(), Some(StartupPhase::ReplayingWriteBuffer));
+        assert!(!readiness.is_ready());
+
+        readiness.finish_startup();
+        assert!(readiness.is_ready());
+        ready.changed().await.unwrap();
+        assert!(*ready.borrow());
+        assert_eq!(readiness.phase(), None);
+    }
+}
@@ -0,0 +1,24 @@
+//! Periodic loading of the owners of the shards moved by other routers.
+
+use std::{sync::Arc, time::Duration};
+
+use server::{ConnectionManagerImpl as ConnectionManager, Server as AppServer};
+use tracing::warn;
+
+/// How often the owners of the shards are loaded from object storage
+const LOAD_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Routes the shards of `server` to the owners recorded in object storage
+/// on startup and then every `LOAD_INTERVAL`, so that the shards moved
+/// through another router sharing the object store are routed the same
+/// way. Runs forever.
+pub async fn load_periodically(server: Arc<AppServer<ConnectionManager>>) {
+    let mut interval = tokio::time::interval(LOAD_INTERVAL);
+
+    loop {
+        interval.tick().await;
+        if let Err(e) = server.load_shard_owners().await {
+            warn!("Error loading the owners of the shards: {}", e);
+        }
+    }
+}
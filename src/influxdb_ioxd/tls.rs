@@ -0,0 +1,426 @@
+//! TLS termination for the HTTP and gRPC listeners.
+//!
+//! Both listeners share a single certificate resolver, so a rotated
+//! certificate / key pair only needs to be reloaded once to take effect for
+//! new connections on both APIs. Connections that are already established
+//! keep using the certificate they were negotiated with.
+//!
+//! The gRPC listener can additionally require clients to present a
+//! certificate signed by a configured CA, optionally also valid for one of
+//! an allowlist of names, for deployments where the storage API is only
+//! called by trusted query nodes.
+
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use futures::{Stream, StreamExt};
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+use tokio::net::TcpStream;
+use tokio_rustls::{
+    rustls::{
+        internal::pemfile,
+        sign::{self, CertifiedKey},
+        AllowAnyAuthenticatedClient, Certificate, ClientHello, NoClientAuth, ResolvesServerCert,
+        RootCertStore, ServerConfig, Session,
+    },
+    webpki::{DNSNameRef, EndEntityCert},
+    TlsAcceptor,
+};
+use tonic::transport::{Certificate as CaCertificate, ClientTlsConfig, Identity};
+use tracing::{error, info, warn};
+
+/// The maximum number of TLS handshakes performed concurrently per listener
+const MAX_CONCURRENT_HANDSHAKES: usize = 64;
+
+/// Connections that have not completed the TLS handshake within this time
+/// are dropped
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Unable to open TLS file {:?}: {}", path, source))]
+    OpeningFile {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("No PEM encoded certificates found in {:?}", path))]
+    NoCertificates { path: PathBuf },
+
+    #[snafu(display("No PEM encoded PKCS8 or RSA private key found in {:?}", path))]
+    NoPrivateKey { path: PathBuf },
+
+    #[snafu(display("Unsupported private key type in {:?}", path))]
+    UnsupportedPrivateKey { path: PathBuf },
+
+    #[snafu(display("No valid PEM encoded CA certificates found in {:?}", path))]
+    NoCaCertificates { path: PathBuf },
+
+    #[snafu(display("Invalid client name {:?}: must be a DNS name", name))]
+    InvalidClientName { name: String },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// A TCP connection on which the TLS handshake has completed
+pub type TlsStream = tokio_rustls::server::TlsStream<TcpStream>;
+
+/// Locations of the PEM encoded certificate chain and private key used to
+/// serve TLS
+#[derive(Debug, Clone, PartialEq)]
+pub struct TlsFiles {
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+}
+
+/// Names of the clients allowed to connect when client certificates are
+/// required. A client's certificate must be valid for one of the names,
+/// which are matched against the DNS names in its subject alternative name
+/// extension; if there are no names, any certificate signed by the CA is
+/// accepted.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ClientAllowlist {
+    names: Vec<String>,
+}
+
+impl ClientAllowlist {
+    /// Creates an allowlist of `names`, returning an error if any is not a
+    /// valid DNS name
+    pub fn new(names: Vec<String>) -> Result<Self> {
+        for name in &names {
+            DNSNameRef::try_from_ascii_str(name)
+                .ok()
+                .context(InvalidClientName { name })?;
+        }
+        Ok(Self { names })
+    }
+
+    /// Returns whether a client presenting the certificate chain `certs`,
+    /// already verified against the CA, may connect
+    pub fn allows(&self, certs: &[Certificate]) -> bool {
+        if self.names.is_empty() {
+            return true;
+        }
+
+        let cert = match certs
+            .first()
+            .and_then(|cert| EndEntityCert::from(&cert.0).ok())
+        {
+            Some(cert) => cert,
+            None => return false,
+        };
+        self.names.iter().any(|name| {
+            DNSNameRef::try_from_ascii_str(name)
+                .map(|name| cert.verify_is_valid_for_dns_name(name).is_ok())
+                .unwrap_or(false)
+        })
+    }
+}
+
+/// A `ResolvesServerCert` that serves the certificate currently loaded from
+/// `TlsFiles` and can reload it from disk while the server is running
+#[derive(Debug)]
+pub struct ReloadingCertResolver {
+    files: TlsFiles,
+    current: RwLock<CertifiedKey>,
+}
+
+impl ReloadingCertResolver {
+    /// Loads the certificate and key, returning an error if they can not be
+    /// read or are not valid
+    pub fn new(files: TlsFiles) -> Result<Self> {
+        let current = RwLock::new(load_certified_key(&files)?);
+        Ok(Self { files, current })
+    }
+
+    /// Reloads the certificate and key from disk. On error the previously
+    /// loaded certificate remains in use.
+    pub fn reload(&self) -> Result<()> {
+        let certified_key = load_certified_key(&self.files)?;
+        *self.current.write().expect("mutex poisoned") = certified_key;
+        Ok(())
+    }
+}
+
+impl ResolvesServerCert for ReloadingCertResolver {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<CertifiedKey> {
+        Some(self.current.read().expect("mutex poisoned").clone())
+    }
+}
+
+/// Reloads the certificate and key of `resolver` from disk every `interval`
+/// so that rotated certificates are picked up without a restart. Runs
+/// forever; errors are logged and the previous certificate kept.
+pub async fn reload_periodically(resolver: Arc<ReloadingCertResolver>, interval: Duration) {
+    let mut interval = tokio::time::interval(interval);
+    // the first tick completes immediately, and the files were just loaded
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+        match resolver.reload() {
+            Ok(()) => info!(cert_path=?resolver.files.cert_path, "Reloaded TLS certificate"),
+            Err(e) => error!(
+                "Error reloading TLS certificate, keeping the current one: {}",
+                e
+            ),
+        }
+    }
+}
+
+/// Creates the acceptor used to terminate TLS on both listeners. Advertises
+/// HTTP/2 (required by gRPC) and HTTP/1.1 via ALPN.
+pub fn make_acceptor(resolver: Arc<ReloadingCertResolver>) -> TlsAcceptor {
+    acceptor(ServerConfig::new(NoClientAuth::new()), resolver)
+}
+
+/// Creates an acceptor like `make_acceptor` that also requires clients to
+/// present a certificate signed by one of the PEM encoded CA certificates in
+/// `ca_path`
+pub fn make_client_auth_acceptor(
+    resolver: Arc<ReloadingCertResolver>,
+    ca_path: &Path,
+) -> Result<TlsAcceptor> {
+    let mut roots = RootCertStore::empty();
+    let (valid, _invalid) = roots.add_pem_file(&mut open(ca_path)?).unwrap_or((0, 0));
+    ensure!(valid > 0, NoCaCertificates { path: ca_path });
+
+    Ok(acceptor(
+        ServerConfig::new(AllowAnyAuthenticatedClient::new(roots)),
+        resolver,
+    ))
+}
+
+/// Returns the TLS settings of the connections to the other servers of the
+/// cluster given as `https://` URLs. The certificate in `files`, if any, is
+/// presented to the servers requiring client certificates, and the servers
+/// are verified against the CA certificates in `ca_path`, if set.
+pub fn client_config(files: Option<&TlsFiles>, ca_path: Option<&Path>) -> Result<ClientTlsConfig> {
+    let mut config = ClientTlsConfig::new();
+    if let Some(files) = files {
+        // checks the certificate and key the same way as when serving them
+        load_certified_key(files)?;
+        config = config.identity(Identity::from_pem(
+            read(&files.cert_path)?,
+            read(&files.key_path)?,
+        ));
+    }
+    if let Some(ca_path) = ca_path {
+        let mut roots = RootCertStore::empty();
+        let (valid, _invalid) = roots.add_pem_file(&mut open(ca_path)?).unwrap_or((0, 0));
+        ensure!(valid > 0, NoCaCertificates { path: ca_path });
+        config = config.ca_certificate(CaCertificate::from_pem(read(ca_path)?));
+    }
+    Ok(config)
+}
+
+fn acceptor(mut config: ServerConfig, resolver: Arc<ReloadingCertResolver>) -> TlsAcceptor {
+    config.cert_resolver = resolver;
+    config.set_protocols(&[b"h2".to_vec(), b"http/1.1".to_vec()]);
+    TlsAcceptor::from(Arc::new(config))
+}
+
+/// Performs the TLS handshake on the accepted `connections`, yielding the
+/// established TLS streams. Handshakes happen
+/// concurrently so a slow client does not hold up others; connections that
+/// fail or time out during the handshake, or whose client certificate is
+/// not allowed by `allowlist`, are logged and dropped.
+pub fn incoming(
+    connections: impl Stream<Item = std::io::Result<TcpStream>>,
+    acceptor: TlsAcceptor,
+    allowlist: Arc<ClientAllowlist>,
+) -> impl Stream<Item = std::io::Result<TlsStream>> {
+    connections
+        .map(move |conn| {
+            let acceptor = acceptor.clone();
+            let allowlist = Arc::clone(&allowlist);
+            async move {
+                let conn = conn?;
+                let stream =
+                    match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(conn)).await {
+                        Ok(res) => res?,
+                        Err(e) => return Err(std::io::Error::new(std::io::ErrorKind::TimedOut, e)),
+                    };
+
+                let certs = stream.get_ref().1.get_peer_certificates();
+                if allowlist.allows(certs.as_deref().unwrap_or_default()) {
+                    Ok(stream)
+                } else {
+                    Err(std::io::Error::new(
+                        std::io::ErrorKind::PermissionDenied,
+                        "client certificate is not valid for any allowed client name",
+                    ))
+                }
+            }
+        })
+        .buffer_unordered(MAX_CONCURRENT_HANDSHAKES)
+        .filter_map(|res| async move {
+            match res {
+                Ok(stream) => Some(Ok(stream)),
+                Err(e) => {
+                    warn!("Error accepting TLS connection: {}", e);
+                    None
+                }
+            }
+        })
+}
+
+fn load_certified_key(files: &TlsFiles) -> Result<CertifiedKey> {
+    let cert_path = &files.cert_path;
+    let certs = pemfile::certs(&mut open(cert_path)?)
+        .ok()
+        .filter(|certs| !certs.is_empty())
+        .context(NoCertificates { path: cert_path })?;
+
+    let key_path = &files.key_path;
+    let key = pemfile::pkcs8_private_keys(&mut open(key_path)?)
+        .ok()
+        .and_then(|mut keys| keys.pop())
+        .or_else(|| {
+            open(key_path)
+                .ok()
+                .and_then(|mut reader| pemfile::rsa_private_keys(&mut reader).ok())
+                .and_then(|mut keys| keys.pop())
+        })
+        .context(NoPrivateKey { path: key_path })?;
+
+    let key = sign::any_supported_type(&key)
+        .ok()
+        .context(UnsupportedPrivateKey { path: key_path })?;
+
+    Ok(CertifiedKey::new(certs, Arc::new(key)))
+}
+
+fn open(path: &Path) -> Result<BufReader<File>> {
+    File::open(path)
+        .map(BufReader::new)
+        .context(OpeningFile { path })
+}
+
+fn read(path: &Path) -> Result<Vec<u8>> {
+    std::fs::read(path).context(OpeningFile { path })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/tls")
+            .join(name)
+    }
+
+    fn test_files() -> TlsFiles {
+        TlsFiles {
+            cert_path: fixture("test-cert.pem"),
+            key_path: fixture("test-key.pem"),
+        }
+    }
+
+    #[test]
+    fn load_certificate_and_key() {
+        let resolver = ReloadingCertResolver::new(test_files()).unwrap();
+        let loaded = resolver.current.read().unwrap().cert.clone();
+        assert_eq!(loaded.len(), 1);
+
+        resolver.reload().unwrap();
+        assert_eq!(resolver.current.read().unwrap().cert, loaded);
+    }
+
+    #[test]
+    fn missing_file() {
+        let files = TlsFiles {
+            cert_path: fixture("does-not-exist.pem"),
+            ..test_files()
+        };
+        let err = ReloadingCertResolver::new(files).unwrap_err();
+        assert!(matches!(err, Error::OpeningFile { .. }), "{}", err);
+    }
+
+    #[test]
+    fn invalid_files() {
+        // a key is not a certificate
+        let files = TlsFiles {
+            cert_path: fixture("test-key.pem"),
+            ..test_files()
+        };
+        let err = ReloadingCertResolver::new(files).unwrap_err();
+        assert!(matches!(err, Error::NoCertificates { .. }), "{}", err);
+
+        // and a certificate is not a key
+        let files = TlsFiles {
+            key_path: fixture("test-cert.pem"),
+            ..test_files()
+        };
+        let err = ReloadingCertResolver::new(files).unwrap_err();
+        assert!(matches!(err, Error::NoPrivateKey { .. }), "{}", err);
+    }
+
+    #[test]
+    fn failed_reload_keeps_current_certificate() {
+        let dir = test_helpers::tmp_dir().unwrap();
+        let files = TlsFiles {
+            cert_path: dir.path().join("cert.pem"),
+            key_path: dir.path().join("key.pem"),
+        };
+        std::fs::copy(fixture("test-cert.pem"), &files.cert_path).unwrap();
+        std::fs::copy(fixture("test-key.pem"), &files.key_path).unwrap();
+
+        let resolver = ReloadingCertResolver::new(files.clone()).unwrap();
+        let loaded = resolver.current.read().unwrap().cert.clone();
+
+        std::fs::write(&files.cert_path, "not a certificate").unwrap();
+        assert!(resolver.reload().is_err());
+        assert_eq!(resolver.current.read().unwrap().cert, loaded);
+    }
+
+    #[test]
+    fn client_auth() {
+        let resolver = Arc::new(ReloadingCertResolver::new(test_files()).unwrap());
+        make_client_auth_acceptor(Arc::clone(&resolver), &fixture("test-cert.pem")).unwrap();
+
+        let err = make_client_auth_acceptor(resolver, &fixture("test-key.pem")).unwrap_err();
+        assert!(matches!(err, Error::NoCaCertificates { .. }), "{}", err);
+    }
+
+    #[test]
+    fn client_config() {
+        super::client_config(Some(&test_files()), Some(&fixture("test-cert.pem"))).unwrap();
+        super::client_config(None, None).unwrap();
+
+        let err = super::client_config(None, Some(&fixture("test-key.pem"))).unwrap_err();
+        assert!(matches!(err, Error::NoCaCertificates { .. }), "{}", err);
+
+        let files = TlsFiles {
+            cert_path: fixture("does-not-exist.pem"),
+            key_path: fixture("test-key.pem"),
+        };
+        let err = super::client_config(Some(&files), None).unwrap_err();
+        assert!(matches!(err, Error::OpeningFile { .. }), "{}", err);
+    }
+
+    #[test]
+    fn client_allowlist() {
+        // the test certificate is valid for localhost
+        let certs = pemfile::certs(&mut open(&fixture("test-cert.pem")).unwrap()).unwrap();
+
+        let allowlist = ClientAllowlist::new(vec!["query-1".into(), "localhost".into()]).unwrap();
+        assert!(allowlist.allows(&certs));
+        assert!(!allowlist.allows(&[]));
+
+        let allowlist = ClientAllowlist::new(vec!["query-1".into()]).unwrap();
+        assert!(!allowlist.allows(&certs));
+
+        assert!(ClientAllowlist::default().allows(&certs));
+        assert!(ClientAllowlist::default().allows(&[]));
+
+        let err = ClientAllowlist::new(vec!["not a name".into()]).unwrap_err();
+        assert!(matches!(err, Error::InvalidClientName { .. }), "{}", err);
+    }
+}
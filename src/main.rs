@@ -43,6 +43,9 @@ Examples:
     # Display all server settings
     influxdb_iox server --help
 
+    # Run the InfluxDB IOx server with settings from a config file
+    influxdb_iox server --config /etc/influxdb_iox/config.toml
+
     # Run the InfluxDB IOx server with extra verbose logging
     influxdb_iox -v
 
@@ -60,6 +63,8 @@ Examples:
 "#;
     // load all environment variables from .env before doing anything
     load_dotenv();
+    // then any settings from the config file not set in the environment
+    load_config_file();
 
     let matches = App::new(help)
         .version(crate_version!())
@@ -129,8 +134,9 @@ Examples:
             "Enables verbose logging (use 'vv' for even more verbosity). You can also set log level via \
                        the environment variable RUST_LOG=<value>",
         ))
-        .arg(Arg::with_name("num-threads").long("num-threads").takes_value(true).help(
-            "Set the maximum number of threads to use. Defaults to the number of cores on the system",
+        .arg(Arg::with_name("num-threads").long("num-threads").env("INFLUXDB_IOX_NUM_THREADS").takes_value(true).help(
+            "Set the number of threads handling requests and writes. Defaults to the number of cores on the system. \
+             Queries run on threads of their own, see `server --query-threads`",
         ))
         .get_matches();
 
@@ -279,3 +285,18 @@ fn load_dotenv() {
         }
     };
 }
+
+/// Sets the environment variables for the settings in the config file given
+/// with `--config` (or `INFLUXDB_IOX_CONFIG_FILE`) that are not already set,
+/// so that they are picked up when the Config struct is initialised.
+fn load_config_file() {
+    use commands::config::file;
+
+    if let Some(path) = file::config_file_path(std::env::args()) {
+        if let Err(e) = file::load(&path) {
+            eprintln!("FATAL {}", e);
+            eprintln!("Aborting");
+            std::process::exit(1);
+        }
+    }
+}
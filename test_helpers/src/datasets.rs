@@ -0,0 +1,57 @@
+//! Generates datasets of line protocol for benchmarks, of a given number of
+//! series (cardinality) and points.
+//!
+//! The datasets are deterministic, so that runs of a benchmark on different
+//! commits measure the same work. Each line is a point of the `cpu`
+//! measurement, with a `host` tag unique to its series and a `region` tag
+//! shared by a tenth of the series, so that filtering on the region keeps
+//! many series and filtering on the host keeps one.
+
+/// The name of the measurement of the generated lines
+pub const MEASUREMENT: &str = "cpu";
+
+/// The number of distinct values of the `region` tag
+pub const REGIONS: usize = 10;
+
+/// The time of the first point of each series, in nanoseconds
+pub const BASE_TIME: i64 = 1_600_000_000_000_000_000;
+
+/// The interval between the points of a series, in nanoseconds
+pub const INTERVAL: i64 = 10_000_000_000;
+
+/// Returns the value of the `host` tag of the series `series`, padded so
+/// that the hosts sort in the order of their series
+pub fn host(series: usize) -> String {
+    format!("host{:08}", series)
+}
+
+/// Returns the value of the `region` tag of the series `series`
+pub fn region(series: usize) -> String {
+    format!("region{}", series % REGIONS)
+}
+
+/// Returns `points_per_series` lines for each of `cardinality` series. The
+/// lines are sorted by series key and then by time, as the read path
+/// expects the rows of a series set to be.
+pub fn line_protocol(cardinality: usize, points_per_series: usize) -> String {
+    let mut lines = String::new();
+    for series in 0..cardinality {
+        let host = host(series);
+        let region = region(series);
+        for point in 0..points_per_series {
+            let time = BASE_TIME + point as i64 * INTERVAL;
+            let usage = ((series * 31 + point * 17) % 1000) as f64 / 10.0;
+            lines.push_str(&format!(
+                "{},host={},region={} usage_user={},usage_system={},processes={}i {}\n",
+                MEASUREMENT,
+                host,
+                region,
+                usage,
+                100.0 - usage,
+                point % 500,
+                time
+            ));
+        }
+    }
+    lines
+}
@@ -13,6 +13,7 @@ use std::{
 };
 pub use tempfile;
 
+pub mod datasets;
 pub mod tracing;
 
 pub type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
@@ -298,7 +298,7 @@ async fn test_http_error_messages(client: &influxdb2_client::Client) -> Result<(
         .await
         .expect_err("Should have errored");
 
-    let expected_error = "HTTP request returned an error: 400 Bad Request, `{\"error\":\"Error parsing line protocol: A generic parsing error occurred: TakeWhile1\",\"error_code\":100}`";
+    let expected_error = "HTTP request returned an error: 400 Bad Request, `{\"code\":\"invalid\",\"message\":\"Error parsing line 1 of line protocol: A generic parsing error occurred: TakeWhile1\",\"line\":1,\"error_code\":100}`";
     assert_eq!(result.to_string(), expected_error);
 
     Ok(())
@@ -417,14 +417,17 @@ impl TestServer {
 
         let try_http_connect = async {
             let client = reqwest::Client::new();
-            let url = format!("{}/health", HTTP_BASE);
+            let url = format!("{}/ready", HTTP_BASE);
             let mut interval = tokio::time::interval(Duration::from_millis(500));
             loop {
                 match client.get(&url).send().await {
-                    Ok(resp) => {
+                    Ok(resp) if resp.status().is_success() => {
                         println!("Successfully got a response from HTTP: {:?}", resp);
                         return;
                     }
+                    Ok(resp) => {
+                        println!("Waiting for HTTP server to be ready: {:?}", resp);
+                    }
                     Err(e) => {
                         println!("Waiting for HTTP server to be up: {}", e);
                     }
@@ -433,13 +436,15 @@ impl TestServer {
             }
         };
 
+        // The server only reports itself as ready, over both HTTP and the
+        // gRPC health service, once startup has completed
         let pair = future::join(try_http_connect, try_grpc_connect);
 
-        let capped_check = tokio::time::timeout(Duration::from_secs(3), pair);
+        let capped_check = tokio::time::timeout(Duration::from_secs(30), pair);
 
         match capped_check.await {
             Ok(_) => println!("Server is up correctly"),
-            Err(e) => println!("WARNING: server was not ready: {}", e),
+            Err(e) => panic!("Server was not ready: {}", e),
         }
     }
 }